@@ -0,0 +1,148 @@
+// Grid-to-particle transfer; see sim_gpu.rs.  Each particle picks its new velocity up from the grid,
+// FLIP/PIC or APIC style, just as apply_grid() does on the CPU.
+
+struct Params {
+    rows: u32,
+    cols: u32,
+    wrap_horizontal: u32,
+    wrap_vertical: u32,
+    cell_size: f32,
+    point_offset: f32,    // How far velocity points sit from the corners of their cells.
+    particle_count: u32,
+    apic: u32,
+    pic_ratio: f32,
+    _padding: u32,
+    gravity_step: vec2<f32>,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    affine_velocity: vec4<f32>, // The columns of a Mat2, one after the other.
+    weight: f32,                // Material density times mass.
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> particles: array<Particle>;
+// The grid's velocities, followed by its velocities from before the pressure solve.
+@group(0) @binding(2) var<storage, read> velocity_u: array<f32>;
+@group(0) @binding(3) var<storage, read> velocity_v: array<f32>;
+
+const NO_VELOCITY: f32 = -3.40282347e38;
+
+// Same as interpolate_velocity_field(), reading the velocities that start at `first_u`/`first_v`.
+fn interpolate_velocity(position: vec2<f32>, first_u: u32, first_v: u32) -> vec2<f32> {
+    let cell_size: f32 = params.cell_size;
+    let grid_height: f32 = f32(params.rows) * cell_size;
+    let row: u32 = u32(clamp(floor((grid_height - position.y) / cell_size), 0.0, f32(params.rows - 1u)));
+    let col: u32 = u32(clamp(floor(position.x / cell_size), 0.0, f32(params.cols - 1u)));
+
+    // Like get_cell_position_from_coordinates(), this is the cell's bottom left corner.
+    let corner: vec2<f32> = vec2<f32>(
+        clamp(floor(f32(col) * cell_size), 0.0, f32(params.cols) * cell_size),
+        clamp(floor(grid_height - cell_size - f32(row) * cell_size), 0.0, grid_height - cell_size),
+    );
+    let half_cell: f32 = cell_size / 2.0;
+    let left: f32 = corner.x - half_cell;
+    let right: f32 = corner.x + half_cell;
+    let top: f32 = corner.y + half_cell;
+    let bottom: f32 = corner.y - half_cell;
+
+    let left_velocity: f32 = velocity_u[first_u + row * (params.cols + 1u) + col];
+    let right_velocity: f32 = velocity_u[first_u + row * (params.cols + 1u) + col + 1u];
+    let top_velocity: f32 = velocity_v[first_v + row * params.cols + col];
+    let bottom_velocity: f32 = velocity_v[first_v + (row + 1u) * params.cols + col];
+
+    return vec2<f32>(
+        (((right - position.x) / (right - left)) * left_velocity)
+            + (((position.x - left) / (right - left)) * right_velocity),
+        ((top - position.y) / (top - bottom) * bottom_velocity)
+            + (((position.y - bottom) / (top - bottom)) * top_velocity),
+    );
+}
+
+fn sample_u(row: u32, col: u32) -> f32 {
+    let velocity: f32 = velocity_u[row * (params.cols + 1u) + col];
+    return select(velocity, 0.0, velocity == NO_VELOCITY);
+}
+
+fn sample_v(row: u32, col: u32) -> f32 {
+    let velocity: f32 = velocity_v[row * params.cols + col];
+    return select(velocity, 0.0, velocity == NO_VELOCITY);
+}
+
+// Same as interpolate_velocity_component_with_gradient(); returns the value, then the gradient.
+fn interpolate_with_gradient(position: vec2<f32>, horizontal: bool) -> vec3<f32> {
+    let cell_size: f32 = params.cell_size;
+    let grid_height: f32 = f32(params.rows) * cell_size;
+    var first_point: vec2<f32> = vec2<f32>(params.point_offset, grid_height);
+    var point_rows: u32 = params.rows + 1u;
+    var point_cols: u32 = params.cols;
+    if horizontal {
+        first_point = vec2<f32>(0.0, grid_height - params.point_offset);
+        point_rows = params.rows;
+        point_cols = params.cols + 1u;
+    }
+
+    let point_x: f32 = clamp((position.x - first_point.x) / cell_size, 0.0, f32(point_cols - 1u));
+    let point_y: f32 = clamp((first_point.y - position.y) / cell_size, 0.0, f32(point_rows - 1u));
+    let col: u32 = min(u32(point_x), point_cols - 2u);
+    let row: u32 = min(u32(point_y), point_rows - 2u);
+    let x_weight: f32 = point_x - f32(col);
+    let y_weight: f32 = point_y - f32(row);
+
+    var top_left: f32;
+    var top_right: f32;
+    var bottom_left: f32;
+    var bottom_right: f32;
+    if horizontal {
+        top_left = sample_u(row, col);
+        top_right = sample_u(row, col + 1u);
+        bottom_left = sample_u(row + 1u, col);
+        bottom_right = sample_u(row + 1u, col + 1u);
+    } else {
+        top_left = sample_v(row, col);
+        top_right = sample_v(row, col + 1u);
+        bottom_left = sample_v(row + 1u, col);
+        bottom_right = sample_v(row + 1u, col + 1u);
+    }
+
+    let top: f32 = top_left + (top_right - top_left) * x_weight;
+    let bottom: f32 = bottom_left + (bottom_right - bottom_left) * x_weight;
+    let value: f32 = top + (bottom - top) * y_weight;
+
+    // Rows run down the screen, so the gradient's y component is flipped.
+    let slope_x: f32 = (top_right - top_left)
+        + ((bottom_right - bottom_left) - (top_right - top_left)) * y_weight;
+    let slope_y: f32 = (bottom_left - top_left)
+        + ((bottom_right - top_right) - (bottom_left - top_left)) * x_weight;
+    return vec3<f32>(value, vec2<f32>(slope_x, -slope_y) / cell_size);
+}
+
+@compute @workgroup_size(64)
+fn transfer(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= params.particle_count {
+        return;
+    }
+    var particle: Particle = particles[index];
+
+    if params.apic != 0u {
+        let u: vec3<f32> = interpolate_with_gradient(particle.position, true);
+        let v: vec3<f32> = interpolate_with_gradient(particle.position, false);
+        particle.affine_velocity = vec4<f32>(u.y, v.y, u.z, v.z);
+        particle.velocity = vec2<f32>(u.x, v.x) + params.gravity_step;
+    } else {
+        let point_count_u: u32 = params.rows * (params.cols + 1u);
+        let point_count_v: u32 = (params.rows + 1u) * params.cols;
+        let velocity: vec2<f32> = interpolate_velocity(particle.position, 0u, 0u);
+        let previous_velocity: vec2<f32> =
+            interpolate_velocity(particle.position, point_count_u, point_count_v);
+        let flip_velocity: vec2<f32> = particle.velocity + (velocity - previous_velocity);
+        particle.affine_velocity = vec4<f32>(0.0);
+        particle.velocity = (params.pic_ratio * velocity)
+            + ((1.0 - params.pic_ratio) * flip_velocity) + params.gravity_step;
+    }
+
+    particles[index] = particle;
+}
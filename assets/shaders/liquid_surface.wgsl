@@ -0,0 +1,28 @@
+// Draws the fluid as one continuous liquid surface; see juice_renderer.rs.  The density texture holds
+// each grid cell's fluid fraction, and sampling it with bilinear filtering blends neighboring cells
+// together like metaballs, so thresholding it gives a smooth outline around the fluid.
+
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+struct LiquidSurface {
+    color: vec4<f32>,
+    threshold: f32, // Fluid fraction the surface is drawn at.
+}
+
+@group(1) @binding(0) var<uniform> surface: LiquidSurface;
+@group(1) @binding(1) var density_texture: texture_2d<f32>;
+@group(1) @binding(2) var density_sampler: sampler;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let fraction: f32 = textureSample(density_texture, density_sampler, in.uv).r;
+
+    // Blend across about a pixel on either side of the threshold, so the edge is antialiased.
+    let edge: f32 = max(fwidth(fraction), 1e-4);
+    let coverage: f32 = smoothstep(surface.threshold - edge, surface.threshold + edge, fraction);
+
+    // Thin fluid near the surface is drawn a little lighter than the deep fluid inside.
+    let depth: f32 = clamp((fraction - surface.threshold) / (1.0 - surface.threshold), 0.0, 1.0);
+    let color: vec3<f32> = mix(surface.color.rgb * 1.3, surface.color.rgb, depth);
+    return vec4<f32>(color, surface.color.a * coverage);
+}
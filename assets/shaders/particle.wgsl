@@ -0,0 +1,21 @@
+// Default particle shader for JuiceBox's "Custom Particle Shader" option.  It draws particles the
+// same way as the built-in sprites; copy it and change the fragment function to restyle the fluid.
+// The file is reloaded automatically when it changes on disk.
+
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+struct ParticleInputs {
+    color: vec4<f32>,    // Color chosen by the "Color by" visualization option.
+    velocity: vec2<f32>, // Simulation velocity.
+    density: f32,        // Density of the grid cell the particle is in.
+    age: f32,            // Simulated seconds since the particle was spawned.
+};
+
+@group(1) @binding(0) var<uniform> particle: ParticleInputs;
+@group(1) @binding(1) var particle_texture: texture_2d<f32>;
+@group(1) @binding(2) var particle_sampler: sampler;
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+    return particle.color * textureSample(particle_texture, particle_sampler, mesh.uv);
+}
@@ -0,0 +1,45 @@
+// Draws every sprite-rendered particle in one instanced draw call; see particle_batch.rs.  Each
+// instance is one particle, and its quad is built from the vertex index alone, so there is no mesh.
+
+#import bevy_sprite::mesh2d_view_bindings::view
+
+struct ParticleInstance {
+    @location(0) position_size: vec4<f32>, // Position, then size; the last component is unused.
+    @location(1) color: vec4<f32>,         // Linear RGBA.
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@group(1) @binding(0) var particle_texture: texture_2d<f32>;
+@group(1) @binding(1) var particle_sampler: sampler;
+
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32, instance: ParticleInstance) -> VertexOutput {
+    // Two triangles covering the unit square, counter-clockwise.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner: vec2<f32> = corners[vertex_index % 6u];
+    let position: vec2<f32> =
+        instance.position_size.xy + (corner - vec2<f32>(0.5)) * instance.position_size.z;
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * textureSample(particle_texture, particle_sampler, in.uv);
+}
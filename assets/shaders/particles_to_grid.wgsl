@@ -0,0 +1,168 @@
+// Particle-to-grid transfer; see sim_gpu.rs.  Each velocity point gathers the particles in the cells
+// around it, which are sorted by cell (particles in cell i are cell_starts[i]..cell_starts[i + 1]).
+
+struct Params {
+    rows: u32,
+    cols: u32,
+    wrap_horizontal: u32,
+    wrap_vertical: u32,
+    cell_size: f32,
+    point_offset: f32,    // How far velocity points sit from the corners of their cells.
+    particle_count: u32,
+    apic: u32,
+    pic_ratio: f32,
+    _padding: u32,
+    gravity_step: vec2<f32>,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    affine_velocity: vec4<f32>, // The columns of a Mat2, one after the other.
+    weight: f32,                // Material density times mass.
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> particles: array<Particle>;
+@group(0) @binding(2) var<storage, read> cell_starts: array<u32>;
+// Whether each point of velocity_u (then of velocity_v) gets a velocity at all: 0 if not, 1 if so,
+// or IDLE_POINT if it's in an inactive chunk and stays at zero.
+@group(0) @binding(3) var<storage, read> transferred: array<u32>;
+@group(0) @binding(4) var<storage, read_write> velocity_u: array<f32>;
+@group(0) @binding(5) var<storage, read_write> velocity_v: array<f32>;
+
+const NO_VELOCITY: f32 = -3.40282347e38;
+const IDLE_POINT: u32 = 2u;
+
+// Same as SimGrid::wrap_offset(); Rust rounds halves away from zero, unlike WGSL's round().
+fn wrap_offset(offset: vec2<f32>) -> vec2<f32> {
+    let size: vec2<f32> = vec2<f32>(f32(params.cols), f32(params.rows)) * params.cell_size;
+    var wrapped: vec2<f32> = offset;
+    if params.wrap_horizontal != 0u {
+        let laps: f32 = offset.x / size.x;
+        wrapped.x = offset.x - size.x * (sign(laps) * floor(abs(laps) + 0.5));
+    }
+    if params.wrap_vertical != 0u {
+        let laps: f32 = offset.y / size.y;
+        wrapped.y = offset.y - size.y * (sign(laps) * floor(abs(laps) + 0.5));
+    }
+    return wrapped;
+}
+
+// Same as find_influence().
+fn find_influence(particle_position: vec2<f32>, point: vec2<f32>) -> f32 {
+    let scaled_distance: f32 = distance(point, particle_position) / params.cell_size;
+    if scaled_distance > 1.0 || scaled_distance <= 0.0 {
+        return 0.0;
+    }
+    return 1.0 - scaled_distance;
+}
+
+// Weighted average of one velocity component of every particle within a cell of `point`, found in
+// the rows and columns of cells given (as signed offsets from the point's cell).
+fn gather(point: vec2<f32>, row: i32, col: i32, last_row: i32, last_col: i32, component: u32) -> f32 {
+    var velocity_sum: f32 = 0.0;
+    var influence_sum: f32 = 0.0;
+    var visited: array<u32, 9>;
+    var visited_count: u32 = 0u;
+    for (var cell_row: i32 = row; cell_row <= last_row; cell_row++) {
+        for (var cell_col: i32 = col; cell_col <= last_col; cell_col++) {
+            var wrapped_row: i32 = cell_row;
+            var wrapped_col: i32 = cell_col;
+            if params.wrap_vertical != 0u {
+                wrapped_row = (cell_row + i32(params.rows)) % i32(params.rows);
+            }
+            if params.wrap_horizontal != 0u {
+                wrapped_col = (cell_col + i32(params.cols)) % i32(params.cols);
+            }
+            if wrapped_row < 0 || wrapped_row >= i32(params.rows) || wrapped_col < 0
+                || wrapped_col >= i32(params.cols) {
+                continue;
+            }
+
+            // Small wrapped grids can reach the same cell from both sides.
+            let cell: u32 = u32(wrapped_row) * params.cols + u32(wrapped_col);
+            var seen: bool = false;
+            for (var index: u32 = 0u; index < visited_count; index++) {
+                seen = seen || visited[index] == cell;
+            }
+            if seen {
+                continue;
+            }
+            visited[visited_count] = cell;
+            visited_count++;
+
+            for (var index: u32 = cell_starts[cell]; index < cell_starts[cell + 1u]; index++) {
+                let particle: Particle = particles[index];
+                let position: vec2<f32> = point - wrap_offset(point - particle.position);
+                let influence: f32 = find_influence(position, point) * particle.weight;
+                if influence != 0.0 {
+                    let offset: vec2<f32> = point - position;
+                    let affine_velocity: vec2<f32> = particle.affine_velocity.xy * offset.x
+                        + particle.affine_velocity.zw * offset.y;
+                    influence_sum += influence;
+                    velocity_sum += (particle.velocity[component] + affine_velocity[component])
+                        * influence;
+                }
+            }
+        }
+    }
+
+    if influence_sum == 0.0 {
+        return 0.0;
+    }
+    return velocity_sum / influence_sum;
+}
+
+@compute @workgroup_size(64)
+fn transfer_horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= params.rows * (params.cols + 1u) {
+        return;
+    }
+    if transferred[index] == 0u {
+        velocity_u[index] = NO_VELOCITY;
+        return;
+    }
+    if transferred[index] == IDLE_POINT {
+        velocity_u[index] = 0.0;
+        return;
+    }
+    let row: u32 = index / (params.cols + 1u);
+    let col: u32 = index % (params.cols + 1u);
+    let grid_height: f32 = f32(params.rows) * params.cell_size;
+    let point: vec2<f32> = vec2<f32>(
+        f32(col) * params.cell_size,
+        grid_height - (f32(row) * params.cell_size + params.point_offset),
+    );
+
+    // Particles within a cell of a point on a cell's left edge lie in the 3x2 cells around it.
+    velocity_u[index] = gather(point, i32(row) - 1, i32(col) - 1, i32(row) + 1, i32(col), 0u);
+}
+
+@compute @workgroup_size(64)
+fn transfer_vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= (params.rows + 1u) * params.cols {
+        return;
+    }
+    let kind: u32 = transferred[params.rows * (params.cols + 1u) + index];
+    if kind == 0u {
+        velocity_v[index] = NO_VELOCITY;
+        return;
+    }
+    if kind == IDLE_POINT {
+        velocity_v[index] = 0.0;
+        return;
+    }
+    let row: u32 = index / params.cols;
+    let col: u32 = index % params.cols;
+    let grid_height: f32 = f32(params.rows) * params.cell_size;
+    let point: vec2<f32> = vec2<f32>(
+        f32(col) * params.cell_size + params.point_offset,
+        grid_height - f32(row) * params.cell_size,
+    );
+
+    // Particles within a cell of a point on a cell's top edge lie in the 2x3 cells around it.
+    velocity_v[index] = gather(point, i32(row) - 1, i32(col) - 1, i32(row), i32(col) + 1, 1u);
+}
@@ -0,0 +1,136 @@
+// Red-black Gauss-Seidel pressure solve; see sim_gpu.rs.  Each iteration relaxes every cell of one
+// color at once (calculate_corrections), then moves each face by the corrections of the cells on
+// either side of it (apply_horizontal_corrections and apply_vertical_corrections).  Every iteration
+// is dispatched up front; once one has brought the divergence within tolerance, the rest do nothing.
+
+struct Params {
+    rows: u32,
+    cols: u32,
+    wrap_horizontal: u32,
+    wrap_vertical: u32,
+    color: u32,           // Which color of cells to relax; see cell_color().
+    iteration: u32,       // Which slot of `residuals` this iteration's divergence goes in.
+    overrelaxation: f32,
+    tolerance: f32,       // Fraction of the first iteration's divergence the solve stops at.
+}
+
+// Everything relaxing a cell depends on besides its faces' velocities (see SimCellRelaxation).
+struct Cell {
+    flows: vec4<f32>,     // Left, right, up, down.
+    weights: vec4<f32>,   // Left, right, up, down.
+    weight_sum: f32,
+    compression: f32,
+    relaxed: f32,         // 1 for cells the solver relaxes, 0 for the rest.
+    _padding: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> velocity_u: array<f32>;
+@group(0) @binding(2) var<storage, read_write> velocity_v: array<f32>;
+@group(0) @binding(3) var<storage, read> cells: array<Cell>;
+@group(0) @binding(4) var<storage, read_write> corrections: array<vec4<f32>>;
+@group(0) @binding(5) var<storage, read_write> pressure: array<f32>;
+@group(0) @binding(6) var<storage, read_write> residuals: array<atomic<u32>>;
+
+// Colored like a checkerboard, except that when the grid wraps around an odd number of cells, the
+// last column (and row) get colors of their own; see gauss_seidel_color() in sim_physics_engine.rs.
+fn cell_color(row: u32, col: u32) -> u32 {
+    let odd_wrap_horizontal: bool = params.wrap_horizontal != 0u && params.cols % 2u == 1u;
+    let odd_wrap_vertical: bool = params.wrap_vertical != 0u && params.rows % 2u == 1u;
+    let last_col: bool = odd_wrap_horizontal && col == params.cols - 1u;
+    let last_row: bool = odd_wrap_vertical && row == params.rows - 1u;
+    return (row + col) % 2u
+        + 2u * (u32(last_col) + (1u + u32(odd_wrap_horizontal)) * u32(last_row));
+}
+
+@compute @workgroup_size(64)
+fn calculate_corrections(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= params.rows * params.cols {
+        return;
+    }
+    let row: u32 = index / params.cols;
+    let col: u32 = index % params.cols;
+    corrections[index] = vec4<f32>(0.0);
+
+    // The iteration before this one measured its divergence in full; stop if that was good enough.
+    if params.iteration > 0u {
+        let initial: f32 = bitcast<f32>(atomicLoad(&residuals[0]));
+        let last: f32 = bitcast<f32>(atomicLoad(&residuals[params.iteration - 1u]));
+        if last <= params.tolerance * initial {
+            return;
+        }
+    }
+
+    let cell: Cell = cells[index];
+    if cell_color(row, col) != params.color || cell.relaxed == 0.0 {
+        return;
+    }
+
+    // Inflow counts as negative divergence, as does over-compression.
+    let left: f32 = velocity_u[row * (params.cols + 1u) + col] * cell.flows.x;
+    let right: f32 = velocity_u[row * (params.cols + 1u) + col + 1u] * cell.flows.y;
+    let up: f32 = velocity_v[row * params.cols + col] * cell.flows.z;
+    let down: f32 = velocity_v[(row + 1u) * params.cols + col] * cell.flows.w;
+    let divergence: f32 = ((right - left) + (up - down)) - cell.compression;
+
+    // Non-negative floats sort the same way as their bits do.
+    atomicMax(&residuals[params.iteration], bitcast<u32>(abs(divergence)));
+
+    if cell.weight_sum <= 0.0 {
+        return;
+    }
+    let momentum: f32 = params.overrelaxation * ((0.0 - divergence) / cell.weight_sum);
+    corrections[index] = cell.weights * momentum;
+    pressure[index] += momentum;
+}
+
+@compute @workgroup_size(64)
+fn apply_horizontal_corrections(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= params.rows * (params.cols + 1u) {
+        return;
+    }
+    let row: u32 = index / (params.cols + 1u);
+    let col: u32 = index % (params.cols + 1u);
+    let wraps: bool = params.wrap_horizontal != 0u;
+
+    // The face is the left face of the cell to its right, and the right face of the cell to its left.
+    var velocity: f32 = velocity_u[index];
+    if col < params.cols {
+        velocity -= corrections[row * params.cols + col].x;
+    } else if wraps {
+        velocity -= corrections[row * params.cols].x;
+    }
+    if col > 0u {
+        velocity += corrections[row * params.cols + col - 1u].y;
+    } else if wraps {
+        velocity += corrections[row * params.cols + params.cols - 1u].y;
+    }
+    velocity_u[index] = velocity;
+}
+
+@compute @workgroup_size(64)
+fn apply_vertical_corrections(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index: u32 = id.x;
+    if index >= (params.rows + 1u) * params.cols {
+        return;
+    }
+    let row: u32 = index / params.cols;
+    let col: u32 = index % params.cols;
+    let wraps: bool = params.wrap_vertical != 0u;
+
+    // The face is the up face of the cell below it, and the down face of the cell above it.
+    var velocity: f32 = velocity_v[index];
+    if row < params.rows {
+        velocity += corrections[row * params.cols + col].z;
+    } else if wraps {
+        velocity += corrections[col].z;
+    }
+    if row > 0u {
+        velocity -= corrections[(row - 1u) * params.cols + col].w;
+    } else if wraps {
+        velocity -= corrections[(params.rows - 1u) * params.cols + col].w;
+    }
+    velocity_v[index] = velocity;
+}
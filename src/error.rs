@@ -23,4 +23,13 @@ pub enum Error {
 
     #[error("Cannot connect to file explorer: `{0}`")]
     FileExplorer(&'static str),
+
+    #[error("Invalid scene: `{0}`")]
+    InvalidScene(&'static str),
+
+    #[error("Invalid region: `{0}`")]
+    InvalidRegion(&'static str),
+
+    #[error("Invalid keyframe: `{0}`")]
+    InvalidKeyframe(&'static str),
 }
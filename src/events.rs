@@ -1,5 +1,7 @@
 use crate::file_system;
-use crate::juice_renderer::FluidColorRenderType;
+use crate::juice_renderer::{FluidColorRenderType, FluidSizeRenderType, GridHeatmapType};
+use crate::simulation::sim_sequencer::SimKeyframe;
+use crate::simulation::PARTICLE_GROUP_COUNT;
 use crate::ui::{SimTool, UIStateManager};
 use bevy::ecs::event::Event;
 use bevy::prelude::*;
@@ -9,12 +11,13 @@ use bevy::prelude::*;
   the event to be handled by the simulation
   state manager
 */
-#[derive(Event)]
+#[derive(Event, Clone, Copy)]
 pub struct UseToolEvent {
     pub tool: SimTool,                     // Tool Used
     pub pos: Vec2,                         // Mouse position
     pub mouse_button: Option<MouseButton>, // Mouse button pressed
     pub mouse_held: bool,                  // Is the mouse being held or has it just been pressed?
+    pub domain: Option<Entity>,            // Extra simulation domain the tool was used on, if any
 }
 
 impl UseToolEvent {
@@ -24,29 +27,113 @@ impl UseToolEvent {
         pos: Vec2,
         mouse_button: Option<MouseButton>,
         mouse_held: bool,
+        domain: Option<Entity>,
     ) -> Self {
         Self {
             tool,
             pos,
             mouse_button,
             mouse_held,
+            domain,
         }
     }
 }
 
 /**
-    Reset event for reseting the simulation.
-    Handled by the simulation state manager
+    Describes the scene a reset should rebuild the simulation into.
+    Named presets are built by the simulation state manager, while
+    files are loaded by the file system.
 */
-#[derive(Event)]
-pub struct ResetEvent;
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SceneDescriptor {
+    #[default]
+    Default, // The default template layout.
+    Preset(String), // A named, built-in scene layout.
+    File(String),   // Path to a .juice file, without its extension.
+}
+
+/**
+    Reset event for reseting the simulation into a scene.
+    Handled by the simulation state manager, or by the
+    file system when the scene is a file
+*/
+#[derive(Event, Default)]
+pub struct ResetEvent {
+    pub scene: SceneDescriptor, // Scene to rebuild the simulation into
+}
+
+impl ResetEvent {
+    /// New function for creating new events
+    pub fn new(scene: SceneDescriptor) -> Self {
+        Self { scene }
+    }
+}
 
 /**
-    Deletes all particles, drains, and faucets.
+    Clears the selected parts of the scene; particles, walls,
+    and emitters (faucets, drains, attractors, and wind) can each be kept, and
+    clearing particles can be limited to a single group.
     Handled by the simulation state manager
 */
-#[derive(Event)]
-pub struct ClearEvent;
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct ClearEvent {
+    pub particles: bool,   // Delete all particles?
+    pub walls: bool,       // Turn all non-border solid cells back into air?
+    pub emitters: bool,    // Delete all faucets, drains, and attractors, and calm the wind?
+    pub group: Option<u8>, // Only delete particles tagged with this group?
+}
+
+impl ClearEvent {
+    /// Clear particles, walls, faucets, and drains.
+    pub fn everything() -> Self {
+        Self {
+            particles: true,
+            walls: true,
+            emitters: true,
+            group: None,
+        }
+    }
+
+    /// Clear only the particles, keeping walls, faucets, and drains in place.
+    pub fn particles_only() -> Self {
+        Self {
+            particles: true,
+            walls: false,
+            emitters: false,
+            group: None,
+        }
+    }
+
+    /// Clear only the walls, keeping the fluid and its faucets and drains.
+    pub fn walls_only() -> Self {
+        Self {
+            particles: false,
+            walls: true,
+            emitters: false,
+            group: None,
+        }
+    }
+
+    /// Clear only the particles tagged with `group`, keeping everything else.
+    pub fn group(group: u8) -> Self {
+        Self {
+            particles: true,
+            walls: false,
+            emitters: false,
+            group: Some(group),
+        }
+    }
+
+    /// Clear only faucets, drains, attractors, and wind.
+    pub fn emitters_only() -> Self {
+        Self {
+            particles: false,
+            walls: false,
+            emitters: true,
+            group: None,
+        }
+    }
+}
 
 #[derive(Event)]
 pub struct FileEvent {
@@ -72,15 +159,47 @@ impl PlayPauseStepEvent {
     }
 }
 
+/**
+    Edits to the scene's timeline made from the sequencer
+    panel.  Handled by the simulation's sequencer
+*/
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub enum SequencerEvent {
+    AddKeyframe(SimKeyframe), // Schedule a new keyframe
+    RemoveKeyframe(usize),    // Remove the keyframe at this index
+}
+
 #[derive(Event)]
 pub struct ModifyVisualizationEvent {
     pub show_grid: bool,
+    pub show_cell_types: bool,
     pub show_velocities: bool,
+    pub show_streamlines: bool,
+    pub streamlines_from_cursor: bool,
     pub show_gravity: bool,
+    pub show_temperature: bool,
+    pub show_liquid_surface: bool,
+    pub show_liquid_outline: bool,
+    pub show_trails: bool,
+    pub trail_length: usize,
+    pub trail_fade: f32,
+    pub show_onion_skin: bool,
+    pub onion_skin_frames: usize,
+    pub heatmap_variable: GridHeatmapType,
 
     pub color_variable: FluidColorRenderType,
     pub fluid_colors: [[f32; 3]; 4],
     pub particle_size: f32,
+    pub size_variable: FluidSizeRenderType,
+    pub bloom: bool,
+    pub background_image: Option<String>, // Background image path, relative to the assets folder.
+    pub background_scale: f32,
+    pub background_offset: Vec2,
+    pub custom_shader: Option<String>, // Particle shader path, relative to the assets folder.
+
+    // Which particle groups are drawn, and the tint (if any) each group is drawn with.
+    pub group_visible: [bool; PARTICLE_GROUP_COUNT],
+    pub group_tints: [Option<[f32; 3]>; PARTICLE_GROUP_COUNT],
 }
 
 /* Create a new visualization modification event, copying the appropriate parameters from the UI
@@ -91,16 +210,64 @@ impl ModifyVisualizationEvent {
         let fluid_color_variable: FluidColorRenderType = match ui_state.fluid_color_variable {
             0 => FluidColorRenderType::Velocity,
             1 => FluidColorRenderType::Density,
+            2 => FluidColorRenderType::Temperature,
+            3 => FluidColorRenderType::Vorticity,
+            4 => FluidColorRenderType::Pressure,
+            5 => FluidColorRenderType::Material,
             _ => FluidColorRenderType::Arbitrary,
         };
+        let size_variable: FluidSizeRenderType = match ui_state.particle_size_variable {
+            1 => FluidSizeRenderType::Density,
+            2 => FluidSizeRenderType::Speed,
+            _ => FluidSizeRenderType::Radius,
+        };
+        let heatmap_variable: GridHeatmapType = match ui_state.grid_heatmap_variable {
+            1 => GridHeatmapType::Pressure,
+            2 => GridHeatmapType::Divergence,
+            3 => GridHeatmapType::Density,
+            4 => GridHeatmapType::Vorticity,
+            _ => GridHeatmapType::None,
+        };
+
+        let mut group_tints: [Option<[f32; 3]>; PARTICLE_GROUP_COUNT] =
+            [None; PARTICLE_GROUP_COUNT];
+        for group in 0..PARTICLE_GROUP_COUNT {
+            if ui_state.group_tinted[group] {
+                group_tints[group] = Some(ui_state.group_tints[group]);
+            }
+        }
 
         Self {
             show_grid: ui_state.show_grid,
+            show_cell_types: ui_state.show_cell_types,
             show_velocities: ui_state.show_velocity_vectors,
+            show_streamlines: ui_state.show_streamlines,
+            streamlines_from_cursor: ui_state.streamlines_from_cursor,
             show_gravity: ui_state.show_gravity_vector,
+            show_temperature: ui_state.show_temperature,
+            show_liquid_surface: ui_state.show_liquid_surface,
+            show_liquid_outline: ui_state.show_liquid_outline,
+            show_trails: ui_state.show_particle_trails,
+            trail_length: ui_state.trail_length,
+            trail_fade: ui_state.trail_fade,
+            show_onion_skin: ui_state.show_onion_skin,
+            onion_skin_frames: ui_state.onion_skin_frames,
+            heatmap_variable: heatmap_variable,
             color_variable: fluid_color_variable,
             fluid_colors: ui_state.fluid_colors,
             particle_size: ui_state.particle_physical_size,
+            size_variable: size_variable,
+            bloom: ui_state.use_bloom,
+            background_image: ui_state
+                .use_background_image
+                .then(|| ui_state.background_image_path.clone()),
+            background_scale: ui_state.background_scale,
+            background_offset: Vec2::from(ui_state.background_offset),
+            custom_shader: ui_state
+                .use_custom_shader
+                .then(|| ui_state.custom_shader_path.clone()),
+            group_visible: ui_state.group_visible,
+            group_tints: group_tints,
         }
     }
 }
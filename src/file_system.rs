@@ -9,8 +9,23 @@ use std;
 use std::path::PathBuf;
 
 use crate::error::Error;
+use crate::events::{ResetEvent, SceneDescriptor};
+use crate::simulation::sim_domains::SimDomainMember;
+use crate::simulation::sim_flow_meter::SimFlowMeter;
+use crate::simulation::sim_inflow::SimInflowProfile;
+use crate::simulation::sim_particle_pool::{SimInactiveParticle, SimParticlePool};
+use crate::simulation::sim_pressure_solver::SimPressureSolver;
+use crate::simulation::sim_probes::SimProbe;
+use crate::simulation::sim_pump::{SimPump, SimPumpedParticle};
+use crate::simulation::sim_safeguards::SimSafeguardResponse;
+use crate::simulation::sim_sequencer::{SimKeyframe, SimSequencer, SimSequencerAction};
+use crate::simulation::sim_spatial_lookup::SimSpatialLookup;
+use crate::simulation::sim_sph::SimSolverKind;
+use crate::simulation::sim_step_task::finish_background_step;
 use crate::simulation::{
-    SimConstraints, SimDrain, SimFaucet, SimGrid, SimGridCellType, SimParticle, SimSurfaceDirection,
+    SimAdvectionScheme, SimAttractor, SimConstraints, SimContainer, SimDrain, SimEdgeBoundary,
+    SimFaucet, SimFaucetSchedule, SimFaucetShape, SimFluidMaterial, SimGrid, SimGridCellType,
+    SimParticle, SimSpinner, SimSurfaceDirection, SimTransferScheme, SimWallMaterial, SimWallSlip,
 };
 use crate::ui::UIStateManager;
 
@@ -22,16 +37,27 @@ pub struct FileSystem;
 impl Plugin for FileSystem {
     fn build(&self, app: &mut App) {
         app.insert_resource(CurrentFile::default());
+        app.insert_resource(PendingSceneFile::default());
+        app.insert_resource(PendingNewSceneSave::default());
 
         // Setting up the type registry so the data can be accessed
 
         // Registering SimParticle and it's associated types
         app.register_type::<SimParticle>();
+        app.register_type::<Vec<SimParticle>>();
+        app.register_type::<Vec<Vec<SimParticle>>>(); // Needed for loading narrow-band FLIP's interior
+        app.register_type::<Mat2>(); // Needed for loading a particle's affine_velocity
         app.register_type::<Option<Vec2>>(); // Needed for loading position, velocity, and any other Vec2 types
 
         // Registering SimConstraints
         // All associated types are f32, usize, u8, and Vec2. All already registered
         app.register_type::<SimConstraints>();
+        app.register_type::<SimSafeguardResponse>();
+        app.register_type::<SimPressureSolver>();
+        app.register_type::<SimSolverKind>();
+        app.register_type::<SimTransferScheme>();
+        app.register_type::<SimAdvectionScheme>();
+        app.register_type::<SimInflowProfile>();
         app.register_type::<(Entity, Vec2)>();
         app.register_type::<Vec<(Entity, Vec2)>>();
 
@@ -41,16 +67,50 @@ impl Plugin for FileSystem {
         app.register_type::<SimGridCellType>();
         app.register_type::<Vec<SimGridCellType>>();
         app.register_type::<Vec<Vec<SimGridCellType>>>(); // Needed for loading the cell_type
+        app.register_type::<SimWallMaterial>();
+        app.register_type::<Vec<SimWallMaterial>>();
+        app.register_type::<Vec<Vec<SimWallMaterial>>>(); // Needed for loading the cell_material
+        app.register_type::<SimFluidMaterial>();
         app.register_type::<Vec<f32>>();
         app.register_type::<Vec<Vec<f32>>>(); // Needed for loading cell_center, velocity_u, velocity_v, and density
         app.register_type::<Vec<Entity>>();
-        app.register_type::<Vec<Vec<Entity>>>(); // Needed for loading spatial_lookup
         app.register_type::<Option<Rect>>(); // Pretty sure needed for loading any <Vec<Vec<T>>>()
+        app.register_type::<Option<Vec2>>();
+        app.register_type::<Vec<Option<Vec2>>>(); // Needed for loading moving_solid_velocity
+        app.register_type::<SimEdgeBoundary>();
+        app.register_type::<[SimEdgeBoundary; 4]>(); // Needed for loading edge_boundaries
 
         // Registering SimFaucet, SimDrain, and their associated types
         app.register_type::<SimFaucet>();
+        app.register_type::<SimFaucetShape>();
+        app.register_type::<SimFaucetSchedule>();
+        app.register_type::<Vec<Vec2>>(); // Needed for loading a faucet's path
         app.register_type::<SimDrain>();
         app.register_type::<SimSurfaceDirection>();
+        app.register_type::<Option<SimSurfaceDirection>>();
+        app.register_type::<Vec<Option<SimSurfaceDirection>>>(); // Needed for loading valves
+        app.register_type::<SimWallSlip>();
+        app.register_type::<Option<SimWallSlip>>();
+        app.register_type::<Vec<Option<SimWallSlip>>>(); // Needed for loading walls' slip conditions
+        app.register_type::<Option<f32>>(); // Needed for loading a drain's capacity
+        app.register_type::<Option<usize>>(); // Needed for loading a drain's volume
+        app.register_type::<SimContainer>();
+        app.register_type::<SimSpinner>();
+        app.register_type::<SimAttractor>();
+        app.register_type::<SimPump>();
+        app.register_type::<SimPumpedParticle>();
+        app.register_type::<Vec<SimPumpedParticle>>(); // Needed for loading the fluid inside pumps
+        app.register_type::<SimProbe>();
+        app.register_type::<SimFlowMeter>();
+
+        // Registering the scene's timeline and its associated types
+        app.register_type::<SimSequencer>();
+        app.register_type::<SimKeyframe>();
+        app.register_type::<Vec<SimKeyframe>>();
+        app.register_type::<SimSequencerAction>();
+
+        // Registering the time quick saves are taken at
+        app.register_type::<QuickSaveTime>();
 
         // Loading and saving funcitonality is called using Bevy's state transitions
         // Since they have direct world and file access, they freeze all other processes. This is to prevent them being scheduled in Update.
@@ -60,6 +120,15 @@ impl Plugin for FileSystem {
         app.add_systems(OnEnter(JuiceStates::Reloading), handle_reloading);
         app.add_systems(OnEnter(JuiceStates::Saving), handle_saving);
         app.add_systems(OnEnter(JuiceStates::SavingAs), handle_saving_as);
+        app.add_systems(OnEnter(JuiceStates::ResettingScene), handle_scene_reset);
+        app.add_systems(OnEnter(JuiceStates::QuickSaving), handle_quick_saving);
+        app.add_systems(OnEnter(JuiceStates::QuickLoading), handle_quick_loading);
+        app.add_systems(Update, queue_scene_file_reset);
+        // Only once we're back to running, so the reset has been applied before saving.
+        app.add_systems(
+            Update,
+            save_new_scene.run_if(in_state(JuiceStates::Running)),
+        );
         app.add_systems(OnExit(JuiceStates::Running), reset_file_state); // Scheduled after handle_loading or handle_saving since it can't run in parellel.
     }
 }
@@ -83,6 +152,16 @@ impl CurrentFile {
     }
 }
 
+/// Whether a new file has been reset into the default layout, but not saved yet.
+#[derive(Resource, Default)]
+pub struct PendingNewSceneSave(bool);
+
+/// Scene file requested by the most recent ResetEvent, loaded once we have exclusive world access.
+#[derive(Resource, Default)]
+pub struct PendingSceneFile {
+    filepath: Option<String>,
+}
+
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum JuiceStates {
     Running,
@@ -91,6 +170,9 @@ pub enum JuiceStates {
     Reloading,
     Saving,
     SavingAs,
+    ResettingScene,
+    QuickSaving,
+    QuickLoading,
 }
 
 impl Default for JuiceStates {
@@ -119,7 +201,23 @@ impl Format for JUICEFormat {
         reader: R,
         seed: S,
     ) -> Result<T, bevy_save::Error> {
-        JSONFormat::deserialize(reader, seed)
+        let mut file: serde_json::Value =
+            serde_json::from_reader(reader).map_err(bevy_save::Error::loading)?;
+        upgrade_scene_file(&mut file);
+
+        seed.deserialize(file).map_err(bevy_save::Error::loading)
+    }
+}
+
+/// Drops anything an older scene file saved that's no longer part of the simulation, so it still loads.
+fn upgrade_scene_file(file: &mut serde_json::Value) {
+    // The spatial lookup used to be saved along with the grid; it's rebuilt after loading now.
+    let grid_pointer: String = format!("/resources/{}", SimGrid::type_path());
+    if let Some(grid) = file
+        .pointer_mut(&grid_pointer)
+        .and_then(|grid| grid.as_object_mut())
+    {
+        grid.remove("spatial_lookup");
     }
 }
 
@@ -144,7 +242,7 @@ impl Pipeline for JuicePipeline {
         return &self.key;
     }
 
-    /// Generates a snapshot of bevy's world, the current SimGrid, SimConstraints, all SimParticles,
+    /// Generates a snapshot of bevy's world, the current SimGrid, SimConstraints, SimSequencer, all SimParticles,
     /// all SimDrains, and all SimFaucets.
     ///
     /// This is the Pipeline's way to save files. Most of the implementation is in bevy_save.
@@ -154,11 +252,17 @@ impl Pipeline for JuicePipeline {
             .allow::<SimGrid>()
             .allow::<SimConstraints>()
             .allow::<SimParticle>()
+            .allow::<SimSequencer>()
             // .allow::<SimFaucet>()
             // .allow::<SimDrain>()
             .extract_resource::<SimGrid>()
             .extract_resource::<SimConstraints>()
-            .extract_entities_matching(|e| e.contains::<SimParticle>())
+            .extract_resource::<SimSequencer>()
+            .extract_entities_matching(|e| {
+                e.contains::<SimParticle>()
+                    && !e.contains::<SimInactiveParticle>()
+                    && !e.contains::<SimDomainMember>()
+            })
             // .extract_entities_matching(|e| e.contains::<SimFaucet>())
             // .extract_entities_matching(|e| e.contains::<SimDrain>())
             .build()
@@ -169,10 +273,147 @@ impl Pipeline for JuicePipeline {
     ///
     /// This is the Pipeline's way to load files. Most of the implementation is in bevy_save.
     fn apply(world: &mut World, snapshot: &Snapshot) -> Result<(), bevy_save::Error> {
-        snapshot
+        // A job still in flight would land on top of the loaded scene.
+        finish_background_step(world);
+
+        /* Take the saved resources out first, so they're rebuilt from the file (with defaults for
+        anything the file predates) instead of having the file merged into the current scene. */
+        let constraints: Option<SimConstraints> = world.remove_resource::<SimConstraints>();
+        let grid: Option<SimGrid> = world.remove_resource::<SimGrid>();
+        world.remove_resource::<SimSequencer>();
+
+        let result: Result<(), bevy_save::Error> = snapshot
+            .applier(world)
+            // Despawning all entities, except those of extra domains, which scenes don't save.
+            .despawn::<(
+                Or<(With<SimParticle>, With<SimFaucet>, With<SimDrain>)>,
+                Without<SimDomainMember>,
+            )>()
+            .apply();
+
+        restore_unsaved_state(world, constraints, grid);
+
+        result
+    }
+}
+
+/** Carry over what scene files don't save from the resources a scene was loaded over, and put back
+any resource the file didn't have at all. */
+fn restore_unsaved_state(
+    world: &mut World,
+    constraints: Option<SimConstraints>,
+    grid: Option<SimGrid>,
+) {
+    if let Some(constraints) = constraints {
+        if !world.contains_resource::<SimConstraints>() {
+            world.insert_resource(constraints);
+        }
+    }
+
+    if let Some(grid) = grid {
+        match world.get_resource_mut::<SimGrid>() {
+            // The pooled particles' entities are despawned by loading, so the pool starts out empty.
+            Some(mut loaded_grid) => loaded_grid.fit_cell_data(),
+            None => world.insert_resource(grid),
+        }
+    }
+
+    if !world.contains_resource::<SimSequencer>() {
+        world.insert_resource(SimSequencer::default());
+    }
+}
+
+/// Simulated time a quick save was taken at, so quick loading picks the scene's timeline back up where it left off.
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+struct QuickSaveTime(f32);
+
+/// Pipeline for quick saves. Unlike scene files, quick saves snapshot the entire simulation (faucets, drains, and
+/// containers included) into a named slot in bevy_save's save directory, using its compact binary format.
+struct QuickSavePipeline {
+    slot: String, // Name of the slot to save to or load from.
+}
+
+impl QuickSavePipeline {
+    pub fn new(slot: String) -> Self {
+        Self { slot: slot }
+    }
+}
+
+impl Pipeline for QuickSavePipeline {
+    type Backend = DefaultBackend; // Saves into bevy_save's save directory, rather than next to the scene files.
+    type Format = DefaultFormat; // MessagePack; quick saves aren't meant to be edited by hand.
+
+    type Key<'a> = &'a str;
+
+    fn key(&self) -> Self::Key<'_> {
+        return &self.slot;
+    }
+
+    /// Generates a snapshot of everything in the simulation.
+    fn capture(builder: SnapshotBuilder) -> Snapshot {
+        builder
+            .deny_all()
+            .allow::<SimGrid>()
+            .allow::<SimConstraints>()
+            .allow::<SimSequencer>()
+            .allow::<QuickSaveTime>()
+            .allow::<SimParticle>()
+            .allow::<SimFaucet>()
+            .allow::<SimDrain>()
+            .allow::<SimContainer>()
+            .allow::<SimSpinner>()
+            .allow::<SimAttractor>()
+            .allow::<SimPump>()
+            .allow::<SimProbe>()
+            .allow::<SimFlowMeter>()
+            .extract_resource::<SimGrid>()
+            .extract_resource::<SimConstraints>()
+            .extract_resource::<SimSequencer>()
+            .extract_resource::<QuickSaveTime>()
+            .extract_entities_matching(|e| {
+                !e.contains::<SimDomainMember>()
+                    && ((e.contains::<SimParticle>() && !e.contains::<SimInactiveParticle>())
+                        || e.contains::<SimFaucet>()
+                        || e.contains::<SimDrain>()
+                        || e.contains::<SimContainer>()
+                        || e.contains::<SimSpinner>()
+                        || e.contains::<SimAttractor>()
+                        || e.contains::<SimPump>()
+                        || e.contains::<SimProbe>()
+                        || e.contains::<SimFlowMeter>())
+            })
+            .build()
+    }
+
+    /// Despawns everything in the simulation, then restores a quick save.
+    fn apply(world: &mut World, snapshot: &Snapshot) -> Result<(), bevy_save::Error> {
+        finish_background_step(world);
+
+        let result: Result<(), bevy_save::Error> = snapshot
             .applier(world)
-            .despawn::<Or<(With<SimParticle>, With<SimFaucet>, With<SimDrain>)>>() // Despawning all entities.
-            .apply()
+            .despawn::<(
+                Or<(
+                    With<SimParticle>,
+                    With<SimFaucet>,
+                    With<SimDrain>,
+                    With<SimContainer>,
+                    With<SimSpinner>,
+                    With<SimAttractor>,
+                    With<SimPump>,
+                    With<SimProbe>,
+                    With<SimFlowMeter>,
+                )>,
+                Without<SimDomainMember>,
+            )>()
+            .apply();
+
+        // Pooled particles are despawned along with the rest, so there's nothing left to reuse.
+        if let Some(mut grid) = world.get_resource_mut::<SimGrid>() {
+            grid.particle_pool = SimParticlePool::default();
+        }
+
+        result
     }
 }
 
@@ -191,8 +432,50 @@ fn handle_new_scene(world: &mut World) {
         current_file.filepath = key.clone();
     };
 
-    load_scene(String::from("metadata/default-file"), world);
-    save_scene(key, world);
+    /* Start the new file from the default layout through the usual reset, and save it once the reset
+    has been applied. */
+    world.send_event(ResetEvent::default());
+    world.insert_resource(PendingNewSceneSave(true));
+}
+
+/// Saves a new file once the reset into its default layout has been applied.
+fn save_new_scene(
+    mut pending_save: ResMut<PendingNewSceneSave>,
+    mut ui_state: ResMut<UIStateManager>,
+) {
+    if pending_save.0 {
+        pending_save.0 = false;
+        ui_state.file_state = JuiceStates::Saving;
+    }
+}
+
+/// Picks up ResetEvents that target a scene file and switches to JuiceStates::ResettingScene to load it.
+fn queue_scene_file_reset(
+    mut ev_reset: EventReader<ResetEvent>,
+    mut pending_scene: ResMut<PendingSceneFile>,
+    mut file_state: ResMut<NextState<JuiceStates>>,
+) {
+    for ev in ev_reset.read() {
+        if let SceneDescriptor::File(key) = &ev.scene {
+            pending_scene.filepath = Some(key.clone());
+            file_state.set(JuiceStates::ResettingScene);
+        }
+    }
+}
+
+/// Loads the scene file requested by a ResetEvent. Function runs when state = JuiceStates::ResettingScene.
+///
+/// Does not change CurrentFile, so saving afterwards won't overwrite the scene file.
+fn handle_scene_reset(world: &mut World) {
+    let key: String = match world.get_resource_mut::<PendingSceneFile>() {
+        Some(mut pending_scene) => match pending_scene.filepath.take() {
+            Some(key) => key,
+            None => return (),
+        },
+        None => return (),
+    };
+
+    load_scene(key, world);
 }
 
 /// Runs file dialog asking user for filepath, loads the file into the world. Function runs when state = JuiceStates::Loading.
@@ -252,6 +535,64 @@ fn handle_saving_as(world: &mut World) {
     save_scene(key, world);
 }
 
+/// Quick saves the simulation into the slot named in the UI. Function runs when state = JuiceStates::QuickSaving.
+fn handle_quick_saving(world: &mut World) {
+    let slot: String = match world.get_resource::<UIStateManager>() {
+        Some(ui_state) => ui_state.quick_save_slot.clone(),
+        None => return (),
+    };
+    let simulated_time: f32 = match world.get_resource::<SimConstraints>() {
+        Some(constraints) => constraints.simulated_time,
+        None => return (),
+    };
+    world.insert_resource(QuickSaveTime(simulated_time));
+
+    match world.save(QuickSavePipeline::new(slot.clone())) {
+        Ok(_ok) => println!("Quick saved to slot \"{}\".", slot),
+        Err(_e) => println!(
+            "{}",
+            Error::FileExplorer("Did not quick save correctly, perhaps the slot name is invalid?")
+        ),
+    }
+}
+
+/// Restores the quick save in the slot named in the UI. Function runs when state = JuiceStates::QuickLoading.
+fn handle_quick_loading(world: &mut World) {
+    let slot: String = match world.get_resource::<UIStateManager>() {
+        Some(ui_state) => ui_state.quick_save_slot.clone(),
+        None => return (),
+    };
+
+    match world.load(QuickSavePipeline::new(slot.clone())) {
+        Ok(_ok) => println!("Quick loaded slot \"{}\".", slot),
+        Err(_e) => {
+            println!(
+                "{}",
+                Error::FileExplorer(
+                    "Did not quick load correctly, perhaps nothing was saved to this slot?"
+                )
+            );
+            return ();
+        }
+    }
+
+    clear_spatial_lookup(world);
+    settle_loaded_particles(world);
+    show_loaded_settings(world);
+
+    // Pick the scene's timeline back up where the quick save left off.
+    let simulated_time: f32 = world
+        .get_resource::<QuickSaveTime>()
+        .map_or(0.0, |quick_save_time| quick_save_time.0);
+    if let Some(mut constraints) = world.get_resource_mut::<SimConstraints>() {
+        constraints.simulated_time = simulated_time;
+        constraints.selected_particles.clear();
+    }
+    if let Some(mut sequencer) = world.get_resource_mut::<SimSequencer>() {
+        sequencer.seek(simulated_time);
+    }
+}
+
 /// Sets state back to JuiceStates::Running.
 fn reset_file_state(
     mut file_state: ResMut<NextState<JuiceStates>>,
@@ -337,7 +678,7 @@ fn create_new_file() -> Result<String, Error> {
 }
 
 /// Initiate new pipeline and load scene to key.
-fn load_scene(key: String, world: &mut World) {
+pub fn load_scene(key: String, world: &mut World) {
     match world.load(JuicePipeline::new(key)) {
         Ok(_ok) => {}
         Err(_e) => {
@@ -351,26 +692,59 @@ fn load_scene(key: String, world: &mut World) {
         }
     }
 
-    // Erase the spatial lookup table, this will cause "ghost particles" otherwise.
-    if let Some(mut grid) = world.get_resource_mut::<SimGrid>() {
-        grid.spatial_lookup = vec![
-            vec![Entity::PLACEHOLDER; 0];
-            grid.dimensions.0 as usize * grid.dimensions.1 as usize
-        ];
-    } else {
-        println!("Grid not constructed in time; please reset simulation before continuing!");
-    }
+    clear_spatial_lookup(world);
+    settle_loaded_particles(world);
+    show_loaded_settings(world);
 
-    // Pause the simulation once we have loaded in!
+    // Pause the simulation once we have loaded in, and play the scene's timeline from the start!
     if let Some(mut constraints) = world.get_resource_mut::<SimConstraints>() {
         constraints.is_paused = true;
+        constraints.simulated_time = 0.0;
     } else {
         println!("Constraints not constructed in time; cannot pause!");
     }
+    if let Some(mut sequencer) = world.get_resource_mut::<SimSequencer>() {
+        sequencer.restart();
+    }
+}
+
+/// Erase the spatial lookup table after loading, this will cause "ghost particles" otherwise.
+fn clear_spatial_lookup(world: &mut World) {
+    if let Some(mut grid) = world.get_resource_mut::<SimGrid>() {
+        grid.spatial_lookup =
+            SimSpatialLookup::new(grid.dimensions.0 as usize * grid.dimensions.1 as usize);
+    } else {
+        println!("Grid not constructed in time; please reset simulation before continuing!");
+    }
+}
+
+/// Loaded particles haven't moved yet, so don't draw them sliding in from where they were before the last step.
+fn settle_loaded_particles(world: &mut World) {
+    for mut particle in world
+        .query_filtered::<&mut SimParticle, Without<SimInactiveParticle>>()
+        .iter_mut(world)
+    {
+        particle.previous_position = particle.position;
+    }
+}
+
+/// The UI writes its settings into the simulation when they change, so show it the ones we loaded.
+fn show_loaded_settings(world: &mut World) {
+    if !world.contains_resource::<UIStateManager>() {
+        return;
+    }
+    world.resource_scope(|world: &mut World, mut ui_state: Mut<UIStateManager>| {
+        if let (Some(constraints), Some(grid)) = (
+            world.get_resource::<SimConstraints>(),
+            world.get_resource::<SimGrid>(),
+        ) {
+            ui_state.read_simulation_settings(constraints, grid);
+        }
+    });
 }
 
 /// Initiate new pipeline and save scene to key.
-fn save_scene(key: String, world: &mut World) {
+pub fn save_scene(key: String, world: &mut World) {
     match world.save(JuicePipeline::new(key)) {
         Ok(_ok) => {}
         Err(_e) => {
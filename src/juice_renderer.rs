@@ -1,15 +1,64 @@
-use std::f32::consts::PI;
+use std::{collections::VecDeque, f32::consts::PI};
 
 use crate::{
     events::ModifyVisualizationEvent,
-    simulation::{SimConstraints, SimDrain, SimFaucet, SimGrid, SimGridCellType, SimParticle},
+    particle_batch::{ParticleBatch, ParticleBatchPlugin, ParticleInstance},
+    simulation::{
+        self,
+        sim_domains::{grid_extent, SimDomain, SimDomainMember},
+        sim_flow_meter::SimFlowMeter,
+        sim_obstacles::SimObstacle,
+        sim_particle_pool::SimInactiveParticle,
+        sim_physics_engine::calculate_cell_divergence,
+        sim_probes::SimProbe,
+        sim_pump::SimPump,
+        sim_safeguards::average_fluid_density,
+        sim_secondary::{SimSecondaryKind, SimSecondaryParticle},
+        sim_streamlines::trace_streamline,
+        sim_surface::{extract_liquid_surface, SimSurface},
+        SimAttractor, SimConstraints, SimContainer, SimDrain, SimFaucet, SimGrid, SimGridCellType,
+        SimParticle, SimSpinner, SimStepClock, SimWallMaterial, PARTICLE_GROUP_COUNT,
+    },
     ui::{SimTool, UIStateManager},
     util::{
         self, cartesian_to_polar, degrees_to_radians, get_cursor_position, JUICE_BLUE, JUICE_GREEN,
         JUICE_SKY_BLUE,
     },
 };
-use bevy::{core_pipeline::prelude::ClearColor, prelude::*};
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{bloom::BloomSettings, prelude::ClearColor, tonemapping::Tonemapping},
+    ecs::system::EntityCommands,
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle},
+};
+
+/** The particle shader pipeline always renders with the shader stored under this handle; whichever
+shader the user picks is copied here whenever it (re)loads. */
+const PARTICLE_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x6a75_6963_655f_6275_6262_6c65_735f_7367);
+/// Shader that draws the fluid as a continuous liquid surface.
+const LIQUID_SURFACE_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x6a75_6963_655f_7375_7266_6163_655f_7368);
+/// Fluid fraction (density relative to a typical fluid cell's) the liquid surface is drawn at.
+const LIQUID_SURFACE_THRESHOLD: f32 = 0.4;
+/// Slowest a particle can move and still be drawn with a trail, in world units per second.
+const TRAIL_MIN_SPEED: f32 = 60.0;
+/// Farthest a particle can move in one frame without its trail starting over, in cells.
+const TRAIL_MAX_JUMP_CELLS: f32 = 4.0;
+/// Opacity of the newest onion skin ghost, relative to the particle it was taken from.
+const ONION_SKIN_OPACITY: f32 = 0.5;
+/// Fewest pixels apart grid lines are drawn on screen; closer than this they're only noise.
+const GRID_LINE_MIN_SPACING: f32 = 4.0;
+/// Pixels apart grid lines have to be to be drawn at full strength.
+const GRID_LINE_FULL_SPACING: f32 = 12.0;
+/// Every this many grid lines is a major line, which is still drawn once the rest fade out.
+pub const GRID_MAJOR_LINE_INTERVAL: usize = 8;
 
 pub struct JuiceRenderer;
 impl Plugin for JuiceRenderer {
@@ -17,18 +66,85 @@ impl Plugin for JuiceRenderer {
         app.insert_resource(ClearColor(Color::BLACK));
         app.insert_resource(FluidRenderData::default());
         app.insert_resource(GridRenderData::default());
+        app.insert_resource(GridHeatmapLegend::default());
+        app.insert_resource(ParticleShaderData::default());
+        app.insert_resource(SimSurface::default());
+        app.insert_resource(OnionSkin::default());
+
+        load_internal_asset!(
+            app,
+            LIQUID_SURFACE_SHADER_HANDLE,
+            "../assets/shaders/liquid_surface.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugins((
+            Material2dPlugin::<ParticleShaderMaterial>::default(),
+            Material2dPlugin::<LiquidSurfaceMaterial>::default(),
+            ParticleBatchPlugin,
+        ));
 
         app.add_systems(Startup, setup_renderer);
 
         app.add_systems(Update, handle_events);
 
-        app.add_systems(Update, update_particle_position);
+        // Particles are drawn between the last two steps, so only once this frame's steps are in.
+        app.add_systems(Update, update_particle_position.after(simulation::update));
         app.add_systems(Update, update_particle_color);
+        app.add_systems(Update, filter_particle_groups.after(update_particle_color));
+        app.add_systems(
+            Update,
+            highlight_grabbed_particles.after(filter_particle_groups),
+        );
         app.add_systems(Update, update_particle_size);
+        app.add_systems(Update, update_secondary_particle_sprites);
+        app.add_systems(Update, update_drain_color);
+        app.add_systems(Update, update_faucet_position);
+        app.add_systems(Update, load_particle_shader);
+        app.add_systems(Update, swap_particle_renderers);
+        app.add_systems(
+            Update,
+            update_particle_shader_inputs.after(highlight_grabbed_particles),
+        );
+        app.add_systems(
+            Update,
+            update_particle_batch
+                .after(simulation::update)
+                .after(highlight_grabbed_particles)
+                .after(update_particle_size)
+                .after(update_secondary_particle_sprites)
+                .after(update_onion_skin),
+        );
+        app.add_systems(Update, update_bloom);
+        app.add_systems(Update, update_background);
+        app.add_systems(
+            Update,
+            update_onion_skin
+                .after(simulation::update)
+                .after(filter_particle_groups)
+                .after(update_particle_size),
+        );
+        app.add_systems(Update, update_particle_trails);
+        app.add_systems(Update, draw_particle_trails.after(update_particle_trails));
+        app.add_systems(Update, update_liquid_surface);
+        app.add_systems(Update, update_grid_heatmap);
+        app.add_systems(Update, draw_liquid_outline);
 
         app.add_systems(Update, draw_grid_vectors);
+        app.add_systems(Update, draw_streamlines);
         app.add_systems(Update, draw_grid_cells);
-        app.add_systems(Update, draw_grid_solids);
+        app.add_systems(Update, update_grid_cell_sprite);
+        app.add_systems(Update, draw_grid_temperature);
+        app.add_systems(Update, draw_containers);
+        app.add_systems(Update, draw_spinners);
+        app.add_systems(Update, draw_wind);
+        app.add_systems(Update, draw_valves);
+        app.add_systems(Update, draw_narrow_band_interior);
+        app.add_systems(Update, draw_attractors);
+        app.add_systems(Update, draw_pumps);
+        app.add_systems(Update, draw_probes);
+        app.add_systems(Update, draw_flow_meters);
+        app.add_systems(Update, draw_domains);
+        app.add_systems(Update, draw_faucet_paths);
 
         app.add_systems(PostUpdate, validate_entity_sprites);
         app.add_systems(PostUpdate, draw_gravity_arrow);
@@ -41,9 +157,48 @@ pub enum FluidColorRenderType {
     Arbitrary,
     Velocity,
     Density,
+    Temperature,
+    Vorticity,
+    Pressure,
+    Material,
     GridCell,
     Spume,
 }
+/// What, besides its radius, a fluid particle's drawn size follows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FluidSizeRenderType {
+    Radius,
+    Density,
+    Speed,
+}
+/// Grid field a heatmap is drawn of.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GridHeatmapType {
+    None,
+    Pressure,
+    Divergence,
+    Density,
+    Vorticity, // Curl of the velocity field; positive is counter-clockwise.
+}
+
+/** What the grid heatmap's colors mean, for the UI to draw a legend of.  Cells at `range.0` are drawn
+in `colors[0]` and cells at `range.1` in `colors[1]`, fading to clear in the middle of the range. */
+#[derive(Resource, Clone, Copy)]
+pub struct GridHeatmapLegend {
+    pub title: &'static str,
+    pub range: (f32, f32),
+    pub colors: [Color; 2],
+}
+
+impl Default for GridHeatmapLegend {
+    fn default() -> Self {
+        Self {
+            title: "",
+            range: (-1.0, 1.0),
+            colors: [Color::NONE; 2],
+        }
+    }
+}
 enum _FluidGridVectorType {
     Velocity,
 }
@@ -53,8 +208,22 @@ struct FluidRenderData {
     color_render_type: FluidColorRenderType,
     fluid_colors: [Color; 4],
     velocity_magnitude_color_scale: f32,
-    density_magnitude_color_scale: f32,
+    density_color_range: f32, // Multiple of the rest density at the end of the density gradient.
+    temperature_color_range: (f32, f32),
+    vorticity_color_scale: f32,
     particle_render_scale: f32,
+    size_render_type: FluidSizeRenderType,
+    draw_bloom: bool, // Draw in HDR, and make the fluid glow.
+    group_visible: [bool; PARTICLE_GROUP_COUNT],
+    group_tints: [Option<Color>; PARTICLE_GROUP_COUNT],
+    draw_liquid_surface: bool, // Draw the fluid as one liquid surface instead of as particles.
+    draw_liquid_outline: bool, // Outline the liquid's surface.
+    liquid_outline_color: Color,
+    draw_trails: bool,
+    trail_length: usize,   // Frames of history each trail holds.
+    trail_fade: f32,       // How quickly trails fade towards their tails; higher is quicker.
+    draw_onion_skin: bool, // Draw the fluid's last few steps faded out behind it while paused.
+    onion_skin_frames: usize,
 }
 
 impl Default for FluidRenderData {
@@ -68,8 +237,22 @@ impl Default for FluidRenderData {
                 util::JUICE_RED,
             ],
             velocity_magnitude_color_scale: 400.0,
-            density_magnitude_color_scale: 250.0,
+            density_color_range: 2.0,
+            temperature_color_range: (0.0, 100.0),
+            vorticity_color_scale: 40.0,
             particle_render_scale: 0.4,
+            size_render_type: FluidSizeRenderType::Radius,
+            draw_bloom: false,
+            group_visible: [true; PARTICLE_GROUP_COUNT],
+            group_tints: [None; PARTICLE_GROUP_COUNT],
+            draw_liquid_surface: false,
+            draw_liquid_outline: false,
+            liquid_outline_color: JUICE_SKY_BLUE,
+            draw_trails: false,
+            trail_length: 12,
+            trail_fade: 1.5,
+            draw_onion_skin: false,
+            onion_skin_frames: 4,
         }
     }
 }
@@ -79,12 +262,38 @@ struct GridRenderData {
     draw_grid: bool,
     grid_color: Color,
     solid_cell_color: Color,
+    bouncy_cell_color: Color,
+    sticky_cell_color: Color,
+    rough_cell_color: Color,
+    sand_cell_color: Color,
+    porous_cell_color: Color,
+    draw_cell_types: bool, // Tint fluid and air cells, too.
+    fluid_cell_color: Color,
+    air_cell_color: Color,
 
     draw_vectors: bool,
     vector_color: Color,
     vector_magnitude_scale: f32,
+    vector_max_length: f32, // Longest a velocity arrow is drawn, in world units.
+
+    draw_streamlines: bool,
+    streamlines_from_cursor: bool, // Seed streamlines around the cursor instead of across the grid.
+    streamline_color: Color,
+    streamline_spacing: u16, // Cells between streamline seeds across the grid.
+    streamline_max_steps: usize, // Most steps each streamline is traced up- and downstream.
 
     draw_gravity: bool,
+
+    draw_temperature: bool,
+    temperature_colors: [Color; 2],
+    temperature_range: (f32, f32),
+
+    heatmap_type: GridHeatmapType,
+    heatmap_colors: [Color; 2], // Colors of the most negative and most positive cells.
+
+    background_path: Option<String>, // Background image, relative to the assets folder.
+    background_scale: f32,           // Background size, relative to the width of the grid.
+    background_offset: Vec2,         // How far the background sits from the grid's center.
 }
 
 impl Default for GridRenderData {
@@ -93,37 +302,200 @@ impl Default for GridRenderData {
             draw_grid: false,
             grid_color: Color::DARK_GRAY,
             solid_cell_color: Color::GOLD,
+            bouncy_cell_color: Color::LIME_GREEN,
+            sticky_cell_color: Color::VIOLET,
+            rough_cell_color: Color::ORANGE_RED,
+            sand_cell_color: Color::rgb(0.76, 0.64, 0.42),
+            porous_cell_color: Color::rgb(0.9, 0.85, 0.3),
+            draw_cell_types: false,
+            fluid_cell_color: Color::rgba(0.2, 0.4, 1.0, 0.3),
+            air_cell_color: Color::rgba(1.0, 1.0, 1.0, 0.08),
 
             draw_vectors: false,
             vector_color: Color::WHITE,
             vector_magnitude_scale: 0.05,
+            vector_max_length: 10.0,
+
+            draw_streamlines: false,
+            streamlines_from_cursor: false,
+            streamline_color: Color::rgba(0.6, 0.9, 1.0, 0.6),
+            streamline_spacing: 5,
+            streamline_max_steps: 100,
 
             draw_gravity: false,
+
+            draw_temperature: false,
+            temperature_colors: [Color::BLUE, Color::RED],
+            temperature_range: (0.0, 100.0),
+
+            heatmap_type: GridHeatmapType::None,
+            heatmap_colors: [Color::rgb(0.2, 0.4, 1.0), Color::rgb(1.0, 0.25, 0.2)],
+
+            background_path: None,
+            background_scale: 1.0,
+            background_offset: Vec2::ZERO,
         }
     }
 }
 
+/** How a fluid, foam, spray, or bubble particle looks this frame.  Particles have no sprites of their
+own; `update_particle_batch` gathers these into the particle batch, which draws them all at once. */
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ParticleAppearance {
+    pub color: Color,
+    pub size: f32,
+    pub visible: bool,
+}
+
+impl Default for ParticleAppearance {
+    fn default() -> Self {
+        Self {
+            // Invisible until colored, so we don't get big ugly white blobs everywhere.
+            color: Color::NONE,
+            size: 0.0,
+            visible: true,
+        }
+    }
+}
+
+/** Material used to draw particles with a user-provided WGSL fragment shader.  Fields sharing a
+uniform binding are merged into one struct, so in WGSL the inputs are:
+```wgsl
+struct ParticleInputs {
+    color: vec4<f32>,    // Color chosen by the "Color by" visualization option.
+    velocity: vec2<f32>, // Simulation velocity.
+    density: f32,        // Density of the grid cell the particle is in.
+    age: f32,            // Simulated seconds since the particle was spawned.
+};
+@group(1) @binding(0) var<uniform> particle: ParticleInputs;
+@group(1) @binding(1) var particle_texture: texture_2d<f32>;
+@group(1) @binding(2) var particle_sampler: sampler;
+``` */
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct ParticleShaderMaterial {
+    #[uniform(0)]
+    pub color: Color,
+    #[uniform(0)]
+    pub velocity: Vec2,
+    #[uniform(0)]
+    pub density: f32,
+    #[uniform(0)]
+    pub age: f32,
+    #[texture(1)]
+    #[sampler(2)]
+    pub texture: Handle<Image>,
+}
+
+impl Material2d for ParticleShaderMaterial {
+    fn fragment_shader() -> ShaderRef {
+        PARTICLE_SHADER_HANDLE.into()
+    }
+}
+
+/** Material used to draw the fluid as one continuous liquid surface.  `density` holds the fluid
+fraction of each grid cell, top row first; the shader blends neighboring cells together and draws
+the fluid wherever the blended fraction reaches `threshold`. */
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct LiquidSurfaceMaterial {
+    #[uniform(0)]
+    pub color: Color,
+    #[uniform(0)]
+    pub threshold: f32,
+    #[texture(1)]
+    #[sampler(2)]
+    pub density: Handle<Image>,
+}
+
+impl Material2d for LiquidSurfaceMaterial {
+    fn fragment_shader() -> ShaderRef {
+        LIQUID_SURFACE_SHADER_HANDLE.into()
+    }
+}
+
+/// The quad the liquid surface is drawn on, and the size of the grid it was built to cover.
+#[derive(Component)]
+struct LiquidSurface {
+    grid_size: Vec2,
+}
+
+/// The sprite the grid's walls are drawn on, and the size of the grid it was built to cover.
+#[derive(Component)]
+struct GridCellSprite {
+    grid_size: Vec2,
+}
+
+/// The sprite a background image is drawn on, and the path it was loaded from.
+#[derive(Component)]
+struct Background {
+    path: String,
+}
+
+/// The sprite grid heatmaps are drawn on, and the size of the grid it was built to cover.
+#[derive(Component)]
+struct GridHeatmap {
+    grid_size: Vec2,
+}
+
+/** Tracks the custom particle shader, if one is in use.  Particles are drawn by the particle batch
+normally, and as quads using `ParticleShaderMaterial` while a custom shader is enabled. */
+#[derive(Resource, Default)]
+struct ParticleShaderData {
+    // Shader to use, relative to the assets folder; None for sprites.
+    shader_path: Option<String>,
+    // Path of the shader most recently loaded.
+    loaded_path: Option<String>,
+    // The user's shader as loaded by the asset server.
+    shader_source: Option<Handle<Shader>>,
+    // Mesh shared by every shaded particle, and the size it was built at.
+    quad: Option<Handle<Mesh>>,
+    quad_size: f32,
+}
+
 /// Handle events sent to the renderer.
 fn handle_events(
     mut ev_viz: EventReader<ModifyVisualizationEvent>,
     mut grid_render_data: ResMut<GridRenderData>,
     mut fluid_render_data: ResMut<FluidRenderData>,
+    mut particle_shader_data: ResMut<ParticleShaderData>,
 ) {
     for viz_mod in ev_viz.read() {
         grid_render_data.draw_grid = viz_mod.show_grid;
+        grid_render_data.draw_cell_types = viz_mod.show_cell_types;
         grid_render_data.draw_gravity = viz_mod.show_gravity;
         grid_render_data.draw_vectors = viz_mod.show_velocities;
+        grid_render_data.draw_streamlines = viz_mod.show_streamlines;
+        grid_render_data.streamlines_from_cursor = viz_mod.streamlines_from_cursor;
+        grid_render_data.draw_temperature = viz_mod.show_temperature;
+        grid_render_data.heatmap_type = viz_mod.heatmap_variable;
+        grid_render_data.background_path = viz_mod.background_image.clone();
+        grid_render_data.background_scale = viz_mod.background_scale;
+        grid_render_data.background_offset = viz_mod.background_offset;
 
         for i in 0..fluid_render_data.fluid_colors.len() {
             fluid_render_data.fluid_colors[i] = viz_mod.fluid_colors[i].into();
         }
         fluid_render_data.color_render_type = viz_mod.color_variable;
         fluid_render_data.particle_render_scale = viz_mod.particle_size;
+        fluid_render_data.size_render_type = viz_mod.size_variable;
+        fluid_render_data.draw_bloom = viz_mod.bloom;
+        fluid_render_data.draw_liquid_surface = viz_mod.show_liquid_surface;
+        fluid_render_data.draw_liquid_outline = viz_mod.show_liquid_outline;
+        fluid_render_data.draw_trails = viz_mod.show_trails;
+        fluid_render_data.trail_length = viz_mod.trail_length;
+        fluid_render_data.trail_fade = viz_mod.trail_fade;
+        fluid_render_data.draw_onion_skin = viz_mod.show_onion_skin;
+        fluid_render_data.onion_skin_frames = viz_mod.onion_skin_frames;
+        particle_shader_data.shader_path = viz_mod.custom_shader.clone();
+
+        fluid_render_data.group_visible = viz_mod.group_visible;
+        for group in 0..PARTICLE_GROUP_COUNT {
+            fluid_render_data.group_tints[group] = viz_mod.group_tints[group].map(Color::from);
+        }
     }
 }
 
 /// Custom rendering pipeline initialization.
-fn setup_renderer(mut commands: Commands, grid: Res<SimGrid>) {
+fn setup_renderer(mut commands: Commands, grid: Res<SimGrid>, asset_server: Res<AssetServer>) {
     // Spawn a camera to view our simulation world!
     commands.spawn(Camera2dBundle {
         transform: Transform {
@@ -137,43 +509,170 @@ fn setup_renderer(mut commands: Commands, grid: Res<SimGrid>) {
         },
         ..default()
     });
+
+    // Every particle drawn as a sprite is drawn through this one batch.
+    commands.spawn(ParticleBatch {
+        instances: Vec::new(),
+        texture: asset_server.load("../assets/particle.png"),
+    });
 }
 
-/** Creates and links a new sprite to the specified particle; **Must be called each time a new
-particle is added to the simulation!** */
-pub fn link_particle_sprite(
-    commands: &mut Commands,
-    asset_server: &AssetServer,
-    particle: Entity,
-    position: Vec2,
+/// How a camera was set up before bloom took it over, so turning bloom off can put it back.
+#[derive(Component)]
+struct PreBloomCamera {
+    hdr: bool,
+    tonemapping: Tonemapping,
+}
+
+/** Turn bloom on the camera on or off to match the visualization settings.  Bloom needs the camera to
+render in HDR, and to be tonemapped back down so the glowing fluid doesn't just clip to white; turning
+it off restores whatever HDR and tonemapping settings the camera had before. */
+fn update_bloom(
+    mut commands: Commands,
+    fluid_render_data: Res<FluidRenderData>,
+    mut cameras: Query<(
+        Entity,
+        &mut Camera,
+        &mut Tonemapping,
+        Option<&BloomSettings>,
+        Option<&PreBloomCamera>,
+    )>,
 ) {
-    // Chad activity of using the default 1x1 pixel sprite:
-    // commands.entity(particle).insert(SpriteBundle::default());
+    if !fluid_render_data.is_changed() {
+        return;
+    }
 
-    // Sigma activity of loading a cool particle sprite that you made in paint.net:
-    let particle_image = asset_server.load("../assets/particle.png");
+    for (camera_id, mut camera, mut tonemapping, bloom, pre_bloom) in cameras.iter_mut() {
+        if fluid_render_data.draw_bloom == bloom.is_some() {
+            continue;
+        }
+        if fluid_render_data.draw_bloom {
+            commands.entity(camera_id).insert((
+                BloomSettings::default(),
+                PreBloomCamera {
+                    hdr: camera.hdr,
+                    tonemapping: *tonemapping,
+                },
+            ));
+            camera.hdr = true;
+            *tonemapping = Tonemapping::TonyMcMapface;
+        } else {
+            if let Some(pre_bloom) = pre_bloom {
+                camera.hdr = pre_bloom.hdr;
+                *tonemapping = pre_bloom.tonemapping;
+            }
+            commands
+                .entity(camera_id)
+                .remove::<(BloomSettings, PreBloomCamera)>();
+        }
+    }
+}
 
-    /* Beta activity of making a structure and then modifying its fields afterward because you can't
-    read and fix cargo error messages: */
-    let mut particle_sprite_bundle = SpriteBundle {
-        texture: particle_image,
-        ..default()
-    };
-    particle_sprite_bundle.transform.translation = Vec3 {
-        x: position.x,
-        y: position.y,
-        z: 0.0,
-    };
-    particle_sprite_bundle.transform.scale = Vec3 {
-        x: 1.5,
-        y: 1.5,
-        z: 1.0,
+/** Draw the user's background image (a sink, an aquarium, a landscape...) behind the grid, in place of
+the flat clear color.  The image is loaded whenever its path changes, and stretched to fit the width
+of the grid (keeping its aspect ratio) before being scaled and offset as the user asks. */
+fn update_background(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    grid: Res<SimGrid>,
+    grid_render_data: Res<GridRenderData>,
+    images: Res<Assets<Image>>,
+    mut backgrounds: Query<(
+        Entity,
+        &Background,
+        &Handle<Image>,
+        &mut Sprite,
+        &mut Transform,
+    )>,
+) {
+    let Some(path) = grid_render_data.background_path.as_ref() else {
+        for (background_id, _, _, _, _) in backgrounds.iter() {
+            commands.entity(background_id).despawn();
+        }
+        return;
     };
-    // Make the sprite invisible when it spawns so we don't get big ugly white blobs everywhere.
-    particle_sprite_bundle.sprite.color = Color::NONE;
 
-    // Phi male method of spawning a particle:
-    commands.entity(particle).insert(particle_sprite_bundle);
+    let grid_size: Vec2 = Vec2::new(
+        (grid.dimensions.1 * grid.cell_size) as f32,
+        (grid.dimensions.0 * grid.cell_size) as f32,
+    );
+    let mut found: bool = false;
+    for (background_id, background, texture, mut sprite, mut transform) in backgrounds.iter_mut() {
+        if background.path != *path {
+            commands.entity(background_id).despawn();
+            continue;
+        }
+        found = true;
+
+        // Nothing to size until the image has loaded.
+        let Some(image) = images.get(texture) else {
+            continue;
+        };
+        let image_size: Vec2 = image.size_f32();
+        let fitted_size: Vec2 = Vec2::new(grid_size.x, grid_size.x * image_size.y / image_size.x);
+        sprite.custom_size = Some(fitted_size * grid_render_data.background_scale);
+        // Far behind everything else.
+        transform.translation =
+            (grid_size / 2.0 + grid_render_data.background_offset).extend(-10.0);
+    }
+
+    if !found {
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load(path.clone()),
+                // Drawn at no size until the image loads and can be fit to the grid.
+                transform: Transform::from_translation((grid_size / 2.0).extend(-10.0)),
+                sprite: Sprite {
+                    custom_size: Some(Vec2::ZERO),
+                    ..default()
+                },
+                ..default()
+            },
+            Background { path: path.clone() },
+        ));
+    }
+}
+
+/** How brightly fluid particles glow with bloom on, for each way of coloring them.  Modes whose colors
+carry meaning (like velocity) glow the most, so the interesting parts of the flow stand out. */
+fn emissive_intensity(color_render_type: FluidColorRenderType) -> f32 {
+    match color_render_type {
+        FluidColorRenderType::Velocity => 3.0,
+        FluidColorRenderType::Vorticity => 2.5,
+        FluidColorRenderType::Temperature | FluidColorRenderType::Pressure => 2.0,
+        FluidColorRenderType::Density | FluidColorRenderType::Spume => 1.5,
+        FluidColorRenderType::Material
+        | FluidColorRenderType::GridCell
+        | FluidColorRenderType::Arbitrary => 1.2,
+    }
+}
+
+/** What to scale the fluid's colors by, however it's drawn (as sprites, with a custom shader, or as
+a liquid surface): brighter than white with bloom on, so it glows, and left as is otherwise. */
+fn fluid_intensity(fluid_render_data: &FluidRenderData) -> f32 {
+    if fluid_render_data.draw_bloom {
+        emissive_intensity(fluid_render_data.color_render_type)
+    } else {
+        1.0
+    }
+}
+
+/** Creates and links a new sprite to the specified particle; **Must be called each time a new
+particle is added to the simulation!** */
+pub fn link_particle_sprite(commands: &mut Commands, particle: Entity) {
+    /* Sigma activity of drawing every particle with the cool particle sprite you made in paint.net,
+    all in one go (see `update_particle_batch`): */
+    commands
+        .entity(particle)
+        .insert(ParticleAppearance::default());
+}
+
+/** Creates and links a new sprite to the specified foam, spray, or bubble particle.  These are drawn
+just above the fluid so they show up on top of it. */
+pub fn link_secondary_particle_sprite(commands: &mut Commands, secondary_particle: Entity) {
+    commands
+        .entity(secondary_particle)
+        .insert(ParticleAppearance::default());
 }
 
 /** Creates and links a new sprite for the specified faucet. */
@@ -232,59 +731,702 @@ pub fn link_drain_sprite(
     commands.entity(drain).insert(drain_sprite_bundle);
 }
 
-/// Update the visual transform of all particles to be rendered.
-fn update_particle_position(mut particles: Query<(&SimParticle, &mut Transform)>) {
-    for (particle, mut transform) in particles.iter_mut() {
+/// Keep faucet sprites on top of faucets that move along a path.
+fn update_faucet_position(
+    mut faucets: Query<(&SimFaucet, &mut Transform, Option<&SimDomainMember>)>,
+    domains: Query<&SimDomain>,
+) {
+    for (faucet, mut transform, member) in faucets.iter_mut() {
+        let position: Vec2 = domain_origin(member, &domains) + faucet.position;
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+/// Tint drains that can't keep up with the fluid reaching them, and darken clogged ones.
+fn update_drain_color(mut drains: Query<(&SimDrain, &mut Sprite)>) {
+    for (drain, mut sprite) in drains.iter_mut() {
+        sprite.color = if drain.is_clogged() {
+            Color::DARK_GRAY
+        } else if drain.is_backed_up {
+            Color::ORANGE_RED
+        } else {
+            Color::WHITE
+        };
+    }
+}
+
+/** Snapshots of the fluid particles from the last few simulation steps, newest (the current step)
+first, kept while the onion skin is shown. */
+#[derive(Resource, Default)]
+struct OnionSkin {
+    frames: VecDeque<Vec<ParticleInstance>>,
+}
+
+/** Take a snapshot of the fluid particles whenever the simulation steps, keeping as many as the onion
+skin shows plus the current step.  The snapshots start over whenever time goes backwards, like when
+the scene is reset or a save is loaded. */
+fn update_onion_skin(
+    mut onion_skin: ResMut<OnionSkin>,
+    constraints: Res<SimConstraints>,
+    fluid_render_data: Res<FluidRenderData>,
+    particles: Query<
+        (&SimParticle, &ParticleAppearance, Option<&SimDomainMember>),
+        Without<SimInactiveParticle>,
+    >,
+    domains: Query<&SimDomain>,
+    mut last_snapshot_time: Local<f32>,
+) {
+    if !fluid_render_data.draw_onion_skin {
+        onion_skin.frames.clear();
+        return;
+    }
+    if constraints.simulated_time < *last_snapshot_time {
+        onion_skin.frames.clear();
+    } else if constraints.simulated_time == *last_snapshot_time && !onion_skin.frames.is_empty() {
+        return;
+    }
+    *last_snapshot_time = constraints.simulated_time;
+
+    onion_skin.frames.push_front(
+        particles
+            .iter()
+            .filter(|(_, appearance, _)| appearance.visible)
+            .map(|(particle, appearance, member)| ParticleInstance {
+                position: domain_origin(member, &domains) + particle.position,
+                size: appearance.size,
+                color: appearance.color,
+            })
+            .collect(),
+    );
+    onion_skin
+        .frames
+        .truncate(fluid_render_data.onion_skin_frames + 1);
+}
+
+/** Where to draw a particle this frame.  Positions are interpolated between the last two completed
+simulation steps so motion looks smooth at any frame rate. */
+fn particle_render_position(particle: &SimParticle, step_clock: &SimStepClock) -> Vec2 {
+    let alpha: f32 = step_clock.interpolation_alpha.clamp(0.0, 1.0);
+    particle.previous_position.lerp(particle.position, alpha)
+}
+
+/// Where the extra domain something belongs to is drawn; the main simulation is drawn in place.
+fn domain_origin(member: Option<&SimDomainMember>, domains: &Query<&SimDomain>) -> Vec2 {
+    member
+        .and_then(|member| domains.get(member.0).ok())
+        .map_or(Vec2::ZERO, |domain| domain.origin)
+}
+
+/** Where the simulation a particle belongs to is drawn, and the step clock its motion is interpolated
+with: the main simulation's, or those of the extra domain it's a member of. */
+fn domain_placement<'a>(
+    member: Option<&SimDomainMember>,
+    step_clock: &'a SimStepClock,
+    domains: &'a Query<(&SimDomain, &SimStepClock)>,
+) -> (Vec2, &'a SimStepClock) {
+    member
+        .and_then(|member| domains.get(member.0).ok())
+        .map_or((Vec2::ZERO, step_clock), |(domain, domain_clock)| {
+            (domain.origin, domain_clock)
+        })
+}
+
+/** The grid and settings of the main simulation and of each extra domain, along with the domain
+their particles are members of (`None` for the main simulation).  The main simulation's are global
+resources rather than a domain's components (see `SimDomain`), so they're added here. */
+fn simulation_frames<'a>(
+    grid: &'a SimGrid,
+    constraints: &'a SimConstraints,
+    domains: &'a Query<(Entity, &SimGrid, &SimConstraints), With<SimDomain>>,
+) -> Vec<(Option<Entity>, &'a SimGrid, &'a SimConstraints)> {
+    std::iter::once((None, grid, constraints))
+        .chain(
+            domains
+                .iter()
+                .map(|(domain_id, grid, constraints)| (Some(domain_id), grid, constraints)),
+        )
+        .collect()
+}
+
+/// Whether something is a member of the simulation `domain_id` names (`None` for the main one).
+fn is_in_frame(member: Option<&SimDomainMember>, domain_id: Option<Entity>) -> bool {
+    member.map(|member| member.0) == domain_id
+}
+
+/// Update the visual transform of all particles drawn with the custom particle shader.
+pub fn update_particle_position(
+    mut particles: Query<
+        (&SimParticle, &mut Transform, Option<&SimDomainMember>),
+        Without<SimInactiveParticle>,
+    >,
+    step_clock: Res<SimStepClock>,
+    domains: Query<(&SimDomain, &SimStepClock)>,
+) {
+    for (particle, mut transform, member) in particles.iter_mut() {
+        let (origin, step_clock) = domain_placement(member, &step_clock, &domains);
+        let render_position: Vec2 = origin + particle_render_position(particle, step_clock);
         transform.translation = Vec3 {
-            x: particle.position.x,
-            y: particle.position.y,
-            /* IMPORTANT: Keep this at the same z-value for all particles.  This allows Bevy to do
-            sprite batching, cutting render costs by quite a bit.  If we change the z-index we
-            will likely see a large performance drop. */
+            x: render_position.x,
+            y: render_position.y,
             z: 0.0,
         };
     }
 }
 
+/** Gather every particle not drawn with the custom particle shader into the particle batch, fluid
+first and then foam, spray, and bubbles on top of it. */
+fn update_particle_batch(
+    mut batches: Query<&mut ParticleBatch>,
+    particles: Query<
+        (&SimParticle, &ParticleAppearance, Option<&SimDomainMember>),
+        (
+            Without<Handle<ParticleShaderMaterial>>,
+            Without<SimInactiveParticle>,
+        ),
+    >,
+    secondary_particles: Query<(&SimSecondaryParticle, &ParticleAppearance)>,
+    step_clock: Res<SimStepClock>,
+    domains: Query<(&SimDomain, &SimStepClock)>,
+    constraints: Res<SimConstraints>,
+    fluid_render_data: Res<FluidRenderData>,
+    onion_skin: Res<OnionSkin>,
+) {
+    for mut batch in batches.iter_mut() {
+        // Reuse last frame's list, so gathering doesn't allocate.
+        batch.instances.clear();
+        // Ghosts of the fluid's previous steps go underneath everything, oldest first.
+        if constraints.is_paused && fluid_render_data.draw_onion_skin {
+            let ghost_count: usize = onion_skin.frames.len().saturating_sub(1);
+            for (age, frame) in onion_skin.frames.iter().enumerate().skip(1).rev() {
+                let fade: f32 = ONION_SKIN_OPACITY * (1.0 - (age - 1) as f32 / ghost_count as f32);
+                batch
+                    .instances
+                    .extend(frame.iter().map(|ghost| ParticleInstance {
+                        color: ghost.color.with_a(ghost.color.a() * fade),
+                        ..*ghost
+                    }));
+            }
+        }
+        let intensity: f32 = fluid_intensity(&fluid_render_data);
+        // The liquid surface stands in for the main simulation's fluid particles while it's drawn.
+        batch.instances.extend(
+            particles
+                .iter()
+                .filter(|(_, appearance, member)| {
+                    appearance.visible
+                        && !(fluid_render_data.draw_liquid_surface && member.is_none())
+                })
+                .map(|(particle, appearance, member)| {
+                    let (origin, step_clock) = domain_placement(member, &step_clock, &domains);
+                    ParticleInstance {
+                        position: origin + particle_render_position(particle, step_clock),
+                        size: appearance.size,
+                        color: appearance.color.as_rgba_linear() * intensity,
+                    }
+                }),
+        );
+        batch.instances.extend(
+            secondary_particles
+                .iter()
+                .filter_map(|(secondary, appearance)| {
+                    appearance.visible.then_some(ParticleInstance {
+                        position: secondary.position,
+                        size: appearance.size,
+                        color: appearance.color,
+                    })
+                }),
+        );
+    }
+}
+
+/** The last few places a particle was drawn, newest first, kept while particle trails are shown.
+Particles only get one of these once trails are turned on, and lose it when they're turned off. */
+#[derive(Component, Clone, Debug, Default)]
+pub struct ParticleTrail {
+    pub positions: VecDeque<Vec2>,
+}
+
+/** Record where each particle is drawn this frame onto its trail, dropping positions older than the
+trail length.  A particle that jumps too far in one frame (wrapping around the grid, or reused from
+the particle pool) starts its trail over, so it doesn't streak across the screen. */
+fn update_particle_trails(
+    mut commands: Commands,
+    grid: Res<SimGrid>,
+    step_clock: Res<SimStepClock>,
+    fluid_render_data: Res<FluidRenderData>,
+    mut particles: Query<
+        (
+            Entity,
+            &SimParticle,
+            Option<&mut ParticleTrail>,
+            Option<&SimDomainMember>,
+        ),
+        Without<SimInactiveParticle>,
+    >,
+    stale_trails: Query<Entity, (With<ParticleTrail>, With<SimInactiveParticle>)>,
+    domains: Query<(&SimDomain, &SimStepClock)>,
+) {
+    for trail_id in stale_trails.iter() {
+        commands.entity(trail_id).remove::<ParticleTrail>();
+    }
+    if !fluid_render_data.draw_trails {
+        for (particle_id, _, trail, _) in particles.iter() {
+            if trail.is_some() {
+                commands.entity(particle_id).remove::<ParticleTrail>();
+            }
+        }
+        return;
+    }
+
+    let max_jump: f32 = grid.cell_size as f32 * TRAIL_MAX_JUMP_CELLS;
+    for (particle_id, particle, trail, member) in particles.iter_mut() {
+        let (origin, step_clock) = domain_placement(member, &step_clock, &domains);
+        let position: Vec2 = origin + particle_render_position(particle, step_clock);
+        let Some(mut trail) = trail else {
+            commands.entity(particle_id).insert(ParticleTrail {
+                positions: VecDeque::from([position]),
+            });
+            continue;
+        };
+        if trail
+            .positions
+            .front()
+            .is_some_and(|newest| newest.distance(position) > max_jump)
+        {
+            trail.positions.clear();
+        }
+        trail.positions.push_front(position);
+        trail
+            .positions
+            .truncate(fluid_render_data.trail_length.max(1));
+    }
+}
+
+/// Draw a trail behind each fast particle, fading out from the particle's color towards its tail.
+fn draw_particle_trails(
+    fluid_render_data: Res<FluidRenderData>,
+    particles: Query<
+        (&SimParticle, &ParticleTrail, &ParticleAppearance),
+        Without<SimInactiveParticle>,
+    >,
+    mut gizmos: Gizmos,
+) {
+    if !fluid_render_data.draw_trails {
+        return;
+    }
+
+    for (particle, trail, appearance) in particles.iter() {
+        if !appearance.visible
+            || trail.positions.len() < 2
+            || particle.velocity.length() < TRAIL_MIN_SPEED
+        {
+            continue;
+        }
+        let tail_index: f32 = (trail.positions.len() - 1) as f32;
+        let base_alpha: f32 = appearance.color.a();
+        gizmos.linestrip_gradient_2d(trail.positions.iter().enumerate().map(|(i, position)| {
+            let freshness: f32 = 1.0 - i as f32 / tail_index;
+            let alpha: f32 = base_alpha * freshness.powf(fluid_render_data.trail_fade);
+            (*position, appearance.color.with_a(alpha))
+        }));
+    }
+}
+
+/** Draw the fluid as one continuous liquid surface, if enabled.  Each cell's fluid fraction (its
+density relative to a typical fluid cell's) is written to a texture the size of the grid, which the
+liquid surface shader blends and thresholds into a smooth blob of liquid, metaball style. */
+fn update_liquid_surface(
+    mut commands: Commands,
+    grid: Res<SimGrid>,
+    fluid_render_data: Res<FluidRenderData>,
+    mut surfaces: Query<(
+        Entity,
+        &LiquidSurface,
+        &Handle<LiquidSurfaceMaterial>,
+        &mut Visibility,
+    )>,
+    mut materials: ResMut<Assets<LiquidSurfaceMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if !fluid_render_data.draw_liquid_surface {
+        for (_, _, _, mut visibility) in surfaces.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    }
+
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+    let color: Color =
+        fluid_render_data.fluid_colors[0].as_rgba_linear() * fluid_intensity(&fluid_render_data);
+    let fluid_density: f32 = average_fluid_density(&grid);
+    let fractions: Vec<u8> = (0..rows * cols)
+        .map(|index| {
+            if fluid_density <= 0.0 {
+                return 0;
+            }
+            ((grid.density[index] / fluid_density).clamp(0.0, 1.0) * 255.0) as u8
+        })
+        .collect();
+
+    // Update the surface in place, or build a new one if there isn't one for a grid this size.
+    for (surface_id, surface, material, mut visibility) in surfaces.iter_mut() {
+        if surface.grid_size != grid_size {
+            commands.entity(surface_id).despawn();
+            continue;
+        }
+        *visibility = Visibility::Visible;
+        let Some(material) = materials.get_mut(material) else {
+            return;
+        };
+        material.color = color;
+        if let Some(image) = images.get_mut(&material.density) {
+            image.data = fractions;
+        }
+        return;
+    }
+
+    let mut density: Image = Image::new(
+        Extent3d {
+            width: cols as u32,
+            height: rows as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        fractions,
+        TextureFormat::R8Unorm,
+    );
+    // Blending neighboring cells together is what rounds the surface off.
+    density.sampler = ImageSampler::linear();
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: meshes.add(Mesh::from(shape::Quad::new(grid_size))).into(),
+            material: materials.add(LiquidSurfaceMaterial {
+                color,
+                threshold: LIQUID_SURFACE_THRESHOLD,
+                density: images.add(density),
+            }),
+            // Just behind the particles, which are drawn at z = 0.
+            transform: Transform::from_translation((grid_size / 2.0).extend(-1.0)),
+            ..default()
+        },
+        LiquidSurface { grid_size },
+    ));
+}
+
+/** Tint each fluid cell by its pressure, divergence, or density, if a heatmap is selected.  Pressure
+and divergence are scaled against the largest one on the grid this frame, and density against the
+rest density, so overcompressed cells stand out from ones at rest.  Cells fade from transparent in
+the middle of the range to `heatmap_colors` at either end (see `GridHeatmapLegend`); the heatmap is
+written to a texture the size of the grid and drawn behind everything else. */
+fn update_grid_heatmap(
+    mut commands: Commands,
+    grid: Res<SimGrid>,
+    constraints: Res<SimConstraints>,
+    grid_render_data: Res<GridRenderData>,
+    mut legend: ResMut<GridHeatmapLegend>,
+    mut heatmaps: Query<(Entity, &GridHeatmap, &Handle<Image>, &mut Visibility)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if grid_render_data.heatmap_type == GridHeatmapType::None {
+        for (_, _, _, mut visibility) in heatmaps.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    }
+
+    // Only fluid cells have a meaningful value to show; everything else is left clear.
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let rest_density: f32 = rest_density(&grid, &constraints);
+    let values: Vec<Option<f32>> = (0..rows * cols)
+        .map(|index| {
+            let (row, col) = (index / cols, index % cols);
+            if grid.cell_type[row][col] != SimGridCellType::Fluid {
+                return None;
+            }
+            match grid_render_data.heatmap_type {
+                GridHeatmapType::Pressure => Some(grid.cell_center[row][col]),
+                GridHeatmapType::Divergence => Some(calculate_cell_divergence(&grid, row, col)),
+                GridHeatmapType::Density => {
+                    Some(grid.density[index] / rest_density).filter(|_| rest_density > 0.0)
+                }
+                GridHeatmapType::Vorticity => Some(grid.get_cell_vorticity(row, col)),
+                GridHeatmapType::None => None,
+            }
+        })
+        .collect();
+
+    // Density is centered on the rest density, and everything else on zero.
+    let (title, middle, half_range): (&str, f32, f32) = match grid_render_data.heatmap_type {
+        GridHeatmapType::Density => ("Density (x rest)", 1.0, 1.0),
+        heatmap_type => {
+            let max_magnitude: f32 = values
+                .iter()
+                .flatten()
+                .fold(0.0, |max: f32, value| max.max(value.abs()));
+            let title: &'static str = match heatmap_type {
+                GridHeatmapType::Pressure => "Pressure",
+                GridHeatmapType::Vorticity => "Vorticity (CW - / CCW +)",
+                _ => "Divergence",
+            };
+            (title, 0.0, max_magnitude)
+        }
+    };
+    *legend = GridHeatmapLegend {
+        title,
+        range: (middle - half_range, middle + half_range),
+        colors: grid_render_data.heatmap_colors,
+    };
+
+    let pixels: Vec<u8> = values
+        .iter()
+        .flat_map(|value| {
+            let Some(value) = value.filter(|_| half_range > 0.0) else {
+                return [0; 4];
+            };
+            let offset: f32 = (value - middle) / half_range;
+            let color: Color = if offset < 0.0 {
+                grid_render_data.heatmap_colors[0]
+            } else {
+                grid_render_data.heatmap_colors[1]
+            };
+            color.with_a(offset.abs().clamp(0.0, 1.0)).as_rgba_u8()
+        })
+        .collect();
+
+    // Update the heatmap in place, or build a new one if there isn't one for a grid this size.
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+    for (heatmap_id, heatmap, texture, mut visibility) in heatmaps.iter_mut() {
+        if heatmap.grid_size != grid_size {
+            commands.entity(heatmap_id).despawn();
+            continue;
+        }
+        *visibility = Visibility::Visible;
+        if let Some(image) = images.get_mut(texture) {
+            image.data = pixels;
+        }
+        return;
+    }
+
+    // Behind the liquid surface and the particles.
+    spawn_grid_sprite(&mut commands, &mut images, &grid, pixels, -2.0)
+        .insert(GridHeatmap { grid_size });
+}
+
+/** Spawn a sprite covering the whole grid, drawn at depth `z` from an image with one pixel per cell.
+`pixels` holds each cell's color as sRGB bytes, row by row from the top of the grid. */
+fn spawn_grid_sprite<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    images: &mut Assets<Image>,
+    grid: &SimGrid,
+    pixels: Vec<u8>,
+    z: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let (rows, cols) = (grid.dimensions.0 as u32, grid.dimensions.1 as u32);
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+    let mut image: Image = Image::new(
+        Extent3d {
+            width: cols,
+            height: rows,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // Keep each cell's color to itself.
+    image.sampler = ImageSampler::nearest();
+    commands.spawn(SpriteBundle {
+        sprite: Sprite {
+            custom_size: Some(grid_size),
+            ..default()
+        },
+        texture: images.add(image),
+        transform: Transform::from_translation((grid_size / 2.0).extend(z)),
+        ..default()
+    })
+}
+
+/** Outline the liquid's surface, traced through the grid's density field with marching squares each
+frame it's shown; a lightweight alternative to the liquid surface shader. */
+fn draw_liquid_outline(
+    grid: Res<SimGrid>,
+    fluid_render_data: Res<FluidRenderData>,
+    mut surface: ResMut<SimSurface>,
+    mut gizmos: Gizmos,
+) {
+    if !fluid_render_data.draw_liquid_outline {
+        return;
+    }
+
+    extract_liquid_surface(&grid, &mut surface);
+    for contour in surface.contours.iter() {
+        // Each contour is closed, so finish back at its first point.
+        gizmos.linestrip_2d(
+            contour.iter().chain(contour.first()).copied(),
+            fluid_render_data.liquid_outline_color,
+        );
+    }
+}
+
+/** Style foam, spray, and bubble particles.  Each kind gets its own look, and fades out as its
+particle's life runs out. */
+fn update_secondary_particle_sprites(
+    mut secondary_particles: Query<(&SimSecondaryParticle, &mut ParticleAppearance)>,
+    constraints: Res<SimConstraints>,
+    fluid_render_data: Res<FluidRenderData>,
+) {
+    let particle_size: f32 =
+        constraints.particle_radius * 2.0 * fluid_render_data.particle_render_scale;
+    for (secondary, mut appearance) in secondary_particles.iter_mut() {
+        let (color, size_scale): (Color, f32) = match secondary.kind {
+            SimSecondaryKind::Spray => (Color::rgba(0.75, 0.9, 1.0, 0.8), 0.5),
+            SimSecondaryKind::Foam => (Color::rgba(1.0, 1.0, 1.0, 0.9), 0.8),
+            SimSecondaryKind::Bubble => (Color::rgba(0.85, 0.95, 1.0, 0.45), 0.7),
+        };
+        appearance.color = color.with_a(color.a() * secondary.lifetime.clamp(0.0, 1.0));
+        appearance.size = particle_size * size_scale;
+    }
+}
+
 /// When an entity exists without a sprite, give it one!
 fn validate_entity_sprites(
-    particles: Query<(Entity, &SimParticle), Without<Sprite>>,
-    faucets: Query<(Entity, &SimFaucet), Without<Sprite>>,
-    drains: Query<(Entity, &SimDrain), Without<Sprite>>,
+    particles: Query<
+        Entity,
+        (
+            With<SimParticle>,
+            Without<ParticleAppearance>,
+            Without<SimInactiveParticle>,
+        ),
+    >,
+    secondary_particles: Query<Entity, (With<SimSecondaryParticle>, Without<ParticleAppearance>)>,
+    faucets: Query<(Entity, &SimFaucet, Option<&SimDomainMember>), Without<Sprite>>,
+    drains: Query<(Entity, &SimDrain, Option<&SimDomainMember>), Without<Sprite>>,
+    domains: Query<&SimDomain>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
 ) {
-    for (particle_id, particle) in particles.iter() {
-        link_particle_sprite(&mut commands, &asset_server, particle_id, particle.position);
+    for particle_id in particles.iter() {
+        link_particle_sprite(&mut commands, particle_id);
     }
-    for (faucet_id, faucet) in faucets.iter() {
-        link_faucet_sprite(&mut commands, &asset_server, faucet_id, faucet.position);
+    for secondary_id in secondary_particles.iter() {
+        link_secondary_particle_sprite(&mut commands, secondary_id);
     }
-    for (drain_id, drain) in drains.iter() {
-        link_drain_sprite(&mut commands, &asset_server, drain_id, drain.position);
+    for (faucet_id, faucet, member) in faucets.iter() {
+        let position: Vec2 = domain_origin(member, &domains) + faucet.position;
+        link_faucet_sprite(&mut commands, &asset_server, faucet_id, position);
+    }
+    for (drain_id, drain, member) in drains.iter() {
+        let position: Vec2 = domain_origin(member, &domains) + drain.position;
+        link_drain_sprite(&mut commands, &asset_server, drain_id, position);
     }
 }
 
-/// Update the size of all particles to be rendered.
+/// Fluid particles are drawn this much bigger than their size, so neighbors blend together.
+const FLUID_SPRITE_SCALE: f32 = 1.5;
+/// Smallest and largest a particle sized by density or speed is drawn, relative to its radius.
+const SIZE_MAPPING_RANGE: (f32, f32) = (0.6, 1.6);
+
+/** Update the size of all particles to be rendered; merged particles are drawn bigger.  Particles can
+also be sized by the density of their cell, so crowded fluid blends into one mass, or by their
+speed.  Density is measured on the grid of whichever simulation each particle belongs to. */
 fn update_particle_size(
-    mut particles: Query<(&SimParticle, &mut Sprite)>,
+    mut particles: Query<
+        (
+            &SimParticle,
+            &mut ParticleAppearance,
+            Option<&SimDomainMember>,
+        ),
+        Without<SimInactiveParticle>,
+    >,
+    grid: Res<SimGrid>,
     constraints: Res<SimConstraints>,
+    domains: Query<(Entity, &SimGrid, &SimConstraints), With<SimDomain>>,
     fluid_render_data: Res<FluidRenderData>,
 ) {
-    for (_, mut sprite) in particles.iter_mut() {
+    for (domain_id, grid, constraints) in simulation_frames(&grid, &constraints, &domains) {
+        update_frame_particle_size(
+            particles
+                .iter_mut()
+                .filter(|(_, _, member)| is_in_frame(*member, domain_id))
+                .map(|(particle, appearance, _)| (particle, appearance)),
+            grid,
+            constraints,
+            fluid_render_data.as_ref(),
+        );
+    }
+}
+
+/// Size the particles of one simulation, given its grid and settings.
+fn update_frame_particle_size<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    fluid_render_data: &FluidRenderData,
+) {
+    let rest_density: f32 = rest_density(grid, constraints);
+    for (particle, mut appearance) in particles {
         /* Multiply this by 2, because we are dealing with the radius.  To account for the full
         size of the particle, we need to multiply the radius by 2. */
-        let size: f32 = constraints.particle_radius * 2.0 * fluid_render_data.particle_render_scale;
-        sprite.custom_size = Some(Vec2::splat(size));
+        let size: f32 = particle.radius * 2.0 * fluid_render_data.particle_render_scale;
+
+        // How far along the size range the particle is, if its size is mapped to anything.
+        let mapping: Option<f32> = match fluid_render_data.size_render_type {
+            FluidSizeRenderType::Radius => None,
+            FluidSizeRenderType::Density => (rest_density > 0.0).then(|| {
+                let cell_coordinates: Vec2 =
+                    grid.get_cell_coordinates_from_position(&particle.position);
+                grid.density[grid.get_lookup_index(cell_coordinates)] / (2.0 * rest_density)
+            }),
+            FluidSizeRenderType::Speed => {
+                Some(particle.velocity.length() / fluid_render_data.velocity_magnitude_color_scale)
+            }
+        };
+        let size_scale: f32 = mapping.map_or(1.0, |mapping| {
+            let (smallest, largest) = SIZE_MAPPING_RANGE;
+            smallest + (largest - smallest) * mapping.clamp(0.0, 1.0)
+        });
+        appearance.size = size * size_scale * FLUID_SPRITE_SCALE;
     }
 }
 
-/// Update the color of all particles to be rendered.
+/** Update the color of all particles to be rendered.  Particles are colored by the grid and settings
+of whichever simulation they belong to. */
 fn update_particle_color(
-    particles: Query<(&SimParticle, &mut Sprite)>,
+    mut particles: Query<
+        (
+            &SimParticle,
+            &mut ParticleAppearance,
+            Option<&SimDomainMember>,
+        ),
+        Without<SimInactiveParticle>,
+    >,
     grid: Res<SimGrid>,
     constraints: Res<SimConstraints>,
+    domains: Query<(Entity, &SimGrid, &SimConstraints), With<SimDomain>>,
     particle_render_data: Res<FluidRenderData>,
+) {
+    for (domain_id, grid, constraints) in simulation_frames(&grid, &constraints, &domains) {
+        color_frame_particles(
+            particles
+                .iter_mut()
+                .filter(|(_, _, member)| is_in_frame(*member, domain_id))
+                .map(|(particle, appearance, _)| (particle, appearance)),
+            grid,
+            constraints,
+            particle_render_data.as_ref(),
+        );
+    }
+}
+
+/// Color the particles of one simulation, given its grid and settings.
+fn color_frame_particles<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    particle_render_data: &FluidRenderData,
 ) {
     match particle_render_data.color_render_type {
         FluidColorRenderType::Velocity => color_particles_by_velocity(
@@ -294,16 +1436,31 @@ fn update_particle_color(
         ),
         FluidColorRenderType::Density => color_particles_by_density(
             particles,
-            grid.as_ref(),
-            particle_render_data.density_magnitude_color_scale * constraints.particle_rest_density
-                / constraints.particle_radius,
+            grid,
+            particle_render_data.density_color_range * rest_density(grid, constraints),
             &particle_render_data.fluid_colors.to_vec(),
         ),
+        FluidColorRenderType::Temperature => color_particles_by_temperature(
+            particles,
+            particle_render_data.temperature_color_range,
+            &vec![Color::BLUE, Color::RED],
+        ),
+        FluidColorRenderType::Vorticity => color_particles_by_vorticity(
+            particles,
+            grid,
+            particle_render_data.vorticity_color_scale,
+            &vec![Color::BLUE, Color::WHITE, Color::RED],
+        ),
+        FluidColorRenderType::Pressure => color_particles_by_pressure(
+            particles,
+            grid,
+            &particle_render_data.fluid_colors.to_vec(),
+        ),
+        FluidColorRenderType::Material => color_particles_by_material(particles),
         FluidColorRenderType::Spume => color_particles_by_density(
             particles,
-            grid.as_ref(),
-            particle_render_data.density_magnitude_color_scale * constraints.particle_rest_density
-                / constraints.particle_radius,
+            grid,
+            particle_render_data.density_color_range * rest_density(grid, constraints),
             &vec![
                 Color::ANTIQUE_WHITE,
                 util::JUICE_SKY_BLUE,
@@ -314,21 +1471,247 @@ fn update_particle_color(
         FluidColorRenderType::Arbitrary => {
             color_particles(particles, particle_render_data.fluid_colors[0])
         }
-        FluidColorRenderType::GridCell => {
-            color_particles_by_grid_cell(particles, grid.as_ref(), JUICE_BLUE, JUICE_GREEN)
+        FluidColorRenderType::GridCell => {
+            color_particles_by_grid_cell(particles, grid, JUICE_BLUE, JUICE_GREEN)
+        }
+    }
+}
+
+/// How strongly a group's tint is mixed into its particles' colors.
+const GROUP_TINT_STRENGTH: f32 = 0.7;
+
+/// Hide particles in hidden groups, and mix each tinted group's tint into its particles' colors.
+fn filter_particle_groups(
+    mut particles: Query<
+        (
+            &SimParticle,
+            &mut ParticleAppearance,
+            Option<&mut Visibility>,
+        ),
+        Without<SimInactiveParticle>,
+    >,
+    particle_render_data: Res<FluidRenderData>,
+) {
+    for (particle, mut appearance, visibility) in particles.iter_mut() {
+        let group: usize = particle.group as usize % PARTICLE_GROUP_COUNT;
+
+        appearance.visible = particle_render_data.group_visible[group];
+        // Particles drawn with the custom shader are meshes, with visibility of their own.
+        if let Some(mut visibility) = visibility {
+            *visibility = if appearance.visible {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+        }
+
+        if let Some(tint) = particle_render_data.group_tints[group] {
+            appearance.color = util::generate_color_from_gradient(
+                &vec![appearance.color, tint],
+                GROUP_TINT_STRENGTH,
+            );
+        }
+    }
+}
+
+/// How strongly grabbed particles' colors are mixed towards the Grab tool's color.
+const GRAB_HIGHLIGHT_STRENGTH: f32 = 0.6;
+
+/** Tint the particles the Grab tool is holding in the same color as its selection circle, so it's
+clear what's being dragged around.  `SimConstraints::selected_particles` still holds the last grab
+after the mouse is let go, so particles are only highlighted while a mouse button is held. */
+fn highlight_grabbed_particles(
+    constraints: Res<SimConstraints>,
+    ui_state: Res<UIStateManager>,
+    mouse: Res<Input<MouseButton>>,
+    mut particles: Query<
+        &mut ParticleAppearance,
+        (With<SimParticle>, Without<SimInactiveParticle>),
+    >,
+) {
+    if ui_state.selected_tool != SimTool::Grab
+        || !mouse.any_pressed([MouseButton::Left, MouseButton::Right])
+    {
+        return;
+    }
+
+    for (particle_id, _) in constraints.selected_particles.iter() {
+        let Ok(mut appearance) = particles.get_mut(*particle_id) else {
+            continue;
+        };
+        appearance.color = util::generate_color_from_gradient(
+            &vec![
+                appearance.color,
+                JUICE_SKY_BLUE.with_a(appearance.color.a()),
+            ],
+            GRAB_HIGHLIGHT_STRENGTH,
+        );
+    }
+}
+
+/** Load the user's particle shader whenever they pick a new one, and copy it to the handle the
+particle pipeline renders with whenever it finishes (re)loading.  The asset watcher reloads the shader
+when its file changes on disk, so edits show up immediately. */
+fn load_particle_shader(
+    mut shader_data: ResMut<ParticleShaderData>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut shader_events: EventReader<AssetEvent<Shader>>,
+    asset_server: Res<AssetServer>,
+) {
+    if shader_data.shader_path.is_some() && shader_data.shader_path != shader_data.loaded_path {
+        let path: String = shader_data.shader_path.clone().unwrap();
+        let shader_source: Handle<Shader> = asset_server.load(path.clone());
+
+        // The shader may already be loaded if we used it recently, in which case no event will come.
+        if let Some(shader) = shaders.get(&shader_source).cloned() {
+            shaders.insert(PARTICLE_SHADER_HANDLE, shader);
+        }
+        shader_data.shader_source = Some(shader_source);
+        shader_data.loaded_path = Some(path);
+    }
+
+    let Some(source_id) = shader_data.shader_source.as_ref().map(|source| source.id()) else {
+        return;
+    };
+    for event in shader_events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        if *id != source_id {
+            continue;
+        }
+        if let Some(shader) = shaders.get(*id).cloned() {
+            shaders.insert(PARTICLE_SHADER_HANDLE, shader);
+        }
+    }
+}
+
+/** Swap particles between the particle batch and custom shader rendering.  Shaded particles keep
+their `ParticleAppearance` so the usual coloring and sizing systems still run; having a material is
+enough to leave them out of the batch. */
+fn swap_particle_renderers(
+    mut commands: Commands,
+    mut shader_data: ResMut<ParticleShaderData>,
+    mut materials: ResMut<Assets<ParticleShaderMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    sprite_particles: Query<
+        Entity,
+        (
+            With<SimParticle>,
+            Without<Handle<ParticleShaderMaterial>>,
+            Without<SimInactiveParticle>,
+        ),
+    >,
+    shaded_particles: Query<
+        Entity,
+        (
+            With<SimParticle>,
+            With<Handle<ParticleShaderMaterial>>,
+            Without<SimInactiveParticle>,
+        ),
+    >,
+    mut pooled_particles: Query<
+        &mut Visibility,
+        (
+            With<Handle<ParticleShaderMaterial>>,
+            With<SimInactiveParticle>,
+        ),
+    >,
+    asset_server: Res<AssetServer>,
+) {
+    // Pooled particles keep their meshes, so hide them until they're reused.
+    for mut visibility in pooled_particles.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+
+    if shader_data.shader_path.is_none() {
+        for particle in shaded_particles.iter() {
+            commands
+                .entity(particle)
+                .remove::<(Mesh2dHandle, Handle<ParticleShaderMaterial>, SpatialBundle)>();
+        }
+        return;
+    }
+
+    let quad: Handle<Mesh> = shader_data
+        .quad
+        .get_or_insert_with(|| meshes.add(Mesh::from(shape::Quad::default())))
+        .clone();
+    for particle in sprite_particles.iter() {
+        let material: Handle<ParticleShaderMaterial> = materials.add(ParticleShaderMaterial {
+            color: Color::NONE,
+            velocity: Vec2::ZERO,
+            density: 0.0,
+            age: 0.0,
+            texture: asset_server.load("../assets/particle.png"),
+        });
+        commands.entity(particle).insert((
+            Mesh2dHandle(quad.clone()),
+            material,
+            SpatialBundle::from_transform(Transform::from_scale(Vec3::new(
+                FLUID_SPRITE_SCALE,
+                FLUID_SPRITE_SCALE,
+                1.0,
+            ))),
+        ));
+    }
+}
+
+/// Hand each shaded particle's current state to the custom particle shader.
+fn update_particle_shader_inputs(
+    particles: Query<
+        (
+            &SimParticle,
+            &ParticleAppearance,
+            &Handle<ParticleShaderMaterial>,
+            Option<&SimDomainMember>,
+        ),
+        Without<SimInactiveParticle>,
+    >,
+    grid: Res<SimGrid>,
+    constraints: Res<SimConstraints>,
+    domains: Query<(Entity, &SimGrid, &SimConstraints), With<SimDomain>>,
+    fluid_render_data: Res<FluidRenderData>,
+    mut shader_data: ResMut<ParticleShaderData>,
+    mut materials: ResMut<Assets<ParticleShaderMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    // Resize the shared quad to match the particles' size (see `update_particle_size`).
+    let size: f32 = constraints.particle_radius * 2.0 * fluid_render_data.particle_render_scale;
+    if let Some(quad) = shader_data.quad.clone() {
+        if shader_data.quad_size != size {
+            meshes.insert(quad, Mesh::from(shape::Quad::new(Vec2::splat(size))));
+            shader_data.quad_size = size;
+        }
+    }
+
+    // Each particle's density is read off the grid of whichever simulation it belongs to.
+    let intensity: f32 = fluid_intensity(&fluid_render_data);
+    for (domain_id, grid, _) in simulation_frames(&grid, &constraints, &domains) {
+        for (particle, appearance, material_handle, _) in particles
+            .iter()
+            .filter(|(_, _, _, member)| is_in_frame(*member, domain_id))
+        {
+            let Some(material) = materials.get_mut(material_handle) else {
+                continue;
+            };
+            material.color = appearance.color.as_rgba_linear() * intensity;
+            material.velocity = particle.velocity;
+            material.density = grid.get_density_at_position(particle.position);
+            material.age = particle.age;
         }
     }
 }
 
 /// Color all particles in the simulation by their velocities.
-fn color_particles_by_velocity(
-    mut particles: Query<(&SimParticle, &mut Sprite)>,
+fn color_particles_by_velocity<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
     velocity_magnitude_color_scale: f32,
     color_list: &Vec<Color>,
 ) {
     // For each
-    for (particle, mut sprite) in particles.iter_mut() {
-        sprite.color = util::generate_color_from_gradient(
+    for (particle, mut appearance) in particles {
+        appearance.color = util::generate_color_from_gradient(
             color_list,
             util::vector_magnitude(particle.velocity) / velocity_magnitude_color_scale,
         );
@@ -336,87 +1719,458 @@ fn color_particles_by_velocity(
 }
 
 /// Color all particles in the simulation by the density of the cell they belong to.
-fn color_particles_by_density(
-    mut particles: Query<(&SimParticle, &mut Sprite)>,
+fn color_particles_by_density<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    grid: &SimGrid,
+    max_density: f32,
+    color_list: &Vec<Color>,
+) {
+    if max_density <= 0.0 {
+        return;
+    }
+    for (particle, mut appearance) in particles {
+        let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        let density: f32 = grid.density[grid.get_lookup_index(cell_coordinates)];
+        let color: Color = util::generate_color_from_gradient(color_list, density / max_density);
+        appearance.color = color;
+    }
+}
+
+/** The density fluid settles at, as measured by the pressure solver; or the average density of the
+fluid cells, if the solver hasn't measured it yet. */
+fn rest_density(grid: &SimGrid, constraints: &SimConstraints) -> f32 {
+    if constraints.particle_rest_density > 0.0 {
+        constraints.particle_rest_density
+    } else {
+        average_fluid_density(grid)
+    }
+}
+
+/// Color all particles in the simulation by their temperature, from coldest to hottest.
+fn color_particles_by_temperature<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    temperature_range: (f32, f32),
+    color_list: &Vec<Color>,
+) {
+    for (particle, mut appearance) in particles {
+        appearance.color = util::generate_color_from_gradient(
+            color_list,
+            (particle.temperature - temperature_range.0)
+                / (temperature_range.1 - temperature_range.0),
+        );
+    }
+}
+
+/** Color all particles in the simulation by the vorticity of the flow around them, using a diverging
+color scale: clockwise rotation takes the first color, still fluid the middle, and counter-clockwise
+rotation the last. */
+fn color_particles_by_vorticity<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
     grid: &SimGrid,
-    density_magnitude_color_scale: f32,
+    vorticity_color_scale: f32,
     color_list: &Vec<Color>,
 ) {
-    for (particle, mut sprite) in particles.iter_mut() {
-        let density: f32 = grid.get_density_at_position(particle.position);
-        let color: Color = util::generate_color_from_gradient(
+    for (particle, mut appearance) in particles {
+        let vorticity: f32 = grid.get_vorticity_at_position(particle.position);
+        appearance.color = util::generate_color_from_gradient(
             color_list,
-            density / (density_magnitude_color_scale * 0.45),
+            0.5 + vorticity / (2.0 * vorticity_color_scale),
         );
-        sprite.color = color;
+    }
+}
+
+/** Color all particles in the simulation by the pressure around them, from none (or suction) up to
+the highest pressure of any fluid cell this frame. */
+fn color_particles_by_pressure<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    grid: &SimGrid,
+    color_list: &Vec<Color>,
+) {
+    let mut max_pressure: f32 = 0.0;
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            if grid.cell_type[row][col] == SimGridCellType::Fluid {
+                max_pressure = max_pressure.max(grid.cell_center[row][col]);
+            }
+        }
+    }
+    if max_pressure <= 0.0 {
+        return;
+    }
+
+    for (particle, mut appearance) in particles {
+        let pressure: f32 = grid.get_pressure_at_position(particle.position);
+        appearance.color = util::generate_color_from_gradient(color_list, pressure / max_pressure);
+    }
+}
+
+/// Color all particles in the simulation by the fluid material they are made of.
+fn color_particles_by_material<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+) {
+    for (particle, mut appearance) in particles {
+        appearance.color = particle.material.color();
     }
 }
 
 /// Color all particles in the simulation as anything you want!
-fn color_particles(mut particles: Query<(&SimParticle, &mut Sprite)>, color: Color) {
-    for (_, mut sprite) in particles.iter_mut() {
-        sprite.color = color;
+fn color_particles<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
+    color: Color,
+) {
+    for (_, mut appearance) in particles {
+        appearance.color = color;
     }
 }
 
 /// Color all particles in the simulation by their grid cell.
-fn color_particles_by_grid_cell(
-    mut particles: Query<(&SimParticle, &mut Sprite)>,
+fn color_particles_by_grid_cell<'a>(
+    particles: impl Iterator<Item = (&'a SimParticle, Mut<'a, ParticleAppearance>)>,
     grid: &SimGrid,
     color_even: Color,
     color_odd: Color,
 ) {
-    for (particle, mut sprite) in particles.iter_mut() {
+    for (particle, mut appearance) in particles {
         let cell_pos: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
         let cell_row: usize = cell_pos[1] as usize;
         let cell_col: usize = cell_pos[0] as usize;
 
         if (cell_row + cell_col) % 2 == 0 {
-            sprite.color = color_even;
+            appearance.color = color_even;
         } else {
-            sprite.color = color_odd;
+            appearance.color = color_odd;
+        }
+    }
+}
+
+/** Fill in the grid's walls, colored by what they're made of; porous cells are drawn fainter the more
+freely fluid flows through them.  With `draw_cell_types` on, fluid and air cells are tinted too, to
+see how the simulation has labeled each cell.  The cells are written to a texture the size of the
+grid, drawn in front of the particles (but behind faucets and drains). */
+fn update_grid_cell_sprite(
+    mut commands: Commands,
+    grid: Res<SimGrid>,
+    grid_render_data: Res<GridRenderData>,
+    cell_sprites: Query<(Entity, &GridCellSprite, &Handle<Image>)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let pixels: Vec<u8> = (0..rows * cols)
+        .flat_map(|index| {
+            let (row, col) = (index / cols, index % cols);
+            let color: Color = match grid.cell_type[row][col] {
+                SimGridCellType::Solid => match grid.get_wall_material(row, col) {
+                    SimWallMaterial::Normal => grid_render_data.solid_cell_color,
+                    SimWallMaterial::Bouncy => grid_render_data.bouncy_cell_color,
+                    SimWallMaterial::Sticky => grid_render_data.sticky_cell_color,
+                    SimWallMaterial::Rough => grid_render_data.rough_cell_color,
+                    SimWallMaterial::Sand => grid_render_data.sand_cell_color,
+                },
+                SimGridCellType::Porous(permeability) => grid_render_data
+                    .porous_cell_color
+                    .with_a(1.0 - 0.75 * permeability.clamp(0.0, 1.0)),
+                SimGridCellType::Fluid if grid_render_data.draw_cell_types => {
+                    grid_render_data.fluid_cell_color
+                }
+                SimGridCellType::Air if grid_render_data.draw_cell_types => {
+                    grid_render_data.air_cell_color
+                }
+                _ => Color::NONE,
+            };
+            color.as_rgba_u8()
+        })
+        .collect();
+
+    // Update the cells in place, or build a new sprite if there isn't one for a grid this size.
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+    for (sprite_id, cell_sprite, texture) in cell_sprites.iter() {
+        if cell_sprite.grid_size != grid_size {
+            commands.entity(sprite_id).despawn();
+            continue;
+        }
+        if let Some(image) = images.get_mut(texture) {
+            if image.data != pixels {
+                image.data = pixels;
+            }
+        }
+        return;
+    }
+
+    spawn_grid_sprite(&mut commands, &mut images, &grid, pixels, 0.5)
+        .insert(GridCellSprite { grid_size });
+}
+
+/** Outline every extra simulation domain beside the main one, and fill in its walls.  Its fluid is
+drawn along with the main simulation's, offset by the domain's origin. */
+fn draw_domains(
+    domains: Query<(&SimDomain, &SimGrid)>,
+    grid_render_data: Res<GridRenderData>,
+    mut gizmos: Gizmos,
+) {
+    for (domain, grid) in domains.iter() {
+        let extent: Vec2 = grid_extent(grid);
+        gizmos.rect_2d(
+            domain.origin + extent * 0.5,
+            0.0,
+            extent,
+            grid_render_data.grid_color,
+        );
+
+        let cell_size: f32 = grid.cell_size as f32;
+        for row in 0..grid.dimensions.0 as usize {
+            for col in 0..grid.dimensions.1 as usize {
+                if grid.cell_type[row][col] != SimGridCellType::Solid {
+                    continue;
+                }
+                let center: Vec2 = grid
+                    .get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+                gizmos.rect_2d(
+                    domain.origin + center,
+                    0.0,
+                    Vec2::splat(cell_size),
+                    grid_render_data.solid_cell_color,
+                );
+            }
         }
     }
 }
 
-/// Draw the solid grid cells within the grid.
-fn draw_grid_solids(grid: Res<SimGrid>, grid_render_data: Res<GridRenderData>, mut gizmos: Gizmos) {
-    // For each column in each row, determine each cell's type.
+/// Draw a heatmap of each fluid cell's temperature, from blue (cold) to red (hot).
+fn draw_grid_temperature(
+    grid: Res<SimGrid>,
+    grid_render_data: Res<GridRenderData>,
+    mut gizmos: Gizmos,
+) {
+    if !grid_render_data.draw_temperature {
+        return;
+    }
+
+    let (min_temperature, max_temperature) = grid_render_data.temperature_range;
     for row in 0..grid.dimensions.0 {
         for col in 0..grid.dimensions.1 {
-            // Uncomment to visualize all non-air cells within the simulation!
-            // if grid.cell_type[row as usize][col as usize] != SimGridCellType::Air {
-            // 	draw_solid_cell(		// Draw something if solid.
-            // 		grid.as_ref(),
-            // 		Vec2 { x: row as f32, y: col as f32 },
-            // 		Color::BLUE,
-            // 		&mut gizmos
-            // 	);
-            // }
-
-            // Uncomment to visualize density per grid cell.
-            // let density: f32 = grid.get_density_at_position(grid.get_cell_position_from_coordinates(Vec2 { x: row as f32, y: col as f32 })) / 25.0;
-            // draw_solid_cell(
-            // 	grid.as_ref(),
-            // 	Vec2 { x: row as f32, y: col as f32 },
-            // 	util::generate_color_from_gradient(&vec![JUICE_BLUE, JUICE_GREEN, JUICE_YELLOW, JUICE_RED], density),
-            // 	&mut gizmos
-            // );
-
-            match grid.cell_type[row as usize][col as usize] {
-                SimGridCellType::Fluid => continue, // Do nothing if fluid.
-                SimGridCellType::Air => continue,   // Do nothing if air.
-                SimGridCellType::Solid => draw_solid_cell(
-                    // Draw something if solid.
-                    grid.as_ref(),
-                    Vec2 {
-                        x: row as f32,
-                        y: col as f32,
-                    },
-                    grid_render_data.solid_cell_color,
-                    &mut gizmos,
+            // Only fluid cells have a meaningful temperature; everything else is ambient.
+            if grid.cell_type[row as usize][col as usize] != SimGridCellType::Fluid {
+                continue;
+            }
+
+            let cell_coordinates: Vec2 = Vec2 {
+                x: row as f32,
+                y: col as f32,
+            };
+            let temperature: f32 = grid.temperature[grid.get_lookup_index(cell_coordinates)];
+            draw_solid_cell(
+                grid.as_ref(),
+                cell_coordinates,
+                util::generate_color_from_gradient(
+                    &grid_render_data.temperature_colors.to_vec(),
+                    (temperature - min_temperature) / (max_temperature - min_temperature),
                 ),
+                &mut gizmos,
+            );
+        }
+    }
+}
+
+/// Draw the paths that path-following faucets sweep along.
+fn draw_faucet_paths(
+    faucets: Query<(&SimFaucet, Option<&SimDomainMember>)>,
+    domains: Query<&SimDomain>,
+    mut gizmos: Gizmos,
+) {
+    for (faucet, member) in faucets.iter() {
+        if faucet.path.len() > 1 {
+            let origin: Vec2 = domain_origin(member, &domains);
+            gizmos.linestrip_2d(
+                faucet.path.iter().map(|point| origin + *point),
+                Color::BISQUE.with_a(0.4),
+            );
+        }
+    }
+}
+
+/// Outline each container, with a marker line showing how high its fluid would sit at rest.
+fn draw_containers(containers: Query<&SimContainer>, mut gizmos: Gizmos) {
+    for container in containers.iter() {
+        let size: Vec2 = container.max - container.min;
+        gizmos.rect_2d(container.min + size * 0.5, 0.0, size, Color::CYAN);
+
+        let fill_height: f32 = container.min.y + size.y * container.fill_level().min(1.0);
+        gizmos.line_2d(
+            Vec2::new(container.min.x, fill_height),
+            Vec2::new(container.max.x, fill_height),
+            Color::YELLOW,
+        );
+    }
+}
+
+/// Draw each spinner's hub and blades where they are right now.
+fn draw_spinners(
+    spinners: Query<(&SimSpinner, Option<&SimDomainMember>)>,
+    domains: Query<&SimDomain>,
+    mut gizmos: Gizmos,
+) {
+    for (spinner, member) in spinners.iter() {
+        let position: Vec2 = domain_origin(member, &domains) + spinner.position;
+        // Waterwheels are drawn with a rim, so they can be told apart from motorized spinners.
+        let color: Color = if spinner.inertia.is_some() {
+            gizmos.circle_2d(position, spinner.radius, Color::BISQUE.with_a(0.4));
+            Color::BISQUE
+        } else {
+            Color::ORANGE
+        };
+        gizmos.circle_2d(position, 2.0, color);
+        for direction in spinner.blade_directions() {
+            gizmos.line_2d(position, position + direction * spinner.radius, color);
+        }
+    }
+}
+
+/** Draw each attractor's center and how far its pull reaches; attractors are purple and repulsors
+are red. */
+fn draw_attractors(attractors: Query<&SimAttractor>, mut gizmos: Gizmos) {
+    for attractor in attractors.iter() {
+        let color: Color = if attractor.strength >= 0.0 {
+            Color::PURPLE
+        } else {
+            Color::SALMON
+        };
+        gizmos.circle_2d(attractor.position, 2.0, color);
+        gizmos.circle_2d(attractor.position, attractor.radius, color.with_a(0.4));
+    }
+}
+
+/** Draw each flow meter as a line across the fluid, with a short arrow from its middle showing which
+way fluid has to cross it to count as positive flow. */
+fn draw_flow_meters(flow_meters: Query<&SimFlowMeter>, mut gizmos: Gizmos) {
+    for flow_meter in flow_meters.iter() {
+        gizmos.line_2d(flow_meter.start, flow_meter.end, Color::GOLD);
+        let middle: Vec2 = (flow_meter.start + flow_meter.end) * 0.5;
+        let tip: Vec2 = middle + flow_meter.normal() * 8.0;
+        gizmos.line_2d(middle, tip, Color::GOLD);
+        gizmos.circle_2d(tip, 1.5, Color::GOLD);
+    }
+}
+
+/// Draw each probe as a small crosshair over the point it samples.
+fn draw_probes(probes: Query<&SimProbe>, mut gizmos: Gizmos) {
+    for probe in probes.iter() {
+        gizmos.circle_2d(probe.position, 3.0, Color::LIME_GREEN);
+        gizmos.line_2d(
+            probe.position - Vec2::X * 5.0,
+            probe.position + Vec2::X * 5.0,
+            Color::LIME_GREEN,
+        );
+        gizmos.line_2d(
+            probe.position - Vec2::Y * 5.0,
+            probe.position + Vec2::Y * 5.0,
+            Color::LIME_GREEN,
+        );
+    }
+}
+
+/** Draw each pump's intake and outlet, joined by a dashed line, with an arrow showing which way its
+outlet pushes fluid.  Pumps with fluid inside of them are drawn brighter. */
+fn draw_pumps(pumps: Query<&SimPump>, mut gizmos: Gizmos) {
+    for pump in pumps.iter() {
+        let color: Color = if pump.in_transit.is_empty() {
+            Color::TEAL.with_a(0.5)
+        } else {
+            Color::TEAL
+        };
+        gizmos.circle_2d(pump.intake, 4.0, color);
+        gizmos.circle_2d(pump.outlet, 2.0, color);
+
+        let dash_count: usize = (pump.intake.distance(pump.outlet) / 8.0).ceil().max(1.0) as usize;
+        for dash in (0..dash_count).step_by(2) {
+            let start: Vec2 = pump
+                .intake
+                .lerp(pump.outlet, dash as f32 / dash_count as f32);
+            let end: Vec2 = pump
+                .intake
+                .lerp(pump.outlet, (dash + 1) as f32 / dash_count as f32);
+            gizmos.line_2d(start, end, color);
+        }
+
+        let polar_velocity: Vec2 = cartesian_to_polar(pump.velocity);
+        draw_vector_arrow(
+            pump.outlet,
+            polar_velocity.y,
+            (polar_velocity.x * 0.1).min(20.0),
+            color,
+            &mut gizmos,
+        );
+    }
+}
+
+/** Draw a faint arrow in every cell the wind is blowing through, pointing the way it blows; the
+arrows grow with the wind's strength, up to two cells long. */
+fn draw_wind(grid: Res<SimGrid>, mut gizmos: Gizmos) {
+    let cell_size: f32 = grid.cell_size as f32;
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            let Some(wind) = grid.wind.get(row * grid.dimensions.1 as usize + col) else {
+                continue;
+            };
+            if *wind == Vec2::ZERO {
+                continue;
             }
+
+            let polar_wind: Vec2 = cartesian_to_polar(*wind);
+            let cell_center: Vec2 =
+                grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+            draw_vector_arrow(
+                cell_center,
+                polar_wind.y,
+                cell_size * (polar_wind.x / 500.0).min(2.0),
+                Color::ALICE_BLUE.with_a(0.3),
+                &mut gizmos,
+            );
+        }
+    }
+}
+
+/// Draw an arrow across every one-way valve cell, pointing the way it lets fluid through.
+fn draw_valves(grid: Res<SimGrid>, mut gizmos: Gizmos) {
+    let cell_size: f32 = grid.cell_size as f32;
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            let Some(direction) = grid.get_valve(row, col) else {
+                continue;
+            };
+
+            let allowed: Vec2 = direction.as_vector();
+            let cell_center: Vec2 =
+                grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+            draw_vector_arrow(
+                cell_center - allowed * cell_size * 0.4,
+                cartesian_to_polar(allowed).y,
+                cell_size * 0.8,
+                Color::LIME_GREEN,
+                &mut gizmos,
+            );
+        }
+    }
+}
+
+/** Outline the cells whose particles narrow-band FLIP has set aside, in the color of their fluid;
+otherwise the fluid below the band would look like it had drained away. */
+fn draw_narrow_band_interior(grid: Res<SimGrid>, mut gizmos: Gizmos) {
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            let lookup_index: usize = row * grid.dimensions.1 as usize + col;
+            let Some(particle) = grid
+                .narrow_band_interior
+                .get(lookup_index)
+                .and_then(|stored| stored.first())
+            else {
+                continue;
+            };
+            draw_solid_cell(
+                &grid,
+                Vec2::new(row as f32, col as f32),
+                particle.material.color(),
+                &mut gizmos,
+            );
         }
     }
 }
@@ -435,8 +2189,30 @@ fn draw_solid_cell(grid: &SimGrid, cell_coordinates: Vec2, color: Color, gizmos:
     gizmos.rect_2d(position, 0.0, Vec2::splat(grid.cell_size as f32), color);
 }
 
-/// Draw grid cells based on SimGrid using Bevy's Gizmos!
-fn draw_grid_cells(grid: Res<SimGrid>, grid_render_data: Res<GridRenderData>, mut gizmos: Gizmos) {
+/** Work out which grid lines to draw when each cell is `cell_pixels` wide on screen.  Returns how
+many cells apart the major lines are, and how opaque to draw the minor lines between them: these fade
+out as the camera zooms out, and the major lines spread further apart (by a factor of
+`GRID_MAJOR_LINE_INTERVAL` at a time) whenever they'd otherwise be crammed together. */
+pub fn grid_line_levels(cell_pixels: f32) -> (usize, f32) {
+    let cell_pixels: f32 = cell_pixels.max(f32::EPSILON);
+    let mut major_interval: usize = GRID_MAJOR_LINE_INTERVAL;
+    while (major_interval as f32) * cell_pixels < GRID_LINE_MIN_SPACING {
+        major_interval *= GRID_MAJOR_LINE_INTERVAL;
+    }
+    let minor_opacity: f32 = ((cell_pixels - GRID_LINE_MIN_SPACING)
+        / (GRID_LINE_FULL_SPACING - GRID_LINE_MIN_SPACING))
+        .clamp(0.0, 1.0);
+    (major_interval, minor_opacity)
+}
+
+/** Draw grid cells based on SimGrid using Bevy's Gizmos!  Only the lines the camera can see are
+drawn, and how many of them depends on how far the camera is zoomed out (see `grid_line_levels`). */
+fn draw_grid_cells(
+    grid: Res<SimGrid>,
+    grid_render_data: Res<GridRenderData>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut gizmos: Gizmos,
+) {
     let grid_width: f32 = (grid.dimensions.1 * grid.cell_size) as f32;
     let grid_height: f32 = (grid.dimensions.0 * grid.cell_size) as f32;
 
@@ -463,42 +2239,66 @@ fn draw_grid_cells(grid: Res<SimGrid>, grid_render_data: Res<GridRenderData>, mu
         return;
     }
 
+    // Find how big a cell is on screen, and which part of the grid the camera can see.
+    let cell_size: f32 = grid.cell_size as f32;
+    let (cell_pixels, view): (f32, Rect) = match cameras.get_single() {
+        Ok((transform, projection)) => {
+            let center: Vec2 = transform.translation.truncate();
+            let scale: Vec2 = transform.scale.truncate();
+            (
+                cell_size / (projection.scale * scale.x),
+                Rect::from_corners(
+                    center + projection.area.min * scale,
+                    center + projection.area.max * scale,
+                ),
+            )
+        }
+        Err(_) => (cell_size, Rect::new(0.0, 0.0, grid_width, grid_height)),
+    };
+    let (major_interval, minor_opacity): (usize, f32) = grid_line_levels(cell_pixels);
+    let minor_color: Color = grid_render_data
+        .grid_color
+        .with_a(grid_render_data.grid_color.a() * minor_opacity);
+
+    // The lines, out of `count` cells' worth, lying between `min` and `max` along one axis.
+    let visible_lines = |min: f32, max: f32, count: usize| {
+        let first: usize = (min / cell_size).floor().clamp(0.0, count as f32) as usize;
+        let last: usize = (max / cell_size).ceil().clamp(0.0, count as f32) as usize;
+        first..=last
+    };
+    // The color to draw line `i` of `count` in, if it's drawn at all.
+    let line_color = |i: usize, count: usize| -> Option<Color> {
+        if i % major_interval == 0 || i == count {
+            Some(grid_render_data.grid_color)
+        } else if minor_opacity > 0.0 {
+            Some(minor_color)
+        } else {
+            None
+        }
+    };
+
     // Draw vertical grid lines.
-    for i in 0..((grid.dimensions.1 as u16) + 1) {
-        let cell_bottom_position: Vec2 = Vec2 {
-            x: (i * grid.cell_size) as f32,
-            y: 0.0,
-        };
-        let cell_top_position: Vec2 = Vec2 {
-            x: (i * grid.cell_size) as f32,
-            y: grid_height,
+    for i in visible_lines(view.min.x, view.max.x, grid.dimensions.1 as usize) {
+        let Some(color) = line_color(i, grid.dimensions.1 as usize) else {
+            continue;
         };
-        gizmos.line_2d(
-            cell_bottom_position,
-            cell_top_position,
-            grid_render_data.grid_color,
-        );
+        let x: f32 = i as f32 * cell_size;
+        gizmos.line_2d(Vec2 { x, y: 0.0 }, Vec2 { x, y: grid_height }, color);
     }
 
     // Draw horizontal grid lines.
-    for i in 0..((grid.dimensions.0 as u16) + 1) {
-        let cell_left_position: Vec2 = Vec2 {
-            x: 0.0,
-            y: (i * grid.cell_size) as f32,
+    for i in visible_lines(view.min.y, view.max.y, grid.dimensions.0 as usize) {
+        let Some(color) = line_color(i, grid.dimensions.0 as usize) else {
+            continue;
         };
-        let cell_right_position: Vec2 = Vec2 {
-            x: grid_width,
-            y: (i * grid.cell_size) as f32,
-        };
-        gizmos.line_2d(
-            cell_left_position,
-            cell_right_position,
-            grid_render_data.grid_color,
-        );
+        let y: f32 = i as f32 * cell_size;
+        gizmos.line_2d(Vec2 { x: 0.0, y }, Vec2 { x: grid_width, y }, color);
     }
 }
 
-/// Draw velocity vectors based on SimGrid using Bevy's Gizmos!
+/** Draw each cell's velocity as an arrow using Bevy's Gizmos!  Arrow length is the velocity's
+magnitude scaled by `vector_magnitude_scale`, clamped to `vector_max_length` so fast-moving fluid
+doesn't scribble over its neighbors. */
 fn draw_grid_vectors(
     grid: Res<SimGrid>,
     grid_render_data: Res<GridRenderData>,
@@ -508,61 +2308,30 @@ fn draw_grid_vectors(
         return;
     }
 
-    for row in 0..grid.dimensions.0 {
-        for col in 0..grid.dimensions.1 {
-            /* Indices for each column/row of each u/v velocity component on the grid.  Note that
-            because each cell has two velocity components going in either direction, the
-            vectors containing said components are one element larger in either rows or
-            columns.  This fact prevents the following code from going out of bounds, so long
-            as grid.velocity_u and grid.velocity_v are constructed properly. */
-            let column_u0: usize = col as usize;
-            let column_u1: usize = (col + 1) as usize;
-            let row_u: usize = row as usize;
-
-            let row_v0: usize = row as usize;
-            let row_v1: usize = (row + 1) as usize;
-            let column_v: usize = col as usize;
-
-            // Horizontal velocity components.
-            let velocities_u: [f32; 2] = [
-                grid.velocity_u[row_u][column_u0],
-                grid.velocity_u[row_u][column_u1],
-            ];
-            // Vertical velocity components.
-            let velocities_v: [f32; 2] = [
-                grid.velocity_v[row_v0][column_v],
-                grid.velocity_v[row_v1][column_v],
-            ];
-
-            // Calculate magnitude of velocity within the call.
-            let velocity_vector_cartesian: Vec2 = Vec2 {
-                x: (velocities_u[0] + velocities_u[1]) / 2.0,
-                y: (velocities_v[0] + velocities_v[1]) / 2.0,
-            };
-
-            // Calculate velocity direction and magnitude based on u and v components.
-            let velocity_vector_polar: Vec2 = util::cartesian_to_polar(velocity_vector_cartesian);
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            // Calculate velocity direction and magnitude from the cell's averaged u and v components.
+            let velocity_vector_polar: Vec2 =
+                util::cartesian_to_polar(grid.get_cell_velocity(row, col));
 
             // Skip drawing if the vector is too short.
             if velocity_vector_polar[0] < 0.2 {
                 continue;
             }
 
-            // Find the center of each grid cell to draw the vector arrows.
-            // TODO: Refactor to use grid.get_cell_center_position_from_coordinates().
-            let half_cell_size: f32 = (grid.cell_size as f32) / 2.0;
-            let cell_x: f32 = (col * grid.cell_size) as f32;
-            let cell_y: f32 = (row * grid.cell_size) as f32;
-            let grid_height: f32 = (grid.dimensions.0 * grid.cell_size) as f32;
-            let cell_center_position: Vec2 = Vec2 {
-                x: cell_x + half_cell_size,
-                y: grid_height - cell_y - half_cell_size,
-            };
+            let cell_center_position: Vec2 =
+                grid.get_cell_center_position_from_coordinates(&Vec2 {
+                    x: row as f32,
+                    y: col as f32,
+                });
+            let arrow_length: f32 = (velocity_vector_polar[0]
+                * grid_render_data.vector_magnitude_scale)
+                .min(grid_render_data.vector_max_length);
 
             draw_vector_arrow(
                 cell_center_position,
                 velocity_vector_polar[1],
-                velocity_vector_polar[0] * grid_render_data.vector_magnitude_scale,
+                arrow_length,
                 grid_render_data.vector_color,
                 &mut gizmos,
             );
@@ -570,6 +2339,60 @@ fn draw_grid_vectors(
     }
 }
 
+/** Draw streamlines through the grid's velocity field, which show the shape of the flow far better
+than an arrow per cell.  They're seeded on a regular lattice across the grid, or around the cursor
+if `streamlines_from_cursor` is set. */
+fn draw_streamlines(
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    grid: Res<SimGrid>,
+    grid_render_data: Res<GridRenderData>,
+    mut gizmos: Gizmos,
+) {
+    if !grid_render_data.draw_streamlines {
+        return;
+    }
+
+    let cell_size: f32 = grid.cell_size as f32;
+    let seeds: Vec<Vec2> = if grid_render_data.streamlines_from_cursor {
+        // A ring of seeds around the cursor, so the flow around it shows from every side.
+        let cursor_position: Vec2 = get_cursor_position(&windows, &cameras);
+        let seed_count: usize = 8;
+        let seed_radius: f32 = cell_size * 2.0;
+        (0..seed_count)
+            .map(|i| {
+                let angle: f32 = i as f32 / seed_count as f32 * PI * 2.0;
+                cursor_position + Vec2::new(angle.cos(), angle.sin()) * seed_radius
+            })
+            .collect()
+    } else {
+        let spacing: usize = grid_render_data.streamline_spacing.max(1) as usize;
+        let mut seeds: Vec<Vec2> = Vec::new();
+        for row in (spacing / 2..grid.dimensions.0 as usize).step_by(spacing) {
+            for col in (spacing / 2..grid.dimensions.1 as usize).step_by(spacing) {
+                seeds.push(grid.get_cell_center_position_from_coordinates(&Vec2 {
+                    x: row as f32,
+                    y: col as f32,
+                }));
+            }
+        }
+        seeds
+    };
+
+    for seed in seeds {
+        let streamline: Vec<Vec2> = trace_streamline(
+            &grid,
+            seed,
+            cell_size * 0.5,
+            grid_render_data.streamline_max_steps,
+        );
+        if streamline.len() < 2 {
+            continue;
+        }
+        gizmos.linestrip_2d(streamline, grid_render_data.streamline_color);
+    }
+}
+
 /// Helper function to draw a vector arrow using Bevy's Gizmos.
 pub fn draw_vector_arrow(
     tail_position: Vec2,
@@ -656,6 +2479,13 @@ fn draw_tool_guides(
             JUICE_SKY_BLUE,
         ),
         SimTool::AddFaucet => {
+            // Preview the path being drawn for a path-following faucet.
+            if ui_state.is_drawing_faucet_path {
+                let mut path: Vec<Vec2> = ui_state.faucet_path.clone();
+                path.push(cursor_position);
+                gizmos.linestrip_2d(path, Color::BISQUE);
+            }
+
             draw_vector_arrow(
                 cursor_position,
                 degrees_to_radians(ui_state.faucet_direction),
@@ -670,6 +2500,57 @@ fn draw_tool_guides(
             ui_state.drain_radius,
             Color::GOLD,
         ),
+        SimTool::Spinner => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            ui_state.spinner_radius,
+            Color::ORANGE,
+        ),
+        SimTool::AddValve => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            grid.cell_size as f32 * 1.5,
+            Color::LIME_GREEN,
+        ),
+        SimTool::AddPorousWall => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            grid.cell_size as f32 * 1.5,
+            Color::YELLOW,
+        ),
+        SimTool::Probe => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            grid.cell_size as f32 * 0.5,
+            Color::LIME_GREEN,
+        ),
+        SimTool::Attractor => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            ui_state.attractor_radius,
+            Color::PURPLE,
+        ),
+        SimTool::Vortex => draw_selection_circle(
+            &mut gizmos,
+            cursor_position,
+            ui_state.vortex_radius,
+            JUICE_SKY_BLUE,
+        ),
+        SimTool::Wind => {
+            draw_selection_circle(
+                &mut gizmos,
+                cursor_position,
+                ui_state.wind_radius,
+                Color::ALICE_BLUE,
+            );
+            draw_vector_arrow(
+                cursor_position,
+                degrees_to_radians(ui_state.wind_direction),
+                ui_state.wind_radius,
+                Color::ALICE_BLUE,
+                &mut gizmos,
+            );
+        }
         SimTool::AddWall => draw_selection_circle(
             &mut gizmos,
             cursor_position,
@@ -682,6 +2563,78 @@ fn draw_tool_guides(
             grid.cell_size as f32 * 1.5,
             Color::SALMON,
         ),
+        SimTool::Container => {
+            // Preview the container being dragged out.
+            if !ui_state.is_drawing_container {
+                return;
+            }
+
+            let center: Vec2 = (ui_state.container_corner + cursor_position) * 0.5;
+            let size: Vec2 = (ui_state.container_corner - cursor_position).abs();
+            gizmos.rect_2d(center, 0.0, size, Color::CYAN);
+        }
+        SimTool::FlowMeter => {
+            // Preview the flow meter being dragged out.
+            if !ui_state.is_drawing_flow_meter {
+                return;
+            }
+
+            gizmos.line_2d(ui_state.flow_meter_start, cursor_position, Color::GOLD);
+        }
+        SimTool::AddPump => {
+            // Preview the pump being dragged out, along with which way its outlet will push fluid.
+            if !ui_state.is_drawing_pump {
+                return;
+            }
+
+            gizmos.line_2d(ui_state.pump_intake, cursor_position, Color::TEAL);
+            draw_vector_arrow(
+                cursor_position,
+                degrees_to_radians(ui_state.pump_direction),
+                ui_state.pump_pressure,
+                Color::TEAL,
+                &mut gizmos,
+            );
+        }
+        SimTool::AddPipe => {
+            // Preview the pipe being drawn, extended to wherever the mouse currently is.
+            if !ui_state.is_drawing_pipe {
+                return;
+            }
+
+            let mut path: Vec<Vec2> = ui_state.pipe_path.clone();
+            path.push(cursor_position);
+            for cell in
+                grid.get_pipe_cells(&path, ui_state.pipe_inlet_width, ui_state.pipe_outlet_width)
+            {
+                draw_solid_cell(grid.as_ref(), cell, Color::GOLD, &mut gizmos);
+            }
+        }
+        SimTool::AddObstacle => {
+            // Preview the obstacle's cells where it would be stamped.
+            if !grid.is_position_within_grid(&cursor_position) {
+                return;
+            }
+
+            let obstacle: SimObstacle = ui_state.selected_obstacle.into();
+            let center: Vec2 = grid.get_cell_coordinates_from_position(&cursor_position);
+            for (row_offset, col_offset) in obstacle.cells(ui_state.obstacle_rotation) {
+                let cell_coordinates: Vec2 = Vec2 {
+                    x: center.x + row_offset as f32,
+                    y: center.y + col_offset as f32,
+                };
+
+                // Cells outside of the grid are skipped when stamping, so don't preview them.
+                if cell_coordinates.x < 0.0
+                    || cell_coordinates.y < 0.0
+                    || cell_coordinates.x >= grid.dimensions.0 as f32
+                    || cell_coordinates.y >= grid.dimensions.1 as f32
+                {
+                    continue;
+                }
+                draw_solid_cell(grid.as_ref(), cell_coordinates, Color::GOLD, &mut gizmos);
+            }
+        }
         SimTool::AddFluid => draw_selection_circle(
             &mut gizmos,
             cursor_position,
@@ -4,6 +4,7 @@ use bevy_save::SavePlugin;
 pub mod error;
 pub mod file_system;
 pub mod juice_renderer;
+pub mod particle_batch;
 pub mod simulation;
 pub mod util;
 
@@ -0,0 +1,347 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::core_2d::Transparent2d,
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult,
+            RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer,
+            BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, FragmentState,
+            MultisampleState, PipelineCache, PrimitiveState, RenderPipelineDescriptor,
+            SamplerBindingType, ShaderStages, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, TextureSampleType, TextureViewDimension,
+            VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ExtractedView, ViewTarget},
+        Render, RenderApp, RenderSet,
+    },
+    sprite::{Mesh2dPipeline, Mesh2dPipelineKey, SetMesh2dViewBindGroup},
+    utils::FloatOrd,
+};
+
+/// Shader that draws the particle batch.
+const PARTICLE_BATCH_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x6a75_6963_655f_6261_7463_685f_7368_6472);
+/// f32s in the particle batch shader's ParticleInstance struct.
+const PARTICLE_INSTANCE_FLOATS: usize = 8;
+/// Fewest particles the instance buffer is made to hold.
+const MIN_INSTANCE_CAPACITY: usize = 1024;
+
+/** Draws every particle rendered as a sprite with a single instanced draw call, rather than giving
+each one a sprite of its own for Bevy to extract, sort, and batch every frame.  The main world
+gathers each frame's particles into a `ParticleBatch` (see `juice_renderer::update_particle_batch`),
+which is copied into one GPU buffer of positions, sizes, and colors and drawn among the other
+transparent 2D items. */
+pub struct ParticleBatchPlugin;
+impl Plugin for ParticleBatchPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            PARTICLE_BATCH_SHADER_HANDLE,
+            "../assets/shaders/particle_batch.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugins(ExtractComponentPlugin::<ParticleBatch>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<SpecializedRenderPipelines<ParticleBatchPipeline>>()
+            .init_resource::<ParticleBatchBuffers>()
+            .add_render_command::<Transparent2d, DrawParticleBatch>()
+            .add_systems(
+                Render,
+                (
+                    queue_particle_batches.in_set(RenderSet::Queue),
+                    prepare_particle_batches.in_set(RenderSet::PrepareResources),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        // The pipeline borrows the 2D mesh pipeline's view layout, which only exists once it's built.
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<ParticleBatchPipeline>();
+    }
+}
+
+/// One particle as the particle batch shader sees it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ParticleInstance {
+    pub position: Vec2,
+    pub size: f32,
+    pub color: Color,
+}
+
+/** Every particle to draw this frame, in the order they're drawn (later ones on top).  There is one
+of these, on an entity of its own spawned with the camera. */
+#[derive(Component, Clone, Default, ExtractComponent)]
+pub struct ParticleBatch {
+    pub instances: Vec<ParticleInstance>,
+    pub texture: Handle<Image>,
+}
+
+/// The render pipeline for particle batches, along with the bind group layouts it's built from.
+#[derive(Resource)]
+struct ParticleBatchPipeline {
+    view_layout: BindGroupLayout,
+    texture_layout: BindGroupLayout,
+}
+
+impl FromWorld for ParticleBatchPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let view_layout: BindGroupLayout = world.resource::<Mesh2dPipeline>().view_layout.clone();
+        let texture_layout: BindGroupLayout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("particle_batch_texture_layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        Self {
+            view_layout,
+            texture_layout,
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for ParticleBatchPipeline {
+    type Key = Mesh2dPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let format: TextureFormat = if key.contains(Mesh2dPipelineKey::HDR) {
+            ViewTarget::TEXTURE_FORMAT_HDR
+        } else {
+            TextureFormat::bevy_default()
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("particle_batch_pipeline".into()),
+            layout: vec![self.view_layout.clone(), self.texture_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: PARTICLE_BATCH_SHADER_HANDLE,
+                shader_defs: Vec::new(),
+                entry_point: "vertex".into(),
+                buffers: vec![VertexBufferLayout {
+                    array_stride: (PARTICLE_INSTANCE_FLOATS * 4) as u64,
+                    step_mode: VertexStepMode::Instance,
+                    attributes: vec![
+                        VertexAttribute {
+                            format: VertexFormat::Float32x4,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32x4,
+                            offset: 16,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+            },
+            fragment: Some(FragmentState {
+                shader: PARTICLE_BATCH_SHADER_HANDLE,
+                shader_defs: Vec::new(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        }
+    }
+}
+
+/** The GPU side of the particle batch.  The instance buffer (and the byte staging buffer it's
+filled from) are kept from frame to frame, and only grow when there are more particles to draw than
+they hold. */
+#[derive(Resource, Default)]
+struct ParticleBatchBuffers {
+    instances: Option<Buffer>,
+    capacity: usize,
+    bytes: Vec<u8>,
+    instance_count: u32,
+    texture_bind_group: Option<BindGroup>,
+}
+
+/// Add the particle batch to every 2D view's transparent phase.
+fn queue_particle_batches(
+    draw_functions: Res<DrawFunctions<Transparent2d>>,
+    pipeline: Res<ParticleBatchPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<ParticleBatchPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    msaa: Res<Msaa>,
+    batches: Query<(Entity, &ParticleBatch)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent2d>)>,
+) {
+    let draw_function = draw_functions.read().id::<DrawParticleBatch>();
+    for (view, mut transparent_phase) in views.iter_mut() {
+        let key: Mesh2dPipelineKey = Mesh2dPipelineKey::from_msaa_samples(msaa.samples())
+            | Mesh2dPipelineKey::from_hdr(view.hdr);
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &pipeline, key);
+        for (entity, batch) in batches.iter() {
+            if batch.instances.is_empty() {
+                continue;
+            }
+            // Drawn at the same depth the particle sprites used to be, beneath faucets and drains.
+            transparent_phase.add(Transparent2d {
+                sort_key: FloatOrd(0.0),
+                entity,
+                pipeline: pipeline_id,
+                draw_function,
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+/// Copy the particle batch into the instance buffer, and bind its texture.
+fn prepare_particle_batches(
+    batches: Query<&ParticleBatch>,
+    pipeline: Res<ParticleBatchPipeline>,
+    images: Res<RenderAssets<Image>>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut buffers: ResMut<ParticleBatchBuffers>,
+) {
+    buffers.instance_count = 0;
+    let Some(batch) = batches.iter().next() else {
+        return;
+    };
+    let Some(image) = images.get(&batch.texture) else {
+        return;
+    };
+    if batch.instances.is_empty() {
+        return;
+    }
+
+    if buffers.instances.is_none() || buffers.capacity < batch.instances.len() {
+        let capacity: usize = batch
+            .instances
+            .len()
+            .next_power_of_two()
+            .max(MIN_INSTANCE_CAPACITY);
+        buffers.instances = Some(device.create_buffer(&BufferDescriptor {
+            label: Some("particle_batch_instances"),
+            size: (capacity * PARTICLE_INSTANCE_FLOATS * 4) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        buffers.capacity = capacity;
+    }
+
+    let mut bytes: Vec<u8> = std::mem::take(&mut buffers.bytes);
+    bytes.clear();
+    for instance in batch.instances.iter() {
+        let [red, green, blue, alpha] = instance.color.as_linear_rgba_f32();
+        let floats: [f32; PARTICLE_INSTANCE_FLOATS] = [
+            instance.position.x,
+            instance.position.y,
+            instance.size,
+            0.0,
+            red,
+            green,
+            blue,
+            alpha,
+        ];
+        bytes.extend(floats.iter().flat_map(|value| value.to_ne_bytes()));
+    }
+    if let Some(instances) = buffers.instances.as_ref() {
+        queue.write_buffer(instances, 0, &bytes);
+    }
+    buffers.bytes = bytes;
+
+    buffers.texture_bind_group = Some(device.create_bind_group(
+        "particle_batch_texture_bind_group",
+        &pipeline.texture_layout,
+        &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&image.sampler),
+            },
+        ],
+    ));
+    buffers.instance_count = batch.instances.len() as u32;
+}
+
+type DrawParticleBatch = (
+    SetItemPipeline,
+    SetMesh2dViewBindGroup<0>,
+    DrawParticleInstances,
+);
+
+/// Bind the particle texture and instance buffer, and draw a quad for every particle in the batch.
+struct DrawParticleInstances;
+impl<P: PhaseItem> RenderCommand<P> for DrawParticleInstances {
+    type Param = SRes<ParticleBatchBuffers>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: (),
+        buffers: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let buffers: &'w ParticleBatchBuffers = buffers.into_inner();
+        let (Some(instances), Some(texture_bind_group)) = (
+            buffers.instances.as_ref(),
+            buffers.texture_bind_group.as_ref(),
+        ) else {
+            return RenderCommandResult::Failure;
+        };
+        if buffers.instance_count == 0 {
+            return RenderCommandResult::Failure;
+        }
+
+        pass.set_bind_group(1, texture_bind_group, &[]);
+        pass.set_vertex_buffer(0, instances.slice(..));
+        pass.draw(0..6, 0..buffers.instance_count);
+        RenderCommandResult::Success
+    }
+}
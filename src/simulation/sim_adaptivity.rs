@@ -0,0 +1,179 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use super::sim_state_manager::{delete_particle, spawn_particle};
+use super::util::interpolate_velocity_component_with_gradient;
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+
+/** Spend the fluid's particles where they matter while `constraints.adaptive_particles` is on.  Slow
+particles that touch in calm flow are merged into bigger, heavier ones (up to
+`constraints.max_particle_mass`), so still pools run on fewer particles; merged particles caught in
+flow shearing faster than `constraints.split_shear` are split back in half, so splashes keep their
+detail. */
+pub fn adapt_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+) {
+    if !constraints.adaptive_particles {
+        return;
+    }
+
+    merge_slow_particles(commands, constraints, grid, particles);
+    split_sheared_particles(commands, constraints, grid, particles);
+}
+
+/** How quickly the flow at a position is being sheared out of shape, per second, along with the
+direction it is being stretched in. */
+pub fn calculate_shear_rate(grid: &SimGrid, position: Vec2) -> (f32, Vec2) {
+    let (_, u_gradient) =
+        interpolate_velocity_component_with_gradient(position, grid, &grid.velocity_u, true);
+    let (_, v_gradient) =
+        interpolate_velocity_component_with_gradient(position, grid, &grid.velocity_v, false);
+
+    // Strain rate's two independent parts: stretching along the axes, and shearing across them.
+    let stretch: f32 = u_gradient.x - v_gradient.y;
+    let shear: f32 = u_gradient.y + v_gradient.x;
+    let rate: f32 = (stretch * stretch + shear * shear).sqrt();
+    (rate, Vec2::from_angle(0.5 * shear.atan2(stretch)))
+}
+
+/** Merge pairs of touching particles in the same cell that are both slow, in calm flow, and of the
+same material and group.  Each particle merges at most once per step. */
+fn merge_slow_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+) {
+    let mut merged: HashSet<Entity> = HashSet::new();
+    for lookup_index in 0..grid.spatial_lookup.cell_count() {
+        let cell_particles: Vec<Entity> = grid
+            .get_particles_in_lookup(lookup_index)
+            .iter()
+            .copied()
+            .filter(|id| particles.contains(*id) && !grid.is_particle_pending_removal(*id))
+            .collect();
+
+        for (index, first) in cell_particles.iter().enumerate() {
+            for second in cell_particles[index + 1..].iter() {
+                if merged.contains(first) || merged.contains(second) {
+                    continue;
+                }
+
+                let Ok([(_, mut kept), (_, absorbed)]) = particles.get_many_mut([*first, *second])
+                else {
+                    continue;
+                };
+                if !can_merge(constraints, grid, &kept, &absorbed) {
+                    continue;
+                }
+                combine_particles(kept.as_mut(), &absorbed, constraints.particle_radius);
+
+                merged.insert(*first);
+                merged.insert(*second);
+                let _ = delete_particle(commands, constraints, particles, grid, *second);
+            }
+        }
+    }
+}
+
+/// Whether two particles are allowed to merge into one.
+fn can_merge(
+    constraints: &SimConstraints,
+    grid: &SimGrid,
+    first: &SimParticle,
+    second: &SimParticle,
+) -> bool {
+    if first.material != second.material
+        || first.group != second.group
+        || first.mass + second.mass > constraints.max_particle_mass
+    {
+        return false;
+    }
+    if first.velocity.length() > constraints.merge_speed
+        || second.velocity.length() > constraints.merge_speed
+    {
+        return false;
+    }
+    if first.position.distance(second.position) > first.radius + second.radius {
+        return false;
+    }
+
+    let midpoint: Vec2 = (first.position + second.position) * 0.5;
+    calculate_shear_rate(grid, midpoint).0 < constraints.split_shear
+}
+
+/** Fold `absorbed` into `kept`, conserving mass and momentum.  The merged particle covers as much
+area as the two did. */
+fn combine_particles(kept: &mut SimParticle, absorbed: &SimParticle, base_radius: f32) {
+    let mass: f32 = kept.mass + absorbed.mass;
+    let kept_share: f32 = kept.mass / mass;
+    let absorbed_share: f32 = absorbed.mass / mass;
+
+    kept.position = kept.position * kept_share + absorbed.position * absorbed_share;
+    kept.previous_position =
+        kept.previous_position * kept_share + absorbed.previous_position * absorbed_share;
+    kept.velocity = kept.velocity * kept_share + absorbed.velocity * absorbed_share;
+    kept.affine_velocity =
+        kept.affine_velocity * kept_share + absorbed.affine_velocity * absorbed_share;
+    kept.temperature = kept.temperature * kept_share + absorbed.temperature * absorbed_share;
+    kept.age = kept.age.min(absorbed.age);
+    kept.mass = mass;
+    kept.radius = base_radius * mass.sqrt();
+}
+
+/** Split merged particles caught in fast shearing flow into two halves, laid out along the direction
+the flow is stretching in.  Particles that would leave half of themselves inside a wall stay whole. */
+fn split_sheared_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+) {
+    let mut halves: Vec<SimParticle> = Vec::new();
+    for (id, mut particle) in particles.iter_mut() {
+        if particle.mass <= 1.0 || grid.is_particle_pending_removal(id) {
+            continue;
+        }
+        let (shear_rate, stretch_direction) = calculate_shear_rate(grid, particle.position);
+        if shear_rate <= constraints.split_shear {
+            continue;
+        }
+
+        let mass: f32 = particle.mass * 0.5;
+        let radius: f32 = constraints.particle_radius * mass.sqrt();
+        let offset: Vec2 = stretch_direction * radius;
+        let half_position: Vec2 = particle.position + offset;
+        if !grid.is_position_within_grid(&half_position) {
+            continue;
+        }
+        let half_cell: Vec2 = grid.get_cell_coordinates_from_position(&half_position);
+        if grid.cell_type[half_cell.x as usize][half_cell.y as usize] == SimGridCellType::Solid {
+            continue;
+        }
+
+        particle.mass = mass;
+        particle.radius = radius;
+        halves.push(SimParticle {
+            position: half_position,
+            velocity: particle.velocity,
+            lookup_index: 0,
+            temperature: particle.temperature,
+            age: particle.age,
+            group: particle.group,
+            material: particle.material,
+            mass,
+            radius,
+            affine_velocity: particle.affine_velocity,
+            previous_position: particle.previous_position + offset,
+            spawn_id: 0,
+        });
+    }
+
+    for half in halves {
+        let _ = spawn_particle(commands, constraints, grid, half);
+    }
+}
@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+/// Width and height, in cells, of each chunk the grid is split into.
+pub const SIM_CHUNK_SIZE: usize = 16;
+
+/** The grid split into square chunks of `SIM_CHUNK_SIZE` cells, and whether each one has anything
+going on.  A chunk is active if it or any chunk touching it holds fluid (or the cells bordering it),
+a moving solid, a pump's intake or outlet, or a waterwheel; every other chunk is left out of the
+full-grid passes entirely, so a huge world that is only wet in one corner costs about as much as a
+small one.  The margin of neighboring chunks keeps every cell those passes read near the fluid
+inside of an active chunk.  Until it has been built (see `rebuild`), every chunk counts as
+active. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimGridChunks {
+    rows: usize,       // Number of chunks down the grid.
+    cols: usize,       // Number of chunks across the grid.
+    active: Vec<bool>, // Whether each chunk is active, row by row; empty until built.
+}
+
+impl SimGridChunks {
+    /** Work out which chunks of a grid with `dimensions` (rows, columns) are active, given the
+    cells with something going on in them.  `wraps` (vertical, horizontal) says which edges of the
+    grid wrap around, so chunks across them count as neighbors. */
+    pub fn rebuild(
+        &mut self,
+        dimensions: (usize, usize),
+        wraps: (bool, bool),
+        cells: impl Iterator<Item = (usize, usize)>,
+    ) {
+        self.rows = (dimensions.0 + SIM_CHUNK_SIZE - 1) / SIM_CHUNK_SIZE;
+        self.cols = (dimensions.1 + SIM_CHUNK_SIZE - 1) / SIM_CHUNK_SIZE;
+        let mut occupied: Vec<bool> = vec![false; self.rows * self.cols];
+        for (row, col) in cells {
+            occupied[(row / SIM_CHUNK_SIZE) * self.cols + col / SIM_CHUNK_SIZE] = true;
+        }
+
+        // Spread each occupied chunk to its neighbors, across wrapped edges too.
+        let neighbor = |index: usize, offset: isize, count: usize, wraps: bool| -> Option<usize> {
+            let neighbor: isize = index as isize + offset;
+            if (0..count as isize).contains(&neighbor) {
+                Some(neighbor as usize)
+            } else if wraps {
+                Some(neighbor.rem_euclid(count as isize) as usize)
+            } else {
+                None
+            }
+        };
+        self.active.clear();
+        self.active.resize(self.rows * self.cols, false);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                if !occupied[row * self.cols + col] {
+                    continue;
+                }
+                for row_offset in -1..=1 {
+                    for col_offset in -1..=1 {
+                        let neighbor_row = neighbor(row, row_offset, self.rows, wraps.0);
+                        let neighbor_col = neighbor(col, col_offset, self.cols, wraps.1);
+                        if let (Some(neighbor_row), Some(neighbor_col)) =
+                            (neighbor_row, neighbor_col)
+                        {
+                            self.active[neighbor_row * self.cols + neighbor_col] = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget which chunks are active, so every chunk counts as active until the next rebuild.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Number of chunks down and across the grid; zero until built.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Number of active chunks.
+    pub fn active_count(&self) -> usize {
+        self.active.iter().filter(|active| **active).count()
+    }
+
+    /// Whether the chunk holding the cell at (row, col) is active.
+    pub fn is_cell_active(&self, row: usize, col: usize) -> bool {
+        if self.active.is_empty() {
+            return true;
+        }
+        let chunk_row: usize = (row / SIM_CHUNK_SIZE).min(self.rows - 1);
+        let chunk_col: usize = (col / SIM_CHUNK_SIZE).min(self.cols - 1);
+        self.active[chunk_row * self.cols + chunk_col]
+    }
+
+    /** The runs of columns, out of `count` in a row, that lie in active chunks of the chunk row
+    holding `row`.  Also works for rows and columns of velocity points, which run one past the last
+    cell; those belong to the last chunk. */
+    pub fn active_spans(
+        &self,
+        row: usize,
+        count: usize,
+    ) -> impl Iterator<Item = Range<usize>> + '_ {
+        let built: bool = !self.active.is_empty();
+        let chunk_row: usize = (row / SIM_CHUNK_SIZE).min(self.rows.saturating_sub(1));
+        let active = move |chunk_col: usize| self.active[chunk_row * self.cols + chunk_col];
+        let mut whole_row: bool = !built;
+        let mut chunk_col: usize = 0;
+
+        std::iter::from_fn(move || {
+            if !built {
+                return std::mem::take(&mut whole_row).then_some(0..count);
+            }
+            while chunk_col < self.cols && !active(chunk_col) {
+                chunk_col += 1;
+            }
+            if chunk_col == self.cols {
+                return None;
+            }
+            let start: usize = chunk_col * SIM_CHUNK_SIZE;
+            while chunk_col < self.cols && active(chunk_col) {
+                chunk_col += 1;
+            }
+            let end: usize = if chunk_col == self.cols {
+                count
+            } else {
+                chunk_col * SIM_CHUNK_SIZE
+            };
+            Some(start..end)
+        })
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::SimParticle;
+
+/// Samples of the conserved quantities remembered before the oldest start being forgotten.
+pub const DIAGNOSTICS_HISTORY_LENGTH: usize = 600;
+
+/// Number of conserved quantities that can be plotted.
+pub const CONSERVED_QUANTITY_COUNT: usize = 4;
+/// A quantity the solver ought to conserve (or at least not create out of thin air).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimConservedQuantity {
+    #[default]
+    ParticleCount,
+    Mass,
+    Momentum,
+    KineticEnergy,
+}
+
+impl Into<SimConservedQuantity> for usize {
+    fn into(self) -> SimConservedQuantity {
+        match self {
+            0 => SimConservedQuantity::ParticleCount,
+            1 => SimConservedQuantity::Mass,
+            2 => SimConservedQuantity::Momentum,
+            3 => SimConservedQuantity::KineticEnergy,
+            _ => {
+                eprintln!("Invalid conserved quantity; defaulting to particle count!");
+                SimConservedQuantity::ParticleCount
+            }
+        }
+    }
+}
+
+impl SimConservedQuantity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ParticleCount => "Particle Count",
+            Self::Mass => "Mass",
+            Self::Momentum => "Momentum",
+            Self::KineticEnergy => "Kinetic Energy",
+        }
+    }
+}
+
+/// Totals over every fluid particle at one moment of simulated time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimConservationSample {
+    pub time: f32,             // Simulated seconds when the sample was taken.
+    pub particle_count: usize, // Fluid particles in the simulation.
+    pub mass: f32,             // Total mass; each particle's material density times its mass.
+    pub momentum: Vec2,        // Total momentum.
+    pub kinetic_energy: f32,   // Total kinetic energy.
+}
+
+impl SimConservationSample {
+    /// Add up the mass, momentum, and kinetic energy of `particles` at `time`.
+    pub fn measure<'a>(particles: impl Iterator<Item = &'a SimParticle>, time: f32) -> Self {
+        let mut sample = Self { time, ..default() };
+        for particle in particles {
+            let mass: f32 = particle.material.density() * particle.mass;
+            sample.particle_count += 1;
+            sample.mass += mass;
+            sample.momentum += particle.velocity * mass;
+            sample.kinetic_energy += 0.5 * mass * particle.velocity.length_squared();
+        }
+        sample
+    }
+
+    /// Read `quantity` out of this sample; momentum is read as its magnitude.
+    pub fn get(&self, quantity: SimConservedQuantity) -> f32 {
+        match quantity {
+            SimConservedQuantity::ParticleCount => self.particle_count as f32,
+            SimConservedQuantity::Mass => self.mass,
+            SimConservedQuantity::Momentum => self.momentum.length(),
+            SimConservedQuantity::KineticEnergy => self.kinetic_energy,
+        }
+    }
+}
+
+/** Tracks how the fluid's particle count, mass, momentum, and kinetic energy change while the
+simulation runs, so changes to the solver can be checked for leaking (or inventing) any of them.
+Drift is measured against the first sample taken since the diagnostics were last cleared. */
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SimDiagnostics {
+    pub baseline: Option<SimConservationSample>,
+    pub samples: VecDeque<SimConservationSample>,
+}
+
+impl SimDiagnostics {
+    /// Remember `sample`, forgetting the oldest one if the history is full.
+    pub fn record(&mut self, sample: SimConservationSample) {
+        self.baseline.get_or_insert(sample);
+        if self.samples.len() >= DIAGNOSTICS_HISTORY_LENGTH {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Forget every sample, including the baseline drift is measured against.
+    pub fn clear(&mut self) {
+        self.baseline = None;
+        self.samples.clear();
+    }
+
+    /// The most recent sample, if any.
+    pub fn latest(&self) -> Option<&SimConservationSample> {
+        self.samples.back()
+    }
+
+    /** How far `quantity` has drifted from the baseline as of the latest sample.  Momentum drift is
+    the magnitude of the change in momentum, not the change in its magnitude. */
+    pub fn drift(&self, quantity: SimConservedQuantity) -> f32 {
+        let (Some(baseline), Some(latest)) = (self.baseline, self.latest()) else {
+            return 0.0;
+        };
+        match quantity {
+            SimConservedQuantity::Momentum => (latest.momentum - baseline.momentum).length(),
+            _ => latest.get(quantity) - baseline.get(quantity),
+        }
+    }
+}
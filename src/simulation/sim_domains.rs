@@ -0,0 +1,278 @@
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::{EntityCommands, RunSystemOnce};
+use bevy::prelude::*;
+
+use super::sim_gpu::SimGpu;
+use super::sim_particle_pool::SimInactiveParticle;
+use super::sim_rng::SimRng;
+use super::sim_telemetry::SimStepStats;
+use super::{
+    step_simulation, use_tools, SimConstraints, SimContainer, SimDrain, SimFaucet, SimGrid,
+    SimParticle, SimSpinner, SimStepClock,
+};
+use crate::events::UseToolEvent;
+use crate::test::test_state_manager::construct_new_simulation;
+use crate::ui::{SimTool, UIStateManager};
+
+/// World units left between side-by-side simulation domains.
+pub const DOMAIN_SPACING: f32 = 40.0;
+
+/** An extra simulation running alongside the main one.  A domain is an entity with a grid, settings,
+random number generator, and step clock of its own (as `SimGrid`, `SimConstraints`, `SimRng`, and
+`SimStepClock` components); its particles, faucets, drains, and spinners are entities marked with
+`SimDomainMember`, which the main simulation's queries leave out.  Domains share the main
+simulation's GPU and whether it is paused.  They're drawn offset by `origin`, and are not saved
+along with the scene.
+
+The main simulation itself is not a domain: its grid, settings, random number generator, and step
+clock are still the global resources of the same types.  Scene files and quick saves store them as
+resources, the UI's settings are bound to them, and the simulation thread (see sim_step_task) copies
+them in and out, so the main simulation keeps a path of its own: `simulation::update` steps it, its
+entities are picked out with `SimMainDomain`, and the renderer adds it to the domains with
+`simulation_frames`.  Both paths step through `step_simulation` and use tools through `use_tools`,
+so only the bookkeeping around them differs. */
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SimDomain {
+    pub origin: Vec2, // Where the domain's grid is drawn, relative to the main simulation's.
+}
+
+/// Marks a particle, faucet, drain, or spinner as part of the extra domain with this entity.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimDomainMember(pub Entity);
+
+/** Marks a domain, and everything in it, while it is being stepped.  Queries can only filter on what
+components an entity has, not on their values, so this is how the usual simulation code is handed
+one domain's entities at a time. */
+#[derive(Component, Clone, Copy, Debug, Default)]
+#[component(storage = "SparseSet")]
+pub struct SimSteppingDomain;
+
+/// Query filter leaving out everything belonging to an extra domain.
+pub type SimMainDomain = Without<SimDomainMember>;
+/// Query filter for the domain being stepped.
+pub type SimSteppedDomain = With<SimSteppingDomain>;
+
+/** Spawn a domain drawn at `origin`, with `constraints`' settings and a fresh copy of the default
+scene.  Returns the domain's entity. */
+pub fn spawn_domain(
+    commands: &mut Commands,
+    mut constraints: SimConstraints,
+    origin: Vec2,
+) -> Entity {
+    constraints.particle_count = 0;
+    constraints.simulated_time = 0.0;
+    constraints.selected_particles.clear();
+
+    let domain_id: Entity = commands.spawn_empty().id();
+    let mut grid = SimGrid::default();
+    grid.domain = Some(domain_id);
+    construct_new_simulation(&mut constraints, &mut grid, commands);
+    commands.entity(domain_id).insert((
+        SimDomain { origin },
+        SimRng::for_scene(&constraints),
+        constraints,
+        grid,
+        SimStepClock::default(),
+    ));
+
+    domain_id
+}
+
+/// Remove a domain, along with everything in it.
+pub fn despawn_domain(
+    commands: &mut Commands,
+    members: &Query<(Entity, &SimDomainMember)>,
+    domain_id: Entity,
+) {
+    for (member_id, member) in members.iter() {
+        if member.0 == domain_id {
+            commands.entity(member_id).despawn();
+        }
+    }
+    commands.entity(domain_id).despawn();
+}
+
+/// Mark an entity just spawned onto `grid` as part of the domain it belongs to, if any.
+pub fn join_grid_domain(entity: &mut EntityCommands, grid: &SimGrid) {
+    if let Some(domain_id) = grid.domain {
+        entity.insert(SimDomainMember(domain_id));
+    }
+}
+
+/** The domain whose grid `position` (in world units) lies on, if any.  Only tools that work on the
+fluid and what's in the grid are aimed at domains; containers, attractors, pumps, probes, and flow
+meters belong to the main simulation. */
+pub fn find_domain_at<'a>(
+    domains: impl IntoIterator<Item = (Entity, &'a SimDomain, &'a SimGrid)>,
+    tool: SimTool,
+    position: Vec2,
+) -> Option<Entity> {
+    if matches!(
+        tool,
+        SimTool::Container
+            | SimTool::Attractor
+            | SimTool::AddPump
+            | SimTool::Probe
+            | SimTool::FlowMeter
+    ) {
+        return None;
+    }
+    domains
+        .into_iter()
+        .find(|(_, domain, grid)| grid.is_position_within_grid(&(position - domain.origin)))
+        .map(|(domain_id, _, _)| domain_id)
+}
+
+/** Step every extra domain as much as `delta_seconds` of real time calls for (unless the main
+simulation is paused), at its own rate and with its own timestep, then use whichever tools were
+aimed at it. */
+pub fn advance_domains(world: &mut World, delta_seconds: f32, tool_uses: &[UseToolEvent]) {
+    let domain_ids: Vec<Entity> = world
+        .query_filtered::<Entity, With<SimDomain>>()
+        .iter(world)
+        .collect();
+    let paused: bool = world.resource::<SimConstraints>().is_paused;
+
+    for domain_id in domain_ids {
+        let members: Vec<Entity> = world
+            .query::<(Entity, &SimDomainMember)>()
+            .iter(world)
+            .filter(|(_, member)| member.0 == domain_id)
+            .map(|(member_id, _)| member_id)
+            .chain([domain_id])
+            .collect();
+        for member_id in members.iter() {
+            world.entity_mut(*member_id).insert(SimSteppingDomain);
+        }
+
+        let step_count: u8 = if paused {
+            0
+        } else {
+            let mut domain = world.entity_mut(domain_id);
+            let constraints: &SimConstraints = domain.get::<SimConstraints>().unwrap();
+            let (steps_per_second, max_steps) = (
+                constraints.steps_per_second,
+                constraints.max_steps_per_frame,
+            );
+            domain.get_mut::<SimStepClock>().unwrap().advance(
+                delta_seconds,
+                steps_per_second,
+                max_steps,
+            )
+        };
+        for _ in 0..step_count {
+            world.run_system_once(step_domain);
+            // Despawns have been applied by now, so the spatial lookup can safely forget them.
+            world
+                .get_mut::<SimGrid>(domain_id)
+                .unwrap()
+                .flush_lookup_removals();
+        }
+
+        let domain_tool_uses: Vec<UseToolEvent> = tool_uses
+            .iter()
+            .filter(|tool_use| tool_use.domain == Some(domain_id))
+            .copied()
+            .collect();
+        if !domain_tool_uses.is_empty() {
+            world.run_system_once_with(domain_tool_uses, use_domain_tools);
+            world
+                .get_mut::<SimGrid>(domain_id)
+                .unwrap()
+                .flush_lookup_removals();
+        }
+
+        // Members despawned by the domain's tools are already gone.
+        for member_id in members {
+            if let Some(mut member) = world.get_entity_mut(member_id) {
+                member.remove::<SimSteppingDomain>();
+            }
+        }
+    }
+}
+
+/** Step every extra domain alongside the main simulation, using whichever tools the UI aimed at
+each of them this frame. */
+pub fn update_domains(world: &mut World, mut ev_tool_use: Local<ManualEventReader<UseToolEvent>>) {
+    let tool_uses: Vec<UseToolEvent> = ev_tool_use
+        .read(world.resource::<Events<UseToolEvent>>())
+        .filter(|tool_use| tool_use.domain.is_some())
+        .copied()
+        .collect();
+    let delta_seconds: f32 = world.resource::<Time>().delta_seconds();
+    advance_domains(world, delta_seconds, &tool_uses);
+}
+
+/// Step the domain being stepped once.
+fn step_domain(
+    mut commands: Commands,
+    mut domains: Query<(&mut SimConstraints, &mut SimGrid, &mut SimRng), SimSteppedDomain>,
+    mut gpu: Option<ResMut<SimGpu>>,
+    mut particles: Query<
+        (Entity, &mut SimParticle),
+        (Without<SimInactiveParticle>, SimSteppedDomain),
+    >,
+    faucets: Query<(Entity, &mut SimFaucet), SimSteppedDomain>,
+    mut drains: Query<(Entity, &mut SimDrain), SimSteppedDomain>,
+    mut spinners: Query<(Entity, &mut SimSpinner), SimSteppedDomain>,
+) {
+    let Ok((mut constraints, mut grid, mut rng)) = domains.get_single_mut() else {
+        return;
+    };
+    let timestep: f32 = constraints.timestep;
+    let _: Vec<SimStepStats> = step_simulation(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        rng.as_mut(),
+        gpu.as_deref_mut(),
+        &mut particles,
+        &faucets,
+        &mut drains,
+        &mut spinners,
+        timestep,
+    );
+}
+
+/// Use the tools aimed at the domain being stepped, in the domain's own coordinates.
+fn use_domain_tools(
+    In(tool_uses): In<Vec<UseToolEvent>>,
+    mut commands: Commands,
+    mut domains: Query<(&SimDomain, &mut SimConstraints, &mut SimGrid), SimSteppedDomain>,
+    mut particles: Query<
+        (Entity, &mut SimParticle),
+        (Without<SimInactiveParticle>, SimSteppedDomain),
+    >,
+    faucets: Query<(Entity, &mut SimFaucet), SimSteppedDomain>,
+    mut drains: Query<(Entity, &mut SimDrain), SimSteppedDomain>,
+    containers: Query<(Entity, &mut SimContainer)>,
+    mut spinners: Query<(Entity, &mut SimSpinner), SimSteppedDomain>,
+    ui_state: Res<UIStateManager>,
+) {
+    let Ok((domain, mut constraints, mut grid)) = domains.get_single_mut() else {
+        return;
+    };
+    let timestep: f32 = constraints.timestep;
+    use_tools(
+        tool_uses.iter(),
+        domain.origin,
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &mut particles,
+        &faucets,
+        &mut drains,
+        &containers,
+        &mut spinners,
+        ui_state.as_ref(),
+        timestep,
+    );
+}
+
+/// The size of a grid in world units.
+pub fn grid_extent(grid: &SimGrid) -> Vec2 {
+    Vec2::new(
+        (grid.dimensions.1 * grid.cell_size) as f32,
+        (grid.dimensions.0 * grid.cell_size) as f32,
+    )
+}
@@ -0,0 +1,955 @@
+use std::sync::mpsc;
+
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingResource, BindingType, Buffer, BufferBinding, BufferBindingType, BufferDescriptor,
+    BufferSize, BufferUsages, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    MapMode, PipelineLayoutDescriptor, RawComputePipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use wgpu::SubmissionIndex;
+
+use super::sim_physics_engine::{
+    calculate_cell_relaxation, gauss_seidel_color_count, is_velocity_point_transferred,
+    particle_visit_order, SimCellRelaxation, GAUSS_SEIDEL_MAX_COLORS, GAUSS_SEIDEL_OVERRELAXATION,
+};
+use super::util::reset_buffer;
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle, SimTransferScheme};
+
+/// Compute shaders for the GPU pressure solver.
+const PRESSURE_SOLVE_SHADER: &str = include_str!("../../assets/shaders/pressure_solve.wgsl");
+/// Threads in each compute shader workgroup; must match the shaders' `@workgroup_size`.
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes in the pressure solve shader's Params struct.
+const PRESSURE_PARAMS_SIZE: u64 = 32;
+/// Bytes in the pressure solve shader's Cell struct.
+const PRESSURE_CELL_SIZE: u64 = 48;
+/// Compute shader for the GPU particle-to-grid transfer.
+const PARTICLES_TO_GRID_SHADER: &str = include_str!("../../assets/shaders/particles_to_grid.wgsl");
+/// Compute shader for the GPU grid-to-particle transfer.
+const GRID_TO_PARTICLES_SHADER: &str = include_str!("../../assets/shaders/grid_to_particles.wgsl");
+/// Bytes in the transfer shaders' Params struct.
+const TRANSFER_PARAMS_SIZE: u64 = 48;
+/// f32s in the transfer shaders' Particle struct.
+const TRANSFER_PARTICLE_FLOATS: usize = 12;
+/// Marks a velocity point in an inactive chunk, for the particle-to-grid shader to leave at zero.
+const TRANSFER_POINT_IDLE: u32 = 2;
+/// Fewest particles the GPU's particle buffer is made to hold.
+const MIN_GPU_PARTICLE_CAPACITY: usize = 1024;
+
+/** The GPU the simulation runs compute shaders on, borrowed from Bevy's renderer, along with
+whatever each of them has set up on it so far.  Only there as a resource when there's a renderer to
+borrow it from (there isn't in tests, for example); without it, everything runs on the CPU. */
+#[derive(Resource, Clone)]
+pub struct SimGpu {
+    device: RenderDevice,
+    queue: RenderQueue,
+    pressure: Option<SimGpuPressureSolver>,
+    transfers: Option<SimGpuTransfers>,
+}
+
+/// Hand the renderer's GPU to the simulation, if there is one.
+pub fn connect_gpu(
+    mut commands: Commands,
+    device: Option<Res<RenderDevice>>,
+    queue: Option<Res<RenderQueue>>,
+) {
+    if let (Some(device), Some(queue)) = (device, queue) {
+        commands.insert_resource(SimGpu::new(device.clone(), queue.clone()));
+    }
+}
+
+impl SimGpu {
+    pub fn new(device: RenderDevice, queue: RenderQueue) -> Self {
+        Self {
+            device,
+            queue,
+            pressure: None,
+            transfers: None,
+        }
+    }
+
+    /** Another handle on the same GPU, with none of the buffers set up yet; for stepping a second
+    copy of the simulation without the two overwriting each other's buffers. */
+    pub fn share_device(&self) -> Self {
+        Self::new(self.device.clone(), self.queue.clone())
+    }
+
+    /** Make the grid's velocities incompressible on the GPU, relaxing cells in red-black order just
+    as solve_pressure_gauss_seidel() does, and read the corrected velocities and pressures back
+    into the grid.  Every iteration is submitted at once, and the GPU checks for itself whether
+    the solve has converged: each iteration looks at the divergence the one before it measured,
+    and does nothing once that is within `constraints.pressure_tolerance`.  So the CPU only waits
+    on the GPU once, for the results.  Returns how many iterations it ran, or None (leaving the
+    grid untouched) if the results couldn't be read back. */
+    pub fn solve_pressure(
+        &mut self,
+        grid: &mut SimGrid,
+        constraints: &SimConstraints,
+    ) -> Option<u8> {
+        let rows: usize = grid.dimensions.0 as usize;
+        let cols: usize = grid.dimensions.1 as usize;
+        let solver: &SimGpuPressureSolver = match &mut self.pressure {
+            Some(solver) if solver.rows == rows && solver.cols == cols => solver,
+            pressure => pressure.insert(SimGpuPressureSolver::new(&self.device, rows, cols)),
+        };
+        let max_iterations: u8 = constraints.incomp_iters_per_frame;
+        if max_iterations == 0 {
+            for row in grid.cell_center.iter_mut() {
+                row.fill(0.0);
+            }
+            return Some(0);
+        }
+
+        // Everything the shaders need besides the velocities stays the same for the whole solve.
+        let mut cells: Vec<f32> = Vec::with_capacity(rows * cols * 12);
+        for row in 0..rows {
+            for col in 0..cols {
+                match calculate_cell_relaxation(grid, constraints.particle_rest_density, row, col) {
+                    Some(SimCellRelaxation {
+                        face_flows,
+                        face_weights,
+                        weight_sum,
+                        compression,
+                    }) => {
+                        cells.extend(face_flows);
+                        cells.extend(face_weights);
+                        cells.extend([weight_sum, compression, 1.0, 0.0]);
+                    }
+                    None => cells.extend([0.0; 12]),
+                }
+            }
+        }
+        let colors: usize = gauss_seidel_color_count(grid);
+        let mut params: Vec<u8> =
+            vec![0; solver.params_stride as usize * colors * max_iterations as usize];
+        for iteration in 0..max_iterations as usize {
+            for color in 0..colors {
+                let offset: usize = (iteration * colors + color) * solver.params_stride as usize;
+                let values: [u32; 8] = [
+                    rows as u32,
+                    cols as u32,
+                    grid.wrap_horizontal as u32,
+                    grid.wrap_vertical as u32,
+                    color as u32,
+                    iteration as u32,
+                    GAUSS_SEIDEL_OVERRELAXATION.to_bits(),
+                    constraints.pressure_tolerance.to_bits(),
+                ];
+                for (index, value) in values.iter().enumerate() {
+                    params[offset + index * 4..offset + index * 4 + 4]
+                        .copy_from_slice(&value.to_ne_bytes());
+                }
+            }
+        }
+        self.queue.write_buffer(&solver.params, 0, &params);
+        self.queue.write_buffer(
+            &solver.velocity_u,
+            0,
+            &to_bytes(grid.velocity_u.iter().flatten()),
+        );
+        self.queue.write_buffer(
+            &solver.velocity_v,
+            0,
+            &to_bytes(grid.velocity_v.iter().flatten()),
+        );
+        self.queue
+            .write_buffer(&solver.cells, 0, &to_bytes(cells.iter()));
+        self.queue.write_buffer(
+            &solver.pressure,
+            0,
+            &vec![0; solver.pressure.size() as usize],
+        );
+        self.queue.write_buffer(
+            &solver.residuals,
+            0,
+            &vec![0; solver.residuals.size() as usize],
+        );
+
+        let invocations: [usize; 3] = [rows * cols, rows * (cols + 1), (rows + 1) * cols];
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("pressure_solve"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("pressure_solve"),
+            });
+            for iteration in 0..max_iterations as u64 {
+                for color in 0..colors as u64 {
+                    let offset: u64 = (iteration * colors as u64 + color) * solver.params_stride;
+                    pass.set_bind_group(0, &solver.bind_group, &[offset as u32]);
+                    for (pipeline, count) in solver.pipelines.iter().zip(invocations) {
+                        pass.set_pipeline(pipeline);
+                        pass.dispatch_workgroups(workgroup_count(count), 1, 1);
+                    }
+                }
+            }
+        }
+
+        // Bring the results home, along with how far each iteration had left to go.
+        let sizes: [u64; 4] = [
+            solver.velocity_u.size(),
+            solver.velocity_v.size(),
+            solver.pressure.size(),
+            max_iterations as u64 * 4,
+        ];
+        let mut offset: u64 = 0;
+        for (buffer, size) in [
+            &solver.velocity_u,
+            &solver.velocity_v,
+            &solver.pressure,
+            &solver.residuals,
+        ]
+        .into_iter()
+        .zip(sizes)
+        {
+            encoder.copy_buffer_to_buffer(buffer, 0, &solver.readback, offset, size);
+            offset += size;
+        }
+        let submission = self.queue.submit([encoder.finish()]);
+        let results: Vec<f32> = read_back(
+            &self.device,
+            submission,
+            &solver.readback,
+            sizes.iter().sum(),
+        )?;
+
+        /* Each iteration's divergence is stored as the bits of a (non-negative) f32; the iteration
+        that got it within tolerance was the last to run. */
+        let (results, residuals) = results.split_at(results.len() - max_iterations as usize);
+        let initial_residual: f32 = residuals[0];
+        let iterations: u8 = residuals
+            .iter()
+            .position(|residual| *residual <= constraints.pressure_tolerance * initial_residual)
+            .map_or(max_iterations, |iteration| iteration as u8 + 1);
+
+        let (velocity_u, results) = results.split_at(rows * (cols + 1));
+        let (velocity_v, pressure) = results.split_at((rows + 1) * cols);
+        for (row, values) in grid.velocity_u.iter_mut().zip(velocity_u.chunks(cols + 1)) {
+            row.copy_from_slice(values);
+        }
+        for (row, values) in grid.velocity_v.iter_mut().zip(velocity_v.chunks(cols)) {
+            row.copy_from_slice(values);
+        }
+        for (row, values) in grid.cell_center.iter_mut().zip(pressure.chunks(cols)) {
+            row.copy_from_slice(values);
+        }
+
+        Some(iterations)
+    }
+}
+
+impl SimGpu {
+    /** Make sure the transfer shaders' pipelines and buffers are ready, (re)building them if the
+    grid has changed size or there are more particles than they have room for. */
+    fn prepare_transfers(&mut self, rows: usize, cols: usize, particle_count: usize) {
+        let ready: bool = self.transfers.as_ref().is_some_and(|transfers| {
+            transfers.rows == rows
+                && transfers.cols == cols
+                && transfers.particle_capacity >= particle_count
+        });
+        if !ready {
+            let particle_capacity: usize = particle_count
+                .next_power_of_two()
+                .max(MIN_GPU_PARTICLE_CAPACITY);
+            self.transfers = Some(SimGpuTransfers::new(
+                &self.device,
+                rows,
+                cols,
+                particle_capacity,
+            ));
+        }
+    }
+
+    /** Transfer the particles' velocities to the grid on the GPU, just as particles_to_grid() does
+    on the CPU.  The particles are sorted by cell first, so each velocity point only gathers the
+    particles in the cells around it; within a cell they're added up in visit order, so results
+    only differ from the CPU's by rounding.  Returns None (leaving the grid untouched) if the
+    results couldn't be read back. */
+    pub fn transfer_particles_to_grid<F: ReadOnlyWorldQuery>(
+        &mut self,
+        grid: &mut SimGrid,
+        particles: &Query<(Entity, &mut SimParticle), F>,
+        constraints: &SimConstraints,
+    ) -> Option<()> {
+        let rows: usize = grid.dimensions.0 as usize;
+        let cols: usize = grid.dimensions.1 as usize;
+        let (u_count, v_count) = (rows * (cols + 1), (rows + 1) * cols);
+
+        // Sort the particles by the cell they're in, keeping them in visit order within each cell.
+        let order: Vec<Entity> = particle_visit_order(constraints, particles);
+        let cells: Vec<usize> = particles
+            .iter_many(order.iter())
+            .map(|(_, particle)| {
+                grid.get_lookup_index(grid.get_cell_coordinates_from_position(&particle.position))
+            })
+            .collect();
+        let mut cell_starts: Vec<u32> = vec![0; rows * cols + 1];
+        for cell in cells.iter() {
+            cell_starts[cell + 1] += 1;
+        }
+        for cell in 0..rows * cols {
+            cell_starts[cell + 1] += cell_starts[cell];
+        }
+        let mut next_slots: Vec<u32> = cell_starts.clone();
+        let mut sorted: Vec<f32> = vec![0.0; cells.len() * TRANSFER_PARTICLE_FLOATS];
+        for (cell, (_, particle)) in cells.iter().zip(particles.iter_many(order.iter())) {
+            let slot: usize = next_slots[*cell] as usize * TRANSFER_PARTICLE_FLOATS;
+            next_slots[*cell] += 1;
+            sorted[slot..slot + TRANSFER_PARTICLE_FLOATS]
+                .copy_from_slice(&particle_to_floats(particle));
+        }
+
+        // Points in chunks with no fluid anywhere near them are left at zero, as on the CPU.
+        let mut transferred: Vec<u32> = Vec::new();
+        for (point_rows, point_cols, horizontal) in
+            [(rows, cols + 1, true), (rows + 1, cols, false)]
+        {
+            for row in 0..point_rows {
+                let start: usize = transferred.len();
+                transferred.resize(start + point_cols, TRANSFER_POINT_IDLE);
+                for col in grid.chunks.active_spans(row, point_cols).flatten() {
+                    transferred[start + col] =
+                        is_velocity_point_transferred(grid, row, col, horizontal) as u32;
+                }
+            }
+        }
+
+        let params: Vec<u8> = transfer_params(grid, constraints, cells.len());
+        self.prepare_transfers(rows, cols, cells.len());
+        let queue: &RenderQueue = &self.queue;
+        let transfers: &SimGpuTransfers = self.transfers.as_ref()?;
+        queue.write_buffer(&transfers.params, 0, &params);
+        queue.write_buffer(&transfers.particles, 0, &to_bytes(sorted.iter()));
+        queue.write_buffer(
+            &transfers.cell_starts,
+            0,
+            &cell_starts
+                .iter()
+                .flat_map(|start| start.to_ne_bytes())
+                .collect::<Vec<u8>>(),
+        );
+        queue.write_buffer(
+            &transfers.transferred,
+            0,
+            &transferred
+                .iter()
+                .flat_map(|transferred| transferred.to_ne_bytes())
+                .collect::<Vec<u8>>(),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("particles_to_grid"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("particles_to_grid"),
+            });
+            pass.set_bind_group(0, &transfers.particles_to_grid_bind_group, &[]);
+            for (pipeline, count) in transfers.particles_to_grid.iter().zip([u_count, v_count]) {
+                pass.set_pipeline(pipeline);
+                pass.dispatch_workgroups(workgroup_count(count), 1, 1);
+            }
+        }
+        let u_bytes: u64 = u_count as u64 * 4;
+        let v_bytes: u64 = v_count as u64 * 4;
+        encoder.copy_buffer_to_buffer(&transfers.velocity_u, 0, &transfers.readback, 0, u_bytes);
+        encoder.copy_buffer_to_buffer(
+            &transfers.velocity_v,
+            0,
+            &transfers.readback,
+            u_bytes,
+            v_bytes,
+        );
+        let submission = queue.submit([encoder.finish()]);
+        let velocities: Vec<f32> = read_back(
+            &self.device,
+            submission,
+            &transfers.readback,
+            u_bytes + v_bytes,
+        )?;
+
+        let (velocity_u, velocity_v) = velocities.split_at(u_count);
+        reset_buffer(&mut grid.velocity_u, rows, cols + 1, f32::MIN);
+        reset_buffer(&mut grid.velocity_v, rows + 1, cols, f32::MIN);
+        for (row, values) in grid.velocity_u.iter_mut().zip(velocity_u.chunks(cols + 1)) {
+            row.copy_from_slice(values);
+        }
+        for (row, values) in grid.velocity_v.iter_mut().zip(velocity_v.chunks(cols)) {
+            row.copy_from_slice(values);
+        }
+
+        Some(())
+    }
+
+    /** Transfer the grid's velocities back to the particles on the GPU, just as
+    grid_to_particles() does on the CPU: particles in fluid (or porous) cells pick up the grid's
+    velocity, those caught by a moving solid are swept along with it, and those in the air are
+    left alone.  Returns None (leaving the particles untouched) if the results couldn't be read
+    back. */
+    pub fn transfer_grid_to_particles<F: ReadOnlyWorldQuery>(
+        &mut self,
+        grid: &SimGrid,
+        particles: &mut Query<(Entity, &mut SimParticle), F>,
+        constraints: &SimConstraints,
+    ) -> Option<()> {
+        let rows: usize = grid.dimensions.0 as usize;
+        let cols: usize = grid.dimensions.1 as usize;
+        if grid.previous_velocity_u.len() != rows || grid.previous_velocity_v.len() != rows + 1 {
+            return None;
+        }
+
+        // Work out which particles go to the GPU, and which moving solids take care of.
+        let mut carried: Vec<Entity> = Vec::new();
+        let mut swept: Vec<(Entity, Vec2)> = Vec::new();
+        for row in 0..rows {
+            for col in 0..cols {
+                let lookup_index: usize = row * cols + col;
+                match grid.cell_type[row][col] {
+                    SimGridCellType::Air => {}
+                    SimGridCellType::Solid => {
+                        if let Some(velocity) = grid.get_moving_solid_velocity(row, col) {
+                            swept.extend(
+                                grid.get_particles_in_lookup(lookup_index)
+                                    .iter()
+                                    .map(|id| (*id, velocity)),
+                            );
+                        }
+                    }
+                    SimGridCellType::Fluid | SimGridCellType::Porous(_) => {
+                        carried.extend_from_slice(grid.get_particles_in_lookup(lookup_index));
+                    }
+                }
+            }
+        }
+        carried.retain(|id| particles.contains(*id));
+
+        if !carried.is_empty() {
+            let mut floats: Vec<f32> = Vec::with_capacity(carried.len() * TRANSFER_PARTICLE_FLOATS);
+            for (_, particle) in particles.iter_many(carried.iter()) {
+                floats.extend(particle_to_floats(particle));
+            }
+
+            let params: Vec<u8> = transfer_params(grid, constraints, carried.len());
+            self.prepare_transfers(rows, cols, carried.len());
+            let queue: &RenderQueue = &self.queue;
+            let transfers: &SimGpuTransfers = self.transfers.as_ref()?;
+            queue.write_buffer(&transfers.params, 0, &params);
+            queue.write_buffer(&transfers.particles, 0, &to_bytes(floats.iter()));
+            queue.write_buffer(
+                &transfers.velocity_u,
+                0,
+                &to_bytes(
+                    grid.velocity_u
+                        .iter()
+                        .flatten()
+                        .chain(grid.previous_velocity_u.iter().flatten()),
+                ),
+            );
+            queue.write_buffer(
+                &transfers.velocity_v,
+                0,
+                &to_bytes(
+                    grid.velocity_v
+                        .iter()
+                        .flatten()
+                        .chain(grid.previous_velocity_v.iter().flatten()),
+                ),
+            );
+
+            let mut encoder = self
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("grid_to_particles"),
+                });
+            {
+                let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("grid_to_particles"),
+                });
+                pass.set_bind_group(0, &transfers.grid_to_particles_bind_group, &[]);
+                pass.set_pipeline(&transfers.grid_to_particles[0]);
+                pass.dispatch_workgroups(workgroup_count(carried.len()), 1, 1);
+            }
+            let particle_bytes: u64 = (floats.len() * 4) as u64;
+            encoder.copy_buffer_to_buffer(
+                &transfers.particles,
+                0,
+                &transfers.readback,
+                0,
+                particle_bytes,
+            );
+            let submission = queue.submit([encoder.finish()]);
+            let results: Vec<f32> = read_back(
+                &self.device,
+                submission,
+                &transfers.readback,
+                particle_bytes,
+            )?;
+
+            for (id, values) in carried
+                .iter()
+                .zip(results.chunks_exact(TRANSFER_PARTICLE_FLOATS))
+            {
+                if let Ok((_, mut particle)) = particles.get_mut(*id) {
+                    particle.velocity = Vec2::new(values[2], values[3]);
+                    particle.affine_velocity = Mat2::from_cols_slice(&values[4..8]);
+                }
+            }
+        }
+
+        // Particles caught by a spinner's blade are swept along with it.
+        for (id, velocity) in swept {
+            if let Ok((_, mut particle)) = particles.get_mut(id) {
+                particle.velocity = velocity;
+                particle.affine_velocity = Mat2::ZERO;
+            }
+        }
+
+        Some(())
+    }
+}
+
+/** The GPU pressure solver's pipelines and buffers, for a grid of `rows` x `cols` cells.  Rebuilt
+whenever the grid changes size. */
+#[derive(Clone)]
+struct SimGpuPressureSolver {
+    rows: usize,
+    cols: usize,
+    params_stride: u64, // Bytes between each iteration and color's Params, as uniforms must be aligned.
+    // Calculating corrections, then applying them to horizontal faces, then to vertical faces.
+    pipelines: [ComputePipeline; 3],
+    bind_group: BindGroup,
+    params: Buffer,     // Params for every iteration and color.
+    velocity_u: Buffer, // Horizontal face velocities, row by row.
+    velocity_v: Buffer, // Vertical face velocities, row by row.
+    cells: Buffer,      // Each cell's SimCellRelaxation.
+    pressure: Buffer,   // Each cell's pressure.
+    residuals: Buffer,  // Largest divergence relaxed in each iteration.
+    readback: Buffer,   // Where results are copied to be read on the CPU.
+}
+
+impl SimGpuPressureSolver {
+    fn new(device: &RenderDevice, rows: usize, cols: usize) -> Self {
+        let alignment: u64 = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let params_stride: u64 = (PRESSURE_PARAMS_SIZE + alignment - 1) / alignment * alignment;
+
+        let (layout, pipelines) = create_compute_pipelines(
+            device,
+            "pressure_solve",
+            PRESSURE_SOLVE_SHADER,
+            &[
+                buffer_layout_entry(
+                    0,
+                    BufferBindingType::Uniform,
+                    true,
+                    BufferSize::new(PRESSURE_PARAMS_SIZE),
+                ),
+                storage_layout_entry(1, false),
+                storage_layout_entry(2, false),
+                storage_layout_entry(3, true),
+                storage_layout_entry(4, false),
+                storage_layout_entry(5, false),
+                storage_layout_entry(6, false),
+            ],
+            [
+                "calculate_corrections",
+                "apply_horizontal_corrections",
+                "apply_vertical_corrections",
+            ],
+        );
+
+        let params: Buffer = create_buffer(
+            device,
+            "pressure_params",
+            params_stride * GAUSS_SEIDEL_MAX_COLORS as u64 * u8::MAX as u64,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+        let velocity_u: Buffer = create_storage_buffer(
+            device,
+            "pressure_velocity_u",
+            (rows * (cols + 1) * 4) as u64,
+        );
+        let velocity_v: Buffer = create_storage_buffer(
+            device,
+            "pressure_velocity_v",
+            ((rows + 1) * cols * 4) as u64,
+        );
+        let cells: Buffer = create_storage_buffer(
+            device,
+            "pressure_cells",
+            rows as u64 * cols as u64 * PRESSURE_CELL_SIZE,
+        );
+        // Each cell's change to its left, right, up, and down faces; only the shaders ever touch it.
+        let corrections: Buffer =
+            create_storage_buffer(device, "pressure_corrections", (rows * cols * 16) as u64);
+        let pressure: Buffer = create_storage_buffer(device, "pressure", (rows * cols * 4) as u64);
+        let residuals: Buffer =
+            create_storage_buffer(device, "pressure_residuals", u8::MAX as u64 * 4);
+        let readback: Buffer = create_readback_buffer(
+            device,
+            "pressure_readback",
+            velocity_u.size() + velocity_v.size() + pressure.size() + residuals.size(),
+        );
+
+        let bind_group: BindGroup = create_bind_group(
+            device,
+            "pressure_solve",
+            &layout,
+            [
+                BindingResource::Buffer(BufferBinding {
+                    buffer: &params,
+                    offset: 0,
+                    size: BufferSize::new(PRESSURE_PARAMS_SIZE),
+                }),
+                velocity_u.as_entire_binding(),
+                velocity_v.as_entire_binding(),
+                cells.as_entire_binding(),
+                corrections.as_entire_binding(),
+                pressure.as_entire_binding(),
+                residuals.as_entire_binding(),
+            ],
+        );
+
+        Self {
+            rows,
+            cols,
+            params_stride,
+            pipelines,
+            bind_group,
+            params,
+            velocity_u,
+            velocity_v,
+            cells,
+            pressure,
+            residuals,
+            readback,
+        }
+    }
+}
+
+/** The transfer shaders' pipelines and buffers, for a grid of `rows` x `cols` cells and up to
+`particle_capacity` particles.  Rebuilt whenever the grid changes size, or there are more particles
+than they have room for. */
+#[derive(Clone)]
+struct SimGpuTransfers {
+    rows: usize,
+    cols: usize,
+    particle_capacity: usize,
+    // Transferring to horizontal velocity points, then to vertical ones.
+    particles_to_grid: [ComputePipeline; 2],
+    grid_to_particles: [ComputePipeline; 1],
+    particles_to_grid_bind_group: BindGroup,
+    grid_to_particles_bind_group: BindGroup,
+    params: Buffer,      // Grid size, transfer scheme, and so on.
+    particles: Buffer,   // The particles being transferred, as the shaders' Particle struct.
+    cell_starts: Buffer, // Where each cell's particles start in `particles`, for particle-to-grid.
+    transferred: Buffer, // Whether each velocity point gets a velocity (or stays at zero), for P2G.
+    velocity_u: Buffer,  // Horizontal velocities, then those from before the pressure solve.
+    velocity_v: Buffer,  // Vertical velocities, then those from before the pressure solve.
+    readback: Buffer,    // Where results are copied to be read on the CPU.
+}
+
+impl SimGpuTransfers {
+    fn new(device: &RenderDevice, rows: usize, cols: usize, particle_capacity: usize) -> Self {
+        let (u_count, v_count) = (rows * (cols + 1), (rows + 1) * cols);
+        let params_entry: BindGroupLayoutEntry = buffer_layout_entry(
+            0,
+            BufferBindingType::Uniform,
+            false,
+            BufferSize::new(TRANSFER_PARAMS_SIZE),
+        );
+        let (particles_to_grid_layout, particles_to_grid) = create_compute_pipelines(
+            device,
+            "particles_to_grid",
+            PARTICLES_TO_GRID_SHADER,
+            &[
+                params_entry,
+                storage_layout_entry(1, true),
+                storage_layout_entry(2, true),
+                storage_layout_entry(3, true),
+                storage_layout_entry(4, false),
+                storage_layout_entry(5, false),
+            ],
+            ["transfer_horizontal", "transfer_vertical"],
+        );
+        let (grid_to_particles_layout, grid_to_particles) = create_compute_pipelines(
+            device,
+            "grid_to_particles",
+            GRID_TO_PARTICLES_SHADER,
+            &[
+                params_entry,
+                storage_layout_entry(1, false),
+                storage_layout_entry(2, true),
+                storage_layout_entry(3, true),
+            ],
+            ["transfer"],
+        );
+
+        let params: Buffer = create_buffer(
+            device,
+            "transfer_params",
+            TRANSFER_PARAMS_SIZE,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+        let particles: Buffer = create_storage_buffer(
+            device,
+            "transfer_particles",
+            (particle_capacity * TRANSFER_PARTICLE_FLOATS * 4) as u64,
+        );
+        let cell_starts: Buffer = create_storage_buffer(
+            device,
+            "transfer_cell_starts",
+            ((rows * cols + 1) * 4) as u64,
+        );
+        let transferred: Buffer = create_storage_buffer(
+            device,
+            "transfer_transferred",
+            ((u_count + v_count) * 4) as u64,
+        );
+        let velocity_u: Buffer =
+            create_storage_buffer(device, "transfer_velocity_u", (u_count * 2 * 4) as u64);
+        let velocity_v: Buffer =
+            create_storage_buffer(device, "transfer_velocity_v", (v_count * 2 * 4) as u64);
+        let readback: Buffer = create_readback_buffer(
+            device,
+            "transfer_readback",
+            ((u_count + v_count) * 4).max(particle_capacity * TRANSFER_PARTICLE_FLOATS * 4) as u64,
+        );
+
+        let particles_to_grid_bind_group: BindGroup = create_bind_group(
+            device,
+            "particles_to_grid",
+            &particles_to_grid_layout,
+            [
+                params.as_entire_binding(),
+                particles.as_entire_binding(),
+                cell_starts.as_entire_binding(),
+                transferred.as_entire_binding(),
+                velocity_u.as_entire_binding(),
+                velocity_v.as_entire_binding(),
+            ],
+        );
+        let grid_to_particles_bind_group: BindGroup = create_bind_group(
+            device,
+            "grid_to_particles",
+            &grid_to_particles_layout,
+            [
+                params.as_entire_binding(),
+                particles.as_entire_binding(),
+                velocity_u.as_entire_binding(),
+                velocity_v.as_entire_binding(),
+            ],
+        );
+
+        Self {
+            rows,
+            cols,
+            particle_capacity,
+            particles_to_grid,
+            grid_to_particles,
+            particles_to_grid_bind_group,
+            grid_to_particles_bind_group,
+            params,
+            particles,
+            cell_starts,
+            transferred,
+            velocity_u,
+            velocity_v,
+            readback,
+        }
+    }
+}
+
+/// The transfer shaders' Params, for transferring `particle_count` particles.
+fn transfer_params(grid: &SimGrid, constraints: &SimConstraints, particle_count: usize) -> Vec<u8> {
+    let gravity_step: Vec2 = constraints.gravity * constraints.timestep;
+    let values: [u32; 12] = [
+        grid.dimensions.0 as u32,
+        grid.dimensions.1 as u32,
+        grid.wrap_horizontal as u32,
+        grid.wrap_vertical as u32,
+        (grid.cell_size as f32).to_bits(),
+        // Velocity points sit a whole number of units from their cells' corners.
+        ((grid.cell_size / 2) as f32).to_bits(),
+        particle_count as u32,
+        (constraints.transfer_scheme == SimTransferScheme::Apic) as u32,
+        constraints.grid_particle_ratio.to_bits(),
+        0,
+        gravity_step.x.to_bits(),
+        gravity_step.y.to_bits(),
+    ];
+    values
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect()
+}
+
+/// A particle laid out as the transfer shaders' Particle struct.
+fn particle_to_floats(particle: &SimParticle) -> [f32; TRANSFER_PARTICLE_FLOATS] {
+    let affine: [f32; 4] = particle.affine_velocity.to_cols_array();
+    [
+        particle.position.x,
+        particle.position.y,
+        particle.velocity.x,
+        particle.velocity.y,
+        affine[0],
+        affine[1],
+        affine[2],
+        affine[3],
+        particle.material.density() * particle.mass,
+        0.0,
+        0.0,
+        0.0,
+    ]
+}
+
+/// Describe a buffer binding visible to compute shaders.
+fn buffer_layout_entry(
+    binding: u32,
+    ty: BufferBindingType,
+    has_dynamic_offset: bool,
+    min_binding_size: Option<BufferSize>,
+) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset,
+            min_binding_size,
+        },
+        count: None,
+    }
+}
+
+/// Describe a storage buffer binding visible to compute shaders.
+fn storage_layout_entry(binding: u32, read_only: bool) -> BindGroupLayoutEntry {
+    buffer_layout_entry(
+        binding,
+        BufferBindingType::Storage { read_only },
+        false,
+        None,
+    )
+}
+
+/// Compile a compute pipeline for each of a shader's entry points, all sharing one bind group layout.
+fn create_compute_pipelines<const N: usize>(
+    device: &RenderDevice,
+    label: &str,
+    source: &'static str,
+    entries: &[BindGroupLayoutEntry],
+    entry_points: [&str; N],
+) -> (BindGroupLayout, [ComputePipeline; N]) {
+    let layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries,
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(source.into()),
+    });
+    let pipelines: [ComputePipeline; N] = entry_points.map(|entry_point| {
+        device.create_compute_pipeline(&RawComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point,
+        })
+    });
+    (layout, pipelines)
+}
+
+/// Bind each resource to the binding of the same number.
+fn create_bind_group<const N: usize>(
+    device: &RenderDevice,
+    label: &str,
+    layout: &BindGroupLayout,
+    resources: [BindingResource; N],
+) -> BindGroup {
+    let entries: Vec<BindGroupEntry> = resources
+        .into_iter()
+        .enumerate()
+        .map(|(binding, resource)| BindGroupEntry {
+            binding: binding as u32,
+            resource,
+        })
+        .collect();
+    device.create_bind_group(label, layout, &entries)
+}
+
+fn create_buffer(device: &RenderDevice, label: &str, size: u64, usage: BufferUsages) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+/// A buffer shaders can read and write, and that can be written to and copied from.
+fn create_storage_buffer(device: &RenderDevice, label: &str, size: u64) -> Buffer {
+    create_buffer(
+        device,
+        label,
+        size,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+    )
+}
+
+/// A buffer results can be copied into, to be read back by read_back().
+fn create_readback_buffer(device: &RenderDevice, label: &str, size: u64) -> Buffer {
+    create_buffer(
+        device,
+        label,
+        size,
+        BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+    )
+}
+
+/// Workgroups needed for one thread per item.
+fn workgroup_count(items: usize) -> u32 {
+    (items as u32 + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+/// Lay out a run of f32s the way the GPU expects to find them.
+fn to_bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    values.flat_map(|value| value.to_ne_bytes()).collect()
+}
+
+/** Wait for the GPU to finish `submission` (and only that; the renderer's work is left be), then
+read the first `size` bytes of a mappable buffer it copied results into back as f32s.  Returns None
+if the buffer couldn't be mapped. */
+fn read_back(
+    device: &RenderDevice,
+    submission: SubmissionIndex,
+    buffer: &Buffer,
+    size: u64,
+) -> Option<Vec<f32>> {
+    let slice = buffer.slice(0..size);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+    receiver.recv().ok()?.ok()?;
+
+    let values: Vec<f32> = slice
+        .get_mapped_range()
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    buffer.unmap();
+    Some(values)
+}
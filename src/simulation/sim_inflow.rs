@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+
+use super::sim_state_manager::add_particle;
+use super::{
+    SimConstraints, SimEdgeBoundary, SimFluidMaterial, SimGrid, SimGridCellType, SimGridEdge,
+    AMBIENT_TEMPERATURE,
+};
+
+/// Particles each open cell along an inflow edge is kept topped up with.
+const INFLOW_PARTICLES_PER_CELL: usize = 4;
+/// Where new particles are placed within an inflow cell, relative to its center and in cells.
+const INFLOW_SEED_OFFSETS: [Vec2; INFLOW_PARTICLES_PER_CELL] = [
+    Vec2::new(-0.25, -0.25),
+    Vec2::new(0.25, 0.25),
+    Vec2::new(0.25, -0.25),
+    Vec2::new(-0.25, 0.25),
+];
+
+pub const INFLOW_PROFILE_COUNT: usize = 2;
+
+/// How fast fluid flows in through different parts of an inflow edge.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum SimInflowProfile {
+    // The whole edge flows in at the same speed.
+    #[default]
+    Uniform = 0,
+    // Fastest in the middle of the edge and still at its ends, like water flowing through a pipe.
+    Parabolic,
+}
+
+impl Into<SimInflowProfile> for usize {
+    fn into(self) -> SimInflowProfile {
+        match self {
+            0 => SimInflowProfile::Uniform,
+            1 => SimInflowProfile::Parabolic,
+            _ => {
+                eprintln!("Invalid SimInflowProfile; defaulting to Uniform!");
+                SimInflowProfile::Uniform
+            }
+        }
+    }
+}
+
+impl SimInflowProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uniform => "Uniform",
+            Self::Parabolic => "Parabolic",
+        }
+    }
+}
+
+/** Velocity fluid flows in at through a point on an inflow edge: straight into the grid, at
+`constraints.inflow_speed` shaped by `constraints.inflow_profile`.  The profile spans the open part
+of the edge, between the walled corners. */
+pub fn calculate_inflow_velocity(
+    constraints: &SimConstraints,
+    grid: &SimGrid,
+    edge: SimGridEdge,
+    position: Vec2,
+) -> Vec2 {
+    let cell_size: f32 = grid.cell_size as f32;
+    let (along, span) = match edge {
+        SimGridEdge::Left | SimGridEdge::Right => (position.y, grid.dimensions.0 as f32),
+        SimGridEdge::Top | SimGridEdge::Bottom => (position.x, grid.dimensions.1 as f32),
+    };
+    let t: f32 = ((along / cell_size - 1.0) / (span - 2.0).max(1.0)).clamp(0.0, 1.0);
+    let speed: f32 = match constraints.inflow_profile {
+        SimInflowProfile::Uniform => constraints.inflow_speed,
+        SimInflowProfile::Parabolic => constraints.inflow_speed * 4.0 * t * (1.0 - t),
+    };
+
+    let inward: Vec2 = match edge {
+        SimGridEdge::Left => Vec2::X,
+        SimGridEdge::Right => Vec2::NEG_X,
+        SimGridEdge::Top => Vec2::NEG_Y,
+        SimGridEdge::Bottom => Vec2::Y,
+    };
+    inward * speed
+}
+
+/** The open cells along an inflow edge, and the velocity face each one shares with the edge, as
+(row, column, whether the face is horizontal, face row, face column). */
+fn get_inflow_cells(grid: &SimGrid, edge: SimGridEdge) -> Vec<(usize, usize, bool, usize, usize)> {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let cells: Vec<(usize, usize, bool, usize, usize)> = match edge {
+        SimGridEdge::Left => (0..rows).map(|row| (row, 0, true, row, 0)).collect(),
+        SimGridEdge::Right => (0..rows)
+            .map(|row| (row, cols - 1, true, row, cols))
+            .collect(),
+        SimGridEdge::Top => (0..cols).map(|col| (0, col, false, 0, col)).collect(),
+        SimGridEdge::Bottom => (0..cols)
+            .map(|col| (rows - 1, col, false, rows, col))
+            .collect(),
+    };
+    cells
+        .into_iter()
+        .filter(|(row, col, ..)| grid.cell_type[*row][*col] != SimGridCellType::Solid)
+        .collect()
+}
+
+/** Set every velocity face along an inflow edge to the inflow's velocity.  The grid treats the area
+past an inflow edge like a wall, so the pressure solvers leave these faces alone and the fluid next
+to them is pushed into the grid. */
+pub fn apply_inflow_velocities(constraints: &SimConstraints, grid: &mut SimGrid) {
+    for edge in SimGridEdge::ALL {
+        if grid.get_edge_boundary(edge) != Some(SimEdgeBoundary::Inflow) {
+            continue;
+        }
+
+        for (_, _, horizontal, face_row, face_col) in get_inflow_cells(grid, edge) {
+            let face_position: Vec2 = grid.get_velocity_point_pos(face_row, face_col, horizontal);
+            let velocity: Vec2 = calculate_inflow_velocity(constraints, grid, edge, face_position);
+            if horizontal {
+                grid.velocity_u[face_row][face_col] = velocity.x;
+            } else {
+                grid.velocity_v[face_row][face_col] = velocity.y;
+            }
+        }
+    }
+}
+
+/** Keep every open cell along each inflow edge topped up with particles moving in at the inflow's
+velocity.  Cells refill as fast as their particles flow out of them, so faster inflows let more
+fluid in. */
+pub fn seed_inflow_particles(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+) {
+    let cell_size: f32 = grid.cell_size as f32;
+    for edge in SimGridEdge::ALL {
+        if grid.get_edge_boundary(edge) != Some(SimEdgeBoundary::Inflow) {
+            continue;
+        }
+
+        for (row, col, ..) in get_inflow_cells(grid, edge) {
+            // Count particles spawned earlier this frame too, so substeps don't seed cells twice.
+            let lookup_index: usize = grid.get_lookup_index(Vec2::new(row as f32, col as f32));
+            let existing_count: usize = grid
+                .get_particles_in_lookup(lookup_index)
+                .iter()
+                .filter(|id| !grid.is_particle_pending_removal(**id))
+                .count();
+            let center: Vec2 =
+                grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+            for seed in existing_count..INFLOW_PARTICLES_PER_CELL {
+                let position: Vec2 = center + INFLOW_SEED_OFFSETS[seed] * cell_size;
+                let velocity: Vec2 = calculate_inflow_velocity(constraints, grid, edge, position);
+                if velocity == Vec2::ZERO {
+                    continue;
+                }
+                let _ = add_particle(
+                    commands,
+                    constraints,
+                    grid,
+                    position,
+                    velocity,
+                    AMBIENT_TEMPERATURE,
+                    0,
+                    SimFluidMaterial::Water,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::VecDeque;
+
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_physics_engine::{particle_visit_order, sample_grid_velocity};
+use super::sim_sph::SimSolverKind;
+use super::sim_state_manager::{delete_particle, spawn_particle};
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+
+/** Narrow-band FLIP: while `constraints.narrow_band` is on, only fluid within
+`constraints.narrow_band_width` cells of the surface keeps its particles.  The particles of fluid
+cells more than a cell deeper than that are set aside in `grid.narrow_band_interior`, and the grid
+alone carries the fluid there; the cells stay labeled as fluid, are treated as full of fluid like
+the rest of the band, and their velocities are carried from one step to the next on the grid.  Once
+the surface comes back within reach of a cell (or narrow-band FLIP is turned off), its particles
+are put back, picking up the grid's velocity.  Anything that only looks at particles (like drains,
+pumps, and probes) sees the band's particles alone. */
+pub fn update_narrow_band<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    grid.narrow_band_interior.resize(rows * cols, Vec::new());
+
+    // SPH has no grid to carry the fluid, so it needs every particle back.
+    let enabled: bool = constraints.narrow_band && constraints.solver_kind == SimSolverKind::Flip;
+    let depths: Vec<u32> = calculate_surface_depths(grid);
+    let width: u32 = constraints.narrow_band_width.max(1) as u32;
+    // Cells are only set aside one cell deeper than the band, so those at its edge don't flicker.
+    let set_aside_depth: u32 = width + 1;
+
+    // Put back the particles of every cell that is within reach of the surface again.
+    for row in 0..rows {
+        for col in 0..cols {
+            let lookup_index: usize = row * cols + col;
+            if enabled && depths[lookup_index] > width {
+                continue;
+            }
+            for mut particle in std::mem::take(&mut grid.narrow_band_interior[lookup_index]) {
+                // The grid has been carrying the fluid here, so it knows how fast it moves by now.
+                if let Some(velocity) = sample_grid_velocity(grid, particle.position) {
+                    particle.velocity = velocity;
+                }
+                particle.previous_position = particle.position;
+                let _ = spawn_particle(commands, constraints, grid, particle);
+            }
+        }
+    }
+    if !enabled {
+        return;
+    }
+
+    // Set aside the particles of fluid that is deep enough below the surface.
+    for id in particle_visit_order(constraints, particles) {
+        let Ok((_, particle)) = particles.get(id) else {
+            continue;
+        };
+        if grid.is_particle_pending_removal(id) || !grid.is_position_within_grid(&particle.position)
+        {
+            continue;
+        }
+        let coordinates: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        let lookup_index: usize = grid.get_lookup_index(coordinates);
+        if depths[lookup_index] <= set_aside_depth
+            || grid.cell_type[coordinates.x as usize][coordinates.y as usize]
+                != SimGridCellType::Fluid
+        {
+            continue;
+        }
+
+        let particle: SimParticle = particle.clone();
+        if delete_particle(commands, constraints, particles, grid, id).is_ok() {
+            grid.narrow_band_interior[lookup_index].push(particle);
+        }
+    }
+}
+
+/** How many cells each cell (by lookup index) is from the fluid's surface: air cells are 0 deep,
+fluid cells beside them are 1 deep, and so on.  Cells no surface can be reached from (like solids,
+or fluid sealed off from any air) are u32::MAX deep. */
+fn calculate_surface_depths(grid: &SimGrid) -> Vec<u32> {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let mut depths: Vec<u32> = vec![u32::MAX; rows * cols];
+    let mut frontier: VecDeque<(usize, usize)> = VecDeque::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            if grid.cell_type[row][col] == SimGridCellType::Air {
+                depths[row * cols + col] = 0;
+                frontier.push_back((row, col));
+            }
+        }
+    }
+
+    while let Some((row, col)) = frontier.pop_front() {
+        let depth: u32 = depths[row * cols + col] + 1;
+        for (row_offset, col_offset) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let neighbor_row: usize = usize::wrapping_add_signed(row, row_offset);
+            let neighbor_col: usize = usize::wrapping_add_signed(col, col_offset);
+            if neighbor_row >= rows
+                || neighbor_col >= cols
+                || grid.cell_type[neighbor_row][neighbor_col] != SimGridCellType::Fluid
+                || depths[neighbor_row * cols + neighbor_col] <= depth
+            {
+                continue;
+            }
+            depths[neighbor_row * cols + neighbor_col] = depth;
+            frontier.push_back((neighbor_row, neighbor_col));
+        }
+    }
+
+    depths
+}
+
+/** Fill in the density and temperature of every set-aside cell, since it has no particles of its
+own to deposit them; otherwise the fluid below the narrow band would look empty to the pressure
+solver.  Set-aside cells are treated as just as full as the average fluid cell in the band, with the
+materials and temperatures of the particles they stand in for.  Run after the band's particles have
+deposited theirs. */
+pub fn deposit_narrow_band_interior(grid: &mut SimGrid) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let mut density_sum: f32 = 0.0;
+    let mut band_cell_count: usize = 0;
+    for row in 0..rows {
+        for col in 0..cols {
+            if grid.cell_type[row][col] == SimGridCellType::Fluid
+                && !grid.is_narrow_band_interior(row, col)
+            {
+                density_sum += grid.density[row * cols + col];
+                band_cell_count += 1;
+            }
+        }
+    }
+    if band_cell_count == 0 {
+        return;
+    }
+    let band_density: f32 = density_sum / band_cell_count as f32;
+
+    let interior: Vec<Vec<SimParticle>> = std::mem::take(&mut grid.narrow_band_interior);
+    for (lookup_index, stored) in interior.iter().enumerate() {
+        if stored.is_empty() || lookup_index >= grid.density.len() {
+            continue;
+        }
+        let count: f32 = stored.len() as f32;
+        let material_density: f32 = stored
+            .iter()
+            .map(|particle| particle.material.density())
+            .sum::<f32>()
+            / count;
+        let material_viscosity: f32 = stored
+            .iter()
+            .map(|particle| particle.material.viscosity())
+            .sum::<f32>()
+            / count;
+        grid.density[lookup_index] = band_density;
+        grid.material_density[lookup_index] = band_density * material_density;
+        grid.material_viscosity[lookup_index] = band_density * material_viscosity;
+        for particle in stored {
+            grid.update_grid_temperature(particle.position, particle.temperature);
+        }
+    }
+    grid.narrow_band_interior = interior;
+}
+
+/** Carry the velocities of every face touching a set-aside cell forward by `delta_time`, since
+there are no particles there to bring them over to the next step.  They are advected
+semi-Lagrangian style (traced back along the grid's velocity field) and have gravity added, just as
+the particles do.  The results are kept in the grid's scratch buffers (reused from step to step)
+until `restore_narrow_band_velocities()` puts them back.  Returns false when no cells are set
+aside. */
+pub fn advect_narrow_band_velocities(
+    grid: &mut SimGrid,
+    constraints: &SimConstraints,
+    delta_time: f32,
+) -> bool {
+    if grid
+        .narrow_band_interior
+        .iter()
+        .all(|stored| stored.is_empty())
+    {
+        return false;
+    }
+
+    // Borrow the scratch buffers so the grid's velocities can be sampled while they're filled in.
+    let mut velocity_u: Vec<Vec<f32>> = std::mem::take(&mut grid.scratch.advected_velocity_u);
+    let mut velocity_v: Vec<Vec<f32>> = std::mem::take(&mut grid.scratch.advected_velocity_v);
+    velocity_u.clone_from(&grid.velocity_u);
+    velocity_v.clone_from(&grid.velocity_v);
+    for (horizontal, velocities) in [(true, &mut velocity_u), (false, &mut velocity_v)] {
+        for row in 0..velocities.len() {
+            for col in 0..velocities[row].len() {
+                if !is_face_beside_narrow_band_interior(grid, row, col, horizontal) {
+                    continue;
+                }
+
+                let position: Vec2 = grid.get_velocity_point_pos(row, col, horizontal);
+                let Some(velocity) = sample_grid_velocity(grid, position) else {
+                    continue;
+                };
+                let departure: Vec2 = position - velocity * delta_time;
+                let advected: Vec2 = sample_grid_velocity(grid, departure).unwrap_or(velocity)
+                    + constraints.gravity * delta_time;
+                velocities[row][col] = if horizontal { advected.x } else { advected.y };
+            }
+        }
+    }
+    grid.scratch.advected_velocity_u = velocity_u;
+    grid.scratch.advected_velocity_v = velocity_v;
+
+    true
+}
+
+/** Put the velocities carried by `advect_narrow_band_velocities()` back onto every face between
+set-aside cells (or between a set-aside cell and one without any particles), replacing whatever the
+particles transferred there.  Faces the band's particles reach keep the particles' velocities. */
+pub fn restore_narrow_band_velocities(grid: &mut SimGrid) {
+    let velocity_u: Vec<Vec<f32>> = std::mem::take(&mut grid.scratch.advected_velocity_u);
+    let velocity_v: Vec<Vec<f32>> = std::mem::take(&mut grid.scratch.advected_velocity_v);
+    let has_particles = |grid: &SimGrid, (row, col): (usize, usize)| -> bool {
+        !grid
+            .get_particles_in_lookup(row * grid.dimensions.1 as usize + col)
+            .is_empty()
+    };
+    for (horizontal, velocities) in [(true, &velocity_u), (false, &velocity_v)] {
+        for row in 0..velocities.len() {
+            for col in 0..velocities[row].len() {
+                if velocities[row][col] == f32::MIN
+                    || !is_face_beside_narrow_band_interior(grid, row, col, horizontal)
+                    || face_cells(row, col, horizontal)
+                        .into_iter()
+                        .any(|cell| has_particles(grid, cell))
+                {
+                    continue;
+                }
+                let target: &mut Vec<Vec<f32>> = if horizontal {
+                    &mut grid.velocity_u
+                } else {
+                    &mut grid.velocity_v
+                };
+                if let Some(face) = target.get_mut(row).and_then(|faces| faces.get_mut(col)) {
+                    *face = velocities[row][col];
+                }
+            }
+        }
+    }
+    grid.scratch.advected_velocity_u = velocity_u;
+    grid.scratch.advected_velocity_v = velocity_v;
+}
+
+/// The two cells on either side of a velocity face; horizontal faces sit left of their cell.
+fn face_cells(row: usize, col: usize, horizontal: bool) -> [(usize, usize); 2] {
+    if horizontal {
+        [(row, col), (row, usize::wrapping_sub(col, 1))]
+    } else {
+        [(row, col), (usize::wrapping_sub(row, 1), col)]
+    }
+}
+
+/** Whether a velocity face touches a set-aside cell, and lies between two open cells.  Faces
+against walls (or the grid's edge) are left to the particles and the solver, as usual. */
+fn is_face_beside_narrow_band_interior(
+    grid: &SimGrid,
+    row: usize,
+    col: usize,
+    horizontal: bool,
+) -> bool {
+    let is_open = |(row, col): (usize, usize)| -> bool {
+        grid.cell_type
+            .get(row)
+            .and_then(|cells| cells.get(col))
+            .is_some_and(|cell_type| {
+                !matches!(
+                    cell_type,
+                    SimGridCellType::Solid | SimGridCellType::Porous(_)
+                )
+            })
+    };
+    let cells: [(usize, usize); 2] = face_cells(row, col, horizontal);
+    cells.into_iter().all(is_open)
+        && cells
+            .into_iter()
+            .any(|(row, col)| grid.is_narrow_band_interior(row, col))
+}
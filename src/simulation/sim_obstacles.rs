@@ -0,0 +1,125 @@
+pub const OBSTACLE_COUNT: usize = 5;
+
+/// Prefab obstacles that can be stamped into the simulation grid as solid cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimObstacle {
+    Cup = 0,
+    Funnel,
+    MazeSegment,
+    Stairs,
+    PipeElbow,
+}
+
+impl Into<SimObstacle> for usize {
+    fn into(self) -> SimObstacle {
+        match self {
+            0 => SimObstacle::Cup,
+            1 => SimObstacle::Funnel,
+            2 => SimObstacle::MazeSegment,
+            3 => SimObstacle::Stairs,
+            4 => SimObstacle::PipeElbow,
+            _ => {
+                eprintln!("Invalid SimObstacle; defaulting to Cup!");
+                SimObstacle::Cup
+            }
+        }
+    }
+}
+
+impl SimObstacle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cup => "Cup",
+            Self::Funnel => "Funnel",
+            Self::MazeSegment => "Maze Segment",
+            Self::Stairs => "Stairs",
+            Self::PipeElbow => "Pipe Elbow",
+        }
+    }
+
+    /** Cell pattern for this obstacle, drawn as it appears on screen; each line is a row of cells,
+    where '#' is a solid cell and anything else is left untouched.  Leading whitespace and blank
+    lines are ignored. */
+    fn pattern(&self) -> &'static str {
+        match self {
+            Self::Cup => {
+                "
+                #.....#
+                #.....#
+                #.....#
+                #.....#
+                #######
+                "
+            }
+            Self::Funnel => {
+                "
+                #.......#
+                .#.....#.
+                ..#...#..
+                ...#.#...
+                ...#.#...
+                "
+            }
+            Self::MazeSegment => {
+                "
+                #########
+                ........#
+                #######.#
+                #.......#
+                #.#######
+                #........
+                #########
+                "
+            }
+            Self::Stairs => {
+                "
+                ##......
+                ####....
+                ######..
+                ########
+                "
+            }
+            Self::PipeElbow => {
+                "
+                #######
+                ......#
+                ......#
+                ####..#
+                ...#..#
+                ...#..#
+                "
+            }
+        }
+    }
+
+    /** Returns the (row, column) offsets of this obstacle's solid cells relative to the center of
+    its pattern, rotated clockwise by `quarter_turns` * 90 degrees. */
+    pub fn cells(&self, quarter_turns: u8) -> Vec<(i32, i32)> {
+        let pattern: Vec<&str> = self
+            .pattern()
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+        let center_row: i32 = pattern.len() as i32 / 2;
+        let center_col: i32 = pattern[0].len() as i32 / 2;
+
+        let mut cells: Vec<(i32, i32)> = Vec::new();
+        for (row, line) in pattern.iter().enumerate() {
+            for (col, cell) in line.chars().enumerate() {
+                if cell != '#' {
+                    continue;
+                }
+
+                // Rows grow downwards, so a clockwise quarter turn maps (row, col) to (col, -row).
+                let mut offset: (i32, i32) = (row as i32 - center_row, col as i32 - center_col);
+                for _ in 0..quarter_turns % 4 {
+                    offset = (offset.1, -offset.0);
+                }
+                cells.push(offset);
+            }
+        }
+
+        cells
+    }
+}
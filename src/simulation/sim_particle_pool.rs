@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use super::SimParticle;
+
+/** Marks a particle entity that has left the simulation.  It keeps its `SimParticle`, so reusing it
+only overwrites the particle's data, but every particle query leaves it out. */
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct SimInactiveParticle;
+
+/** Entities of particles that have left the simulation, kept to be handed to the next particles
+spawned.  Faucets, drains, and the like add and remove thousands of particles, and despawning and
+spawning an entity for each one churns the ECS (and makes the renderer link a fresh sprite every
+time).  Instead, a removed particle is only marked with `SimInactiveParticle`, which every particle
+query filters out; once its removal from the spatial lookup has been flushed, its entity joins the
+pool, and spawning a particle overwrites its `SimParticle` and takes the mark off again. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimParticlePool {
+    inactive: Vec<Entity>,
+}
+
+impl SimParticlePool {
+    /// Number of inactive particle entities waiting to be reused.
+    pub fn len(&self) -> usize {
+        self.inactive.len()
+    }
+
+    /// Whether there are no inactive particle entities to reuse.
+    pub fn is_empty(&self) -> bool {
+        self.inactive.is_empty()
+    }
+
+    /** Take a particle out of the simulation, leaving its entity behind to be pooled.  Queue its
+    removal from the spatial lookup before calling this. */
+    pub fn deactivate(commands: &mut Commands, particle_id: Entity) {
+        commands.entity(particle_id).insert(SimInactiveParticle);
+    }
+
+    /// Add a deactivated particle's entity to the pool, once it has left the spatial lookup.
+    pub fn release(&mut self, particle_id: Entity) {
+        self.inactive.push(particle_id);
+    }
+
+    /// Keep only the pooled entities `keep` holds on to.
+    pub fn retain(&mut self, keep: impl FnMut(&Entity) -> bool) {
+        self.inactive.retain(keep);
+    }
+
+    /// Swap every pooled entity that `entity_map` has an entry for with the entity it maps to.
+    pub fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for id in self.inactive.iter_mut() {
+            *id = *entity_map.get(id).unwrap_or(id);
+        }
+    }
+
+    /** Spawn a particle, reusing an inactive particle entity if there is one.  Entities despawned
+    while they were pooled (like when a save is loaded) are skipped. */
+    pub fn spawn(&mut self, commands: &mut Commands, particle: SimParticle) -> Entity {
+        while let Some(particle_id) = self.inactive.pop() {
+            if let Some(mut entity) = commands.get_entity(particle_id) {
+                entity.insert(particle).remove::<SimInactiveParticle>();
+                return particle_id;
+            }
+        }
+        commands.spawn(particle).id()
+    }
+}
@@ -1,241 +1,324 @@
+use super::sim_chunks::SimGridChunks;
+use super::sim_gpu::SimGpu;
+use super::sim_inflow::apply_inflow_velocities;
+use super::sim_narrow_band::deposit_narrow_band_interior;
+use super::sim_pressure_solver::{
+    solve_pressure_conjugate_gradient, solve_pressure_multigrid, SimPressureSolver,
+    SimSolverResidual,
+};
+use super::sim_safeguards::average_fluid_density;
 use super::util::*;
-use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+use super::{
+    SimAdvectionScheme, SimConstraints, SimFluidMaterial, SimGrid, SimGridCellType, SimGridEdge,
+    SimGridScratch, SimParticle, SimTransferScheme, SimWallMaterial, SimWallSlip,
+    FLUID_MATERIAL_COUNT,
+};
 use crate::error::Error;
+use bevy::ecs::query::ReadOnlyWorldQuery;
 use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use bevy::utils::HashMap;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+/// How far past a full correction Gauss-Seidel pushes each cell, to converge in fewer iterations.
+pub const GAUSS_SEIDEL_OVERRELAXATION: f32 = 1.99;
+/// How many times a second fluid in a porous cell loses `1 - permeability` of its velocity.
+pub const POROUS_DRAG_RATE: f32 = 60.0;
+
+/** The order particles are visited in wherever it changes the result, such as when their
+contributions are summed up.  In deterministic mode this is sorted by spawn id, so it doesn't
+depend on how Bevy happens to have stored them (or on which entities they were given, which differs
+from one run of a scene to the next as entities are recycled); otherwise it is whatever order the
+query finds them in. */
+pub fn particle_visit_order<F: ReadOnlyWorldQuery>(
+    constraints: &SimConstraints,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+) -> Vec<Entity> {
+    if !constraints.deterministic {
+        return particles.iter().map(|(id, _)| id).collect();
+    }
+
+    let mut order: Vec<(u64, Entity)> = particles
+        .iter()
+        .map(|(id, particle)| (particle.spawn_id, id))
+        .collect();
+    order.sort_unstable();
+    order.into_iter().map(|(_, id)| id).collect()
+}
+
+/** Transfer particle velocities to the grid, on `gpu` if `constraints.gpu_transfers` is set and
+there's one to run on, otherwise (or if it fails us) on the CPU with particles_to_grid(). */
+pub fn transfer_particles_to_grid<F: ReadOnlyWorldQuery>(
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    constraints: &SimConstraints,
+    gpu: Option<&mut SimGpu>,
+) {
+    let transferred: Option<()> = gpu
+        .filter(|_| constraints.gpu_transfers)
+        .and_then(|gpu| gpu.transfer_particles_to_grid(grid, particles, constraints));
+    if transferred.is_none() {
+        particles_to_grid(grid, particles, constraints);
+    }
+}
+
+/** Transfer grid velocities back to the particles, on `gpu` if `constraints.gpu_transfers` is set
+and there's one to run on, otherwise (or if it fails us) on the CPU with grid_to_particles(). */
+pub fn transfer_grid_to_particles<F: ReadOnlyWorldQuery>(
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    constraints: &SimConstraints,
+    gpu: Option<&mut SimGpu>,
+) {
+    let transferred: Option<()> = gpu
+        .filter(|_| constraints.gpu_transfers)
+        .and_then(|gpu| gpu.transfer_grid_to_particles(grid, particles, constraints));
+    if transferred.is_none() {
+        grid_to_particles(grid, particles, constraints);
+    }
+}
+
 /// Applies Particle velocities to grid velocity points
-pub fn particles_to_grid(
+pub fn particles_to_grid<F: ReadOnlyWorldQuery>(
     grid: &mut SimGrid,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
-) -> SimGrid {
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    constraints: &SimConstraints,
+) {
     // for velocity_u points and velocity_v points,
     // up all particle velocities nearby scaled
     // by their distance / cell width (their influence)
     // then divide by the summation of all their
     // influences
 
-    // This function, after applying particle velocities
-    // to the grid, returns the previous grid
-
-    // easy measurement for half the cell size
-    let half_cell = grid.cell_size as f32 / 2.0;
-
-    let (rows, cols) = grid.dimensions;
-
-    let grid_height = rows as f32 * grid.cell_size as f32;
-    let grid_width = cols as f32 * grid.cell_size as f32;
-
-    // Create new, blank grids
-    let mut velocity_u = vec![vec![f32::MIN; (cols + 1) as usize]; rows as usize];
-    let mut velocity_v = vec![vec![f32::MIN; cols as usize]; (rows + 1) as usize];
-
-    // Go through each horizontal u velocity point in the MAC grid
-    for row_index in 0..rows as usize {
-        for col_index in 0..cols as usize + 1 {
-            // Get (x, y) of current velocity point
-            let pos = grid.get_velocity_point_pos(row_index, col_index, true);
-
-            let left_center = pos - Vec2::new(half_cell, 0.0);
-            let right_center = pos + Vec2::new(half_cell, 0.0);
-
-            // If the velocity point lies on the simulation
-            // boundary, skip it
-            if left_center.x < 0.0 {
-                continue;
-            }
-
-            if right_center.x > grid_width {
-                continue;
-            }
-
-            // Determine if this velocity point lies between two air cells, and if so,
-            // skip it
-            let left_center_coords = grid.get_cell_coordinates_from_position(&left_center);
-            let right_center_coords = grid.get_cell_coordinates_from_position(&right_center);
+    // Floating point sums depend on their order, so always add particles up in the same one.
+    let order: Vec<Entity> = particle_visit_order(constraints, particles);
 
-            if grid.cell_type[left_center_coords.x as usize][left_center_coords.y as usize]
-                == SimGridCellType::Air
-                && grid.cell_type[right_center_coords.x as usize][right_center_coords.y as usize]
-                    == SimGridCellType::Air
-            {
-                continue;
-            }
+    // Borrow the velocity and scratch buffers so their allocations are reused.
+    let mut scratch = std::mem::take(&mut grid.scratch);
+    let mut velocity_u = std::mem::take(&mut grid.velocity_u);
+    let mut velocity_v = std::mem::take(&mut grid.velocity_v);
+    scatter_velocity_component(grid, particles, &order, &mut velocity_u, &mut scratch, true);
+    scatter_velocity_component(
+        grid,
+        particles,
+        &order,
+        &mut velocity_v,
+        &mut scratch,
+        false,
+    );
+    grid.velocity_u = velocity_u;
+    grid.velocity_v = velocity_v;
+    grid.scratch = scratch;
+}
 
-            if grid.cell_type[left_center_coords.x as usize][left_center_coords.y as usize]
-                == SimGridCellType::Solid
-                && grid.cell_type[right_center_coords.x as usize][right_center_coords.y as usize]
-                    == SimGridCellType::Solid
-            {
-                continue;
-            }
+/** Scatter one component of the particles' velocities (horizontal if `horizontal` is set) onto
+`velocities`, the matching velocity points of `grid`.  Each particle only visits the few points
+close enough for it to influence, so this takes one pass over the particles; since every point is
+added to in visit order, the sums come out exactly as they would gathering from every particle. */
+fn scatter_velocity_component<F: ReadOnlyWorldQuery>(
+    grid: &SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    order: &[Entity],
+    velocities: &mut Vec<Vec<f32>>,
+    scratch: &mut SimGridScratch,
+    horizontal: bool,
+) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let (point_rows, point_cols) = if horizontal {
+        (rows, cols + 1)
+    } else {
+        (rows + 1, cols)
+    };
+    let component: usize = if horizontal { 0 } else { 1 };
+    reset_buffer(velocities, point_rows, point_cols, 0.0);
+    reset_buffer(&mut scratch.transfer_influence, point_rows, point_cols, 0.0);
+    let influences: &mut Vec<Vec<f32>> = &mut scratch.transfer_influence;
+
+    // How far (in cells) the first row and column of velocity points are from the grid's corner.
+    let cell_size: f32 = grid.cell_size as f32;
+    let half_cell: f32 = (grid.cell_size / 2) as f32 / cell_size;
+    let (row_offset, col_offset) = if horizontal {
+        (half_cell, 0.0)
+    } else {
+        (0.0, half_cell)
+    };
+    let grid_height: f32 = grid.dimensions.0 as f32 * cell_size;
+
+    let mut nearby_rows: Vec<usize> = Vec::new();
+    let mut nearby_cols: Vec<usize> = Vec::new();
+    for (_, particle) in particles.iter_many(order.iter()) {
+        find_nearby_point_indices(
+            (grid_height - particle.position.y) / cell_size - row_offset,
+            point_rows,
+            rows,
+            grid.wrap_vertical,
+            &mut nearby_rows,
+        );
+        find_nearby_point_indices(
+            particle.position.x / cell_size - col_offset,
+            point_cols,
+            cols,
+            grid.wrap_horizontal,
+            &mut nearby_cols,
+        );
 
-            let mut scaled_velocity_sum = 0.0;
+        for row in nearby_rows.iter() {
+            for col in nearby_cols.iter() {
+                let pos: Vec2 = grid.get_velocity_point_pos(*row, *col, horizontal);
 
-            let mut scaled_influence_sum = 0.0;
+                // Particles just across a wrapped edge count from wherever they're closest.
+                let position: Vec2 = pos - grid.wrap_offset(pos - particle.position);
 
-            particles.for_each(|(_, particle)| {
-                let influence = find_influence(particle.position, pos, grid.cell_size);
+                // Heavier materials (and merged particles) carry more momentum onto the grid.
+                let influence = find_influence(position, pos, grid.cell_size)
+                    * particle.material.density()
+                    * particle.mass;
 
                 if influence != 0.0 {
-                    scaled_influence_sum += influence;
-                    scaled_velocity_sum += particle.velocity[0] * influence;
+                    // APIC particles also carry how their velocity changes out to this point.
+                    let affine_velocity: Vec2 = particle.affine_velocity * (pos - position);
+                    influences[*row][*col] += influence;
+                    velocities[*row][*col] +=
+                        (particle.velocity[component] + affine_velocity[component]) * influence;
                 }
-            });
-
-            if scaled_influence_sum == 0.0 {
-                velocity_u[row_index][col_index] = 0.0;
-                continue;
             }
-
-            let new_velocity = scaled_velocity_sum / scaled_influence_sum;
-
-            velocity_u[row_index][col_index] = new_velocity;
         }
     }
 
-    // Do the same thing for vertical velocity points within the MAC grid
-    for row_index in 0..rows as usize + 1 {
-        for col_index in 0..cols as usize {
-            let pos = grid.get_velocity_point_pos(row_index, col_index, false);
-
-            let bottom_center = pos - Vec2::new(0.0, half_cell);
-            let top_center = pos + Vec2::new(0.0, half_cell);
-
-            if bottom_center.y < 0.0 {
-                continue;
-            }
-
-            if top_center.y > grid_height {
-                continue;
-            }
-
-            let bottom_center_coords = grid.get_cell_coordinates_from_position(&bottom_center);
-            let top_center_coords = grid.get_cell_coordinates_from_position(&top_center);
-
-            if grid.cell_type[bottom_center_coords.x as usize][bottom_center_coords.y as usize]
-                == SimGridCellType::Air
-                && grid.cell_type[top_center_coords.x as usize][top_center_coords.y as usize]
-                    == SimGridCellType::Air
-            {
-                continue;
-            }
-
-            if grid.cell_type[bottom_center_coords.x as usize][bottom_center_coords.y as usize]
-                == SimGridCellType::Solid
-                && grid.cell_type[top_center_coords.x as usize][top_center_coords.y as usize]
-                    == SimGridCellType::Solid
-            {
-                continue;
-            }
-
-            let mut scaled_velocity_sum = 0.0;
-
-            let mut scaled_influence_sum = 0.0;
-
-            particles.for_each(|(_, particle)| {
-                let influence = find_influence(particle.position, pos, grid.cell_size);
-
-                if influence != 0.0 {
-                    scaled_influence_sum += influence;
-                    scaled_velocity_sum += particle.velocity[1] * influence;
-                }
-            });
-
-            if scaled_influence_sum == 0.0 {
-                velocity_v[row_index][col_index] = 0.0;
-                continue;
-            }
-
-            let new_velocity = scaled_velocity_sum / scaled_influence_sum;
-
-            velocity_v[row_index][col_index] = new_velocity;
+    // Points in chunks with no fluid anywhere near them are left at zero.
+    for row in 0..point_rows {
+        for col in grid.chunks.active_spans(row, point_cols).flatten() {
+            // Leave points on walled edges, or between two air or two solid cells, without one.
+            velocities[row][col] = if !is_velocity_point_transferred(grid, row, col, horizontal) {
+                f32::MIN
+            } else if influences[row][col] == 0.0 {
+                0.0
+            } else {
+                velocities[row][col] / influences[row][col]
+            };
         }
     }
-
-    let old_grid = grid.clone();
-
-    grid.velocity_u = velocity_u;
-    grid.velocity_v = velocity_v;
-
-    old_grid
 }
 
-/**
-    Create a SimGrid with values containing the difference between
-    The old grid and new grid
-*/
-pub fn create_change_grid(old_grid: &SimGrid, new_grid: &SimGrid) -> SimGrid {
-    // Here we are creating a SimGrid that holds the delta or change
-    // in values after applying the particle velocities to the grid.
-    // These values are needed when interpolating the velocity
-    // values transfered to the particles from the grid.
-
-    let (rows, cols) = old_grid.dimensions;
-
-    let mut change_grid = old_grid.clone();
-    let mut change_u = vec![vec![f32::MIN; (cols + 1) as usize]; rows as usize];
-    let mut change_v = vec![vec![f32::MIN; cols as usize]; (rows + 1) as usize];
-
-    for row_index in 0..rows as usize {
-        for col_index in 0..(cols as usize + 1) {
-            let change_in_u = new_grid.velocity_u[row_index][col_index]
-                - old_grid.velocity_u[row_index][col_index];
-
-            change_u[row_index][col_index] = change_in_u;
+/** Fill `indices` with the indices of the velocity points along one axis that lie within a cell of
+`coordinate` (in cells from the first point), out of `point_count` points spanning `cell_count`
+cells.  On a wrapped axis, points across the edge are found too, as are both points on the edge. */
+fn find_nearby_point_indices(
+    coordinate: f32,
+    point_count: usize,
+    cell_count: usize,
+    wraps: bool,
+    indices: &mut Vec<usize>,
+) {
+    indices.clear();
+    let first: isize = coordinate.floor() as isize - 1;
+    for index in first..=first + 3 {
+        if wraps {
+            let index: usize = index.rem_euclid(cell_count as isize) as usize;
+            for index in [index, index + cell_count] {
+                if index < point_count && !indices.contains(&index) {
+                    indices.push(index);
+                }
+            }
+        } else if index >= 0 && (index as usize) < point_count {
+            indices.push(index as usize);
         }
     }
+}
 
-    for row_index in 0..(rows as usize + 1) {
-        for col_index in 0..cols as usize {
-            let change_in_v = new_grid.velocity_v[row_index][col_index]
-                - old_grid.velocity_v[row_index][col_index];
+/** Whether particles transfer their velocities to a velocity point (a point of `grid.velocity_u`
+if `horizontal` is set, otherwise of `grid.velocity_v`).  Points on a walled edge of the grid, and
+points between two air cells or two solid cells, are left without a velocity (f32::MIN). */
+pub fn is_velocity_point_transferred(
+    grid: &SimGrid,
+    row: usize,
+    col: usize,
+    horizontal: bool,
+) -> bool {
+    // easy measurement for half the cell size
+    let half_cell: f32 = grid.cell_size as f32 / 2.0;
+    let grid_height: f32 = grid.dimensions.0 as f32 * grid.cell_size as f32;
+    let grid_width: f32 = grid.dimensions.1 as f32 * grid.cell_size as f32;
+
+    // The centers of the cells on either side of the point; left and right, or bottom and top.
+    let pos: Vec2 = grid.get_velocity_point_pos(row, col, horizontal);
+    let offset: Vec2 = if horizontal {
+        Vec2::new(half_cell, 0.0)
+    } else {
+        Vec2::new(0.0, half_cell)
+    };
+    let (before_center, after_center) = (pos - offset, pos + offset);
 
-            change_v[row_index][col_index] = change_in_v;
-        }
+    // If the velocity point lies on the simulation boundary, skip it (unless the boundary wraps around or is open)
+    let walled: bool = if horizontal {
+        (before_center.x < 0.0 && grid.is_edge_walled(SimGridEdge::Left))
+            || (after_center.x > grid_width && grid.is_edge_walled(SimGridEdge::Right))
+    } else {
+        (before_center.y < 0.0 && grid.is_edge_walled(SimGridEdge::Bottom))
+            || (after_center.y > grid_height && grid.is_edge_walled(SimGridEdge::Top))
+    };
+    if walled {
+        return false;
     }
 
-    change_grid.velocity_u = change_u;
-    change_grid.velocity_v = change_v;
-
-    change_grid
+    let [before_type, after_type] = [before_center, after_center].map(|center| {
+        let coords: Vec2 = grid.get_cell_coordinates_from_position(&grid.wrap_position(center));
+        grid.cell_type[coords.x as usize][coords.y as usize].clone()
+    });
+    !((before_type == SimGridCellType::Air && after_type == SimGridCellType::Air)
+        || (before_type == SimGridCellType::Solid && after_type == SimGridCellType::Solid))
 }
 
 /**
     Extrapolates values in velocity_u and velocity_v up to the stated depth
-    using the Fast Sweeping algorithm
+    using the Fast Sweeping algorithm.  Only the velocity points around the
+    active cells are extended to; particles never reach the rest of the grid
 */
 
 pub fn extrapolate_values(grid: &mut SimGrid, depth: i32) {
-    let (rows, cols) = grid.dimensions;
-
-    let mut d_u = vec![vec![0; (cols + 1) as usize]; rows as usize];
-    let mut d_v = vec![vec![0; cols as usize]; (rows + 1) as usize];
-
-    // Initialize caches for u and v components
-    for row in 0..rows as usize {
-        for col in 0..cols as usize + 1 {
-            if grid.velocity_u[row][col] != f32::MIN {
-                d_u[row][col] = 0;
-            } else {
-                d_u[row][col] = i32::MAX;
-            }
-        }
-    }
+    // Borrow the scratch buffers for the duration of the pass so the velocity
+    // grids can be mutated alongside them
+    let mut scratch = std::mem::take(&mut grid.scratch);
+    let cell_dimensions: (usize, usize) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+
+    extrapolate_component(
+        &mut grid.velocity_u,
+        &mut scratch,
+        &grid.active_cells,
+        cell_dimensions,
+        true,
+        depth,
+    );
+    extrapolate_component(
+        &mut grid.velocity_v,
+        &mut scratch,
+        &grid.active_cells,
+        cell_dimensions,
+        false,
+        depth,
+    );
 
-    for row in 0..rows as usize + 1 {
-        for col in 0..cols as usize {
-            if grid.velocity_v[row][col] != f32::MIN {
-                d_v[row][col] = 0;
-            } else {
-                d_v[row][col] = i32::MAX;
-            }
-        }
-    }
+    grid.scratch = scratch;
+}
 
-    let mut wave_u: Vec<Vec2> = Vec::new();
-    let mut wave_v: Vec<Vec2> = Vec::new();
+/**
+    Extrapolates a single velocity component grid (horizontal or not), using
+    the scratch buffers for the distance cache and wavefronts instead of
+    allocating new ones.  The first wave is only looked for on the faces of the
+    active cells (lookup indices into a grid of `cell_dimensions`) and the
+    cells around them, which is as far as two passes can extend from the fluid
+*/
+fn extrapolate_component(
+    velocity: &mut Vec<Vec<f32>>,
+    scratch: &mut SimGridScratch,
+    active_cells: &[usize],
+    cell_dimensions: (usize, usize),
+    horizontal: bool,
+    depth: i32,
+) {
+    let rows = velocity.len();
+    let cols = velocity[0].len();
 
     // Set up surrounding index offsets
     let surrounding = [
@@ -249,163 +332,116 @@ pub fn extrapolate_values(grid: &mut SimGrid, depth: i32) {
         [1, -1],
     ];
 
-    // Create first waves for u and v components
-    for row in 0..rows as usize {
-        for col in 0..cols as usize + 1 {
-            if d_u[row][col] != 0 {
-                if check_surrounding(&d_u, surrounding, (row, col), 0).len() != 0 {
-                    d_u[row][col] = 1;
-                    wave_u.push(Vec2::new(row as f32, col as f32));
-                }
-            }
-        }
-    }
-
-    for row in 0..rows as usize + 1 {
-        for col in 0..cols as usize {
-            if d_v[row][col] != 0 {
-                if check_surrounding(&d_v, surrounding, (row, col), 0).len() != 0 {
-                    d_v[row][col] = 1;
-                    wave_v.push(Vec2::new(row as f32, col as f32));
-                }
+    // Initialize the distance cache
+    let distance = &mut scratch.distance;
+    reset_buffer(distance, rows, cols, 0);
+    for row in 0..rows {
+        for col in 0..cols {
+            if velocity[row][col] == f32::MIN {
+                distance[row][col] = i32::MAX;
             }
         }
     }
 
-    // For both u and v components, extend their
-    // velocities to empty neighbor velocity points
-    let mut wavefronts_u: Vec<Vec<Vec2>> = Vec::new();
-    wavefronts_u.push(wave_u);
-    let mut wavefronts_v: Vec<Vec<Vec2>> = Vec::new();
-    wavefronts_v.push(wave_v);
-
-    let mut curr_wave_index = 0;
-
-    while curr_wave_index < depth {
-        let cur_wave = wavefronts_u.iter().nth(curr_wave_index as usize).unwrap();
-
-        let mut next_wave = Vec::new();
-
-        for i in 0..cur_wave.len() {
-            let index = cur_wave.iter().nth(i).unwrap();
-
-            let mut average = 0.0;
-            let mut num_used = 0;
-
-            for k in 0..8 {
-                let offset_x = surrounding[k][0];
-                let offset_y = surrounding[k][1];
-                let neighbor_x = index.y as i32 + offset_x;
-                let neighbor_y = index.x as i32 + offset_y;
-
-                if neighbor_x >= 0
-                    && neighbor_x < grid.velocity_u[0].len() as i32
-                    && neighbor_y >= 0
-                    && neighbor_y < grid.velocity_u.len() as i32
-                {
-                    if d_u[neighbor_y as usize][neighbor_x as usize]
-                        < d_u[index.x as usize][index.y as usize]
+    // Create first wave
+    let wave = &mut scratch.wave;
+    let next_wave = &mut scratch.next_wave;
+    wave.clear();
+    next_wave.clear();
+
+    let (cell_rows, cell_cols) = cell_dimensions;
+    for index in active_cells.iter() {
+        let (active_row, active_col) = (index / cell_cols, index % cell_cols);
+        for cell_row in active_row.saturating_sub(1)..(active_row + 2).min(cell_rows) {
+            for cell_col in active_col.saturating_sub(1)..(active_col + 2).min(cell_cols) {
+                let faces = if horizontal {
+                    [(cell_row, cell_col), (cell_row, cell_col + 1)]
+                } else {
+                    [(cell_row, cell_col), (cell_row + 1, cell_col)]
+                };
+
+                // Cells share faces; a face already in the wave no longer has an unknown distance.
+                for (row, col) in faces {
+                    if distance[row][col] == i32::MAX
+                        && has_surrounding(distance, surrounding, (row, col), 0)
                     {
-                        average += grid.velocity_u[neighbor_y as usize][neighbor_x as usize];
-                        num_used += 1;
-                    } else if d_u[neighbor_y as usize][neighbor_x as usize] == i32::MAX {
-                        d_u[neighbor_y as usize][neighbor_x as usize] =
-                            d_u[index.x as usize][index.y as usize] + 1;
-                        next_wave.push(Vec2::new(neighbor_y as f32, neighbor_x as f32));
+                        distance[row][col] = 1;
+                        wave.push(Vec2::new(row as f32, col as f32));
                     }
                 }
             }
-            average /= num_used as f32;
-            grid.velocity_u[index.x as usize][index.y as usize] = average;
         }
-
-        wavefronts_u.push(next_wave);
-        curr_wave_index += 1;
     }
 
-    curr_wave_index = 0;
-
-    while curr_wave_index < depth {
-        let cur_wave = wavefronts_v.iter().nth(curr_wave_index as usize).unwrap();
-
-        let mut next_wave = Vec::new();
-
-        for i in 0..cur_wave.len() {
-            let index = cur_wave.iter().nth(i).unwrap();
-
+    // Extend velocities to empty neighbor velocity points, one wavefront at a time
+    for _ in 0..depth {
+        for index in wave.iter() {
             let mut average = 0.0;
             let mut num_used = 0;
 
-            for k in 0..8 {
-                let offset_x = surrounding[k][0];
-                let offset_y = surrounding[k][1];
-                let neighbor_x = index.y as i32 + offset_x;
-                let neighbor_y = index.x as i32 + offset_y;
+            for offset in surrounding.iter() {
+                let neighbor_x = index.y as i32 + offset[0];
+                let neighbor_y = index.x as i32 + offset[1];
 
                 if neighbor_x >= 0
-                    && neighbor_x < grid.velocity_v[0].len() as i32
+                    && neighbor_x < cols as i32
                     && neighbor_y >= 0
-                    && neighbor_y < grid.velocity_v.len() as i32
+                    && neighbor_y < rows as i32
                 {
-                    if d_v[neighbor_y as usize][neighbor_x as usize]
-                        < d_v[index.x as usize][index.y as usize]
+                    if distance[neighbor_y as usize][neighbor_x as usize]
+                        < distance[index.x as usize][index.y as usize]
                     {
-                        average += grid.velocity_v[neighbor_y as usize][neighbor_x as usize];
+                        average += velocity[neighbor_y as usize][neighbor_x as usize];
                         num_used += 1;
-                    } else if d_v[neighbor_y as usize][neighbor_x as usize] == i32::MAX {
-                        d_v[neighbor_y as usize][neighbor_x as usize] =
-                            d_v[index.x as usize][index.y as usize] + 1;
+                    } else if distance[neighbor_y as usize][neighbor_x as usize] == i32::MAX {
+                        distance[neighbor_y as usize][neighbor_x as usize] =
+                            distance[index.x as usize][index.y as usize] + 1;
                         next_wave.push(Vec2::new(neighbor_y as f32, neighbor_x as f32));
                     }
                 }
             }
             average /= num_used as f32;
-            grid.velocity_v[index.x as usize][index.y as usize] = average;
+            velocity[index.x as usize][index.y as usize] = average;
         }
 
-        wavefronts_v.push(next_wave);
-        curr_wave_index += 1;
+        std::mem::swap(wave, next_wave);
+        next_wave.clear();
     }
 }
 
 /**
-    Helper function to check surrounding velocity points
+    Helper function to check whether any surrounding velocity point holds the
+    given value
 */
-fn check_surrounding(
+fn has_surrounding(
     grid: &Vec<Vec<i32>>,
     surroundings: [[i32; 2]; 8],
     index: (usize, usize),
     value: i32,
-) -> Vec<i32> {
-    let mut valid_neighbors: Vec<i32> = Vec::new();
+) -> bool {
     let grid_width = grid[0].len() as i32;
     let grid_height = grid.len() as i32;
 
-    for i in 0..8 {
-        let offset_x = surroundings[i][0];
-        let offset_y = surroundings[i][1];
-        let neighbor_x = index.1 as i32 + offset_x;
-        let neighbor_y = index.0 as i32 + offset_y;
-
-        if neighbor_x >= 0 && neighbor_x < grid_width && neighbor_y >= 0 && neighbor_y < grid_height
-        {
-            if grid[neighbor_y as usize][neighbor_x as usize] == value {
-                valid_neighbors.push(i as i32);
-            }
-        }
-    }
+    surroundings.iter().any(|offset| {
+        let neighbor_x = index.1 as i32 + offset[0];
+        let neighbor_y = index.0 as i32 + offset[1];
 
-    valid_neighbors
+        neighbor_x >= 0
+            && neighbor_x < grid_width
+            && neighbor_y >= 0
+            && neighbor_y < grid_height
+            && grid[neighbor_y as usize][neighbor_x as usize] == value
+    })
 }
 
 /**
     Collects all the particles within a cell and returns
     a vector of particles with their ID and data
 */
-fn collect_particles<'a>(
+fn collect_particles<'a, F: ReadOnlyWorldQuery>(
     grid: &SimGrid,
     center: Vec2,
-    particles: &'a mut Query<(Entity, &mut SimParticle)>,
+    particles: &'a mut Query<(Entity, &mut SimParticle), F>,
 ) -> Vec<(Entity, Mut<'a, SimParticle>)> {
     let mut particle_bag = Vec::new();
 
@@ -436,7 +472,6 @@ fn collect_particles<'a>(
 fn apply_grid<'a>(
     particles: Vec<(Entity, Mut<'a, SimParticle>)>,
     grid: &SimGrid,
-    change_grid: &SimGrid,
     constraints: &SimConstraints,
 ) {
     // New velocity value using equation from section 7.6
@@ -446,8 +481,41 @@ fn apply_grid<'a>(
     let pic_coef = constraints.grid_particle_ratio;
 
     for (_, mut particle) in particles {
+        if constraints.transfer_scheme == SimTransferScheme::Apic {
+            /* APIC takes the grid's velocity outright, along with how it changes across the
+            particle, so the particle can hand that back to the grid next step. */
+            let (velocity_u, gradient_u) = interpolate_velocity_component_with_gradient(
+                particle.position,
+                grid,
+                &grid.velocity_u,
+                true,
+            );
+            let (velocity_v, gradient_v) = interpolate_velocity_component_with_gradient(
+                particle.position,
+                grid,
+                &grid.velocity_v,
+                false,
+            );
+            particle.affine_velocity = Mat2::from_cols(
+                Vec2::new(gradient_u.x, gradient_v.x),
+                Vec2::new(gradient_u.y, gradient_v.y),
+            );
+            particle.velocity =
+                Vec2::new(velocity_u, velocity_v) + (constraints.gravity * constraints.timestep);
+            continue;
+        }
+        particle.affine_velocity = Mat2::ZERO;
+
+        /* Interpolation is linear, so sampling the current and previous velocity fields and taking
+        the difference is the same as sampling a "change grid", minus the allocation. */
         let interp_vel = interpolate_velocity(particle.position, &grid);
-        let change_vel = interpolate_velocity(particle.position, &change_grid);
+        let previous_vel = interpolate_velocity_field(
+            particle.position,
+            &grid,
+            &grid.previous_velocity_u,
+            &grid.previous_velocity_v,
+        );
+        let change_vel = interp_vel - previous_vel;
 
         let pic_velocity = interp_vel;
         let flip_velocity = particle.velocity + change_vel;
@@ -457,10 +525,9 @@ fn apply_grid<'a>(
 }
 
 /// Apply grid velocities to particle velocities
-pub fn grid_to_particles(
+pub fn grid_to_particles<F: ReadOnlyWorldQuery>(
     grid: &mut SimGrid,
-    change_grid: &SimGrid,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
     constraints: &SimConstraints,
 ) {
     // Basic idea right now is to go through each cell,
@@ -476,9 +543,19 @@ pub fn grid_to_particles(
                     continue;
                 }
                 SimGridCellType::Solid => {
-                    continue;
+                    // Particles caught by a spinner's blade are swept along with it.
+                    let Some(velocity) = grid.get_moving_solid_velocity(row_index, col_index)
+                    else {
+                        continue;
+                    };
+                    let coords = Vec2::new(row_index as f32, col_index as f32);
+                    for (_, mut particle) in collect_particles(grid, coords, particles) {
+                        particle.velocity = velocity;
+                        particle.affine_velocity = Mat2::ZERO;
+                    }
                 }
-                SimGridCellType::Fluid => {
+                // Fluid soaking through porous cells is carried along by the grid all the same.
+                SimGridCellType::Fluid | SimGridCellType::Porous(_) => {
                     // Grab the center postition of the cell
                     let coords = Vec2::new(row_index as f32, col_index as f32);
 
@@ -486,323 +563,1680 @@ pub fn grid_to_particles(
                     let particles_in_cell = collect_particles(grid, coords, particles);
 
                     // Solve for the new velocities of the particles
-                    apply_grid(particles_in_cell, grid, change_grid, constraints);
+                    apply_grid(particles_in_cell, grid, constraints);
                 }
             }
         }
     }
 }
 
-/// Update the particle's lookup_index based on position, then update the grid's lookup table.
-pub fn update_particle_lookup(particle_id: Entity, particle: &mut SimParticle, grid: &mut SimGrid) {
-    // Find the cell that this particle belongs to and update our spatial lookup accordingly.
-    let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
-    let lookup_index: usize = grid.get_lookup_index(cell_coordinates);
-
-    // Remove the particle from its old lookup cell and place it here in its new one.
-    if !grid.spatial_lookup[lookup_index].contains(&particle_id) {
-        grid.remove_particle_from_lookup(particle_id, particle.lookup_index);
-        grid.spatial_lookup[lookup_index].push(particle_id);
-        particle.lookup_index = lookup_index;
+/** Update every particle's lookup_index based on its position, then rebuild the grid's lookup
+table in place, adding particles to their cells in `order`.  Particles that were spawned this frame
+(so the query can't see them yet) stay in whichever cell they were added to. */
+pub fn rebuild_particle_lookup<F: ReadOnlyWorldQuery>(
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    order: &[Entity],
+) {
+    // Find the cell that each particle belongs to.
+    for id in order.iter() {
+        if let Ok((_, mut particle)) = particles.get_mut(*id) {
+            let cell_coordinates: Vec2 =
+                grid.get_cell_coordinates_from_position(&particle.position);
+            particle.lookup_index = grid.get_lookup_index(cell_coordinates);
+        }
     }
+
+    let cell_count: usize = grid.dimensions.0 as usize * grid.dimensions.1 as usize;
+    grid.spatial_lookup.rebuild(
+        cell_count,
+        particles
+            .iter_many(order.iter())
+            .map(|(id, particle)| (id, particle.lookup_index)),
+        |id| !particles.contains(id),
+    );
 }
 
 /** For each particle: integrate velocity into position, update cell type, update spatial lookup,
-and update density value of the cell the particle is in. */
-pub fn update_particles(
+and update density value of the cell the particle is in.  With `parallel` set, particles are
+integrated (and their density deposited) across the compute task pool; the results are identical
+either way. */
+pub fn update_particles<F: ReadOnlyWorldQuery>(
     constraints: &SimConstraints,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
     grid: &mut SimGrid,
     delta_time: f32,
+    parallel: bool,
 ) {
     grid.clear_density_values();
+    grid.clear_temperature_values();
+    grid.update_solid_distance();
+
+    // Every particle moves independently of the others, so they can all be moved at once.
+    let grid_view: &SimGrid = grid;
+    let integrate = |(_, mut particle): (Entity, Mut<SimParticle>)| {
+        particle.age += delta_time;
+
+        // Anything caught in the wind is blown along by it.
+        let wind: Vec2 = grid_view.get_wind_at_position(particle.position);
+        particle.velocity += wind * delta_time;
 
-    for (id, mut particle) in particles.iter_mut() {
         // Integrate the particles while handling collisions.
         let target_velocity: Vec2 = particle.velocity + constraints.gravity * delta_time;
-        let target_position: Vec2 = particle.position + target_velocity * delta_time;
+        let target_position: Vec2 = advect_particle(grid_view, constraints, &particle, delta_time);
         integrate_particle_with_collisions(
-            grid,
+            grid_view,
+            constraints,
             particle.as_mut(),
             &target_position,
             &target_velocity,
         );
+    };
+    if parallel {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        particles.par_iter_mut().for_each(integrate);
+    } else {
+        particles.iter_mut().for_each(integrate);
+    }
 
-        // Update the grid's spatial lookup based on this particle's position!
-        update_particle_lookup(id, particle.as_mut(), grid);
+    // Update the grid's spatial lookup based on each particle's new position!
+    let order: Vec<Entity> = particle_visit_order(constraints, particles);
+    rebuild_particle_lookup(grid, particles, &order);
+
+    // Update the grid's density and temperature values with every particle's contribution.
+    let deposited: Vec<(Vec2, SimFluidMaterial, f32, f32)> = particles
+        .iter_many(order.iter())
+        .map(|(_, particle)| {
+            (
+                particle.position,
+                particle.material,
+                particle.mass,
+                particle.temperature,
+            )
+        })
+        .collect();
+    deposit_particles(grid, &deposited, parallel);
+
+    // Fluid below the narrow band still weighs on the grid, even without its particles.
+    deposit_narrow_band_interior(grid);
+    grid.normalize_temperature_values();
+
+    // Particles find their neighbors through the lookup, so keep its cells in a repeatable order too.
+    if constraints.deterministic {
+        grid.sort_spatial_lookup(|id| {
+            particles
+                .get(id)
+                .map_or(u64::MAX, |(_, particle)| particle.spawn_id)
+        });
+    }
+}
+
+/// Particles whose density and temperature are added up together before being added to the grid.
+pub const PARTICLES_PER_DEPOSIT_CHUNK: usize = 1024;
+
+/// Density and temperature deposited by one chunk of particles, by lookup index.
+#[derive(Clone, Default)]
+pub struct SimParticleDeposits {
+    density: Vec<f32>,
+    material_density: Vec<f32>,
+    material_viscosity: Vec<f32>,
+    temperature: Vec<f32>,
+    temperature_weight: Vec<f32>,
+}
+
+/** Deposit the density and temperature of every particle (given as position, material, mass, and
+temperature) onto the grid.  Particles are split into chunks of `PARTICLES_PER_DEPOSIT_CHUNK`,
+each chunk adds up its particles' deposits on its own (across the compute task pool, with
+`parallel` set), and the chunks are then added to the grid in order.  Floating point sums depend on
+their order, so the chunks are the same whether or not they are added up in parallel.  Each chunk's
+deposits are kept in the grid's scratch buffers, so their allocations are reused from step to step. */
+fn deposit_particles(
+    grid: &mut SimGrid,
+    particles: &[(Vec2, SimFluidMaterial, f32, f32)],
+    parallel: bool,
+) {
+    let chunks = particles.chunks(PARTICLES_PER_DEPOSIT_CHUNK);
+    let mut chunk_deposits: Vec<SimParticleDeposits> = std::mem::take(&mut grid.scratch.deposits);
+    chunk_deposits.resize_with(chunks.len(), SimParticleDeposits::default);
+    if parallel {
+        let task_pool: &TaskPool = ComputeTaskPool::get_or_init(TaskPool::default);
+        let grid: &SimGrid = grid;
+        task_pool.scope(|scope| {
+            for (chunk, deposits) in chunks.zip(chunk_deposits.iter_mut()) {
+                scope.spawn(async move { calculate_particle_deposits(grid, chunk, deposits) });
+            }
+        });
+    } else {
+        for (chunk, deposits) in chunks.zip(chunk_deposits.iter_mut()) {
+            calculate_particle_deposits(grid, chunk, deposits);
+        }
+    }
 
-        // Update the grid's density value for this current cell.
-        grid.update_grid_density(particle.position);
+    for deposits in chunk_deposits.iter() {
+        for (total, deposit) in [
+            (&mut grid.density, &deposits.density),
+            (&mut grid.material_density, &deposits.material_density),
+            (&mut grid.material_viscosity, &deposits.material_viscosity),
+            (&mut grid.temperature, &deposits.temperature),
+            (
+                &mut grid.scratch.temperature_weight,
+                &deposits.temperature_weight,
+            ),
+        ] {
+            for (total, deposit) in total.iter_mut().zip(deposit) {
+                *total += deposit;
+            }
+        }
     }
+    grid.scratch.deposits = chunk_deposits;
 }
 
-/// Find the maximum distance a particle can move before hitting a solid!
-fn integrate_particle_with_collisions(
+/** Add up the density and temperature one chunk of particles deposits onto the grid, overwriting
+whatever `deposits` held before. */
+fn calculate_particle_deposits(
     grid: &SimGrid,
-    particle: &mut SimParticle,
-    target_position: &Vec2,
-    target_velocity: &Vec2,
+    particles: &[(Vec2, SimFluidMaterial, f32, f32)],
+    deposits: &mut SimParticleDeposits,
 ) {
-    // Calculate the cell coords. (even if they are OOB) the particle will be in next frame if unimpeded.
-    let target_coordinates: Vec2 =
-        grid.get_hypothetical_cell_coordinates_from_position(&target_position);
-    if grid.is_position_within_grid(target_position) {
-        // Figure out the type of the valid grid cell that the particle is heading towards.
-        let target_cell_type: u8 =
-            grid.get_cell_type_value(target_coordinates.x as usize, target_coordinates.y as usize);
+    for (buffer, length) in [
+        (&mut deposits.density, grid.density.len()),
+        (&mut deposits.material_density, grid.material_density.len()),
+        (
+            &mut deposits.material_viscosity,
+            grid.material_viscosity.len(),
+        ),
+        (&mut deposits.temperature, grid.temperature.len()),
+        (
+            &mut deposits.temperature_weight,
+            grid.scratch.temperature_weight.len(),
+        ),
+    ] {
+        buffer.clear();
+        buffer.resize(length, 0.0);
+    }
 
-        // If the target position is not inside of a solid cell, move as normal.
-        if target_cell_type != 0 {
-            particle.position = *target_position;
-            particle.velocity = *target_velocity;
-            return;
+    for (position, material, mass, temperature) in particles.iter() {
+        for (lookup_index, weight) in grid.calculate_density_weights(*position, *mass) {
+            deposits.density[lookup_index] += weight;
+            deposits.material_density[lookup_index] += weight * material.density();
+            deposits.material_viscosity[lookup_index] += weight * material.viscosity();
         }
+
+        let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(position);
+        let lookup_index: usize = grid.get_lookup_index(cell_coordinates);
+        deposits.temperature[lookup_index] += temperature;
+        deposits.temperature_weight[lookup_index] += 1.0;
     }
+}
 
-    // If we've gotten here, we are headed for a solid cell (or a boundary); we must collide with it!
-    let cell_center: Vec2 = grid.get_cell_center_position_from_coordinates(&target_coordinates);
+/** Find where a particle ends up after `delta_time`, using the constraints' advection scheme.
+Higher-order schemes follow the grid's velocity field, sampling it at intermediate positions so
+particles stay on curved streamlines; wherever the grid has no velocity to sample, the particle's own
+velocity is used instead.  Gravity is applied the same way for every scheme. */
+pub fn advect_particle(
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    particle: &SimParticle,
+    delta_time: f32,
+) -> Vec2 {
+    let acceleration: Vec2 = constraints.gravity * delta_time;
+    let particle_velocity: Vec2 = particle.velocity + acceleration;
+    let velocity_at = |position: Vec2| -> Vec2 {
+        sample_grid_velocity(grid, position)
+            .map_or(particle_velocity, |velocity| velocity + acceleration)
+    };
 
-    // Check which direction the particle moved into the cell from this frame.
-    let cell_half_size: f32 = (grid.cell_size as f32) / 2.0;
-    let cell_left: f32 = cell_center.x - cell_half_size; // - constraints.particle_radius;
-    let cell_right: f32 = cell_center.x + cell_half_size; // + constraints.particle_radius;
-    let cell_top: f32 = cell_center.y + cell_half_size; // + constraints.particle_radius;
-    let cell_bottom: f32 = cell_center.y - cell_half_size; // - constraints.particle_radius;
+    let position: Vec2 = particle.position;
+    match constraints.advection_scheme {
+        SimAdvectionScheme::Euler => position + particle_velocity * delta_time,
+        SimAdvectionScheme::Midpoint => {
+            let start_velocity: Vec2 = velocity_at(position);
+            let midpoint_velocity: Vec2 = velocity_at(position + start_velocity * delta_time * 0.5);
+            position + midpoint_velocity * delta_time
+        }
+        SimAdvectionScheme::Rk3 => {
+            // Ralston's third-order method.
+            let k1: Vec2 = velocity_at(position);
+            let k2: Vec2 = velocity_at(position + k1 * delta_time * 0.5);
+            let k3: Vec2 = velocity_at(position + k2 * delta_time * 0.75);
+            position + (k1 * 2.0 + k2 * 3.0 + k3 * 4.0) * (delta_time / 9.0)
+        }
+    }
+}
+
+/** Sample the grid's velocity at a position, or None if it's off the grid or any face of its cell
+was never given a velocity. */
+pub fn sample_grid_velocity(grid: &SimGrid, position: Vec2) -> Option<Vec2> {
+    if !grid.is_position_within_grid(&position) {
+        return None;
+    }
+
+    let coordinates: Vec2 = grid.get_cell_coordinates_from_position(&position);
+    let (row, col) = (coordinates.x as usize, coordinates.y as usize);
+    let faces: [f32; 4] = [
+        grid.velocity_u[row][col],
+        grid.velocity_u[row][col + 1],
+        grid.velocity_v[row][col],
+        grid.velocity_v[row + 1][col],
+    ];
+    if faces.contains(&f32::MIN) {
+        return None;
+    }
+
+    Some(interpolate_velocity(position, grid))
+}
+
+/** Move a particle towards its target, pushing it back out of (and bouncing it off of) any wall it
+ends up inside of.  Walls are found with the grid's signed distance field, so particles slide
+smoothly along corners and slopes instead of snapping to cell edges.  Walls are at least as bouncy
+and rough as `constraints` asks for, whatever they are made of. */
+pub fn integrate_particle_with_collisions(
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    particle: &mut SimParticle,
+    target_position: &Vec2,
+    target_velocity: &Vec2,
+) {
+    /* Particles leaving across a wrapped edge come back in from the opposite one; their previous
+    position comes along so they aren't drawn streaking across the whole grid. */
+    let wrapped_position: Vec2 = grid.wrap_position(*target_position);
+    particle.previous_position += wrapped_position - *target_position;
+    let target_position: &Vec2 = &wrapped_position;
+
+    // If the target position is clear of every wall (or out through an open edge), move as normal.
+    let distance: f32 = grid.sample_solid_distance(*target_position);
+    if distance >= 0.0 || grid.is_position_past_open_edge(*target_position) {
+        particle.position = *target_position;
+        particle.velocity = *target_velocity;
+        return;
+    }
 
-    // Set a small collision tolerance so our particles don't get stuck to walls.
+    /* If we've gotten here, we are headed into a solid (or off the grid); we must collide with it!
+    Push the particle back out along the wall's normal, with a small collision tolerance so our
+    particles don't get stuck to walls. */
     let tolerance: f32 = 0.1;
+    let mut normal: Vec2 = grid.get_solid_distance_normal(*target_position);
+    if normal == Vec2::ZERO {
+        normal = (particle.position - *target_position).normalize_or_zero();
+    }
+    if normal == Vec2::ZERO {
+        normal = Vec2::Y;
+    }
+    particle.position = *target_position + normal * (tolerance - distance);
 
-    if particle.position.x <= cell_left && target_position.x >= cell_left {
-        particle.position.x = cell_left - tolerance;
-        particle.velocity.x = 0.0;
-    } else if particle.position.x >= cell_right && target_position.x <= cell_right {
-        particle.position.x = cell_right + tolerance;
-        particle.velocity.x = 0.0;
+    // The grid's boundary behaves like a normal wall.
+    let cell_half_size: f32 = (grid.cell_size as f32) / 2.0;
+    let wall_position: Vec2 = particle.position - normal * (tolerance + cell_half_size);
+    let material: SimWallMaterial = if grid.is_position_within_grid(&wall_position) {
+        let wall_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&wall_position);
+        grid.get_wall_material(wall_coordinates.x as usize, wall_coordinates.y as usize)
     } else {
-        particle.velocity.x = target_velocity.x;
-        particle.position.x = target_position.x;
+        SimWallMaterial::Normal
+    };
+
+    // Particles already moving away from the wall keep going.
+    let normal_speed: f32 = target_velocity.dot(normal);
+    if normal_speed >= 0.0 {
+        particle.velocity = *target_velocity;
+        return;
     }
 
-    if particle.position.y <= cell_bottom && target_position.y >= cell_bottom {
-        particle.position.y = cell_bottom - tolerance;
-        particle.velocity.y = 0.0;
-    } else if particle.position.y >= cell_top && target_position.y <= cell_top {
-        particle.position.y = cell_top + tolerance;
-        particle.velocity.y = 0.0;
-    } else {
-        particle.velocity.y = target_velocity.y;
-        particle.position.y = target_position.y;
+    /* Bounce off of the wall's surface.  Friction slows particles sliding along it, and adhesion
+    holds them to it. */
+    let restitution: f32 = material
+        .restitution()
+        .max(constraints.collision_restitution);
+    let friction: f32 = 1.0 - (1.0 - material.friction()) * (1.0 - constraints.collision_friction);
+    particle.velocity = bounce_off_surface(*target_velocity, normal, restitution, friction)
+        * (1.0 - material.adhesion());
+}
+
+/** Bounce a velocity headed into a surface back off of it.  `restitution` is how fast it bounces
+back off, as a fraction of how fast it was headed into the surface, and `friction` is the fraction
+of its speed along the surface that is lost.  Velocities already headed away from the surface are
+left alone. */
+pub fn bounce_off_surface(velocity: Vec2, normal: Vec2, restitution: f32, friction: f32) -> Vec2 {
+    let normal_speed: f32 = velocity.dot(normal);
+    if normal_speed >= 0.0 {
+        return velocity;
     }
+
+    let normal_velocity: Vec2 = normal * normal_speed;
+    let tangent_velocity: Vec2 = velocity - normal_velocity;
+    tangent_velocity * (1.0 - friction) - normal_velocity * restitution
 }
 
-/// Handle particle collisions with the grid.
-pub fn handle_particle_grid_collisions(
+/** Handle particle collisions with the grid, bouncing particles off of its edges like off of walls.
+With `parallel` set, particles are handled across the compute task pool. */
+pub fn handle_particle_grid_collisions<F: ReadOnlyWorldQuery>(
     constraints: &SimConstraints,
     grid: &SimGrid,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    parallel: bool,
 ) {
-    for (_, mut particle) in particles.iter_mut() {
+    let restitution: f32 = constraints.collision_restitution;
+    let friction: f32 = constraints.collision_friction;
+    let collide = |(_, mut particle): (Entity, Mut<SimParticle>)| {
         // Don't let particles escape the grid!
         let grid_width: f32 = (grid.cell_size * grid.dimensions.1) as f32;
         let grid_height: f32 = (grid.cell_size * grid.dimensions.0) as f32;
 
-        // Left/right collision checks.
-        if particle.position.x < constraints.particle_radius {
-            particle.position.x = constraints.particle_radius;
-            particle.velocity.x = 0.0;
-        } else if particle.position.x > grid_width - constraints.particle_radius {
-            particle.position.x = grid_width - constraints.particle_radius;
-            particle.velocity.x = 0.0;
+        // Wrapped edges hand particles over to the opposite edge instead of stopping them.
+        let wrapped_position: Vec2 = grid.wrap_position(particle.position);
+        if wrapped_position != particle.position {
+            let wrap_offset: Vec2 = wrapped_position - particle.position;
+            particle.previous_position += wrap_offset;
+            particle.position = wrapped_position;
+        }
+
+        // Left/right collision checks; particles only get past wrapped and open edges.
+        let radius: f32 = particle.radius;
+        if particle.position.x < radius && !grid.is_edge_passable(SimGridEdge::Left) {
+            particle.position.x = radius;
+            particle.velocity =
+                bounce_off_surface(particle.velocity, Vec2::X, restitution, friction);
+        } else if particle.position.x > grid_width - radius
+            && !grid.is_edge_passable(SimGridEdge::Right)
+        {
+            particle.position.x = grid_width - radius;
+            particle.velocity =
+                bounce_off_surface(particle.velocity, Vec2::NEG_X, restitution, friction);
         }
 
         // Up/down collision checks.
-        if particle.position.y < constraints.particle_radius {
-            particle.position.y = constraints.particle_radius;
-            particle.velocity.y = 0.0;
-        } else if particle.position.y > grid_height - constraints.particle_radius {
-            particle.position.y = grid_height - constraints.particle_radius;
-            particle.velocity.y = 0.0;
+        if particle.position.y < radius && !grid.is_edge_passable(SimGridEdge::Bottom) {
+            particle.position.y = radius;
+            particle.velocity =
+                bounce_off_surface(particle.velocity, Vec2::Y, restitution, friction);
+        } else if particle.position.y > grid_height - radius
+            && !grid.is_edge_passable(SimGridEdge::Top)
+        {
+            particle.position.y = grid_height - radius;
+            particle.velocity =
+                bounce_off_surface(particle.velocity, Vec2::NEG_Y, restitution, friction);
         }
+    };
+    if parallel {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        particles.par_iter_mut().for_each(collide);
+    } else {
+        particles.iter_mut().for_each(collide);
     }
 }
 
 /** Push particles apart so that we account for drift and grid cells with incorrect densities.
-TODO: Improve collision solving speed between particles within cells.  Lots of particles in
-one cell leads to a large slowdown. */
-pub fn push_particles_apart(
+Each particle is checked against the others in its own cell and in the cells right beside it, once
+per pair.  Those cells all lie within the 3x3 block around it, so cells three apart never touch the
+same particles; cells are colored in a 3x3 pattern, and (with `parallel` set) every cell of one
+color is handled at once across the compute task pool.  The results are identical either way. */
+pub fn push_particles_apart<F: ReadOnlyWorldQuery>(
     constraints: &SimConstraints,
     grid: &SimGrid,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    parallel: bool,
 ) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+
+    // Work on copies of the particles, found by their slot in `state`.
+    let order: Vec<Entity> = particle_visit_order(constraints, particles);
+    let slots: HashMap<Entity, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(slot, id)| (*id, slot))
+        .collect();
+    let mut state: Vec<SimParticle> = particles
+        .iter_many(order.iter())
+        .map(|(_, particle)| particle.clone())
+        .collect();
+    let cell_slots: Vec<Vec<usize>> = (0..rows * cols)
+        .map(|lookup_index| {
+            grid.get_particles_in_lookup(lookup_index)
+                .iter()
+                .filter_map(|id| slots.get(id).copied())
+                .collect()
+        })
+        .collect();
+
+    // Only cells with particles in them have any work to do.
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    for (lookup_index, slots) in cell_slots.iter().enumerate() {
+        if slots.is_empty() {
+            continue;
+        }
+        let row_color: usize = separation_color(lookup_index / cols, rows, grid.wrap_vertical);
+        let col_color: usize = separation_color(lookup_index % cols, cols, grid.wrap_horizontal);
+        let color: usize = row_color * 5 + col_color;
+        if colors.len() <= color {
+            colors.resize(color + 1, Vec::new());
+        }
+        colors[color].push(lookup_index);
+    }
+
     for _i in 0..constraints.collision_iters_per_frame {
-        // For each grid cell.
-        for lookup_index in 0..grid.spatial_lookup.len() {
-            // Create a vector of all particles in all of the surrounding cells.
-            let nearby_particles: Vec<Entity> = grid.get_nearby_particles(lookup_index);
-            let possible_collisions: Vec<Entity> = nearby_particles.clone();
-
-            // For each particle within neighboring grid cell.
-            for particle0_id in nearby_particles.iter() {
-                // For each OTHER particle within this grid cell.
-                for particle1_id in possible_collisions.iter() {
-                    // Don't process a collision between ourself!
-                    if particle0_id == particle1_id {
-                        continue;
+        for cells in colors.iter().filter(|cells| !cells.is_empty()) {
+            let separate = |cells: &[usize]| -> Vec<(usize, SimParticle)> {
+                cells
+                    .iter()
+                    .flat_map(|lookup_index| {
+                        separate_cell_particles(
+                            constraints,
+                            grid,
+                            &cell_slots,
+                            &state,
+                            *lookup_index,
+                        )
+                    })
+                    .collect()
+            };
+            let separated: Vec<Vec<(usize, SimParticle)>> = if parallel {
+                let task_pool: &TaskPool = ComputeTaskPool::get_or_init(TaskPool::default);
+                let cells_per_task: usize =
+                    ((cells.len() + task_pool.thread_num() - 1) / task_pool.thread_num()).max(1);
+                let separate = &separate;
+                task_pool.scope(|scope| {
+                    for task_cells in cells.chunks(cells_per_task) {
+                        scope.spawn(async move { separate(task_cells) });
                     }
+                })
+            } else {
+                vec![separate(cells)]
+            };
 
-                    // Get both particles involved in the collision.
-                    let particle_combo_result =
-                        particles.get_many_mut([*particle0_id, *particle1_id]);
-                    let particle_combo = match particle_combo_result {
-                        Ok(particle_combo_result) => particle_combo_result,
-                        Err(_error) => {
-                            // eprintln!("Invalid particle combo; skipping!");
-                            continue;
-                        }
-                    };
-
-                    // Push both particles apart.
-                    separate_particle_pair(constraints, grid, particle_combo);
-                }
+            // No two cells of one color touch the same particle, so these never overwrite each other.
+            for (slot, particle) in separated.into_iter().flatten() {
+                state[slot] = particle;
             }
         }
     }
+
+    for (slot, id) in order.iter().enumerate() {
+        if let Ok((_, mut particle)) = particles.get_mut(*id) {
+            *particle = state[slot].clone();
+        }
+    }
 }
 
-/// Helper function for push_particles_apart().
-fn separate_particle_pair(
+/** Which of the 3x3 separation colors a row (or column) falls in.  Wrapped axes that don't divide
+into threes leave their last row or two next to row 0 across the edge, so those get colors of their
+own. */
+fn separation_color(index: usize, count: usize, wraps: bool) -> usize {
+    let leftover: usize = count % 3;
+    if wraps && leftover != 0 && index >= count - leftover {
+        3 + index - (count - leftover)
+    } else {
+        index % 3
+    }
+}
+
+/** Push apart every pair of particles in a cell, and every particle in it from the particles in the
+cells to its right and below it (the cells to its left and above check against it themselves).
+Returns the slot and new state of every particle that was checked. */
+fn separate_cell_particles(
     constraints: &SimConstraints,
     grid: &SimGrid,
-    mut particle_combo: [(Entity, Mut<'_, SimParticle>); 2],
-) {
+    cell_slots: &Vec<Vec<usize>>,
+    state: &Vec<SimParticle>,
+    lookup_index: usize,
+) -> Vec<(usize, SimParticle)> {
+    let cols: usize = grid.dimensions.1 as usize;
+    let (row, col) = (lookup_index / cols, lookup_index % cols);
+
+    // This cell's particles come first, followed by those of its neighbors.
+    let mut cells: Vec<usize> = vec![lookup_index];
+    for (row_offset, col_offset) in [(0, 1), (1, -1), (1, 0), (1, 1)] {
+        let Some((neighbor_row, neighbor_col)) = grid.wrap_cell(
+            usize::wrapping_add_signed(row, row_offset),
+            usize::wrapping_add_signed(col, col_offset),
+        ) else {
+            continue;
+        };
+
+        // Small wrapped grids can reach the same cell from both sides.
+        let neighbor_index: usize = neighbor_row * cols + neighbor_col;
+        if !cells.contains(&neighbor_index) {
+            cells.push(neighbor_index);
+        }
+    }
+    let mut local: Vec<(usize, SimParticle)> = cells
+        .iter()
+        .flat_map(|cell| cell_slots[*cell].iter())
+        .map(|slot| (*slot, state[*slot].clone()))
+        .collect();
+
+    for first in 0..cell_slots[lookup_index].len() {
+        for second in first + 1..local.len() {
+            let (head, tail) = local.split_at_mut(second);
+            separate_particle_pair(constraints, grid, &mut head[first].1, &mut tail[0].1);
+        }
+    }
+
+    local
+}
+
+/** Helper function for push_particles_apart().  Besides being pushed apart, particles closing in on
+each other have `constraints.collision_restitution` of the speed they're closing in at turned back
+around (so at 1 they bounce apart just as fast as they closed in), and lose
+`constraints.collision_friction` of the speed they're sliding past each other at. */
+fn separate_particle_pair(
+    constraints: &SimConstraints,
+    grid: &SimGrid,
+    particle0: &mut SimParticle,
+    particle1: &mut SimParticle,
+) {
     // Collision radii used to find the particle pair's push force on each other.
-    let collision_radius: f32 = constraints.particle_radius * 2.0;
+    let collision_radius: f32 = particle0.radius + particle1.radius;
     let collision_radius_squared: f32 = collision_radius * collision_radius;
 
     // Figure out if we even need to push the particles apart in the first place!
-    let mut delta_position: Vec2 = Vec2 {
-        x: particle_combo[0].1.position[0] - particle_combo[1].1.position[0],
-        y: particle_combo[0].1.position[1] - particle_combo[1].1.position[1],
-    };
+    let mut delta_position: Vec2 = grid.wrap_offset(Vec2 {
+        x: particle0.position[0] - particle1.position[0],
+        y: particle0.position[1] - particle1.position[1],
+    });
     let distance_squared: f32 =
         (delta_position.x * delta_position.x) + (delta_position.y * delta_position.y);
     if distance_squared > collision_radius_squared || distance_squared <= 0.0 {
         return;
     }
 
-    // Calculate the distance we need to separate the particles by.
+    /* Calculate the distance we need to separate the particles by; heavier particles give up less
+    of the ground. */
     let distance: f32 = distance_squared.sqrt();
-    let separation_scale: f32 = 0.5 * (collision_radius - distance) / distance;
+    let separation_scale: f32 = (collision_radius - distance) / distance;
     delta_position *= separation_scale;
+    let total_mass: f32 = (particle0.mass + particle1.mass).max(f32::EPSILON);
+    let share0: f32 = particle1.mass / total_mass;
+    let share1: f32 = particle0.mass / total_mass;
+
+    /* Trade momentum along (and across) the line between the particles, again in proportion to
+    their masses so that none is gained or lost. */
+    let mut target_velocity0: Vec2 = particle0.velocity;
+    let mut target_velocity1: Vec2 = particle1.velocity;
+    let normal: Vec2 = delta_position.normalize_or_zero();
+    let relative_velocity: Vec2 = target_velocity0 - target_velocity1;
+    let relative_normal_speed: f32 = relative_velocity.dot(normal);
+    let mut exchange: Vec2 =
+        (relative_velocity - normal * relative_normal_speed) * -constraints.collision_friction;
+    if relative_normal_speed < 0.0 {
+        exchange -= normal * relative_normal_speed * 2.0 * constraints.collision_restitution;
+    }
+    target_velocity0 += exchange * share0;
+    target_velocity1 -= exchange * share1;
 
     // Move the particles apart!
-    let target_velocity0: Vec2 = particle_combo[0].1.velocity;
-    let target_velocity1: Vec2 = particle_combo[1].1.velocity;
 
-    let target_position0: Vec2 = particle_combo[0].1.position + delta_position;
-    let target_position1: Vec2 = particle_combo[1].1.position - delta_position;
+    let target_position0: Vec2 = particle0.position + delta_position * share0;
+    let target_position1: Vec2 = particle1.position - delta_position * share1;
 
     integrate_particle_with_collisions(
         grid,
-        particle_combo[0].1.as_mut(),
+        constraints,
+        particle0,
         &target_position0,
         &target_velocity0,
     );
     integrate_particle_with_collisions(
         grid,
-        particle_combo[1].1.as_mut(),
+        constraints,
+        particle1,
         &target_position1,
         &target_velocity1,
     );
 }
 
-/** Force velocity incompressibility for each grid cell within the simulation.  Uses the
-Gauss-Seidel method. */
-pub fn make_grid_velocities_incompressible(grid: &mut SimGrid, constraints: &mut SimConstraints) {
-    // Get the "particle rest density" for the simulation domain.
-    let mut fluid_cell_count: f32 = 0.0;
+/** Diffuse grid velocities to model viscosity by explicitly integrating `viscosity * laplacian(u)`.
+Each cell's viscosity is `viscosity` plus the average viscosity of the fluid materials in it.  The
+step is split into substeps whenever it would be too large to integrate stably, so thick fluids
+like honey don't blow up.  Only faces bordering fluid (and not solids) are diffused. */
+pub fn apply_viscosity(grid: &mut SimGrid, viscosity: f32, delta_time: f32) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+
+    // Borrow the scratch buffers so the velocity grids can be mutated alongside them.
+    let mut scratch = std::mem::take(&mut grid.scratch);
+    scratch.cell_viscosity.clear();
+    scratch.cell_viscosity.resize(rows * cols, viscosity);
+    let mut max_viscosity: f32 = 0.0;
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            let lookup_index: usize = grid.get_lookup_index(Vec2::new(row as f32, col as f32));
+            let cell_viscosity: f32 = viscosity + grid.get_cell_material_viscosity(lookup_index);
+            if grid.cell_type[row][col] == SimGridCellType::Fluid {
+                max_viscosity = max_viscosity.max(cell_viscosity);
+            }
+            scratch.cell_viscosity[lookup_index] = cell_viscosity;
+        }
+    }
+    if max_viscosity <= 0.0 {
+        grid.scratch = scratch;
+        return;
+    }
+
+    // Explicit diffusion on a 2D grid is only stable for diffusion numbers of at most 1/4.
+    let cell_size: f32 = grid.cell_size as f32;
+    let diffusion_scale: f32 = delta_time / (cell_size * cell_size);
+    let substep_count: usize = (max_viscosity * diffusion_scale / 0.25).ceil() as usize;
+    let substep_scale: f32 = diffusion_scale / substep_count as f32;
+
+    for _ in 0..substep_count {
+        // Horizontal faces sit between a cell and its left neighbor; vertical faces, its upper one.
+        diffuse_component(
+            &mut grid.velocity_u,
+            &grid.cell_type,
+            &grid.chunks,
+            &scratch.cell_viscosity,
+            (0, 1),
+            &mut scratch.diffused,
+            substep_scale,
+        );
+        diffuse_component(
+            &mut grid.velocity_v,
+            &grid.cell_type,
+            &grid.chunks,
+            &scratch.cell_viscosity,
+            (1, 0),
+            &mut scratch.diffused,
+            substep_scale,
+        );
+    }
+    grid.scratch = scratch;
+}
+
+/** Run one explicit diffusion step over a single velocity component grid.  Face (row, col) lies
+between cells (row, col) and (row - offset.0, col - offset.1); faces on the edge of the grid, and in
+inactive chunks, are left alone.  Each face diffuses by the average viscosity of its two cells times `diffusion_scale`.
+Neighboring faces without a velocity are treated as moving with the face being diffused, so fluid
+doesn't drag against empty air. */
+fn diffuse_component(
+    velocity: &mut Vec<Vec<f32>>,
+    cell_type: &Vec<Vec<SimGridCellType>>,
+    chunks: &SimGridChunks,
+    cell_viscosity: &Vec<f32>,
+    offset: (usize, usize),
+    diffused: &mut Vec<Vec<f32>>,
+    diffusion_scale: f32,
+) {
+    let rows: usize = velocity.len();
+    let cols: usize = velocity[0].len();
+    let cell_cols: usize = cell_type[0].len();
+
+    diffused.clone_from(velocity);
+    for row in offset.0..rows - offset.0 {
+        for col in chunks
+            .active_spans(row, cols)
+            .flatten()
+            .filter(|col| (offset.1..cols - offset.1).contains(col))
+        {
+            let center: f32 = velocity[row][col];
+            let near_cell: &SimGridCellType = &cell_type[row][col];
+            let far_cell: &SimGridCellType = &cell_type[row - offset.0][col - offset.1];
+            if center == f32::MIN
+                || *near_cell == SimGridCellType::Solid
+                || *far_cell == SimGridCellType::Solid
+                || (*near_cell != SimGridCellType::Fluid && *far_cell != SimGridCellType::Fluid)
+            {
+                continue;
+            }
+
+            let mut laplacian: f32 = 0.0;
+            for (row_offset, col_offset) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let neighbor_row: usize = usize::wrapping_add_signed(row, row_offset);
+                let neighbor_col: usize = usize::wrapping_add_signed(col, col_offset);
+                let neighbor: f32 = match velocity
+                    .get(neighbor_row)
+                    .and_then(|faces| faces.get(neighbor_col))
+                {
+                    Some(value) if *value != f32::MIN => *value,
+                    _ => center,
+                };
+                laplacian += neighbor - center;
+            }
+
+            let face_viscosity: f32 = 0.5
+                * (cell_viscosity[row * cell_cols + col]
+                    + cell_viscosity[(row - offset.0) * cell_cols + col - offset.1]);
+            diffused[row][col] = center + face_viscosity * diffusion_scale * laplacian;
+        }
+    }
+    std::mem::swap(velocity, diffused);
+}
+
+/** Pull the fluid's surface inward wherever it curves, so droplets bead up and small blobs hold
+together.  Uses the continuum surface force model: each cell's fluid fraction (its density relative
+to a typical fluid cell's) is treated as a level set whose gradient points into the fluid, and the
+divergence of its normals gives the surface's curvature.  Strength is measured in grid cells. */
+pub fn apply_surface_tension<F: ReadOnlyWorldQuery>(
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    surface_tension: f32,
+    delta_time: f32,
+) {
+    let fluid_density: f32 = average_fluid_density(grid);
+    if surface_tension <= 0.0 || fluid_density <= 0.0 {
+        return;
+    }
+
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let index = |row: usize, col: usize| -> usize { row * cols + col };
+    let mut scratch = std::mem::take(&mut grid.scratch);
+
+    // Fluid fraction of each cell; solid cells count as full so walls don't look like a surface.
+    let fraction: &mut Vec<f32> = &mut scratch.surface_fraction;
+    fraction.clear();
+    fraction.resize(rows * cols, 0.0);
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            fraction[index(row, col)] = if grid.cell_type[row][col] == SimGridCellType::Solid {
+                1.0
+            } else {
+                (grid.density[index(row, col)] / fluid_density).min(1.0)
+            };
+        }
+    }
+
+    // Gradient (per cell) of the fluid fraction and its direction, which points into the fluid.
+    let gradient: &mut Vec<Vec2> = &mut scratch.surface_gradient;
+    let normal: &mut Vec<Vec2> = &mut scratch.surface_normal;
+    gradient.clear();
+    gradient.resize(rows * cols, Vec2::ZERO);
+    normal.clear();
+    normal.resize(rows * cols, Vec2::ZERO);
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            let (up, down, left, right) = neighbor_cells(row, col, rows, cols);
+            let cell_gradient: Vec2 = Vec2 {
+                x: fraction[index(row, right)] - fraction[index(row, left)],
+                y: fraction[index(up, col)] - fraction[index(down, col)],
+            } * 0.5;
+            gradient[index(row, col)] = cell_gradient;
+            if cell_gradient.length() > 1e-3 {
+                normal[index(row, col)] = cell_gradient.normalize();
+            }
+        }
+    }
+
+    // Surfaces curving around the fluid pull inward; curvature is clamped to keep noise in check.
+    let force: &mut Vec<Vec2> = &mut scratch.surface_force;
+    force.clear();
+    force.resize(rows * cols, Vec2::ZERO);
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            let (up, down, left, right) = neighbor_cells(row, col, rows, cols);
+            let divergence: f32 = (normal[index(row, right)].x - normal[index(row, left)].x
+                + normal[index(up, col)].y
+                - normal[index(down, col)].y)
+                * 0.5;
+            let curvature: f32 = (-divergence).clamp(-1.0, 1.0);
+            force[index(row, col)] = surface_tension * curvature * gradient[index(row, col)];
+        }
+    }
+
+    // Interpolate the force between cell centers at each particle.
+    let cell_size: f32 = grid.cell_size as f32;
+    let grid_height: f32 = rows as f32 * cell_size;
+    for (_, mut particle) in particles.iter_mut() {
+        let row_position: f32 =
+            ((grid_height - particle.position.y) / cell_size - 0.5).clamp(0.0, (rows - 1) as f32);
+        let col_position: f32 =
+            (particle.position.x / cell_size - 0.5).clamp(0.0, (cols - 1) as f32);
+        let row0: usize = row_position as usize;
+        let col0: usize = col_position as usize;
+        let row1: usize = (row0 + 1).min(rows - 1);
+        let col1: usize = (col0 + 1).min(cols - 1);
+        let row_weight: f32 = row_position - row0 as f32;
+        let col_weight: f32 = col_position - col0 as f32;
+
+        let top: Vec2 = force[index(row0, col0)].lerp(force[index(row0, col1)], col_weight);
+        let bottom: Vec2 = force[index(row1, col0)].lerp(force[index(row1, col1)], col_weight);
+        particle.velocity += top.lerp(bottom, row_weight) * delta_time;
+    }
+
+    grid.scratch = scratch;
+}
+
+/** Pull the boundaries between different fluids (say, oil floating on water) inward wherever they
+curve, so one fluid gathers into smooth blobs and layers inside another rather than being stirred
+through it.  The same continuum surface force model as `apply_surface_tension`, except each cell's
+level set is the share of its fluid made up of each material, so only fluid-fluid boundaries pull
+and the fluid's surface against the air is left to surface tension.  Strength is measured in grid
+cells. */
+pub fn apply_interface_tension<F: ReadOnlyWorldQuery>(
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    interface_tension: f32,
+    delta_time: f32,
+) {
+    if interface_tension <= 0.0 {
+        return;
+    }
+
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let index = |row: usize, col: usize| -> usize { row * cols + col };
+    let mut scratch = std::mem::take(&mut grid.scratch);
+
+    // How much of each material every cell holds, by mass.
+    let share: &mut Vec<[f32; FLUID_MATERIAL_COUNT]> = &mut scratch.interface_share;
+    share.clear();
+    share.resize(rows * cols, [0.0; FLUID_MATERIAL_COUNT]);
+    let mut materials_present: [bool; FLUID_MATERIAL_COUNT] = [false; FLUID_MATERIAL_COUNT];
+    for (_, particle) in particles.iter() {
+        if !grid.is_position_within_grid(&particle.position) {
+            continue;
+        }
+        let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        share[index(cell.x as usize, cell.y as usize)][particle.material as usize] += particle.mass;
+        materials_present[particle.material as usize] = true;
+    }
+
+    // A lone fluid has no boundaries with any other.
+    if materials_present.iter().filter(|present| **present).count() < 2 {
+        grid.scratch = scratch;
+        return;
+    }
+
+    // Turn each cell's masses into shares of its fluid; cells without fluid are left all zeroes.
+    for cell_share in share.iter_mut() {
+        let total: f32 = cell_share.iter().sum();
+        if total > 0.0 {
+            cell_share
+                .iter_mut()
+                .for_each(|material_share| *material_share /= total);
+        }
+    }
+    let has_fluid = |cell: usize| -> bool {
+        share[cell]
+            .iter()
+            .any(|material_share| *material_share > 0.0)
+    };
+
+    // Every material's boundaries pull on all the fluid around them, half from either side.
+    let gradient: &mut Vec<Vec2> = &mut scratch.surface_gradient;
+    let normal: &mut Vec<Vec2> = &mut scratch.surface_normal;
+    let force: &mut Vec<Vec2> = &mut scratch.surface_force;
+    force.clear();
+    force.resize(rows * cols, Vec2::ZERO);
+    for material in 0..FLUID_MATERIAL_COUNT {
+        if !materials_present[material] {
+            continue;
+        }
+
+        /* Gradient (per cell) of the material's share and its direction, which points into the
+        material.  Neighbors without fluid borrow the cell's own share, so the air doesn't look like
+        another fluid. */
+        gradient.clear();
+        gradient.resize(rows * cols, Vec2::ZERO);
+        normal.clear();
+        normal.resize(rows * cols, Vec2::ZERO);
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell: usize = index(row, col);
+                if !has_fluid(cell) {
+                    continue;
+                }
+                let (up, down, left, right) = neighbor_cells(row, col, rows, cols);
+                let share_at = |neighbor: usize| -> f32 {
+                    if has_fluid(neighbor) {
+                        share[neighbor][material]
+                    } else {
+                        share[cell][material]
+                    }
+                };
+                let cell_gradient: Vec2 = Vec2 {
+                    x: share_at(index(row, right)) - share_at(index(row, left)),
+                    y: share_at(index(up, col)) - share_at(index(down, col)),
+                } * 0.5;
+                gradient[cell] = cell_gradient;
+                if cell_gradient.length() > 1e-3 {
+                    normal[cell] = cell_gradient.normalize();
+                }
+            }
+        }
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let (up, down, left, right) = neighbor_cells(row, col, rows, cols);
+                let divergence: f32 = (normal[index(row, right)].x - normal[index(row, left)].x
+                    + normal[index(up, col)].y
+                    - normal[index(down, col)].y)
+                    * 0.5;
+                let curvature: f32 = (-divergence).clamp(-1.0, 1.0);
+                force[index(row, col)] +=
+                    0.5 * interface_tension * curvature * gradient[index(row, col)];
+            }
+        }
+    }
+
+    // Push each particle by the force in the cell it's in.
+    for (_, mut particle) in particles.iter_mut() {
+        if !grid.is_position_within_grid(&particle.position) {
+            continue;
+        }
+        let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        particle.velocity += force[index(cell.x as usize, cell.y as usize)] * delta_time;
+    }
+
+    grid.scratch = scratch;
+}
+
+/** Let hot fluid rise and cold fluid sink, using the Boussinesq approximation: the fluid's density
+only changes through this buoyancy force.  Each face bordering fluid (and not solids) is pushed
+against gravity by `thermal_expansion * (T - T_average)` times gravity, where `T` is the average
+temperature of the fluid cells on either side and `T_average` is that of all the fluid. */
+pub fn apply_thermal_buoyancy(
+    grid: &mut SimGrid,
+    gravity: Vec2,
+    thermal_expansion: f32,
+    delta_time: f32,
+) {
+    if thermal_expansion == 0.0 {
+        return;
+    }
+
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let mut fluid_temperature_sum: f32 = 0.0;
+    let mut fluid_cell_count: usize = 0;
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            if grid.cell_type[row][col] == SimGridCellType::Fluid {
+                fluid_temperature_sum += grid.temperature[row * cols + col];
+                fluid_cell_count += 1;
+            }
+        }
+    }
+    if fluid_cell_count == 0 {
+        return;
+    }
+    let average_temperature: f32 = fluid_temperature_sum / fluid_cell_count as f32;
+
+    /* How much warmer than average the fluid on either side of a face is; None if the face touches
+    a solid or doesn't border any fluid. */
+    let face_temperature_difference = |cells: [(usize, usize); 2]| -> Option<f32> {
+        let mut temperature_sum: f32 = 0.0;
+        let mut fluid_count: f32 = 0.0;
+        for (row, col) in cells {
+            match grid.cell_type[row][col] {
+                SimGridCellType::Solid => return None,
+                SimGridCellType::Fluid => {
+                    temperature_sum += grid.temperature[row * cols + col];
+                    fluid_count += 1.0;
+                }
+                SimGridCellType::Air | SimGridCellType::Porous(_) => {}
+            }
+        }
+        (fluid_count > 0.0).then(|| temperature_sum / fluid_count - average_temperature)
+    };
+
+    let acceleration_scale: f32 = -thermal_expansion * delta_time;
+    let mut u_changes: Vec<(usize, usize, f32)> = Vec::new();
+    for row in 0..rows {
+        for col in grid
+            .chunks
+            .active_spans(row, cols)
+            .flatten()
+            .filter(|col| *col > 0)
+        {
+            if let Some(difference) = face_temperature_difference([(row, col - 1), (row, col)]) {
+                u_changes.push((row, col, gravity.x * acceleration_scale * difference));
+            }
+        }
+    }
+    let mut v_changes: Vec<(usize, usize, f32)> = Vec::new();
+    for row in 1..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            if let Some(difference) = face_temperature_difference([(row - 1, col), (row, col)]) {
+                v_changes.push((row, col, gravity.y * acceleration_scale * difference));
+            }
+        }
+    }
+
+    for (row, col, change) in u_changes {
+        if grid.velocity_u[row][col] != f32::MIN {
+            grid.velocity_u[row][col] += change;
+        }
+    }
+    for (row, col, change) in v_changes {
+        if grid.velocity_v[row][col] != f32::MIN {
+            grid.velocity_v[row][col] += change;
+        }
+    }
+}
+
+/** Re-inject the small swirls that get smoothed away by transferring velocities between the
+particles and the grid.  Computes the curl at each cell's center, then pushes each fluid face
+around whichever nearby cells are spinning hardest (`strength * cell_size * (N x curl)`, where `N`
+points towards stronger swirling). */
+pub fn apply_vorticity_confinement(grid: &mut SimGrid, strength: f32, delta_time: f32) {
+    if strength <= 0.0 {
+        return;
+    }
+
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let index = |row: usize, col: usize| -> usize { row * cols + col };
+    let mut scratch = std::mem::take(&mut grid.scratch);
+
+    // Curl at every cell's center (in an active chunk).
+    let vorticity: &mut Vec<f32> = &mut scratch.vorticity;
+    vorticity.clear();
+    vorticity.resize(rows * cols, 0.0);
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            vorticity[index(row, col)] = grid.get_cell_vorticity(row, col);
+        }
+    }
+
+    /* Confinement force at the center of each fluid cell.  The gradient of the curl's magnitude
+    points towards the center of the swirl, so crossing it with the curl pushes along the swirl. */
+    let force: &mut Vec<Vec2> = &mut scratch.confinement_force;
+    force.clear();
+    force.resize(rows * cols, Vec2::ZERO);
+    let cell_size: f32 = grid.cell_size as f32;
+    for row in 0..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            if grid.cell_type[row][col] != SimGridCellType::Fluid {
+                continue;
+            }
+
+            let (up, down, left, right) = neighbor_cells(row, col, rows, cols);
+            let gradient: Vec2 = Vec2 {
+                x: vorticity[index(row, right)].abs() - vorticity[index(row, left)].abs(),
+                y: vorticity[index(up, col)].abs() - vorticity[index(down, col)].abs(),
+            };
+            if gradient.length() <= 1e-5 {
+                continue;
+            }
+
+            let normal: Vec2 = gradient.normalize();
+            let curl: f32 = vorticity[index(row, col)];
+            force[index(row, col)] =
+                strength * cell_size * Vec2::new(normal.y * curl, -normal.x * curl);
+        }
+    }
+
+    /* Apply the force to each face between two non-solid cells, at least one of which is fluid.
+    Horizontal faces sit between a cell and its left neighbor; vertical faces, its upper one. */
+    let is_open_face = |near: &SimGridCellType, far: &SimGridCellType| -> bool {
+        *near != SimGridCellType::Solid
+            && *far != SimGridCellType::Solid
+            && (*near == SimGridCellType::Fluid || *far == SimGridCellType::Fluid)
+    };
+    for row in 0..rows {
+        for col in grid
+            .chunks
+            .active_spans(row, cols)
+            .flatten()
+            .filter(|col| *col > 0)
+        {
+            if grid.velocity_u[row][col] == f32::MIN
+                || !is_open_face(&grid.cell_type[row][col], &grid.cell_type[row][col - 1])
+            {
+                continue;
+            }
+            let face_force: f32 = 0.5 * (force[index(row, col)].x + force[index(row, col - 1)].x);
+            grid.velocity_u[row][col] += face_force * delta_time;
+        }
+    }
+    for row in 1..rows {
+        for col in grid.chunks.active_spans(row, cols).flatten() {
+            if grid.velocity_v[row][col] == f32::MIN
+                || !is_open_face(&grid.cell_type[row][col], &grid.cell_type[row - 1][col])
+            {
+                continue;
+            }
+            let face_force: f32 = 0.5 * (force[index(row, col)].y + force[index(row - 1, col)].y);
+            grid.velocity_v[row][col] += face_force * delta_time;
+        }
+    }
+
+    grid.scratch = scratch;
+}
+
+/// Rows and columns of a cell's (up, down, left, right) neighbors, clamped to the grid.
+fn neighbor_cells(
+    row: usize,
+    col: usize,
+    rows: usize,
+    cols: usize,
+) -> (usize, usize, usize, usize) {
+    (
+        row.saturating_sub(1),
+        (row + 1).min(rows - 1),
+        col.saturating_sub(1),
+        (col + 1).min(cols - 1),
+    )
+}
+
+/** Force velocity incompressibility for each grid cell within the simulation, using whichever
+pressure solver the constraints ask for (the GPU one running on `gpu`, if there is one).  Returns
+how many solver iterations were run. */
+pub fn make_grid_velocities_incompressible(
+    grid: &mut SimGrid,
+    constraints: &mut SimConstraints,
+    gpu: Option<&mut SimGpu>,
+) -> u8 {
+    // Moving solids push the fluid around them; the solvers never touch solid faces, so this sticks.
+    apply_moving_solid_velocities(grid);
+    // The same goes for fluid being pushed in through inflow edges.
+    apply_inflow_velocities(constraints, grid);
+    // No-slip walls hold back the fluid sliding along them.
+    apply_no_slip_walls(grid, constraints.wall_slip);
+
+    /* Get the "particle rest density" for the simulation domain.  Density only builds up around
+    the fluid, so only the active cells need adding up; it's still averaged over the whole domain. */
+    let fluid_cell_count: f32 = grid.density.len() as f32;
     let mut density_sum: f32 = 0.0;
-    for i in 0..grid.density.len() {
-        density_sum += grid.density[i];
-        fluid_cell_count += 1.0;
+    for i in grid.active_cells.iter() {
+        density_sum += grid.density[*i];
     }
     if fluid_cell_count > 0.0 {
         constraints.particle_rest_density = density_sum / fluid_cell_count;
     }
 
-    // Allows the user to make the simulation go BRRRRRRR or brrr.
-    for _ in 0..constraints.incomp_iters_per_frame {
-        /* For each grid cell, calculate the inflow/outflow (divergence).  Then, find out how many
-        surrounding cells are solid and adjust grid velocities accordingly. */
-        for row in 0..grid.dimensions.0 {
-            for col in 0..grid.dimensions.1 {
-                // Don't process this cell if we are not inside of a fluid cell.
-                if grid.cell_type[row as usize][col as usize] != SimGridCellType::Fluid {
+    let iterations: u8 = match constraints.pressure_solver {
+        SimPressureSolver::GaussSeidel => solve_pressure_gauss_seidel(grid, constraints, true),
+        SimPressureSolver::ConjugateGradient => {
+            solve_pressure_conjugate_gradient(grid, constraints)
+        }
+        SimPressureSolver::Multigrid => solve_pressure_multigrid(grid, constraints),
+        SimPressureSolver::Gpu => {
+            // Without a GPU to run on (or if it fails us), the CPU steps in.
+            let iterations: Option<u8> = gpu.and_then(|gpu| gpu.solve_pressure(grid, constraints));
+            iterations.unwrap_or_else(|| solve_pressure_gauss_seidel(grid, constraints, true))
+        }
+    };
+
+    // Valves shut against any flow the solver sends through them the wrong way.
+    apply_valves(grid);
+
+    // Measure how much divergence the solver left behind, for the UI and telemetry.
+    let (max_divergence, rms_divergence) = calculate_divergence_residual(grid);
+    constraints.solver_residual = SimSolverResidual {
+        max_divergence,
+        rms_divergence,
+        iterations,
+    };
+
+    iterations
+}
+
+/** Force velocity incompressibility by relaxing each fluid cell in turn (the Gauss-Seidel method).
+Cells are relaxed in red-black order: colored so that a cell never shares a face with another of
+its color (see gauss_seidel_color()), every cell of one color can be relaxed at once.  With
+`parallel` set, each color's corrections are calculated across the compute task pool; the results
+are identical either way.  Stops once the divergence falls below `constraints.pressure_tolerance`
+(relative to the divergence going into the first iteration) or after
+`constraints.incomp_iters_per_frame` iterations, and returns how many it took. */
+pub fn solve_pressure_gauss_seidel(
+    grid: &mut SimGrid,
+    constraints: &SimConstraints,
+    parallel: bool,
+) -> u8 {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+
+    /* Borrow the scratch buffer (and the active cells, which are the only ones with any fluid to
+    relax) so the velocity grids can be mutated alongside them. */
+    let active_cells: Vec<usize> = std::mem::take(&mut grid.active_cells);
+    let mut corrections: Vec<[f32; 5]> = std::mem::take(&mut grid.scratch.pressure.corrections);
+    corrections.clear();
+    corrections.resize(active_cells.len(), [0.0; 5]);
+
+    // Pressure is worked out from scratch every solve, by adding up each cell's corrections.
+    for row in grid.cell_center.iter_mut() {
+        row.fill(0.0);
+    }
+
+    /* Allows the user to make the simulation go BRRRRRRR or brrr.  The divergence each cell had
+    right before it was relaxed is tracked along the way, so the solver knows when it can stop
+    without measuring the whole grid again. */
+    let mut iterations: u8 = 0;
+    let mut initial_residual: Option<f32> = None;
+    while iterations < constraints.incomp_iters_per_frame {
+        iterations += 1;
+        let mut residual: f32 = 0.0;
+        for color in 0..gauss_seidel_color_count(grid) {
+            let color_residual: f32 = if parallel {
+                let task_pool: &TaskPool = ComputeTaskPool::get_or_init(TaskPool::default);
+                let cells_per_task: usize = ((active_cells.len() + task_pool.thread_num() - 1)
+                    / task_pool.thread_num())
+                .max(1);
+                let grid: &SimGrid = grid;
+                task_pool
+                    .scope(|scope| {
+                        for (cells, band) in active_cells
+                            .chunks(cells_per_task)
+                            .zip(corrections.chunks_mut(cells_per_task))
+                        {
+                            scope.spawn(async move {
+                                calculate_cell_corrections(grid, constraints, color, cells, band)
+                            });
+                        }
+                    })
+                    .into_iter()
+                    .fold(0.0, f32::max)
+            } else {
+                calculate_cell_corrections(
+                    grid,
+                    constraints,
+                    color,
+                    &active_cells,
+                    &mut corrections,
+                )
+            };
+            residual = residual.max(color_residual);
+
+            // Each face borders only one cell of this color, so the order these are applied in doesn't matter.
+            for (index, &correction) in active_cells.iter().zip(corrections.iter()) {
+                let (row, col) = (index / cols, index % cols);
+                if gauss_seidel_color(grid, row, col) != color {
                     continue;
                 }
+                grid.cell_center[row][col] += correction[4];
+                grid.velocity_u[row][col] -= correction[0];
+                grid.velocity_u[row][col + 1] += correction[1];
+                grid.velocity_v[row][col] += correction[2];
+                grid.velocity_v[row + 1][col] -= correction[3];
+
+                // Faces on a wrapped edge have a copy on the opposite edge to keep in step.
+                if grid.wrap_horizontal && col == 0 {
+                    grid.velocity_u[row][cols] -= correction[0];
+                }
+                if grid.wrap_horizontal && col == cols - 1 {
+                    grid.velocity_u[row][0] += correction[1];
+                }
+                if grid.wrap_vertical && row == 0 {
+                    grid.velocity_v[rows][col] += correction[2];
+                }
+                if grid.wrap_vertical && row == rows - 1 {
+                    grid.velocity_v[0][col] -= correction[3];
+                }
+            }
+        }
 
-                // Calculate and sum the solid modifiers for each surrounding cell.
-                let solids: [u8; 5] = calculate_cell_solids(&grid, row as usize, col as usize);
-                let left_solid: u8 = solids[1];
-                let right_solid: u8 = solids[2];
-                let up_solid: u8 = solids[3];
-                let down_solid: u8 = solids[4];
-                let solids_sum: u8 = left_solid + right_solid + up_solid + down_solid;
+        let initial_residual: f32 = *initial_residual.get_or_insert(residual);
+        if residual <= constraints.pressure_tolerance * initial_residual {
+            break;
+        }
+    }
 
-                if solids_sum == 0 {
-                    continue;
-                } // else if solids_sum != 4 {
-                  // println!("Solids: {:?}, Position: {}, State: {:?}", solids, grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32)), grid.cell_type[row as usize][col as usize]);
-                  // }
-
-                // Determine the inflow/outflow of the current cell.
-                let mut divergence: f32 =
-                    calculate_cell_divergence(&grid, row as usize, col as usize);
-
-                /* Density calculations; will reduce jittering in high-density areas by negatively
-                increasing divergence, indicating there is too much inflow. */
-                if constraints.particle_rest_density > 0.0 {
-                    let stiffness: f32 = 1.0;
-                    let cell_coordinates: Vec2 = Vec2 {
-                        x: row as f32,
-                        y: col as f32,
-                    };
-                    let density: f32 = grid.density[grid.get_lookup_index(cell_coordinates)];
-                    let compression: f32 = density - constraints.particle_rest_density;
-                    if compression > 0.0 {
-                        divergence -= stiffness * compression;
-                    }
+    grid.scratch.pressure.corrections = corrections;
+    grid.active_cells = active_cells;
+    iterations
+}
+
+/// The most colors gauss_seidel_color_count() ever splits a grid's cells into.
+pub const GAUSS_SEIDEL_MAX_COLORS: usize = 8;
+
+/** How many colors solve_pressure_gauss_seidel() relaxes the grid's cells in.  Two, like a
+checkerboard, unless the grid wraps around an odd number of cells, where the cells along the last
+column and row need colors of their own. */
+pub fn gauss_seidel_color_count(grid: &SimGrid) -> usize {
+    let (odd_wrap_vertical, odd_wrap_horizontal) = odd_wraps(grid);
+    2 * (1 + odd_wrap_horizontal as usize) * (1 + odd_wrap_vertical as usize)
+}
+
+/** Which color a cell is relaxed with, so that no two cells of the same color share a face.  Cells
+are colored like a checkerboard (0 where row + column is even, 1 where it's odd), but across a
+wrapped edge with an odd number of cells between, the cells on either side would have the same
+color; the last column (and row) are given the next colors along instead. */
+pub fn gauss_seidel_color(grid: &SimGrid, row: usize, col: usize) -> usize {
+    let (odd_wrap_vertical, odd_wrap_horizontal) = odd_wraps(grid);
+    let last_col: bool = odd_wrap_horizontal && col == grid.dimensions.1 as usize - 1;
+    let last_row: bool = odd_wrap_vertical && row == grid.dimensions.0 as usize - 1;
+    (row + col) % 2
+        + 2 * (last_col as usize + (1 + odd_wrap_horizontal as usize) * last_row as usize)
+}
+
+/// Whether the grid wraps around an odd number of rows (vertically) and columns (horizontally).
+fn odd_wraps(grid: &SimGrid) -> (bool, bool) {
+    (
+        grid.wrap_vertical && grid.dimensions.0 % 2 == 1,
+        grid.wrap_horizontal && grid.dimensions.1 % 2 == 1,
+    )
+}
+
+/** Calculate how much each face of every fluid cell of one color (see gauss_seidel_color()) needs
+to change to make the cell incompressible.  `corrections` holds one entry
+per cell in `cells` (given by lookup index), in left, right, up, down order for each cell, followed
+by how much that raises the cell's pressure.  Returns the largest divergence any of those cells had. */
+fn calculate_cell_corrections(
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    color: usize,
+    cells: &[usize],
+    corrections: &mut [[f32; 5]],
+) -> f32 {
+    let cols: usize = grid.dimensions.1 as usize;
+    let mut max_divergence: f32 = 0.0;
+    for (index, correction) in cells.iter().zip(corrections.iter_mut()) {
+        let row: usize = index / cols;
+        let col: usize = index % cols;
+        *correction = [0.0; 5];
+
+        // Don't process this cell if it's another color or we are not inside of a fluid cell.
+        if gauss_seidel_color(grid, row, col) != color {
+            continue;
+        }
+        let Some(relaxation) =
+            calculate_cell_relaxation(grid, constraints.particle_rest_density, row, col)
+        else {
+            continue;
+        };
+
+        // Determine the inflow/outflow of the current cell, counting over-compression as inflow.
+        let divergence: f32 = calculate_cell_divergence(grid, row, col) - relaxation.compression;
+        max_divergence = max_divergence.max(divergence.abs());
+
+        // Force incompressibility on this cell.
+        if relaxation.weight_sum <= 0.0 {
+            continue;
+        }
+        let momentum: f32 =
+            GAUSS_SEIDEL_OVERRELAXATION * ((0.0 - divergence) / relaxation.weight_sum);
+        let [left, right, up, down] = relaxation.face_weights.map(|weight| momentum * weight);
+        *correction = [left, right, up, down, momentum];
+    }
+
+    max_divergence
+}
+
+/** Everything relaxing a cell depends on besides its faces' velocities, none of which changes
+during a pressure solve. */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimCellRelaxation {
+    pub face_flows: [f32; 4], // How much of each face's velocity counts towards the divergence.
+    pub face_weights: [f32; 4], // Share of the correction each face takes.
+    pub weight_sum: f32,      // Sum of each face's weight times its flow.
+    pub compression: f32,     // Over-compression counted as extra inflow.
+}
+
+/** Work out how a fluid cell is relaxed, or None if the solver leaves it alone (it isn't fluid, or
+is walled in on every side).  Each open face takes a share of the correction inversely proportional
+to the density of the fluid around it, so heavy fluids resist being pushed around and sink below
+lighter ones.  Faces partly covered by a wall move less fluid, so they count for less when working
+out how hard to push. */
+pub fn calculate_cell_relaxation(
+    grid: &SimGrid,
+    rest_density: f32,
+    row: usize,
+    col: usize,
+) -> Option<SimCellRelaxation> {
+    if grid.cell_type[row][col] != SimGridCellType::Fluid {
+        return None;
+    }
+
+    // Calculate and sum the solid modifiers for each surrounding cell.
+    let solids: [f32; 5] = calculate_cell_solids(grid, row, col);
+    let solids_sum: f32 = solids[1] + solids[2] + solids[3] + solids[4];
+    if solids_sum == 0.0 {
+        return None;
+    }
+
+    let face_weights: [f32; 4] = calculate_face_weights(grid, row, col);
+    let face_flows: [f32; 4] = calculate_face_flows(grid, row, col);
+    let weight_sum: f32 = (0..4)
+        .map(|face| face_weights[face] * face_flows[face])
+        .sum();
+    Some(SimCellRelaxation {
+        face_flows,
+        face_weights,
+        weight_sum,
+        compression: calculate_cell_compression(grid, rest_density, row, col),
+    })
+}
+
+/** Zero out every face velocity flowing through a one-way valve cell against its direction.  Only
+the two faces along the valve's axis are checked, so fluid can still slosh sideways within the
+valve. */
+pub fn apply_valves(grid: &mut SimGrid) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    for row in 0..rows {
+        for col in 0..cols {
+            let Some(direction) = grid.get_valve(row, col) else {
+                continue;
+            };
+            if grid.cell_type[row][col] == SimGridCellType::Solid {
+                continue;
+            }
+
+            // The valve's two faces along its axis, as (whether the face is horizontal, row, column).
+            let allowed: Vec2 = direction.as_vector();
+            let faces: [(bool, usize, usize); 2] = if allowed.x != 0.0 {
+                [(true, row, col), (true, row, col + 1)]
+            } else {
+                [(false, row, col), (false, row + 1, col)]
+            };
+
+            // Faces without a velocity (f32::MIN) are left for extrapolation to fill in.
+            let sign: f32 = allowed.x + allowed.y;
+            for (horizontal, face_row, face_col) in faces {
+                let face: &mut f32 = if horizontal {
+                    &mut grid.velocity_u[face_row][face_col]
+                } else {
+                    &mut grid.velocity_v[face_row][face_col]
+                };
+                if *face != f32::MIN && *face * sign < 0.0 {
+                    *face = 0.0;
                 }
+            }
+        }
+    }
+}
+
+/** Slow down the flow through every face touching a porous cell.  Faces lose
+`1 - permeability` of their velocity every `1 / POROUS_DRAG_RATE` seconds, going by the less
+permeable cell on either side. */
+pub fn apply_porous_drag(grid: &mut SimGrid, delta_time: f32) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let face_drag = |grid: &SimGrid, cell: (usize, usize), neighbor: (usize, usize)| -> f32 {
+        let permeability: f32 = grid
+            .get_cell_type_value(cell.0, cell.1)
+            .min(grid.get_cell_type_value(neighbor.0, neighbor.1));
+        if permeability <= 0.0 {
+            return 1.0;
+        }
+        permeability.powf(delta_time * POROUS_DRAG_RATE)
+    };
+
+    for row in 0..rows {
+        for col in 0..=cols {
+            let drag: f32 = face_drag(grid, (row, usize::wrapping_sub(col, 1)), (row, col));
+            if drag < 1.0 && grid.velocity_u[row][col] != f32::MIN {
+                grid.velocity_u[row][col] *= drag;
+            }
+        }
+    }
+    for row in 0..=rows {
+        for col in 0..cols {
+            let drag: f32 = face_drag(grid, (usize::wrapping_sub(row, 1), col), (row, col));
+            if drag < 1.0 && grid.velocity_v[row][col] != f32::MIN {
+                grid.velocity_v[row][col] *= drag;
+            }
+        }
+    }
+}
+
+/** Set every face between a moving solid (such as a spinner's blade) and an open cell to the solid's
+velocity, so the fluid on the other side of the face is pushed along with it. */
+fn apply_moving_solid_velocities(grid: &mut SimGrid) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let is_open = |grid: &SimGrid, row: usize, col: usize| -> bool {
+        row < rows && col < cols && grid.cell_type[row][col] != SimGridCellType::Solid
+    };
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let Some(velocity) = grid.get_moving_solid_velocity(row, col) else {
+                continue;
+            };
 
-                // Force incompressibility on this cell.
-                let overrelaxation: f32 = 1.99;
-                let momentum: f32 = overrelaxation * ((0.0 - divergence) / solids_sum as f32);
+            if is_open(grid, row, usize::wrapping_sub(col, 1)) {
+                grid.velocity_u[row][col] = velocity.x;
+            }
+            if is_open(grid, row, col + 1) {
+                grid.velocity_u[row][col + 1] = velocity.x;
+            }
+            if is_open(grid, usize::wrapping_sub(row, 1), col) {
+                grid.velocity_v[row][col] = velocity.y;
+            }
+            if is_open(grid, row + 1, col) {
+                grid.velocity_v[row + 1][col] = velocity.y;
+            }
+        }
+    }
+}
+
+/** Bring fluid sliding along no-slip walls to a stop (or up to speed with the wall, for spinner
+blades).  Only the faces running alongside a wall are touched; which walls are no-slip is up to each
+wall, falling back to `default_slip` for walls that haven't been given a slip condition. */
+pub fn apply_no_slip_walls(grid: &mut SimGrid, default_slip: SimWallSlip) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+
+    /* Average velocity of the no-slip walls among `cells`, which lie alongside a face; None if
+    none of them are no-slip walls. */
+    let wall_velocity = |grid: &SimGrid, cells: [(usize, usize); 4]| -> Option<Vec2> {
+        let mut velocity_sum: Vec2 = Vec2::ZERO;
+        let mut wall_count: f32 = 0.0;
+        for (row, col) in cells {
+            if row >= rows
+                || col >= cols
+                || grid.cell_type[row][col] != SimGridCellType::Solid
+                || grid.get_wall_slip(row, col, default_slip) != SimWallSlip::NoSlip
+            {
+                continue;
+            }
+            velocity_sum += grid
+                .get_moving_solid_velocity(row, col)
+                .unwrap_or(Vec2::ZERO);
+            wall_count += 1.0;
+        }
+        (wall_count > 0.0).then(|| velocity_sum / wall_count)
+    };
+    // Whether the face between two cells carries fluid along it.
+    let carries_fluid = |grid: &SimGrid, a: (usize, usize), b: (usize, usize)| -> bool {
+        let (a_type, b_type) = (&grid.cell_type[a.0][a.1], &grid.cell_type[b.0][b.1]);
+        *a_type != SimGridCellType::Solid
+            && *b_type != SimGridCellType::Solid
+            && (*a_type == SimGridCellType::Fluid || *b_type == SimGridCellType::Fluid)
+    };
 
-                grid.velocity_u[row as usize][col as usize] -= momentum * left_solid as f32;
-                grid.velocity_u[row as usize][(col + 1) as usize] += momentum * right_solid as f32;
-                grid.velocity_v[row as usize][col as usize] += momentum * up_solid as f32;
-                grid.velocity_v[(row + 1) as usize][col as usize] -= momentum * down_solid as f32;
+    // Horizontal flow along walls above and below.
+    for row in 0..rows {
+        for col in 1..cols {
+            if !carries_fluid(grid, (row, col - 1), (row, col)) {
+                continue;
+            }
+            let (above, below) = (usize::wrapping_sub(row, 1), row + 1);
+            let alongside = [
+                (above, col - 1),
+                (above, col),
+                (below, col - 1),
+                (below, col),
+            ];
+            if let Some(velocity) = wall_velocity(grid, alongside) {
+                grid.velocity_u[row][col] = velocity.x;
+            }
+        }
+    }
 
-                // grid.velocity_u[row as usize][col as usize]			*= left_solid as f32;
-                // grid.velocity_u[row as usize][(col + 1) as usize]	*= right_solid as f32;
-                // grid.velocity_v[row as usize][col as usize]			*= up_solid as f32;
-                // grid.velocity_v[(row + 1) as usize][col as usize]	*= down_solid as f32;
+    // Vertical flow along walls to the left and right.
+    for row in 1..rows {
+        for col in 0..cols {
+            if !carries_fluid(grid, (row - 1, col), (row, col)) {
+                continue;
+            }
+            let (left, right) = (usize::wrapping_sub(col, 1), col + 1);
+            let alongside = [(row - 1, left), (row, left), (row - 1, right), (row, right)];
+            if let Some(velocity) = wall_velocity(grid, alongside) {
+                grid.velocity_v[row][col] = velocity.y;
             }
         }
     }
 }
 
+/** Density calculations; will reduce jittering in high-density areas by treating the amount a cell
+is denser than the rest density as extra inflow.  Returns 0.0 for cells that aren't over-compressed. */
+pub fn calculate_cell_compression(
+    grid: &SimGrid,
+    rest_density: f32,
+    cell_row: usize,
+    cell_col: usize,
+) -> f32 {
+    if rest_density <= 0.0 {
+        return 0.0;
+    }
+
+    let stiffness: f32 = 1.0;
+    let cell_coordinates: Vec2 = Vec2 {
+        x: cell_row as f32,
+        y: cell_col as f32,
+    };
+    let density: f32 = grid.density[grid.get_lookup_index(cell_coordinates)];
+    let compression: f32 = density - rest_density;
+    stiffness * compression.max(0.0)
+}
+
+/// Find the largest divergence (by magnitude) of any fluid cell in the grid.
+pub fn calculate_max_divergence(grid: &SimGrid) -> f32 {
+    let mut max_divergence: f32 = 0.0;
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            if grid.cell_type[row][col] != SimGridCellType::Fluid {
+                continue;
+            }
+            max_divergence = max_divergence.max(calculate_cell_divergence(grid, row, col).abs());
+        }
+    }
+
+    max_divergence
+}
+
+/// Find the largest and the root mean square divergence left in the grid's fluid cells.
+pub fn calculate_divergence_residual(grid: &SimGrid) -> (f32, f32) {
+    let cols: usize = grid.dimensions.1 as usize;
+    let mut max_divergence: f32 = 0.0;
+    let mut squared_sum: f32 = 0.0;
+    let mut fluid_cell_count: usize = 0;
+    for index in grid.active_cells.iter() {
+        let (row, col) = (index / cols, index % cols);
+        if grid.cell_type[row][col] != SimGridCellType::Fluid {
+            continue;
+        }
+        let divergence: f32 = calculate_cell_divergence(grid, row, col);
+        max_divergence = max_divergence.max(divergence.abs());
+        squared_sum += divergence * divergence;
+        fluid_cell_count += 1;
+    }
+
+    if fluid_cell_count == 0 {
+        return (0.0, 0.0);
+    }
+    (
+        max_divergence,
+        (squared_sum / fluid_cell_count as f32).sqrt(),
+    )
+}
+
 /** Calculate the divergence (inflow/outflow) of a grid cell.  If this number is not zero, then
 the fluid must be made incompressible.  **A negative divergence indicates there is too much
-inflow, whereas a positive divergence indicates too much outflow.** */
-fn calculate_cell_divergence(grid: &SimGrid, cell_row: usize, cell_col: usize) -> f32 {
+inflow, whereas a positive divergence indicates too much outflow.**  Flow through each face is
+scaled by how open the face is (see calculate_face_fraction). */
+pub fn calculate_cell_divergence(grid: &SimGrid, cell_row: usize, cell_col: usize) -> f32 {
     /* Retrieve velocities for each face of the current cell.  Note: this will not go out of
     bounds of the velocity arrays; each array is guaranteed to have sufficient space allocated
     to index like this. */
-    let left_velocity: f32 = grid.velocity_u[cell_row][cell_col];
-    let right_velocity: f32 = grid.velocity_u[cell_row][cell_col + 1];
-    let up_velocity: f32 = grid.velocity_v[cell_row][cell_col];
-    let down_velocity: f32 = grid.velocity_v[cell_row + 1][cell_col];
+    let flows: [f32; 4] = calculate_face_flows(grid, cell_row, cell_col);
+    let left_velocity: f32 = grid.velocity_u[cell_row][cell_col] * flows[0];
+    let right_velocity: f32 = grid.velocity_u[cell_row][cell_col + 1] * flows[1];
+    let up_velocity: f32 = grid.velocity_v[cell_row][cell_col] * flows[2];
+    let down_velocity: f32 = grid.velocity_v[cell_row + 1][cell_col] * flows[3];
 
     // BUG: The up and down flows may need to be reversed.
     let x_divergence: f32 = right_velocity - left_velocity;
@@ -812,16 +2246,107 @@ fn calculate_cell_divergence(grid: &SimGrid, cell_row: usize, cell_col: usize) -
     divergence
 }
 
-/** Returns the cell solid modifiers (0 for solid, 1 otherwise) for cells in the order of: center,
-left, right, up, down. **/
-fn calculate_cell_solids(grid: &SimGrid, cell_row: usize, cell_col: usize) -> [u8; 5] {
+/** Returns how much of each face's velocity counts towards a cell's divergence, in the order of:
+left, right, up, down.  Open faces count as much of their velocity as they are open.  Faces against
+a solid cell carry the solid's own velocity (zero for walls), which counts in full. */
+fn calculate_face_flows(grid: &SimGrid, cell_row: usize, cell_col: usize) -> [f32; 4] {
+    calculate_neighbor_cells(cell_row, cell_col).map(|neighbor| {
+        if grid.get_cell_type_value(cell_row, cell_col) == 0.0
+            || grid.get_cell_type_value(neighbor.0, neighbor.1) == 0.0
+        {
+            1.0
+        } else {
+            calculate_face_fraction(grid, (cell_row, cell_col), neighbor)
+        }
+    })
+}
+
+/// Returns the coordinates of a cell's neighbors, in the order of: left, right, up, down.
+fn calculate_neighbor_cells(cell_row: usize, cell_col: usize) -> [(usize, usize); 4] {
+    [
+        (cell_row, usize::wrapping_sub(cell_col, 1)),
+        (cell_row, cell_col + 1),
+        (usize::wrapping_sub(cell_row, 1), cell_col),
+        (cell_row + 1, cell_col),
+    ]
+}
+
+/** Returns how open the face between two neighboring cells is: 0 if either cell is solid (or off
+a closed edge of the grid), otherwise however much of the face the walls' smoothed outline leaves uncovered. */
+pub fn calculate_face_fraction(
+    grid: &SimGrid,
+    cell: (usize, usize),
+    neighbor: (usize, usize),
+) -> f32 {
+    if grid.get_cell_type_value(cell.0, cell.1) == 0.0
+        || grid.get_cell_type_value(neighbor.0, neighbor.1) == 0.0
+    {
+        return 0.0;
+    }
+
+    let solid_fraction: f32 = (grid.get_cell_solid_fraction(cell.0, cell.1)
+        + grid.get_cell_solid_fraction(neighbor.0, neighbor.1))
+        * 0.5;
+    (1.0 - solid_fraction).clamp(0.0, 1.0)
+}
+
+/** Returns how much of a cell's pressure correction each of its faces takes, in the order of: left,
+right, up, down.  Solid faces take none; open faces are weighted by the inverse of the average
+material density of the two cells they separate, so a uniform fluid splits the correction evenly. */
+fn calculate_face_weights(grid: &SimGrid, cell_row: usize, cell_col: usize) -> [f32; 4] {
+    [
+        calculate_face_weight(
+            grid,
+            (cell_row, cell_col),
+            (cell_row, usize::wrapping_sub(cell_col, 1)),
+        ),
+        calculate_face_weight(grid, (cell_row, cell_col), (cell_row, cell_col + 1)),
+        calculate_face_weight(
+            grid,
+            (cell_row, cell_col),
+            (usize::wrapping_sub(cell_row, 1), cell_col),
+        ),
+        calculate_face_weight(grid, (cell_row, cell_col), (cell_row + 1, cell_col)),
+    ]
+}
+
+/** Returns how easily pressure pushes fluid across the face between two neighboring cells: 0 if
+either cell is solid (or off a closed edge of the grid), otherwise the inverse of their average
+material density, scaled down by how permeable the less permeable of the two cells is. */
+pub fn calculate_face_weight(
+    grid: &SimGrid,
+    cell: (usize, usize),
+    neighbor: (usize, usize),
+) -> f32 {
+    if grid.get_cell_type_value(cell.0, cell.1) == 0.0
+        || grid.get_cell_type_value(neighbor.0, neighbor.1) == 0.0
+    {
+        return 0.0;
+    }
+    // Past an open edge, the fluid is taken to be the same as the cell's own.
+    let neighbor: (usize, usize) = grid.wrap_cell(neighbor.0, neighbor.1).unwrap_or(cell);
+
+    let cell_density: f32 = grid
+        .get_cell_material_density(grid.get_lookup_index(Vec2::new(cell.0 as f32, cell.1 as f32)));
+    let neighbor_density: f32 = grid.get_cell_material_density(
+        grid.get_lookup_index(Vec2::new(neighbor.0 as f32, neighbor.1 as f32)),
+    );
+    let permeability: f32 = grid
+        .get_cell_type_value(cell.0, cell.1)
+        .min(grid.get_cell_type_value(neighbor.0, neighbor.1));
+    permeability * 2.0 / (cell_density + neighbor_density)
+}
+
+/** Returns the cell solid modifiers (0 for solid, 1 for open, and in between for porous) for cells
+in the order of: center, left, right, up, down. **/
+fn calculate_cell_solids(grid: &SimGrid, cell_row: usize, cell_col: usize) -> [f32; 5] {
     /* Calculate collision modifiers for each cell face.  Note that we must perform a wrapping
     subtraction to prevent an underflow for our usize types. */
-    let collision_center: u8 = grid.get_cell_type_value(cell_row, cell_col);
-    let collision_left: u8 = grid.get_cell_type_value(cell_row, usize::wrapping_sub(cell_col, 1));
-    let collision_right: u8 = grid.get_cell_type_value(cell_row, cell_col + 1);
-    let collision_up: u8 = grid.get_cell_type_value(usize::wrapping_sub(cell_row, 1), cell_col);
-    let collision_down: u8 = grid.get_cell_type_value(cell_row + 1, cell_col);
+    let collision_center: f32 = grid.get_cell_type_value(cell_row, cell_col);
+    let collision_left: f32 = grid.get_cell_type_value(cell_row, usize::wrapping_sub(cell_col, 1));
+    let collision_right: f32 = grid.get_cell_type_value(cell_row, cell_col + 1);
+    let collision_up: f32 = grid.get_cell_type_value(usize::wrapping_sub(cell_row, 1), cell_col);
+    let collision_down: f32 = grid.get_cell_type_value(cell_row + 1, cell_col);
 
     [
         collision_center,
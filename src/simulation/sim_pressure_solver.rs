@@ -0,0 +1,684 @@
+use bevy::prelude::*;
+
+use super::sim_physics_engine::{
+    calculate_cell_compression, calculate_cell_divergence, calculate_face_fraction,
+    calculate_face_weight,
+};
+use super::{SimConstraints, SimGrid, SimGridCellType};
+
+pub const PRESSURE_SOLVER_COUNT: usize = 4;
+
+/// Method used to make the fluid's velocities incompressible.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum SimPressureSolver {
+    #[default]
+    GaussSeidel = 0,
+    ConjugateGradient,
+    Multigrid,
+    Gpu,
+}
+
+impl Into<SimPressureSolver> for usize {
+    fn into(self) -> SimPressureSolver {
+        match self {
+            0 => SimPressureSolver::GaussSeidel,
+            1 => SimPressureSolver::ConjugateGradient,
+            2 => SimPressureSolver::Multigrid,
+            3 => SimPressureSolver::Gpu,
+            _ => {
+                eprintln!("Invalid SimPressureSolver; defaulting to GaussSeidel!");
+                SimPressureSolver::GaussSeidel
+            }
+        }
+    }
+}
+
+impl SimPressureSolver {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GaussSeidel => "Gauss-Seidel",
+            Self::ConjugateGradient => "Conjugate Gradient",
+            Self::Multigrid => "Multigrid",
+            Self::Gpu => "Gauss-Seidel (GPU)",
+        }
+    }
+}
+
+/// How much divergence the last pressure solve left behind, and how hard it had to work.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimSolverResidual {
+    pub max_divergence: f32, // Largest divergence left in a fluid cell.
+    pub rms_divergence: f32, // Root mean square divergence over every fluid cell.
+    pub iterations: u8,      // Iterations (or V-cycles) the solver ran.
+}
+
+/** Working memory for the pressure solvers; one value per grid cell, indexed by row * cols + col.
+Kept between steps to avoid per-frame allocations. */
+#[derive(Clone, Default)]
+pub struct SimPressureScratch {
+    pub matrix: SimPressureMatrix, // Pressure equations for the simulation grid.
+    // Gauss-Seidel's change to each cell's left, right, up, and down faces, then to its pressure.
+    pub corrections: Vec<[f32; 5]>,
+    pub pressure: Vec<f32>,                // Pressure of each fluid cell.
+    pub residual: Vec<f32>,                // Divergence left over by the current pressure guess.
+    pub auxiliary: Vec<f32>,               // Preconditioned residual.
+    pub search: Vec<f32>,                  // Direction the next pressure guess is searched along.
+    pub preconditioner: Vec<f32>,          // Incomplete Cholesky factor's diagonal.
+    pub multigrid: Vec<SimMultigridLevel>, // Ever coarser copies of the grid, for multigrid.
+}
+
+/** Pressure equations for a grid of cells: how much each cell's divergence changes for a given set
+of pressures.  Couplings are symmetric, so only the ones to the right and below are stored.  Cells
+that aren't fluid have no equation (and zero pressure). */
+#[derive(Clone, Default)]
+pub struct SimPressureMatrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub diagonal: Vec<f32>, // How strongly each cell's pressure affects its own divergence.
+    pub coupling_right: Vec<f32>, // How strongly each cell is coupled to its right neighbor.
+    pub coupling_down: Vec<f32>, // How strongly each cell is coupled to the neighbor below it.
+    /* Whether the grid wraps around; if so, the rightmost (bottom) cells' couplings reach around to
+    the leftmost (top) cells. */
+    pub wrap_horizontal: bool,
+    pub wrap_vertical: bool,
+}
+
+impl SimPressureMatrix {
+    /// Clear out the matrix, resizing it for a grid of `rows` x `cols` cells.
+    fn reset(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        for buffer in [
+            &mut self.diagonal,
+            &mut self.coupling_right,
+            &mut self.coupling_down,
+        ] {
+            buffer.clear();
+            buffer.resize(rows * cols, 0.0);
+        }
+    }
+
+    /// Compute `result = A * vector`.
+    fn multiply(&self, vector: &[f32], result: &mut [f32]) {
+        for index in 0..self.rows * self.cols {
+            result[index] = self.diagonal[index] * vector[index] + self.neighbor_sum(vector, index);
+        }
+    }
+
+    /// Sum of the couplings between a cell and its neighbors, times the neighbors' values.
+    fn neighbor_sum(&self, vector: &[f32], index: usize) -> f32 {
+        let (rows, cols) = (self.rows, self.cols);
+        let (row, col) = (index / cols, index % cols);
+
+        let mut sum: f32 = 0.0;
+        if col > 0 {
+            sum += self.coupling_right[index - 1] * vector[index - 1];
+        } else if self.wrap_horizontal {
+            let left: usize = index + cols - 1;
+            sum += self.coupling_right[left] * vector[left];
+        }
+        if col + 1 < cols {
+            sum += self.coupling_right[index] * vector[index + 1];
+        } else if self.wrap_horizontal {
+            sum += self.coupling_right[index] * vector[index + 1 - cols];
+        }
+        if row > 0 {
+            sum += self.coupling_down[index - cols] * vector[index - cols];
+        } else if self.wrap_vertical {
+            let up: usize = index + (rows - 1) * cols;
+            sum += self.coupling_down[up] * vector[up];
+        }
+        if row + 1 < rows {
+            sum += self.coupling_down[index] * vector[index + cols];
+        } else if self.wrap_vertical {
+            sum += self.coupling_down[index] * vector[col];
+        }
+        sum
+    }
+
+    /// Compute `residual = rhs - A * pressure`.
+    fn calculate_residual(&self, rhs: &[f32], pressure: &[f32], residual: &mut [f32]) {
+        self.multiply(pressure, residual);
+        for index in 0..self.rows * self.cols {
+            residual[index] = rhs[index] - residual[index];
+        }
+    }
+
+    /// Relax `A * pressure = rhs` with a few Gauss-Seidel sweeps.
+    fn relax(&self, rhs: &[f32], pressure: &mut [f32], sweeps: usize) {
+        for _ in 0..sweeps {
+            for index in 0..self.rows * self.cols {
+                if self.diagonal[index] <= 0.0 {
+                    continue;
+                }
+                pressure[index] =
+                    (rhs[index] - self.neighbor_sum(pressure, index)) / self.diagonal[index];
+            }
+        }
+    }
+}
+
+/// One level of the multigrid hierarchy.
+#[derive(Clone, Default)]
+pub struct SimMultigridLevel {
+    pub cell_type: Vec<SimGridCellType>,
+    pub matrix: SimPressureMatrix,
+    pub rhs: Vec<f32>, // Divergence to cancel out (or, on coarse levels, residual to correct).
+    pub pressure: Vec<f32>, // Pressure (or, on coarse levels, pressure correction).
+    pub residual: Vec<f32>, // What's left of the right-hand side after relaxing.
+}
+
+/// Residual divergence (relative to the largest initial divergence) the solvers settle for by default.
+pub const DEFAULT_PRESSURE_TOLERANCE: f32 = 1e-5;
+/// How much of the modified incomplete Cholesky correction to use; 0.0 is plain incomplete Cholesky.
+const MIC_TUNING: f32 = 0.97;
+/// Fall back to the unfactored diagonal wherever the factorization gets this close to breaking down.
+const MIC_SAFETY: f32 = 0.25;
+/// Multigrid stops coarsening once a level is this small in either direction.
+const MULTIGRID_MIN_SIZE: usize = 4;
+/// Gauss-Seidel sweeps on the way down and back up each multigrid level.
+const MULTIGRID_SMOOTHING_SWEEPS: usize = 2;
+/// Gauss-Seidel sweeps used to solve the coarsest multigrid level.
+const MULTIGRID_COARSEST_SWEEPS: usize = 30;
+
+/** Build the pressure equations for every fluid cell in the grid: the change in a fluid cell's
+divergence is the sum, over its open faces, of the face's weight times the pressure difference
+across it.  Air cells have zero pressure, and solid faces are left alone.  The right-hand side
+cancels out each cell's current divergence (counting over-compression as inflow).  The air past
+an open edge has zero pressure too.  Returns the largest right-hand side value, by magnitude. */
+fn build_pressure_equations(
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    matrix: &mut SimPressureMatrix,
+    rhs: &mut Vec<f32>,
+) -> f32 {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    matrix.reset(rows, cols);
+    matrix.wrap_horizontal = grid.wrap_horizontal;
+    matrix.wrap_vertical = grid.wrap_vertical;
+    rhs.clear();
+    rhs.resize(rows * cols, 0.0);
+
+    let mut max_rhs: f32 = 0.0;
+    for &index in grid.active_cells.iter() {
+        let (row, col) = (index / cols, index % cols);
+        if grid.cell_type[row][col] != SimGridCellType::Fluid {
+            continue;
+        }
+
+        let neighbors: [(usize, usize); 4] = [
+            (row, usize::wrapping_sub(col, 1)),
+            (row, col + 1),
+            (usize::wrapping_sub(row, 1), col),
+            (row + 1, col),
+        ];
+        // Faces partly covered by a wall carry less flow, so they couple cells more weakly.
+        let coefficients: [f32; 4] = neighbors.map(|neighbor| {
+            calculate_face_weight(grid, (row, col), neighbor)
+                * calculate_face_fraction(grid, (row, col), neighbor)
+        });
+        matrix.diagonal[index] = coefficients.iter().sum();
+        // The rightmost and bottom cells of a wrapped grid are coupled to the opposite edge.
+        let is_fluid = |neighbor: Option<(usize, usize)>| -> bool {
+            neighbor.is_some_and(|(row, col)| grid.cell_type[row][col] == SimGridCellType::Fluid)
+        };
+        if is_fluid(grid.wrap_cell(row, col + 1)) {
+            matrix.coupling_right[index] = -coefficients[1];
+        }
+        if is_fluid(grid.wrap_cell(row + 1, col)) {
+            matrix.coupling_down[index] = -coefficients[3];
+        }
+
+        rhs[index] = calculate_cell_compression(grid, constraints.particle_rest_density, row, col)
+            - calculate_cell_divergence(grid, row, col);
+        max_rhs = max_rhs.max(rhs[index].abs());
+    }
+
+    max_rhs
+}
+
+/** Force velocity incompressibility by solving for every fluid cell's pressure at once with the
+conjugate gradient method, preconditioned with a modified incomplete Cholesky factorization.  Stops
+once the divergence falls below `constraints.pressure_tolerance` (relative to where it started) or
+after `constraints.incomp_iters_per_frame` iterations, and returns how many it took. */
+pub fn solve_pressure_conjugate_gradient(grid: &mut SimGrid, constraints: &SimConstraints) -> u8 {
+    let cell_count: usize = grid.dimensions.0 as usize * grid.dimensions.1 as usize;
+
+    // Borrow the scratch buffers so the velocity grids can be mutated alongside them.
+    let mut scratch = std::mem::take(&mut grid.scratch.pressure);
+    let max_residual: f32 = build_pressure_equations(
+        grid,
+        constraints,
+        &mut scratch.matrix,
+        &mut scratch.residual,
+    );
+    for buffer in [
+        &mut scratch.pressure,
+        &mut scratch.auxiliary,
+        &mut scratch.search,
+        &mut scratch.preconditioner,
+    ] {
+        buffer.clear();
+        buffer.resize(cell_count, 0.0);
+    }
+
+    let mut iterations: u8 = 0;
+    if max_residual > 0.0 {
+        let tolerance: f32 = constraints.pressure_tolerance * max_residual;
+        build_preconditioner(&mut scratch);
+        apply_preconditioner(&mut scratch);
+        scratch.search.clone_from(&scratch.auxiliary);
+        let mut sigma: f32 = dot(&scratch.auxiliary, &scratch.residual);
+
+        while iterations < constraints.incomp_iters_per_frame {
+            iterations += 1;
+
+            // Reuse the auxiliary buffer to hold A * search while stepping along the search direction.
+            scratch
+                .matrix
+                .multiply(&scratch.search, &mut scratch.auxiliary);
+            let search_curvature: f32 = dot(&scratch.auxiliary, &scratch.search);
+            if search_curvature <= 0.0 {
+                break;
+            }
+            let alpha: f32 = sigma / search_curvature;
+            for index in 0..cell_count {
+                scratch.pressure[index] += alpha * scratch.search[index];
+                scratch.residual[index] -= alpha * scratch.auxiliary[index];
+            }
+            if scratch
+                .residual
+                .iter()
+                .all(|residual| residual.abs() <= tolerance)
+            {
+                break;
+            }
+
+            // Pick the next search direction, conjugate to all of the previous ones.
+            apply_preconditioner(&mut scratch);
+            let next_sigma: f32 = dot(&scratch.auxiliary, &scratch.residual);
+            let beta: f32 = next_sigma / sigma;
+            for index in 0..cell_count {
+                scratch.search[index] = scratch.auxiliary[index] + beta * scratch.search[index];
+            }
+            sigma = next_sigma;
+        }
+
+        apply_pressure(grid, &scratch.pressure);
+    }
+
+    grid.scratch.pressure = scratch;
+    iterations
+}
+
+/// Sum of the element-wise products of two equally sized buffers.
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+/// Factor the pressure matrix with a modified incomplete Cholesky decomposition, MIC(0).
+fn build_preconditioner(scratch: &mut SimPressureScratch) {
+    let matrix: &SimPressureMatrix = &scratch.matrix;
+    let cols: usize = matrix.cols;
+    for index in 0..matrix.rows * cols {
+        if matrix.diagonal[index] <= 0.0 {
+            continue;
+        }
+
+        let mut e: f32 = matrix.diagonal[index];
+        if index % cols > 0 {
+            let left: usize = index - 1;
+            let coupling: f32 = matrix.coupling_right[left] * scratch.preconditioner[left];
+            e -= coupling * coupling;
+            e -= MIC_TUNING
+                * matrix.coupling_right[left]
+                * matrix.coupling_down[left]
+                * scratch.preconditioner[left].powi(2);
+        }
+        if index >= cols {
+            let up: usize = index - cols;
+            let coupling: f32 = matrix.coupling_down[up] * scratch.preconditioner[up];
+            e -= coupling * coupling;
+            e -= MIC_TUNING
+                * matrix.coupling_down[up]
+                * matrix.coupling_right[up]
+                * scratch.preconditioner[up].powi(2);
+        }
+
+        if e < MIC_SAFETY * matrix.diagonal[index] {
+            e = matrix.diagonal[index];
+        }
+        scratch.preconditioner[index] = 1.0 / e.sqrt();
+    }
+}
+
+/// Apply the preconditioner to the residual, storing the result in the auxiliary buffer.
+fn apply_preconditioner(scratch: &mut SimPressureScratch) {
+    let matrix: &SimPressureMatrix = &scratch.matrix;
+    let (rows, cols) = (matrix.rows, matrix.cols);
+
+    // Solve L * q = residual, storing q in the auxiliary buffer...
+    for index in 0..rows * cols {
+        if matrix.diagonal[index] <= 0.0 {
+            scratch.auxiliary[index] = 0.0;
+            continue;
+        }
+
+        let mut t: f32 = scratch.residual[index];
+        if index % cols > 0 {
+            let left: usize = index - 1;
+            t -= matrix.coupling_right[left]
+                * scratch.preconditioner[left]
+                * scratch.auxiliary[left];
+        }
+        if index >= cols {
+            let up: usize = index - cols;
+            t -= matrix.coupling_down[up] * scratch.preconditioner[up] * scratch.auxiliary[up];
+        }
+        scratch.auxiliary[index] = t * scratch.preconditioner[index];
+    }
+
+    // ...then solve L^T * z = q in place.
+    for index in (0..rows * cols).rev() {
+        if matrix.diagonal[index] <= 0.0 {
+            continue;
+        }
+
+        let mut t: f32 = scratch.auxiliary[index];
+        if index % cols + 1 < cols {
+            t -= matrix.coupling_right[index]
+                * scratch.preconditioner[index]
+                * scratch.auxiliary[index + 1];
+        }
+        if index + cols < rows * cols {
+            t -= matrix.coupling_down[index]
+                * scratch.preconditioner[index]
+                * scratch.auxiliary[index + cols];
+        }
+        scratch.auxiliary[index] = t * scratch.preconditioner[index];
+    }
+}
+
+/** Force velocity incompressibility with geometric multigrid V-cycles.  The grid's cell types and
+divergence are repeatedly coarsened (2x2 cells at a time) so that errors spanning many cells can be
+smoothed out cheaply on a small grid, then the corrections are carried back up to the full grid.
+Stops once the divergence falls below `constraints.pressure_tolerance` (relative to where it started)
+or after `constraints.incomp_iters_per_frame` V-cycles, and returns how many it took. */
+pub fn solve_pressure_multigrid(grid: &mut SimGrid, constraints: &SimConstraints) -> u8 {
+    // Borrow the scratch buffers so the velocity grids can be mutated alongside them.
+    let mut levels: Vec<SimMultigridLevel> = std::mem::take(&mut grid.scratch.pressure.multigrid);
+    let max_residual: f32 = build_multigrid_levels(grid, constraints, &mut levels);
+
+    let mut iterations: u8 = 0;
+    if max_residual > 0.0 {
+        let tolerance: f32 = constraints.pressure_tolerance * max_residual;
+        while iterations < constraints.incomp_iters_per_frame {
+            iterations += 1;
+            run_v_cycle(&mut levels);
+
+            let finest: &mut SimMultigridLevel = &mut levels[0];
+            finest
+                .matrix
+                .calculate_residual(&finest.rhs, &finest.pressure, &mut finest.residual);
+            if finest
+                .residual
+                .iter()
+                .all(|residual| residual.abs() <= tolerance)
+            {
+                break;
+            }
+        }
+
+        apply_pressure(grid, &levels[0].pressure);
+    }
+
+    grid.scratch.pressure.multigrid = levels;
+    iterations
+}
+
+/** Build the multigrid hierarchy, starting with the full grid's pressure equations.  A coarse cell
+is fluid if any of its (up to four) children are, otherwise air if any of them are, and solid
+only if all of them are.  Returns the largest divergence to cancel out on the full grid. */
+fn build_multigrid_levels(
+    grid: &SimGrid,
+    constraints: &SimConstraints,
+    levels: &mut Vec<SimMultigridLevel>,
+) -> f32 {
+    let mut rows: usize = grid.dimensions.0 as usize;
+    let mut cols: usize = grid.dimensions.1 as usize;
+    let mut level_count: usize = 1;
+    while rows > MULTIGRID_MIN_SIZE && cols > MULTIGRID_MIN_SIZE {
+        rows = (rows + 1) / 2;
+        cols = (cols + 1) / 2;
+        level_count += 1;
+    }
+    levels.resize_with(level_count, SimMultigridLevel::default);
+
+    // The finest level is the simulation grid itself.
+    let finest: &mut SimMultigridLevel = &mut levels[0];
+    let max_residual: f32 =
+        build_pressure_equations(grid, constraints, &mut finest.matrix, &mut finest.rhs);
+    finest.cell_type.clear();
+    finest
+        .cell_type
+        .extend(grid.cell_type.iter().flatten().cloned());
+
+    for level in 0..level_count {
+        if level > 0 {
+            let (finer_levels, coarser_levels) = levels.split_at_mut(level);
+            coarsen_level(&finer_levels[level - 1], &mut coarser_levels[0]);
+        }
+
+        let current: &mut SimMultigridLevel = &mut levels[level];
+        let cell_count: usize = current.matrix.rows * current.matrix.cols;
+        for buffer in [&mut current.pressure, &mut current.residual] {
+            buffer.clear();
+            buffer.resize(cell_count, 0.0);
+        }
+        current.rhs.resize(cell_count, 0.0);
+    }
+
+    max_residual
+}
+
+/// Build a coarse level's cell types and pressure equations from the next finer level.
+fn coarsen_level(fine: &SimMultigridLevel, coarse: &mut SimMultigridLevel) {
+    let (fine_rows, fine_cols) = (fine.matrix.rows, fine.matrix.cols);
+    let rows: usize = (fine_rows + 1) / 2;
+    let cols: usize = (fine_cols + 1) / 2;
+
+    coarse.cell_type.clear();
+    coarse.cell_type.resize(rows * cols, SimGridCellType::Solid);
+    for fine_row in 0..fine_rows {
+        for fine_col in 0..fine_cols {
+            let index: usize = (fine_row / 2) * cols + fine_col / 2;
+            match (
+                &fine.cell_type[fine_row * fine_cols + fine_col],
+                &coarse.cell_type[index],
+            ) {
+                (SimGridCellType::Fluid, _) => coarse.cell_type[index] = SimGridCellType::Fluid,
+                (SimGridCellType::Air | SimGridCellType::Porous(_), SimGridCellType::Solid) => {
+                    coarse.cell_type[index] = SimGridCellType::Air
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /* The coarse equations are the fine ones summed over each coarse cell's children: faces
+    leaving a coarse cell keep their weights, and faces between two of its children cancel out. */
+    coarse.matrix.reset(rows, cols);
+    coarse.matrix.wrap_horizontal = fine.matrix.wrap_horizontal;
+    coarse.matrix.wrap_vertical = fine.matrix.wrap_vertical;
+    let fine_matrix: &SimPressureMatrix = &fine.matrix;
+    for fine_row in 0..fine_rows {
+        for fine_col in 0..fine_cols {
+            let fine_index: usize = fine_row * fine_cols + fine_col;
+            if fine_matrix.diagonal[fine_index] <= 0.0 {
+                continue;
+            }
+            let index: usize = (fine_row / 2) * cols + fine_col / 2;
+            coarse.matrix.diagonal[index] += fine_matrix.diagonal[fine_index];
+
+            // A lone child on the far edge of an odd-sized wrapped grid still couples across it.
+            let right_coupling: f32 = fine_matrix.coupling_right[fine_index];
+            if fine_col % 2 == 0 && fine_col + 1 < fine_cols {
+                coarse.matrix.diagonal[index] += 2.0 * right_coupling;
+            } else {
+                coarse.matrix.coupling_right[index] += right_coupling;
+            }
+            let down_coupling: f32 = fine_matrix.coupling_down[fine_index];
+            if fine_row % 2 == 0 && fine_row + 1 < fine_rows {
+                coarse.matrix.diagonal[index] += 2.0 * down_coupling;
+            } else {
+                coarse.matrix.coupling_down[index] += down_coupling;
+            }
+        }
+    }
+}
+
+/** Run one V-cycle down from the first of `levels`: smooth, hand what's left of the residual down
+to the next coarser level to be corrected there, carry the correction back up, and smooth again. */
+fn run_v_cycle(levels: &mut [SimMultigridLevel]) {
+    let Some((fine, coarser_levels)) = levels.split_first_mut() else {
+        return;
+    };
+    let Some(coarse) = coarser_levels.first_mut() else {
+        fine.matrix
+            .relax(&fine.rhs, &mut fine.pressure, MULTIGRID_COARSEST_SWEEPS);
+        return;
+    };
+
+    fine.matrix
+        .relax(&fine.rhs, &mut fine.pressure, MULTIGRID_SMOOTHING_SWEEPS);
+    fine.matrix
+        .calculate_residual(&fine.rhs, &fine.pressure, &mut fine.residual);
+
+    // Restrict by summing each coarse cell's children, matching how its equations were built.
+    let (fine_rows, fine_cols) = (fine.matrix.rows, fine.matrix.cols);
+    let coarse_cols: usize = coarse.matrix.cols;
+    coarse.rhs.fill(0.0);
+    coarse.pressure.fill(0.0);
+    for fine_row in 0..fine_rows {
+        for fine_col in 0..fine_cols {
+            let fine_index: usize = fine_row * fine_cols + fine_col;
+            if fine.matrix.diagonal[fine_index] > 0.0 {
+                coarse.rhs[(fine_row / 2) * coarse_cols + fine_col / 2] +=
+                    fine.residual[fine_index];
+            }
+        }
+    }
+
+    run_v_cycle(coarser_levels);
+
+    // Carry the coarse correction back up to each of its children.
+    let coarse: &SimMultigridLevel = &coarser_levels[0];
+    for fine_row in 0..fine_rows {
+        for fine_col in 0..fine_cols {
+            let fine_index: usize = fine_row * fine_cols + fine_col;
+            if fine.matrix.diagonal[fine_index] > 0.0 {
+                fine.pressure[fine_index] +=
+                    coarse.pressure[(fine_row / 2) * coarse_cols + fine_col / 2];
+            }
+        }
+    }
+
+    fine.matrix
+        .relax(&fine.rhs, &mut fine.pressure, MULTIGRID_SMOOTHING_SWEEPS);
+}
+
+/** Subtract the pressure gradient from every face bordering fluid.  Faces point right and up, so
+each face's velocity drops by its weight times the pressure difference in that direction. */
+fn apply_pressure(grid: &mut SimGrid, pressure: &[f32]) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let borders_fluid = |grid: &SimGrid, a: (usize, usize), b: (usize, usize)| -> bool {
+        grid.cell_type[a.0][a.1] == SimGridCellType::Fluid
+            || grid.cell_type[b.0][b.1] == SimGridCellType::Fluid
+    };
+
+    // Horizontal faces sit between a cell and its left neighbor.
+    for row in 0..rows {
+        for col in 1..cols {
+            if !borders_fluid(grid, (row, col - 1), (row, col)) {
+                continue;
+            }
+            let weight: f32 = calculate_face_weight(grid, (row, col - 1), (row, col));
+            let index: usize = row * cols + col;
+            grid.velocity_u[row][col] -= weight * (pressure[index] - pressure[index - 1]);
+        }
+    }
+
+    // Vertical faces sit between a cell and its upper neighbor; up is the positive direction.
+    for row in 1..rows {
+        for col in 0..cols {
+            if !borders_fluid(grid, (row - 1, col), (row, col)) {
+                continue;
+            }
+            let weight: f32 = calculate_face_weight(grid, (row - 1, col), (row, col));
+            let index: usize = row * cols + col;
+            grid.velocity_v[row][col] -= weight * (pressure[index - cols] - pressure[index]);
+        }
+    }
+
+    // Keep the pressures around for anything that measures them.
+    for row in 0..rows {
+        for col in 0..cols {
+            grid.cell_center[row][col] = pressure[row * cols + col];
+        }
+    }
+
+    /* On a wrapped grid, the faces along the edges sit between the cells on opposite edges, and
+    both copies of each face get the same velocity. */
+    if grid.wrap_horizontal {
+        for row in 0..rows {
+            if !borders_fluid(grid, (row, cols - 1), (row, 0)) {
+                continue;
+            }
+            let weight: f32 = calculate_face_weight(grid, (row, cols - 1), (row, cols));
+            let index: usize = row * cols;
+            let velocity: f32 =
+                grid.velocity_u[row][0] - weight * (pressure[index] - pressure[index + cols - 1]);
+            grid.velocity_u[row][0] = velocity;
+            grid.velocity_u[row][cols] = velocity;
+        }
+    }
+    if grid.wrap_vertical {
+        for col in 0..cols {
+            if !borders_fluid(grid, (rows - 1, col), (0, col)) {
+                continue;
+            }
+            let weight: f32 = calculate_face_weight(grid, (rows - 1, col), (rows, col));
+            let velocity: f32 = grid.velocity_v[0][col]
+                - weight * (pressure[(rows - 1) * cols + col] - pressure[col]);
+            grid.velocity_v[0][col] = velocity;
+            grid.velocity_v[rows][col] = velocity;
+        }
+    }
+
+    // Faces on an open edge sit between a cell and the zero-pressure air past the edge.
+    let is_fluid = |grid: &SimGrid, row: usize, col: usize| -> bool {
+        grid.cell_type[row][col] == SimGridCellType::Fluid
+    };
+    for row in 0..rows {
+        if grid.is_cell_past_open_edge(row, usize::MAX) && is_fluid(grid, row, 0) {
+            let weight: f32 = calculate_face_weight(grid, (row, 0), (row, usize::MAX));
+            grid.velocity_u[row][0] -= weight * pressure[row * cols];
+        }
+        if grid.is_cell_past_open_edge(row, cols) && is_fluid(grid, row, cols - 1) {
+            let weight: f32 = calculate_face_weight(grid, (row, cols - 1), (row, cols));
+            grid.velocity_u[row][cols] += weight * pressure[row * cols + cols - 1];
+        }
+    }
+    for col in 0..cols {
+        if grid.is_cell_past_open_edge(usize::MAX, col) && is_fluid(grid, 0, col) {
+            let weight: f32 = calculate_face_weight(grid, (0, col), (usize::MAX, col));
+            grid.velocity_v[0][col] += weight * pressure[col];
+        }
+        if grid.is_cell_past_open_edge(rows, col) && is_fluid(grid, rows - 1, col) {
+            let weight: f32 = calculate_face_weight(grid, (rows - 1, col), (rows, col));
+            grid.velocity_v[rows][col] -= weight * pressure[(rows - 1) * cols + col];
+        }
+    }
+}
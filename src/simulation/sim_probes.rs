@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use super::sim_physics_engine::{calculate_cell_divergence, sample_grid_velocity};
+use super::SimGrid;
+
+/// Samples each probe remembers before it starts forgetting its oldest ones.
+pub const PROBE_HISTORY_LENGTH: usize = 600;
+/// Column headers for exported probe samples.
+pub const PROBE_CSV_HEADER: &str = "probe,time,pressure,divergence,velocity_x,velocity_y";
+
+/// Number of quantities a probe records that can be plotted.
+pub const PROBE_QUANTITY_COUNT: usize = 3;
+/// A quantity recorded by probes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimProbeQuantity {
+    #[default]
+    Pressure,
+    Divergence,
+    Speed,
+}
+
+impl Into<SimProbeQuantity> for usize {
+    fn into(self) -> SimProbeQuantity {
+        match self {
+            0 => SimProbeQuantity::Pressure,
+            1 => SimProbeQuantity::Divergence,
+            2 => SimProbeQuantity::Speed,
+            _ => {
+                eprintln!("Invalid probe quantity; defaulting to pressure!");
+                SimProbeQuantity::Pressure
+            }
+        }
+    }
+}
+
+impl SimProbeQuantity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pressure => "Pressure",
+            Self::Divergence => "Divergence",
+            Self::Speed => "Speed",
+        }
+    }
+}
+
+/// What a probe measured in its cell at one moment of simulated time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimProbeSample {
+    pub time: f32,       // Simulated seconds when the sample was taken.
+    pub pressure: f32,   // Pressure the last solve left in the probe's cell.
+    pub divergence: f32, // Net flow out of the probe's cell.
+    pub velocity: Vec2,  // Fluid velocity at the probe's position.
+}
+
+impl SimProbeSample {
+    /// Read `quantity` out of this sample.
+    pub fn get(&self, quantity: SimProbeQuantity) -> f32 {
+        match quantity {
+            SimProbeQuantity::Pressure => self.pressure,
+            SimProbeQuantity::Divergence => self.divergence,
+            SimProbeQuantity::Speed => self.velocity.length(),
+        }
+    }
+}
+
+/** A sensor placed in the simulation that records the pressure, divergence, and velocity of the
+fluid in its cell over time.  Only the latest `PROBE_HISTORY_LENGTH` samples are kept, and they are
+not saved along with the scene. */
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct SimProbe {
+    pub position: Vec2,
+    #[reflect(ignore)]
+    pub samples: VecDeque<SimProbeSample>,
+}
+
+impl SimProbe {
+    pub fn new(position: Vec2) -> Self {
+        Self {
+            position,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /** Record what the fluid in the probe's cell is doing at `time`, forgetting the oldest sample
+    if the probe's history is full.  Probes that have been moved off of the grid record nothing. */
+    pub fn sample(&mut self, grid: &SimGrid, time: f32) {
+        if !grid.is_position_within_grid(&self.position) {
+            return;
+        }
+
+        let cell: Vec2 = grid.get_cell_coordinates_from_position(&self.position);
+        let (row, col) = (cell.x as usize, cell.y as usize);
+        let sample = SimProbeSample {
+            time,
+            pressure: grid.cell_center[row][col],
+            divergence: calculate_cell_divergence(grid, row, col),
+            velocity: sample_grid_velocity(grid, self.position).unwrap_or(Vec2::ZERO),
+        };
+
+        if self.samples.len() >= PROBE_HISTORY_LENGTH {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// The most recent sample this probe has taken, if any.
+    pub fn latest(&self) -> Option<&SimProbeSample> {
+        self.samples.back()
+    }
+}
+
+/// A new, timestamped path to export probe samples to, in the working directory.
+pub fn probe_export_file_path() -> PathBuf {
+    let seconds: u64 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    PathBuf::from(format!("probes-{}.csv", seconds))
+}
+
+/// Format one probe sample as a line of CSV, matching `PROBE_CSV_HEADER`.
+pub fn format_probe_sample(probe_index: usize, sample: &SimProbeSample) -> String {
+    format!(
+        "{},{},{},{},{},{}",
+        probe_index,
+        sample.time,
+        sample.pressure,
+        sample.divergence,
+        sample.velocity.x,
+        sample.velocity.y
+    )
+}
+
+/** Write every sample each probe remembers to a CSV file at `path`, replacing any file already
+there.  Probes are numbered in the order they're given, starting from 1. */
+pub fn export_probe_samples<'a>(
+    path: &Path,
+    probes: impl Iterator<Item = &'a SimProbe>,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", PROBE_CSV_HEADER)?;
+    for (i, probe) in probes.enumerate() {
+        for sample in probe.samples.iter() {
+            writeln!(writer, "{}", format_probe_sample(i + 1, sample))?;
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,101 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_state_manager::{delete_particle, spawn_particle};
+use super::{SimConstraints, SimGrid, SimParticle};
+
+/// How far (in cells) from its intake a pump swallows fluid.
+pub const PUMP_INTAKE_RADIUS: f32 = 1.5;
+/// How far (in cells) from its outlet a pump spreads the fluid it lets out.
+const PUMP_OUTLET_RADIUS: f32 = 0.5;
+
+/// A particle of fluid travelling through a pump, on its way from the intake to the outlet.
+#[derive(Debug, Clone, Default, Reflect)]
+pub struct SimPumpedParticle {
+    pub time_left: f32, // Seconds until it comes out of the outlet.
+    pub particle: SimParticle,
+}
+
+/** A drain and faucet joined by a pipe: fluid swallowed at the intake comes back out of the outlet
+`delay` seconds later, at `velocity`.  Pumps never create or destroy fluid: the particles inside of
+every pump and those in the simulation always add up to the same count, and each particle comes back
+out exactly as it went in apart from its position and velocity. */
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct SimPump {
+    pub intake: Vec2,   // Where the pump swallows fluid.
+    pub outlet: Vec2,   // Where the pump lets fluid back out.
+    pub velocity: Vec2, // Velocity fluid leaves the outlet at.
+    pub delay: f32,     // Seconds fluid spends inside the pump.
+    // Fluid inside the pump, in the order it was swallowed.
+    pub in_transit: Vec<SimPumpedParticle>,
+}
+
+impl SimPump {
+    pub fn new(intake: Vec2, outlet: Vec2, velocity: Vec2, delay: f32) -> Self {
+        Self {
+            intake,
+            outlet,
+            velocity,
+            delay,
+            in_transit: Vec::new(),
+        }
+    }
+
+    /** Let out whatever fluid has made it all the way through the pump, then swallow any fluid that
+    has reached the intake.  Fluid that can't be let out (because the outlet has been walled over)
+    waits inside of the pump until it can. */
+    pub fn run<F: ReadOnlyWorldQuery>(
+        &mut self,
+        commands: &mut Commands,
+        constraints: &mut SimConstraints,
+        grid: &mut SimGrid,
+        particles: &Query<(Entity, &mut SimParticle), F>,
+        delta_time: f32,
+    ) {
+        let cell_size: f32 = grid.cell_size as f32;
+        for pumped in self.in_transit.iter_mut() {
+            pumped.time_left -= delta_time;
+        }
+
+        /* Fan the fluid out around the outlet so it doesn't all come out on top of itself; stepping
+        around by the golden ratio keeps each particle away from the ones let out just before it. */
+        let mut still_inside: Vec<SimPumpedParticle> = Vec::new();
+        for (index, mut pumped) in std::mem::take(&mut self.in_transit).into_iter().enumerate() {
+            let angle: f32 = index as f32 * std::f32::consts::TAU * 0.618034;
+            let position: Vec2 =
+                self.outlet + Vec2::from_angle(angle) * PUMP_OUTLET_RADIUS * cell_size;
+            if pumped.time_left > 0.0 || !grid.is_position_within_grid(&position) {
+                still_inside.push(pumped);
+                continue;
+            }
+
+            pumped.particle.position = position;
+            pumped.particle.previous_position = position;
+            pumped.particle.velocity = self.velocity;
+            pumped.particle.affine_velocity = Mat2::ZERO;
+            if spawn_particle(commands, constraints, grid, pumped.particle.clone()).is_err() {
+                still_inside.push(pumped);
+            }
+        }
+        self.in_transit = still_inside;
+
+        let intake_radius: f32 = PUMP_INTAKE_RADIUS * cell_size;
+        let swallowed: Vec<(Entity, SimParticle)> = particles
+            .iter()
+            .filter(|(id, particle)| {
+                self.intake.distance(particle.position) <= intake_radius
+                    && !grid.is_particle_pending_removal(*id)
+            })
+            .map(|(id, particle)| (id, particle.clone()))
+            .collect();
+        for (id, particle) in swallowed {
+            if delete_particle(commands, constraints, particles, grid, id).is_ok() {
+                self.in_transit.push(SimPumpedParticle {
+                    time_left: self.delay,
+                    particle,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_state_manager::{add_particle, delete_particle};
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+
+/// Where new particles are placed within a cell, relative to its center and in cells, in the order used.
+const SEED_OFFSETS: [Vec2; 5] = [
+    Vec2::new(-0.25, -0.25),
+    Vec2::new(0.25, 0.25),
+    Vec2::new(0.25, -0.25),
+    Vec2::new(-0.25, 0.25),
+    Vec2::ZERO,
+];
+
+/** Keep the fluid evenly covered with particles while `constraints.reseeding` is on.  Fluid cells
+inside the fluid with fewer than `constraints.min_particles_per_cell` particles are topped up with
+new particles, which copy the material, group, velocity, and temperature of the particles around
+them; any fluid cell with more than `constraints.max_particles_per_cell` has its surplus removed.
+Cells on the fluid's surface are never topped up, since they are only partly full. */
+pub fn reseed_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+) {
+    if !constraints.reseeding {
+        return;
+    }
+
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let min_count: usize = constraints.min_particles_per_cell as usize;
+    let max_count: usize = (constraints.max_particles_per_cell as usize).max(min_count);
+
+    for row in 0..rows {
+        for col in 0..cols {
+            // Cells below the narrow band are full without any particles of their own.
+            if grid.cell_type[row][col] != SimGridCellType::Fluid
+                || grid.is_narrow_band_interior(row, col)
+            {
+                continue;
+            }
+
+            let cell_particles: Vec<Entity> = live_particles_in_cell(grid, particles, row, col);
+            if cell_particles.len() > max_count {
+                for id in cell_particles[max_count..].iter() {
+                    let _ = delete_particle(commands, constraints, particles, grid, *id);
+                }
+            } else if cell_particles.len() < min_count && !borders_air(grid, row, col) {
+                seed_cell(
+                    commands,
+                    constraints,
+                    grid,
+                    particles,
+                    row,
+                    col,
+                    cell_particles.len(),
+                    min_count - cell_particles.len(),
+                );
+            }
+        }
+    }
+}
+
+/// Particles in a cell that aren't already on their way out.
+fn live_particles_in_cell<F: ReadOnlyWorldQuery>(
+    grid: &SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    row: usize,
+    col: usize,
+) -> Vec<Entity> {
+    let lookup_index: usize = grid.get_lookup_index(Vec2::new(row as f32, col as f32));
+    grid.get_particles_in_lookup(lookup_index)
+        .iter()
+        .copied()
+        .filter(|id| particles.contains(*id) && !grid.is_particle_pending_removal(*id))
+        .collect()
+}
+
+/// Whether a cell shares an edge with an air cell (or the edge of the grid).
+fn borders_air(grid: &SimGrid, row: usize, col: usize) -> bool {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().any(
+        |(row_offset, col_offset): (isize, isize)| {
+            let neighbor_row: usize = usize::wrapping_add_signed(row, row_offset);
+            let neighbor_col: usize = usize::wrapping_add_signed(col, col_offset);
+            neighbor_row >= rows
+                || neighbor_col >= cols
+                || grid.cell_type[neighbor_row][neighbor_col] == SimGridCellType::Air
+        },
+    )
+}
+
+/** Add `count` particles to a cell that already has `existing_count`.  The new particles take after
+the particles in and around the cell; if there are none to take after, the cell is left alone. */
+fn seed_cell<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    row: usize,
+    col: usize,
+    existing_count: usize,
+    count: usize,
+) {
+    let mut donors: Vec<&SimParticle> = Vec::new();
+    for neighbor_row in row.saturating_sub(1)..=row + 1 {
+        for neighbor_col in col.saturating_sub(1)..=col + 1 {
+            if neighbor_row >= grid.dimensions.0 as usize
+                || neighbor_col >= grid.dimensions.1 as usize
+            {
+                continue;
+            }
+            for id in live_particles_in_cell(grid, particles, neighbor_row, neighbor_col) {
+                if let Ok((_, particle)) = particles.get(id) {
+                    donors.push(particle);
+                }
+            }
+        }
+    }
+    if donors.is_empty() {
+        return;
+    }
+
+    let donor_count: f32 = donors.len() as f32;
+    let velocity: Vec2 = donors.iter().map(|donor| donor.velocity).sum::<Vec2>() / donor_count;
+    let temperature: f32 = donors.iter().map(|donor| donor.temperature).sum::<f32>() / donor_count;
+
+    let cell_size: f32 = grid.cell_size as f32;
+    let center: Vec2 =
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+    for seed in existing_count..existing_count + count {
+        let position: Vec2 = center + SEED_OFFSETS[seed % SEED_OFFSETS.len()] * cell_size;
+
+        // Take the material and group of whichever particle is closest to the new one.
+        let Some(nearest) = donors.iter().min_by(|a, b| {
+            a.position
+                .distance_squared(position)
+                .total_cmp(&b.position.distance_squared(position))
+        }) else {
+            return;
+        };
+        let (group, material) = (nearest.group, nearest.material);
+
+        let _ = add_particle(
+            commands,
+            constraints,
+            grid,
+            position,
+            velocity,
+            temperature,
+            group,
+            material,
+        );
+    }
+}
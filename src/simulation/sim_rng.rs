@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+
+use super::SimConstraints;
+use crate::util::get_millis_since_epoch;
+
+/// Seed used in deterministic mode until the user picks another.
+pub const DEFAULT_SIM_SEED: u64 = 0x4A55_4943_4542_4F58;
+
+/** The simulation's source of randomness; a xorshift generator (see "Xorshift RNGs" by George
+Marsaglia) that carries its state from one draw to the next.  Every random choice the simulation
+makes (which surface particle evaporates, where raindrops fall, how a cone faucet sprays) is drawn
+from this one resource, so seeding it with the same value replays the same choices.  Unlike
+`generate_random_usize()`, it never looks at the clock after it has been seeded.  It isn't saved
+with scenes; resetting the scene reseeds it instead. */
+#[derive(Resource, Component, Clone, Debug, PartialEq, Eq)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIM_SEED)
+    }
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift gets stuck on a state of zero, so nudge it off of zero.
+        Self {
+            state: if seed == 0 { DEFAULT_SIM_SEED } else { seed },
+        }
+    }
+
+    /// A generator seeded from the clock, for when results don't need to be repeatable.
+    pub fn from_clock() -> Self {
+        Self::new(get_millis_since_epoch() as u64)
+    }
+
+    /** A generator for a fresh run of a scene.  In deterministic mode every run draws the same
+    numbers, from the constraints' seed; otherwise each run gets its own. */
+    pub fn for_scene(constraints: &SimConstraints) -> Self {
+        if constraints.deterministic {
+            Self::new(constraints.seed)
+        } else {
+            Self::from_clock()
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A random index from 0 up to (but not including) `bound`; always 0 if `bound` is 0.
+    pub fn next_index(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// A random value from 0.0 up to (but not including) 1.0.
+    pub fn next_f32(&mut self) -> f32 {
+        // The top 24 bits fit in an f32's mantissa exactly.
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,236 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_state_manager::delete_particle;
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+
+pub const SAFEGUARD_RESPONSE_COUNT: usize = 3;
+
+/// How the stability safeguards deal with over-compressed cells and runaway particles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum SimSafeguardResponse {
+    #[default]
+    ClampVelocity = 0,
+    Redistribute,
+    Despawn,
+}
+
+impl Into<SimSafeguardResponse> for usize {
+    fn into(self) -> SimSafeguardResponse {
+        match self {
+            0 => SimSafeguardResponse::ClampVelocity,
+            1 => SimSafeguardResponse::Redistribute,
+            2 => SimSafeguardResponse::Despawn,
+            _ => {
+                eprintln!("Invalid SimSafeguardResponse; defaulting to ClampVelocity!");
+                SimSafeguardResponse::ClampVelocity
+            }
+        }
+    }
+}
+
+impl SimSafeguardResponse {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ClampVelocity => "Clamp Velocity",
+            Self::Redistribute => "Redistribute",
+            Self::Despawn => "Despawn",
+        }
+    }
+}
+
+/// Tally of what the safeguards have done since the report was last taken.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimSafeguardReport {
+    pub clamped: usize,       // Particles whose velocities were clamped.
+    pub redistributed: usize, // Particles moved out of over-compressed cells.
+    pub despawned: usize,     // Particles removed from the simulation.
+}
+
+impl SimSafeguardReport {
+    /// Whether the safeguards haven't had to step in at all.
+    pub fn is_empty(&self) -> bool {
+        self.clamped == 0 && self.redistributed == 0 && self.despawned == 0
+    }
+
+    /// Add another report's tallies to this one.
+    pub fn add(&mut self, other: &SimSafeguardReport) {
+        self.clamped += other.clamped;
+        self.redistributed += other.redistributed;
+        self.despawned += other.despawned;
+    }
+
+    /// Describe what the safeguards did for the user (if anything), then clear the report.
+    pub fn take_warning(&mut self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut actions: Vec<String> = Vec::new();
+        if self.clamped > 0 {
+            actions.push(format!("slowed {} particles", self.clamped));
+        }
+        if self.redistributed > 0 {
+            actions.push(format!("spread out {} particles", self.redistributed));
+        }
+        if self.despawned > 0 {
+            actions.push(format!("removed {} particles", self.despawned));
+        }
+        *self = Self::default();
+
+        Some(format!("Stability safeguards {}.", actions.join(", ")))
+    }
+}
+
+/** Catch the simulation before it visibly explodes.  Particles faster than the speed ceiling and
+cells denser than a multiple of the fluid's typical density are dealt with according to the response chosen in
+`constraints`; everything that was done is tallied in `constraints.safeguard_report`. */
+pub fn enforce_safeguards<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+) {
+    let response: SimSafeguardResponse = constraints.safeguard_response;
+    let mut report: SimSafeguardReport = SimSafeguardReport::default();
+
+    /* Runaway particles.  There is nowhere sensible to redistribute a particle's speed to, so only
+    despawning differs from clamping here. */
+    let max_speed: f32 = constraints.max_particle_speed;
+    let mut runaway_particles: Vec<Entity> = Vec::new();
+    for (id, mut particle) in particles.iter_mut() {
+        if particle.velocity.length() <= max_speed {
+            continue;
+        }
+
+        if response == SimSafeguardResponse::Despawn {
+            runaway_particles.push(id);
+        } else {
+            particle.velocity = particle.velocity.clamp_length_max(max_speed);
+            report.clamped += 1;
+        }
+    }
+    for id in runaway_particles {
+        if grid.is_particle_pending_removal(id) {
+            continue;
+        }
+        let _ = delete_particle(commands, constraints, particles, grid, id);
+        report.despawned += 1;
+    }
+
+    /* Over-compressed cells.  The solver's rest density is averaged over the whole domain, air
+    included, so compare against the average density of fluid cells instead. */
+    let max_density: f32 = average_fluid_density(grid) * constraints.max_density_ratio;
+    if max_density <= 0.0 {
+        constraints.safeguard_report.add(&report);
+        return;
+    }
+
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            let cell_coordinates: Vec2 = Vec2::new(row as f32, col as f32);
+            let lookup_index: usize = grid.get_lookup_index(cell_coordinates);
+            let density: f32 = grid.density[lookup_index];
+            if density <= max_density || grid.cell_type[row][col] == SimGridCellType::Solid {
+                continue;
+            }
+
+            let cell_particles: Vec<Entity> = grid
+                .get_particles_in_lookup(lookup_index)
+                .iter()
+                .copied()
+                .filter(|id| !grid.is_particle_pending_removal(*id))
+                .collect();
+            let excess_count: usize =
+                (cell_particles.len() as f32 * (1.0 - max_density / density)).ceil() as usize;
+
+            match response {
+                // Slow the cell's particles down in proportion to how over-compressed it is.
+                SimSafeguardResponse::ClampVelocity => {
+                    for id in cell_particles.iter() {
+                        if let Ok((_, mut particle)) = particles.get_mut(*id) {
+                            particle.velocity *= max_density / density;
+                            report.clamped += 1;
+                        }
+                    }
+                }
+                // Move the excess particles into the emptiest neighboring cell.
+                SimSafeguardResponse::Redistribute => {
+                    let Some(target_cell) = find_emptiest_neighbor(grid, row, col) else {
+                        continue;
+                    };
+                    let target_center: Vec2 =
+                        grid.get_cell_center_position_from_coordinates(&target_cell);
+                    let spread: f32 = grid.cell_size as f32 * 0.25;
+                    for (i, id) in cell_particles.iter().take(excess_count).enumerate() {
+                        if let Ok((_, mut particle)) = particles.get_mut(*id) {
+                            // Fan the particles out by the golden angle so they don't overlap.
+                            let angle: f32 = i as f32 * 2.399;
+                            particle.position =
+                                target_center + Vec2::new(angle.cos(), angle.sin()) * spread;
+                            particle.velocity = Vec2::ZERO;
+                            report.redistributed += 1;
+                        }
+                    }
+                }
+                SimSafeguardResponse::Despawn => {
+                    for id in cell_particles.into_iter().take(excess_count) {
+                        let _ = delete_particle(commands, constraints, particles, grid, id);
+                        report.despawned += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    constraints.safeguard_report.add(&report);
+}
+
+/// Average density of the grid's fluid cells, or 0.0 if there are none.
+pub fn average_fluid_density(grid: &SimGrid) -> f32 {
+    let mut density_sum: f32 = 0.0;
+    let mut fluid_cell_count: usize = 0;
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            if grid.cell_type[row][col] != SimGridCellType::Fluid {
+                continue;
+            }
+            density_sum += grid.density[grid.get_lookup_index(Vec2::new(row as f32, col as f32))];
+            fluid_cell_count += 1;
+        }
+    }
+
+    if fluid_cell_count == 0 {
+        return 0.0;
+    }
+    density_sum / fluid_cell_count as f32
+}
+
+/// Find the least dense non-solid cell surrounding (row, col), if there is one.
+fn find_emptiest_neighbor(grid: &SimGrid, row: usize, col: usize) -> Option<Vec2> {
+    let mut emptiest: Option<(Vec2, f32)> = None;
+    for row_offset in -1..=1_i32 {
+        for col_offset in -1..=1_i32 {
+            let neighbor_row: i32 = row as i32 + row_offset;
+            let neighbor_col: i32 = col as i32 + col_offset;
+            if (row_offset == 0 && col_offset == 0)
+                || neighbor_row < 0
+                || neighbor_col < 0
+                || neighbor_row >= grid.dimensions.0 as i32
+                || neighbor_col >= grid.dimensions.1 as i32
+                || grid.cell_type[neighbor_row as usize][neighbor_col as usize]
+                    == SimGridCellType::Solid
+            {
+                continue;
+            }
+
+            let neighbor: Vec2 = Vec2::new(neighbor_row as f32, neighbor_col as f32);
+            let density: f32 = grid.density[grid.get_lookup_index(neighbor)];
+            if emptiest.map_or(true, |(_, lowest_density)| density < lowest_density) {
+                emptiest = Some((neighbor, density));
+            }
+        }
+    }
+
+    emptiest.map(|(cell, _)| cell)
+}
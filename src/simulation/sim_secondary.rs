@@ -0,0 +1,178 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_physics_engine::sample_grid_velocity;
+use super::{SimConstraints, SimGrid, SimGridCellType, SimParticle};
+
+/// Speed at which fluid particles start throwing off foam, spray, and bubbles.
+pub const SECONDARY_MIN_SPEED: f32 = 150.0;
+/// Speed at which fluid particles throw off as much foam, spray, and bubbles as they ever will.
+pub const SECONDARY_FULL_SPEED: f32 = 450.0;
+/// Seconds a secondary particle lasts before it pops or fizzles out.
+pub const SECONDARY_LIFETIME: f32 = 2.5;
+/// Speed bubbles rise through the fluid at, on top of being carried along by it.
+const BUBBLE_RISE_SPEED: f32 = 40.0;
+
+/// What a secondary particle currently is, depending on where it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimSecondaryKind {
+    // Flying through the air; falls ballistically.
+    #[default]
+    Spray,
+    // Floating on the fluid's surface; drifts along with it.
+    Foam,
+    // Trapped under the surface; floats upwards.
+    Bubble,
+}
+
+/** A cheap, purely visual particle thrown off by churning fluid.  Secondary particles are moved
+by the fluid but never push back on it. */
+#[derive(Component, Clone, Debug, Default)]
+pub struct SimSecondaryParticle {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub kind: SimSecondaryKind,
+    pub lifetime: f32, // Seconds left before this particle disappears.
+}
+
+/** Spawn foam, spray, and bubbles where the fluid is churning, then move every secondary particle
+along.  Fast particles on the fluid's surface throw off more the more exposed they are (crests and
+droplets more than flat surfaces), and fast particles under the surface trap a little air as
+bubbles.  Does nothing but clean up while `constraints.secondary_particles` is off. */
+pub fn step_secondary_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &SimGrid,
+    particles: &Query<&SimParticle, F>,
+    secondary_particles: &mut Query<(Entity, &mut SimSecondaryParticle)>,
+    delta_time: f32,
+) {
+    if !constraints.secondary_particles {
+        for (id, _) in secondary_particles.iter() {
+            commands.entity(id).despawn();
+        }
+        constraints.secondary_spawn_progress = 0.0;
+        return;
+    }
+
+    let mut secondary_count: usize = secondary_particles.iter().count();
+    for particle in particles.iter() {
+        let potential: f32 = calculate_spawn_potential(grid, particle);
+        if potential <= 0.0 {
+            continue;
+        }
+
+        constraints.secondary_spawn_progress +=
+            potential * constraints.secondary_spawn_rate * delta_time;
+        while constraints.secondary_spawn_progress >= 1.0 {
+            constraints.secondary_spawn_progress -= 1.0;
+            if secondary_count >= constraints.max_secondary_particles {
+                continue;
+            }
+            commands.spawn(SimSecondaryParticle {
+                position: particle.position,
+                velocity: particle.velocity,
+                kind: classify_position(grid, particle.position),
+                lifetime: SECONDARY_LIFETIME,
+            });
+            secondary_count += 1;
+        }
+    }
+
+    for (id, mut secondary) in secondary_particles.iter_mut() {
+        move_secondary_particle(constraints, grid, secondary.as_mut(), delta_time);
+        if secondary.lifetime <= 0.0 || classify_cell(grid, secondary.position).is_none() {
+            commands.entity(id).despawn();
+        }
+    }
+}
+
+/** How much foam, spray, and bubbles a fluid particle throws off, from 0 (none) to 1 (as much as
+possible). */
+fn calculate_spawn_potential(grid: &SimGrid, particle: &SimParticle) -> f32 {
+    let speed_potential: f32 = ((particle.velocity.length() - SECONDARY_MIN_SPEED)
+        / (SECONDARY_FULL_SPEED - SECONDARY_MIN_SPEED))
+        .clamp(0.0, 1.0);
+    if speed_potential <= 0.0 || !grid.is_position_within_grid(&particle.position) {
+        return 0.0;
+    }
+
+    // Surface particles throw off more the more air is around them; buried ones only trap a little.
+    let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+    let exposure: f32 = count_air_neighbors(grid, cell.x as usize, cell.y as usize) as f32 / 8.0;
+    if exposure > 0.0 {
+        speed_potential * exposure
+    } else {
+        speed_potential * 0.1
+    }
+}
+
+/// Count the air cells among a cell's eight neighbors.
+fn count_air_neighbors(grid: &SimGrid, row: usize, col: usize) -> usize {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let mut air_count: usize = 0;
+    for neighbor_row in row.saturating_sub(1)..=(row + 1).min(rows - 1) {
+        for neighbor_col in col.saturating_sub(1)..=(col + 1).min(cols - 1) {
+            if (neighbor_row, neighbor_col) != (row, col)
+                && grid.cell_type[neighbor_row][neighbor_col] == SimGridCellType::Air
+            {
+                air_count += 1;
+            }
+        }
+    }
+    air_count
+}
+
+/** What a secondary particle at `position` would be; None if it's off the grid or inside a
+wall. */
+fn classify_cell(grid: &SimGrid, position: Vec2) -> Option<SimSecondaryKind> {
+    if !grid.is_position_within_grid(&position) {
+        return None;
+    }
+
+    let cell: Vec2 = grid.get_cell_coordinates_from_position(&position);
+    let (row, col) = (cell.x as usize, cell.y as usize);
+    match grid.cell_type[row][col] {
+        SimGridCellType::Solid => None,
+        SimGridCellType::Air | SimGridCellType::Porous(_) => Some(SimSecondaryKind::Spray),
+        SimGridCellType::Fluid if count_air_neighbors(grid, row, col) > 0 => {
+            Some(SimSecondaryKind::Foam)
+        }
+        SimGridCellType::Fluid => Some(SimSecondaryKind::Bubble),
+    }
+}
+
+/// What a secondary particle at `position` would be, treating walls and the grid's edges as air.
+fn classify_position(grid: &SimGrid, position: Vec2) -> SimSecondaryKind {
+    classify_cell(grid, position).unwrap_or(SimSecondaryKind::Spray)
+}
+
+/** Move a secondary particle by the rules for whatever it currently is: spray falls freely, foam
+drifts with the fluid, and bubbles are carried along by the fluid as they float up
+through it. */
+fn move_secondary_particle(
+    constraints: &SimConstraints,
+    grid: &SimGrid,
+    secondary: &mut SimSecondaryParticle,
+    delta_time: f32,
+) {
+    secondary.kind = classify_position(grid, secondary.position);
+    let fluid_velocity: Option<Vec2> = sample_grid_velocity(grid, secondary.position);
+
+    match secondary.kind {
+        SimSecondaryKind::Spray => {
+            secondary.velocity += constraints.gravity * delta_time;
+        }
+        SimSecondaryKind::Foam => {
+            secondary.velocity = fluid_velocity.unwrap_or(secondary.velocity);
+        }
+        SimSecondaryKind::Bubble => {
+            let up: Vec2 = -constraints.gravity.normalize_or_zero();
+            secondary.velocity = fluid_velocity.unwrap_or(Vec2::ZERO) + up * BUBBLE_RISE_SPEED;
+        }
+    }
+
+    secondary.position += secondary.velocity * delta_time;
+    secondary.lifetime -= delta_time;
+}
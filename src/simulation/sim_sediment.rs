@@ -0,0 +1,182 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use super::sim_physics_engine::sample_grid_velocity;
+use super::sim_state_manager::{add_particle, delete_particle};
+use super::{
+    SimConstraints, SimFluidMaterial, SimGrid, SimGridCellType, SimParticle, SimWallMaterial,
+    AMBIENT_TEMPERATURE,
+};
+
+/// Sediment particles a sand cell wears away into, and that settle back into one.
+pub const SEDIMENT_PER_CELL: usize = 4;
+/// Seconds it takes flow at twice `constraints.erosion_speed` to wear away a sand cell.
+const EROSION_TIME: f32 = 1.0;
+/// Where sediment is placed within a freshly eroded cell, relative to its center and in cells.
+const SEDIMENT_OFFSETS: [Vec2; SEDIMENT_PER_CELL] = [
+    Vec2::new(-0.25, -0.25),
+    Vec2::new(0.25, 0.25),
+    Vec2::new(0.25, -0.25),
+    Vec2::new(-0.25, 0.25),
+];
+
+/** Carve sand walls away with fast flow and build them back up from calm sediment.  Sand cells
+wear away while the fluid beside them flows faster than `constraints.erosion_speed`, and crumble
+into sediment particles carried along by the flow.  Sediment slower than
+`constraints.deposit_speed` settles on whatever solid is beneath it (along gravity), and every
+`SEDIMENT_PER_CELL` settled particles in a cell pack back into sand. */
+pub fn transport_sediment<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    delta_time: f32,
+) {
+    erode_sand(commands, constraints, grid, delta_time);
+    deposit_sediment(commands, constraints, grid, particles);
+}
+
+/** Velocity of the fastest fluid flowing past a cell, sampled at the centers of its fluid
+neighbors; None if none of its neighbors are fluid. */
+fn calculate_passing_flow(grid: &SimGrid, row: usize, col: usize) -> Option<Vec2> {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let neighbors: [(usize, usize); 4] = [
+        (usize::wrapping_sub(row, 1), col),
+        (row + 1, col),
+        (row, usize::wrapping_sub(col, 1)),
+        (row, col + 1),
+    ];
+
+    neighbors
+        .into_iter()
+        .filter(|(neighbor_row, neighbor_col)| {
+            *neighbor_row < rows
+                && *neighbor_col < cols
+                && grid.cell_type[*neighbor_row][*neighbor_col] == SimGridCellType::Fluid
+        })
+        .filter_map(|(neighbor_row, neighbor_col)| {
+            let center: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(
+                neighbor_row as f32,
+                neighbor_col as f32,
+            ));
+            sample_grid_velocity(grid, center)
+        })
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+}
+
+/// Wear down sand cells under fast flow, crumbling the ones that wear through into sediment.
+fn erode_sand(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    delta_time: f32,
+) {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    grid.erosion.resize(rows * cols, 0.0);
+    if constraints.erosion_speed <= 0.0 {
+        return;
+    }
+
+    let mut eroded: Vec<(usize, usize, Vec2)> = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            if grid.cell_type[row][col] != SimGridCellType::Solid
+                || grid.get_wall_material(row, col) != SimWallMaterial::Sand
+            {
+                continue;
+            }
+            let Some(flow) = calculate_passing_flow(grid, row, col) else {
+                continue;
+            };
+
+            // Faster flow wears sand away faster; flow slower than the erosion speed leaves it be.
+            let wear: f32 = flow.length() / constraints.erosion_speed - 1.0;
+            if wear <= 0.0 {
+                continue;
+            }
+            let erosion: &mut f32 = &mut grid.erosion[row * cols + col];
+            *erosion += wear * delta_time / EROSION_TIME;
+            if *erosion >= 1.0 {
+                eroded.push((row, col, flow));
+            }
+        }
+    }
+
+    let cell_size: f32 = grid.cell_size as f32;
+    for (row, col, flow) in eroded {
+        let _ = grid.set_grid_cell_type(row, col, SimGridCellType::Air);
+        let center: Vec2 =
+            grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32));
+        for offset in SEDIMENT_OFFSETS {
+            let _ = add_particle(
+                commands,
+                constraints,
+                grid,
+                center + offset * cell_size,
+                flow,
+                AMBIENT_TEMPERATURE,
+                0,
+                SimFluidMaterial::Sediment,
+            );
+        }
+    }
+}
+
+/** Pack slow sediment resting on solid ground back into sand, one cell for every
+`SEDIMENT_PER_CELL` particles settled in it. */
+fn deposit_sediment<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+) {
+    // Without gravity, nothing settles anywhere.
+    let down: Vec2 = constraints.gravity.normalize_or_zero();
+    if down == Vec2::ZERO {
+        return;
+    }
+
+    // Gather up settled sediment by the cell it has settled in.
+    let cell_size: f32 = grid.cell_size as f32;
+    let mut settled: HashMap<(usize, usize), Vec<Entity>> = HashMap::new();
+    for (id, particle) in particles.iter() {
+        if particle.material != SimFluidMaterial::Sediment
+            || particle.velocity.length() > constraints.deposit_speed
+            || grid.is_particle_pending_removal(id)
+        {
+            continue;
+        }
+
+        let below: Vec2 = particle.position + down * cell_size;
+        if !grid.is_position_within_grid(&particle.position)
+            || !grid.is_position_within_grid(&below)
+        {
+            continue;
+        }
+        let below_cell: Vec2 = grid.get_cell_coordinates_from_position(&below);
+        if grid.cell_type[below_cell.x as usize][below_cell.y as usize] != SimGridCellType::Solid {
+            continue;
+        }
+
+        let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        settled
+            .entry((cell.x as usize, cell.y as usize))
+            .or_default()
+            .push(id);
+    }
+
+    for ((row, col), ids) in settled {
+        if ids.len() < SEDIMENT_PER_CELL || grid.cell_type[row][col] == SimGridCellType::Solid {
+            continue;
+        }
+
+        for id in ids.into_iter().take(SEDIMENT_PER_CELL) {
+            let _ = delete_particle(commands, constraints, particles, grid, id);
+        }
+        let _ = grid.set_grid_cell_type(row, col, SimGridCellType::Solid);
+        let _ = grid.set_wall_material(row, col, SimWallMaterial::Sand);
+    }
+}
@@ -0,0 +1,226 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_state_manager::{add_faucet, delete_all_faucets};
+use super::{
+    Result, SimConstraints, SimFaucet, SimFaucetSchedule, SimFaucetShape, SimFluidMaterial,
+    SimGrid, SimGridCellType,
+};
+use crate::error::Error;
+
+pub const SEQUENCER_ACTION_COUNT: usize = 4;
+
+/// Something the sequencer does to the scene once playback reaches its keyframe.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum SimSequencerAction {
+    // Start pouring from a new faucet.
+    AddFaucet { position: Vec2, velocity: Vec2 },
+    // Stop every faucet in the scene.
+    RemoveFaucets,
+    // Knock out the (interior) walls within a rectangle.
+    OpenWalls { corner: Vec2, opposite_corner: Vec2 },
+    // Point gravity somewhere new.
+    SetGravity(Vec2),
+}
+
+impl SimSequencerAction {
+    /// Default action of each kind, in the order they are listed in the sequencer panel.
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            0 => Self::AddFaucet {
+                position: Vec2::ZERO,
+                velocity: Vec2::ZERO,
+            },
+            1 => Self::RemoveFaucets,
+            2 => Self::OpenWalls {
+                corner: Vec2::ZERO,
+                opposite_corner: Vec2::ZERO,
+            },
+            3 => Self::SetGravity(Vec2::ZERO),
+            _ => {
+                eprintln!("Invalid SimSequencerAction; defaulting to RemoveFaucets!");
+                Self::RemoveFaucets
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AddFaucet { .. } => "Add Faucet",
+            Self::RemoveFaucets => "Remove Faucets",
+            Self::OpenWalls { .. } => "Open Walls",
+            Self::SetGravity(_) => "Set Gravity",
+        }
+    }
+
+    /// Describe the action (along with its parameters) for the sequencer panel.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::AddFaucet { position, velocity } => format!(
+                "{} at ({:.0}, {:.0}) pouring ({:.0}, {:.0})",
+                self.as_str(),
+                position.x,
+                position.y,
+                velocity.x,
+                velocity.y
+            ),
+            Self::RemoveFaucets => self.as_str().to_owned(),
+            Self::OpenWalls {
+                corner,
+                opposite_corner,
+            } => format!(
+                "{} from ({:.0}, {:.0}) to ({:.0}, {:.0})",
+                self.as_str(),
+                corner.x,
+                corner.y,
+                opposite_corner.x,
+                opposite_corner.y
+            ),
+            Self::SetGravity(gravity) => {
+                format!("{} to ({:.0}, {:.0})", self.as_str(), gravity.x, gravity.y)
+            }
+        }
+    }
+}
+
+/// An action scheduled to happen `time` simulated seconds after the scene starts.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct SimKeyframe {
+    pub time: f32,
+    pub action: SimSequencerAction,
+}
+
+/** The scene's timeline.  Keyframes are saved with the scene and played back in order as the
+simulation runs; resetting the simulation plays the timeline back from the start. */
+#[derive(Resource, Clone, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct SimSequencer {
+    pub keyframes: Vec<SimKeyframe>, // Sorted by time.
+
+    // Index of the next keyframe to fire.
+    #[reflect(ignore)]
+    next_keyframe: usize,
+    // Simulated time playback has reached.
+    #[reflect(ignore)]
+    time: f32,
+}
+
+impl SimSequencer {
+    /// Simulated seconds since playback started.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /** Schedule a keyframe, keeping the timeline sorted.  Keyframes scheduled for a time playback
+    has already passed will fire the next time the timeline is played back. */
+    pub fn add_keyframe(&mut self, keyframe: SimKeyframe) {
+        let index: usize = self
+            .keyframes
+            .partition_point(|existing| existing.time <= keyframe.time);
+        if index < self.next_keyframe || keyframe.time < self.time {
+            self.next_keyframe += 1;
+        }
+        self.keyframes.insert(index, keyframe);
+    }
+
+    /// Remove a keyframe from the timeline.
+    pub fn remove_keyframe(&mut self, index: usize) -> Result<SimKeyframe> {
+        if index >= self.keyframes.len() {
+            return Err(Error::InvalidKeyframe("Keyframe index is out of range!"));
+        }
+
+        if index < self.next_keyframe {
+            self.next_keyframe -= 1;
+        }
+        Ok(self.keyframes.remove(index))
+    }
+
+    /// Play the timeline back from the start.
+    pub fn restart(&mut self) {
+        self.next_keyframe = 0;
+        self.time = 0.0;
+    }
+
+    /// Jump playback to `time` simulated seconds without firing any of the keyframes before it.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+        self.next_keyframe = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+    }
+
+    /** Advance playback to `time` simulated seconds, returning the actions that came due (in
+    order).  If time went backwards, the simulation was reset, so playback restarts. */
+    pub fn advance(&mut self, time: f32) -> Vec<SimSequencerAction> {
+        if time < self.time {
+            self.restart();
+        }
+        self.time = time;
+
+        let mut due_actions: Vec<SimSequencerAction> = Vec::new();
+        while let Some(keyframe) = self.keyframes.get(self.next_keyframe) {
+            if keyframe.time > time {
+                break;
+            }
+            due_actions.push(keyframe.action);
+            self.next_keyframe += 1;
+        }
+
+        due_actions
+    }
+}
+
+/// Carry out a keyframe's action.
+pub fn apply_sequencer_action<D: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    faucets: &Query<(Entity, &mut SimFaucet), D>,
+    action: SimSequencerAction,
+) -> Result<()> {
+    match action {
+        SimSequencerAction::AddFaucet { position, velocity } => add_faucet(
+            commands,
+            grid,
+            position,
+            None,
+            1.0,
+            velocity,
+            Vec::new(),
+            0.0,
+            0,
+            SimFluidMaterial::Water,
+            SimFaucetShape::Point,
+            0.0,
+            SimFaucetSchedule::default(),
+        ),
+        SimSequencerAction::RemoveFaucets => {
+            delete_all_faucets(commands, faucets);
+            Ok(())
+        }
+        SimSequencerAction::OpenWalls {
+            corner,
+            opposite_corner,
+        } => {
+            // The grid's border always stays solid.
+            let corner_cell: Vec2 = grid.get_cell_coordinates_from_position(&corner);
+            let opposite_cell: Vec2 = grid.get_cell_coordinates_from_position(&opposite_corner);
+            let min_cell: Vec2 = corner_cell.min(opposite_cell).max(Vec2::ONE);
+            let max_cell: Vec2 = corner_cell.max(opposite_cell).min(Vec2::new(
+                grid.dimensions.0 as f32 - 2.0,
+                grid.dimensions.1 as f32 - 2.0,
+            ));
+
+            for row in min_cell.x as usize..=max_cell.x as usize {
+                for col in min_cell.y as usize..=max_cell.y as usize {
+                    grid.set_grid_cell_type(row, col, SimGridCellType::Air)?;
+                }
+            }
+            Ok(())
+        }
+        SimSequencerAction::SetGravity(gravity) => {
+            constraints.gravity = gravity;
+            Ok(())
+        }
+    }
+}
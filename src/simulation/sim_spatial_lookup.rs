@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+/** The particles inside each cell of the grid, by lookup index.  Every cell's particles sit side by
+side in one flat array, with `cell_starts` marking where each cell's run begins (compressed sparse
+row style), so looking up a cell hands back a slice instead of a fresh Vec.  Once the particles have
+moved each step the whole table is rebuilt in place, reusing its allocations.  Particles spawned or
+removed in between are queued up and slotted in (or taken out) by that rebuild, rather than shifting
+the whole flat array over for each one; until then, the table still shows them where they were. */
+#[derive(Clone, Debug, Default)]
+pub struct SimSpatialLookup {
+    cell_starts: Vec<usize>, // Where each cell's run starts in `particles`, plus where the last ends.
+    particles: Vec<Entity>,  // Every cell's particles, one cell after another.
+    entries: Vec<(Entity, usize)>, // Each particle and its cell, while rebuilding.
+    queued_inserts: Vec<(Entity, usize)>, // Particles to add at the next rebuild, and their cells.
+    queued_removals: HashSet<Entity>, // Particles to take out at the next rebuild.
+}
+
+impl PartialEq for SimSpatialLookup {
+    fn eq(&self, other: &Self) -> bool {
+        self.cell_starts == other.cell_starts
+            && self.particles == other.particles
+            && self.queued_inserts == other.queued_inserts
+            && self.queued_removals == other.queued_removals
+    }
+}
+
+impl SimSpatialLookup {
+    /// An empty lookup table for `cell_count` cells.
+    pub fn new(cell_count: usize) -> Self {
+        Self {
+            cell_starts: vec![0; cell_count + 1],
+            ..default()
+        }
+    }
+
+    /// Number of cells in the table.
+    pub fn cell_count(&self) -> usize {
+        self.cell_starts.len().saturating_sub(1)
+    }
+
+    /// Number of particles in the table, not counting those queued to be added or removed.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Whether the table has no particles in it.
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// The particles in the cell at `lookup_index`; none if it's out of bounds.
+    pub fn cell(&self, lookup_index: usize) -> &[Entity] {
+        if lookup_index >= self.cell_count() {
+            return &[];
+        }
+        &self.particles[self.cell_starts[lookup_index]..self.cell_starts[lookup_index + 1]]
+    }
+
+    /** Queue a particle to be added to the end of the cell at `lookup_index` at the next rebuild.
+    Returns false (leaving the table alone) if the cell is out of bounds. */
+    pub fn insert(&mut self, particle_id: Entity, lookup_index: usize) -> bool {
+        if lookup_index >= self.cell_count() {
+            return false;
+        }
+        self.queued_inserts.push((particle_id, lookup_index));
+        true
+    }
+
+    /** Queue a particle to be taken out of whichever cell it's in at the next rebuild, along with
+    any additions of it queued so far.  Adding it again afterwards puts it back in. */
+    pub fn remove(&mut self, particle_id: Entity) {
+        self.queued_inserts.retain(|(id, _)| *id != particle_id);
+        self.queued_removals.insert(particle_id);
+    }
+
+    /// Whether any particles are waiting on a rebuild to be added or removed.
+    pub fn has_queued_changes(&self) -> bool {
+        !self.queued_inserts.is_empty() || !self.queued_removals.is_empty()
+    }
+
+    /** Swap every particle in the table (or queued to be added or removed) that `entity_map` has an
+    entry for with the entity it maps to. */
+    pub fn map_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        let map = |id: Entity| *entity_map.get(&id).unwrap_or(&id);
+        for id in self.particles.iter_mut() {
+            *id = map(*id);
+        }
+        for (id, _) in self.queued_inserts.iter_mut() {
+            *id = map(*id);
+        }
+        self.queued_removals = self.queued_removals.drain().map(map).collect();
+    }
+
+    /** Sort the particles in each cell by the key `sort_key` gives them (ties broken by entity), so
+    the order they're found in doesn't depend on the order they were added in. */
+    pub fn sort_cells<K: Ord>(&mut self, sort_key: impl Fn(Entity) -> K) {
+        for cell in self.cell_starts.windows(2) {
+            self.particles[cell[0]..cell[1]].sort_unstable_by_key(|id| (sort_key(*id), *id));
+        }
+    }
+
+    /** Rebuild the table for `cell_count` cells from scratch, with each of `entries` (a particle and
+    the lookup index of its cell) in its cell.  Particles already in the table (or queued to be
+    added) that `keep` holds on to go first, in the cells they were in, unless they're queued to be
+    removed; the rest are dropped, as are entries whose cell is out of bounds.  Within each cell,
+    particles keep the order they were given in. */
+    pub fn rebuild(
+        &mut self,
+        cell_count: usize,
+        entries: impl IntoIterator<Item = (Entity, usize)>,
+        mut keep: impl FnMut(Entity) -> bool,
+    ) {
+        // Reuse the scratch list (and the table itself) so a rebuild doesn't allocate.
+        let mut all_entries: Vec<(Entity, usize)> = std::mem::take(&mut self.entries);
+        all_entries.clear();
+        for lookup_index in 0..self.cell_count() {
+            all_entries.extend(
+                self.cell(lookup_index)
+                    .iter()
+                    .filter(|id| !self.queued_removals.contains(*id) && keep(**id))
+                    .map(|id| (*id, lookup_index)),
+            );
+        }
+        all_entries.extend(self.queued_inserts.drain(..).filter(|(id, _)| keep(*id)));
+        self.queued_removals.clear();
+        all_entries.extend(entries);
+        all_entries.retain(|(_, lookup_index)| *lookup_index < cell_count);
+
+        // Count each cell's particles, then add them up into where each cell's run ends.
+        self.cell_starts.clear();
+        self.cell_starts.resize(cell_count + 1, 0);
+        for (_, lookup_index) in all_entries.iter() {
+            self.cell_starts[lookup_index + 1] += 1;
+        }
+        for lookup_index in 0..cell_count {
+            self.cell_starts[lookup_index + 1] += self.cell_starts[lookup_index];
+        }
+
+        /* Fill each run from its end back, so every end is walked back to its cell's start; then
+        shift them over to line up with their cells again. */
+        self.particles.clear();
+        self.particles
+            .resize(all_entries.len(), Entity::PLACEHOLDER);
+        for (particle_id, lookup_index) in all_entries.iter().rev() {
+            self.cell_starts[lookup_index + 1] -= 1;
+            self.particles[self.cell_starts[lookup_index + 1]] = *particle_id;
+        }
+        self.cell_starts.copy_within(1.., 0);
+        self.cell_starts[cell_count] = all_entries.len();
+
+        self.entries = all_entries;
+    }
+}
@@ -0,0 +1,312 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::f32::consts::PI;
+
+use super::sim_physics_engine::{
+    integrate_particle_with_collisions, particle_visit_order, rebuild_particle_lookup,
+};
+use super::{SimConstraints, SimGrid, SimParticle};
+
+pub const SOLVER_KIND_COUNT: usize = 2;
+
+/// Smoothing radius of the SPH kernels, in particle radii.
+pub const SPH_SMOOTHING_RADII: f32 = 4.0;
+/// Speed of sound in the SPH fluid; stiffer fluids compress less, but need smaller steps.
+pub const SPH_SOUND_SPEED: f32 = 300.0;
+/// Viscosity every SPH fluid has, on top of the fluid's own; damps out the solver's jitter.
+pub const SPH_BASE_VISCOSITY: f32 = 40.0;
+/// Fraction of a smoothing radius a particle may travel (or a pressure wave cross) per substep.
+const SPH_CFL: f32 = 0.4;
+
+/// Method used to move the fluid each step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum SimSolverKind {
+    // Particles carry the fluid, and a grid makes it incompressible.
+    #[default]
+    Flip = 0,
+    // Particles push each other apart based on how crowded they are; no grid needed.
+    Sph,
+}
+
+impl Into<SimSolverKind> for usize {
+    fn into(self) -> SimSolverKind {
+        match self {
+            0 => SimSolverKind::Flip,
+            1 => SimSolverKind::Sph,
+            _ => {
+                eprintln!("Invalid SimSolverKind; defaulting to Flip!");
+                SimSolverKind::Flip
+            }
+        }
+    }
+}
+
+impl SimSolverKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Flip => "FLIP",
+            Self::Sph => "SPH",
+        }
+    }
+}
+
+/// A particle's state, copied out of its component for the duration of an SPH step.
+struct SphParticle {
+    id: Entity,
+    position: Vec2,
+    velocity: Vec2,
+    mass: f32,
+    density: f32,
+    pressure: f32,
+}
+
+/** Step the fluid with weakly compressible smoothed particle hydrodynamics.  Each particle's density
+is estimated from the particles around it (found through the grid's spatial lookup), crowded
+particles push their neighbors away, and viscosity evens out their velocities.  The step is split
+into substeps whenever it would be too large to integrate stably.  Afterwards, the grid's density
+and temperature are refreshed so everything reading them keeps working. */
+pub fn step_sph<F: ReadOnlyWorldQuery>(
+    constraints: &SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    delta_time: f32,
+) {
+    grid.update_solid_distance();
+
+    let smoothing_radius: f32 = constraints.particle_radius * SPH_SMOOTHING_RADII;
+    let rest_density: f32 = calculate_rest_density(constraints.particle_radius, smoothing_radius);
+    let viscosity: f32 = SPH_BASE_VISCOSITY + constraints.viscosity;
+
+    let mut sph_particles: Vec<SphParticle> = particles
+        .iter_many(particle_visit_order(constraints, particles))
+        .map(|(id, particle)| SphParticle {
+            id,
+            position: particle.position,
+            velocity: particle.velocity,
+            mass: particle.material.density() * particle.mass,
+            density: 0.0,
+            pressure: 0.0,
+        })
+        .collect();
+    let indices: HashMap<Entity, usize> = sph_particles
+        .iter()
+        .enumerate()
+        .map(|(index, particle)| (particle.id, index))
+        .collect();
+    let order: Vec<Entity> = sph_particles.iter().map(|particle| particle.id).collect();
+
+    // Take as many substeps as it takes to keep both the particles and pressure waves in check.
+    let mut remaining_time: f32 = delta_time;
+    while remaining_time > 0.0 {
+        let max_speed: f32 = sph_particles
+            .iter()
+            .map(|particle| particle.velocity.length())
+            .fold(0.0, f32::max);
+        let max_substep: f32 = SPH_CFL * smoothing_radius / (SPH_SOUND_SPEED + max_speed);
+        let substep: f32 = remaining_time.min(max_substep);
+        remaining_time -= substep;
+
+        // Particles are in spawn order in deterministic mode, so their indices sort the same way.
+        if constraints.deterministic {
+            grid.sort_spatial_lookup(|id| indices.get(&id).map_or(u64::MAX, |index| *index as u64));
+        }
+        let neighbors: Vec<Vec<usize>> =
+            find_neighbors(grid, &sph_particles, &indices, smoothing_radius);
+        calculate_densities(
+            &mut sph_particles,
+            &neighbors,
+            smoothing_radius,
+            rest_density,
+        );
+        let accelerations: Vec<Vec2> = calculate_accelerations(
+            &sph_particles,
+            &neighbors,
+            smoothing_radius,
+            viscosity,
+            constraints.gravity,
+        );
+
+        // Integrate the particles while handling collisions, then update the spatial lookup.
+        for (sph_particle, acceleration) in sph_particles.iter_mut().zip(accelerations) {
+            let Ok((_, mut particle)) = particles.get_mut(sph_particle.id) else {
+                continue;
+            };
+            let target_velocity: Vec2 = sph_particle.velocity + acceleration * substep;
+            let target_position: Vec2 = sph_particle.position + target_velocity * substep;
+            integrate_particle_with_collisions(
+                grid,
+                constraints,
+                particle.as_mut(),
+                &target_position,
+                &target_velocity,
+            );
+
+            sph_particle.position = particle.position;
+            sph_particle.velocity = particle.velocity;
+        }
+        rebuild_particle_lookup(grid, particles, &order);
+    }
+
+    // Refresh the grid's density and temperature for the rest of the simulation.
+    grid.clear_density_values();
+    grid.clear_temperature_values();
+    for id in particle_visit_order(constraints, particles) {
+        let Ok((_, mut particle)) = particles.get_mut(id) else {
+            continue;
+        };
+        particle.age += delta_time;
+        grid.update_grid_density(particle.position, particle.material, particle.mass);
+        grid.update_grid_temperature(particle.position, particle.temperature);
+    }
+    grid.normalize_temperature_values();
+}
+
+/** Density of a fluid at rest, per unit of particle mass: the kernel summed over a square lattice of
+particles packed as tightly as their radii allow. */
+fn calculate_rest_density(particle_radius: f32, smoothing_radius: f32) -> f32 {
+    let spacing: f32 = particle_radius * 2.0;
+    let reach: i32 = (smoothing_radius / spacing).ceil() as i32;
+    let mut density: f32 = 0.0;
+    for row in -reach..=reach {
+        for col in -reach..=reach {
+            let offset: Vec2 = Vec2::new(col as f32, row as f32) * spacing;
+            density += poly6_kernel(offset.length_squared(), smoothing_radius);
+        }
+    }
+    density
+}
+
+/// Find the particles within a smoothing radius of each particle (including itself).
+fn find_neighbors(
+    grid: &SimGrid,
+    sph_particles: &[SphParticle],
+    indices: &HashMap<Entity, usize>,
+    smoothing_radius: f32,
+) -> Vec<Vec<usize>> {
+    let (rows, cols) = (grid.dimensions.0 as i32, grid.dimensions.1 as i32);
+    let reach: i32 = (smoothing_radius / grid.cell_size as f32).ceil() as i32;
+    let smoothing_radius_squared: f32 = smoothing_radius * smoothing_radius;
+
+    sph_particles
+        .iter()
+        .map(|particle| {
+            let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+            let mut neighbors: Vec<usize> = Vec::new();
+            for row in (cell.x as i32 - reach).max(0)..=(cell.x as i32 + reach).min(rows - 1) {
+                for col in (cell.y as i32 - reach).max(0)..=(cell.y as i32 + reach).min(cols - 1) {
+                    let lookup_index: usize =
+                        grid.get_lookup_index(Vec2::new(row as f32, col as f32));
+                    for id in grid.get_particles_in_lookup(lookup_index).iter() {
+                        let Some(neighbor) = indices.get(id) else {
+                            continue;
+                        };
+                        if particle
+                            .position
+                            .distance_squared(sph_particles[*neighbor].position)
+                            < smoothing_radius_squared
+                        {
+                            neighbors.push(*neighbor);
+                        }
+                    }
+                }
+            }
+            neighbors
+        })
+        .collect()
+}
+
+/** Estimate each particle's density from its neighbors, and the pressure that pushes it back towards
+its rest density.  Pressure never pulls, so the fluid's surface doesn't clump together. */
+fn calculate_densities(
+    sph_particles: &mut [SphParticle],
+    neighbors: &[Vec<usize>],
+    smoothing_radius: f32,
+    rest_density: f32,
+) {
+    for index in 0..sph_particles.len() {
+        let position: Vec2 = sph_particles[index].position;
+        let density: f32 = neighbors[index]
+            .iter()
+            .map(|neighbor| {
+                let neighbor: &SphParticle = &sph_particles[*neighbor];
+                neighbor.mass
+                    * poly6_kernel(
+                        position.distance_squared(neighbor.position),
+                        smoothing_radius,
+                    )
+            })
+            .sum();
+
+        let particle: &mut SphParticle = &mut sph_particles[index];
+        let particle_rest_density: f32 = rest_density * particle.mass;
+        particle.density = density.max(f32::EPSILON);
+        particle.pressure =
+            (SPH_SOUND_SPEED * SPH_SOUND_SPEED * (density - particle_rest_density)).max(0.0);
+    }
+}
+
+/// Sum the pressure, viscosity, and gravity accelerating each particle.
+fn calculate_accelerations(
+    sph_particles: &[SphParticle],
+    neighbors: &[Vec<usize>],
+    smoothing_radius: f32,
+    viscosity: f32,
+    gravity: Vec2,
+) -> Vec<Vec2> {
+    sph_particles
+        .iter()
+        .zip(neighbors)
+        .map(|(particle, neighbors)| {
+            let mut acceleration: Vec2 = gravity;
+            let particle_pressure: f32 = particle.pressure / (particle.density * particle.density);
+            for neighbor in neighbors.iter().map(|neighbor| &sph_particles[*neighbor]) {
+                let offset: Vec2 = particle.position - neighbor.position;
+                let distance: f32 = offset.length();
+                if distance <= f32::EPSILON {
+                    continue;
+                }
+
+                // Crowded particles push each other apart...
+                let neighbor_pressure: f32 =
+                    neighbor.pressure / (neighbor.density * neighbor.density);
+                acceleration -= neighbor.mass
+                    * (particle_pressure + neighbor_pressure)
+                    * spiky_kernel_gradient(offset, distance, smoothing_radius);
+
+                // ...and drag each other along.
+                acceleration += viscosity * neighbor.mass * (neighbor.velocity - particle.velocity)
+                    / neighbor.density
+                    * viscosity_kernel_laplacian(distance, smoothing_radius);
+            }
+            acceleration
+        })
+        .collect()
+}
+
+/// Smoothing kernel used to estimate density, from the squared distance between two particles.
+fn poly6_kernel(distance_squared: f32, smoothing_radius: f32) -> f32 {
+    let smoothing_radius_squared: f32 = smoothing_radius * smoothing_radius;
+    if distance_squared >= smoothing_radius_squared {
+        return 0.0;
+    }
+    4.0 / (PI * smoothing_radius_squared.powi(4))
+        * (smoothing_radius_squared - distance_squared).powi(3)
+}
+
+/// Gradient of the kernel used for pressure; stays steep up close so particles never overlap.
+fn spiky_kernel_gradient(offset: Vec2, distance: f32, smoothing_radius: f32) -> Vec2 {
+    if distance >= smoothing_radius {
+        return Vec2::ZERO;
+    }
+    -30.0 / (PI * smoothing_radius.powi(5)) * (smoothing_radius - distance).powi(2) * offset
+        / distance
+}
+
+/// Laplacian of the kernel used for viscosity.
+fn viscosity_kernel_laplacian(distance: f32, smoothing_radius: f32) -> f32 {
+    if distance >= smoothing_radius {
+        return 0.0;
+    }
+    40.0 / (PI * smoothing_radius.powi(5)) * (smoothing_radius - distance)
+}
@@ -0,0 +1,139 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_physics_engine::particle_visit_order;
+use super::sim_state_manager::delete_particle;
+use super::{SimConstraints, SimGrid, SimParticle};
+
+/** Cells a particle may cross in one step before the simulation counts as unstable.  Well past the
+usual CFL limit of one cell, since FLIP copes with a little overshoot; anything beyond this is a
+velocity explosion in the making. */
+pub const STABILITY_CFL_LIMIT: f32 = 3.0;
+/// Smallest timestep the stability guard will shrink the simulation's timestep to.
+pub const MIN_STABLE_TIMESTEP: f32 = 1.0 / 960.0;
+/// Steps the simulation must stay stable for before the guard undoes one of its recoveries.
+pub const CALM_STEPS_BEFORE_RELAXING: u32 = 600;
+
+/** Keeps track of what the stability guard has changed to keep the simulation from blowing up, so
+it can be undone once things settle down again. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimStabilityGuard {
+    pub original_timestep: Option<f32>, // Timestep before the guard first shrank it.
+    pub original_iterations: Option<u8>, // Solver iterations before the guard first raised them.
+    pub calm_steps: u32,                // Steps since the simulation was last unstable.
+    pub max_cfl: f32,                   // Cells the fastest particle crossed in the last step.
+    pub warning: Option<String>,        // What the guard did, until the UI picks it up.
+}
+
+impl SimStabilityGuard {
+    /// Whether the guard currently has the timestep or solver iterations changed.
+    pub fn is_recovering(&self) -> bool {
+        self.original_timestep.is_some() || self.original_iterations.is_some()
+    }
+
+    /// Describe what the guard last did for the user (if anything), then forget about it.
+    pub fn take_warning(&mut self) -> Option<String> {
+        self.warning.take()
+    }
+}
+
+/** Watch for the simulation blowing up, and automatically recover from it.  Particles whose
+positions or velocities are no longer numbers are removed, and if any are found (or the fastest
+particle crossed more than `STABILITY_CFL_LIMIT` cells this step), the timestep is halved; once it
+can't be halved any further, the pressure solver gets more iterations instead.  After the
+simulation has been calm for `CALM_STEPS_BEFORE_RELAXING` steps, these changes are undone one at a
+time.  Whatever the guard does is left in `constraints.stability.warning` for the UI. */
+pub fn guard_stability<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    timestep: f32,
+) {
+    // Particles that have gone NaN can't be saved, and would poison the grid on the next step.
+    let mut broken_count: usize = 0;
+    let mut max_speed: f32 = 0.0;
+    for id in particle_visit_order(constraints, particles) {
+        let Ok((_, particle)) = particles.get(id) else {
+            continue;
+        };
+        if particle.position.is_finite() && particle.velocity.is_finite() {
+            max_speed = max_speed.max(particle.velocity.length());
+            continue;
+        }
+        if !grid.is_particle_pending_removal(id) {
+            let _ = delete_particle(commands, constraints, particles, grid, id);
+            broken_count += 1;
+        }
+    }
+
+    let cfl: f32 = max_speed * timestep / grid.cell_size as f32;
+    constraints.stability.max_cfl = cfl;
+    if broken_count == 0 && cfl <= STABILITY_CFL_LIMIT {
+        relax_recovery(constraints);
+        return;
+    }
+
+    let recovery: String = recover_stability(constraints);
+    let cause: String = if broken_count > 0 {
+        format!("{} particles blew up", broken_count)
+    } else {
+        format!("fluid moved {:.1} cells in one step", cfl)
+    };
+    constraints.stability.warning = Some(format!("Unstable ({}); {}.", cause, recovery));
+}
+
+/// Make the simulation more stable, and describe how it was done.
+fn recover_stability(constraints: &mut SimConstraints) -> String {
+    let guard: &mut SimStabilityGuard = &mut constraints.stability;
+    guard.calm_steps = 0;
+
+    // A smaller timestep is the surest cure, as long as there is room to shrink it.
+    if constraints.timestep * 0.5 >= MIN_STABLE_TIMESTEP {
+        guard.original_timestep.get_or_insert(constraints.timestep);
+        constraints.timestep *= 0.5;
+        return format!(
+            "timestep reduced to {:.2} ms",
+            constraints.timestep * 1000.0
+        );
+    }
+
+    // Otherwise, let the pressure solver work harder at keeping the fluid incompressible.
+    if constraints.incomp_iters_per_frame < u8::MAX {
+        guard
+            .original_iterations
+            .get_or_insert(constraints.incomp_iters_per_frame);
+        constraints.incomp_iters_per_frame = constraints
+            .incomp_iters_per_frame
+            .saturating_add(constraints.incomp_iters_per_frame / 2 + 1);
+        return format!(
+            "solver iterations raised to {}",
+            constraints.incomp_iters_per_frame
+        );
+    }
+
+    String::from("nothing left to adjust")
+}
+
+/// Undo one of the guard's recoveries once the simulation has been calm for long enough.
+fn relax_recovery(constraints: &mut SimConstraints) {
+    let guard: &mut SimStabilityGuard = &mut constraints.stability;
+    if !guard.is_recovering() {
+        return;
+    }
+    guard.calm_steps += 1;
+    if guard.calm_steps < CALM_STEPS_BEFORE_RELAXING {
+        return;
+    }
+    guard.calm_steps = 0;
+
+    // Iterations were raised last, so they're put back first.
+    if let Some(iterations) = guard.original_iterations.take() {
+        constraints.incomp_iters_per_frame = iterations;
+    } else if let Some(timestep) = guard.original_timestep {
+        constraints.timestep = (constraints.timestep * 2.0).min(timestep);
+        if constraints.timestep >= timestep {
+            guard.original_timestep = None;
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use std::f32::consts::PI;
 
 use crate::error::Error;
+use bevy::ecs::query::ReadOnlyWorldQuery;
 use bevy::math::Vec2;
 use bevy::prelude::*;
 
+use super::sim_domains::join_grid_domain;
 use super::*;
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -18,9 +20,21 @@ pub fn add_particles_in_radius(
     radius: f32,
     center_position: Vec2,
     velocity: Vec2,
+    temperature: f32,
+    group: u8,
+    material: SimFluidMaterial,
 ) {
     // Create center particle.
-    let _center_particle = add_particle(commands, constraints, grid, center_position, velocity);
+    let _center_particle = add_particle(
+        commands,
+        constraints,
+        grid,
+        center_position,
+        velocity,
+        temperature,
+        group,
+        material,
+    );
 
     // Density for the rings inside the circle.
     let ring_density: f32 = particle_density * 2.0;
@@ -42,27 +56,60 @@ pub fn add_particles_in_radius(
             };
 
             // If particle_position is outside the grid bounds, this will not create a particle:
-            let _particle = add_particle(commands, constraints, grid, particle_position, velocity);
+            let _particle = add_particle(
+                commands,
+                constraints,
+                grid,
+                particle_position,
+                velocity,
+                temperature,
+                group,
+                material,
+            );
         }
     }
 }
 
-pub fn delete_particles_in_radius(
+pub fn delete_particles_in_radius<F: ReadOnlyWorldQuery>(
     commands: &mut Commands,
     grid: &mut SimGrid,
-    particles: &Query<(Entity, &mut SimParticle)>,
+    particles: &Query<(Entity, &mut SimParticle), F>,
     position: Vec2,
     radius: f32,
 ) {
     // Can't be par_iter() because &mut commands doesn't have Clone
     particles.iter().for_each(|(id, particle)| {
-        if position.distance(particle.position) <= radius {
-            commands.entity(id).despawn();
-            grid.remove_particle_from_lookup(id, particle.lookup_index);
+        if position.distance(particle.position) <= radius
+            && grid.queue_particle_lookup_removal(id, particle.lookup_index)
+        {
+            SimParticlePool::deactivate(commands, id);
         }
     });
 }
 
+/** Swirl the particles within `radius` of `position` around it, adding `strength * delta_time` to
+their speed around the center (counter-clockwise, or clockwise for negative strengths).  The swirl
+fades out linearly towards the edge of the radius. */
+pub fn swirl_particles_in_radius<F: ReadOnlyWorldQuery>(
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    position: Vec2,
+    radius: f32,
+    strength: f32,
+    delta_time: f32,
+) {
+    for (_, mut particle) in particles.iter_mut() {
+        let offset: Vec2 = particle.position - position;
+        let distance: f32 = offset.length();
+        if distance >= radius || distance <= 0.0 {
+            continue;
+        }
+
+        let falloff: f32 = 1.0 - distance / radius;
+        let tangent: Vec2 = offset.perp() / distance;
+        particle.velocity += tangent * strength * falloff * delta_time;
+    }
+}
+
 /// Add particles into the simulation.
 pub fn add_particle(
     commands: &mut Commands,
@@ -70,6 +117,9 @@ pub fn add_particle(
     grid: &mut SimGrid,
     position: Vec2,
     velocity: Vec2,
+    temperature: f32,
+    group: u8,
+    material: SimFluidMaterial,
 ) -> Result<()> {
     // Don't allow the user to create particles out of the simulation grid's bounds!
     if position[0] < 0.0 || position[0] > (grid.dimensions.1 * grid.cell_size) as f32 {
@@ -82,8 +132,41 @@ pub fn add_particle(
             "Y-coordinate for particle creation is out of grid bounds!",
         ));
     }
+
+    spawn_particle(
+        commands,
+        constraints,
+        grid,
+        SimParticle {
+            position: position,
+            velocity: velocity,
+            lookup_index: 0,
+            temperature: temperature,
+            age: 0.0,
+            group: group,
+            material: material,
+            mass: 1.0,
+            radius: constraints.particle_radius,
+            affine_velocity: Mat2::ZERO,
+            previous_position: position,
+            spawn_id: 0,
+        },
+    )?;
+
+    Ok(())
+}
+
+/** Spawn an already-built particle into the simulation, unless it would be inside of a wall.  Used
+directly for particles that aren't fresh from an emitter, like halves of a split particle.  The
+particle is given the scene's next spawn id, whatever it had before. */
+pub fn spawn_particle(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    mut particle: SimParticle,
+) -> Result<Entity> {
     // If the cell we are inside of is a solid, don't create the particle!
-    let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&position);
+    let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
     if matches!(
         grid.cell_type[cell_coordinates[0] as usize][cell_coordinates[1] as usize],
         SimGridCellType::Solid
@@ -93,36 +176,38 @@ pub fn add_particle(
 
     // Add every particle to the 0-cell's lookup at first; we will sort this next frame.
     let lookup_index: usize = 0;
-    let particle: Entity = commands
-        .spawn(SimParticle {
-            position: position,
-            velocity: velocity,
-            lookup_index: lookup_index,
-        })
-        .id();
+    particle.lookup_index = lookup_index;
+    particle.spawn_id = constraints.next_spawn_id;
+    constraints.next_spawn_id += 1;
+    let particle: Entity = grid.particle_pool.spawn(commands, particle);
+    join_grid_domain(&mut commands.entity(particle), grid);
     grid.add_particle_to_lookup(particle, lookup_index);
 
     constraints.particle_count += 1;
 
     // IMPORTANT: Links a sprite to each particle for rendering.
-    // juice_renderer::link_particle_sprite(commands, asset_server, particle, position);
+    // juice_renderer::link_particle_sprite(commands, particle);
 
-    Ok(())
+    Ok(particle)
 }
 
 /// Remove a particle with ID particle_id from the simulation.
-pub fn delete_particle(
+pub fn delete_particle<F: ReadOnlyWorldQuery>(
     commands: &mut Commands,
     constraints: &mut SimConstraints,
-    particles: &Query<(Entity, &mut SimParticle)>,
+    particles: &Query<(Entity, &mut SimParticle), F>,
     grid: &mut SimGrid,
     particle_id: Entity,
 ) -> Result<()> {
     // Look for the particle in our particles query.
     if let Ok(particle) = particles.get(particle_id) {
-        // Remove particle from lookup table and despawn it.
-        grid.remove_particle_from_lookup(particle_id, particle.1.lookup_index);
-        commands.entity(particle_id).despawn();
+        /* Take the particle out of the simulation and queue its removal from the lookup table,
+        which happens once that has been applied.  Particles already queued this frame are left
+        alone. */
+        if !grid.queue_particle_lookup_removal(particle_id, particle.1.lookup_index) {
+            return Ok(());
+        }
+        SimParticlePool::deactivate(commands, particle_id);
 
         /* BUG: This overflowed once while testing, and I'm betting it's because I misuse
         Entity::PLACEHOLDER.  Here is my silly little fix: */
@@ -137,22 +222,178 @@ pub fn delete_particle(
 }
 
 /// Reset all simulation components to their default state.
-pub fn delete_all_particles(
+pub fn delete_all_particles<F: ReadOnlyWorldQuery>(
     commands: &mut Commands,
     constraints: &mut SimConstraints,
     grid: &mut SimGrid,
-    particles: &Query<(Entity, &mut SimParticle)>,
+    particles: &Query<(Entity, &mut SimParticle), F>,
 ) {
     // KILL THEM ALL!!!
     for (particle_id, _) in particles.iter() {
         let _ = delete_particle(commands, constraints, particles, grid, particle_id);
     }
+    // Including the ones narrow-band FLIP has set aside.
+    for stored in grid.narrow_band_interior.iter_mut() {
+        stored.clear();
+    }
+}
+
+/// Remove every particle tagged with `group` from the simulation.
+pub fn delete_particles_in_group<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    group: u8,
+) {
+    for (particle_id, particle) in particles.iter() {
+        if particle.group == group {
+            let _ = delete_particle(commands, constraints, particles, grid, particle_id);
+        }
+    }
+    for stored in grid.narrow_band_interior.iter_mut() {
+        stored.retain(|particle| particle.group != group);
+    }
+}
+
+/** Stamp an obstacle into the grid, centered on the cell containing `position` and rotated
+clockwise by `quarter_turns` * 90 degrees.  Particles inside of the new solid cells are deleted. */
+pub fn add_obstacle<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    obstacle: SimObstacle,
+    quarter_turns: u8,
+    position: Vec2,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&position) {
+        return Err(Error::OutOfGridBounds(
+            "Position for obstacle placement is out of grid bounds!",
+        ));
+    }
+
+    let cell_coordinates: Vec2 = grid.get_cell_coordinates_from_position(&position);
+    for cell in grid.stamp_obstacle(obstacle, quarter_turns, cell_coordinates) {
+        let lookup_index: usize = grid.get_lookup_index(cell);
+        grid.delete_all_particles_in_cell(commands, constraints, particles, lookup_index);
+    }
+
+    Ok(())
+}
+
+/** Stamp the walls of a hollow pipe (or funnel, if the outlet is narrower than the inlet) along
+`path` into the grid.  Widths are measured in cells.  Particles inside of the new walls are
+deleted. */
+pub fn add_pipe<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    path: &[Vec2],
+    inlet_width: f32,
+    outlet_width: f32,
+) -> Result<()> {
+    if path.len() < 2 {
+        return Err(Error::VectorLengthMismatch(
+            "A pipe's path needs at least two points!",
+        ));
+    }
+
+    for cell in grid.stamp_pipe(path, inlet_width, outlet_width) {
+        let lookup_index: usize = grid.get_lookup_index(cell);
+        grid.delete_all_particles_in_cell(commands, constraints, particles, lookup_index);
+    }
+
+    Ok(())
+}
+
+/** Fill the container under `position` with a pool of still fluid whose surface is level with the
+cell containing `position`.  Particles are spaced like add_particles_in_radius() spaces them for
+`particle_density`, so they will settle into place.  If `hydrostatic` is set, particles are instead
+packed at the solver's rest spacing and each pool cell's pressure is initialized to the hydrostatic
+solution, so the new pool starts at rest instead of bouncing as it compresses. */
+pub fn add_resting_pool(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    particle_density: f32,
+    position: Vec2,
+    temperature: f32,
+    group: u8,
+    material: SimFluidMaterial,
+    hydrostatic: bool,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&position) {
+        return Err(Error::OutOfGridBounds(
+            "Position for pool placement is out of grid bounds!",
+        ));
+    }
+
+    let row_count: usize = grid.dimensions.0 as usize;
+    let col_count: usize = grid.dimensions.1 as usize;
+    let cell_size: f32 = grid.cell_size as f32;
+    let surface_cell: Vec2 = grid.get_cell_coordinates_from_position(&position);
+    let pool_cells: Vec<Vec2> = grid.get_pool_cells(surface_cell);
+
+    // Remember which cells belong to the pool, and where the fluid's surface is in each column.
+    let mut in_pool: Vec<bool> = vec![false; row_count * col_count];
+    let mut surface_rows: Vec<usize> = vec![row_count; col_count];
+    for cell in pool_cells.iter() {
+        in_pool[grid.get_lookup_index(*cell)] = true;
+        surface_rows[cell.y as usize] = surface_rows[cell.y as usize].min(cell.x as usize);
+    }
+
+    // Lay particles out on a regular lattice from the bottom of the grid up to the surface.
+    let spacing: f32 = if hydrostatic {
+        constraints.particle_radius * 2.0
+    } else {
+        10.0 / (particle_density * 2.0)
+    };
+    let grid_width: f32 = col_count as f32 * cell_size;
+    let surface_height: f32 = (row_count as f32 - surface_cell.x) * cell_size;
+    let mut y: f32 = spacing * 0.5;
+    while y < surface_height {
+        let mut x: f32 = spacing * 0.5;
+        while x < grid_width {
+            let particle_position: Vec2 = Vec2 { x: x, y: y };
+            let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle_position);
+            if in_pool[grid.get_lookup_index(cell)] {
+                let _ = add_particle(
+                    commands,
+                    constraints,
+                    grid,
+                    particle_position,
+                    Vec2::ZERO,
+                    temperature,
+                    group,
+                    material,
+                );
+            }
+            x += spacing;
+        }
+        y += spacing;
+    }
+
+    /* Still water's pressure grows linearly with depth (per unit density); depth is measured from
+    the top of the pool's fluid in each cell's column. */
+    if hydrostatic {
+        let gravity: f32 = constraints.gravity.length();
+        for cell in pool_cells.iter() {
+            let row: usize = cell.x as usize;
+            let col: usize = cell.y as usize;
+            let depth: f32 = ((row - surface_rows[col]) as f32 + 0.5) * cell_size;
+            grid.cell_center[row][col] = gravity * depth;
+        }
+    }
+
+    Ok(())
 }
 
 /** Returns a vector of entity ID's of each particle within a circle centered at `position` with
 radius `radius`; returns an empty vector if no particles are found. */
-pub fn select_particles<'a>(
-    particles: &Query<(Entity, &mut SimParticle)>,
+pub fn select_particles<'a, F: ReadOnlyWorldQuery>(
+    particles: &Query<(Entity, &mut SimParticle), F>,
     grid: &SimGrid,
     position: Vec2,
     radius: f32,
@@ -164,7 +405,11 @@ pub fn select_particles<'a>(
 
     for i in 0..selected_cell_coordinates.len() {
         let cell_lookup_index: usize = grid.get_lookup_index(selected_cell_coordinates[i]);
-        for particle_id in grid.get_particles_in_lookup(cell_lookup_index) {
+        for particle_id in grid
+            .get_particles_in_lookup(cell_lookup_index)
+            .iter()
+            .copied()
+        {
             // Skip particles we can't find
             let Ok(particle_entity) = particles.get(particle_id) else {
                 continue;
@@ -185,6 +430,18 @@ pub fn select_particles<'a>(
     selected_particles
 }
 
+/// Returns a vector of entity ID's of every particle tagged with `group`.
+pub fn select_particles_in_group<F: ReadOnlyWorldQuery>(
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    group: u8,
+) -> Vec<Entity> {
+    particles
+        .iter()
+        .filter(|(_, particle)| particle.group == group)
+        .map(|(particle_id, _)| particle_id)
+        .collect()
+}
+
 pub fn add_faucet(
     commands: &mut Commands,
     grid: &mut SimGrid,
@@ -192,6 +449,13 @@ pub fn add_faucet(
     surface_direction: Option<SimSurfaceDirection>,
     faucet_diameter: f32,
     faucet_flow: Vec2,
+    faucet_path: Vec<Vec2>,
+    faucet_path_speed: f32,
+    faucet_group: u8,
+    faucet_material: SimFluidMaterial,
+    faucet_shape: SimFaucetShape,
+    faucet_spread: f32,
+    faucet_schedule: SimFaucetSchedule,
 ) -> Result<()> {
     if faucet_pos[0] < 0.0 || faucet_pos[0] > (grid.dimensions.1 * grid.cell_size) as f32 {
         return Err(Error::OutOfGridBounds(
@@ -204,23 +468,29 @@ pub fn add_faucet(
         ));
     }
 
-    let _faucet = commands
-        .spawn(SimFaucet::new(
-            faucet_pos,
-            surface_direction,
-            faucet_diameter,
-            faucet_flow,
-        ))
-        .id();
+    let mut faucet = commands.spawn(SimFaucet::new(
+        faucet_pos,
+        surface_direction,
+        faucet_diameter,
+        faucet_flow,
+        faucet_path,
+        faucet_path_speed,
+        faucet_group,
+        faucet_material,
+        faucet_shape,
+        faucet_spread,
+        faucet_schedule,
+    ));
+    join_grid_domain(&mut faucet, grid);
     // link_faucet_sprite(commands, &asset_server, faucet, faucet_pos);
 
     Ok(())
 }
 
 /// Remove a faucet from simulation
-pub fn delete_faucet(
+pub fn delete_faucet<D: ReadOnlyWorldQuery>(
     commands: &mut Commands,
-    faucets: &Query<(Entity, &mut SimFaucet)>,
+    faucets: &Query<(Entity, &mut SimFaucet), D>,
     faucet_id: Entity,
 ) -> Result<()> {
     // Look for the faucet
@@ -234,7 +504,10 @@ pub fn delete_faucet(
 }
 
 /// Remove all faucets from the simulation.
-pub fn delete_all_faucets(commands: &mut Commands, faucets: &Query<(Entity, &mut SimFaucet)>) {
+pub fn delete_all_faucets<D: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    faucets: &Query<(Entity, &mut SimFaucet), D>,
+) {
     // KILL THEM ALL!!!
     for (faucet_id, _) in faucets.iter() {
         let _ = delete_faucet(commands, faucets, faucet_id);
@@ -248,6 +521,8 @@ pub fn add_drain(
     surface_direction: Option<SimSurfaceDirection>,
     drain_radius: f32,
     drain_pressure: f32,
+    drain_capacity: Option<f32>,
+    drain_volume: Option<usize>,
 ) -> Result<()> {
     if drain_pos[0] < 0.0 || drain_pos[0] > (grid.dimensions.1 * grid.cell_size) as f32 {
         return Err(Error::OutOfGridBounds(
@@ -260,23 +535,24 @@ pub fn add_drain(
         ));
     }
 
-    let _drain = commands
-        .spawn(SimDrain::new(
-            drain_pos,
-            surface_direction,
-            drain_radius,
-            drain_pressure,
-        ))
-        .id();
+    let mut drain = commands.spawn(SimDrain::new(
+        drain_pos,
+        surface_direction,
+        drain_radius,
+        drain_pressure,
+        drain_capacity,
+        drain_volume,
+    ));
+    join_grid_domain(&mut drain, grid);
     // link_drain_sprite(commands, &asset_server, drain, drain_pos);
 
     Ok(())
 }
 
 // Delete drain from simulation
-pub fn delete_drain(
+pub fn delete_drain<D: ReadOnlyWorldQuery>(
     commands: &mut Commands,
-    drains: &Query<(Entity, &mut SimDrain)>,
+    drains: &Query<(Entity, &mut SimDrain), D>,
     drain_id: Entity,
 ) -> Result<()> {
     // Look for the drain
@@ -290,27 +566,340 @@ pub fn delete_drain(
 }
 
 /// Remove all drains from the simulation.
-pub fn delete_all_drains(commands: &mut Commands, drains: &Query<(Entity, &mut SimDrain)>) {
+pub fn delete_all_drains<D: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    drains: &Query<(Entity, &mut SimDrain), D>,
+) {
     // KILL THEM ALL!!!
     for (drain_id, _) in drains.iter() {
         let _ = delete_drain(commands, drains, drain_id);
     }
 }
 
-pub fn activate_components(
+/** Add a spinner rotating about `position` with `blade_count` blades, each `radius` long, turning at
+`angular_velocity` radians per second (counter-clockwise when positive). */
+pub fn add_spinner(
+    commands: &mut Commands,
+    grid: &SimGrid,
+    position: Vec2,
+    radius: f32,
+    blade_count: u8,
+    angular_velocity: f32,
+    inertia: Option<f32>,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&position) {
+        return Err(Error::OutOfGridBounds(
+            "Spinner's pivot must be within the grid's bounds!",
+        ));
+    }
+    if blade_count == 0 || radius < grid.cell_size as f32 {
+        return Err(Error::InvalidRegion(
+            "Spinners need at least one blade, at least one cell long!",
+        ));
+    }
+
+    // Waterwheels start out still and are left for the fluid to turn.
+    let mut spinner = match inertia {
+        Some(inertia) => commands.spawn(SimSpinner::new_waterwheel(
+            position,
+            radius,
+            blade_count,
+            inertia,
+        )),
+        None => commands.spawn(SimSpinner::new(
+            position,
+            radius,
+            blade_count,
+            angular_velocity,
+        )),
+    };
+    join_grid_domain(&mut spinner, grid);
+
+    Ok(())
+}
+
+/// Remove a spinner from the simulation; its blades are cleared from the grid on the next step.
+pub fn delete_spinner<D: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    spinners: &Query<(Entity, &mut SimSpinner), D>,
+    spinner_id: Entity,
+) -> Result<()> {
+    if let Err(_) = spinners.get(spinner_id) {
+        return Err(Error::InvalidEntityID("Invalid spinner entity ID!"));
+    }
+
+    commands.entity(spinner_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all spinners from the simulation.
+pub fn delete_all_spinners<D: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    spinners: &Query<(Entity, &mut SimSpinner), D>,
+) {
+    for (spinner_id, _) in spinners.iter() {
+        let _ = delete_spinner(commands, spinners, spinner_id);
+    }
+}
+
+/** Add an attractor at `position` that pulls particles within `radius` towards it, or pushes them away
+if `strength` is negative. */
+pub fn add_attractor(
+    commands: &mut Commands,
+    grid: &SimGrid,
+    position: Vec2,
+    radius: f32,
+    strength: f32,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&position) {
+        return Err(Error::OutOfGridBounds(
+            "Attractor's center must be within the grid's bounds!",
+        ));
+    }
+    if radius <= 0.0 {
+        return Err(Error::InvalidRegion(
+            "Attractors must reach out at least some distance!",
+        ));
+    }
+
+    commands.spawn(SimAttractor::new(position, radius, strength));
+
+    Ok(())
+}
+
+/// Remove an attractor from the simulation.
+pub fn delete_attractor(
+    commands: &mut Commands,
+    attractors: &Query<(Entity, &mut SimAttractor)>,
+    attractor_id: Entity,
+) -> Result<()> {
+    if let Err(_) = attractors.get(attractor_id) {
+        return Err(Error::InvalidEntityID("Invalid attractor entity ID!"));
+    }
+
+    commands.entity(attractor_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all attractors from the simulation.
+pub fn delete_all_attractors(
+    commands: &mut Commands,
+    attractors: &Query<(Entity, &mut SimAttractor)>,
+) {
+    for (attractor_id, _) in attractors.iter() {
+        let _ = delete_attractor(commands, attractors, attractor_id);
+    }
+}
+
+/// Add a pump that carries fluid from its intake to its outlet.
+pub fn add_pump(
+    commands: &mut Commands,
+    grid: &SimGrid,
+    intake: Vec2,
+    outlet: Vec2,
+    velocity: Vec2,
+    delay: f32,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&intake) || !grid.is_position_within_grid(&outlet) {
+        return Err(Error::OutOfGridBounds(
+            "Pump intakes and outlets must be within the grid's bounds!",
+        ));
+    }
+
+    commands.spawn(SimPump::new(intake, outlet, velocity, delay.max(0.0)));
+
+    Ok(())
+}
+
+/// Remove a pump from the simulation, letting out any fluid still inside of it at its outlet.
+pub fn delete_pump(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    pumps: &Query<(Entity, &mut SimPump)>,
+    pump_id: Entity,
+) -> Result<()> {
+    let Ok((_, pump)) = pumps.get(pump_id) else {
+        return Err(Error::InvalidEntityID("Invalid pump entity ID!"));
+    };
+
+    for pumped in pump.in_transit.iter() {
+        let mut particle: SimParticle = pumped.particle.clone();
+        particle.position = pump.outlet;
+        particle.previous_position = pump.outlet;
+        particle.velocity = Vec2::ZERO;
+        let _ = spawn_particle(commands, constraints, grid, particle);
+    }
+    commands.entity(pump_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all pumps from the simulation, along with any fluid still inside of them.
+pub fn delete_all_pumps(commands: &mut Commands, pumps: &Query<(Entity, &mut SimPump)>) {
+    for (pump_id, _) in pumps.iter() {
+        commands.entity(pump_id).despawn();
+    }
+}
+
+/// Add a probe that records the pressure, divergence, and velocity of the fluid at `position`.
+pub fn add_probe(commands: &mut Commands, grid: &SimGrid, position: Vec2) -> Result<()> {
+    if !grid.is_position_within_grid(&position) {
+        return Err(Error::OutOfGridBounds(
+            "Probes must be within the grid's bounds!",
+        ));
+    }
+
+    commands.spawn(SimProbe::new(position));
+
+    Ok(())
+}
+
+/// Remove a probe from the simulation, along with everything it has recorded.
+pub fn delete_probe(
+    commands: &mut Commands,
+    probes: &Query<(Entity, &mut SimProbe)>,
+    probe_id: Entity,
+) -> Result<()> {
+    if let Err(_) = probes.get(probe_id) {
+        return Err(Error::InvalidEntityID("Invalid probe entity ID!"));
+    }
+
+    commands.entity(probe_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all probes from the simulation.
+pub fn delete_all_probes(commands: &mut Commands, probes: &Query<(Entity, &mut SimProbe)>) {
+    for (probe_id, _) in probes.iter() {
+        let _ = delete_probe(commands, probes, probe_id);
+    }
+}
+
+/// Add a flow meter that measures how much fluid crosses the line from `start` to `end`.
+pub fn add_flow_meter(
+    commands: &mut Commands,
+    grid: &SimGrid,
+    start: Vec2,
+    end: Vec2,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&start) || !grid.is_position_within_grid(&end) {
+        return Err(Error::OutOfGridBounds(
+            "Flow meters must be within the grid's bounds!",
+        ));
+    }
+    if start == end {
+        return Err(Error::InvalidRegion(
+            "Flow meters must reach across at least some distance!",
+        ));
+    }
+
+    commands.spawn(SimFlowMeter::new(start, end));
+
+    Ok(())
+}
+
+/// Remove a flow meter from the simulation.
+pub fn delete_flow_meter(
+    commands: &mut Commands,
+    flow_meters: &Query<(Entity, &mut SimFlowMeter)>,
+    flow_meter_id: Entity,
+) -> Result<()> {
+    if let Err(_) = flow_meters.get(flow_meter_id) {
+        return Err(Error::InvalidEntityID("Invalid flow meter entity ID!"));
+    }
+
+    commands.entity(flow_meter_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all flow meters from the simulation.
+pub fn delete_all_flow_meters(
+    commands: &mut Commands,
+    flow_meters: &Query<(Entity, &mut SimFlowMeter)>,
+) {
+    for (flow_meter_id, _) in flow_meters.iter() {
+        let _ = delete_flow_meter(commands, flow_meters, flow_meter_id);
+    }
+}
+
+/// Mark the rectangle between two opposite corners as a container whose fill level is measured.
+pub fn add_container(
+    commands: &mut Commands,
+    grid: &SimGrid,
+    corner: Vec2,
+    opposite_corner: Vec2,
+) -> Result<()> {
+    if !grid.is_position_within_grid(&corner) || !grid.is_position_within_grid(&opposite_corner) {
+        return Err(Error::OutOfGridBounds(
+            "Container corners must be within the grid's bounds!",
+        ));
+    }
+
+    let container: SimContainer = SimContainer::new(corner, opposite_corner);
+    if container.max.x - container.min.x < grid.cell_size as f32
+        || container.max.y - container.min.y < grid.cell_size as f32
+    {
+        return Err(Error::InvalidRegion(
+            "Containers must be at least one cell wide and tall!",
+        ));
+    }
+    commands.spawn(container);
+
+    Ok(())
+}
+
+/// Remove a container from the simulation.
+pub fn delete_container(
+    commands: &mut Commands,
+    containers: &Query<(Entity, &mut SimContainer)>,
+    container_id: Entity,
+) -> Result<()> {
+    if let Err(_) = containers.get(container_id) {
+        return Err(Error::InvalidEntityID("Invalid container entity ID!"));
+    }
+
+    commands.entity(container_id).despawn();
+
+    Ok(())
+}
+
+/// Remove all containers from the simulation.
+pub fn delete_all_containers(
+    commands: &mut Commands,
+    containers: &Query<(Entity, &mut SimContainer)>,
+) {
+    for (container_id, _) in containers.iter() {
+        let _ = delete_container(commands, containers, container_id);
+    }
+}
+
+pub fn activate_components<F: ReadOnlyWorldQuery, D: ReadOnlyWorldQuery>(
     commands: &mut Commands,
     constraints: &mut SimConstraints,
-    particles: &mut Query<(Entity, &mut SimParticle)>,
-    faucets: &Query<(Entity, &mut SimFaucet)>,
-    drains: &Query<(Entity, &mut SimDrain)>,
+    rng: &mut SimRng,
+    particles: &mut Query<(Entity, &mut SimParticle), F>,
+    faucets: &Query<(Entity, &mut SimFaucet), D>,
+    drains: &mut Query<(Entity, &mut SimDrain), D>,
     grid: &mut SimGrid,
+    timestep: f32,
 ) -> Result<()> {
+    // Faucets resting between pulses don't pour anything.
+    let time: f32 = constraints.simulated_time;
     faucets.for_each(|(_, faucet)| {
-        faucet.run(commands, constraints, grid).unwrap();
+        if faucet.schedule.is_on(time) {
+            faucet.run(commands, constraints, grid, rng).unwrap();
+        }
     });
 
-    drains.for_each(|(_, drain)| {
-        drain.drain(commands, grid, particles).unwrap();
+    drains.for_each_mut(|(_, mut drain)| {
+        drain
+            .drain(commands, constraints, grid, particles, timestep)
+            .unwrap();
     });
 
     Ok(())
@@ -0,0 +1,542 @@
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Mutex;
+
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::RunSystemOnce;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use super::sim_domains::SimMainDomain;
+use super::sim_gpu::SimGpu;
+use super::sim_particle_pool::SimInactiveParticle;
+use super::sim_pump::SimPump;
+use super::sim_rng::SimRng;
+use super::sim_telemetry::{kinetic_energy, SimStepStats, SimTelemetry};
+use super::{
+    step_simulation, SimAttractor, SimConstraints, SimDrain, SimFaucet, SimGrid, SimParticle,
+    SimSpinner,
+};
+use crate::events::{ClearEvent, PlayPauseStepEvent, ResetEvent, SequencerEvent, UseToolEvent};
+
+/** Steps the main simulation on a thread of its own, so a slow step doesn't hold up input and the
+UI.  The simulation is double-buffered.  The thread owns the back copy, a world of its own holding
+the simulation's resources, particles, faucets, drains, and spinners, which it keeps stepping from
+one job to the next.  Everything else (the renderer, the UI, the tools) reads the front copy, the
+usual resources and entities.  Each job hands back an immutable snapshot of the back copy (see
+`SimSnapshot`), which is swapped in for the front copy once it lands.
+
+Anything that edits the front copy (tools, resetting, clearing, pausing, the timeline, the settings,
+attractors and pumps, loading a scene) waits for the job in flight to land first, so edits are never
+overwritten by its results; the thread's world is then seeded from the edited front copy before the
+next job.  Faucets are swept along their paths in the front copy, so every job takes them along.
+The simulation steps inline, as it does in the tests, wherever this resource hasn't been added. */
+#[derive(Resource, Default)]
+pub struct SimStepTask {
+    queued_steps: u8, // Steps that have come due, for the next job to take.
+    thread: Option<SimStepThread>,
+    in_flight: bool, // Whether a job has been sent that hasn't landed yet.
+    synced: bool,    // Whether the thread's world is still where the front copy left it.
+    sent_gpu: bool,  // Whether the thread has been handed its own GPU buffers.
+    entity_map: HashMap<Entity, Entity>, // The front's entity for each of the thread's particles.
+}
+
+/// The simulation thread, and the channels jobs go out and snapshots come back on.
+struct SimStepThread {
+    jobs: Sender<SimStepJob>,
+    snapshots: Mutex<Receiver<SimSnapshot>>, // Only ever reached through `get_mut`.
+}
+
+impl SimStepTask {
+    /// Whether a job is in flight.
+    pub fn is_running(&self) -> bool {
+        self.in_flight
+    }
+
+    /** Queue `step_count` more steps for the next job.  Steps keep coming due while a job is in
+    flight, so they are capped at `max_steps` to keep a slow step from snowballing. */
+    pub fn queue_steps(&mut self, step_count: u8, max_steps: u8) {
+        self.queued_steps = self.queued_steps.saturating_add(step_count).min(max_steps);
+    }
+
+    /** Note that the front copy has been edited, so the thread's world is seeded from it again
+    before the next job.  Edit events, attractors, and pumps are noticed without this. */
+    pub fn mark_edited(&mut self) {
+        self.synced = false;
+    }
+
+    /** Start the simulation thread, unless it is already running.  Its world starts out empty, so
+    it is seeded (and handed its GPU buffers) along with its first job.  Returns whether it is
+    running. */
+    fn start_thread(&mut self) -> bool {
+        if self.thread.is_some() {
+            return true;
+        }
+
+        let (jobs, thread_jobs) = channel::<SimStepJob>();
+        let (thread_snapshots, snapshots) = channel::<SimSnapshot>();
+        let spawned = std::thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || run_simulation_thread(thread_jobs, thread_snapshots));
+        if let Err(e) = spawned {
+            eprintln!("Couldn't start the simulation thread: {}", e);
+            return false;
+        }
+
+        self.thread = Some(SimStepThread {
+            jobs,
+            snapshots: Mutex::new(snapshots),
+        });
+        self.synced = false;
+        self.sent_gpu = false;
+        true
+    }
+
+    /// Forget the simulation thread once it has stopped (like if a step panicked).
+    fn lose_thread(&mut self) {
+        eprintln!("The simulation thread stopped; starting over from the last snapshot.");
+        self.thread = None;
+        self.in_flight = false;
+        self.synced = false;
+    }
+
+    /** The snapshot of the job in flight, if it has landed; if `wait` is set, wait for it to land.
+    None if there is no job in flight. */
+    fn receive(&mut self, wait: bool) -> Option<SimSnapshot> {
+        if !self.in_flight {
+            return None;
+        }
+        let snapshots: &mut Receiver<SimSnapshot> =
+            self.thread.as_mut()?.snapshots.get_mut().ok()?;
+        let received: Result<SimSnapshot, TryRecvError> = if wait {
+            snapshots.recv().map_err(|_| TryRecvError::Disconnected)
+        } else {
+            snapshots.try_recv()
+        };
+
+        match received {
+            Ok(snapshot) => {
+                self.in_flight = false;
+                Some(snapshot)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.lose_thread();
+                None
+            }
+        }
+    }
+}
+
+/// Steps for the simulation thread to take.
+struct SimStepJob {
+    step_count: u8,
+    resync: Option<SimStepState>, // The front copy to seed the thread's world with, once edited.
+    faucets: Vec<(Entity, SimFaucet)>, // Where the faucets are now.
+    gpu: Option<SimGpu>,          // The thread's own GPU buffers, with its first job.
+    measure_energy: bool,         // Whether telemetry wants each step's kinetic energy.
+}
+
+/** A copy of the front copy to seed the thread's world with: the simulation's resources, along with
+each of its particles (active or pooled), drains, and spinners under their entities.  The grid's
+spatial lookup and particle pool refer to particles by entity, so the thread's world gives each copy
+the very same entity. */
+struct SimStepState {
+    constraints: SimConstraints,
+    grid: SimGrid,
+    rng: SimRng,
+    particles: Vec<(Entity, SimParticle, bool)>, // Each particle, and whether it is pooled.
+    drains: Vec<(Entity, SimDrain)>,
+    spinners: Vec<(Entity, SimSpinner)>,
+}
+
+impl SimStepState {
+    /// Copy the main simulation out of `world`.
+    fn capture(world: &mut World) -> Self {
+        let particles: Vec<(Entity, SimParticle, bool)> = world
+            .query_filtered::<(Entity, &SimParticle, Has<SimInactiveParticle>), SimMainDomain>()
+            .iter(world)
+            .map(|(id, particle, pooled)| (id, particle.clone(), pooled))
+            .collect();
+
+        /* Pooled entities that have since been despawned (like by loading a scene) aren't copied,
+        and the thread's own new entities could end up with their ids, so leave them out of the
+        pool. */
+        let copied_particles: HashSet<Entity> = particles.iter().map(|(id, _, _)| *id).collect();
+        let mut grid: SimGrid = world.resource::<SimGrid>().clone();
+        grid.particle_pool
+            .retain(|id| copied_particles.contains(id));
+
+        Self {
+            constraints: world.resource::<SimConstraints>().clone(),
+            grid,
+            rng: world.resource::<SimRng>().clone(),
+            particles,
+            drains: world
+                .query_filtered::<(Entity, &SimDrain), SimMainDomain>()
+                .iter(world)
+                .map(|(id, drain)| (id, drain.clone()))
+                .collect(),
+            spinners: world
+                .query_filtered::<(Entity, &SimSpinner), SimMainDomain>()
+                .iter(world)
+                .map(|(id, spinner)| (id, spinner.clone()))
+                .collect(),
+        }
+    }
+
+    /** Replace everything in the thread's `world` but its GPU buffers with this copy, along with
+    `faucets`. */
+    fn seed(self, world: &mut World, faucets: &[(Entity, SimFaucet)]) {
+        world.clear_entities();
+        world.insert_resource(self.constraints);
+        world.insert_resource(self.grid);
+        world.insert_resource(self.rng);
+
+        /* Reserve every copied entity first, in order; reserving an entity below the highest one
+        reserved so far has to search the entities skipped over. */
+        let mut ids: Vec<Entity> = self
+            .particles
+            .iter()
+            .map(|(id, _, _)| *id)
+            .chain(faucets.iter().map(|(id, _)| *id))
+            .chain(self.drains.iter().map(|(id, _)| *id))
+            .chain(self.spinners.iter().map(|(id, _)| *id))
+            .collect();
+        ids.sort_unstable_by_key(|id| id.index());
+        for id in ids {
+            world.get_or_spawn(id);
+        }
+        for (id, particle, pooled) in self.particles {
+            let mut entity = world.entity_mut(id);
+            entity.insert(particle);
+            if pooled {
+                entity.insert(SimInactiveParticle);
+            }
+        }
+        for (id, drain) in self.drains {
+            world.entity_mut(id).insert(drain);
+        }
+        for (id, spinner) in self.spinners {
+            world.entity_mut(id).insert(spinner);
+        }
+    }
+}
+
+/** An immutable snapshot of the thread's world after a job, under the thread's own entities, to be
+swapped in for the front copy.  Stepping doesn't change faucets, so they're left out. */
+pub struct SimSnapshot {
+    constraints: SimConstraints,
+    grid: SimGrid,
+    rng: SimRng,
+    particles: Vec<(Entity, SimParticle, bool)>, // Each particle, and whether it is pooled.
+    drains: Vec<(Entity, SimDrain)>,
+    spinners: Vec<(Entity, SimSpinner)>,
+    // Each substep's measurements, with the particle count and kinetic energy it left behind.
+    steps: Vec<(SimStepStats, usize, f32)>,
+}
+
+impl SimSnapshot {
+    /// Take a snapshot of the thread's `world`, after it has taken `steps`.
+    fn take(world: &mut World, steps: Vec<(SimStepStats, usize, f32)>) -> Self {
+        let constraints: SimConstraints = world.resource::<SimConstraints>().clone();
+
+        // Warnings are handed over along with the snapshot, so the UI only shows each one once.
+        let mut thread_constraints = world.resource_mut::<SimConstraints>();
+        thread_constraints.stability.take_warning();
+        thread_constraints.safeguard_report.take_warning();
+
+        Self {
+            constraints,
+            grid: world.resource::<SimGrid>().clone(),
+            rng: world.resource::<SimRng>().clone(),
+            particles: world
+                .query::<(Entity, &SimParticle, Has<SimInactiveParticle>)>()
+                .iter(world)
+                .map(|(id, particle, pooled)| (id, particle.clone(), pooled))
+                .collect(),
+            drains: world
+                .query::<(Entity, &SimDrain)>()
+                .iter(world)
+                .map(|(id, drain)| (id, drain.clone()))
+                .collect(),
+            spinners: world
+                .query::<(Entity, &SimSpinner)>()
+                .iter(world)
+                .map(|(id, spinner)| (id, spinner.clone()))
+                .collect(),
+            steps,
+        }
+    }
+}
+
+/** The simulation thread: step the thread's world as each job comes in, and send back a snapshot of
+it.  Stops once the jobs' sender or the snapshots' receiver is dropped. */
+fn run_simulation_thread(jobs: Receiver<SimStepJob>, snapshots: Sender<SimSnapshot>) {
+    let mut world = World::new();
+    for job in jobs.iter() {
+        if let Some(gpu) = job.gpu {
+            world.insert_resource(gpu);
+        }
+        if let Some(state) = job.resync {
+            state.seed(&mut world, &job.faucets);
+        }
+        for (id, faucet) in job.faucets {
+            if let Some(mut entity) = world.get_entity_mut(id) {
+                entity.insert(faucet);
+            }
+        }
+
+        let mut steps: Vec<(SimStepStats, usize, f32)> = Vec::new();
+        for _ in 0..job.step_count {
+            steps.extend(world.run_system_once_with(job.measure_energy, step_back_copy));
+            // Despawns have been applied by now, so the spatial lookup can safely forget them.
+            world.resource_mut::<SimGrid>().flush_lookup_removals();
+        }
+
+        if snapshots
+            .send(SimSnapshot::take(&mut world, steps))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Step the back copy once, measuring each substep for telemetry.
+fn step_back_copy(
+    In(measure_energy): In<bool>,
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut rng: ResMut<SimRng>,
+    mut gpu: Option<ResMut<SimGpu>>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+    faucets: Query<(Entity, &mut SimFaucet)>,
+    mut drains: Query<(Entity, &mut SimDrain)>,
+    mut spinners: Query<(Entity, &mut SimSpinner)>,
+) -> Vec<(SimStepStats, usize, f32)> {
+    let timestep: f32 = constraints.timestep;
+    let substep_stats: Vec<SimStepStats> = step_simulation(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        rng.as_mut(),
+        gpu.as_deref_mut(),
+        &mut particles,
+        &faucets,
+        &mut drains,
+        &mut spinners,
+        timestep,
+    );
+
+    // Substeps are measured once they have all run, just as update() records them.
+    let kinetic_energy: f32 = if measure_energy {
+        kinetic_energy(&particles)
+    } else {
+        0.0
+    };
+    substep_stats
+        .into_iter()
+        .map(|stats| (stats, constraints.particle_count, kinetic_energy))
+        .collect()
+}
+
+/// Readers for the events that edit the main simulation, so edits can be noticed.
+#[derive(Default)]
+pub struct SimEditReaders {
+    tool_use: ManualEventReader<UseToolEvent>,
+    reset: ManualEventReader<ResetEvent>,
+    clear: ManualEventReader<ClearEvent>,
+    pause: ManualEventReader<PlayPauseStepEvent>,
+    sequencer: ManualEventReader<SequencerEvent>,
+}
+
+impl SimEditReaders {
+    /** Whether any edits have been sent since last time, or there are attractors or pumps pushing
+    the fluid around.  Tools aimed at extra domains don't count, since those step on their own. */
+    fn read(&mut self, world: &mut World) -> bool {
+        fn any_new<E: Event>(
+            reader: &mut ManualEventReader<E>,
+            world: &World,
+            counts: impl Fn(&E) -> bool,
+        ) -> bool {
+            world
+                .get_resource::<Events<E>>()
+                .is_some_and(|events| reader.read(events).filter(|ev| counts(ev)).count() > 0)
+        }
+
+        // Every reader has to be caught up, so none of these can be skipped.
+        let tool_use: bool = any_new(&mut self.tool_use, world, |ev| ev.domain.is_none());
+        let reset: bool = any_new(&mut self.reset, world, |_| true);
+        let clear: bool = any_new(&mut self.clear, world, |_| true);
+        let pause: bool = any_new(&mut self.pause, world, |_| true);
+        let sequencer: bool = any_new(&mut self.sequencer, world, |_| true);
+        let forces: bool = world
+            .query_filtered::<(), Or<(With<SimAttractor>, With<SimPump>)>>()
+            .iter(world)
+            .next()
+            .is_some();
+        tool_use || reset || clear || pause || sequencer || forces
+    }
+}
+
+/** Hand the steps that have come due to the simulation thread, if there isn't a job in flight
+already.  This runs after everything else that changes the simulation each frame, so if the front
+copy was edited, the thread's world is seeded with all of this frame's edits. */
+pub fn launch_background_step(world: &mut World, mut edit_readers: Local<SimEditReaders>) {
+    let edited: bool = edit_readers.read(world);
+    let Some(mut step_task) = world.get_resource_mut::<SimStepTask>() else {
+        return;
+    };
+    if edited {
+        step_task.mark_edited();
+    }
+    if step_task.is_running() || step_task.queued_steps == 0 || !step_task.start_thread() {
+        return;
+    }
+    let step_count: u8 = std::mem::take(&mut step_task.queued_steps);
+    let resync: bool = !step_task.synced;
+    let sent_gpu: bool = step_task.sent_gpu;
+
+    let faucets: Vec<(Entity, SimFaucet)> = world
+        .query_filtered::<(Entity, &SimFaucet), SimMainDomain>()
+        .iter(world)
+        .map(|(id, faucet)| (id, faucet.clone()))
+        .collect();
+    let state: Option<SimStepState> = resync.then(|| SimStepState::capture(world));
+    /* The thread's GPU buffers are its own, so nothing the front copy does with the GPU (like
+    stepping once while paused) can pull them out from under it. */
+    let gpu: Option<SimGpu> = if sent_gpu {
+        None
+    } else {
+        world.get_resource::<SimGpu>().map(SimGpu::share_device)
+    };
+    let measure_energy: bool = world
+        .get_resource::<SimTelemetry>()
+        .is_some_and(|telemetry| telemetry.is_recording());
+
+    let mut step_task = world.resource_mut::<SimStepTask>();
+    if let Some(state) = state.as_ref() {
+        // The thread's world starts over from the front copy, entities and all.
+        step_task.entity_map = state
+            .particles
+            .iter()
+            .map(|(id, _, _)| (*id, *id))
+            .collect();
+    }
+    step_task.synced = true;
+    step_task.sent_gpu |= gpu.is_some();
+    let job = SimStepJob {
+        step_count,
+        resync: state,
+        faucets,
+        gpu,
+        measure_energy,
+    };
+    let sent: bool = step_task
+        .thread
+        .as_ref()
+        .is_some_and(|thread| thread.jobs.send(job).is_ok());
+    if sent {
+        step_task.in_flight = true;
+    } else {
+        step_task.lose_thread();
+    }
+}
+
+/** Swap in the snapshot of the job in flight once it has landed.  If there are edits waiting on the
+simulation, wait for it to land first; attractors and pumps push the fluid around every frame, so
+while there are any the simulation effectively steps inline. */
+pub fn land_background_step(world: &mut World, mut edit_readers: Local<SimEditReaders>) {
+    let edits_pending: bool = edit_readers.read(world);
+    let snapshot: Option<SimSnapshot> = world
+        .get_resource_mut::<SimStepTask>()
+        .and_then(|mut step_task| step_task.receive(edits_pending));
+    if let Some(snapshot) = snapshot {
+        world.run_system_once_with(snapshot, apply_snapshot);
+    }
+}
+
+/** Wait for the job in flight (if any) to land, and swap its snapshot in for the front copy.  Call
+this before editing the simulation from outside of the usual systems, like when loading a scene; the
+thread's world is seeded from the front copy again before the next job. */
+pub fn finish_background_step(world: &mut World) {
+    let Some(mut step_task) = world.get_resource_mut::<SimStepTask>() else {
+        return;
+    };
+    step_task.mark_edited();
+    if let Some(snapshot) = step_task.receive(true) {
+        world.run_system_once_with(snapshot, apply_snapshot);
+    }
+}
+
+/** Swap a snapshot in for the front copy.  Particles the thread spawned get entities of their own
+in the main world (remembered for as long as the thread's world isn't seeded again), and everything
+that refers to them by entity is pointed at those. */
+fn apply_snapshot(
+    In(snapshot): In<SimSnapshot>,
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut rng: ResMut<SimRng>,
+    mut step_task: ResMut<SimStepTask>,
+    mut telemetry: Option<ResMut<SimTelemetry>>,
+    mut particles: Query<(&mut SimParticle, Has<SimInactiveParticle>)>,
+    mut drains: Query<&mut SimDrain>,
+    mut spinners: Query<&mut SimSpinner>,
+) {
+    let entity_map: &mut HashMap<Entity, Entity> = &mut step_task.entity_map;
+    for (thread_id, particle, pooled) in snapshot.particles {
+        let Some(id) = entity_map.get(&thread_id).copied() else {
+            let mut entity = commands.spawn(particle);
+            if pooled {
+                entity.insert(SimInactiveParticle);
+            }
+            entity_map.insert(thread_id, entity.id());
+            continue;
+        };
+
+        let Ok((mut front_particle, was_pooled)) = particles.get_mut(id) else {
+            continue;
+        };
+        *front_particle = particle;
+        if pooled && !was_pooled {
+            commands.entity(id).insert(SimInactiveParticle);
+        } else if !pooled && was_pooled {
+            commands.entity(id).remove::<SimInactiveParticle>();
+        }
+    }
+
+    // Foam, spray, and bubbles are spawned from the front copy, which keeps its own progress.
+    let secondary_spawn_progress: f32 = constraints.secondary_spawn_progress;
+    *constraints = snapshot.constraints;
+    constraints.secondary_spawn_progress = secondary_spawn_progress;
+    for (id, _) in constraints.selected_particles.iter_mut() {
+        *id = *entity_map.get(id).unwrap_or(id);
+    }
+    *grid = snapshot.grid;
+    grid.map_entities(entity_map);
+    *rng = snapshot.rng;
+
+    for (id, drain) in snapshot.drains {
+        if let Ok(mut front_drain) = drains.get_mut(id) {
+            *front_drain = drain;
+        }
+    }
+    for (id, spinner) in snapshot.spinners {
+        if let Ok(mut front_spinner) = spinners.get_mut(id) {
+            *front_spinner = spinner;
+        }
+    }
+
+    if let Some(telemetry) = telemetry.as_mut() {
+        for (stats, particle_count, kinetic_energy) in snapshot.steps.iter() {
+            telemetry.record_measured(stats, *particle_count, *kinetic_energy);
+        }
+    }
+}
+
+/// Run condition holding systems that edit the simulation off while a job is in flight.
+pub fn simulation_is_idle(step_task: Option<Res<SimStepTask>>) -> bool {
+    !step_task.is_some_and(|step_task| step_task.is_running())
+}
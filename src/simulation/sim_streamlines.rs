@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+use super::{sim_physics_engine::sample_grid_velocity, SimGrid};
+
+/// Slowest the grid's velocity can be for a streamline to keep going, in world units per second.
+pub const STREAMLINE_MIN_SPEED: f32 = 0.2;
+
+/** Trace the streamline running through `seed`: the curve that follows the grid's velocity field
+everywhere along it, so it shows the path fluid flows along at this instant.  The line is traced
+both upstream and downstream of the seed in steps of `step_length` world units (at most `max_steps`
+each way), and stops wherever it leaves the grid, reaches a face with no velocity, or slows to
+almost nothing.  Points are returned in the direction the fluid flows; empty if nothing flows
+through the seed. */
+pub fn trace_streamline(
+    grid: &SimGrid,
+    seed: Vec2,
+    step_length: f32,
+    max_steps: usize,
+) -> Vec<Vec2> {
+    let mut upstream: Vec<Vec2> = trace_streamline_half(grid, seed, -step_length, max_steps);
+    let downstream: Vec<Vec2> = trace_streamline_half(grid, seed, step_length, max_steps);
+    if upstream.is_empty() && downstream.is_empty() {
+        return Vec::new();
+    }
+
+    upstream.reverse();
+    upstream.push(seed);
+    upstream.extend(downstream);
+    upstream
+}
+
+/** Follow the velocity field from `start` (against it, if `step_length` is negative), not including
+`start` itself.  Each step is taken along the direction at its midpoint, which keeps the line from
+drifting outwards around vortices the way plain Euler steps do. */
+fn trace_streamline_half(
+    grid: &SimGrid,
+    start: Vec2,
+    step_length: f32,
+    max_steps: usize,
+) -> Vec<Vec2> {
+    let direction_at = |position: Vec2| -> Option<Vec2> {
+        let velocity: Vec2 = sample_grid_velocity(grid, position)?;
+        if velocity.length() < STREAMLINE_MIN_SPEED {
+            return None;
+        }
+        Some(velocity.normalize())
+    };
+
+    let mut points: Vec<Vec2> = Vec::new();
+    let mut position: Vec2 = start;
+    for _ in 0..max_steps {
+        let Some(direction) = direction_at(position) else {
+            break;
+        };
+        let Some(midpoint_direction) = direction_at(position + direction * step_length * 0.5)
+        else {
+            break;
+        };
+        position += midpoint_direction * step_length;
+        if !grid.is_position_within_grid(&position) {
+            break;
+        }
+        points.push(position);
+    }
+    points
+}
@@ -0,0 +1,174 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use super::{SimGrid, SimGridCellType};
+
+/// Fraction of the fluid's average density at which the liquid's surface is traced.
+pub const SURFACE_ISO_FRACTION: f32 = 0.5;
+
+/** The liquid's boundary, traced through the grid's density field with marching squares once a
+frame.  Each contour is a closed polygon of world-space points (its last point connects back to its
+first); bodies of liquid and the bubbles inside of them each get their own contour. */
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SimSurface {
+    pub contours: Vec<Vec<Vec2>>,
+    // Density the contours were traced at.
+    pub iso_level: f32,
+}
+
+impl SimSurface {
+    /// Every edge of every contour, including the edges closing each contour back up.
+    pub fn segments(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.contours.iter().flat_map(|contour| {
+            contour
+                .iter()
+                .zip(contour.iter().cycle().skip(1))
+                .map(|(start, end)| (*start, *end))
+        })
+    }
+
+    /// Total number of points across every contour.
+    pub fn point_count(&self) -> usize {
+        self.contours.iter().map(Vec::len).sum()
+    }
+}
+
+/** Identifies the edge between two neighboring cell centers that a contour crosses: the row and
+column of the edge's first center, and whether the edge runs horizontally (to the next column) or
+vertically (to the next row). */
+type SurfaceEdge = (i32, i32, bool);
+
+/** Trace the liquid's surface through the grid's density field.  Cell centers are treated as the
+corners of each marching square, and the area around the grid counts as empty so every contour
+closes up. */
+pub fn extract_liquid_surface(grid: &SimGrid, surface: &mut SimSurface) {
+    surface.contours.clear();
+    let (rows, cols) = (grid.dimensions.0 as i32, grid.dimensions.1 as i32);
+
+    // Trace the surface at a fraction of the density fluid cells usually have.
+    let mut fluid_cell_count: f32 = 0.0;
+    let mut density_sum: f32 = 0.0;
+    for row in 0..rows as usize {
+        for col in 0..cols as usize {
+            if grid.cell_type[row][col] == SimGridCellType::Fluid {
+                density_sum += grid.density[row * cols as usize + col];
+                fluid_cell_count += 1.0;
+            }
+        }
+    }
+    if fluid_cell_count <= 0.0 || density_sum <= 0.0 {
+        surface.iso_level = 0.0;
+        return;
+    }
+    let iso_level: f32 = SURFACE_ISO_FRACTION * density_sum / fluid_cell_count;
+    surface.iso_level = iso_level;
+
+    let density = |row: i32, col: i32| -> f32 {
+        if row < 0 || col < 0 || row >= rows || col >= cols {
+            return 0.0;
+        }
+        grid.density[(row * cols + col) as usize]
+    };
+    let cell_size: f32 = grid.cell_size as f32;
+    let grid_height: f32 = rows as f32 * cell_size;
+    let center = |row: i32, col: i32| -> Vec2 {
+        Vec2::new(
+            (col as f32 + 0.5) * cell_size,
+            grid_height - (row as f32 + 0.5) * cell_size,
+        )
+    };
+
+    // Find where the surface crosses an edge, interpolating between the densities on either end.
+    let mut crossings: HashMap<SurfaceEdge, Vec2> = HashMap::new();
+    let mut crossing = |edge: SurfaceEdge| -> SurfaceEdge {
+        crossings.entry(edge).or_insert_with(|| {
+            let (row, col, horizontal) = edge;
+            let (end_row, end_col) = if horizontal {
+                (row, col + 1)
+            } else {
+                (row + 1, col)
+            };
+            let (start_density, end_density) = (density(row, col), density(end_row, end_col));
+            let t: f32 =
+                ((iso_level - start_density) / (end_density - start_density)).clamp(0.0, 1.0);
+            center(row, col).lerp(center(end_row, end_col), t)
+        });
+        edge
+    };
+
+    // March over every square of four neighboring cell centers, including those hanging off the grid.
+    let mut segments: Vec<[SurfaceEdge; 2]> = Vec::new();
+    for row in -1..rows {
+        for col in -1..cols {
+            let corners: [f32; 4] = [
+                density(row, col),
+                density(row, col + 1),
+                density(row + 1, col + 1),
+                density(row + 1, col),
+            ];
+            let case: usize = corners.iter().fold(0, |case, corner| {
+                (case << 1) | (*corner > iso_level) as usize
+            });
+
+            let top: SurfaceEdge = (row, col, true);
+            let bottom: SurfaceEdge = (row + 1, col, true);
+            let left: SurfaceEdge = (row, col, false);
+            let right: SurfaceEdge = (row, col + 1, false);
+
+            // Saddles are joined through the middle if the square's average density is inside.
+            let center_inside: bool = corners.iter().sum::<f32>() * 0.25 > iso_level;
+            let square_segments: &[[SurfaceEdge; 2]] = match case {
+                1 | 14 => &[[left, bottom]],
+                2 | 13 => &[[bottom, right]],
+                3 | 12 => &[[left, right]],
+                4 | 11 => &[[top, right]],
+                6 | 9 => &[[top, bottom]],
+                7 | 8 => &[[top, left]],
+                5 if center_inside => &[[top, left], [bottom, right]],
+                5 => &[[left, bottom], [top, right]],
+                10 if center_inside => &[[top, right], [left, bottom]],
+                10 => &[[top, left], [bottom, right]],
+                _ => &[],
+            };
+            for [start, end] in square_segments {
+                segments.push([crossing(*start), crossing(*end)]);
+            }
+        }
+    }
+
+    // Stitch the segments into closed contours; every crossing is shared by exactly two segments.
+    let mut segments_at_edge: HashMap<SurfaceEdge, Vec<usize>> = HashMap::new();
+    for (index, segment) in segments.iter().enumerate() {
+        for edge in segment {
+            segments_at_edge.entry(*edge).or_default().push(index);
+        }
+    }
+
+    let mut visited: Vec<bool> = vec![false; segments.len()];
+    for first in 0..segments.len() {
+        if visited[first] {
+            continue;
+        }
+        visited[first] = true;
+
+        let [start, mut current] = segments[first];
+        let mut contour: Vec<Vec2> = vec![crossings[&start]];
+        while current != start {
+            contour.push(crossings[&current]);
+            let Some(next) = segments_at_edge[&current]
+                .iter()
+                .copied()
+                .find(|index| !visited[*index])
+            else {
+                break;
+            };
+            visited[next] = true;
+            current = if segments[next][0] == current {
+                segments[next][1]
+            } else {
+                segments[next][0]
+            };
+        }
+        surface.contours.push(contour);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::{SimConstraints, SimParticle};
+
+/// Measurements taken while stepping the simulation once.
+#[derive(Clone, Debug)]
+pub struct SimStepStats {
+    // How long each stage of the step took.
+    pub stage_timings: Vec<(&'static str, Duration)>,
+    // Incompressibility iterations run.
+    pub solver_iterations: u8,
+    // Largest divergence left in a fluid cell after the pressure solve.
+    pub max_divergence: f32,
+    stage_start: Instant,
+}
+
+impl SimStepStats {
+    /// Start timing a new step.
+    pub fn new() -> Self {
+        Self {
+            stage_timings: Vec::new(),
+            solver_iterations: 0,
+            max_divergence: 0.0,
+            stage_start: Instant::now(),
+        }
+    }
+
+    /// Record how long the stage that just finished took, and start timing the next one.
+    pub fn end_stage(&mut self, stage: &'static str) {
+        let now: Instant = Instant::now();
+        self.stage_timings.push((stage, now - self.stage_start));
+        self.stage_start = now;
+    }
+}
+
+/// How much of each new step's timings goes into the profile's running averages.
+pub const PROFILE_SMOOTHING: f32 = 0.1;
+
+/** A running average of how long each stage of a step takes, in milliseconds, so the UI can show
+where the frame budget goes without the numbers jumping around every frame.  Stages are kept in the
+order they first ran in. */
+#[derive(Clone, Debug, Default)]
+pub struct SimStageProfile {
+    pub stages: Vec<(&'static str, f32)>,
+}
+
+impl SimStageProfile {
+    /** Blend one step's stage timings into the averages.  Stages seen for the first time start at
+    their timing, and stages the step skipped (like SPH's while running FLIP) fade out towards 0. */
+    pub fn record(&mut self, stats: &SimStepStats) {
+        for (stage, average) in self.stages.iter_mut() {
+            let milliseconds: f32 = stats
+                .stage_timings
+                .iter()
+                .find(|(name, _)| name == stage)
+                .map_or(0.0, |(_, duration)| duration.as_secs_f32() * 1000.0);
+            *average += (milliseconds - *average) * PROFILE_SMOOTHING;
+        }
+        for (stage, duration) in stats.stage_timings.iter() {
+            if self.stages.iter().all(|(name, _)| name != stage) {
+                self.stages.push((stage, duration.as_secs_f32() * 1000.0));
+            }
+        }
+    }
+
+    /// Average milliseconds a whole step takes.
+    pub fn total(&self) -> f32 {
+        self.stages.iter().map(|(_, average)| average).sum()
+    }
+}
+
+/** Streams per-step telemetry to a JSON-lines file while recording, one object per step, so long
+runs can be analyzed offline.  Every step's stage timings also feed `profile`, recording or not. */
+#[derive(Resource, Default)]
+pub struct SimTelemetry {
+    writer: Option<BufWriter<File>>,
+    path: PathBuf,
+    step: u64,
+    pub profile: SimStageProfile,
+}
+
+impl SimTelemetry {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Start recording telemetry to a new file at `path`, replacing any file already there.
+    pub fn start_recording(&mut self, path: &Path) -> std::io::Result<()> {
+        self.stop_recording();
+        self.writer = Some(BufWriter::new(File::create(path)?));
+        self.path = path.to_path_buf();
+        self.step = 0;
+        println!("Recording telemetry to {}...", path.display());
+
+        Ok(())
+    }
+
+    /// Stop recording, making sure everything recorded so far is written out.
+    pub fn stop_recording(&mut self) {
+        let Some(mut writer) = self.writer.take() else {
+            return;
+        };
+        if let Err(e) = writer.flush() {
+            eprintln!("Couldn't finish writing telemetry: {}", e);
+        }
+        println!("Stopped recording telemetry to {}.", self.path.display());
+    }
+
+    /** Profile one step, and record its telemetry if we are recording.  Stops recording if the file
+    can't be written. */
+    pub fn record<F: ReadOnlyWorldQuery>(
+        &mut self,
+        stats: &SimStepStats,
+        constraints: &SimConstraints,
+        particles: &Query<(Entity, &mut SimParticle), F>,
+    ) {
+        // Only add up the kinetic energy if it is going to be written out.
+        let kinetic_energy: f32 = if self.is_recording() {
+            kinetic_energy(particles)
+        } else {
+            0.0
+        };
+        self.record_measured(stats, constraints.particle_count, kinetic_energy);
+    }
+
+    /** Like record(), for a step whose particle count and kinetic energy were measured elsewhere (like
+    on the simulation thread that stepped it). */
+    pub fn record_measured(
+        &mut self,
+        stats: &SimStepStats,
+        particle_count: usize,
+        kinetic_energy: f32,
+    ) {
+        self.profile.record(stats);
+
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+
+        let line: String = format_telemetry_line(self.step, particle_count, kinetic_energy, stats);
+        self.step += 1;
+
+        if let Err(e) = writeln!(writer, "{}", line) {
+            eprintln!("Couldn't write telemetry: {}", e);
+            self.stop_recording();
+        }
+    }
+}
+
+/// Total kinetic energy of the particles.  Particles all have the same (unit) mass.
+pub fn kinetic_energy<F: ReadOnlyWorldQuery>(
+    particles: &Query<(Entity, &mut SimParticle), F>,
+) -> f32 {
+    particles
+        .iter()
+        .map(|(_, particle)| 0.5 * particle.velocity.length_squared())
+        .sum()
+}
+
+/// A new telemetry file in the working directory, named after the time recording started.
+pub fn telemetry_file_path() -> PathBuf {
+    let seconds: u64 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    PathBuf::from(format!("telemetry-{}.jsonl", seconds))
+}
+
+/// Format one step's telemetry as a single line of JSON.  Stage timings are in microseconds.
+pub fn format_telemetry_line(
+    step: u64,
+    particle_count: usize,
+    kinetic_energy: f32,
+    stats: &SimStepStats,
+) -> String {
+    let timings: Vec<String> = stats
+        .stage_timings
+        .iter()
+        .map(|(stage, duration)| format!("\"{}\":{}", stage, duration.as_micros()))
+        .collect();
+
+    format!(
+        "{{\"step\":{},\"particle_count\":{},\"kinetic_energy\":{},\"max_divergence\":{},\"solver_iterations\":{},\"timings_us\":{{{}}}}}",
+        step,
+        particle_count,
+        json_number(kinetic_energy),
+        json_number(stats.max_divergence),
+        stats.solver_iterations,
+        timings.join(",")
+    )
+}
+
+/// JSON has no representation for NaN or infinity, so write those as null.
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
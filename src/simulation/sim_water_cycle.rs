@@ -0,0 +1,160 @@
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::*;
+
+use super::sim_rng::SimRng;
+use super::sim_state_manager::{add_particle, delete_particle};
+use super::{
+    SimConstraints, SimFluidMaterial, SimGrid, SimGridCellType, SimParticle, AMBIENT_TEMPERATURE,
+};
+
+/// Fraction of the water vapor that rains back down each second while condensation is on.
+pub const CONDENSATION_RATE: f32 = 0.5;
+/// Speed raindrops start falling at.
+const RAIN_SPEED: f32 = 50.0;
+
+/** Give long-running scenes a water cycle.  Particles on the fluid's surface evaporate at
+`constraints.evaporation_rate` particles per second and are stored as water vapor; while
+`constraints.condensation` is on, the vapor rains back down from the top of the domain. */
+pub fn run_water_cycle<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    rng: &mut SimRng,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    delta_time: f32,
+) {
+    evaporate_surface_particles(commands, constraints, grid, rng, particles, delta_time);
+    if constraints.condensation {
+        condense_water_vapor(commands, constraints, grid, rng, delta_time);
+    }
+}
+
+/// Remove however many surface particles have evaporated since the last step.
+fn evaporate_surface_particles<F: ReadOnlyWorldQuery>(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    rng: &mut SimRng,
+    particles: &Query<(Entity, &mut SimParticle), F>,
+    delta_time: f32,
+) {
+    if constraints.evaporation_rate <= 0.0 {
+        constraints.evaporation_progress = 0.0;
+        return;
+    }
+
+    constraints.evaporation_progress += constraints.evaporation_rate * delta_time;
+    if constraints.evaporation_progress < 1.0 {
+        return;
+    }
+
+    let mut surface_particles: Vec<Entity> = find_surface_particles(grid);
+    if surface_particles.is_empty() {
+        // Nothing to evaporate; don't let a backlog build up in the meantime.
+        constraints.evaporation_progress = constraints.evaporation_progress.min(1.0);
+        return;
+    }
+
+    // Evaporate randomly chosen surface particles so the surface wears away evenly.
+    while constraints.evaporation_progress >= 1.0 && !surface_particles.is_empty() {
+        let index: usize = rng.next_index(surface_particles.len());
+        let id: Entity = surface_particles.swap_remove(index);
+        if delete_particle(commands, constraints, particles, grid, id).is_ok() {
+            constraints.water_vapor += 1.0;
+        }
+        constraints.evaporation_progress -= 1.0;
+    }
+}
+
+/** Find every particle in a fluid cell bordering air (horizontally or vertically), skipping
+particles that are already on their way out. */
+fn find_surface_particles(grid: &SimGrid) -> Vec<Entity> {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+
+    let mut surface_particles: Vec<Entity> = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            if grid.cell_type[row][col] != SimGridCellType::Fluid {
+                continue;
+            }
+
+            let borders_air: bool = [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().any(
+                |(row_offset, col_offset): (isize, isize)| {
+                    let neighbor_row: usize = usize::wrapping_add_signed(row, row_offset);
+                    let neighbor_col: usize = usize::wrapping_add_signed(col, col_offset);
+                    neighbor_row < rows
+                        && neighbor_col < cols
+                        && grid.cell_type[neighbor_row][neighbor_col] == SimGridCellType::Air
+                },
+            );
+            if !borders_air {
+                continue;
+            }
+
+            let lookup_index: usize = grid.get_lookup_index(Vec2::new(row as f32, col as f32));
+            surface_particles.extend(
+                grid.get_particles_in_lookup(lookup_index)
+                    .iter()
+                    .copied()
+                    .filter(|id| !grid.is_particle_pending_removal(*id)),
+            );
+        }
+    }
+
+    surface_particles
+}
+
+/// Rain some of the water vapor back down from the top of the domain.
+fn condense_water_vapor(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    rng: &mut SimRng,
+    delta_time: f32,
+) {
+    if constraints.water_vapor < 1.0 {
+        return;
+    }
+
+    // Always rain at least a little, so the last few drops don't hang in the air forever.
+    let condensed: f32 = (constraints.water_vapor * CONDENSATION_RATE * delta_time).max(delta_time);
+    constraints.condensation_progress += condensed;
+
+    while constraints.condensation_progress >= 1.0 && constraints.water_vapor >= 1.0 {
+        constraints.condensation_progress -= 1.0;
+        let Some(position) = find_raindrop_position(grid, rng) else {
+            return;
+        };
+        if add_particle(
+            commands,
+            constraints,
+            grid,
+            position,
+            Vec2::new(0.0, -RAIN_SPEED),
+            AMBIENT_TEMPERATURE,
+            0,
+            SimFluidMaterial::Water,
+        )
+        .is_ok()
+        {
+            constraints.water_vapor -= 1.0;
+        }
+    }
+}
+
+/** Pick a random column and find where a raindrop falling into it would first appear: the center of
+its topmost air cell.  Returns None if the column is walled off from the top of the domain. */
+fn find_raindrop_position(grid: &SimGrid, rng: &mut SimRng) -> Option<Vec2> {
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    let col: usize = rng.next_index(cols);
+
+    // Skip past the solid border (and any ceiling) at the top of the column.
+    let row: usize = (0..rows).find(|row| grid.cell_type[*row][col] != SimGridCellType::Solid)?;
+    if grid.cell_type[row][col] != SimGridCellType::Air {
+        return None;
+    }
+
+    Some(grid.get_cell_center_position_from_coordinates(&Vec2::new(row as f32, col as f32)))
+}
@@ -33,6 +33,20 @@ pub fn find_influence(particle_pos: Vec2, grid_point: Vec2, grid_scale: u16) ->
     the cell.
 */
 pub fn interpolate_velocity(particle_pos: Vec2, grid: &SimGrid) -> Vec2 {
+    interpolate_velocity_field(particle_pos, grid, &grid.velocity_u, &grid.velocity_v)
+}
+
+/**
+    Same as `interpolate_velocity()`, but samples the given u/v
+    velocity arrays instead of the grid's current velocities.
+    The arrays must use the same MAC layout as the grid.
+*/
+pub fn interpolate_velocity_field(
+    particle_pos: Vec2,
+    grid: &SimGrid,
+    velocity_u: &Vec<Vec<f32>>,
+    velocity_v: &Vec<Vec<f32>>,
+) -> Vec2 {
     // Grid points 0..3 are the four corners of the bilinear interpolation
     // in order of clockwise rotation around the particle point.
     // https://en.wikipedia.org/wiki/Bilinear_interpolation
@@ -49,10 +63,10 @@ pub fn interpolate_velocity(particle_pos: Vec2, grid: &SimGrid) -> Vec2 {
     let top_v_pos = cell_center + Vec2::new(0.0, half_cell);
     let bottom_v_pos = cell_center - Vec2::new(0.0, half_cell);
 
-    let left_u_velocity = grid.velocity_u[row][col];
-    let top_v_velocity = grid.velocity_v[row][col];
-    let right_u_velocity = grid.velocity_u[row][col + 1];
-    let bottom_v_velocity = grid.velocity_v[row + 1][col];
+    let left_u_velocity = velocity_u[row][col];
+    let top_v_velocity = velocity_v[row][col];
+    let right_u_velocity = velocity_u[row][col + 1];
+    let bottom_v_velocity = velocity_v[row + 1][col];
 
     let interp_velocity_u = (((right_u_pos.x - particle_pos.x) / (right_u_pos.x - left_u_pos.x))
         * left_u_velocity)
@@ -65,3 +79,186 @@ pub fn interpolate_velocity(particle_pos: Vec2, grid: &SimGrid) -> Vec2 {
 
     interp_velocity
 }
+
+/**
+    Find the point `distance` along a polyline, sweeping back and
+    forth along it (ping-pong) once the distance exceeds its length.
+    Returns `None` if the path is empty.
+*/
+pub fn point_along_path(path: &[Vec2], distance: f32) -> Option<Vec2> {
+    let first_point: Vec2 = *path.first()?;
+    let path_length: f32 = path
+        .windows(2)
+        .map(|segment| segment[0].distance(segment[1]))
+        .sum();
+    if path_length <= 0.0 {
+        return Some(first_point);
+    }
+
+    // Fold the distance into one lap of the path, heading back once we pass its end.
+    let mut remaining: f32 = distance.rem_euclid(2.0 * path_length);
+    if remaining > path_length {
+        remaining = 2.0 * path_length - remaining;
+    }
+
+    for segment in path.windows(2) {
+        let segment_length: f32 = segment[0].distance(segment[1]);
+        if remaining <= segment_length {
+            return Some(segment[0].lerp(segment[1], remaining / segment_length.max(f32::EPSILON)));
+        }
+        remaining -= segment_length;
+    }
+
+    path.last().copied()
+}
+
+/**
+    Resize a row-major buffer to rows x cols and fill it with value,
+    reusing the existing allocations where possible.
+*/
+pub fn reset_buffer<T: Clone>(buffer: &mut Vec<Vec<T>>, rows: usize, cols: usize, value: T) {
+    buffer.resize_with(rows, Vec::new);
+    for row in buffer.iter_mut() {
+        row.clear();
+        row.resize(cols, value.clone());
+    }
+}
+
+/**
+    Find the distance (in cells) from the center of each cell of a
+    row-major rows x cols grid to the center of the nearest cell
+    marked in `sites`.  Each cell's nearest site is swept across the
+    grid in a forward and a backward pass, which is exact for almost
+    every cell and never off by more than a fraction of a cell.
+    Cells with no site anywhere are infinitely far away.
+*/
+pub fn calculate_distance_field(sites: &[bool], rows: usize, cols: usize) -> Vec<f32> {
+    let mut nearest: Vec<Option<(usize, usize)>> = sites
+        .iter()
+        .enumerate()
+        .map(|(index, is_site)| is_site.then_some((index / cols, index % cols)))
+        .collect();
+
+    for row in 0..rows {
+        for col in 0..cols {
+            relax_nearest_site(
+                &mut nearest,
+                rows,
+                cols,
+                (row, col),
+                &[(-1, -1), (-1, 0), (-1, 1), (0, -1)],
+            );
+        }
+        for col in (0..cols).rev() {
+            relax_nearest_site(&mut nearest, rows, cols, (row, col), &[(0, 1)]);
+        }
+    }
+    for row in (0..rows).rev() {
+        for col in (0..cols).rev() {
+            relax_nearest_site(
+                &mut nearest,
+                rows,
+                cols,
+                (row, col),
+                &[(1, 1), (1, 0), (1, -1), (0, 1)],
+            );
+        }
+        for col in 0..cols {
+            relax_nearest_site(&mut nearest, rows, cols, (row, col), &[(0, -1)]);
+        }
+    }
+
+    nearest
+        .iter()
+        .enumerate()
+        .map(|(index, site)| match site {
+            Some(site) => (distance_squared((index / cols, index % cols), *site) as f32).sqrt(),
+            None => f32::INFINITY,
+        })
+        .collect()
+}
+
+/// Helper function for calculate_distance_field(); adopt a neighbor's nearest site if it's closer.
+fn relax_nearest_site(
+    nearest: &mut [Option<(usize, usize)>],
+    rows: usize,
+    cols: usize,
+    cell: (usize, usize),
+    offsets: &[(isize, isize)],
+) {
+    for (row_offset, col_offset) in offsets {
+        let neighbor_row: usize = usize::wrapping_add_signed(cell.0, *row_offset);
+        let neighbor_col: usize = usize::wrapping_add_signed(cell.1, *col_offset);
+        if neighbor_row >= rows || neighbor_col >= cols {
+            continue;
+        }
+
+        let Some(site) = nearest[neighbor_row * cols + neighbor_col] else {
+            continue;
+        };
+        let current: &mut Option<(usize, usize)> = &mut nearest[cell.0 * cols + cell.1];
+        if current.map_or(true, |current| {
+            distance_squared(cell, site) < distance_squared(cell, current)
+        }) {
+            *current = Some(site);
+        }
+    }
+}
+
+/// Helper function for calculate_distance_field(); squared distance between two cells.
+fn distance_squared(a: (usize, usize), b: (usize, usize)) -> usize {
+    let row_distance: usize = a.0.abs_diff(b.0);
+    let col_distance: usize = a.1.abs_diff(b.1);
+    row_distance * row_distance + col_distance * col_distance
+}
+
+/**
+    Bilinearly interpolate one component of a MAC velocity grid at
+    a position, along with the component's gradient there (in world
+    units).  `horizontal` picks velocity_u's layout, whose points
+    sit on the cells' left/right faces, over velocity_v's, whose
+    points sit on their top/bottom faces.  Points that were never
+    given a velocity count as still.
+*/
+pub fn interpolate_velocity_component_with_gradient(
+    particle_pos: Vec2,
+    grid: &SimGrid,
+    velocity: &Vec<Vec<f32>>,
+    horizontal: bool,
+) -> (f32, Vec2) {
+    let cell_size: f32 = grid.cell_size as f32;
+    let point_rows: usize = velocity.len();
+    let point_cols: usize = velocity[0].len();
+
+    // Find the particle's position in units of velocity points, relative to the first point.
+    let first_point: Vec2 = grid.get_velocity_point_pos(0, 0, horizontal);
+    let point_x: f32 =
+        ((particle_pos.x - first_point.x) / cell_size).clamp(0.0, (point_cols - 1) as f32);
+    let point_y: f32 =
+        ((first_point.y - particle_pos.y) / cell_size).clamp(0.0, (point_rows - 1) as f32);
+    let col: usize = (point_x as usize).min(point_cols - 2);
+    let row: usize = (point_y as usize).min(point_rows - 2);
+    let (x_weight, y_weight) = (point_x - col as f32, point_y - row as f32);
+
+    let sample = |row: usize, col: usize| -> f32 {
+        if velocity[row][col] == f32::MIN {
+            0.0
+        } else {
+            velocity[row][col]
+        }
+    };
+    let (top_left, top_right) = (sample(row, col), sample(row, col + 1));
+    let (bottom_left, bottom_right) = (sample(row + 1, col), sample(row + 1, col + 1));
+
+    let top: f32 = top_left + (top_right - top_left) * x_weight;
+    let bottom: f32 = bottom_left + (bottom_right - bottom_left) * x_weight;
+    let value: f32 = top + (bottom - top) * y_weight;
+
+    // Rows run down the screen, so the gradient's y component is flipped.
+    let slope_x: f32 =
+        (top_right - top_left) + ((bottom_right - bottom_left) - (top_right - top_left)) * y_weight;
+    let slope_y: f32 = (bottom_left - top_left)
+        + ((bottom_right - top_right) - (bottom_left - top_left)) * x_weight;
+
+    (value, Vec2::new(slope_x, -slope_y) / cell_size)
+}
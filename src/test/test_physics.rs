@@ -1,13 +1,75 @@
 #[cfg(test)]
-use crate::simulation::util::interpolate_velocity;
+use crate::simulation::sim_chunks::SIM_CHUNK_SIZE;
 #[cfg(test)]
-use crate::simulation::{SimConstraints, SimGrid, SimParticle};
+use crate::simulation::sim_diagnostics::{
+    SimConservationSample, SimConservedQuantity, SimDiagnostics, DIAGNOSTICS_HISTORY_LENGTH,
+};
 #[cfg(test)]
-use crate::test::test_state_manager::{test_setup, test_update};
+use crate::simulation::sim_flow_meter::SimFlowMeter;
+#[cfg(test)]
+use crate::simulation::sim_gpu::SimGpu;
+#[cfg(test)]
+use crate::simulation::sim_inflow::{calculate_inflow_velocity, SimInflowProfile};
+#[cfg(test)]
+use crate::simulation::sim_particle_pool::SimInactiveParticle;
+#[cfg(test)]
+use crate::simulation::sim_physics_engine::{
+    advect_particle, apply_interface_tension, apply_no_slip_walls, apply_porous_drag,
+    apply_surface_tension, apply_thermal_buoyancy, apply_valves, apply_viscosity,
+    apply_vorticity_confinement, bounce_off_surface, calculate_cell_divergence,
+    calculate_cell_relaxation, calculate_face_fraction, calculate_face_weight,
+    calculate_max_divergence, grid_to_particles, handle_particle_grid_collisions,
+    integrate_particle_with_collisions, is_velocity_point_transferred,
+    make_grid_velocities_incompressible, particles_to_grid, push_particles_apart,
+    sample_grid_velocity, solve_pressure_gauss_seidel, transfer_grid_to_particles,
+    transfer_particles_to_grid, update_particles, GAUSS_SEIDEL_OVERRELAXATION,
+    PARTICLES_PER_DEPOSIT_CHUNK, POROUS_DRAG_RATE,
+};
+#[cfg(test)]
+use crate::simulation::sim_pressure_solver::SimPressureSolver;
+#[cfg(test)]
+use crate::simulation::sim_probes::{format_probe_sample, SimProbe, PROBE_HISTORY_LENGTH};
+#[cfg(test)]
+use crate::simulation::sim_rng::SimRng;
+#[cfg(test)]
+use crate::simulation::sim_secondary::{
+    step_secondary_particles, SimSecondaryKind, SimSecondaryParticle,
+};
+#[cfg(test)]
+use crate::simulation::sim_sph::SimSolverKind;
+#[cfg(test)]
+use crate::simulation::sim_streamlines::trace_streamline;
+#[cfg(test)]
+use crate::simulation::sim_surface::{extract_liquid_surface, SimSurface};
+#[cfg(test)]
+use crate::simulation::sim_telemetry::{
+    format_telemetry_line, SimStageProfile, SimStepStats, PROFILE_SMOOTHING,
+};
+#[cfg(test)]
+use crate::simulation::util::{
+    find_influence, interpolate_velocity, point_along_path, reset_buffer,
+};
+#[cfg(test)]
+use crate::simulation::{
+    SimAdvectionScheme, SimAttractor, SimConstraints, SimEdgeBoundary, SimFluidMaterial,
+    SimGravityPreset, SimGrid, SimGridCellType, SimGridEdge, SimParticle, SimSpinner,
+    SimSurfaceDirection, SimTransferScheme, SimWallSlip, AMBIENT_TEMPERATURE, EARTH_GRAVITY,
+    GRAVITY_PRESET_COUNT,
+};
+#[cfg(test)]
+use crate::test::test_state_manager::{construct_new_simulation, test_setup, test_update};
+#[cfg(test)]
+use bevy::ecs::system::RunSystemOnce;
 #[cfg(test)]
 use bevy::math::Vec2;
 #[cfg(test)]
 use bevy::prelude::*;
+#[cfg(test)]
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+#[cfg(test)]
+use std::f32::consts::PI;
+#[cfg(test)]
+use std::sync::Arc;
 
 #[test]
 fn interpolation_test() {
@@ -36,6 +98,7 @@ fn velocity_transfer_test() {
     let mut juicebox_test = App::new();
 
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     juicebox_test.add_systems(Startup, test_setup);
@@ -83,7 +146,7 @@ fn velocity_transfer_test() {
     // successfully transfered velocities
     for particle in juicebox_test
         .world
-        .query::<&SimParticle>()
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
         .iter(&juicebox_test.world)
     {
         if particle.velocity != Vec2::ZERO {
@@ -102,6 +165,7 @@ fn extrapolate_test() {
     let mut juicebox_test = App::new();
 
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     juicebox_test.add_systems(Startup, test_setup);
@@ -119,7 +183,7 @@ fn extrapolate_test() {
     // check the velocities around it
     for particle in juicebox_test
         .world
-        .query::<&SimParticle>()
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
         .iter(&juicebox_test.world)
     {
         let particle_coords = grid.get_cell_coordinates_from_position(&particle.position);
@@ -142,3 +206,2477 @@ fn extrapolate_test() {
 
     assert_eq!(true, success);
 }
+
+#[test]
+fn reset_buffer_test() {
+    // Start from a buffer of the wrong shape and stale contents
+    let mut buffer = vec![vec![3.0; 4]; 2];
+
+    reset_buffer(&mut buffer, 3, 2, f32::MIN);
+    assert_eq!(vec![vec![f32::MIN; 2]; 3], buffer);
+
+    reset_buffer(&mut buffer, 1, 5, 0.0);
+    assert_eq!(vec![vec![0.0; 5]; 1], buffer);
+}
+
+#[test]
+fn grid_temperature_test() {
+    let mut grid = SimGrid::default();
+    let cell_size: f32 = grid.cell_size as f32;
+    let grid_height: f32 = grid.dimensions.0 as f32 * cell_size;
+
+    // Two particles in the top-left cell, one hot and one cold.
+    let position = Vec2::new(cell_size * 0.5, grid_height - cell_size * 0.5);
+    grid.clear_temperature_values();
+    grid.update_grid_temperature(position, 40.0);
+    grid.update_grid_temperature(position, 60.0);
+    grid.normalize_temperature_values();
+
+    // The occupied cell averages its particles, and empty cells sit at ambient temperature.
+    assert_eq!(50.0, grid.get_temperature_at_position(position));
+    assert_eq!(
+        AMBIENT_TEMPERATURE,
+        grid.get_temperature_at_position(Vec2::new(grid_height * 0.5, grid_height * 0.5))
+    );
+}
+
+#[test]
+fn telemetry_line_test() {
+    let mut stats = SimStepStats::new();
+    stats.stage_timings = vec![
+        ("integrate", std::time::Duration::from_micros(12)),
+        ("pressure_solve", std::time::Duration::from_micros(340)),
+    ];
+    stats.solver_iterations = 20;
+    stats.max_divergence = f32::NAN;
+
+    // Non-finite values have no JSON representation, so they come out as null.
+    assert_eq!(
+        "{\"step\":3,\"particle_count\":100,\"kinetic_energy\":2.5,\"max_divergence\":null,\"solver_iterations\":20,\"timings_us\":{\"integrate\":12,\"pressure_solve\":340}}",
+        format_telemetry_line(3, 100, 2.5, &stats)
+    );
+}
+
+#[test]
+fn stage_profile_test() {
+    let mut stats = SimStepStats::new();
+    stats.stage_timings = vec![
+        ("integrate", std::time::Duration::from_millis(2)),
+        ("pressure_solve", std::time::Duration::from_millis(8)),
+    ];
+    let mut profile = SimStageProfile::default();
+    profile.record(&stats);
+    assert_eq!(
+        vec![("integrate", 2.0), ("pressure_solve", 8.0)],
+        profile.stages
+    );
+    assert_eq!(10.0, profile.total());
+
+    // Later steps are blended in, and stages a step skips fade out.
+    stats.stage_timings = vec![
+        ("integrate", std::time::Duration::from_millis(12)),
+        ("sph", std::time::Duration::from_millis(1)),
+    ];
+    profile.record(&stats);
+    assert_eq!(3, profile.stages.len());
+    assert!((profile.stages[0].1 - (2.0 + 10.0 * PROFILE_SMOOTHING)).abs() < 1e-4);
+    assert!((profile.stages[1].1 - 8.0 * (1.0 - PROFILE_SMOOTHING)).abs() < 1e-4);
+    assert_eq!(("sph", 1.0), profile.stages[2]);
+}
+
+#[test]
+fn particle_age_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup);
+    juicebox_test.add_systems(Update, test_update);
+
+    // Particles are spawned during startup, so they age by one timestep on every update.
+    juicebox_test.update();
+    juicebox_test.update();
+    juicebox_test.update();
+
+    let timestep: f32 = juicebox_test.world.resource::<SimConstraints>().timestep;
+    for particle in juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
+        .iter(&juicebox_test.world)
+    {
+        assert!((timestep * 3.0 - particle.age).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn grid_vorticity_test() {
+    let mut grid = SimGrid::default();
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+
+    // Spin the whole grid counter-clockwise like a solid body, whose curl is twice its spin.
+    let spin: f32 = 3.0;
+    for row in 0..rows {
+        for col in 0..cols + 1 {
+            let position: Vec2 = grid.get_velocity_point_pos(row, col, true);
+            grid.velocity_u[row][col] = -spin * position.y;
+        }
+    }
+    for row in 0..rows + 1 {
+        for col in 0..cols {
+            let position: Vec2 = grid.get_velocity_point_pos(row, col, false);
+            grid.velocity_v[row][col] = spin * position.x;
+        }
+    }
+
+    let center: Vec2 = Vec2::splat(grid.dimensions.0 as f32 * grid.cell_size as f32 * 0.5);
+    assert!((2.0 * spin - grid.get_vorticity_at_position(center)).abs() < 1e-3);
+
+    // Reversing the spin reverses the vorticity.
+    for row in grid.velocity_u.iter_mut() {
+        row.iter_mut().for_each(|velocity| *velocity = -*velocity);
+    }
+    for row in grid.velocity_v.iter_mut() {
+        row.iter_mut().for_each(|velocity| *velocity = -*velocity);
+    }
+    assert!((2.0 * spin + grid.get_vorticity_at_position(center)).abs() < 1e-3);
+}
+
+#[test]
+fn viscosity_test() {
+    let mut grid = SimGrid::default();
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Fluid);
+    }
+
+    // A lone fast-moving face in still fluid should drag its neighbors along and slow down.
+    grid.velocity_u[25][25] = 100.0;
+    apply_viscosity(&mut grid, 0.0, 1.0);
+    assert_eq!(100.0, grid.velocity_u[25][25]);
+
+    apply_viscosity(&mut grid, 250.0, 1.0 / 120.0);
+    assert!(grid.velocity_u[25][25] < 100.0);
+    assert!(grid.velocity_u[25][24] > 0.0 && grid.velocity_u[24][25] > 0.0);
+
+    // Diffusion only spreads momentum around; it doesn't create or destroy it.
+    let total: f32 = grid.velocity_u.iter().flatten().sum();
+    assert!((100.0 - total).abs() < 1e-2);
+
+    // Even very thick fluids stay stable.
+    apply_viscosity(&mut grid, 1.0e6, 1.0 / 120.0);
+    assert!(grid.velocity_u.iter().flatten().all(|u| u.abs() <= 100.0));
+}
+
+#[test]
+fn vorticity_confinement_test() {
+    let mut grid = SimGrid::default();
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Fluid);
+    }
+
+    // A small counter-clockwise swirl in the middle of still fluid.
+    let center: Vec2 = Vec2::splat(grid.dimensions.0 as f32 * grid.cell_size as f32 * 0.5);
+    let swirl_radius: f32 = 3.0 * grid.cell_size as f32;
+    let spin: f32 = 3.0;
+    for row in 0..grid.velocity_u.len() {
+        for col in 0..grid.velocity_u[row].len() {
+            let offset: Vec2 = grid.get_velocity_point_pos(row, col, true) - center;
+            if offset.length() < swirl_radius {
+                grid.velocity_u[row][col] = -spin * offset.y;
+            }
+        }
+    }
+    for row in 0..grid.velocity_v.len() {
+        for col in 0..grid.velocity_v[row].len() {
+            let offset: Vec2 = grid.get_velocity_point_pos(row, col, false) - center;
+            if offset.length() < swirl_radius {
+                grid.velocity_v[row][col] = spin * offset.x;
+            }
+        }
+    }
+    let kinetic_energy = |grid: &SimGrid| -> f32 {
+        let u_energy: f32 = grid.velocity_u.iter().flatten().map(|u| u * u).sum();
+        let v_energy: f32 = grid.velocity_v.iter().flatten().map(|v| v * v).sum();
+        u_energy + v_energy
+    };
+    let energy_before: f32 = kinetic_energy(&grid);
+    let vorticity_before: f32 = grid.get_vorticity_at_position(center);
+
+    // No strength, no change.
+    apply_vorticity_confinement(&mut grid, 0.0, 1.0 / 120.0);
+    assert_eq!(energy_before, kinetic_energy(&grid));
+
+    // Confinement feeds energy back into the swirl without reversing it.
+    apply_vorticity_confinement(&mut grid, 5.0, 1.0 / 120.0);
+    assert!(kinetic_energy(&grid) > energy_before);
+    assert!(grid.get_vorticity_at_position(center) >= vorticity_before);
+}
+
+#[test]
+fn thermal_buoyancy_test() {
+    let mut grid = SimGrid::default();
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Fluid);
+    }
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(0.0);
+    }
+    for row in grid.velocity_v.iter_mut() {
+        row.fill(0.0);
+    }
+
+    // Warm fluid on the left, cool fluid on the right.
+    let rows: usize = grid.dimensions.0 as usize;
+    let cols: usize = grid.dimensions.1 as usize;
+    for row in 0..rows {
+        for col in 0..cols {
+            grid.temperature[row * cols + col] = if col < cols / 2 {
+                80.0
+            } else {
+                AMBIENT_TEMPERATURE
+            };
+        }
+    }
+    let gravity: Vec2 = Vec2::new(0.0, -385.0);
+    let (middle_row, hot_col, cold_col) = (rows / 2, cols / 4, cols * 3 / 4);
+
+    // No expansion, no buoyancy.
+    apply_thermal_buoyancy(&mut grid, gravity, 0.0, 1.0 / 120.0);
+    assert_eq!(grid.velocity_v[middle_row][hot_col], 0.0);
+
+    // Warm fluid rises, cool fluid sinks, and sideways faces are left alone.
+    apply_thermal_buoyancy(&mut grid, gravity, 0.01, 1.0 / 120.0);
+    assert!(grid.velocity_v[middle_row][hot_col] > 0.0);
+    assert!(grid.velocity_v[middle_row][cold_col] < 0.0);
+    assert_eq!(grid.velocity_u[middle_row][hot_col], 0.0);
+}
+
+/// A walled-in tank of fluid, half full and sloshing around every which way.
+#[cfg(test)]
+fn make_sloshing_tank() -> SimGrid {
+    let mut grid = SimGrid::default();
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            grid.cell_type[row][col] = if row == rows - 1 || col == 0 || col == cols - 1 {
+                SimGridCellType::Solid
+            } else if row >= rows / 2 {
+                SimGridCellType::Fluid
+            } else {
+                SimGridCellType::Air
+            };
+        }
+    }
+
+    // Faces touching a wall stay still.
+    let open = |grid: &SimGrid, a: (usize, usize), b: (usize, usize)| -> bool {
+        grid.cell_type[a.0][a.1] != SimGridCellType::Solid
+            && grid.cell_type[b.0][b.1] != SimGridCellType::Solid
+    };
+    for row in 0..rows {
+        for col in 1..cols {
+            if open(&grid, (row, col - 1), (row, col)) {
+                grid.velocity_u[row][col] = ((row * 7 + col * 3) % 11) as f32 - 5.0;
+            }
+        }
+    }
+    for row in 1..rows {
+        for col in 0..cols {
+            if open(&grid, (row - 1, col), (row, col)) {
+                grid.velocity_v[row][col] = ((row * 5 + col * 13) % 7) as f32 - 3.0;
+            }
+        }
+    }
+    grid.update_active_cells();
+
+    grid
+}
+
+#[test]
+fn pressure_solver_test() {
+    let mut constraints = SimConstraints::default();
+    let initial_divergence: f32 = calculate_max_divergence(&make_sloshing_tank());
+    assert!(initial_divergence > 1.0);
+
+    let mut gauss_seidel_grid: SimGrid = make_sloshing_tank();
+    constraints.pressure_solver = SimPressureSolver::GaussSeidel;
+    let gauss_seidel_iterations: u8 =
+        make_grid_velocities_incompressible(&mut gauss_seidel_grid, &mut constraints, None);
+    assert_eq!(constraints.incomp_iters_per_frame, gauss_seidel_iterations);
+
+    /* Conjugate gradient should get rid of (almost) all of the divergence, in fewer iterations and
+    more thoroughly than Gauss-Seidel manages. */
+    let mut conjugate_gradient_grid: SimGrid = make_sloshing_tank();
+    constraints.pressure_solver = SimPressureSolver::ConjugateGradient;
+    let conjugate_gradient_iterations: u8 =
+        make_grid_velocities_incompressible(&mut conjugate_gradient_grid, &mut constraints, None);
+    assert!(conjugate_gradient_iterations < constraints.incomp_iters_per_frame);
+
+    let conjugate_gradient_divergence: f32 = calculate_max_divergence(&conjugate_gradient_grid);
+    assert!(conjugate_gradient_divergence < initial_divergence * 1e-3);
+    assert!(conjugate_gradient_divergence < calculate_max_divergence(&gauss_seidel_grid));
+
+    // Walls stay walls.
+    assert_eq!(0.0, conjugate_gradient_grid.velocity_u[30][1]);
+    assert_eq!(0.0, conjugate_gradient_grid.velocity_v[49][25]);
+}
+
+#[test]
+fn gpu_pressure_solver_fallback_test() {
+    // Without a GPU to run on, the GPU solver leaves the work to the CPU instead.
+    let mut constraints = SimConstraints::default();
+
+    let mut gauss_seidel_grid: SimGrid = make_sloshing_tank();
+    constraints.pressure_solver = SimPressureSolver::GaussSeidel;
+    let gauss_seidel_iterations: u8 =
+        make_grid_velocities_incompressible(&mut gauss_seidel_grid, &mut constraints, None);
+
+    let mut gpu_grid: SimGrid = make_sloshing_tank();
+    constraints.pressure_solver = SimPressureSolver::Gpu;
+    let gpu_iterations: u8 =
+        make_grid_velocities_incompressible(&mut gpu_grid, &mut constraints, None);
+    assert_eq!(gauss_seidel_iterations, gpu_iterations);
+    assert_eq!(gauss_seidel_grid.velocity_u, gpu_grid.velocity_u);
+    assert_eq!(gauss_seidel_grid.velocity_v, gpu_grid.velocity_v);
+    assert_eq!(gauss_seidel_grid.cell_center, gpu_grid.cell_center);
+}
+
+/// Transfers the particles' velocities to the grid, nudges the grid, and transfers them back.
+#[cfg(test)]
+fn test_transfer_update(
+    In(gpu_transfers): In<bool>,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut gpu: Option<ResMut<SimGpu>>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    constraints.gpu_transfers = gpu_transfers;
+    grid.label_cells();
+    transfer_particles_to_grid(
+        grid.as_mut(),
+        &mut particles,
+        constraints.as_ref(),
+        gpu.as_deref_mut(),
+    );
+    grid.store_previous_velocities();
+    for velocity in grid.velocity_v.iter_mut().flatten() {
+        if *velocity != f32::MIN {
+            *velocity += 1.0;
+        }
+    }
+    transfer_grid_to_particles(
+        grid.as_mut(),
+        &mut particles,
+        constraints.as_ref(),
+        gpu.as_deref_mut(),
+    );
+}
+
+/** Transfer a swirling block of fluid's velocities to the grid and back, and return the grid's
+velocities along with every particle's velocity afterward.  Runs on `gpu`, if there is one and
+`gpu_transfers` is set. */
+#[cfg(test)]
+fn run_transfers(
+    gpu_transfers: bool,
+    gpu: Option<SimGpu>,
+) -> (Vec<Vec<f32>>, Vec<Vec<f32>>, Vec<Vec2>) {
+    let mut juicebox_test = App::new();
+    if let Some(gpu) = gpu {
+        juicebox_test.insert_resource(gpu);
+    }
+    let constraints = SimConstraints::default();
+    let mut grid = SimGrid::default();
+    let cell_size: f32 = grid.cell_size as f32;
+    let mut ids: Vec<Entity> = Vec::new();
+    for index in 0..400 {
+        let position: Vec2 = Vec2::new(
+            (index % 20) as f32 * cell_size * 0.5 + cell_size * 10.0,
+            (index / 20) as f32 * cell_size * 0.5 + cell_size * 10.0,
+        );
+        let angle: f32 = index as f32 * 0.618;
+        let id: Entity = juicebox_test
+            .world
+            .spawn(SimParticle {
+                position,
+                velocity: Vec2::new(angle.cos(), angle.sin()) * 20.0,
+                material: (index % 3).into(),
+                radius: constraints.particle_radius,
+                previous_position: position,
+                ..default()
+            })
+            .id();
+        let lookup_index: usize =
+            grid.get_lookup_index(grid.get_cell_coordinates_from_position(&position));
+        grid.add_particle_to_lookup(id, lookup_index);
+        ids.push(id);
+    }
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    juicebox_test
+        .world
+        .run_system_once_with(gpu_transfers, test_transfer_update);
+
+    let grid: &SimGrid = juicebox_test.world.resource::<SimGrid>();
+    let velocities: Vec<Vec2> = ids
+        .iter()
+        .map(|id| {
+            juicebox_test
+                .world
+                .get::<SimParticle>(*id)
+                .unwrap()
+                .velocity
+        })
+        .collect();
+    (grid.velocity_u.clone(), grid.velocity_v.clone(), velocities)
+}
+
+#[test]
+fn gpu_transfer_fallback_test() {
+    // Without a GPU to run on, GPU transfers leave the work to the CPU instead.
+    let (cpu_velocity_u, cpu_velocity_v, cpu_particles) = run_transfers(false, None);
+    let (gpu_velocity_u, gpu_velocity_v, gpu_particles) = run_transfers(true, None);
+    assert_eq!(cpu_velocity_u, gpu_velocity_u);
+    assert_eq!(cpu_velocity_v, gpu_velocity_v);
+    assert_eq!(cpu_particles, gpu_particles);
+
+    // The fluid's velocities actually made it onto the grid, and the grid's change back again.
+    assert!(cpu_velocity_u
+        .iter()
+        .flatten()
+        .any(|velocity| *velocity != f32::MIN && *velocity != 0.0));
+    assert!(cpu_particles.iter().all(|velocity| velocity.is_finite()));
+}
+
+/** Borrow a GPU of our own, since tests have no renderer to borrow one from.  None if this machine
+doesn't have one. */
+#[cfg(test)]
+fn request_test_gpu() -> Option<SimGpu> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter =
+        bevy::tasks::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    let (device, queue) =
+        bevy::tasks::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+            .ok()?;
+    Some(SimGpu::new(
+        RenderDevice::from(device),
+        RenderQueue(Arc::new(queue)),
+    ))
+}
+
+#[test]
+fn gpu_matches_cpu_test() {
+    let Some(mut gpu) = request_test_gpu() else {
+        println!("No GPU adapter available; skipping GPU vs. CPU comparison.");
+        return;
+    };
+
+    // Both relax cells in the same red-black order, so they only differ by rounding.
+    let mut constraints = SimConstraints::default();
+    constraints.pressure_solver = SimPressureSolver::GaussSeidel;
+    let mut cpu_grid: SimGrid = make_sloshing_tank();
+    let cpu_iterations: u8 =
+        make_grid_velocities_incompressible(&mut cpu_grid, &mut constraints, None);
+    constraints.pressure_solver = SimPressureSolver::Gpu;
+    let mut gpu_grid: SimGrid = make_sloshing_tank();
+    let gpu_iterations: u8 =
+        make_grid_velocities_incompressible(&mut gpu_grid, &mut constraints, Some(&mut gpu));
+    assert!(cpu_iterations.abs_diff(gpu_iterations) <= 1);
+    let close = |cpu: &Vec<Vec<f32>>, gpu: &Vec<Vec<f32>>| {
+        cpu.iter()
+            .flatten()
+            .zip(gpu.iter().flatten())
+            .all(|(cpu, gpu)| (cpu - gpu).abs() <= 1e-2 * cpu.abs().max(1.0))
+    };
+    assert!(close(&cpu_grid.velocity_u, &gpu_grid.velocity_u));
+    assert!(close(&cpu_grid.velocity_v, &gpu_grid.velocity_v));
+    assert!(calculate_max_divergence(&gpu_grid) < calculate_max_divergence(&make_sloshing_tank()));
+
+    let (cpu_velocity_u, cpu_velocity_v, cpu_particles) = run_transfers(false, None);
+    let (gpu_velocity_u, gpu_velocity_v, gpu_particles) = run_transfers(true, Some(gpu));
+    assert!(close(&cpu_velocity_u, &gpu_velocity_u));
+    assert!(close(&cpu_velocity_v, &gpu_velocity_v));
+    assert!(cpu_particles
+        .iter()
+        .zip(gpu_particles.iter())
+        .all(|(cpu, gpu)| cpu.abs_diff_eq(*gpu, 1e-2 * cpu.length().max(1.0))));
+}
+
+#[test]
+fn red_black_gauss_seidel_test() {
+    let constraints = SimConstraints::default();
+    let initial_divergence: f32 = calculate_max_divergence(&make_sloshing_tank());
+
+    // Relaxing each color across the task pool gives exactly the same result as relaxing serially.
+    let mut serial_grid: SimGrid = make_sloshing_tank();
+    let mut parallel_grid: SimGrid = make_sloshing_tank();
+    solve_pressure_gauss_seidel(&mut serial_grid, &constraints, false);
+    solve_pressure_gauss_seidel(&mut parallel_grid, &constraints, true);
+    assert_eq!(serial_grid.velocity_u, parallel_grid.velocity_u);
+    assert_eq!(serial_grid.velocity_v, parallel_grid.velocity_v);
+
+    // Red-black ordering still drives the divergence down.
+    assert!(calculate_max_divergence(&parallel_grid) < initial_divergence * 0.75);
+
+    // Walls stay walls.
+    assert_eq!(0.0, parallel_grid.velocity_u[30][1]);
+    assert_eq!(0.0, parallel_grid.velocity_v[49][25]);
+}
+
+/** A channel of fluid `cols` cells wide between walls along the top and bottom, wrapping around
+from its left edge to its right. */
+#[cfg(test)]
+fn make_wrapped_channel(cols: usize) -> SimGrid {
+    let rows: usize = 20;
+    let mut grid = SimGrid::default();
+    grid.dimensions = (rows as u16, cols as u16);
+    grid.wrap_horizontal = true;
+    grid.cell_type = (0..rows)
+        .map(|row| {
+            let wall: bool = row == 0 || row == rows - 1;
+            let cell_type = if wall {
+                SimGridCellType::Solid
+            } else {
+                SimGridCellType::Fluid
+            };
+            vec![cell_type; cols]
+        })
+        .collect();
+    grid.cell_center = vec![vec![0.0; cols]; rows];
+    grid.solid_fraction = vec![vec![0.0; cols]; rows];
+    grid.velocity_u = vec![vec![0.0; cols + 1]; rows];
+    grid.velocity_v = vec![vec![0.0; cols]; rows + 1];
+    grid.fit_cell_data();
+    grid.clear_density_values();
+
+    // The faces on the wrapped edges are the same face, so they share a velocity.
+    for row in 1..rows - 1 {
+        for col in 0..cols {
+            grid.velocity_u[row][col] = ((row * 7 + col * 3) % 11) as f32 - 5.0;
+        }
+        grid.velocity_u[row][cols] = grid.velocity_u[row][0];
+    }
+    for row in 2..rows - 1 {
+        for col in 0..cols {
+            grid.velocity_v[row][col] = ((row * 5 + col * 13) % 7) as f32 - 3.0;
+        }
+    }
+    grid.update_active_cells();
+
+    grid
+}
+
+/** Relax every fluid cell one at a time, in reading order, for `iterations` iterations; plain
+Gauss-Seidel with nothing run side by side. */
+#[cfg(test)]
+fn solve_pressure_serially(grid: &mut SimGrid, constraints: &SimConstraints, iterations: u8) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    for _ in 0..iterations {
+        for row in 0..rows {
+            for col in 0..cols {
+                let Some(relaxation) =
+                    calculate_cell_relaxation(grid, constraints.particle_rest_density, row, col)
+                else {
+                    continue;
+                };
+                if relaxation.weight_sum <= 0.0 {
+                    continue;
+                }
+                let divergence: f32 =
+                    calculate_cell_divergence(grid, row, col) - relaxation.compression;
+                let momentum: f32 =
+                    GAUSS_SEIDEL_OVERRELAXATION * ((0.0 - divergence) / relaxation.weight_sum);
+                let [left, right, up, down] =
+                    relaxation.face_weights.map(|weight| momentum * weight);
+                grid.velocity_u[row][col] -= left;
+                grid.velocity_u[row][col + 1] += right;
+                grid.velocity_v[row][col] += up;
+                grid.velocity_v[row + 1][col] -= down;
+                if col == 0 {
+                    grid.velocity_u[row][cols] -= left;
+                }
+                if col == cols - 1 {
+                    grid.velocity_u[row][0] += right;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn wrapped_red_black_gauss_seidel_test() {
+    let mut constraints = SimConstraints::default();
+    constraints.pressure_tolerance = 0.0;
+    constraints.incomp_iters_per_frame = 200;
+
+    // With an odd number of columns, the cells either side of the wrapped edge would be the same color.
+    let initial_divergence: f32 = calculate_max_divergence(&make_wrapped_channel(25));
+    let mut serial_grid: SimGrid = make_wrapped_channel(25);
+    let mut red_black_grid: SimGrid = make_wrapped_channel(25);
+    let mut parallel_grid: SimGrid = make_wrapped_channel(25);
+    solve_pressure_serially(&mut serial_grid, &constraints, 200);
+    solve_pressure_gauss_seidel(&mut red_black_grid, &constraints, false);
+    solve_pressure_gauss_seidel(&mut parallel_grid, &constraints, true);
+    assert_eq!(red_black_grid.velocity_u, parallel_grid.velocity_u);
+    assert_eq!(red_black_grid.velocity_v, parallel_grid.velocity_v);
+
+    // Relaxing a color at a time converges about as well as relaxing one cell at a time.
+    let serial_divergence: f32 = calculate_max_divergence(&serial_grid);
+    let red_black_divergence: f32 = calculate_max_divergence(&red_black_grid);
+    assert!(serial_divergence < initial_divergence * 0.5);
+    assert!(red_black_divergence < initial_divergence * 0.5);
+    assert!(red_black_divergence < serial_divergence * 2.0);
+
+    // Both copies of each wrapped face moved together.
+    for row in red_black_grid.velocity_u.iter() {
+        assert_eq!(row[0], row[25]);
+    }
+}
+
+#[test]
+fn solver_tolerance_test() {
+    let mut constraints = SimConstraints::default();
+    let initial_divergence: f32 = calculate_max_divergence(&make_sloshing_tank());
+
+    // A loose tolerance lets Gauss-Seidel (which converges slowly) stop well before it runs out.
+    constraints.pressure_tolerance = 0.9;
+    let mut grid: SimGrid = make_sloshing_tank();
+    let iterations: u8 = make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+    assert!(iterations > 1);
+    assert!(iterations < constraints.incomp_iters_per_frame);
+
+    // The residual it leaves behind is reported, and is what's really left in the grid.
+    let residual = constraints.solver_residual;
+    assert_eq!(iterations, residual.iterations);
+    assert_eq!(calculate_max_divergence(&grid), residual.max_divergence);
+    assert!(residual.max_divergence < initial_divergence);
+    assert!(residual.rms_divergence > 0.0);
+    assert!(residual.rms_divergence <= residual.max_divergence);
+
+    // A tighter tolerance takes longer, and leaves less behind.
+    constraints.pressure_tolerance = 0.6;
+    let mut tighter_grid: SimGrid = make_sloshing_tank();
+    let tighter_iterations: u8 =
+        make_grid_velocities_incompressible(&mut tighter_grid, &mut constraints, None);
+    assert!(tighter_iterations > iterations);
+    assert!(constraints.solver_residual.rms_divergence < residual.rms_divergence);
+}
+
+#[test]
+fn multigrid_test() {
+    let mut constraints = SimConstraints::default();
+    let initial_divergence: f32 = calculate_max_divergence(&make_sloshing_tank());
+
+    // Multigrid should clear out the divergence in a handful of V-cycles.
+    let mut multigrid_grid: SimGrid = make_sloshing_tank();
+    constraints.pressure_solver = SimPressureSolver::Multigrid;
+    let multigrid_iterations: u8 =
+        make_grid_velocities_incompressible(&mut multigrid_grid, &mut constraints, None);
+    assert!(multigrid_iterations < constraints.incomp_iters_per_frame);
+    assert!(calculate_max_divergence(&multigrid_grid) < initial_divergence * 1e-3);
+
+    // The coarse levels cover the whole grid, even when it doesn't halve evenly.
+    assert!(multigrid_grid.scratch.pressure.multigrid.len() > 1);
+    let coarsest = multigrid_grid.scratch.pressure.multigrid.last().unwrap();
+    assert!(coarsest.matrix.rows <= 4 || coarsest.matrix.cols <= 4);
+
+    // Walls stay walls.
+    assert_eq!(0.0, multigrid_grid.velocity_u[30][1]);
+    assert_eq!(0.0, multigrid_grid.velocity_v[49][25]);
+}
+
+#[test]
+fn cut_cell_test() {
+    let mut grid: SimGrid = make_sloshing_tank();
+    grid.update_solid_fractions();
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+
+    // Only the tank's bottom corners are cut by the walls' outline.
+    assert_eq!(1.0, grid.get_cell_solid_fraction(rows - 1, 10));
+    assert_eq!(0.5, grid.get_cell_solid_fraction(rows - 2, 1));
+    assert_eq!(0.5, grid.get_cell_solid_fraction(rows - 2, cols - 2));
+    assert_eq!(0.0, grid.get_cell_solid_fraction(rows - 2, 2));
+    assert_eq!(0.0, grid.get_cell_solid_fraction(10, 1));
+
+    // Faces are as open as the cells on either side of them, and closed against walls.
+    assert_eq!(
+        0.75,
+        calculate_face_fraction(&grid, (rows - 2, 1), (rows - 2, 2))
+    );
+    assert_eq!(
+        1.0,
+        calculate_face_fraction(&grid, (rows - 2, 2), (rows - 2, 3))
+    );
+    assert_eq!(
+        0.0,
+        calculate_face_fraction(&grid, (rows - 2, 1), (rows - 2, 0))
+    );
+
+    // Every solver still makes the cut cells incompressible.
+    let initial_divergence: f32 = calculate_max_divergence(&grid);
+    let mut constraints = SimConstraints::default();
+    for solver in [
+        SimPressureSolver::ConjugateGradient,
+        SimPressureSolver::Multigrid,
+    ] {
+        let mut solved_grid: SimGrid = grid.clone();
+        constraints.pressure_solver = solver;
+        make_grid_velocities_incompressible(&mut solved_grid, &mut constraints, None);
+        assert!(calculate_max_divergence(&solved_grid) < initial_divergence * 1e-3);
+    }
+
+    let mut relaxed_grid: SimGrid = grid.clone();
+    solve_pressure_gauss_seidel(&mut relaxed_grid, &constraints, true);
+    assert!(calculate_max_divergence(&relaxed_grid) < initial_divergence * 0.75);
+}
+
+#[test]
+fn active_cells_test() {
+    // A lone fluid cell is active along with the eight cells around it.
+    let mut grid = SimGrid::default();
+    let cols: usize = grid.dimensions.1 as usize;
+    grid.cell_type[25][25] = SimGridCellType::Fluid;
+    grid.update_active_cells();
+    let mut expected: Vec<usize> = Vec::new();
+    for row in 24..=26 {
+        for col in 24..=26 {
+            expected.push(row * cols + col);
+        }
+    }
+    assert_eq!(expected, grid.active_cells);
+
+    // On a wrapped grid, the cells across the edge are active too.
+    grid.set_wrapping(true, false);
+    grid.cell_type[25][25] = SimGridCellType::Air;
+    grid.cell_type[25][0] = SimGridCellType::Fluid;
+    grid.update_active_cells();
+    assert!(grid.active_cells.contains(&(25 * cols + cols - 1)));
+    assert!(!grid.active_cells.contains(&(25 * cols + 2)));
+
+    // The solver only touches the active cells, and still makes them incompressible.
+    let mut grid: SimGrid = make_sloshing_tank();
+    let initial_divergence: f32 = calculate_max_divergence(&grid);
+    let mut constraints = SimConstraints::default();
+    constraints.incomp_iters_per_frame = 100;
+    let air_velocity: f32 = grid.velocity_u[5][10];
+    make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+    assert_eq!(air_velocity, grid.velocity_u[5][10]);
+    assert!(calculate_max_divergence(&grid) < initial_divergence * 0.75);
+}
+
+#[test]
+fn chunk_test() {
+    // Until the cells are labeled, every chunk is visited.
+    let mut grid = SimGrid::default();
+    assert!(grid.chunks.is_cell_active(40, 40));
+    assert_eq!(
+        vec![0..51],
+        grid.chunks.active_spans(40, 51).collect::<Vec<_>>()
+    );
+
+    // Chunks holding fluid are active, along with the chunks around them.
+    grid.cell_type[5][5] = SimGridCellType::Fluid;
+    grid.update_active_cells();
+    assert_eq!((4, 4), grid.chunks.dimensions());
+    assert_eq!(4, grid.chunks.active_count());
+    assert!(grid.chunks.is_cell_active(20, 20));
+    assert!(!grid.chunks.is_cell_active(40, 40));
+    assert_eq!(
+        vec![0..2 * SIM_CHUNK_SIZE],
+        grid.chunks.active_spans(5, 51).collect::<Vec<_>>()
+    );
+    assert_eq!(0, grid.chunks.active_spans(50, 50).count());
+
+    // Spinner blades keep their chunks active even when they're dry.
+    grid.cell_type[5][5] = SimGridCellType::Air;
+    grid.moving_solid_velocity[45 * 50 + 45] = Some(Vec2::ONE);
+    grid.update_active_cells();
+    assert!(grid.chunks.is_cell_active(45, 45));
+    assert!(!grid.chunks.is_cell_active(5, 5));
+
+    // So do a pump's ends, so fluid can reach the intake and leave the outlet.
+    grid.moving_solid_velocity[45 * 50 + 45] = None;
+    grid.pump_cells = vec![5 * 50 + 5];
+    grid.update_active_cells();
+    assert!(grid.chunks.is_cell_active(5, 5));
+    assert!(!grid.chunks.is_cell_active(45, 45));
+
+    // And the whole of a dry waterwheel, not just the cells its blades are in right now.
+    grid.pump_cells.clear();
+    let pivot: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(40.0, 40.0));
+    let between_blades: usize = 36 * 50 + 44;
+    grid.stamp_spinners([SimSpinner::new(pivot, 30.0, 4, 0.0)].iter());
+    assert!(grid.waterwheel_cells.is_empty());
+    grid.stamp_spinners([SimSpinner::new_waterwheel(pivot, 30.0, 4, 0.1)].iter());
+    assert!(grid.moving_solid_velocity[between_blades].is_none());
+    assert!(grid.waterwheel_cells.contains(&between_blades));
+    grid.update_active_cells();
+    assert!(grid.chunks.is_cell_active(40, 40));
+    assert!(!grid.chunks.is_cell_active(5, 5));
+
+    // Large grids don't overflow their lookup indices.
+    grid.dimensions = (1000, 1000);
+    assert_eq!(999_999, grid.get_lookup_index(Vec2::new(999.0, 999.0)));
+}
+
+#[test]
+fn solid_distance_test() {
+    let mut grid = SimGrid::default();
+    for col in 0..grid.dimensions.1 as usize {
+        let _ = grid.set_grid_cell_type(40, col, SimGridCellType::Solid);
+    }
+    grid.update_solid_distance();
+    let above_floor: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(39.0, 10.0));
+    let in_floor: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(40.0, 10.0));
+    let high_up: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(30.0, 10.0));
+
+    // Distances are measured to the floor's surface: positive above it, negative inside of it.
+    let half_cell: f32 = grid.cell_size as f32 / 2.0;
+    assert!((grid.sample_solid_distance(above_floor) - half_cell).abs() < 1e-4);
+    assert!((grid.sample_solid_distance(in_floor) + half_cell).abs() < 1e-4);
+    assert!(
+        grid.sample_solid_distance((above_floor + in_floor) / 2.0)
+            .abs()
+            < 1e-4
+    );
+    assert!(grid.sample_solid_distance(high_up) > grid.sample_solid_distance(above_floor));
+    assert!(grid
+        .get_solid_distance_normal(above_floor)
+        .abs_diff_eq(Vec2::Y, 1e-4));
+
+    // Leaving the grid counts as running into a wall.
+    assert!(grid.sample_solid_distance(Vec2::new(-1.0, 100.0)) < 0.0);
+    assert!(grid
+        .get_solid_distance_normal(Vec2::new(-1.0, 100.0))
+        .abs_diff_eq(Vec2::X, 1e-4));
+    assert!(grid
+        .get_solid_distance_normal(Vec2::new(1.0, 100.0))
+        .abs_diff_eq(Vec2::X, 1e-4));
+
+    // Changing the walls rebuilds the field.
+    let _ = grid.set_grid_cell_type(39, 10, SimGridCellType::Solid);
+    grid.update_solid_distance();
+    assert!(grid.sample_solid_distance(above_floor) < 0.0);
+}
+
+#[test]
+fn liquid_surface_test() {
+    let mut grid = SimGrid::default();
+    let cols: usize = grid.dimensions.1 as usize;
+    let fill =
+        |grid: &mut SimGrid, rows: std::ops::Range<usize>, cols_range: std::ops::Range<usize>| {
+            for row in rows {
+                for col in cols_range.clone() {
+                    grid.cell_type[row][col] = SimGridCellType::Fluid;
+                    grid.density[row * cols + col] = 1.0;
+                }
+            }
+        };
+
+    // No fluid, no surface.
+    let mut surface = SimSurface::default();
+    extract_liquid_surface(&grid, &mut surface);
+    assert!(surface.contours.is_empty());
+
+    // A square block of fluid is outlined by a single loop hugging its cells' edges.
+    fill(&mut grid, 20..30, 10..20);
+    extract_liquid_surface(&grid, &mut surface);
+    assert_eq!(1, surface.contours.len());
+    assert_eq!(0.5, surface.iso_level);
+    let cell_size: f32 = grid.cell_size as f32;
+    let area: f32 = surface
+        .segments()
+        .map(|(start, end)| start.x * end.y - end.x * start.y)
+        .sum::<f32>()
+        .abs()
+        * 0.5;
+    let block_area: f32 = 100.0 * cell_size * cell_size;
+    assert!((area - block_area).abs() < block_area * 0.02);
+    for point in surface.contours[0].iter() {
+        assert!(point.x >= 10.0 * cell_size && point.x <= 20.0 * cell_size);
+    }
+
+    // Separate puddles get separate contours.
+    fill(&mut grid, 40..45, 30..40);
+    extract_liquid_surface(&grid, &mut surface);
+    assert_eq!(2, surface.contours.len());
+    assert_eq!(
+        surface.point_count(),
+        surface.segments().count(),
+        "every contour should close back on itself"
+    );
+}
+
+#[test]
+fn streamline_test() {
+    let mut grid = SimGrid::default();
+    let cell_size: f32 = grid.cell_size as f32;
+    let seed: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(25.0, 25.0));
+
+    // Nothing flows through still fluid.
+    assert!(trace_streamline(&grid, seed, cell_size * 0.5, 100).is_empty());
+
+    // A steady flow to the right gives a straight line running through the seed, left to right.
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(10.0);
+    }
+    let streamline: Vec<Vec2> = trace_streamline(&grid, seed, cell_size * 0.5, 20);
+    assert_eq!(41, streamline.len());
+    assert_eq!(seed, streamline[20]);
+    for pair in streamline.windows(2) {
+        assert!(pair[1].x > pair[0].x);
+        assert!((pair[1].y - seed.y).abs() < 0.001);
+    }
+
+    // Traced far enough, it runs from one side of the grid to the other without leaving it.
+    let streamline: Vec<Vec2> = trace_streamline(&grid, seed, cell_size * 0.5, 1000);
+    let grid_width: f32 = grid.dimensions.1 as f32 * cell_size;
+    assert!(streamline.first().unwrap().x < cell_size);
+    assert!(streamline.last().unwrap().x > grid_width - cell_size);
+    assert!(streamline
+        .iter()
+        .all(|point| grid.is_position_within_grid(point)));
+}
+
+#[test]
+fn pressure_at_position_test() {
+    let mut grid = SimGrid::default();
+    let center = |grid: &SimGrid, row: f32, col: f32| {
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(row, col))
+    };
+    grid.cell_center[20][10] = 4.0;
+    grid.cell_center[20][11] = 8.0;
+
+    // Cell centers read their own pressure, and points between them blend the two.
+    assert_eq!(
+        4.0,
+        grid.get_pressure_at_position(center(&grid, 20.0, 10.0))
+    );
+    assert_eq!(
+        8.0,
+        grid.get_pressure_at_position(center(&grid, 20.0, 11.0))
+    );
+    let between: Vec2 = (center(&grid, 20.0, 10.0) + center(&grid, 20.0, 11.0)) / 2.0;
+    assert_eq!(6.0, grid.get_pressure_at_position(between));
+
+    // Walls don't drag the pressure next to them down.
+    grid.cell_type[20][11] = SimGridCellType::Solid;
+    assert_eq!(4.0, grid.get_pressure_at_position(between));
+}
+
+/// Set up the default scene, but move its fluid with SPH.
+#[cfg(test)]
+fn sph_setup(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    constraints.solver_kind = SimSolverKind::Sph;
+    construct_new_simulation(constraints.as_mut(), grid.as_mut(), &mut commands);
+}
+
+#[test]
+fn sph_test() {
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.add_systems(Startup, sph_setup);
+    juicebox_test.add_systems(Update, test_update);
+
+    juicebox_test.update();
+    let mean_height = |app: &mut App| -> f32 {
+        let heights: Vec<f32> = app
+            .world
+            .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
+            .iter(&app.world)
+            .map(|particle| particle.position.y)
+            .collect();
+        heights.iter().sum::<f32>() / heights.len() as f32
+    };
+    let starting_height: f32 = mean_height(&mut juicebox_test);
+    let particle_count: usize = juicebox_test
+        .world
+        .resource::<SimConstraints>()
+        .particle_count;
+
+    // Let the blob fall and splash for a second.
+    for _ in 0..120 {
+        juicebox_test.update();
+    }
+
+    // Gravity pulled the fluid down without losing any of it or letting it leak through the walls.
+    assert!(mean_height(&mut juicebox_test) < starting_height * 0.75);
+    let constraints: SimConstraints = juicebox_test.world.resource::<SimConstraints>().clone();
+    assert_eq!(particle_count, constraints.particle_count);
+    let grid: SimGrid = juicebox_test.world.resource::<SimGrid>().clone();
+    for particle in juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
+        .iter(&juicebox_test.world)
+    {
+        assert!(grid.is_position_within_grid(&particle.position));
+        let cell: Vec2 = grid.get_cell_coordinates_from_position(&particle.position);
+        assert_ne!(
+            SimGridCellType::Solid,
+            grid.cell_type[cell.x as usize][cell.y as usize]
+        );
+        assert!(particle.velocity.length() < constraints.max_particle_speed);
+    }
+}
+
+#[test]
+fn fluid_material_test() {
+    let mut grid = SimGrid::default();
+    let cell_size: f32 = grid.cell_size as f32;
+    let honey_cell = Vec2::new(25.0, 24.0);
+    let oil_cell = Vec2::new(25.0, 26.0);
+
+    // Cells average the materials deposited in them; empty cells count as water.
+    grid.clear_density_values();
+    let honey_position: Vec2 = grid.get_cell_center_position_from_coordinates(&honey_cell);
+    let oil_position: Vec2 = grid.get_cell_center_position_from_coordinates(&oil_cell);
+    grid.update_grid_density(honey_position, SimFluidMaterial::Honey, 1.0);
+    grid.update_grid_density(oil_position, SimFluidMaterial::Oil, 1.0);
+    let honey_index: usize = grid.get_lookup_index(honey_cell);
+    let oil_index: usize = grid.get_lookup_index(oil_cell);
+    let mixed_index: usize = grid.get_lookup_index(Vec2::new(25.0, 25.0));
+    let empty_index: usize = grid.get_lookup_index(Vec2::new(10.0, 10.0));
+    assert!(grid.get_cell_material_density(honey_index) > 1.3);
+    assert!(grid.get_cell_material_density(oil_index) < 0.9);
+    let mixed_density: f32 = grid.get_cell_material_density(mixed_index);
+    assert!(mixed_density > 0.8 && mixed_density < 1.4);
+    assert_eq!(1.0, grid.get_cell_material_density(empty_index));
+    assert_eq!(0.0, grid.get_cell_material_viscosity(empty_index));
+    assert!(grid.get_cell_material_viscosity(honey_index) > 0.0);
+
+    /* A lone fluid cell between honey and oil pushes more of its correction into the oil, so
+    heavier fluids get shoved around less than lighter ones. */
+    grid.clear_density_values();
+    grid.update_grid_density(
+        honey_position - Vec2::new(cell_size, 0.0),
+        SimFluidMaterial::Honey,
+        1.0,
+    );
+    grid.update_grid_density(
+        oil_position + Vec2::new(cell_size, 0.0),
+        SimFluidMaterial::Oil,
+        1.0,
+    );
+    grid.cell_type[25][25] = SimGridCellType::Fluid;
+    grid.update_active_cells();
+    grid.velocity_u[25][26] = 10.0;
+    let mut constraints = SimConstraints::default();
+    constraints.incomp_iters_per_frame = 1;
+    make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+
+    let honey_face_change: f32 = grid.velocity_u[25][25].abs();
+    let oil_face_change: f32 = (grid.velocity_u[25][26] - 10.0).abs();
+    let water_face_change: f32 = grid.velocity_v[25][25].abs();
+    assert!(honey_face_change > 0.0);
+    assert!(honey_face_change < water_face_change && water_face_change < oil_face_change);
+}
+
+/// Transfer the particles' velocities to the grid.
+#[cfg(test)]
+fn test_particles_to_grid_update(
+    mut grid: ResMut<SimGrid>,
+    constraints: Res<SimConstraints>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    particles_to_grid(grid.as_mut(), &mut particles, constraints.as_ref());
+}
+
+/** What particles_to_grid() ought to give a velocity point: every particle's velocity, weighted by
+its influence on the point, gathered one particle at a time. */
+#[cfg(test)]
+fn gather_velocity_point(
+    grid: &SimGrid,
+    particles: &[SimParticle],
+    row: usize,
+    col: usize,
+    horizontal: bool,
+) -> f32 {
+    if !is_velocity_point_transferred(grid, row, col, horizontal) {
+        return f32::MIN;
+    }
+    let pos: Vec2 = grid.get_velocity_point_pos(row, col, horizontal);
+    let component: usize = if horizontal { 0 } else { 1 };
+    let (mut velocity_sum, mut influence_sum) = (0.0, 0.0);
+    for particle in particles {
+        let position: Vec2 = pos - grid.wrap_offset(pos - particle.position);
+        let influence: f32 = find_influence(position, pos, grid.cell_size)
+            * particle.material.density()
+            * particle.mass;
+        if influence != 0.0 {
+            let affine_velocity: Vec2 = particle.affine_velocity * (pos - position);
+            influence_sum += influence;
+            velocity_sum += (particle.velocity[component] + affine_velocity[component]) * influence;
+        }
+    }
+    if influence_sum == 0.0 {
+        0.0
+    } else {
+        velocity_sum / influence_sum
+    }
+}
+
+#[test]
+fn particles_to_grid_scatter_test() {
+    // Fluid everywhere, with particles scattered across (and hugging the edges of) a wrapped grid.
+    let mut grid = SimGrid::default();
+    grid.set_wrapping(true, true);
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    grid.cell_type = vec![vec![SimGridCellType::Fluid; cols]; rows];
+    grid.cell_type[10][10] = SimGridCellType::Solid;
+    grid.cell_type[10][11] = SimGridCellType::Solid;
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+
+    let mut juicebox_test = App::new();
+    let mut spawned: Vec<SimParticle> = Vec::new();
+    for index in 0..500 {
+        let angle: f32 = index as f32 * 0.618;
+        let position: Vec2 = Vec2::new(
+            (index as f32 * 7.31).rem_euclid(grid_size.x),
+            (index as f32 * 3.17).rem_euclid(grid_size.y),
+        );
+        let particle = SimParticle {
+            position,
+            velocity: Vec2::new(angle.cos(), angle.sin()) * 10.0,
+            material: (index % 3).into(),
+            mass: 1.0 + (index % 2) as f32,
+            affine_velocity: Mat2::from_cols_array(&[angle.sin(), 0.5, -0.5, angle.cos()]),
+            previous_position: position,
+            ..default()
+        };
+        let id: Entity = juicebox_test.world.spawn(particle.clone()).id();
+        let lookup_index: usize =
+            grid.get_lookup_index(grid.get_cell_coordinates_from_position(&position));
+        grid.add_particle_to_lookup(id, lookup_index);
+        spawned.push(particle);
+    }
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.add_systems(Update, test_particles_to_grid_update);
+    juicebox_test.update();
+
+    // Scattering each particle onto the points near it gives what gathering from every particle does.
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    for row in 0..rows {
+        for col in 0..cols + 1 {
+            let expected: f32 = gather_velocity_point(grid, &spawned, row, col, true);
+            assert!((grid.velocity_u[row][col] - expected).abs() <= 1e-4 * expected.abs().max(1.0));
+        }
+    }
+    for row in 0..rows + 1 {
+        for col in 0..cols {
+            let expected: f32 = gather_velocity_point(grid, &spawned, row, col, false);
+            assert!((grid.velocity_v[row][col] - expected).abs() <= 1e-4 * expected.abs().max(1.0));
+        }
+    }
+
+    // Points between the two solid cells are left without a velocity, and the particles reached plenty.
+    assert_eq!(f32::MIN, grid.velocity_u[10][11]);
+    let moving_count: usize = grid
+        .velocity_u
+        .iter()
+        .flatten()
+        .filter(|velocity| **velocity != 0.0 && **velocity != f32::MIN)
+        .count();
+    assert!(moving_count > rows * cols / 4);
+}
+
+/// Applies a strong surface tension to the particles in the simulation.
+#[cfg(test)]
+fn test_surface_tension_update(
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    apply_surface_tension(grid.as_mut(), &mut particles, 1000.0, 1.0 / 120.0);
+}
+
+/// Hand the grid's velocities to the particles with APIC, then hand them straight back.
+#[cfg(test)]
+fn test_apic_update(
+    mut grid: ResMut<SimGrid>,
+    constraints: Res<SimConstraints>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    grid_to_particles(grid.as_mut(), &mut particles, constraints.as_ref());
+    particles_to_grid(grid.as_mut(), &mut particles, constraints.as_ref());
+}
+
+#[test]
+fn apic_test() {
+    let mut grid = SimGrid::default();
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let cell: Vec2 = Vec2::new(25.0, 25.0);
+    grid.cell_type[cell.x as usize][cell.y as usize] = SimGridCellType::Fluid;
+
+    // Spin the whole grid like a wheel around the fluid cell's center.
+    let pivot: Vec2 = grid.get_cell_center_position_from_coordinates(&cell);
+    let angular_velocity: f32 = 2.0;
+    let spin = |position: Vec2| -> Vec2 { (position - pivot).perp() * angular_velocity };
+    for row in 0..rows {
+        for col in 0..cols + 1 {
+            grid.velocity_u[row][col] = spin(grid.get_velocity_point_pos(row, col, true)).x;
+        }
+    }
+    for row in 0..rows + 1 {
+        for col in 0..cols {
+            grid.velocity_v[row][col] = spin(grid.get_velocity_point_pos(row, col, false)).y;
+        }
+    }
+
+    let position: Vec2 = pivot + Vec2::new(1.0, -0.5);
+    let mut juicebox_test = App::new();
+    let particle: Entity = juicebox_test
+        .world
+        .spawn(SimParticle {
+            position,
+            previous_position: position,
+            ..default()
+        })
+        .id();
+    let lookup_index: usize = grid.get_lookup_index(cell);
+    grid.add_particle_to_lookup(particle, lookup_index);
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(SimConstraints {
+        transfer_scheme: SimTransferScheme::Apic,
+        gravity: Vec2::ZERO,
+        ..default()
+    });
+    juicebox_test.add_systems(Update, test_apic_update);
+    juicebox_test.update();
+
+    // The particle picks up both the wheel's velocity and its spin.
+    let particle: &SimParticle = juicebox_test.world.get::<SimParticle>(particle).unwrap();
+    assert!(particle.velocity.abs_diff_eq(spin(position), 1e-3));
+    let expected_affine = Mat2::from_cols(
+        Vec2::new(0.0, angular_velocity),
+        Vec2::new(-angular_velocity, 0.0),
+    );
+    assert!(particle.affine_velocity.abs_diff_eq(expected_affine, 1e-3));
+
+    /* Handing the velocities back, the lone particle's spin carries over to the faces around it,
+    where a plain PIC/FLIP transfer would give every face the particle's own velocity. */
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    let right_face: Vec2 = grid.get_velocity_point_pos(25, 26, true);
+    let top_face: Vec2 = grid.get_velocity_point_pos(25, 25, false);
+    assert!((grid.velocity_v[25][25] - spin(top_face).y).abs() < 1e-3);
+    assert!((grid.velocity_u[25][26] - spin(right_face).x).abs() < 1e-3);
+}
+
+#[test]
+fn advection_test() {
+    // A field that stretches everything away from the grid's left edge, faster the further out it is.
+    let mut grid = SimGrid::default();
+    let stretch_rate: f32 = 3.0;
+    for row in 0..grid.velocity_u.len() {
+        for col in 0..grid.velocity_u[row].len() {
+            grid.velocity_u[row][col] =
+                stretch_rate * grid.get_velocity_point_pos(row, col, true).x;
+        }
+    }
+
+    // The particle starts out moving with the field, like it would after a grid-to-particle transfer.
+    let start: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(25.0, 10.0));
+    let particle = SimParticle {
+        position: start,
+        velocity: interpolate_velocity(start, &grid),
+        previous_position: start,
+        ..default()
+    };
+    let delta_time: f32 = 1.0 / 30.0;
+
+    // Follow the field in tiny steps to find where the particle really ends up.
+    let mut expected: Vec2 = start;
+    for _ in 0..10000 {
+        expected += interpolate_velocity(expected, &grid) * (delta_time / 10000.0);
+    }
+
+    let error = |advection_scheme: SimAdvectionScheme| -> f32 {
+        let constraints = SimConstraints {
+            advection_scheme,
+            gravity: Vec2::ZERO,
+            ..default()
+        };
+        advect_particle(&grid, &constraints, &particle, delta_time).distance(expected)
+    };
+
+    // Sampling the field partway through the step keeps the particle much closer to its true path.
+    let euler_error: f32 = error(SimAdvectionScheme::Euler);
+    let midpoint_error: f32 = error(SimAdvectionScheme::Midpoint);
+    let rk3_error: f32 = error(SimAdvectionScheme::Rk3);
+    assert!(midpoint_error < euler_error * 0.2);
+    assert!(rk3_error < midpoint_error);
+}
+
+/// Steps the foam, spray, and bubbles by a sixtieth of a second.
+#[cfg(test)]
+fn test_secondary_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    grid: Res<SimGrid>,
+    particles: Query<&SimParticle, Without<SimInactiveParticle>>,
+    mut secondary_particles: Query<(Entity, &mut SimSecondaryParticle)>,
+) {
+    step_secondary_particles(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_ref(),
+        &particles,
+        &mut secondary_particles,
+        1.0 / 60.0,
+    );
+}
+
+#[test]
+fn secondary_particles_test() {
+    // A pool of fluid in the middle of the grid, with a particle leaping off of its surface.
+    let mut grid = SimGrid::default();
+    for row in 20..30 {
+        for col in 10..40 {
+            grid.cell_type[row][col] = SimGridCellType::Fluid;
+        }
+    }
+    let surface_position: Vec2 =
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(20.0, 25.0));
+    let bubble_position: Vec2 =
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(27.0, 25.0));
+    let spray_position: Vec2 =
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(5.0, 25.0));
+
+    let mut juicebox_test = App::new();
+    juicebox_test.world.spawn(SimParticle {
+        position: surface_position,
+        velocity: Vec2::new(0.0, 400.0),
+        previous_position: surface_position,
+        ..default()
+    });
+    let bubble: Entity = juicebox_test
+        .world
+        .spawn(SimSecondaryParticle {
+            position: bubble_position,
+            lifetime: 1.0,
+            ..default()
+        })
+        .id();
+    let spray: Entity = juicebox_test
+        .world
+        .spawn(SimSecondaryParticle {
+            position: spray_position,
+            lifetime: 1.0,
+            ..default()
+        })
+        .id();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(SimConstraints {
+        secondary_particles: true,
+        secondary_spawn_rate: 1000.0,
+        ..default()
+    });
+    juicebox_test.add_systems(Update, test_secondary_update);
+    juicebox_test.update();
+
+    // Bubbles under the surface float up, while spray in the air falls.
+    let bubble: &SimSecondaryParticle = juicebox_test.world.get(bubble).unwrap();
+    assert_eq!(SimSecondaryKind::Bubble, bubble.kind);
+    assert!(bubble.position.y > bubble_position.y);
+    let spray: &SimSecondaryParticle = juicebox_test.world.get(spray).unwrap();
+    assert_eq!(SimSecondaryKind::Spray, spray.kind);
+    assert!(spray.velocity.y < 0.0);
+
+    // The leaping particle throws off foam on its way out of the pool.
+    let mut secondary_particles = juicebox_test.world.query::<&SimSecondaryParticle>();
+    assert!(secondary_particles.iter(&juicebox_test.world).count() > 2);
+    assert!(secondary_particles
+        .iter(&juicebox_test.world)
+        .any(|secondary| secondary.kind == SimSecondaryKind::Foam));
+
+    // Turning them off clears them all away.
+    juicebox_test
+        .world
+        .resource_mut::<SimConstraints>()
+        .secondary_particles = false;
+    juicebox_test.update();
+    let mut secondary_particles = juicebox_test.world.query::<&SimSecondaryParticle>();
+    assert_eq!(0, secondary_particles.iter(&juicebox_test.world).count());
+}
+
+#[test]
+fn surface_tension_test() {
+    // A round blob of fluid in the middle of the grid.
+    let mut grid = SimGrid::default();
+    let blob_cell = Vec2::new(25.0, 25.0);
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            let cell = Vec2::new(row as f32, col as f32);
+            if cell.distance(blob_cell) <= 6.0 {
+                grid.cell_type[row][col] = SimGridCellType::Fluid;
+                let lookup_index: usize = grid.get_lookup_index(cell);
+                grid.density[lookup_index] = 1.0;
+            }
+        }
+    }
+    let blob_center: Vec2 = grid.get_cell_center_position_from_coordinates(&blob_cell);
+    let edge_position: Vec2 = blob_center - Vec2::new(6.0 * grid.cell_size as f32, 0.0);
+
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.world.spawn(SimParticle {
+        position: edge_position,
+        previous_position: edge_position,
+        ..default()
+    });
+    juicebox_test.add_systems(Update, test_surface_tension_update);
+    juicebox_test.update();
+
+    // Particles on the blob's surface get pulled back towards its center.
+    let velocity: Vec2 = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
+        .single(&juicebox_test.world)
+        .velocity;
+    assert!(velocity.x > 0.0);
+    assert!(velocity.y.abs() < velocity.x * 0.1);
+}
+
+/// Applies a strong interface tension to the particles in the simulation.
+#[cfg(test)]
+fn test_interface_tension_update(
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    apply_interface_tension(grid.as_mut(), &mut particles, 1000.0, 1.0 / 120.0);
+}
+
+#[test]
+fn interface_tension_test() {
+    // A round blob of oil in the middle of a square pool of water.
+    let grid = SimGrid::default();
+    let blob_cell = Vec2::new(25.0, 25.0);
+    let mut juicebox_test = App::new();
+    let mut oil_edge: Option<Entity> = None;
+    let mut water_edge: Option<Entity> = None;
+    for row in 15..=35 {
+        for col in 15..=35 {
+            let cell = Vec2::new(row as f32, col as f32);
+            let position: Vec2 = grid.get_cell_center_position_from_coordinates(&cell);
+            let material: SimFluidMaterial = if cell.distance(blob_cell) <= 6.0 {
+                SimFluidMaterial::Oil
+            } else {
+                SimFluidMaterial::Water
+            };
+            let id: Entity = juicebox_test
+                .world
+                .spawn(SimParticle {
+                    position,
+                    material,
+                    previous_position: position,
+                    ..default()
+                })
+                .id();
+            match (row, col) {
+                (25, 19) => oil_edge = Some(id),
+                (25, 15) => water_edge = Some(id),
+                _ => {}
+            }
+        }
+    }
+    juicebox_test.insert_resource(grid);
+    juicebox_test.add_systems(Update, test_interface_tension_update);
+    juicebox_test.update();
+
+    // Oil on the blob's edge gets pulled back towards its center.
+    let velocity: Vec2 = juicebox_test
+        .world
+        .get::<SimParticle>(oil_edge.unwrap())
+        .unwrap()
+        .velocity;
+    assert!(velocity.x > 0.0);
+    assert!(velocity.y.abs() < velocity.x * 0.1);
+
+    // The pool's surface against the air is left alone; that's surface tension's job.
+    let velocity: Vec2 = juicebox_test
+        .world
+        .get::<SimParticle>(water_edge.unwrap())
+        .unwrap()
+        .velocity;
+    assert_eq!(Vec2::ZERO, velocity);
+}
+
+#[test]
+fn point_along_path_test() {
+    let path = vec![Vec2::ZERO, Vec2::new(10.0, 0.0), Vec2::new(10.0, 10.0)];
+
+    assert_eq!(None, point_along_path(&[], 5.0));
+    assert_eq!(Some(Vec2::new(5.0, 0.0)), point_along_path(&path, 5.0));
+    assert_eq!(Some(Vec2::new(10.0, 5.0)), point_along_path(&path, 15.0));
+
+    // Past the end of the path, we head back the way we came and eventually return to the start.
+    assert_eq!(Some(Vec2::new(10.0, 5.0)), point_along_path(&path, 25.0));
+    assert_eq!(Some(Vec2::new(5.0, 0.0)), point_along_path(&path, 35.0));
+    assert_eq!(Some(Vec2::ZERO), point_along_path(&path, 40.0));
+}
+
+#[test]
+fn spinner_test() {
+    let mut grid = SimGrid::default();
+    let pivot: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(25.0, 25.0));
+    let mut spinner = SimSpinner::new(pivot, 20.0, 2, 2.0);
+
+    // A two-bladed spinner lying flat turns the cells to either side of its pivot solid.
+    grid.stamp_spinners(std::iter::once(&spinner));
+    grid.label_cells();
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[25][28]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[25][22]);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[22][25]);
+
+    // Spinning counter-clockwise, the right-hand blade moves straight up.
+    let blade_velocity: Vec2 = grid.get_moving_solid_velocity(25, 28).unwrap();
+    assert!(blade_velocity.y > 0.0);
+    assert!(blade_velocity.x.abs() < 1e-3);
+
+    // A quarter turn later, the blades stand upright and the cells they left are open again.
+    spinner.rotate(PI / 4.0);
+    grid.stamp_spinners(std::iter::once(&spinner));
+    grid.label_cells();
+    assert_eq!(SimGridCellType::Air, grid.cell_type[25][28]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[22][25]);
+    assert!(grid.get_moving_solid_velocity(22, 25).unwrap().x < 0.0);
+
+    // Spinners never carve through walls, and leave them be once they've passed.
+    let _ = grid.set_grid_cell_type(28, 25, SimGridCellType::Solid);
+    grid.stamp_spinners(std::iter::once(&spinner));
+    assert_eq!(None, grid.get_moving_solid_velocity(28, 25));
+    grid.stamp_spinners(std::iter::empty());
+    grid.label_cells();
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[28][25]);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[22][25]);
+}
+
+#[test]
+fn spinner_pushes_fluid_test() {
+    let mut constraints = SimConstraints::default();
+    constraints.pressure_solver = SimPressureSolver::ConjugateGradient;
+
+    // Submerge a spinner in still fluid.
+    let mut grid: SimGrid = make_sloshing_tank();
+    for velocities in [&mut grid.velocity_u, &mut grid.velocity_v] {
+        for row in velocities.iter_mut() {
+            row.fill(0.0);
+        }
+    }
+    let pivot: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(37.0, 25.0));
+    let spinner = SimSpinner::new(pivot, 10.0, 2, 2.0);
+    grid.stamp_spinners(std::iter::once(&spinner));
+    for row in 0..grid.dimensions.0 as usize {
+        for col in 0..grid.dimensions.1 as usize {
+            if grid.get_moving_solid_velocity(row, col).is_some() {
+                grid.cell_type[row][col] = SimGridCellType::Solid;
+            }
+        }
+    }
+
+    // The blade's faces move with the blade, and the fluid around it is pushed out of the way.
+    make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+    let blade_velocity: Vec2 = grid.get_moving_solid_velocity(37, 26).unwrap();
+    assert_eq!(blade_velocity.y, grid.velocity_v[37][26]);
+    assert_eq!(blade_velocity.y, grid.velocity_v[38][26]);
+    assert!(grid.velocity_v[36][26] > 0.0);
+    assert!(calculate_max_divergence(&grid) < blade_velocity.y * 1e-3);
+}
+
+#[test]
+fn waterwheel_test() {
+    // Fluid flows rightwards over the top half of the grid and sits still in the bottom half.
+    let mut grid = SimGrid::default();
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Fluid);
+    }
+    for (row, velocities) in grid.velocity_u.iter_mut().enumerate() {
+        velocities.fill(if row < 25 { 100.0 } else { 0.0 });
+    }
+    let pivot: Vec2 = Vec2::splat(grid.cell_size as f32 * 25.0);
+
+    // Flow over the top of a waterwheel turns it clockwise.
+    let mut waterwheel = SimSpinner::new_waterwheel(pivot, 30.0, 4, 0.1);
+    waterwheel.spin_with_flow(&grid, 0.05);
+    assert!(waterwheel.angular_velocity < 0.0);
+
+    // Left alone in still fluid, friction slowly brings it to a stop.
+    let spin: f32 = waterwheel.angular_velocity;
+    for velocities in grid.velocity_u.iter_mut() {
+        velocities.fill(0.0);
+    }
+    waterwheel.spin_with_flow(&grid, 0.05);
+    assert!(waterwheel.angular_velocity > spin && waterwheel.angular_velocity <= 0.0);
+
+    // Motorized spinners ignore the flow entirely.
+    let mut spinner = SimSpinner::new(pivot, 30.0, 4, 2.0);
+    spinner.spin_with_flow(&grid, 0.05);
+    assert_eq!(2.0, spinner.angular_velocity);
+}
+
+/// Moves the particles one step with gravity switched off.
+#[cfg(test)]
+fn test_wind_update(
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    let constraints = SimConstraints {
+        gravity: Vec2::ZERO,
+        ..default()
+    };
+    update_particles(
+        &constraints,
+        &mut particles,
+        grid.as_mut(),
+        1.0 / 60.0,
+        true,
+    );
+}
+
+#[test]
+fn wind_test() {
+    // Paint a rightward wind over the middle of the grid.
+    let mut grid = SimGrid::default();
+    let center: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(25.0, 25.0));
+    let calm_position: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(5.0, 5.0));
+    let wind: Vec2 = Vec2::new(300.0, 0.0);
+    grid.paint_wind(center, 10.0, wind);
+    assert_eq!(wind, grid.get_wind_at_position(center));
+    assert_eq!(Vec2::ZERO, grid.get_wind_at_position(calm_position));
+
+    // Particles in the wind are blown along by it, while particles outside of it are left be.
+    let mut juicebox_test = App::new();
+    let mut spawn_particle = |position: Vec2| -> Entity {
+        juicebox_test
+            .world
+            .spawn(SimParticle {
+                position,
+                previous_position: position,
+                ..default()
+            })
+            .id()
+    };
+    let blown: Entity = spawn_particle(center);
+    let calm: Entity = spawn_particle(calm_position);
+    juicebox_test.insert_resource(grid);
+    juicebox_test.add_systems(Update, test_wind_update);
+    juicebox_test.update();
+
+    let blown: &SimParticle = juicebox_test.world.get(blown).unwrap();
+    assert!((blown.velocity - wind / 60.0).length() < 1e-3);
+    assert!(blown.position.x > center.x);
+    let calm: &SimParticle = juicebox_test.world.get(calm).unwrap();
+    assert_eq!(Vec2::ZERO, calm.velocity);
+
+    // Painting calm air over the wind stops it.
+    let mut grid = juicebox_test.world.resource_mut::<SimGrid>();
+    grid.paint_wind(center, 10.0, Vec2::ZERO);
+    assert_eq!(Vec2::ZERO, grid.get_wind_at_position(center));
+}
+
+#[cfg(test)]
+/// Move particles along by one frame under whatever gravity the test set.
+fn test_gravity_update(
+    constraints: Res<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    update_particles(
+        constraints.as_ref(),
+        &mut particles,
+        grid.as_mut(),
+        1.0 / 60.0,
+        true,
+    );
+}
+
+#[test]
+fn gravity_preset_test() {
+    // Presets all point straight down, scaled relative to Earth's gravity.
+    assert_eq!(
+        SimConstraints::default().gravity,
+        SimGravityPreset::Earth.gravity()
+    );
+    assert_eq!(
+        Vec2::new(0.0, -EARTH_GRAVITY),
+        SimGravityPreset::Earth.gravity()
+    );
+    assert!(SimGravityPreset::Moon.strength() < SimGravityPreset::Mars.strength());
+    assert!(SimGravityPreset::Jupiter.strength() > EARTH_GRAVITY);
+    assert_eq!(Vec2::ZERO, SimGravityPreset::ZeroG.gravity());
+    assert!(SimGravityPreset::ZeroG.min_surface_tension() > 0.0);
+
+    // Strengths can be matched back up with the preset they came from.
+    for index in 0..GRAVITY_PRESET_COUNT {
+        let preset: SimGravityPreset = index.into();
+        assert_eq!(
+            Some(preset),
+            SimGravityPreset::from_strength(preset.strength())
+        );
+    }
+    assert_eq!(None, SimGravityPreset::from_strength(EARTH_GRAVITY * 0.5));
+
+    // Without gravity, a drifting particle keeps drifting in a straight line rather than falling.
+    let mut juicebox_test = App::new();
+    let position: Vec2 = Vec2::new(250.0, 250.0);
+    let velocity: Vec2 = Vec2::new(30.0, 0.0);
+    let floating: Entity = juicebox_test
+        .world
+        .spawn(SimParticle {
+            position,
+            velocity,
+            previous_position: position,
+            ..default()
+        })
+        .id();
+    juicebox_test.insert_resource(SimConstraints {
+        gravity: SimGravityPreset::ZeroG.gravity(),
+        ..default()
+    });
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.add_systems(Update, test_gravity_update);
+    juicebox_test.update();
+
+    let floating: &SimParticle = juicebox_test.world.get(floating).unwrap();
+    assert_eq!(velocity, floating.velocity);
+    assert_eq!(position.y, floating.position.y);
+    assert!(floating.position.x > position.x);
+}
+
+#[test]
+fn attractor_test() {
+    let center: Vec2 = Vec2::new(100.0, 100.0);
+    let attractor = SimAttractor::new(center, 40.0, 600.0);
+
+    // Particles within reach are pulled straight towards the center, harder the closer they are.
+    let near: Vec2 = attractor.acceleration_at(center + Vec2::new(10.0, 0.0));
+    let far: Vec2 = attractor.acceleration_at(center + Vec2::new(30.0, 0.0));
+    assert!(near.x < far.x && far.x < 0.0);
+    assert_eq!(0.0, near.y);
+    assert!((near.x + 600.0 * 0.75).abs() < 1e-3);
+
+    // Nothing outside the radius (or right at the center) is pulled at all.
+    assert_eq!(
+        Vec2::ZERO,
+        attractor.acceleration_at(center + Vec2::new(0.0, 50.0))
+    );
+    assert_eq!(Vec2::ZERO, attractor.acceleration_at(center));
+
+    // Repulsors push instead.
+    let repulsor = SimAttractor::new(center, 40.0, -600.0);
+    assert!(repulsor.acceleration_at(center + Vec2::new(0.0, 10.0)).y > 0.0);
+}
+
+#[test]
+fn wrap_around_test() {
+    // Wrapping left to right opens the side walls, but leaves the floor and ceiling (and corners).
+    let mut grid = SimGrid::default();
+    grid.force_edge_solids();
+    grid.set_wrapping(true, false);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[25][0]);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[25][49]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[0][0]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[49][25]);
+
+    // Neighbors and positions past the sides come back in from the other side.
+    assert_eq!(Some((25, 49)), grid.wrap_cell(25, usize::MAX));
+    assert_eq!(Some((25, 0)), grid.wrap_cell(25, 50));
+    assert_eq!(None, grid.wrap_cell(50, 25));
+    assert_eq!(
+        Vec2::new(2.0, 100.0),
+        grid.wrap_position(Vec2::new(252.0, 100.0))
+    );
+    assert_eq!(
+        Vec2::new(-4.0, 0.0),
+        grid.wrap_offset(Vec2::new(246.0, 0.0))
+    );
+
+    // A particle moving off of the right edge reappears on the left.
+    let mut juicebox_test = App::new();
+    let position: Vec2 = Vec2::new(248.0, 100.0);
+    let particle: Entity = juicebox_test
+        .world
+        .spawn(SimParticle {
+            position,
+            velocity: Vec2::new(300.0, 0.0),
+            previous_position: position,
+            ..default()
+        })
+        .id();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.add_systems(Update, test_wind_update);
+    juicebox_test.update();
+
+    let particle: &SimParticle = juicebox_test.world.get(particle).unwrap();
+    assert!((particle.position - Vec2::new(3.0, 100.0)).length() < 1e-3);
+    assert!((particle.velocity - Vec2::new(300.0, 0.0)).length() < 1e-3);
+
+    // Unwrapping puts the side walls back.
+    let mut grid = juicebox_test.world.resource_mut::<SimGrid>();
+    grid.set_wrapping(false, false);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[25][0]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[25][49]);
+}
+
+#[test]
+fn wrapped_pressure_test() {
+    // Open the sloshing tank's side walls, so the fluid flows out of one side and into the other.
+    let mut wrapped_tank: SimGrid = make_sloshing_tank();
+    let (rows, cols) = (
+        wrapped_tank.dimensions.0 as usize,
+        wrapped_tank.dimensions.1 as usize,
+    );
+    wrapped_tank.set_wrapping(true, false);
+    for row in rows / 2..rows - 1 {
+        for col in [0, cols - 1] {
+            wrapped_tank.cell_type[row][col] = SimGridCellType::Fluid;
+        }
+        wrapped_tank.velocity_u[row][0] = 4.0;
+        wrapped_tank.velocity_u[row][cols] = 4.0;
+    }
+    wrapped_tank.update_active_cells();
+    let initial_divergence: f32 = calculate_max_divergence(&wrapped_tank);
+    assert!(initial_divergence > 1.0);
+
+    let mut constraints = SimConstraints::default();
+    for solver in [
+        SimPressureSolver::ConjugateGradient,
+        SimPressureSolver::Multigrid,
+    ] {
+        let mut grid: SimGrid = wrapped_tank.clone();
+        constraints.pressure_solver = solver;
+        make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+        assert!(calculate_max_divergence(&grid) < initial_divergence * 1e-3);
+
+        // Both copies of the faces on the wrapped edges stay the same face.
+        for row in rows / 2..rows - 1 {
+            assert_eq!(grid.velocity_u[row][0], grid.velocity_u[row][cols]);
+        }
+    }
+}
+
+#[test]
+fn open_edge_test() {
+    // Opening an edge takes down its wall, but leaves the corners (and the other edges) walled.
+    let mut grid = SimGrid::default();
+    grid.force_edge_solids();
+    grid.set_edge_boundary(SimGridEdge::Bottom, SimEdgeBoundary::Open);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[49][25]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[49][0]);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[25][0]);
+
+    // Past the open edge is open air; past the others (and the corners) is still wall.
+    assert!(grid.is_cell_past_open_edge(50, 25));
+    assert_eq!(1.0, grid.get_cell_type_value(50, 25));
+    assert_eq!(0.0, grid.get_cell_type_value(25, 50));
+    assert!(!grid.is_cell_past_open_edge(50, 50));
+    assert!(grid.is_position_past_open_edge(Vec2::new(125.0, -1.0)));
+    assert!(!grid.is_position_past_open_edge(Vec2::new(-1.0, 125.0)));
+
+    // Particles falling out through the open edge are gone, while the rest stay put.
+    let mut juicebox_test = App::new();
+    let mut spawn_particle = |position: Vec2, velocity: Vec2| -> Entity {
+        juicebox_test
+            .world
+            .spawn(SimParticle {
+                position,
+                velocity,
+                previous_position: position,
+                ..default()
+            })
+            .id()
+    };
+    let falling: Entity = spawn_particle(Vec2::new(125.0, 3.0), Vec2::new(0.0, -600.0));
+    let floating: Entity = spawn_particle(Vec2::new(125.0, 125.0), Vec2::ZERO);
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.add_systems(Update, test_update);
+    juicebox_test.update();
+    let is_active = |entity: Entity| -> bool {
+        let particle = juicebox_test.world.entity(entity);
+        particle.contains::<SimParticle>() && !particle.contains::<SimInactiveParticle>()
+    };
+    assert!(!is_active(falling));
+    assert!(is_active(floating));
+
+    // Walling the edge back up puts the wall back.
+    let mut grid = juicebox_test.world.resource_mut::<SimGrid>();
+    grid.set_edge_boundary(SimGridEdge::Bottom, SimEdgeBoundary::Wall);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[49][25]);
+}
+
+#[test]
+fn open_edge_pressure_test() {
+    // Open the sloshing tank's right wall, so the fluid can push its way out through it.
+    let mut open_tank: SimGrid = make_sloshing_tank();
+    let (rows, cols) = (
+        open_tank.dimensions.0 as usize,
+        open_tank.dimensions.1 as usize,
+    );
+    open_tank.set_edge_boundary(SimGridEdge::Right, SimEdgeBoundary::Open);
+    for row in rows / 2..rows - 1 {
+        open_tank.cell_type[row][cols - 1] = SimGridCellType::Fluid;
+        open_tank.velocity_u[row][cols - 1] = 4.0;
+        open_tank.velocity_u[row][cols] = 0.0;
+    }
+    open_tank.update_active_cells();
+    let initial_divergence: f32 = calculate_max_divergence(&open_tank);
+    assert!(initial_divergence > 1.0);
+
+    /* The air past the edge has no pressure to push back with, so the fluid piling up against it
+    flows straight out. */
+    let mut constraints = SimConstraints::default();
+    for solver in [
+        SimPressureSolver::ConjugateGradient,
+        SimPressureSolver::Multigrid,
+    ] {
+        let mut grid: SimGrid = open_tank.clone();
+        constraints.pressure_solver = solver;
+        make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+        assert!(calculate_max_divergence(&grid) < initial_divergence * 1e-3);
+        assert!(grid.velocity_u[rows - 2][cols] > 0.0);
+    }
+}
+
+#[test]
+fn inflow_test() {
+    let mut grid = SimGrid::default();
+    grid.force_edge_solids();
+    grid.set_edge_boundary(SimGridEdge::Left, SimEdgeBoundary::Inflow);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[25][0]);
+    assert!(!grid.is_edge_passable(SimGridEdge::Left));
+
+    // Uniform inflows push straight into the grid at the same speed all along the edge.
+    let mut constraints = SimConstraints::default();
+    let middle: Vec2 = Vec2::new(0.0, 125.0);
+    let near_corner: Vec2 = Vec2::new(0.0, 7.5);
+    assert_eq!(
+        Vec2::new(100.0, 0.0),
+        calculate_inflow_velocity(&constraints, &grid, SimGridEdge::Left, near_corner)
+    );
+
+    // Parabolic inflows peak in the middle of the edge and slow down towards its ends.
+    constraints.inflow_profile = SimInflowProfile::Parabolic;
+    let peak: Vec2 = calculate_inflow_velocity(&constraints, &grid, SimGridEdge::Left, middle);
+    let edge: Vec2 = calculate_inflow_velocity(&constraints, &grid, SimGridEdge::Left, near_corner);
+    assert!((peak.x - 100.0).abs() < 1.0);
+    assert!(edge.x > 0.0 && edge.x < peak.x * 0.25);
+    assert!(calculate_inflow_velocity(&constraints, &grid, SimGridEdge::Top, middle).y <= 0.0);
+
+    // Stepping the simulation fills the inflow edge with particles and pushes the fluid inwards.
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.add_systems(Update, test_update);
+    juicebox_test.update();
+    juicebox_test.update();
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    let particles: Vec<&SimParticle> = particles.iter(&juicebox_test.world).collect();
+    assert!(particles.len() >= 48 * 4);
+    assert!(particles.iter().all(|particle| particle.position.x < 25.0));
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    assert_eq!(100.0, grid.velocity_u[25][0]);
+}
+
+#[test]
+fn porous_test() {
+    // Porous cells let fluid through in between walls and open air.
+    assert_eq!(0.0, SimGridCellType::Solid.permeability());
+    assert_eq!(1.0, SimGridCellType::Air.permeability());
+    assert_eq!(0.25, SimGridCellType::Porous(0.25).permeability());
+    assert_eq!(1.0, SimGridCellType::Porous(3.0).permeability());
+
+    let mut grid = SimGrid::default();
+    grid.cell_type[25][25] = SimGridCellType::Fluid;
+    grid.cell_type[25][26] = SimGridCellType::Porous(0.25);
+    assert_eq!(0.25, grid.get_cell_type_value(25, 26));
+
+    // Pressure pushes fluid into porous cells only as easily as they let it through.
+    let open_weight: f32 = calculate_face_weight(&grid, (25, 25), (25, 24));
+    let porous_weight: f32 = calculate_face_weight(&grid, (25, 25), (25, 26));
+    assert!((porous_weight - open_weight * 0.25).abs() < 1e-6);
+
+    // Porous cells stay porous when the cells are relabeled.
+    grid.label_cells();
+    assert_eq!(SimGridCellType::Porous(0.25), grid.cell_type[25][26]);
+
+    // Flow through a porous cell's faces is slowed down; flow elsewhere is left alone.
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(100.0);
+    }
+    apply_porous_drag(&mut grid, 1.0 / POROUS_DRAG_RATE);
+    assert!((grid.velocity_u[25][26] - 25.0).abs() < 1e-3);
+    assert!((grid.velocity_u[25][27] - 25.0).abs() < 1e-3);
+    assert_eq!(100.0, grid.velocity_u[25][25]);
+    assert_eq!(100.0, grid.velocity_u[10][10]);
+}
+
+#[test]
+fn valve_test() {
+    let mut grid = SimGrid::default();
+    let _ = grid.set_valve(25, 25, Some(SimSurfaceDirection::East));
+    let _ = grid.set_valve(10, 10, Some(SimSurfaceDirection::North));
+    assert_eq!(Some(SimSurfaceDirection::East), grid.get_valve(25, 25));
+    assert_eq!(None, grid.get_valve(25, 26));
+    assert_eq!(None, grid.get_valve(500, 500));
+
+    // Flow through a valve the wrong way is stopped; flow the right way passes untouched.
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(-50.0);
+    }
+    for row in grid.velocity_v.iter_mut() {
+        row.fill(-50.0);
+    }
+    grid.velocity_u[25][26] = 50.0;
+    apply_valves(&mut grid);
+    assert_eq!(0.0, grid.velocity_u[25][25]);
+    assert_eq!(50.0, grid.velocity_u[25][26]);
+    assert_eq!(-50.0, grid.velocity_u[25][27]);
+    assert_eq!(-50.0, grid.velocity_v[25][25]);
+
+    // Valves pointing north only let fluid flow upwards (positive v) through them.
+    assert_eq!(0.0, grid.velocity_v[10][10]);
+    assert_eq!(0.0, grid.velocity_v[11][10]);
+    assert_eq!(-50.0, grid.velocity_u[10][10]);
+
+    // Removing a valve lets fluid through both ways again.
+    let _ = grid.set_valve(25, 25, None);
+    grid.velocity_u[25][25] = -50.0;
+    apply_valves(&mut grid);
+    assert_eq!(-50.0, grid.velocity_u[25][25]);
+}
+
+#[test]
+fn probe_test() {
+    let mut constraints = SimConstraints::default();
+    let (fluid_row, col): (usize, usize) = (40, 25);
+    let air_row: usize = 10;
+
+    // Every solver leaves its pressures behind in the grid, and air is always at zero pressure.
+    for solver in [
+        SimPressureSolver::GaussSeidel,
+        SimPressureSolver::ConjugateGradient,
+        SimPressureSolver::Multigrid,
+    ] {
+        let mut grid: SimGrid = make_sloshing_tank();
+        constraints.pressure_solver = solver;
+        make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+        assert_ne!(0.0, grid.cell_center[fluid_row][col]);
+        assert_eq!(0.0, grid.cell_center[air_row][col]);
+    }
+
+    // Probes record whatever is going on in their cell.
+    let mut grid: SimGrid = make_sloshing_tank();
+    make_grid_velocities_incompressible(&mut grid, &mut constraints, None);
+    let position: Vec2 =
+        grid.get_cell_center_position_from_coordinates(&Vec2::new(fluid_row as f32, col as f32));
+    let mut probe = SimProbe::new(position);
+    probe.sample(&grid, 0.5);
+    let sample = *probe.latest().unwrap();
+    assert_eq!(0.5, sample.time);
+    assert_eq!(grid.cell_center[fluid_row][col], sample.pressure);
+    assert_eq!(
+        calculate_cell_divergence(&grid, fluid_row, col),
+        sample.divergence
+    );
+    assert_eq!(Some(sample.velocity), sample_grid_velocity(&grid, position));
+
+    // Only the most recent samples are kept.
+    for step in 0..PROBE_HISTORY_LENGTH + 10 {
+        probe.sample(&grid, 1.0 + step as f32);
+    }
+    assert_eq!(PROBE_HISTORY_LENGTH, probe.samples.len());
+    assert_eq!(11.0, probe.samples.front().unwrap().time);
+
+    // Probes off of the grid don't record anything.
+    let mut lost_probe = SimProbe::new(Vec2::splat(-100.0));
+    lost_probe.sample(&grid, 0.5);
+    assert!(lost_probe.samples.is_empty());
+
+    // Samples export as one line of CSV each.
+    let line: String = format_probe_sample(2, &sample);
+    assert_eq!(6, line.split(',').count());
+    assert!(line.starts_with("2,0.5,"));
+}
+
+#[test]
+fn flow_meter_test() {
+    let mut grid = SimGrid::default();
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Fluid);
+    }
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(20.0);
+    }
+    for row in grid.velocity_v.iter_mut() {
+        row.fill(0.0);
+    }
+
+    // Fluid flowing straight through a meter ten cells long carries 200 / cell_size cells across.
+    let cell_size: f32 = grid.cell_size as f32;
+    let top: Vec2 = Vec2::new(cell_size * 25.0, cell_size * 30.0);
+    let bottom: Vec2 = Vec2::new(cell_size * 25.0, cell_size * 20.0);
+    let mut flow_meter = SimFlowMeter::new(top, bottom);
+    flow_meter.measure(&grid, 0.5);
+    let expected_rate: f32 = 20.0 * 10.0 / cell_size;
+    assert!((flow_meter.flow_rate - expected_rate).abs() < 1e-3);
+    assert!((flow_meter.total_flow - expected_rate * 0.5).abs() < 1e-3);
+
+    // Flow adds up over time, and counts the other way for a meter facing the other way.
+    flow_meter.measure(&grid, 0.5);
+    assert!((flow_meter.total_flow - expected_rate).abs() < 1e-3);
+    let mut backwards_meter = SimFlowMeter::new(bottom, top);
+    backwards_meter.measure(&grid, 1.0);
+    assert!((backwards_meter.flow_rate + expected_rate).abs() < 1e-3);
+
+    // Fluid flowing alongside a meter never crosses it, and air carries nothing across.
+    let mut sideways_meter = SimFlowMeter::new(bottom, bottom + Vec2::X * cell_size * 10.0);
+    sideways_meter.measure(&grid, 1.0);
+    assert!(sideways_meter.flow_rate.abs() < 1e-3);
+    for row in grid.cell_type.iter_mut() {
+        row.fill(SimGridCellType::Air);
+    }
+    flow_meter.measure(&grid, 1.0);
+    assert_eq!(0.0, flow_meter.flow_rate);
+
+    // Meters can be picked out by clicking anywhere near their line.
+    assert_eq!(0.0, flow_meter.distance_to((top + bottom) * 0.5));
+    assert_eq!(cell_size, flow_meter.distance_to(top + Vec2::Y * cell_size));
+}
+
+#[test]
+fn collision_response_test() {
+    // Velocities headed into a surface bounce back off of it; ones headed away are left alone.
+    let velocity: Vec2 = Vec2::new(10.0, -20.0);
+    assert_eq!(
+        Vec2::new(10.0, 0.0),
+        bounce_off_surface(velocity, Vec2::Y, 0.0, 0.0)
+    );
+    assert_eq!(
+        Vec2::new(10.0, 10.0),
+        bounce_off_surface(velocity, Vec2::Y, 0.5, 0.0)
+    );
+    assert_eq!(
+        Vec2::new(5.0, 20.0),
+        bounce_off_surface(velocity, Vec2::Y, 1.0, 0.5)
+    );
+    assert_eq!(
+        velocity,
+        bounce_off_surface(velocity, Vec2::NEG_Y, 1.0, 0.5)
+    );
+
+    // Particles falling into a floor bounce and slide as the constraints ask.
+    let mut grid = SimGrid::default();
+    for col in 0..grid.dimensions.1 as usize {
+        let _ = grid.set_grid_cell_type(40, col, SimGridCellType::Solid);
+    }
+    grid.update_solid_distance();
+    let above_floor: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(39.0, 10.0));
+    let in_floor: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(40.0, 10.0));
+
+    let mut constraints = SimConstraints::default();
+    let mut particle = SimParticle {
+        position: above_floor,
+        ..default()
+    };
+    integrate_particle_with_collisions(&grid, &constraints, &mut particle, &in_floor, &velocity);
+    assert!(grid.sample_solid_distance(particle.position) > 0.0);
+    assert!(particle.velocity.abs_diff_eq(Vec2::new(10.0, 0.0), 1e-3));
+
+    constraints.collision_restitution = 0.5;
+    constraints.collision_friction = 0.5;
+    particle.position = above_floor;
+    integrate_particle_with_collisions(&grid, &constraints, &mut particle, &in_floor, &velocity);
+    assert!(particle.velocity.abs_diff_eq(Vec2::new(5.0, 10.0), 1e-3));
+}
+
+/// A floor with fluid sliding along it to the right, everywhere above the floor.
+#[cfg(test)]
+fn make_sliding_floor() -> SimGrid {
+    let mut grid = SimGrid::default();
+    for col in 0..grid.dimensions.1 as usize {
+        for row in 30..40 {
+            grid.cell_type[row][col] = SimGridCellType::Fluid;
+        }
+        grid.cell_type[40][col] = SimGridCellType::Solid;
+    }
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(20.0);
+    }
+    grid
+}
+
+#[test]
+fn no_slip_test() {
+    // Free-slip floors let fluid slide along them untouched.
+    let mut grid: SimGrid = make_sliding_floor();
+    apply_no_slip_walls(&mut grid, SimWallSlip::FreeSlip);
+    assert_eq!(20.0, grid.velocity_u[39][10]);
+
+    // No-slip floors stop the fluid right along them, and only there.
+    apply_no_slip_walls(&mut grid, SimWallSlip::NoSlip);
+    assert_eq!(0.0, grid.velocity_u[39][10]);
+    assert_eq!(20.0, grid.velocity_u[38][10]);
+
+    // Walls can have a slip condition of their own, whatever the simulation's is.
+    let mut grid: SimGrid = make_sliding_floor();
+    let _ = grid.set_wall_slip(40, 10, Some(SimWallSlip::NoSlip));
+    let _ = grid.set_wall_slip(40, 30, Some(SimWallSlip::FreeSlip));
+    let _ = grid.set_wall_slip(40, 31, Some(SimWallSlip::FreeSlip));
+    apply_no_slip_walls(&mut grid, SimWallSlip::FreeSlip);
+    assert_eq!(0.0, grid.velocity_u[39][10]);
+    assert_eq!(20.0, grid.velocity_u[39][20]);
+    apply_no_slip_walls(&mut grid, SimWallSlip::NoSlip);
+    assert_eq!(0.0, grid.velocity_u[39][20]);
+    assert_eq!(20.0, grid.velocity_u[39][31]);
+    // Faces running alongside both kinds of wall are held back by the no-slip one.
+    assert_eq!(0.0, grid.velocity_u[39][30]);
+
+    // Walls that are knocked down forget their slip condition.
+    let _ = grid.set_grid_cell_type(40, 10, SimGridCellType::Air);
+    assert_eq!(
+        SimWallSlip::FreeSlip,
+        grid.get_wall_slip(40, 10, SimWallSlip::FreeSlip)
+    );
+}
+
+#[test]
+fn conservation_diagnostics_test() {
+    let density: f32 = SimFluidMaterial::Water.density();
+    let particle = |velocity: Vec2| SimParticle {
+        velocity,
+        material: SimFluidMaterial::Water,
+        mass: 1.0,
+        ..default()
+    };
+
+    // Two particles heading apart at the same speed carry no net momentum.
+    let mut particles = vec![
+        particle(Vec2::new(10.0, 0.0)),
+        particle(Vec2::new(-10.0, 0.0)),
+    ];
+    let sample = SimConservationSample::measure(particles.iter(), 0.0);
+    assert_eq!(2, sample.particle_count);
+    assert_eq!(2.0 * density, sample.mass);
+    assert_eq!(Vec2::ZERO, sample.momentum);
+    assert_eq!(100.0 * density, sample.kinetic_energy);
+
+    // Drift is measured from the first sample, even once it has left the history.
+    let mut diagnostics = SimDiagnostics::default();
+    assert_eq!(0.0, diagnostics.drift(SimConservedQuantity::Momentum));
+    diagnostics.record(sample);
+    particles[0].velocity = Vec2::new(0.0, 10.0);
+    particles.push(particle(Vec2::ZERO));
+    for i in 0..=DIAGNOSTICS_HISTORY_LENGTH {
+        diagnostics.record(SimConservationSample::measure(particles.iter(), i as f32));
+    }
+    assert_eq!(DIAGNOSTICS_HISTORY_LENGTH, diagnostics.samples.len());
+    assert_eq!(1.0, diagnostics.drift(SimConservedQuantity::ParticleCount));
+    assert_eq!(density, diagnostics.drift(SimConservedQuantity::Mass));
+    assert!(
+        (diagnostics.drift(SimConservedQuantity::Momentum) - 2.0_f32.sqrt() * 10.0 * density).abs()
+            < 1e-3
+    );
+    assert_eq!(0.0, diagnostics.drift(SimConservedQuantity::KineticEnergy));
+
+    // Clearing the diagnostics starts them over.
+    diagnostics.clear();
+    assert_eq!(None, diagnostics.latest());
+    assert_eq!(None, diagnostics.baseline);
+}
+
+/// Moves the particles one step, serially or across the task pool.
+#[cfg(test)]
+fn test_integration_update(
+    In(parallel): In<bool>,
+    constraints: Res<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    update_particles(
+        constraints.as_ref(),
+        &mut particles,
+        grid.as_mut(),
+        1.0 / 60.0,
+        parallel,
+    );
+    handle_particle_grid_collisions(
+        constraints.as_ref(),
+        grid.as_ref(),
+        &mut particles,
+        parallel,
+    );
+}
+
+/// Run a few steps of integration over a churning block of mixed fluids, and return the result.
+#[cfg(test)]
+fn run_integration(parallel: bool) -> (Vec<(Entity, Vec2, Vec2)>, SimGrid) {
+    let mut juicebox_test = App::new();
+    let mut constraints = SimConstraints::default();
+    constraints.deterministic = true;
+    constraints.advection_scheme = SimAdvectionScheme::Rk3;
+
+    // More particles than fit in one deposit chunk, spinning in a vortex so some hit the walls.
+    let mut grid = SimGrid::default();
+    let center: Vec2 = Vec2::splat(grid.cell_size as f32 * 25.0);
+    for row in 0..grid.velocity_u.len() {
+        for col in 0..grid.velocity_u[row].len() {
+            grid.velocity_u[row][col] =
+                (grid.get_velocity_point_pos(row, col, true).y - center.y) * -4.0;
+        }
+    }
+    for row in 0..grid.velocity_v.len() {
+        for col in 0..grid.velocity_v[row].len() {
+            grid.velocity_v[row][col] =
+                (grid.get_velocity_point_pos(row, col, false).x - center.x) * 4.0;
+        }
+    }
+    let particle_count: usize = PARTICLES_PER_DEPOSIT_CHUNK * 2 + 100;
+    for index in 0..particle_count {
+        let angle: f32 = index as f32 * 0.618;
+        let distance: f32 = 20.0 + (index % 200) as f32 * 1.2;
+        let position: Vec2 = center + Vec2::new(angle.cos(), angle.sin()) * distance;
+        juicebox_test.world.spawn(SimParticle {
+            position,
+            velocity: Vec2::new(-angle.sin(), angle.cos()) * distance * 4.0,
+            temperature: AMBIENT_TEMPERATURE + (index % 7) as f32,
+            material: (index % 3).into(),
+            radius: constraints.particle_radius,
+            previous_position: position,
+            ..default()
+        });
+    }
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    for _ in 0..10 {
+        juicebox_test
+            .world
+            .run_system_once_with(parallel, test_integration_update);
+    }
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<(Entity, &SimParticle), Without<SimInactiveParticle>>();
+    let mut state: Vec<(Entity, Vec2, Vec2)> = particles
+        .iter(&juicebox_test.world)
+        .map(|(id, particle)| (id, particle.position, particle.velocity))
+        .collect();
+    state.sort_unstable_by_key(|(id, _, _)| *id);
+    (state, juicebox_test.world.resource::<SimGrid>().clone())
+}
+
+#[test]
+fn parallel_integration_test() {
+    // Integrating across the task pool gives exactly the same result as integrating serially.
+    let (serial_particles, serial_grid) = run_integration(false);
+    let (parallel_particles, parallel_grid) = run_integration(true);
+    assert_eq!(serial_particles, parallel_particles);
+    assert_eq!(serial_grid.density, parallel_grid.density);
+    assert_eq!(serial_grid.material_density, parallel_grid.material_density);
+    assert_eq!(serial_grid.temperature, parallel_grid.temperature);
+    assert_eq!(serial_grid.spatial_lookup, parallel_grid.spatial_lookup);
+
+    // The particles all deposited their density onto the grid.
+    assert!(serial_grid.density.iter().any(|density| *density > 0.0));
+
+    // Each chunk's deposits are kept around to be reused on the next step.
+    assert_eq!(3, serial_grid.scratch.deposits.len());
+}
+
+/// Pushes the particles apart, serially or across the task pool.
+#[cfg(test)]
+fn test_separation_update(
+    In(parallel): In<bool>,
+    constraints: Res<SimConstraints>,
+    grid: Res<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    push_particles_apart(
+        constraints.as_ref(),
+        grid.as_ref(),
+        &mut particles,
+        parallel,
+    );
+}
+
+/** Push apart clumps of overlapping particles scattered over a wrapped grid, and return where each
+particle started and ended up. */
+#[cfg(test)]
+fn run_separation(parallel: bool) -> Vec<(Entity, Vec2, Vec2)> {
+    let mut juicebox_test = App::new();
+    let constraints = SimConstraints::default();
+
+    // Clumps in every cell color, including some straddling the wrapped left and right edges.
+    let mut grid = SimGrid::default();
+    grid.wrap_horizontal = true;
+    let cell_size: f32 = grid.cell_size as f32;
+    let mut starts: Vec<(Entity, Vec2)> = Vec::new();
+    for index in 0..600 {
+        let angle: f32 = index as f32 * 0.618;
+        let clump: usize = index % 40;
+        let mut position: Vec2 = Vec2::new(
+            (clump % 8) as f32 * cell_size * 6.5 + 1.0,
+            (clump / 8) as f32 * cell_size * 7.0 + cell_size * 10.0,
+        ) + Vec2::new(angle.cos(), angle.sin()) * (index % 5) as f32;
+        position.x = position.x.rem_euclid(grid.dimensions.1 as f32 * cell_size);
+        let id: Entity = juicebox_test
+            .world
+            .spawn(SimParticle {
+                position,
+                material: (index % 3).into(),
+                radius: constraints.particle_radius,
+                previous_position: position,
+                ..default()
+            })
+            .id();
+        let lookup_index: usize =
+            grid.get_lookup_index(grid.get_cell_coordinates_from_position(&position));
+        grid.add_particle_to_lookup(id, lookup_index);
+        starts.push((id, position));
+    }
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    juicebox_test
+        .world
+        .run_system_once_with(parallel, test_separation_update);
+
+    starts
+        .into_iter()
+        .map(|(id, start)| {
+            let particle: &SimParticle = juicebox_test.world.get::<SimParticle>(id).unwrap();
+            (id, start, particle.position)
+        })
+        .collect()
+}
+
+#[test]
+fn parallel_separation_test() {
+    // Pushing particles apart across the task pool gives exactly the same result as doing it serially.
+    let serial_particles: Vec<(Entity, Vec2, Vec2)> = run_separation(false);
+    let parallel_particles: Vec<(Entity, Vec2, Vec2)> = run_separation(true);
+    assert_eq!(serial_particles, parallel_particles);
+
+    // The clumps were actually spread out, every particle staying inside the grid.
+    let moved_count: usize = serial_particles
+        .iter()
+        .filter(|(_, start, end)| start != end)
+        .count();
+    assert!(moved_count > serial_particles.len() / 2);
+    assert!(serial_particles
+        .iter()
+        .all(|(_, _, end)| end.is_finite() && end.x >= 0.0 && end.y >= 0.0));
+}
@@ -5,6 +5,22 @@ use crate::{
 };
 use bevy::prelude::*;
 
+#[cfg(test)]
+use crate::events::{ClearEvent, PlayPauseStepEvent, ResetEvent, UseToolEvent};
+#[cfg(test)]
+use crate::juice_renderer::{grid_line_levels, update_particle_position, GRID_MAJOR_LINE_INTERVAL};
+#[cfg(test)]
+use crate::simulation::{
+    self, sim_particle_pool::SimInactiveParticle, sim_rng::SimRng, sim_telemetry::SimTelemetry,
+    SimParticle, SimStepClock,
+};
+#[cfg(test)]
+use crate::test::test_state_manager::test_setup;
+#[cfg(test)]
+use crate::ui::UIStateManager;
+#[cfg(test)]
+use std::time::Duration;
+
 /// Check to see if sprites are linked to particles.
 #[test]
 fn test_sprite_particle_linking() {
@@ -24,8 +40,8 @@ fn test_sprite_particle_linking() {
     let particle: Entity = commands
         .spawn(crate::simulation::SimParticle {
             position: Vec2 { x: 66.098, y: 19.5 },
-            velocity: Vec2::ZERO,
-            lookup_index: 0,
+            previous_position: Vec2 { x: 66.098, y: 19.5 },
+            ..default()
         })
         .id();
     commands.entity(particle).insert(SpriteBundle::default());
@@ -36,7 +52,7 @@ fn test_sprite_particle_linking() {
     // Count the number of particles w/ sprites bound; if we have one, we are good!
     let sprite_count: usize = juicebox_test
         .world
-        .query::<(&crate::simulation::SimParticle, With<Sprite>)>()
+        .query_filtered::<(&crate::simulation::SimParticle, With<Sprite>), Without<SimInactiveParticle>>()
         .iter(&juicebox_test.world)
         .len();
 
@@ -68,3 +84,85 @@ pub fn test_draw_gravity_vector_arrow(
         &mut gizmos,
     );
 }
+
+/// Check that minor grid lines fade out and major lines spread apart as the camera zooms out.
+#[test]
+fn grid_line_levels_test() {
+    // Zoomed in, every line is drawn at full strength.
+    assert_eq!(grid_line_levels(20.0), (GRID_MAJOR_LINE_INTERVAL, 1.0));
+
+    // Zoomed out, the minor lines fade away while the major lines stay put.
+    let (major_interval, minor_opacity) = grid_line_levels(8.0);
+    assert_eq!(major_interval, GRID_MAJOR_LINE_INTERVAL);
+    assert!(minor_opacity > 0.0 && minor_opacity < 1.0);
+    assert_eq!(grid_line_levels(2.0), (GRID_MAJOR_LINE_INTERVAL, 0.0));
+
+    // Zoomed way out, the major lines spread further apart instead of crowding together.
+    let (major_interval, minor_opacity) = grid_line_levels(0.1);
+    assert_eq!(major_interval % GRID_MAJOR_LINE_INTERVAL, 0);
+    assert!(major_interval > GRID_MAJOR_LINE_INTERVAL);
+    assert!(major_interval as f32 * 0.1 >= 4.0);
+    assert_eq!(minor_opacity, 0.0);
+}
+
+#[test]
+fn interpolated_particle_position_test() {
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.insert_resource(SimStepClock::default());
+    juicebox_test.insert_resource(SimTelemetry::default());
+    juicebox_test.insert_resource(UIStateManager::default());
+    juicebox_test.insert_resource(Time::<()>::default());
+    juicebox_test.add_event::<UseToolEvent>();
+    juicebox_test.add_event::<ResetEvent>();
+    juicebox_test.add_event::<ClearEvent>();
+    juicebox_test.add_event::<PlayPauseStepEvent>();
+
+    juicebox_test.add_systems(Startup, test_setup);
+    juicebox_test.add_systems(Update, simulation::update);
+    juicebox_test.add_systems(Update, update_particle_position.after(simulation::update));
+    juicebox_test.update();
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<Entity, (With<SimParticle>, Without<SimInactiveParticle>)>();
+    let particle_ids: Vec<Entity> = particles.iter(&juicebox_test.world).collect();
+    for particle_id in particle_ids {
+        juicebox_test
+            .world
+            .entity_mut(particle_id)
+            .insert(Transform::default());
+    }
+
+    // One whole step, then half of the next one.
+    let step_duration: f32 = 1.0
+        / juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .steps_per_second;
+    for step_fraction in [1.0, 0.5] {
+        juicebox_test
+            .world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(step_duration * step_fraction));
+        juicebox_test.update();
+    }
+
+    // Particles are drawn halfway between where they were before the step and where they are now.
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<(&SimParticle, &Transform), Without<SimInactiveParticle>>();
+    let mut moved: bool = false;
+    for (particle, transform) in particles.iter(&juicebox_test.world) {
+        let drawn_position: Vec2 = transform.translation.truncate();
+        let step_distance: f32 = particle.previous_position.distance(particle.position);
+        let drawn_distance: f32 = particle.previous_position.distance(drawn_position)
+            + drawn_position.distance(particle.position);
+        assert!((drawn_distance - step_distance).abs() < 0.001);
+        let halfway: Vec2 = particle.previous_position.lerp(particle.position, 0.5);
+        assert!(drawn_position.distance(halfway) < 0.01 * step_distance.max(1.0));
+        moved |= step_distance > 0.0;
+    }
+    assert!(moved);
+}
@@ -1,15 +1,94 @@
+use crate::error::Error;
+use crate::events::SceneDescriptor;
+#[cfg(test)]
+use crate::events::{ClearEvent, PlayPauseStepEvent, ResetEvent, UseToolEvent};
+#[cfg(test)]
+use crate::file_system::{load_scene, save_scene, FileSystem, JuiceStates};
 use crate::juice_renderer::draw_selection_circle;
+#[cfg(test)]
+use crate::simulation::sim_adaptivity::adapt_particles;
+#[cfg(test)]
+use crate::simulation::sim_domains::{advance_domains, spawn_domain, SimDomainMember};
+use crate::simulation::sim_gpu::SimGpu;
+#[cfg(test)]
+use crate::simulation::sim_narrow_band::update_narrow_band;
+use crate::simulation::sim_obstacles::SimObstacle;
+use crate::simulation::sim_particle_pool::SimInactiveParticle;
+#[cfg(test)]
+use crate::simulation::sim_pressure_solver::SimPressureSolver;
+#[cfg(test)]
+use crate::simulation::sim_pump::SimPump;
+#[cfg(test)]
+use crate::simulation::sim_reseeding::reseed_particles;
+use crate::simulation::sim_rng::SimRng;
+#[cfg(test)]
+use crate::simulation::sim_sediment::{transport_sediment, SEDIMENT_PER_CELL};
+#[cfg(test)]
+use crate::simulation::sim_sequencer::SimSequencer;
+#[cfg(test)]
+use crate::simulation::sim_spatial_lookup::SimSpatialLookup;
+#[cfg(test)]
+use crate::simulation::sim_stability::{guard_stability, CALM_STEPS_BEFORE_RELAXING};
 use crate::simulation::sim_state_manager::{delete_particle, select_particles};
-use crate::simulation::step_simulation_once;
 #[cfg(test)]
-use crate::simulation::{self, SimSurfaceDirection};
+use crate::simulation::sim_state_manager::{
+    delete_particles_in_group, select_particles_in_group, swirl_particles_in_radius,
+};
+#[cfg(test)]
+use crate::simulation::sim_step_task::{
+    finish_background_step, land_background_step, launch_background_step, SimStepTask,
+};
+use crate::simulation::step_simulation;
+#[cfg(test)]
+use crate::simulation::{
+    self, SimFaucetSchedule, SimFaucetShape, SimSurfaceDirection, SimWallMaterial,
+    DEFAULT_PARTICLE_RADIUS, FAUCET_NOZZLE_WIDTH, FAUCET_SPRAY_PARTICLES,
+};
 use crate::simulation::{
     sim_state_manager::{add_particle, add_particles_in_radius},
-    SimConstraints, SimDrain, SimFaucet, SimGrid, SimGridCellType, SimParticle,
+    SimConstraints, SimDrain, SimFaucet, SimFluidMaterial, SimGrid, SimGridCellType, SimParticle,
+    SimSpinner, AMBIENT_TEMPERATURE,
 };
+#[cfg(test)]
+use crate::ui::{interaction::handle_input, UIStateManager};
 use crate::util::{cartesian_to_polar, get_cursor_position, polar_to_cartesian};
+#[cfg(test)]
+use bevy::core::TypeRegistrationPlugin;
+#[cfg(test)]
+use bevy::ecs::system::RunSystemOnce;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
+#[cfg(test)]
+use bevy::utils::HashMap;
+#[cfg(test)]
+use bevy_save::SavePlugin;
+
+/** Build the layout described by a scene descriptor into an already-reset simulation.  Scene files
+can't be built here, since they are loaded by the file system. */
+pub fn construct_scene(
+    scene: &SceneDescriptor,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    commands: &mut Commands,
+) -> Result<(), Error> {
+    match scene {
+        SceneDescriptor::Default => construct_new_simulation(constraints, grid, commands),
+        SceneDescriptor::Preset(name) => match name.as_str() {
+            "default" => construct_new_simulation(constraints, grid, commands),
+            "test" => construct_test_simulation_layout(constraints, grid, commands),
+            "bias-test" => construct_simulation_bias_test(constraints, grid, commands),
+            "obstacles" => construct_obstacle_course(constraints, grid, commands),
+            _ => return Err(Error::InvalidScene("Unknown scene preset name!")),
+        },
+        SceneDescriptor::File(_) => {
+            return Err(Error::InvalidScene(
+                "Scene files must be loaded by the file system!",
+            ))
+        }
+    }
+
+    Ok(())
+}
 
 /// Construct the new simulation file.
 pub fn construct_new_simulation(
@@ -42,6 +121,9 @@ pub fn construct_new_simulation(
             y: grid_center[1],
         },
         Vec2::ZERO,
+        AMBIENT_TEMPERATURE,
+        0,
+        SimFluidMaterial::Water,
     );
 
     println!(
@@ -55,40 +137,8 @@ pub fn construct_test_simulation_layout(
     grid: &mut SimGrid,
     commands: &mut Commands,
 ) {
-    // Create a lil cup
-    // grid.set_grid_cell_type(40, 32, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(41, 32, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(42, 32, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 32, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 33, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 34, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 35, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 36, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 37, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 38, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 39, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(42, 39, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(41, 39, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(40, 39, SimGridCellType::Solid);
-
-    // // Create a BIG wall
-    // grid.set_grid_cell_type(40, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(41, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(42, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(43, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(44, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(45, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(46, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(47, 14, SimGridCellType::Solid);
-    // grid.set_grid_cell_type(48, 14, SimGridCellType::Solid);
-
-    // // Generate walls around simulation bounds.
-    // for i in 0..50 {
-    //     grid.set_grid_cell_type(49, i, SimGridCellType::Solid);
-    //     grid.set_grid_cell_type(0, i, SimGridCellType::Solid);
-    //     grid.set_grid_cell_type(i, 0, SimGridCellType::Solid);
-    //     grid.set_grid_cell_type(i, 49, SimGridCellType::Solid);
-    // }
+    /* Cups, walls, and other structured geometry are stamped in from the obstacle library; see
+    construct_obstacle_course() below. */
 
     // Add faucet
     // let faucet_pos = Vec2::new(grid.cell_size as f32, grid.cell_size as f32 * 20.0);
@@ -127,6 +177,9 @@ pub fn construct_test_simulation_layout(
             y: grid_center[1] * 0.85,
         },
         Vec2::ZERO,
+        AMBIENT_TEMPERATURE,
+        0,
+        SimFluidMaterial::Water,
     );
 
     println!(
@@ -135,6 +188,50 @@ pub fn construct_test_simulation_layout(
     );
 }
 
+/// Create a walled-in layout with a few prefab obstacles for the fluid to fall through.
+pub fn construct_obstacle_course(
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    commands: &mut Commands,
+) {
+    // Generate walls around simulation bounds.
+    for i in 0..50 {
+        let _ = grid.set_grid_cell_type(49, i, SimGridCellType::Solid);
+        let _ = grid.set_grid_cell_type(0, i, SimGridCellType::Solid);
+        let _ = grid.set_grid_cell_type(i, 0, SimGridCellType::Solid);
+        let _ = grid.set_grid_cell_type(i, 49, SimGridCellType::Solid);
+    }
+
+    // A funnel feeding into a cup, with a staircase off to the side.
+    grid.stamp_obstacle(SimObstacle::Funnel, 0, Vec2 { x: 20.0, y: 25.0 });
+    grid.stamp_obstacle(SimObstacle::Cup, 0, Vec2 { x: 40.0, y: 25.0 });
+    grid.stamp_obstacle(SimObstacle::Stairs, 0, Vec2 { x: 46.0, y: 8.0 });
+
+    // Pour a ball of fluid in above the funnel.
+    let grid_width: f32 = (grid.dimensions.1 * grid.cell_size) as f32;
+    let grid_height: f32 = (grid.dimensions.0 * grid.cell_size) as f32;
+    let _fluid = add_particles_in_radius(
+        commands,
+        constraints,
+        grid,
+        1.0,
+        25.0,
+        Vec2 {
+            x: grid_width * 0.5,
+            y: grid_height * 0.85,
+        },
+        Vec2::ZERO,
+        AMBIENT_TEMPERATURE,
+        0,
+        SimFluidMaterial::Water,
+    );
+
+    println!(
+        "Creating an obstacle course with {} particles...",
+        constraints.particle_count
+    );
+}
+
 /// Create a simulation layout for testing.
 pub fn construct_simulation_bias_test(
     constraints: &mut SimConstraints,
@@ -158,6 +255,9 @@ pub fn construct_simulation_bias_test(
             y: grid_center[1] * 0.85,
         },
         Vec2::ZERO,
+        AMBIENT_TEMPERATURE,
+        0,
+        SimFluidMaterial::Water,
     );
 
     for x in 0..(grid.dimensions.1 * grid.cell_size) as usize {
@@ -168,7 +268,16 @@ pub fn construct_simulation_bias_test(
                     x: x as f32,
                     y: grid_top - y as f32,
                 };
-                let _ = add_particle(commands, constraints, grid, pos, Vec2::ZERO);
+                let _ = add_particle(
+                    commands,
+                    constraints,
+                    grid,
+                    pos,
+                    Vec2::ZERO,
+                    AMBIENT_TEMPERATURE,
+                    0,
+                    SimFluidMaterial::Water,
+                );
             }
         }
     }
@@ -189,7 +298,8 @@ pub fn debug_state_controller(
     cameras: Query<(&Camera, &GlobalTransform)>,
     mut constraints: ResMut<SimConstraints>,
     mut grid: ResMut<SimGrid>,
-    mut particles: Query<(Entity, &mut SimParticle)>,
+    mut rng: ResMut<SimRng>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
     mut faucets: Query<(Entity, &mut SimFaucet)>,
     mut drains: Query<(Entity, &mut SimDrain)>,
 ) {
@@ -199,6 +309,7 @@ pub fn debug_state_controller(
             &mut commands,
             constraints.as_mut(),
             grid.as_mut(),
+            rng.as_mut(),
             &mut particles,
             &mut faucets,
             &mut drains,
@@ -270,21 +381,27 @@ pub fn test_setup(
 pub fn test_update(
     mut constraints: ResMut<SimConstraints>,
     mut grid: ResMut<SimGrid>,
-    mut particles: Query<(Entity, &mut SimParticle)>,
+    mut rng: ResMut<SimRng>,
+    mut gpu: Option<ResMut<SimGpu>>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
     faucets: Query<(Entity, &mut SimFaucet)>,
-    drains: Query<(Entity, &mut SimDrain)>,
+    mut drains: Query<(Entity, &mut SimDrain)>,
+    mut spinners: Query<(Entity, &mut SimSpinner)>,
     mut commands: Commands,
 ) {
     // let delta_time: f32 = time.delta().as_millis() as f32 * 0.001;
     let fixed_timestep: f32 = constraints.timestep;
 
-    step_simulation_once(
+    step_simulation(
         &mut commands,
         constraints.as_mut(),
         grid.as_mut(),
+        rng.as_mut(),
+        gpu.as_deref_mut(),
         &mut particles,
         &faucets,
-        &drains,
+        &mut drains,
+        &mut spinners,
         fixed_timestep,
     );
 }
@@ -294,7 +411,7 @@ pub fn test_select_particles(
     commands: &mut Commands,
     constraints: &mut SimConstraints,
     grid: &mut SimGrid,
-    particles: &Query<(Entity, &mut SimParticle)>,
+    particles: &Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
     windows: &Query<&Window>,
     cameras: &Query<(&Camera, &GlobalTransform)>,
     gizmos: &mut Gizmos,
@@ -357,6 +474,13 @@ fn test_add_faucet_update(mut commands: Commands, mut grid: ResMut<SimGrid>) {
         surface_direction,
         1.0,
         Vec2::ZERO,
+        Vec::new(),
+        0.0,
+        0,
+        SimFluidMaterial::Water,
+        SimFaucetShape::Point,
+        0.0,
+        SimFaucetSchedule::default(),
     ) else {
         return;
     };
@@ -378,6 +502,8 @@ fn test_add_drain_update(mut commands: Commands, mut grid: ResMut<SimGrid>) {
         surface_direction,
         drain_radius,
         1.0,
+        None,
+        None,
     ) else {
         return;
     };
@@ -392,6 +518,7 @@ fn add_faucet_test() {
 
     // Add our constraints and grid
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     // Add our test setup environment
@@ -416,6 +543,7 @@ fn run_faucet_test() {
     let mut juicebox_test = App::new();
 
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     juicebox_test.add_systems(Startup, test_setup);
@@ -446,6 +574,83 @@ fn run_faucet_test() {
     assert_ne!(after_count, before_count);
 }
 
+#[test]
+fn faucet_spray_test() {
+    let cell_size: f32 = SimGrid::default().cell_size as f32;
+    let velocity: Vec2 = Vec2::new(100.0, 0.0);
+    let spread: f32 = std::f32::consts::FRAC_PI_2;
+    let faucet = |shape: SimFaucetShape| -> SimFaucet {
+        SimFaucet::new(
+            Vec2::ZERO,
+            None,
+            1.0,
+            velocity,
+            Vec::new(),
+            0.0,
+            0,
+            SimFluidMaterial::Water,
+            shape,
+            spread,
+            SimFaucetSchedule::default(),
+        )
+    };
+
+    let mut rng = SimRng::default();
+
+    // Line nozzles pour a sheet straight out, as wide as the nozzle and across the flow.
+    let line = faucet(SimFaucetShape::Line).calculate_spray(cell_size, &mut rng);
+    assert_eq!(FAUCET_SPRAY_PARTICLES, line.len());
+    for (offset, particle_velocity) in line.iter() {
+        assert_eq!(velocity, *particle_velocity);
+        assert_eq!(0.0, offset.x);
+    }
+    let width: f32 = line.last().unwrap().0.y - line.first().unwrap().0.y;
+    assert!((width.abs() - FAUCET_NOZZLE_WIDTH * cell_size).abs() < 1e-3);
+
+    // Fan nozzles spread their sheet evenly across the spread angle, edge to edge.
+    let fan = faucet(SimFaucetShape::Fan).calculate_spray(cell_size, &mut rng);
+    let angles: Vec<f32> = fan
+        .iter()
+        .map(|(_, particle_velocity)| particle_velocity.y.atan2(particle_velocity.x))
+        .collect();
+    assert!((angles.first().unwrap().abs() - spread * 0.5).abs() < 1e-3);
+    assert!((angles.last().unwrap().abs() - spread * 0.5).abs() < 1e-3);
+    assert!(angles[FAUCET_SPRAY_PARTICLES / 2].abs() < 1e-3);
+    for (_, particle_velocity) in fan.iter() {
+        assert!((particle_velocity.length() - velocity.length()).abs() < 1e-3);
+    }
+
+    // Cone nozzles spray from a single point, somewhere within the spread angle.
+    for (offset, particle_velocity) in
+        faucet(SimFaucetShape::Cone).calculate_spray(cell_size, &mut rng)
+    {
+        assert_eq!(Vec2::ZERO, offset);
+        assert!(particle_velocity.y.atan2(particle_velocity.x).abs() <= spread * 0.5 + 1e-3);
+    }
+}
+
+#[test]
+fn faucet_schedule_test() {
+    // Faucets without a period pour no matter what.
+    let constant = SimFaucetSchedule::default();
+    assert!(constant.is_on(0.0));
+    assert!(constant.is_on(12.3));
+
+    // A two second period spending a quarter of its time pouring: on for 0.5s, then off for 1.5s.
+    let pulsing = SimFaucetSchedule::new(2.0, 0.25);
+    assert!(pulsing.is_on(0.0));
+    assert!(pulsing.is_on(0.4));
+    assert!(!pulsing.is_on(0.6));
+    assert!(!pulsing.is_on(1.9));
+    assert!(pulsing.is_on(2.1));
+    assert!(!pulsing.is_on(3.0));
+
+    // A faucet that never spends any time pouring stays off.
+    let closed = SimFaucetSchedule::new(1.0, 0.0);
+    assert!(!closed.is_on(0.0));
+    assert!(!closed.is_on(0.5));
+}
+
 #[test]
 fn add_drain_test() {
     //First we setup the test world in bevy
@@ -453,6 +658,7 @@ fn add_drain_test() {
 
     // Add our constraints and grid
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     // Add our test setup environment
@@ -477,6 +683,7 @@ fn drain_drain_test() {
     let mut juicebox_test = App::new();
 
     juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
     juicebox_test.insert_resource(SimConstraints::default());
 
     juicebox_test.add_systems(Startup, test_setup);
@@ -512,3 +719,1807 @@ fn drain_drain_test() {
     // thus, the drain successfully drained
     assert_ne!(after_count, before_count);
 }
+
+/// Crowds a few particles right on top of a drain with the given throughput and volume limits.
+#[cfg(test)]
+fn crowd_drain(
+    commands: &mut Commands,
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    capacity: Option<f32>,
+    volume: Option<usize>,
+) {
+    // Without gravity, the particles stay put on top of the drain.
+    constraints.gravity = Vec2::ZERO;
+
+    let center = Vec2::splat(grid.cell_size as f32 * 25.0);
+    let _ = simulation::sim_state_manager::add_drain(
+        commands, grid, center, None, 0.0, 0.0, capacity, volume,
+    );
+
+    for x in -1..=1 {
+        for y in -1..=1 {
+            let offset = Vec2::new(x as f32, y as f32) * 2.0;
+            let _ = add_particle(
+                commands,
+                constraints,
+                grid,
+                center + offset,
+                Vec2::ZERO,
+                AMBIENT_TEMPERATURE,
+                0,
+                SimFluidMaterial::Water,
+            );
+        }
+    }
+}
+
+/// Crowds a few particles right on top of a drain with a limited throughput.
+#[cfg(test)]
+fn test_setup_crowded_drain(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    crowd_drain(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        Some(240.0),
+        None,
+    );
+}
+
+/// Crowds a few particles right on top of a drain that can only hold a few of them.
+#[cfg(test)]
+fn test_setup_clogging_drain(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    crowd_drain(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        None,
+        Some(4),
+    );
+}
+
+#[test]
+fn drain_capacity_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup_crowded_drain);
+    juicebox_test.add_systems(Update, test_update);
+
+    let before_count = 9;
+    juicebox_test.update();
+    juicebox_test.update();
+    let after_count = juicebox_test
+        .world
+        .resource::<SimConstraints>()
+        .particle_count;
+
+    // The drain only gets to consume about two particles per step, so some fluid must back up.
+    assert!(after_count < before_count);
+    assert!(after_count > 0);
+    let mut drains = juicebox_test.world.query::<&SimDrain>();
+    assert!(drains.single(&juicebox_test.world).is_backed_up);
+}
+
+#[test]
+fn drain_volume_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup_clogging_drain);
+    juicebox_test.add_systems(Update, test_update);
+
+    juicebox_test.update();
+    juicebox_test.update();
+    juicebox_test.update();
+
+    // The drain swallows as much as it can hold, then clogs and leaves the rest alone.
+    let particle_count = juicebox_test
+        .world
+        .resource::<SimConstraints>()
+        .particle_count;
+    assert_eq!(9 - 4, particle_count);
+    let mut drains = juicebox_test.world.query::<&SimDrain>();
+    let drain = drains.single(&juicebox_test.world);
+    assert_eq!(4, drain.drained_count);
+    assert!(drain.is_clogged());
+}
+
+/// Sets up a pump with a few particles sitting right at its intake.
+#[cfg(test)]
+fn test_setup_pump(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    let cell_size: f32 = grid.cell_size as f32;
+    let intake: Vec2 = Vec2::splat(cell_size * 10.0);
+    let outlet: Vec2 = Vec2::splat(cell_size * 40.0);
+    commands.spawn(SimPump::new(intake, outlet, Vec2::new(50.0, 0.0), 0.05));
+
+    for x in -1..=1 {
+        let _ = add_particle(
+            &mut commands,
+            constraints.as_mut(),
+            grid.as_mut(),
+            intake + Vec2::new(x as f32 * 2.0, 0.0),
+            Vec2::ZERO,
+            AMBIENT_TEMPERATURE,
+            0,
+            SimFluidMaterial::Honey,
+        );
+    }
+}
+
+/// Runs every pump for 0.03 seconds.
+#[cfg(test)]
+fn test_run_pumps(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+    mut pumps: Query<&mut SimPump>,
+) {
+    for mut pump in pumps.iter_mut() {
+        pump.run(
+            &mut commands,
+            constraints.as_mut(),
+            grid.as_mut(),
+            &particles,
+            0.03,
+        );
+    }
+}
+
+#[test]
+fn pump_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup_pump);
+    juicebox_test.add_systems(Update, test_run_pumps);
+
+    // The intake swallows all of the fluid around it, which then spends a while inside the pump.
+    juicebox_test.update();
+    juicebox_test.update();
+    assert_eq!(
+        0,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+    let mut pumps = juicebox_test.world.query::<&SimPump>();
+    assert_eq!(3, pumps.single(&juicebox_test.world).in_transit.len());
+
+    // Once its delay is up, the same fluid comes out of the outlet at the pump's velocity.
+    juicebox_test.update();
+    assert_eq!(
+        3,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+    let pump = pumps.single(&juicebox_test.world);
+    assert!(pump.in_transit.is_empty());
+    let outlet: Vec2 = pump.outlet;
+    let cell_size: f32 = juicebox_test.world.resource::<SimGrid>().cell_size as f32;
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    for particle in particles.iter(&juicebox_test.world) {
+        assert!(particle.position.distance(outlet) <= cell_size);
+        assert_eq!(Vec2::new(50.0, 0.0), particle.velocity);
+        assert_eq!(SimFluidMaterial::Honey, particle.material);
+    }
+}
+
+/// Pours a few particles into two different groups.
+#[cfg(test)]
+fn test_setup_particle_groups(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    let center = Vec2::splat(grid.cell_size as f32 * 25.0);
+    for i in 0..5 {
+        let group: u8 = if i < 2 { 0 } else { 3 };
+        let _ = add_particle(
+            &mut commands,
+            constraints.as_mut(),
+            grid.as_mut(),
+            center + Vec2::new(i as f32 * 10.0, 0.0),
+            Vec2::ZERO,
+            AMBIENT_TEMPERATURE,
+            group,
+            SimFluidMaterial::Water,
+        );
+    }
+}
+
+/// Deletes every particle in group 3.
+#[cfg(test)]
+fn test_delete_group_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    assert_eq!(3, select_particles_in_group(&particles, 3).len());
+    delete_particles_in_group(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &particles,
+        3,
+    );
+}
+
+#[test]
+fn particle_group_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup_particle_groups);
+    juicebox_test.add_systems(Update, test_delete_group_update);
+    juicebox_test.update();
+
+    // Only the particles outside of the deleted group are left.
+    assert_eq!(
+        2,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert!(particles
+        .iter(&juicebox_test.world)
+        .all(|particle| particle.group == 0));
+}
+
+/// Spawns three particles, which should reuse the entities of the three deleted ones.
+#[cfg(test)]
+fn test_respawn_particles_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    let center = Vec2::splat(grid.cell_size as f32 * 10.0);
+    for i in 0..3 {
+        let _ = add_particle(
+            &mut commands,
+            constraints.as_mut(),
+            grid.as_mut(),
+            center + Vec2::new(i as f32 * 10.0, 0.0),
+            Vec2::ZERO,
+            AMBIENT_TEMPERATURE,
+            1,
+            SimFluidMaterial::Water,
+        );
+    }
+}
+
+#[test]
+fn particle_pool_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    juicebox_test.add_systems(Startup, test_setup_particle_groups);
+    juicebox_test.add_systems(Update, test_delete_group_update);
+    juicebox_test.update();
+
+    // Deleted particles leave their entities behind, which are pooled once the lookup is flushed.
+    let entity_count: usize = juicebox_test.world.entities().len() as usize;
+    assert_eq!(5, entity_count);
+    juicebox_test
+        .world
+        .resource_mut::<SimGrid>()
+        .flush_lookup_removals();
+    assert_eq!(
+        3,
+        juicebox_test
+            .world
+            .resource::<SimGrid>()
+            .particle_pool
+            .len()
+    );
+
+    // Pooled entities keep their particles, only marked inactive.
+    let mut pooled = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, With<SimInactiveParticle>>();
+    assert_eq!(3, pooled.iter(&juicebox_test.world).count());
+
+    juicebox_test
+        .world
+        .run_system_once(test_respawn_particles_update);
+
+    // New particles are given the pooled entities instead of new ones.
+    let world: &mut World = &mut juicebox_test.world;
+    assert!(world.resource::<SimGrid>().particle_pool.is_empty());
+    assert_eq!(entity_count, world.entities().len() as usize);
+    let mut particles = world.query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert_eq!(5, particles.iter(world).count());
+    assert_eq!(5, world.resource::<SimConstraints>().particle_count);
+}
+
+/// Drops a small blob of particles into the middle of an otherwise empty grid.
+#[cfg(test)]
+fn test_setup_water_cycle(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    // Without gravity, the blob stays put in the middle of the grid.
+    constraints.gravity = Vec2::ZERO;
+
+    let center = Vec2::splat(grid.cell_size as f32 * 25.0);
+    for x in -1..=1 {
+        for y in -1..=1 {
+            let _ = add_particle(
+                &mut commands,
+                constraints.as_mut(),
+                grid.as_mut(),
+                center + Vec2::new(x as f32, y as f32) * 2.0,
+                Vec2::ZERO,
+                AMBIENT_TEMPERATURE,
+                0,
+                SimFluidMaterial::Water,
+            );
+        }
+    }
+}
+
+#[test]
+fn water_cycle_test() {
+    let mut juicebox_test = App::new();
+
+    let mut constraints = SimConstraints::default();
+    constraints.evaporation_rate = 2.0 / constraints.timestep;
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(constraints);
+
+    juicebox_test.add_systems(Startup, test_setup_water_cycle);
+    juicebox_test.add_systems(Update, test_update);
+
+    // Two surface particles evaporate each step, and are kept as vapor.
+    juicebox_test.update();
+    let constraints = juicebox_test.world.resource::<SimConstraints>();
+    assert_eq!(7, constraints.particle_count);
+    assert_eq!(2.0, constraints.water_vapor);
+
+    // With condensation on, vapor rains back down from the top of the grid.
+    let mut constraints = juicebox_test.world.resource_mut::<SimConstraints>();
+    constraints.evaporation_rate = 0.0;
+    constraints.condensation = true;
+    constraints.water_vapor =
+        5.0 / (simulation::sim_water_cycle::CONDENSATION_RATE * constraints.timestep);
+    let vapor_before = constraints.water_vapor;
+    juicebox_test.update();
+
+    let constraints = juicebox_test.world.resource::<SimConstraints>();
+    assert!(constraints.particle_count > 7);
+    assert_eq!(
+        vapor_before - constraints.water_vapor,
+        (constraints.particle_count - 7) as f32
+    );
+    let grid_height = juicebox_test.world.resource::<SimGrid>().cell_size as f32 * 50.0;
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert!(particles
+        .iter(&juicebox_test.world)
+        .any(|particle| particle.position.y > grid_height * 0.9));
+}
+
+/// Give the test layout a water cycle in deterministic mode, so the random number generator gets used.
+#[cfg(test)]
+fn configure_deterministic_run(constraints: &mut SimConstraints) {
+    constraints.deterministic = true;
+    constraints.evaporation_rate = 200.0;
+    constraints.condensation = true;
+}
+
+/// Reset the simulation the way the R key does, and set the deterministic run back up.
+#[cfg(test)]
+fn test_deterministic_reset_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut rng: ResMut<SimRng>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+    faucets: Query<(Entity, &mut SimFaucet)>,
+    drains: Query<(Entity, &mut SimDrain)>,
+) {
+    simulation::reset_simulation_to_default(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        rng.as_mut(),
+        &particles,
+        &faucets,
+        &drains,
+    );
+    configure_deterministic_run(constraints.as_mut());
+    construct_test_simulation_layout(constraints.as_mut(), grid.as_mut(), &mut commands);
+}
+
+/** Step the simulation 20 times, pooling removed particles' entities as the app would, and return
+every particle's spawn id, entity, position, and velocity, in spawn order. */
+#[cfg(test)]
+fn run_deterministic_steps(juicebox_test: &mut App) -> Vec<(u64, Entity, Vec2, Vec2)> {
+    for _ in 0..20 {
+        juicebox_test.update();
+        juicebox_test
+            .world
+            .resource_mut::<SimGrid>()
+            .flush_lookup_removals();
+    }
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<(Entity, &SimParticle), Without<SimInactiveParticle>>();
+    let mut state: Vec<(u64, Entity, Vec2, Vec2)> = particles
+        .iter(&juicebox_test.world)
+        .map(|(id, particle)| (particle.spawn_id, id, particle.position, particle.velocity))
+        .collect();
+    state.sort_unstable_by_key(|(spawn_id, _, _, _)| *spawn_id);
+    state
+}
+
+#[test]
+fn deterministic_test() {
+    // The same seed always draws the same numbers.
+    let (mut first, mut second) = (SimRng::new(42), SimRng::new(42));
+    for _ in 0..100 {
+        assert_eq!(first.next_u64(), second.next_u64());
+        let value: f32 = first.next_f32();
+        assert!((0.0..1.0).contains(&value));
+        assert_eq!(value, second.next_f32());
+        assert!(first.next_index(7) < 7);
+        second.next_index(7);
+    }
+
+    // Nothing runs on the GPU in deterministic mode.
+    let mut constraints = SimConstraints::default();
+    constraints.pressure_solver = SimPressureSolver::Gpu;
+    constraints.gpu_transfers = true;
+    configure_deterministic_run(&mut constraints);
+    constraints.keep_deterministic();
+    assert_eq!(SimPressureSolver::GaussSeidel, constraints.pressure_solver);
+    assert!(!constraints.gpu_transfers);
+
+    let mut juicebox_test = App::new();
+    constraints.seed = 42;
+    juicebox_test.insert_resource(SimRng::for_scene(&constraints));
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(constraints);
+    juicebox_test.add_systems(Startup, test_setup);
+    juicebox_test.add_systems(Update, test_update);
+    let first_run = run_deterministic_steps(&mut juicebox_test);
+    assert!(!first_run.is_empty());
+
+    /* Resetting reseeds the generator and restarts the spawn ids, so running the scene again in the
+    same app plays out exactly the same, bit for bit; even though its particles are given the first
+    run's pooled entities, in a different order. */
+    juicebox_test
+        .world
+        .run_system_once(test_deterministic_reset_update);
+    let second_run = run_deterministic_steps(&mut juicebox_test);
+    let without_entities = |run: &Vec<(u64, Entity, Vec2, Vec2)>| -> Vec<(u64, Vec2, Vec2)> {
+        run.iter()
+            .map(|(spawn_id, _, position, velocity)| (*spawn_id, *position, *velocity))
+            .collect()
+    };
+    assert_eq!(without_entities(&first_run), without_entities(&second_run));
+    assert!(first_run
+        .iter()
+        .zip(second_run.iter())
+        .any(|(first, second)| first.1 != second.1));
+}
+
+#[test]
+fn substep_test() {
+    let mut juicebox_test = App::new();
+
+    let mut constraints = SimConstraints::default();
+    constraints.substeps = 4;
+    let timestep: f32 = constraints.timestep;
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(constraints);
+
+    juicebox_test.add_systems(Startup, test_setup_particle_groups);
+    juicebox_test.add_systems(Update, test_update);
+    juicebox_test.update();
+
+    // Substeps split the step up; they don't simulate any more time than a single step would.
+    let simulated_time: f32 = juicebox_test
+        .world
+        .resource::<SimConstraints>()
+        .simulated_time;
+    assert!((timestep - simulated_time).abs() < 1e-6);
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    for particle in particles.iter(&juicebox_test.world) {
+        assert!((timestep - particle.age).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn substep_interpolation_test() {
+    let mut juicebox_test = App::new();
+
+    let mut constraints = SimConstraints::default();
+    constraints.substeps = 4;
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(constraints);
+
+    juicebox_test.add_systems(Startup, test_setup_particle_groups);
+    juicebox_test.add_systems(Update, test_update);
+    juicebox_test.update();
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<(Entity, &SimParticle), Without<SimInactiveParticle>>();
+    let positions: HashMap<Entity, Vec2> = particles
+        .iter(&juicebox_test.world)
+        .map(|(particle_id, particle)| (particle_id, particle.position))
+        .collect();
+    juicebox_test.update();
+
+    // The renderer interpolates from where each particle was before the whole step, not its last substep.
+    assert!(!positions.is_empty());
+    let mut moved: bool = false;
+    for (particle_id, particle) in particles.iter(&juicebox_test.world) {
+        assert_eq!(positions[&particle_id], particle.previous_position);
+        moved |= particle.position != particle.previous_position;
+    }
+    assert!(moved);
+}
+
+#[test]
+fn step_clock_test() {
+    let mut step_clock = simulation::SimStepClock::default();
+
+    // Half a step's worth of time shouldn't step the simulation, but should be halfway there.
+    let step_count = step_clock.advance(0.5 / 60.0, 60.0, 4);
+    assert_eq!(0, step_count);
+    assert!((step_clock.interpolation_alpha - 0.5).abs() < 0.001);
+
+    // Enough time for two more steps.
+    let step_count = step_clock.advance(2.0 / 60.0, 60.0, 4);
+    assert_eq!(2, step_count);
+
+    // A huge hitch should be capped and the leftover backlog dropped.
+    let step_count = step_clock.advance(1.0, 60.0, 4);
+    assert_eq!(4, step_count);
+    assert!(step_clock.accumulator < 1.0 / 60.0);
+}
+
+#[test]
+fn spatial_lookup_test() {
+    let mut lookup = SimSpatialLookup::new(4);
+    let [first, second, third, fourth] = [1, 2, 3, 4].map(Entity::from_raw);
+    assert!(lookup.insert(first, 2));
+    assert!(lookup.insert(second, 0));
+    assert!(lookup.insert(third, 2));
+    assert!(!lookup.insert(fourth, 4));
+
+    // Additions wait for the next rebuild.
+    assert!(lookup.is_empty());
+    lookup.rebuild(4, [], |_| true);
+    assert!(!lookup.has_queued_changes());
+    assert_eq!(&[second], lookup.cell(0));
+    assert!(lookup.cell(1).is_empty());
+    assert_eq!(&[first, third], lookup.cell(2));
+    assert!(lookup.cell(9).is_empty());
+
+    // So do removals, which take particles out of whichever cell they're in.
+    lookup.remove(first);
+    assert_eq!(&[first, third], lookup.cell(2));
+    lookup.rebuild(4, [], |_| true);
+    assert_eq!(&[third], lookup.cell(2));
+
+    // Removing a particle cancels its queued additions, but adding it back afterwards doesn't.
+    lookup.insert(fourth, 1);
+    lookup.remove(fourth);
+    lookup.remove(second);
+    lookup.insert(second, 3);
+    lookup.rebuild(4, [], |_| true);
+    assert!(lookup.cell(0).is_empty());
+    assert!(lookup.cell(1).is_empty());
+    assert_eq!(&[second], lookup.cell(3));
+
+    /* Rebuilding puts every particle in its new cell in the order given, after the particles it
+    keeps where they were; the rest are dropped, as are particles in cells that don't exist. */
+    lookup.insert(first, 0);
+    lookup.rebuild(
+        5,
+        [(fourth, 4), (first, 1), (second, 4), (third, 7)],
+        |id| id == third,
+    );
+    assert_eq!(4, lookup.len());
+    assert!(lookup.cell(0).is_empty());
+    assert_eq!(&[first], lookup.cell(1));
+    assert_eq!(&[third], lookup.cell(2));
+    assert_eq!(&[fourth, second], lookup.cell(4));
+    lookup.sort_cells(|id| id);
+    assert_eq!(&[second, fourth], lookup.cell(4));
+}
+
+#[test]
+fn deferred_lookup_removal_test() {
+    let mut grid = SimGrid::default();
+    let stays_put = Entity::from_raw(1);
+    let moves_cells = Entity::from_raw(2);
+    grid.add_particle_to_lookup(stays_put, 3);
+    grid.add_particle_to_lookup(moves_cells, 3);
+    grid.apply_lookup_changes();
+
+    // Queueing a removal keeps the particle in the lookup until the flush, and only queues once.
+    assert!(grid.queue_particle_lookup_removal(stays_put, 3));
+    assert!(!grid.queue_particle_lookup_removal(stays_put, 3));
+    assert!(grid.queue_particle_lookup_removal(moves_cells, 3));
+    assert!(grid.get_particles_in_lookup(3).contains(&stays_put));
+
+    // A particle that changes cells after being queued must still be found.
+    grid.remove_particle_from_lookup(moves_cells);
+    grid.add_particle_to_lookup(moves_cells, 7);
+
+    grid.flush_lookup_removals();
+    assert!(grid.get_particles_in_lookup(3).is_empty());
+    assert!(grid.get_particles_in_lookup(7).is_empty());
+    assert!(!grid.is_particle_pending_removal(stays_put));
+}
+
+#[test]
+fn construct_scene_test() {
+    let world = World::new();
+    let mut command_queue = bevy::ecs::system::CommandQueue::default();
+    let mut commands = Commands::new(&mut command_queue, &world);
+    let mut constraints = SimConstraints::default();
+    let mut grid = SimGrid::default();
+
+    // Named presets build their layout; unknown names and files are rejected.
+    let preset = SceneDescriptor::Preset(String::from("test"));
+    assert!(construct_scene(&preset, &mut constraints, &mut grid, &mut commands).is_ok());
+    assert!(constraints.particle_count > 0);
+
+    let unknown = SceneDescriptor::Preset(String::from("not-a-real-scene"));
+    assert!(construct_scene(&unknown, &mut constraints, &mut grid, &mut commands).is_err());
+
+    let file = SceneDescriptor::File(String::from("saves/my-file"));
+    assert!(construct_scene(&file, &mut constraints, &mut grid, &mut commands).is_err());
+}
+
+#[test]
+fn clear_walls_test() {
+    let mut grid = SimGrid::default();
+    let _ = grid.set_grid_cell_type(0, 10, SimGridCellType::Solid);
+    let _ = grid.set_grid_cell_type(20, 20, SimGridCellType::Solid);
+    let _ = grid.set_grid_cell_type(21, 20, SimGridCellType::Fluid);
+
+    // Interior walls are cleared, but the border and non-solid cells are left alone.
+    grid.clear_interior_solid_cells();
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[0][10]);
+    assert_eq!(SimGridCellType::Air, grid.cell_type[20][20]);
+    assert_eq!(SimGridCellType::Fluid, grid.cell_type[21][20]);
+}
+
+#[test]
+fn obstacle_stamp_test() {
+    // A full turn brings the obstacle back to where it started.
+    let cup_cells = SimObstacle::Cup.cells(0);
+    assert_eq!(cup_cells, SimObstacle::Cup.cells(4));
+
+    // A clockwise quarter turn moves the bottom of the cup to its left side.
+    assert!(cup_cells.contains(&(2, 0)));
+    assert!(SimObstacle::Cup.cells(1).contains(&(0, -2)));
+
+    // Stamping makes every cell solid, skipping any that fall outside of the grid.
+    let mut grid = SimGrid::default();
+    let stamped = grid.stamp_obstacle(SimObstacle::Cup, 0, Vec2 { x: 20.0, y: 20.0 });
+    assert_eq!(cup_cells.len(), stamped.len());
+    for cell in stamped.iter() {
+        assert_eq!(
+            SimGridCellType::Solid,
+            grid.cell_type[cell.x as usize][cell.y as usize]
+        );
+    }
+
+    let stamped = grid.stamp_obstacle(SimObstacle::Cup, 0, Vec2 { x: 0.0, y: 0.0 });
+    assert!(stamped.len() < cup_cells.len());
+}
+
+#[test]
+fn pipe_cells_test() {
+    let grid = SimGrid::default();
+
+    // A horizontal pipe along row 25, three cells wide on the inside.
+    let path = [
+        grid.get_cell_center_position_from_coordinates(&Vec2 { x: 25.0, y: 10.0 }),
+        grid.get_cell_center_position_from_coordinates(&Vec2 { x: 25.0, y: 30.0 }),
+    ];
+    let cells = grid.get_pipe_cells(&path, 3.0, 3.0);
+    assert!(!cells.is_empty());
+
+    // The walls sit on either side of the pipe, leaving its inside open.
+    for cell in cells.iter() {
+        assert!(
+            cell.x == 23.0 || cell.x == 27.0,
+            "unexpected wall row {}",
+            cell.x
+        );
+    }
+
+    // Narrowing the outlet turns the pipe into a funnel whose walls close in towards the end.
+    let funnel_cells = grid.get_pipe_cells(&path, 7.0, 1.0);
+    let inlet_rows: Vec<f32> = funnel_cells
+        .iter()
+        .filter(|cell| cell.y == 10.0)
+        .map(|cell| cell.x)
+        .collect();
+    let outlet_rows: Vec<f32> = funnel_cells
+        .iter()
+        .filter(|cell| cell.y == 30.0)
+        .map(|cell| cell.x)
+        .collect();
+    assert!(inlet_rows.contains(&21.0) && inlet_rows.contains(&29.0));
+    assert!(outlet_rows.contains(&24.0) && outlet_rows.contains(&26.0));
+}
+
+#[test]
+fn wall_material_test() {
+    let mut grid = SimGrid::default();
+
+    // Walls remember their material until they stop being solid.
+    let _ = grid.set_grid_cell_type(10, 10, SimGridCellType::Solid);
+    let _ = grid.set_wall_material(10, 10, SimWallMaterial::Bouncy);
+    assert_eq!(SimWallMaterial::Bouncy, grid.get_wall_material(10, 10));
+
+    let _ = grid.set_grid_cell_type(10, 10, SimGridCellType::Air);
+    assert_eq!(SimWallMaterial::Normal, grid.get_wall_material(10, 10));
+
+    // Out-of-bounds cells can't be given a material, and read back as Normal.
+    assert!(grid
+        .set_wall_material(500, 10, SimWallMaterial::Sticky)
+        .is_err());
+    assert_eq!(SimWallMaterial::Normal, grid.get_wall_material(500, 10));
+
+    // Normal walls behave exactly like walls always have.
+    assert_eq!(0.0, SimWallMaterial::Normal.restitution());
+    assert_eq!(0.0, SimWallMaterial::Normal.friction());
+    assert_eq!(0.0, SimWallMaterial::Normal.adhesion());
+    assert!(SimWallMaterial::Bouncy.restitution() > 0.0);
+}
+
+#[test]
+fn pool_cells_test() {
+    let mut grid = SimGrid::default();
+
+    // A small cup with an open top: a floor on row 10 and walls on columns 5 and 9.
+    for col in 5..=9 {
+        let _ = grid.set_grid_cell_type(10, col, SimGridCellType::Solid);
+    }
+    for row in 7..10 {
+        let _ = grid.set_grid_cell_type(row, 5, SimGridCellType::Solid);
+        let _ = grid.set_grid_cell_type(row, 9, SimGridCellType::Solid);
+    }
+
+    // Filling from row 8 stays inside the cup and never rises above the starting row.
+    let cells = grid.get_pool_cells(Vec2 { x: 8.0, y: 7.0 });
+    assert_eq!(6, cells.len());
+    for cell in cells.iter() {
+        assert!(
+            cell.x == 8.0 || cell.x == 9.0,
+            "unexpected pool row {}",
+            cell.x
+        );
+        assert!(
+            cell.y >= 6.0 && cell.y <= 8.0,
+            "unexpected pool column {}",
+            cell.y
+        );
+    }
+
+    // Starting inside of a wall fills nothing.
+    assert!(grid.get_pool_cells(Vec2 { x: 10.0, y: 7.0 }).is_empty());
+}
+
+#[test]
+fn container_measure_test() {
+    let mut grid = SimGrid::default();
+    let particle_radius = SimConstraints::default().particle_radius;
+
+    // A container over the bottom-left 10x5 cells of the grid, with one solid cell in it.
+    let mut container = simulation::SimContainer::new(Vec2::new(50.0, 25.0), Vec2::ZERO);
+    assert_eq!(Vec2::ZERO, container.min);
+    let _ = grid.set_grid_cell_type(49, 0, SimGridCellType::Solid);
+
+    // Three particles inside the container, one of which is on its way out, and one outside.
+    let inside = grid.get_lookup_index(Vec2 { x: 48.0, y: 2.0 });
+    let outside = grid.get_lookup_index(Vec2 { x: 10.0, y: 2.0 });
+    for id in 1..=3 {
+        grid.add_particle_to_lookup(Entity::from_raw(id), inside);
+    }
+    grid.add_particle_to_lookup(Entity::from_raw(4), outside);
+    grid.apply_lookup_changes();
+    grid.queue_particle_lookup_removal(Entity::from_raw(3), inside);
+
+    container.measure(&grid, particle_radius);
+    assert_eq!(2, container.particle_count);
+
+    // 49 open cells, each holding (5 / 4)^2 resting particles.
+    assert_eq!(77, container.capacity);
+    assert!(container.fill_level() > 0.0 && container.fill_level() < 0.1);
+}
+
+#[test]
+fn safeguard_report_test() {
+    let mut report = simulation::sim_safeguards::SimSafeguardReport::default();
+    assert_eq!(None, report.take_warning());
+
+    // Tallies add up, and taking the warning clears them.
+    report.clamped = 2;
+    report.add(&simulation::sim_safeguards::SimSafeguardReport {
+        clamped: 1,
+        redistributed: 0,
+        despawned: 4,
+    });
+    assert_eq!(
+        Some("Stability safeguards slowed 3 particles, removed 4 particles.".to_owned()),
+        report.take_warning()
+    );
+    assert!(report.is_empty());
+}
+
+/// Adds a calm particle, a runaway particle, and a particle that has already blown up.
+#[cfg(test)]
+fn test_setup_unstable_particles(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    for (position, velocity) in [
+        (Vec2::splat(100.0), Vec2::ZERO),
+        (Vec2::splat(120.0), Vec2::new(1.0e5, 0.0)),
+        (Vec2::splat(140.0), Vec2::NAN),
+    ] {
+        let _ = add_particle(
+            &mut commands,
+            constraints.as_mut(),
+            grid.as_mut(),
+            position,
+            velocity,
+            AMBIENT_TEMPERATURE,
+            0,
+            SimFluidMaterial::Water,
+        );
+    }
+}
+
+/// Run just the stability guard, as if a step had just finished.
+#[cfg(test)]
+fn test_guard_stability(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    let timestep: f32 = constraints.timestep;
+    guard_stability(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &mut particles,
+        timestep,
+    );
+}
+
+#[test]
+fn stability_guard_test() {
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.add_systems(Startup, test_setup_unstable_particles);
+    juicebox_test.add_systems(Update, test_guard_stability);
+    let timestep: f32 = SimConstraints::default().timestep;
+
+    // The broken particle is removed, and the explosion shrinks the timestep (with a warning).
+    juicebox_test.update();
+    let mut constraints = juicebox_test.world.resource_mut::<SimConstraints>();
+    assert_eq!(2, constraints.particle_count);
+    assert_eq!(timestep * 0.5, constraints.timestep);
+    assert_eq!(Some(timestep), constraints.stability.original_timestep);
+    assert!(constraints.stability.take_warning().is_some());
+
+    // Once the runaway particle calms down, the timestep is eventually put back.
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&mut SimParticle, Without<SimInactiveParticle>>();
+    for mut particle in particles.iter_mut(&mut juicebox_test.world) {
+        particle.velocity = Vec2::ZERO;
+    }
+    for _ in 0..CALM_STEPS_BEFORE_RELAXING {
+        juicebox_test.update();
+    }
+    let constraints = juicebox_test.world.resource::<SimConstraints>();
+    assert_eq!(timestep, constraints.timestep);
+    assert!(!constraints.stability.is_recovering());
+    assert_eq!(None, constraints.stability.warning);
+}
+
+#[test]
+fn sequencer_playback_test() {
+    use simulation::sim_sequencer::{SimKeyframe, SimSequencer, SimSequencerAction};
+
+    let flip_gravity = SimSequencerAction::SetGravity(Vec2::new(0.0, 385.0));
+    let mut sequencer = SimSequencer::default();
+    sequencer.add_keyframe(SimKeyframe {
+        time: 10.0,
+        action: flip_gravity,
+    });
+    sequencer.add_keyframe(SimKeyframe {
+        time: 2.0,
+        action: SimSequencerAction::RemoveFaucets,
+    });
+
+    // Keyframes fire once each, in time order, as playback reaches them.
+    assert!(sequencer.advance(1.0).is_empty());
+    assert_eq!(
+        vec![SimSequencerAction::RemoveFaucets],
+        sequencer.advance(5.0)
+    );
+    assert!(sequencer.advance(6.0).is_empty());
+
+    // Keyframes scheduled in the past wait for the next playback.
+    sequencer.add_keyframe(SimKeyframe {
+        time: 3.0,
+        action: SimSequencerAction::RemoveFaucets,
+    });
+    assert_eq!(vec![flip_gravity], sequencer.advance(12.0));
+
+    // Going back in time means the simulation was reset, so everything plays again.
+    assert!(sequencer.remove_keyframe(3).is_err());
+    assert!(sequencer.remove_keyframe(1).is_ok());
+    assert_eq!(
+        vec![SimSequencerAction::RemoveFaucets, flip_gravity],
+        sequencer.advance(10.0)
+    );
+
+    // Seeking (e.g. after a quick load) skips the keyframes before it without firing them.
+    sequencer.seek(5.0);
+    assert_eq!(5.0, sequencer.time());
+    assert_eq!(vec![flip_gravity], sequencer.advance(11.0));
+}
+
+/// Tops up and thins out the fluid's cells.
+#[cfg(test)]
+fn test_reseed_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    reseed_particles(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &particles,
+    );
+}
+
+#[test]
+fn reseeding_test() {
+    let mut juicebox_test = App::new();
+    let mut grid = SimGrid::default();
+    let mut constraints = SimConstraints::default();
+    constraints.reseeding = true;
+    constraints.min_particles_per_cell = 2;
+    constraints.max_particles_per_cell = 4;
+
+    /* A 5x5 block of fluid with one particle per cell, except for an empty cell and a crowded one
+    inside of it. */
+    let empty_cell = Vec2::new(21.0, 21.0);
+    let crowded_cell = Vec2::new(22.0, 22.0);
+    let velocity = Vec2::new(3.0, 0.0);
+    for row in 20..25 {
+        for col in 20..25 {
+            grid.cell_type[row][col] = SimGridCellType::Fluid;
+            let cell = Vec2::new(row as f32, col as f32);
+            let particle_count: usize = if cell == empty_cell {
+                0
+            } else if cell == crowded_cell {
+                7
+            } else {
+                1
+            };
+            for _ in 0..particle_count {
+                let position: Vec2 = grid.get_cell_center_position_from_coordinates(&cell);
+                let lookup_index: usize = grid.get_lookup_index(cell);
+                let particle: Entity = juicebox_test
+                    .world
+                    .spawn(SimParticle {
+                        position,
+                        velocity,
+                        lookup_index,
+                        group: 2,
+                        material: SimFluidMaterial::Oil,
+                        previous_position: position,
+                        ..default()
+                    })
+                    .id();
+                grid.add_particle_to_lookup(particle, lookup_index);
+                constraints.particle_count += 1;
+            }
+        }
+    }
+    let particle_count_before: usize = constraints.particle_count;
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    juicebox_test.add_systems(Update, test_reseed_update);
+    juicebox_test.update();
+
+    let grid = juicebox_test.world.resource::<SimGrid>().clone();
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    let mut count_in_cell = |cell: Vec2| -> usize {
+        particles
+            .iter(&juicebox_test.world)
+            .filter(|particle| grid.get_cell_coordinates_from_position(&particle.position) == cell)
+            .count()
+    };
+
+    // Cells inside the fluid are brought into range, while cells on its surface are left alone.
+    assert_eq!(2, count_in_cell(empty_cell));
+    assert_eq!(4, count_in_cell(crowded_cell));
+    assert_eq!(2, count_in_cell(Vec2::new(23.0, 21.0)));
+    assert_eq!(1, count_in_cell(Vec2::new(20.0, 22.0)));
+    assert_eq!(
+        particle_count_before + 6,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+
+    // New particles take after the fluid around them.
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert!(particles.iter(&juicebox_test.world).all(|particle| {
+        particle.velocity == velocity
+            && particle.group == 2
+            && particle.material == SimFluidMaterial::Oil
+    }));
+}
+
+/// Merges and splits the fluid's particles.
+#[cfg(test)]
+fn test_adapt_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    adapt_particles(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &mut particles,
+    );
+}
+
+#[test]
+fn adaptive_particles_test() {
+    let mut juicebox_test = App::new();
+    let mut grid = SimGrid::default();
+    let mut constraints = SimConstraints::default();
+    constraints.adaptive_particles = true;
+
+    // Two still particles touching in the middle of the grid.
+    let cell = Vec2::new(25.0, 25.0);
+    let center: Vec2 = grid.get_cell_center_position_from_coordinates(&cell);
+    let lookup_index: usize = grid.get_lookup_index(cell);
+    for offset in [-0.5, 0.5] {
+        let position: Vec2 = center + Vec2::new(offset, 0.0);
+        let particle: Entity = juicebox_test
+            .world
+            .spawn(SimParticle {
+                position,
+                lookup_index,
+                radius: constraints.particle_radius,
+                previous_position: position,
+                ..default()
+            })
+            .id();
+        grid.add_particle_to_lookup(particle, lookup_index);
+        constraints.particle_count += 1;
+    }
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    juicebox_test.add_systems(Update, test_adapt_update);
+    juicebox_test.update();
+
+    // Calm particles merge into one twice as heavy, covering as much area as both did.
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    let merged: &SimParticle = particles.single(&juicebox_test.world);
+    assert_eq!(2.0, merged.mass);
+    assert!(merged.position.abs_diff_eq(center, 1e-4));
+    assert!((merged.radius - 2.0 * 2.0_f32.sqrt()).abs() < 1e-4);
+    assert_eq!(
+        1,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+
+    // Shear the flow hard, and the merged particle splits back into two ordinary ones.
+    let mut grid = juicebox_test.world.resource_mut::<SimGrid>();
+    for row in 0..grid.velocity_u.len() {
+        for col in 0..grid.velocity_u[row].len() {
+            grid.velocity_u[row][col] = 100.0 * grid.get_velocity_point_pos(row, col, true).y;
+        }
+    }
+    juicebox_test.update();
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert_eq!(2, particles.iter(&juicebox_test.world).count());
+    assert!(particles
+        .iter(&juicebox_test.world)
+        .all(|particle| particle.mass == 1.0 && particle.radius == 2.0));
+}
+
+/// Sets aside (or puts back) the particles of fluid deep below the surface.
+#[cfg(test)]
+fn test_narrow_band_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    update_narrow_band(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &particles,
+    );
+}
+
+#[test]
+fn narrow_band_test() {
+    let mut juicebox_test = App::new();
+    let mut grid = SimGrid::default();
+    let mut constraints = SimConstraints::default();
+    constraints.narrow_band = true;
+    constraints.narrow_band_width = 1;
+
+    // A 9x9 block of fluid surrounded by air, with one particle per cell.
+    let velocity = Vec2::new(-4.0, 1.0);
+    for row in 20..29 {
+        for col in 20..29 {
+            grid.cell_type[row][col] = SimGridCellType::Fluid;
+            let cell = Vec2::new(row as f32, col as f32);
+            let position: Vec2 = grid.get_cell_center_position_from_coordinates(&cell);
+            let lookup_index: usize = grid.get_lookup_index(cell);
+            let particle: Entity = juicebox_test
+                .world
+                .spawn(SimParticle {
+                    position,
+                    velocity,
+                    lookup_index,
+                    group: 1,
+                    material: SimFluidMaterial::Honey,
+                    radius: constraints.particle_radius,
+                    previous_position: position,
+                    ..default()
+                })
+                .id();
+            grid.add_particle_to_lookup(particle, lookup_index);
+            constraints.particle_count += 1;
+        }
+    }
+    grid.apply_lookup_changes();
+    juicebox_test.insert_resource(grid);
+    juicebox_test.insert_resource(constraints);
+    juicebox_test.add_systems(Update, test_narrow_band_update);
+    juicebox_test.update();
+    juicebox_test
+        .world
+        .resource_mut::<SimGrid>()
+        .flush_lookup_removals();
+
+    /* The ring of cells at the surface keeps its particles, as does the ring just past the band; the
+    5x5 core gives them up. */
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert_eq!(56, particles.iter(&juicebox_test.world).count());
+    assert_eq!(
+        56,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+    let mut grid = juicebox_test.world.resource::<SimGrid>().clone();
+    assert!(grid.is_narrow_band_interior(24, 24));
+    assert!(grid.is_narrow_band_interior(22, 26));
+    assert!(!grid.is_narrow_band_interior(21, 24));
+    assert_eq!(
+        25,
+        grid.narrow_band_interior
+            .iter()
+            .map(Vec::len)
+            .sum::<usize>()
+    );
+
+    // The core is still full of fluid, even without any particles.
+    grid.label_cells();
+    assert_eq!(SimGridCellType::Fluid, grid.cell_type[24][24]);
+
+    // Turning narrow-band FLIP off puts every particle back, just as it was.
+    juicebox_test
+        .world
+        .resource_mut::<SimConstraints>()
+        .narrow_band = false;
+    juicebox_test.update();
+
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert_eq!(81, particles.iter(&juicebox_test.world).count());
+    assert!(particles
+        .iter(&juicebox_test.world)
+        .all(|particle| { particle.group == 1 && particle.material == SimFluidMaterial::Honey }));
+    assert!(juicebox_test
+        .world
+        .resource::<SimGrid>()
+        .narrow_band_interior
+        .iter()
+        .all(Vec::is_empty));
+}
+
+/// Swirls the particles around the middle of the grid, counter-clockwise.
+#[cfg(test)]
+fn test_vortex_update(
+    grid: Res<SimGrid>,
+    mut particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    let center = Vec2::splat(grid.cell_size as f32 * 25.0);
+    swirl_particles_in_radius(&mut particles, center, 30.0, 600.0, 1.0 / 60.0);
+}
+
+#[test]
+fn vortex_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+
+    // A row of particles running out from the vortex's center, past its edge.
+    juicebox_test.add_systems(Startup, test_setup_particle_groups);
+    juicebox_test.add_systems(Update, test_vortex_update);
+    juicebox_test.update();
+
+    let center = Vec2::splat(SimGrid::default().cell_size as f32 * 25.0);
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    for particle in particles.iter(&juicebox_test.world) {
+        let offset: Vec2 = particle.position - center;
+        if offset.length() >= 30.0 {
+            assert_eq!(Vec2::ZERO, particle.velocity);
+            continue;
+        }
+
+        // Particles inside spin counter-clockwise, and slower the further out they are.
+        if offset.length() > 0.0 {
+            assert!(offset.perp_dot(particle.velocity) > 0.0);
+            assert!(offset.dot(particle.velocity).abs() < 1e-3);
+            let falloff: f32 = 1.0 - offset.length() / 30.0;
+            assert!((particle.velocity.length() - 10.0 * falloff).abs() < 1e-3);
+        }
+    }
+}
+
+/// Lays a sand wall in a fast current, and four slow grains of sediment on a floor beside it.
+#[cfg(test)]
+fn test_setup_sediment(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+) {
+    // Fluid flows quickly to the right past the sand cell.
+    let _ = grid.set_grid_cell_type(10, 10, SimGridCellType::Solid);
+    let _ = grid.set_wall_material(10, 10, SimWallMaterial::Sand);
+    let _ = grid.set_grid_cell_type(10, 9, SimGridCellType::Fluid);
+    for row in grid.velocity_u.iter_mut() {
+        row.fill(400.0);
+    }
+    for row in grid.velocity_v.iter_mut() {
+        row.fill(0.0);
+    }
+
+    // Sediment resting just above a plain wall.
+    let _ = grid.set_grid_cell_type(31, 25, SimGridCellType::Solid);
+    let center: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(30.0, 25.0));
+    for offset in [-1.0, 1.0] {
+        for other_offset in [-1.0, 1.0] {
+            let _ = add_particle(
+                &mut commands,
+                constraints.as_mut(),
+                grid.as_mut(),
+                center + Vec2::new(offset, other_offset),
+                Vec2::ZERO,
+                AMBIENT_TEMPERATURE,
+                0,
+                SimFluidMaterial::Sediment,
+            );
+        }
+    }
+}
+
+/// Runs a full second of erosion and deposition.
+#[cfg(test)]
+fn test_sediment_update(
+    mut commands: Commands,
+    mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    particles: Query<(Entity, &mut SimParticle), Without<SimInactiveParticle>>,
+) {
+    transport_sediment(
+        &mut commands,
+        constraints.as_mut(),
+        grid.as_mut(),
+        &particles,
+        1.0,
+    );
+}
+
+#[test]
+fn sediment_test() {
+    let mut juicebox_test = App::new();
+
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.add_systems(Startup, test_setup_sediment);
+    juicebox_test.add_systems(Update, test_sediment_update);
+    juicebox_test.update();
+
+    // The sand wore through and crumbled into sediment carried off by the current.
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    assert_eq!(SimGridCellType::Air, grid.cell_type[10][10]);
+    let sand_center: Vec2 = grid.get_cell_center_position_from_coordinates(&Vec2::new(10.0, 10.0));
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    let sediment: Vec<&SimParticle> = particles
+        .iter(&juicebox_test.world)
+        .filter(|particle| particle.position.distance(sand_center) < 10.0)
+        .collect();
+    assert_eq!(SEDIMENT_PER_CELL, sediment.len());
+    for particle in sediment {
+        assert_eq!(SimFluidMaterial::Sediment, particle.material);
+        assert_eq!(Vec2::new(400.0, 0.0), particle.velocity);
+    }
+
+    // The settled sediment packed back into a sand cell on top of the floor.
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[30][25]);
+    assert_eq!(SimWallMaterial::Sand, grid.get_wall_material(30, 25));
+    assert_eq!(
+        SEDIMENT_PER_CELL,
+        juicebox_test
+            .world
+            .resource::<SimConstraints>()
+            .particle_count
+    );
+}
+
+#[test]
+fn domain_test() {
+    let mut juicebox_test = App::new();
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.insert_resource(SimGrid::default());
+    let world: &mut World = &mut juicebox_test.world;
+
+    // Two tanks side by side, only one of which has gravity.
+    let (falling, floating) = world.run_system_once(|mut commands: Commands| {
+        let mut zero_gravity = SimConstraints::default();
+        zero_gravity.gravity = Vec2::ZERO;
+        (
+            spawn_domain(&mut commands, SimConstraints::default(), Vec2::ZERO),
+            spawn_domain(&mut commands, zero_gravity, Vec2::new(500.0, 0.0)),
+        )
+    });
+    let heights = |world: &mut World, domain_id: Entity| -> Vec<f32> {
+        world
+            .query_filtered::<(&SimParticle, &SimDomainMember), Without<SimInactiveParticle>>()
+            .iter(world)
+            .filter(|(_, member)| member.0 == domain_id)
+            .map(|(particle, _)| particle.position.y)
+            .collect()
+    };
+    let average = |heights: Vec<f32>| -> f32 { heights.iter().sum::<f32>() / heights.len() as f32 };
+
+    // Each domain starts out with its own copy of the default scene.
+    let particle_count: usize = heights(world, falling).len();
+    assert!(particle_count > 0);
+    assert_eq!(
+        particle_count,
+        world.get::<SimConstraints>(falling).unwrap().particle_count
+    );
+    assert_eq!(particle_count, heights(world, floating).len());
+    let starting_height: f32 = average(heights(world, falling));
+
+    // Each domain steps with its own settings, without touching the other or the main simulation.
+    for _ in 0..30 {
+        advance_domains(world, 1.0 / 60.0, &[]);
+    }
+    assert!(world.get::<SimConstraints>(falling).unwrap().simulated_time > 0.0);
+    assert!(average(heights(world, falling)) < starting_height - 1.0);
+    assert!((average(heights(world, floating)) - starting_height).abs() < 1.0);
+    assert_eq!(particle_count, heights(world, falling).len());
+    assert_eq!(world.resource::<SimConstraints>().simulated_time, 0.0);
+    assert_eq!(
+        world
+            .query_filtered::<(), (With<SimParticle>, Without<SimDomainMember>)>()
+            .iter(world)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn background_step_test() {
+    /* Two copies of the default scene with a faucet added, one stepped inline and one on the
+    simulation thread. */
+    let new_scene = || -> App {
+        let mut juicebox_test = App::new();
+        juicebox_test.insert_resource(SimConstraints::default());
+        juicebox_test.insert_resource(SimGrid::default());
+        juicebox_test.insert_resource(SimRng::default());
+        juicebox_test.world.run_system_once(
+            |mut commands: Commands,
+             mut constraints: ResMut<SimConstraints>,
+             mut grid: ResMut<SimGrid>| {
+                construct_new_simulation(constraints.as_mut(), grid.as_mut(), &mut commands);
+            },
+        );
+        juicebox_test.world.run_system_once(test_add_faucet_update);
+        juicebox_test
+    };
+    // Particles the thread spawns get new entities in the main world, so go by spawn order instead.
+    let particles = |world: &mut World| -> Vec<(u64, Vec2)> {
+        let mut particles: Vec<(u64, Vec2)> = world
+            .query_filtered::<&SimParticle, Without<SimInactiveParticle>>()
+            .iter(world)
+            .map(|particle| (particle.spawn_id, particle.position))
+            .collect();
+        particles.sort_by_key(|(spawn_id, _)| *spawn_id);
+        particles
+    };
+    let step_inline = |world: &mut World, step_count: u8| {
+        for _ in 0..step_count {
+            world.run_system_once(test_update);
+            world.resource_mut::<SimGrid>().flush_lookup_removals();
+        }
+    };
+    let launch = |world: &mut World, step_count: u8| {
+        world
+            .resource_mut::<SimStepTask>()
+            .queue_steps(step_count, step_count);
+        world.run_system_once(launch_background_step);
+    };
+    // Land the way the app does, which (unlike finish_background_step) leaves the thread's world be.
+    let land = |world: &mut World| {
+        while world.resource::<SimStepTask>().is_running() {
+            std::thread::yield_now();
+            world.run_system_once(land_background_step);
+        }
+    };
+    let mut inline = new_scene();
+    let mut background = new_scene();
+    background.insert_resource(SimStepTask::default());
+    let starting_particles: Vec<(u64, Vec2)> = particles(&mut background.world);
+
+    // The front copy is left alone while a job is in flight.
+    let step_count: u8 = 20;
+    step_inline(&mut inline.world, step_count);
+    launch(&mut background.world, step_count);
+    assert!(background.world.resource::<SimStepTask>().is_running());
+    assert_eq!(
+        background.world.resource::<SimConstraints>().simulated_time,
+        0.0
+    );
+    assert_eq!(particles(&mut background.world), starting_particles);
+
+    // Once it lands, the main simulation is just where stepping inline would have left it.
+    finish_background_step(&mut background.world);
+    assert!(!background.world.resource::<SimStepTask>().is_running());
+    assert_eq!(
+        background.world.resource::<SimConstraints>().simulated_time,
+        inline.world.resource::<SimConstraints>().simulated_time
+    );
+    assert_ne!(particles(&mut background.world), starting_particles);
+    assert_eq!(
+        particles(&mut background.world),
+        particles(&mut inline.world)
+    );
+
+    /* The thread keeps stepping its own world from one job to the next, spawning from the faucet as
+    it goes, and picks up edits made to the front copy in between. */
+    for edit in [false, true] {
+        if edit {
+            inline.world.resource_mut::<SimConstraints>().gravity = Vec2::ZERO;
+            background.world.resource_mut::<SimConstraints>().gravity = Vec2::ZERO;
+            background.world.resource_mut::<SimStepTask>().mark_edited();
+        }
+        step_inline(&mut inline.world, step_count);
+        launch(&mut background.world, step_count);
+        land(&mut background.world);
+        assert_eq!(
+            particles(&mut background.world),
+            particles(&mut inline.world)
+        );
+    }
+    assert_eq!(
+        background.world.resource::<SimConstraints>().particle_count,
+        inline.world.resource::<SimConstraints>().particle_count
+    );
+    assert!(
+        background.world.resource::<SimConstraints>().particle_count > starting_particles.len()
+    );
+}
+
+/// An app with the file system's type registrations, for saving and loading scene files.
+#[cfg(test)]
+fn scene_file_test_app() -> App {
+    let mut juicebox_test = App::new();
+    juicebox_test.add_plugins((TypeRegistrationPlugin, FileSystem, SavePlugin));
+    juicebox_test.add_event::<ResetEvent>();
+    juicebox_test.insert_resource(UIStateManager::default());
+    juicebox_test.insert_resource(SimGrid::default());
+    juicebox_test.insert_resource(SimConstraints::default());
+    juicebox_test.insert_resource(SimRng::default());
+    juicebox_test.insert_resource(SimSequencer::default());
+
+    juicebox_test
+}
+
+#[test]
+fn baseline_scene_file_test() {
+    // A scene file saved before any of the newer grid, constraint, or particle fields existed.
+    let (rows, cols): (usize, usize) = (60, 60);
+    let cell_type: Vec<Vec<&str>> = (0..rows)
+        .map(|row| {
+            (0..cols)
+                .map(|col| {
+                    let wall: bool = row == 0 || row == rows - 1 || col == 0 || col == cols - 1;
+                    if wall {
+                        "Solid"
+                    } else {
+                        "Air"
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let particle = |x: f32| {
+        serde_json::json!({ "components": { SimParticle::type_path(): {
+            "position": { "x": x, "y": 150.0 },
+            "velocity": { "x": 0.0, "y": 0.0 },
+            "lookup_index": 0,
+        }}})
+    };
+    let scene_file = serde_json::json!({
+        "entities": { "0": particle(100.0), "1": particle(105.0) },
+        "resources": {
+            SimGrid::type_path(): {
+                "dimensions": [rows, cols],
+                "cell_size": 5,
+                "cell_type": cell_type,
+                "cell_center": vec![vec![0.0; cols]; rows],
+                "velocity_u": vec![vec![0.0; cols + 1]; rows],
+                "velocity_v": vec![vec![0.0; cols]; rows + 1],
+                "spatial_lookup": vec![Vec::<u64>::new(); rows * cols],
+                "density": vec![0.0; rows * cols],
+            },
+            SimConstraints::type_path(): {
+                "is_paused": false,
+                "timestep": 1.0 / 120.0,
+                "gravity": { "x": 0.0, "y": -385.0 },
+                "grid_particle_ratio": 0.3,
+                "incomp_iters_per_frame": 100,
+                "collision_iters_per_frame": 2,
+                "particle_radius": 2.0,
+                "particle_count": 2,
+                "particle_rest_density": 0.0,
+                "selected_particles": [],
+            },
+        },
+    });
+    let key: String = std::env::temp_dir()
+        .join("juicebox-baseline-scene")
+        .to_string_lossy()
+        .into_owned();
+    std::fs::write(format!("{}.juice", key), scene_file.to_string()).unwrap();
+
+    // Load it over a scene with settings of its own.
+    let mut juicebox_test = scene_file_test_app();
+    juicebox_test.add_systems(Update, test_update);
+    juicebox_test
+        .world
+        .resource_mut::<SimConstraints>()
+        .viscosity = 5.0;
+    load_scene(key, &mut juicebox_test.world);
+
+    // Everything the file has was loaded...
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert_eq!(2, particles.iter(&juicebox_test.world).count());
+    let grid = juicebox_test.world.resource::<SimGrid>();
+    assert_eq!((60, 60), grid.dimensions);
+    assert_eq!(SimGridCellType::Solid, grid.cell_type[59][30]);
+
+    // ...and everything it predates took on its default, sized to the loaded grid.
+    assert_eq!(rows, grid.cell_material.len());
+    assert_eq!(rows * cols, grid.wall_slip.len());
+    assert_eq!(rows * cols, grid.narrow_band_interior.len());
+    let default_constraints = SimConstraints::default();
+    let constraints = juicebox_test.world.resource::<SimConstraints>();
+    assert_eq!(default_constraints.viscosity, constraints.viscosity);
+    assert_eq!(default_constraints.substeps, constraints.substeps);
+    for particle in particles.iter(&juicebox_test.world) {
+        assert_eq!(1.0, particle.mass);
+        assert_eq!(DEFAULT_PARTICLE_RADIUS, particle.radius);
+        assert_eq!(AMBIENT_TEMPERATURE, particle.temperature);
+        assert_eq!(particle.position, particle.previous_position);
+    }
+
+    // The loaded scene runs like any other.
+    juicebox_test
+        .world
+        .resource_mut::<SimConstraints>()
+        .is_paused = false;
+    for _ in 0..10 {
+        juicebox_test.update();
+    }
+    let mut particles = juicebox_test
+        .world
+        .query_filtered::<&SimParticle, Without<SimInactiveParticle>>();
+    assert!(particles
+        .iter(&juicebox_test.world)
+        .all(|particle| particle.position.y < 150.0));
+}
+
+#[test]
+fn loaded_settings_survive_ui_test() {
+    let key: String = std::env::temp_dir()
+        .join("juicebox-loaded-settings")
+        .to_string_lossy()
+        .into_owned();
+    let mut saved_scene = scene_file_test_app();
+    {
+        let mut constraints = saved_scene.world.resource_mut::<SimConstraints>();
+        constraints.viscosity = 3.0;
+        constraints.substeps = 4;
+    }
+    save_scene(key.clone(), &mut saved_scene.world);
+
+    // The UI has been running for a while before the scene is loaded.
+    let mut juicebox_test = scene_file_test_app();
+    juicebox_test.add_state::<JuiceStates>();
+    juicebox_test.add_event::<ClearEvent>();
+    juicebox_test.add_event::<UseToolEvent>();
+    juicebox_test.add_event::<PlayPauseStepEvent>();
+    juicebox_test.insert_resource(Time::<()>::default());
+    juicebox_test.insert_resource(Input::<KeyCode>::default());
+    juicebox_test.insert_resource(Input::<MouseButton>::default());
+    juicebox_test.add_systems(Update, handle_input);
+    juicebox_test.update();
+
+    load_scene(key, &mut juicebox_test.world);
+    juicebox_test.update();
+
+    let constraints = juicebox_test.world.resource::<SimConstraints>();
+    assert_eq!(3.0, constraints.viscosity);
+    assert_eq!(4, constraints.substeps);
+    let ui_state = juicebox_test.world.resource::<UIStateManager>();
+    assert_eq!(3.0, ui_state.viscosity);
+    assert_eq!(4, ui_state.substeps);
+}
+
+#[test]
+fn loaded_scene_particle_pool_test() {
+    let key: String = std::env::temp_dir()
+        .join("juicebox-loaded-particle-pool")
+        .to_string_lossy()
+        .into_owned();
+    let mut saved_scene = scene_file_test_app();
+    save_scene(key.clone(), &mut saved_scene.world);
+
+    // Pool a few particles' entities before loading.
+    let mut juicebox_test = scene_file_test_app();
+    juicebox_test
+        .world
+        .run_system_once(test_setup_particle_groups);
+    juicebox_test
+        .world
+        .run_system_once(test_delete_group_update);
+    juicebox_test
+        .world
+        .resource_mut::<SimGrid>()
+        .flush_lookup_removals();
+    let pooled: Vec<Entity> = juicebox_test
+        .world
+        .query_filtered::<Entity, With<SimInactiveParticle>>()
+        .iter(&juicebox_test.world)
+        .collect();
+    assert_eq!(3, pooled.len());
+    assert_eq!(
+        3,
+        juicebox_test
+            .world
+            .resource::<SimGrid>()
+            .particle_pool
+            .len()
+    );
+
+    // Loading despawns the pooled entities, so the loaded grid doesn't hand them out again.
+    load_scene(key, &mut juicebox_test.world);
+    assert!(juicebox_test
+        .world
+        .resource::<SimGrid>()
+        .particle_pool
+        .is_empty());
+    juicebox_test
+        .world
+        .run_system_once(test_respawn_particles_update);
+    let world: &mut World = &mut juicebox_test.world;
+    let spawned: Vec<Entity> = world
+        .query_filtered::<Entity, (With<SimParticle>, Without<SimInactiveParticle>)>()
+        .iter(world)
+        .collect();
+    assert_eq!(3, spawned.len());
+    assert!(spawned.iter().all(|id| !pooled.contains(id)));
+    assert!(pooled.iter().all(|id| world.get_entity(*id).is_none()));
+}
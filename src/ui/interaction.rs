@@ -1,28 +1,68 @@
-use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
 
 use crate::events::{ClearEvent, PlayPauseStepEvent, ResetEvent, UseToolEvent};
 use crate::file_system::JuiceStates;
-use crate::simulation::{change_gravity, SimConstraints, SimGrid};
+use crate::simulation::sim_domains::{find_domain_at, SimDomain, SimMainDomain};
+use crate::simulation::sim_particle_pool::SimInactiveParticle;
+use crate::simulation::sim_step_task::SimStepTask;
+use crate::simulation::{
+    change_gravity, SimConstraints, SimEdgeBoundary, SimGrid, SimGridEdge, SimParticle,
+};
 use crate::ui::UIStateManager;
 use crate::util::*;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::window::PrimaryWindow;
 
 use super::SimTool;
 
+/// How quickly the camera catches up to whatever it's following; higher is snappier.
+const CAMERA_FOLLOW_RATE: f32 = 4.0;
+/// Farthest from the cursor a particle can be clicked to follow it, in world units.
+const CAMERA_FOLLOW_PICK_RADIUS: f32 = 10.0;
+
+/// The simulation settings as the UI last set them, so only the ones the user changes get copied over.
+#[derive(Default)]
+pub struct UISettings {
+    constraints: SimConstraints,
+    wrapping: (bool, bool),
+    edge_boundaries: [SimEdgeBoundary; 4],
+}
+
+/** Copy a setting from the UI over to the simulation, if the user has changed it since last time.
+Returns whether it was copied. */
+fn copy_if_changed<T: Copy + PartialEq>(
+    ui_value: T,
+    last_ui_value: &mut T,
+    sim_value: &mut T,
+) -> bool {
+    if ui_value != *last_ui_value {
+        *last_ui_value = ui_value;
+        *sim_value = ui_value;
+        return true;
+    }
+    false
+}
+
 /// Debugging state controller.
 pub fn handle_input(
     mut constraints: ResMut<SimConstraints>,
+    mut grid: ResMut<SimGrid>,
+    time: Res<Time>,
     keys: Res<Input<KeyCode>>,
     mouse: Res<Input<MouseButton>>,
     windows: Query<&Window>,
     cameras: Query<(&Camera, &GlobalTransform)>,
+    domains: Query<(Entity, &SimDomain, &SimGrid)>,
     mut ui_state: ResMut<UIStateManager>,
     mut ev_reset: EventWriter<ResetEvent>,
     mut ev_clear: EventWriter<ClearEvent>,
     mut ev_tool_use: EventWriter<UseToolEvent>,
     mut ev_pause: EventWriter<PlayPauseStepEvent>,
     mut file_state: ResMut<NextState<JuiceStates>>,
+    mut ui_settings: Local<UISettings>,
+    mut step_task: Option<ResMut<SimStepTask>>,
 ) {
     let left_mouse_pressed: bool = mouse.pressed(MouseButton::Left);
     let right_mouse_pressed: bool = mouse.pressed(MouseButton::Right);
@@ -32,15 +72,17 @@ pub fn handle_input(
     let r_key_pressed: bool = keys.just_pressed(KeyCode::R);
     let f_key_pressed: bool = keys.just_pressed(KeyCode::F);
     let space_pressed: bool = keys.just_pressed(KeyCode::Space);
+    let f6_key_pressed: bool = keys.just_pressed(KeyCode::F6);
+    let f7_key_pressed: bool = keys.just_pressed(KeyCode::F7);
 
     // Reset simulation when we press R or when UI button is pressed.
     if r_key_pressed {
-        ev_reset.send(ResetEvent);
+        ev_reset.send(ResetEvent::default());
         return;
     }
     if ui_state.reset {
         ui_state.reset = false;
-        ev_reset.send(ResetEvent);
+        ev_reset.send(ResetEvent::default());
         return;
     }
     // Pause/unpause the simulation if Space is pressed.
@@ -53,6 +95,13 @@ pub fn handle_input(
         ev_pause.send(PlayPauseStepEvent::new(true));
         return;
     }
+    // Quick save with F6 and quick load with F7.
+    if f6_key_pressed {
+        ui_state.file_state = JuiceStates::QuickSaving;
+    }
+    if f7_key_pressed {
+        ui_state.file_state = JuiceStates::QuickLoading;
+    }
     ui_state.is_paused = constraints.is_paused;
 
     // Handle tool usage for both mouse buttons.
@@ -66,37 +115,301 @@ pub fn handle_input(
         let mouse_held: bool = !mouse.just_pressed(mouse_button);
         let cursor_position = get_cursor_position(&windows, &cameras);
 
+        // Record the path of the pipe being drawn, spacing its points about a cell apart.
+        if ui_state.selected_tool == SimTool::AddPipe && mouse_button == MouseButton::Left {
+            if !mouse_held {
+                ui_state.pipe_path = vec![cursor_position];
+                ui_state.is_drawing_pipe = true;
+            } else if ui_state.is_drawing_pipe {
+                let last_point: Vec2 = ui_state.pipe_path[ui_state.pipe_path.len() - 1];
+                if last_point.distance(cursor_position) >= grid.cell_size as f32 {
+                    ui_state.pipe_path.push(cursor_position);
+                }
+            }
+        }
+
+        // Likewise, record the path a path-following faucet will sweep along.
+        if ui_state.selected_tool == SimTool::AddFaucet
+            && ui_state.faucet_follow_path
+            && mouse_button == MouseButton::Left
+        {
+            if !mouse_held {
+                ui_state.faucet_path = vec![cursor_position];
+                ui_state.is_drawing_faucet_path = true;
+            } else if ui_state.is_drawing_faucet_path {
+                let last_point: Vec2 = ui_state.faucet_path[ui_state.faucet_path.len() - 1];
+                if last_point.distance(cursor_position) >= grid.cell_size as f32 {
+                    ui_state.faucet_path.push(cursor_position);
+                }
+            }
+        }
+
+        // Remember where the container being dragged out started.
+        if ui_state.selected_tool == SimTool::Container
+            && mouse_button == MouseButton::Left
+            && !mouse_held
+        {
+            ui_state.container_corner = cursor_position;
+            ui_state.is_drawing_container = true;
+        }
+
+        // The same goes for the intake of the pump being dragged out.
+        if ui_state.selected_tool == SimTool::AddPump
+            && mouse_button == MouseButton::Left
+            && !mouse_held
+        {
+            ui_state.pump_intake = cursor_position;
+            ui_state.is_drawing_pump = true;
+        }
+
+        // And for the start of the flow meter being dragged out.
+        if ui_state.selected_tool == SimTool::FlowMeter
+            && mouse_button == MouseButton::Left
+            && !mouse_held
+        {
+            ui_state.flow_meter_start = cursor_position;
+            ui_state.is_drawing_flow_meter = true;
+        }
+
+        // Right clicking with the obstacle tool rotates the obstacle a quarter turn clockwise.
+        if ui_state.selected_tool == SimTool::AddObstacle
+            && mouse_button == MouseButton::Right
+            && !mouse_held
+        {
+            ui_state.obstacle_rotation = (ui_state.obstacle_rotation + 1) % 4;
+        }
+
         ev_tool_use.send(UseToolEvent::new(
             ui_state.selected_tool,
             cursor_position,
             Some(mouse_button),
             mouse_held,
+            find_domain_at(domains.iter(), ui_state.selected_tool, cursor_position),
+        ));
+    }
+
+    /* Once the mouse is released, finish the pipe's path and tell the simulation to build it; an
+    event without a mouse button marks the end of the drag.  The pipe is built in whichever domain
+    it was started in. */
+    if ui_state.is_drawing_pipe && mouse.just_released(MouseButton::Left) {
+        ui_state.is_drawing_pipe = false;
+
+        let cursor_position = get_cursor_position(&windows, &cameras);
+        ui_state.pipe_path.push(cursor_position);
+        ev_tool_use.send(UseToolEvent::new(
+            SimTool::AddPipe,
+            cursor_position,
+            None,
+            false,
+            find_domain_at(domains.iter(), SimTool::AddPipe, ui_state.pipe_path[0]),
+        ));
+    }
+
+    // The same goes for the path of a path-following faucet.
+    if ui_state.is_drawing_faucet_path && mouse.just_released(MouseButton::Left) {
+        ui_state.is_drawing_faucet_path = false;
+
+        let cursor_position = get_cursor_position(&windows, &cameras);
+        ui_state.faucet_path.push(cursor_position);
+        ev_tool_use.send(UseToolEvent::new(
+            SimTool::AddFaucet,
+            cursor_position,
+            None,
+            false,
+            find_domain_at(domains.iter(), SimTool::AddFaucet, ui_state.faucet_path[0]),
+        ));
+    }
+
+    // Likewise, once the mouse is released the dragged-out container can be added.
+    if ui_state.is_drawing_container && mouse.just_released(MouseButton::Left) {
+        ui_state.is_drawing_container = false;
+
+        let cursor_position = get_cursor_position(&windows, &cameras);
+        ev_tool_use.send(UseToolEvent::new(
+            SimTool::Container,
+            cursor_position,
+            None,
+            false,
+            None,
+        ));
+    }
+
+    // Once it is released, the dragged-out flow meter can be placed too.
+    if ui_state.is_drawing_flow_meter && mouse.just_released(MouseButton::Left) {
+        ui_state.is_drawing_flow_meter = false;
+
+        let cursor_position = get_cursor_position(&windows, &cameras);
+        ev_tool_use.send(UseToolEvent::new(
+            SimTool::FlowMeter,
+            cursor_position,
+            None,
+            false,
+            None,
+        ));
+    }
+
+    // And once it is released, the dragged-out pump can be connected to its outlet.
+    if ui_state.is_drawing_pump && mouse.just_released(MouseButton::Left) {
+        ui_state.is_drawing_pump = false;
+
+        let cursor_position = get_cursor_position(&windows, &cameras);
+        ev_tool_use.send(UseToolEvent::new(
+            SimTool::AddPump,
+            cursor_position,
+            None,
+            false,
+            None,
         ));
     }
 
-    /* Rotate/scale gravity when we press the arrow keys.  First, set the simulation's gravity to
-    that which is found in the UI.  Then, change the simulation's gravity values based on
-    keyboard input.  Finally, convert the modified gravity value from the simulation back into
-    values that the UI can display.  This allows for keyboard and UI slider control to work
-    in tandem. */
-    constraints.gravity = polar_to_cartesian(Vec2 {
-        x: ui_state.gravity_magnitude * ui_state.gravity_magnitude * 4.0,
-        y: degrees_to_radians(ui_state.gravity_direction) - PI,
-    });
-    change_gravity(constraints.as_mut(), up_down * 6.0, left_right);
-    let polar_gravity = cartesian_to_polar(constraints.gravity);
-    ui_state.gravity_magnitude = f32::sqrt(polar_gravity.x / 4.0);
-    ui_state.gravity_direction = radians_to_degrees(polar_gravity.y + PI);
+    /* Settings copied over while a job is in flight would be overwritten once it lands, so they
+    wait for it (the UI keeps showing what the user picked until then); the simulation thread then
+    has to pick them up. */
+    let idle: bool = !step_task
+        .as_ref()
+        .is_some_and(|step_task| step_task.is_running());
+    if idle
+        && copy_ui_settings(
+            constraints.as_mut(),
+            grid.as_mut(),
+            ui_state.as_mut(),
+            &mut ui_settings,
+            up_down,
+            left_right,
+        )
+    {
+        if let Some(step_task) = step_task.as_mut() {
+            step_task.mark_edited();
+        }
+    }
+
+    // Let the user know whenever the stability guard or safeguards have had to step in.
+    ui_state.toast_seconds_left = (ui_state.toast_seconds_left - time.delta_seconds()).max(0.0);
+    let warning: Option<String> = match constraints.stability.take_warning() {
+        Some(warning) => Some(warning),
+        None => constraints.safeguard_report.take_warning(),
+    };
+    if let Some(warning) = warning {
+        ui_state.toast_message = warning;
+        ui_state.toast_seconds_left = 3.0;
+    }
 
     file_state.set(ui_state.file_state.clone());
 
-    if ui_state.clear == true {
-        ev_clear.send(ClearEvent);
-        ui_state.clear = false;
+    if let Some(clear_event) = ui_state.clear.take() {
+        ev_clear.send(clear_event);
         return;
     }
 }
 
+/** Copy each setting the user changed since last frame over to the simulation.  Settings they
+haven't touched are left be, so whatever else changed them (loading a scene, resetting, the
+scene's timeline) keeps its values; see UIStateManager::read_simulation_settings().  The arrow keys
+(`up_down` and `left_right`) then rotate and scale gravity.  Returns whether anything changed. */
+fn copy_ui_settings(
+    constraints: &mut SimConstraints,
+    grid: &mut SimGrid,
+    ui_state: &mut UIStateManager,
+    last: &mut UISettings,
+    up_down: f32,
+    left_right: f32,
+) -> bool {
+    let mut changed: bool = copy_if_changed(
+        ui_state.gravity(),
+        &mut last.constraints.gravity,
+        &mut constraints.gravity,
+    );
+    // The UI and the simulation name their settings the same; the UI keeps choices as indices.
+    macro_rules! copy_settings_if_changed {
+        ($($setting:ident),* $(,)?) => {
+            $(changed |= copy_if_changed(
+                ui_state.$setting.into(),
+                &mut last.constraints.$setting,
+                &mut constraints.$setting,
+            );)*
+        };
+    }
+    copy_settings_if_changed!(
+        substeps,
+        solver_kind,
+        transfer_scheme,
+        advection_scheme,
+        pressure_solver,
+        pressure_tolerance,
+        gpu_transfers,
+        viscosity,
+        surface_tension,
+        interface_tension,
+        erosion_speed,
+        deposit_speed,
+        collision_restitution,
+        collision_friction,
+        wall_slip,
+        vorticity_confinement,
+        thermal_expansion,
+        evaporation_rate,
+        condensation,
+        secondary_particles,
+        secondary_spawn_rate,
+        reseeding,
+        min_particles_per_cell,
+        max_particles_per_cell,
+        adaptive_particles,
+        merge_speed,
+        split_shear,
+        max_particle_mass,
+        narrow_band,
+        narrow_band_width,
+        safeguard_response,
+        max_density_ratio,
+        max_particle_speed,
+        deterministic,
+        seed,
+        inflow_speed,
+        inflow_profile,
+    );
+    ui_state.solver_residual = constraints.solver_residual;
+
+    // Deterministic mode keeps the solver and transfers off of the GPU; show that it has.
+    if constraints.deterministic {
+        constraints.keep_deterministic();
+        ui_state.pressure_solver = constraints.pressure_solver as usize;
+        ui_state.gpu_transfers = constraints.gpu_transfers;
+    }
+
+    // Only touch the grid's edges when the user changes them, since that opens or closes the edge walls.
+    let wrapping: (bool, bool) = (ui_state.wrap_horizontal, ui_state.wrap_vertical);
+    if wrapping != last.wrapping {
+        last.wrapping = wrapping;
+        if (grid.wrap_horizontal, grid.wrap_vertical) != wrapping {
+            grid.set_wrapping(wrapping.0, wrapping.1);
+            changed = true;
+        }
+    }
+    for edge in SimGridEdge::ALL {
+        let boundary: SimEdgeBoundary = ui_state.edge_boundaries[edge as usize].into();
+        if boundary != last.edge_boundaries[edge as usize] {
+            last.edge_boundaries[edge as usize] = boundary;
+            if grid.edge_boundaries[edge as usize] != boundary {
+                grid.set_edge_boundary(edge, boundary);
+                changed = true;
+            }
+        }
+    }
+
+    /* Rotate/scale gravity when we press the arrow keys, then show the simulation's gravity on the
+    sliders; this allows for keyboard and UI slider control to work in tandem.  Zero gravity can't
+    be rotated or survive the trip through polar coordinates (which change_gravity keeps away from
+    zero), so it is left be. */
+    if constraints.gravity != Vec2::ZERO && (up_down != 0.0 || left_right != 0.0) {
+        change_gravity(constraints, up_down * 6.0, left_right);
+        changed = true;
+    }
+    ui_state.show_gravity(constraints.gravity);
+    last.constraints.gravity = ui_state.gravity();
+    changed
+}
+
 /// Handle all user input as it relates to the camera!
 pub fn handle_camera_input(
     mut constraints: ResMut<SimConstraints>,
@@ -159,6 +472,93 @@ pub fn handle_camera_input(
     );
 }
 
+/** Keep the camera on the fluid as it moves, if the Camera tool's follow mode asks for it: either on
+the centroid of every particle, or on one particle picked by clicking near it with the Camera tool.
+The camera eases towards its target rather than snapping to it. */
+pub fn follow_camera_target(
+    time: Res<Time>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut camera_transforms: Query<&mut Transform, With<Camera>>,
+    particles: Query<(Entity, &SimParticle), (Without<SimInactiveParticle>, SimMainDomain)>,
+    mut ui_state: ResMut<UIStateManager>,
+) {
+    let target: Option<Vec2> = match ui_state.camera_follow_mode {
+        1 => {
+            let particle_count: usize = particles.iter().len();
+            (particle_count > 0).then(|| {
+                particles
+                    .iter()
+                    .map(|(_, particle)| particle.position)
+                    .sum::<Vec2>()
+                    / particle_count as f32
+            })
+        }
+        2 => {
+            // Pick the particle nearest the cursor when the Camera tool clicks.
+            if ui_state.selected_tool == SimTool::Camera && mouse.just_pressed(MouseButton::Left) {
+                let cursor_position: Vec2 = get_cursor_position(&windows, &cameras);
+                if let Some((particle_id, _)) = particles
+                    .iter()
+                    .map(|(particle_id, particle)| {
+                        (particle_id, particle.position.distance(cursor_position))
+                    })
+                    .filter(|(_, distance)| *distance <= CAMERA_FOLLOW_PICK_RADIUS)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                {
+                    ui_state.followed_particle = Some(particle_id);
+                }
+            }
+
+            // Stop following particles that have left the simulation.
+            let followed: Option<Vec2> = ui_state
+                .followed_particle
+                .and_then(|particle_id| particles.get(particle_id).ok())
+                .map(|(_, particle)| particle.position);
+            if followed.is_none() {
+                ui_state.followed_particle = None;
+            }
+            followed
+        }
+        _ => None,
+    };
+    let Some(target) = target else {
+        return;
+    };
+
+    let blend: f32 = 1.0 - (-CAMERA_FOLLOW_RATE * time.delta_seconds()).exp();
+    for mut transform in camera_transforms.iter_mut() {
+        let position: Vec2 = transform.translation.truncate().lerp(target, blend);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+/** Tell the minimap which part of the world the camera can see, and move the camera to wherever the
+minimap was clicked (which stops it following anything). */
+pub fn update_minimap_camera(
+    mut cameras: Query<(&mut Transform, &OrthographicProjection), With<Camera>>,
+    mut ui_state: ResMut<UIStateManager>,
+) {
+    let Ok((mut transform, projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    if let Some(target) = ui_state.minimap_jump.take() {
+        transform.translation.x = target.x;
+        transform.translation.y = target.y;
+        ui_state.camera_follow_mode = 0;
+    }
+
+    let center: Vec2 = transform.translation.truncate();
+    let scale: Vec2 = transform.scale.truncate();
+    ui_state.camera_view = Rect::from_corners(
+        center + projection.area.min * scale,
+        center + projection.area.max * scale,
+    );
+}
+
 /// Handles incoming events from the UI
 pub fn change_cursor_icon(
     mut ev_reset: EventReader<ResetEvent>,
@@ -186,10 +586,22 @@ pub fn change_cursor_icon(
         SimTool::RemoveFluid => window.cursor.icon = CursorIcon::Hand,
         SimTool::AddWall => window.cursor.icon = CursorIcon::Hand,
         SimTool::RemoveWall => window.cursor.icon = CursorIcon::Hand,
+        SimTool::AddObstacle => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::AddPipe => window.cursor.icon = CursorIcon::Crosshair,
         SimTool::AddDrain => window.cursor.icon = CursorIcon::Hand,
         SimTool::RemoveDrain => window.cursor.icon = CursorIcon::Hand,
         SimTool::AddFaucet => window.cursor.icon = CursorIcon::Hand,
         SimTool::RemoveFaucet => window.cursor.icon = CursorIcon::Hand,
+        SimTool::Container => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::Spinner => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::Wind => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::Vortex => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::Attractor => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::AddPorousWall => window.cursor.icon = CursorIcon::Hand,
+        SimTool::AddValve => window.cursor.icon = CursorIcon::Hand,
+        SimTool::AddPump => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::Probe => window.cursor.icon = CursorIcon::Crosshair,
+        SimTool::FlowMeter => window.cursor.icon = CursorIcon::Crosshair,
     }
 
     // For tools that need an icon change when in use:
@@ -200,3 +612,44 @@ pub fn change_cursor_icon(
         }
     }
 }
+
+/** Save a screenshot of the window to a new file in the screenshot directory when F12 is pressed
+or the UI asks for one, and let the user know where it went. */
+pub fn capture_screenshot(
+    keys: Res<Input<KeyCode>>,
+    windows: Query<Entity, With<PrimaryWindow>>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    mut ui_state: ResMut<UIStateManager>,
+) {
+    if !keys.just_pressed(KeyCode::F12) && !ui_state.take_screenshot {
+        return;
+    }
+    ui_state.take_screenshot = false;
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+
+    let path: PathBuf = screenshot_file_path(Path::new(&ui_state.screenshot_directory));
+    ui_state.toast_message = match std::fs::create_dir_all(&ui_state.screenshot_directory)
+        .map_err(|error| error.to_string())
+        .and_then(|_| {
+            screenshot_manager
+                .save_screenshot_to_disk(window, &path)
+                .map_err(|error| error.to_string())
+        }) {
+        // The capture is only queued here; the file is written a frame or two later.
+        Ok(()) => format!("Saving screenshot to {}", path.display()),
+        Err(error) => format!("Couldn't save screenshot: {}", error),
+    };
+    ui_state.toast_seconds_left = 3.0;
+}
+
+/** A new, timestamped path to save a screenshot to in `directory`.  Stamped to the millisecond, so
+screenshots taken in quick succession don't overwrite each other. */
+pub fn screenshot_file_path(directory: &Path) -> PathBuf {
+    let milliseconds: u128 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    directory.join(format!("screenshot-{}.png", milliseconds))
+}
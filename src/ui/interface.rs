@@ -18,8 +18,27 @@ use egui::FontId;
 use egui::TextStyle::*;
 
 use crate::{
-    events::{ModifyVisualizationEvent, PlayPauseStepEvent},
+    events::{ClearEvent, ModifyVisualizationEvent, PlayPauseStepEvent, SequencerEvent},
     file_system::JuiceStates,
+    juice_renderer::GridHeatmapLegend,
+    simulation::{
+        sim_diagnostics::{SimConservedQuantity, SimDiagnostics, CONSERVED_QUANTITY_COUNT},
+        sim_flow_meter::SimFlowMeter,
+        sim_inflow::{SimInflowProfile, INFLOW_PROFILE_COUNT},
+        sim_obstacles::{SimObstacle, OBSTACLE_COUNT},
+        sim_pressure_solver::{SimPressureSolver, PRESSURE_SOLVER_COUNT},
+        sim_probes::{SimProbe, SimProbeQuantity, PROBE_QUANTITY_COUNT},
+        sim_safeguards::{SimSafeguardResponse, SAFEGUARD_RESPONSE_COUNT},
+        sim_sequencer::{SimKeyframe, SimSequencer, SimSequencerAction, SEQUENCER_ACTION_COUNT},
+        sim_sph::{SimSolverKind, SOLVER_KIND_COUNT},
+        sim_telemetry::{SimStageProfile, SimTelemetry},
+        SimAdvectionScheme, SimContainer, SimEdgeBoundary, SimFaucetShape, SimFluidMaterial,
+        SimGravityPreset, SimGrid, SimGridCellType, SimGridEdge, SimSurfaceDirection,
+        SimTransferScheme, SimWallMaterial, SimWallSlip, ADVECTION_SCHEME_COUNT,
+        EDGE_BOUNDARY_COUNT, FAUCET_SHAPE_COUNT, FLUID_MATERIAL_COUNT, GRAVITY_PRESET_COUNT,
+        PARTICLE_GROUP_COUNT, SURFACE_DIRECTION_COUNT, TRANSFER_SCHEME_COUNT, WALL_MATERIAL_COUNT,
+        WALL_SLIP_COUNT,
+    },
 };
 
 pub fn init_user_interface(
@@ -59,8 +78,17 @@ pub fn draw_user_interface(
     mut contexts: EguiContexts,
     mut ui_state: ResMut<UIStateManager>,
     windows: Query<&Window>,
+    containers: Query<&SimContainer>,
+    probes: Query<&SimProbe>,
+    flow_meters: Query<&SimFlowMeter>,
+    sequencer: Res<SimSequencer>,
+    diagnostics: Res<SimDiagnostics>,
+    telemetry: Res<SimTelemetry>,
+    grid: Res<SimGrid>,
+    heatmap_legend: Res<GridHeatmapLegend>,
     ev_viz: EventWriter<ModifyVisualizationEvent>,
     ev_pause: EventWriter<PlayPauseStepEvent>,
+    ev_sequencer: EventWriter<SequencerEvent>,
 ) {
     // Make sure the UI is aware of the window size so we can grow/shrink when needed.
     calculate_window_parameters(&mut ui_state, &mut contexts, windows.single());
@@ -74,11 +102,861 @@ pub fn draw_user_interface(
         show_current_tool_menu(&mut ui_state, &mut contexts);
     }
     if ui_state.show_visualization {
-        show_visualization_menu(&mut ui_state, &mut contexts, ev_viz);
+        show_visualization_menu(&mut ui_state, &mut contexts, &heatmap_legend, ev_viz);
     }
     if ui_state.show_informational {
         show_informational_menu(&mut ui_state, &mut contexts);
     }
+    if !containers.is_empty() {
+        show_container_gauges(&mut ui_state, &mut contexts, &containers);
+    }
+    if !probes.is_empty() {
+        show_probe_readings(&mut ui_state, &mut contexts, &probes);
+    }
+    if !flow_meters.is_empty() {
+        show_flow_meter_readouts(&mut ui_state, &mut contexts, &flow_meters);
+    }
+    if ui_state.show_simulation_settings {
+        show_simulation_settings_menu(&mut ui_state, &mut contexts);
+    }
+    if ui_state.show_diagnostics {
+        show_conservation_diagnostics(&mut ui_state, &mut contexts, &diagnostics);
+    }
+    if ui_state.show_profiler {
+        show_performance_profile(&mut ui_state, &mut contexts, &telemetry.profile);
+    }
+    if ui_state.show_minimap {
+        show_minimap(&mut ui_state, &mut contexts, &grid);
+    }
+    if ui_state.show_sequencer {
+        show_sequencer_menu(&mut ui_state, &mut contexts, &sequencer, ev_sequencer);
+    }
+    if ui_state.toast_seconds_left > 0.0 {
+        show_toast(&mut ui_state, &mut contexts);
+    }
+}
+
+/** Create the scene timeline menu, which lists the scene's keyframes and lets the user schedule new
+ones.  Positions and vectors are in simulation coordinates. */
+fn show_sequencer_menu(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    sequencer: &SimSequencer,
+    mut ev_sequencer: EventWriter<SequencerEvent>,
+) {
+    egui::Window::new("Sequencer")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::LEFT_BOTTOM)
+        .default_pos(Pos2 {
+            x: 0.0,
+            y: ui_state.window_size.y,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("Time: {:.1}s", sequencer.time()));
+            ui.separator();
+
+            // Scheduled keyframes, in the order they will fire.
+            if sequencer.keyframes.is_empty() {
+                ui.label("No keyframes yet!");
+            }
+            for (i, keyframe) in sequencer.keyframes.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let is_done: bool = keyframe.time <= sequencer.time();
+                    let text: String =
+                        format!("{:.1}s: {}", keyframe.time, keyframe.action.describe());
+                    if is_done {
+                        ui.weak(text);
+                    } else {
+                        ui.label(text);
+                    }
+                    if ui.small_button("Remove").clicked() {
+                        ev_sequencer.send(SequencerEvent::RemoveKeyframe(i));
+                    }
+                });
+            }
+            ui.separator();
+
+            // New keyframe settings.
+            ui.add(
+                egui::DragValue::new(&mut ui_state.sequencer_time)
+                    .clamp_range(0.0..=600.0)
+                    .speed(0.1)
+                    .suffix("s"),
+            );
+            egui::ComboBox::from_id_source("sequencer_action").show_index(
+                ui,
+                &mut ui_state.sequencer_action,
+                SEQUENCER_ACTION_COUNT,
+                |i| SimSequencerAction::from_index(i).as_str().to_owned(),
+            );
+
+            let point = ui_state.sequencer_point;
+            let other_point = ui_state.sequencer_other_point;
+            let action: SimSequencerAction =
+                match SimSequencerAction::from_index(ui_state.sequencer_action) {
+                    SimSequencerAction::AddFaucet { .. } => {
+                        show_vector_editor(ui, "Position", &mut ui_state.sequencer_point);
+                        show_vector_editor(ui, "Velocity", &mut ui_state.sequencer_other_point);
+                        SimSequencerAction::AddFaucet {
+                            position: point,
+                            velocity: other_point,
+                        }
+                    }
+                    SimSequencerAction::OpenWalls { .. } => {
+                        show_vector_editor(ui, "Corner", &mut ui_state.sequencer_point);
+                        show_vector_editor(
+                            ui,
+                            "Opposite Corner",
+                            &mut ui_state.sequencer_other_point,
+                        );
+                        SimSequencerAction::OpenWalls {
+                            corner: point,
+                            opposite_corner: other_point,
+                        }
+                    }
+                    SimSequencerAction::SetGravity(_) => {
+                        show_vector_editor(ui, "Gravity", &mut ui_state.sequencer_point);
+                        SimSequencerAction::SetGravity(point)
+                    }
+                    action => action,
+                };
+
+            if ui.button("Add Keyframe").clicked() {
+                ev_sequencer.send(SequencerEvent::AddKeyframe(SimKeyframe {
+                    time: ui_state.sequencer_time,
+                    action,
+                }));
+            }
+        });
+}
+
+/// Show a labeled pair of drag values for editing a vector.
+fn show_vector_editor(ui: &mut Ui, label: &str, vector: &mut bevy::math::Vec2) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(egui::DragValue::new(&mut vector.x).prefix("x: "));
+        ui.add(egui::DragValue::new(&mut vector.y).prefix("y: "));
+    });
+}
+
+/// Show a dropdown for picking one of the particle groups.
+fn show_group_picker(ui: &mut Ui, label: &str, group: &mut usize) {
+    egui::ComboBox::from_label(label).show_index(ui, group, PARTICLE_GROUP_COUNT, |i| {
+        format!("Group {}", i + 1)
+    });
+}
+
+/// Show a dropdown for picking what kind of fluid to add.
+fn show_fluid_material_picker(ui: &mut Ui, material: &mut usize) {
+    egui::ComboBox::from_label("Material").show_index(ui, material, FLUID_MATERIAL_COUNT, |i| {
+        Into::<SimFluidMaterial>::into(i).as_str().to_owned()
+    });
+}
+
+/// Create the menu for tuning how the simulation itself behaves.
+fn show_simulation_settings_menu(ui_state: &mut UIStateManager, contexts: &mut EguiContexts) {
+    egui::Window::new("Simulation Settings")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::LEFT_CENTER)
+        .default_pos(Pos2 {
+            x: 0.0,
+            y: ui_state.window_size.y / 2.0,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            /* How hard the solver works; more substeps stop fast fluid from tunneling through
+            thin walls, but cost a whole solver step each. */
+            ui.label("Solver");
+            ui.add(egui::Slider::new(&mut ui_state.substeps, 1..=8).text("Substeps"));
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Method:");
+                egui::ComboBox::from_id_source("solver_kind").show_index(
+                    ui,
+                    &mut ui_state.solver_kind,
+                    SOLVER_KIND_COUNT,
+                    |i| {
+                        let solver_kind: SimSolverKind = i.into();
+                        solver_kind.as_str().to_owned()
+                    },
+                );
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Transfer:");
+                egui::ComboBox::from_id_source("transfer_scheme").show_index(
+                    ui,
+                    &mut ui_state.transfer_scheme,
+                    TRANSFER_SCHEME_COUNT,
+                    |i| {
+                        let scheme: SimTransferScheme = i.into();
+                        scheme.as_str().to_owned()
+                    },
+                );
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Advection:");
+                egui::ComboBox::from_id_source("advection_scheme").show_index(
+                    ui,
+                    &mut ui_state.advection_scheme,
+                    ADVECTION_SCHEME_COUNT,
+                    |i| {
+                        let scheme: SimAdvectionScheme = i.into();
+                        scheme.as_str().to_owned()
+                    },
+                );
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Pressure:");
+                egui::ComboBox::from_id_source("pressure_solver").show_index(
+                    ui,
+                    &mut ui_state.pressure_solver,
+                    PRESSURE_SOLVER_COUNT,
+                    |i| {
+                        let solver: SimPressureSolver = i.into();
+                        solver.as_str().to_owned()
+                    },
+                );
+            });
+
+            // The solver stops early once the divergence is this small a fraction of where it began.
+            ui.add(
+                egui::Slider::new(&mut ui_state.pressure_tolerance, 1e-6..=1e-1)
+                    .logarithmic(true)
+                    .text("Solver Tolerance"),
+            );
+            let residual = ui_state.solver_residual;
+            ui.label(format!(
+                "Residual: max {:.4}, RMS {:.4} ({} iterations)",
+                residual.max_divergence, residual.rms_divergence, residual.iterations
+            ));
+
+            // Move velocities between particles and the grid on the GPU; the CPU steps in without one.
+            ui.checkbox(&mut ui_state.gpu_transfers, "GPU Particle Transfers");
+
+            // Let fluid leaving one edge of the grid come back in from the opposite edge.
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut ui_state.wrap_horizontal, "Wrap Left/Right");
+                ui.checkbox(&mut ui_state.wrap_vertical, "Wrap Top/Bottom");
+            });
+
+            // Walled edges keep fluid in, open ones let it flow off of the grid, and inflows feed it.
+            for edge in SimGridEdge::ALL {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(format!("{} Edge:", edge.as_str()));
+                    egui::ComboBox::from_id_source(("edge_boundary", edge as usize)).show_index(
+                        ui,
+                        &mut ui_state.edge_boundaries[edge as usize],
+                        EDGE_BOUNDARY_COUNT,
+                        |i| {
+                            let boundary: SimEdgeBoundary = i.into();
+                            boundary.as_str().to_owned()
+                        },
+                    );
+                });
+            }
+
+            // Inflow edges push fresh fluid into the grid.
+            let inflow_edge: usize = SimEdgeBoundary::Inflow as usize;
+            if ui_state.edge_boundaries.contains(&inflow_edge) {
+                ui.add(
+                    egui::Slider::new(&mut ui_state.inflow_speed, 0.0..=500.0).text("Inflow Speed"),
+                );
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Inflow Profile:");
+                    egui::ComboBox::from_id_source("inflow_profile").show_index(
+                        ui,
+                        &mut ui_state.inflow_profile,
+                        INFLOW_PROFILE_COUNT,
+                        |i| {
+                            let profile: SimInflowProfile = i.into();
+                            profile.as_str().to_owned()
+                        },
+                    );
+                });
+            }
+
+            // Keep every fluid cell's particle count in range, so the fluid has no holes or clumps.
+            ui.checkbox(&mut ui_state.reseeding, "Reseed Particles");
+            if ui_state.reseeding {
+                ui.add(
+                    egui::Slider::new(&mut ui_state.min_particles_per_cell, 0..=8)
+                        .text("Min Particles/Cell"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut ui_state.max_particles_per_cell, 1..=16)
+                        .text("Max Particles/Cell"),
+                );
+                ui_state.max_particles_per_cell = ui_state
+                    .max_particles_per_cell
+                    .max(ui_state.min_particles_per_cell);
+            }
+
+            // Merge particles in calm fluid to save time, and split them where the fluid churns.
+            ui.checkbox(&mut ui_state.adaptive_particles, "Adaptive Particles");
+            if ui_state.adaptive_particles {
+                ui.add(
+                    egui::Slider::new(&mut ui_state.merge_speed, 0.0..=200.0).text("Merge Speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut ui_state.split_shear, 1.0..=100.0)
+                        .logarithmic(true)
+                        .text("Split Shear"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut ui_state.max_particle_mass, 2.0..=16.0)
+                        .text("Max Particle Mass"),
+                );
+            }
+
+            // Leave the fluid deep below the surface to the grid, so big pools need fewer particles.
+            ui.checkbox(&mut ui_state.narrow_band, "Narrow-Band FLIP");
+            if ui_state.narrow_band {
+                ui.add(
+                    egui::Slider::new(&mut ui_state.narrow_band_width, 1..=8)
+                        .text("Band Width (Cells)"),
+                );
+            }
+
+            ui.separator();
+
+            // How the fluid itself behaves; thick fluids like honey have a high viscosity.
+            ui.label("Fluid");
+            ui.add(
+                egui::Slider::new(&mut ui_state.viscosity, 0.0..=2000.0)
+                    .logarithmic(true)
+                    .text("Viscosity"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.surface_tension, 0.0..=2000.0)
+                    .logarithmic(true)
+                    .text("Surface Tension"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.interface_tension, 0.0..=2000.0)
+                    .logarithmic(true)
+                    .text("Interface Tension"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.vorticity_confinement, 0.0..=20.0)
+                    .text("Vorticity Confinement"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.thermal_expansion, 0.0..=0.05)
+                    .text("Thermal Buoyancy"),
+            );
+
+            // A water cycle for long-running scenes; surface fluid evaporates and rains back down.
+            ui.add(
+                egui::Slider::new(&mut ui_state.evaporation_rate, 0.0..=100.0)
+                    .text("Evaporation (particles/s)"),
+            );
+            ui.checkbox(&mut ui_state.condensation, "Rain (Condensation)");
+
+            // Purely visual foam, spray, and bubbles thrown off wherever the fluid churns.
+            ui.checkbox(&mut ui_state.secondary_particles, "Foam, Spray & Bubbles");
+            if ui_state.secondary_particles {
+                ui.add(
+                    egui::Slider::new(&mut ui_state.secondary_spawn_rate, 1.0..=200.0)
+                        .logarithmic(true)
+                        .text("Foam Amount"),
+                );
+            }
+
+            // Sand walls wear away under fast flow; sediment settles back into sand where it's calm.
+            ui.add(
+                egui::Slider::new(&mut ui_state.erosion_speed, 10.0..=1000.0)
+                    .logarithmic(true)
+                    .text("Erosion Speed"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.deposit_speed, 0.0..=200.0).text("Deposit Speed"),
+            );
+
+            // Whether walls hold back fluid sliding along them (unless they have a say of their own).
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Walls:");
+                egui::ComboBox::from_id_source("wall_slip").show_index(
+                    ui,
+                    &mut ui_state.wall_slip,
+                    WALL_SLIP_COUNT,
+                    |i| {
+                        let slip: SimWallSlip = i.into();
+                        slip.as_str().to_owned()
+                    },
+                );
+            });
+
+            // How particles bounce off of walls (on top of their materials) and off of each other.
+            ui.add(
+                egui::Slider::new(&mut ui_state.collision_restitution, 0.0..=1.0)
+                    .text("Collision Bounciness"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.collision_friction, 0.0..=1.0)
+                    .text("Collision Friction"),
+            );
+
+            ui.separator();
+
+            // Stability safeguards; how they respond, and what they consider unstable.
+            ui.label("Stability Safeguards");
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Response:");
+                egui::ComboBox::from_id_source("safeguard_response").show_index(
+                    ui,
+                    &mut ui_state.safeguard_response,
+                    SAFEGUARD_RESPONSE_COUNT,
+                    |i| {
+                        let response: SimSafeguardResponse = i.into();
+                        response.as_str().to_owned()
+                    },
+                );
+            });
+            ui.add(
+                egui::Slider::new(&mut ui_state.max_density_ratio, 1.5..=10.0)
+                    .text("Max Density (x Average)"),
+            );
+            ui.add(
+                egui::Slider::new(&mut ui_state.max_particle_speed, 100.0..=5000.0)
+                    .text("Max Particle Speed"),
+            );
+
+            ui.separator();
+
+            // Deterministic runs replay exactly; the seed is picked up whenever the scene resets.
+            ui.checkbox(&mut ui_state.deterministic, "Deterministic");
+            if ui_state.deterministic {
+                ui.horizontal(|ui| {
+                    ui.label("Seed:");
+                    ui.add(egui::DragValue::new(&mut ui_state.seed))
+                        .on_hover_text("Takes effect the next time the scene is reset.");
+                });
+            }
+
+            // Stream per-step measurements to a file for offline analysis.
+            ui.checkbox(&mut ui_state.record_telemetry, "Record Telemetry");
+            ui.checkbox(
+                &mut ui_state.show_diagnostics,
+                "Show Conservation Diagnostics",
+            );
+            ui.checkbox(&mut ui_state.show_profiler, "Show Performance");
+
+            ui.separator();
+
+            // Extra domains run side by side with this one, starting out with its current settings.
+            ui.label(format!("Extra Domains: {}", ui_state.domain_count));
+            ui.horizontal(|ui| {
+                if ui.button("Add Domain").clicked() {
+                    ui_state.add_domain = true;
+                }
+                if ui.button("Remove Domains").clicked() {
+                    ui_state.clear_domains = true;
+                }
+            });
+        });
+}
+
+/// Briefly show a warning message just above the play/pause button.
+fn show_toast(ui_state: &mut UIStateManager, contexts: &mut EguiContexts) {
+    egui::Window::new("Toast")
+        .title_bar(false)
+        .frame(ui_state.window_frame)
+        .fixed_pos(Pos2 {
+            x: ui_state.window_size.x / 2.0,
+            y: ui_state.window_size.y * 0.87,
+        })
+        .pivot(Align2::CENTER_CENTER)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.colored_label(Color32::GOLD, &ui_state.toast_message);
+        });
+}
+
+/// Show a fill gauge for each container the user has marked in the simulation.
+fn show_container_gauges(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    containers: &Query<&SimContainer>,
+) {
+    egui::Window::new("Containers")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::RIGHT_TOP)
+        .default_pos(Pos2 {
+            x: ui_state.window_size.x,
+            y: 0.0,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            for (i, container) in containers.iter().enumerate() {
+                let fill_level: f32 = container.fill_level();
+                ui.label(format!("Container {}", i + 1));
+                ui.add(egui::ProgressBar::new(fill_level.min(1.0)).text(format!(
+                    "{:.0}% ({} particles)",
+                    fill_level * 100.0,
+                    container.particle_count
+                )));
+            }
+        });
+}
+
+/// Show how fast fluid is crossing each flow meter, and how much has crossed it in total.
+fn show_flow_meter_readouts(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    flow_meters: &Query<&SimFlowMeter>,
+) {
+    egui::Window::new("Flow Meters")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::RIGHT_CENTER)
+        .default_pos(Pos2 {
+            x: ui_state.window_size.x,
+            y: ui_state.window_size.y / 2.0,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            for (i, flow_meter) in flow_meters.iter().enumerate() {
+                ui.label(format!("Flow Meter {}", i + 1));
+                ui.label(format!("Flow: {:.2} cells/s", flow_meter.flow_rate));
+                ui.label(format!("Total: {:.1} cells", flow_meter.total_flow));
+            }
+        });
+}
+
+/** Show each probe's latest readings alongside a small plot of its recent history, and let the user
+export every probe's samples to CSV. */
+fn show_probe_readings(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    probes: &Query<&SimProbe>,
+) {
+    egui::Window::new("Probes")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::RIGHT_BOTTOM)
+        .default_pos(Pos2 {
+            x: ui_state.window_size.x,
+            y: ui_state.window_size.y,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ComboBox::from_label("Plot").show_index(
+                ui,
+                &mut ui_state.probe_quantity,
+                PROBE_QUANTITY_COUNT,
+                |i| Into::<SimProbeQuantity>::into(i).as_str().to_owned(),
+            );
+            let quantity: SimProbeQuantity = ui_state.probe_quantity.into();
+
+            for (i, probe) in probes.iter().enumerate() {
+                ui.separator();
+                let Some(latest) = probe.latest() else {
+                    ui.label(format!("Probe {}: no samples yet", i + 1));
+                    continue;
+                };
+                ui.label(format!(
+                    "Probe {}: p {:.2}, div {:.2}, v ({:.1}, {:.1})",
+                    i + 1,
+                    latest.pressure,
+                    latest.divergence,
+                    latest.velocity.x,
+                    latest.velocity.y
+                ));
+                let values: Vec<f32> = probe.samples.iter().map(|s| s.get(quantity)).collect();
+                show_line_plot(ui, &values);
+            }
+
+            ui.separator();
+            if ui.button("Export CSV").clicked() {
+                ui_state.export_probes = true;
+            }
+        });
+}
+
+/** Show the fluid's current particle count, mass, momentum, and kinetic energy, how far each has
+drifted since the scene started, and a plot of one of them over time. */
+fn show_conservation_diagnostics(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    diagnostics: &SimDiagnostics,
+) {
+    egui::Window::new("Conservation")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::RIGHT_TOP)
+        .default_pos(Pos2 {
+            x: ui_state.window_size.x,
+            y: 0.0,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let Some(latest) = diagnostics.latest() else {
+                ui.label("No samples yet");
+                return;
+            };
+            for i in 0..CONSERVED_QUANTITY_COUNT {
+                let quantity: SimConservedQuantity = i.into();
+                ui.label(format!(
+                    "{}: {:.2} (drift {:+.2})",
+                    quantity.as_str(),
+                    latest.get(quantity),
+                    diagnostics.drift(quantity)
+                ));
+            }
+
+            ui.separator();
+            egui::ComboBox::from_label("Plot").show_index(
+                ui,
+                &mut ui_state.diagnostics_quantity,
+                CONSERVED_QUANTITY_COUNT,
+                |i| Into::<SimConservedQuantity>::into(i).as_str().to_owned(),
+            );
+            let quantity: SimConservedQuantity = ui_state.diagnostics_quantity.into();
+            let values: Vec<f32> = diagnostics
+                .samples
+                .iter()
+                .map(|s| s.get(quantity))
+                .collect();
+            show_line_plot(ui, &values);
+        });
+}
+
+/** Show how long each stage of a simulation step takes on average, as a bar chart, along with how
+long the whole step takes. */
+fn show_performance_profile(
+    ui_state: &mut UIStateManager,
+    contexts: &mut EguiContexts,
+    profile: &SimStageProfile,
+) {
+    egui::Window::new("Performance")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::RIGHT_BOTTOM)
+        .default_pos(Pos2 {
+            x: ui_state.window_size.x,
+            y: ui_state.window_size.y,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            if profile.stages.is_empty() {
+                ui.label("No steps yet");
+                return;
+            }
+            ui.label(format!("Step: {:.2} ms", profile.total()));
+            ui.separator();
+
+            // Bars are scaled so the slowest stage fills the chart.
+            let slowest: f32 = profile
+                .stages
+                .iter()
+                .map(|(_, average)| *average)
+                .fold(f32::EPSILON, f32::max);
+            for (stage, average) in profile.stages.iter() {
+                let (response, painter) =
+                    ui.allocate_painter(Vec2::new(320.0, 16.0), egui::Sense::hover());
+                let rect: egui::Rect = response.rect;
+                let bar_area: egui::Rect =
+                    egui::Rect::from_min_max(rect.center_top(), rect.right_bottom());
+                let bar: egui::Rect = egui::Rect::from_min_size(
+                    bar_area.left_top(),
+                    Vec2::new(bar_area.width() * average / slowest, bar_area.height()),
+                );
+                painter.rect_filled(bar, 0.0, Color32::LIGHT_BLUE);
+                painter.text(
+                    rect.left_center(),
+                    Align2::LEFT_CENTER,
+                    stage,
+                    egui::FontId::proportional(12.0),
+                    Color32::WHITE,
+                );
+                painter.text(
+                    bar_area.left_center(),
+                    Align2::LEFT_CENTER,
+                    format!("{:.2} ms", average),
+                    egui::FontId::proportional(12.0),
+                    Color32::BLACK,
+                );
+            }
+        });
+}
+
+/** Draw the grid heatmap's legend: a bar fading from one end of its color range to the other, with
+the values at either end and in the middle labeled underneath. */
+fn show_heatmap_legend(ui: &mut Ui, legend: &GridHeatmapLegend) {
+    ui.label(legend.title);
+    let (response, painter) = ui.allocate_painter(Vec2::new(180.0, 12.0), egui::Sense::hover());
+    let rect: egui::Rect = response.rect;
+    let to_color32 = |color: bevy::render::color::Color, alpha: f32| -> Color32 {
+        let [red, green, blue, _] = color.as_rgba_u8();
+        Color32::from_rgba_unmultiplied(red, green, blue, (alpha * 255.0) as u8)
+    };
+
+    // The heatmap fades to clear in the middle, so the bar does too.
+    let slice_count: usize = 32;
+    let slice_width: f32 = rect.width() / slice_count as f32;
+    for slice in 0..slice_count {
+        let offset: f32 = (slice as f32 + 0.5) / slice_count as f32 * 2.0 - 1.0;
+        let color: Color32 = if offset < 0.0 {
+            to_color32(legend.colors[0], -offset)
+        } else {
+            to_color32(legend.colors[1], offset)
+        };
+        let slice_rect: egui::Rect = egui::Rect::from_min_size(
+            rect.left_top() + Vec2::new(slice as f32 * slice_width, 0.0),
+            Vec2::new(slice_width, rect.height()),
+        );
+        painter.rect_filled(slice_rect, 0.0, color);
+    }
+    painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, Color32::DARK_GRAY));
+
+    let (min, max) = legend.range;
+    ui.horizontal(|ui| {
+        ui.set_width(rect.width());
+        ui.label(format!("{:.2}", min));
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            ui.label(format!("{:.2}", max));
+            ui.centered_and_justified(|ui| ui.label(format!("{:.2}", (min + max) / 2.0)));
+        });
+    });
+}
+
+/// Most blocks of cells drawn across (or down) the minimap; bigger grids are drawn more coarsely.
+const MINIMAP_MAX_BLOCKS: usize = 64;
+
+/** A small map of the whole grid: its walls, roughly where the fluid is, and the part of it the camera
+can see.  Clicking (or dragging) on the map moves the camera there. */
+fn show_minimap(ui_state: &mut UIStateManager, contexts: &mut EguiContexts, grid: &SimGrid) {
+    let (rows, cols) = (grid.dimensions.0 as usize, grid.dimensions.1 as usize);
+    let grid_size: Vec2 = Vec2::new(cols as f32, rows as f32) * grid.cell_size as f32;
+    if rows == 0 || cols == 0 {
+        return;
+    }
+
+    egui::Window::new("Minimap")
+        .frame(ui_state.window_frame)
+        .pivot(Align2::LEFT_BOTTOM)
+        .default_pos(Pos2 {
+            x: 0.0,
+            y: ui_state.window_size.y,
+        })
+        .default_width(0.0)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            // Keep the grid's shape, fitting it within a 160 point square.
+            let map_scale: f32 = 160.0 / grid_size.x.max(grid_size.y);
+            let (response, painter) =
+                ui.allocate_painter(grid_size * map_scale, egui::Sense::click_and_drag());
+            let rect: egui::Rect = response.rect;
+            painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+
+            // Draw the grid in square blocks of cells, so huge grids don't take forever.
+            let block_size: usize = (rows.max(cols) + MINIMAP_MAX_BLOCKS - 1) / MINIMAP_MAX_BLOCKS;
+            let block_extent: f32 = block_size as f32 * grid.cell_size as f32 * map_scale;
+            for block_row in (0..rows).step_by(block_size) {
+                for block_col in (0..cols).step_by(block_size) {
+                    let (mut fluid_cells, mut solid_cells, mut cells) = (0, 0, 0);
+                    for row in block_row..(block_row + block_size).min(rows) {
+                        for col in block_col..(block_col + block_size).min(cols) {
+                            match grid.cell_type[row][col] {
+                                SimGridCellType::Fluid => fluid_cells += 1,
+                                SimGridCellType::Solid | SimGridCellType::Porous(_) => {
+                                    solid_cells += 1
+                                }
+                                SimGridCellType::Air => {}
+                            }
+                            cells += 1;
+                        }
+                    }
+                    let color: Color32 = if solid_cells * 2 > cells {
+                        Color32::GOLD
+                    } else if fluid_cells > 0 {
+                        let fullness: f32 = fluid_cells as f32 / cells as f32;
+                        Color32::from_rgba_unmultiplied(
+                            40,
+                            120,
+                            255,
+                            (80.0 + 175.0 * fullness) as u8,
+                        )
+                    } else {
+                        continue;
+                    };
+                    let block_min: Pos2 = rect.left_top()
+                        + Vec2::new(block_col as f32, block_row as f32)
+                            * grid.cell_size as f32
+                            * map_scale;
+                    let block_rect: egui::Rect =
+                        egui::Rect::from_min_size(block_min, Vec2::splat(block_extent))
+                            .intersect(rect);
+                    painter.rect_filled(block_rect, 0.0, color);
+                }
+            }
+
+            // Outline what the camera can see; the world's y axis points up, the map's down.
+            let to_map = |x: f32, y: f32| -> Pos2 {
+                rect.left_top() + Vec2::new(x, grid_size.y - y) * map_scale
+            };
+            let view = ui_state.camera_view;
+            let view_rect: egui::Rect = egui::Rect::from_two_pos(
+                to_map(view.min.x, view.min.y),
+                to_map(view.max.x, view.max.y),
+            );
+            painter.rect_stroke(view_rect, 0.0, egui::Stroke::new(1.0, Color32::WHITE));
+
+            if response.clicked() || response.dragged() {
+                if let Some(pointer) = response.interact_pointer_pos() {
+                    let offset: Vec2 = (pointer - rect.left_top()) / map_scale;
+                    ui_state.minimap_jump =
+                        Some(bevy::math::Vec2::new(offset.x, grid_size.y - offset.y));
+                }
+            }
+        });
+}
+
+/// Draw `values` as a simple line plot, scaled to fit between their smallest and largest values.
+fn show_line_plot(ui: &mut Ui, values: &[f32]) {
+    let (response, painter) = ui.allocate_painter(Vec2::new(240.0, 60.0), egui::Sense::hover());
+    let rect: egui::Rect = response.rect;
+    painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, Color32::DARK_GRAY));
+    if values.len() < 2 {
+        return;
+    }
+
+    let min: f32 = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max: f32 = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range: f32 = (max - min).max(f32::EPSILON);
+    let points: Vec<Pos2> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let x: f32 = i as f32 / (values.len() - 1) as f32;
+            let y: f32 = (value - min) / range;
+            Pos2::new(
+                rect.left() + x * rect.width(),
+                rect.bottom() - y * rect.height(),
+            )
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, Color32::LIGHT_BLUE),
+    ));
+    painter.text(
+        rect.left_top(),
+        Align2::LEFT_TOP,
+        format!("{:.2}", max),
+        egui::FontId::proportional(12.0),
+        Color32::GRAY,
+    );
+    painter.text(
+        rect.left_bottom(),
+        Align2::LEFT_BOTTOM,
+        format!("{:.2}", min),
+        egui::FontId::proportional(12.0),
+        Color32::GRAY,
+    );
 }
 
 /// Create the "splash" menu that appears once when the program is started.
@@ -117,6 +995,8 @@ fn show_informational_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCon
                 ui.end_row();
                 ui.label(" • F (Tap) - Step through the simulation!");
                 ui.end_row();
+                ui.label(" • F6 & F7 - Quick save/quick load.");
+                ui.end_row();
 
                 ui.vertical_centered(|ui| {
                     ui.add_visible(false, egui::Separator::default());
@@ -172,7 +1052,15 @@ fn show_scene_manager_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCon
 fn show_file_manager_panel(ui_state: &mut UIStateManager, ui: &mut Ui) {
     ui.horizontal_wrapped(|ui| {
         // "File" scene saving/loading dropdown.
-        let file_options = ["File", "New", "Load", "Save", "Save as"];
+        let file_options = [
+            "File",
+            "New",
+            "Load",
+            "Save",
+            "Save as",
+            "Quick save",
+            "Quick load",
+        ];
         let mut file_selection = 0;
         egui::ComboBox::from_id_source(0).show_index(
             ui,
@@ -186,11 +1074,24 @@ fn show_file_manager_panel(ui_state: &mut UIStateManager, ui: &mut Ui) {
             2 => ui_state.file_state = JuiceStates::Loading,
             3 => ui_state.file_state = JuiceStates::Saving,
             4 => ui_state.file_state = JuiceStates::SavingAs,
+            5 => ui_state.file_state = JuiceStates::QuickSaving,
+            6 => ui_state.file_state = JuiceStates::QuickLoading,
             _ => {}
         }
 
+        // Name of the slot quick saves go into.
+        ui.add(egui::TextEdit::singleline(&mut ui_state.quick_save_slot).desired_width(80.0))
+            .on_hover_text("Quick save slot");
+
         // "Edit" scene dropdown.
-        let edit_options = ["Edit", "Reload", "Clear"];
+        let edit_options = [
+            "Edit",
+            "Reload",
+            "Clear all",
+            "Clear fluid",
+            "Clear walls",
+            "Clear faucets, drains & forces",
+        ];
         let mut edit_selection = 0;
         egui::ComboBox::from_id_source(1).show_index(
             ui,
@@ -201,12 +1102,22 @@ fn show_file_manager_panel(ui_state: &mut UIStateManager, ui: &mut Ui) {
         // Do stuff when selection changes.
         match edit_selection {
             1 => ui_state.file_state = JuiceStates::Reloading,
-            2 => { ui_state.clear = true }
+            2 => ui_state.clear = Some(ClearEvent::everything()),
+            3 => ui_state.clear = Some(ClearEvent::particles_only()),
+            4 => ui_state.clear = Some(ClearEvent::walls_only()),
+            5 => ui_state.clear = Some(ClearEvent::emitters_only()),
             _ => {}
         }
 
         // "View" scene dropdown.
-        let view_options = ["View", "Tool", "Visuals", "Controls"];
+        let view_options = [
+            "View",
+            "Tool",
+            "Visuals",
+            "Simulation",
+            "Sequencer",
+            "Controls",
+        ];
         let mut view_selection = 0;
         egui::ComboBox::from_id_source(2).show_index(
             ui,
@@ -218,7 +1129,9 @@ fn show_file_manager_panel(ui_state: &mut UIStateManager, ui: &mut Ui) {
         match view_selection {
             1 => ui_state.show_selected_tool = !ui_state.show_selected_tool,
             2 => ui_state.show_visualization = !ui_state.show_visualization,
-            3 => ui_state.show_informational = !ui_state.show_informational,
+            3 => ui_state.show_simulation_settings = !ui_state.show_simulation_settings,
+            4 => ui_state.show_sequencer = !ui_state.show_sequencer,
+            5 => ui_state.show_informational = !ui_state.show_informational,
             _ => {}
         }
 
@@ -295,6 +1208,25 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                     // For the Move Camera tool, show a slider for the grabbing radius.
                     SimTool::Camera => {
                         ui.label("Click and drag (or use WASD) to move the camera around!");
+
+                        // Camera follow mode dropdown.
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Follow:");
+                            let follow_options = ["Nothing", "Fluid", "Clicked Particle"];
+                            egui::ComboBox::from_id_source("camera_follow_mode").show_index(
+                                ui,
+                                &mut ui_state.camera_follow_mode,
+                                follow_options.len(),
+                                |i| follow_options[i].to_owned(),
+                            );
+                        });
+                        if ui_state.camera_follow_mode == 2 {
+                            ui.label(if ui_state.followed_particle.is_some() {
+                                "Following a particle; click another to switch."
+                            } else {
+                                "Click a particle to follow it."
+                            });
+                        }
                     }
 
                     // For the Zoom tool, show a slider for the zooming radius.
@@ -321,6 +1253,38 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                             egui::Slider::new(&mut ui_state.gravity_magnitude, 0.0001..=20.0)
                                 .text("Gravity Strength"),
                         );
+                        ui.checkbox(&mut ui_state.zero_gravity, "Zero Gravity");
+
+                        // Presets set the strength of gravity, but leave its direction alone.
+                        let strength: f32 = ui_state.gravity_magnitude * ui_state.gravity_magnitude * 4.0;
+                        let current_preset: Option<SimGravityPreset> = if ui_state.zero_gravity {
+                            Some(SimGravityPreset::ZeroG)
+                        } else {
+                            SimGravityPreset::from_strength(strength)
+                        };
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Preset:");
+                            egui::ComboBox::from_id_source("gravity_preset")
+                                .selected_text(current_preset.map_or("Custom", |preset| preset.as_str()))
+                                .show_ui(ui, |ui| {
+                                    for index in 0..GRAVITY_PRESET_COUNT {
+                                        let preset: SimGravityPreset = index.into();
+                                        if !ui
+                                            .selectable_label(current_preset == Some(preset), preset.as_str())
+                                            .clicked()
+                                        {
+                                            continue;
+                                        }
+
+                                        ui_state.zero_gravity = preset == SimGravityPreset::ZeroG;
+                                        if !ui_state.zero_gravity {
+                                            ui_state.gravity_magnitude = (preset.strength() / 4.0).sqrt();
+                                        }
+                                        ui_state.surface_tension =
+                                            ui_state.surface_tension.max(preset.min_surface_tension());
+                                    }
+                                });
+                        });
                     }
 
                     // For the Grab tool, show a slider for the grabbing radius.
@@ -329,6 +1293,12 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                             egui::Slider::new(&mut ui_state.grab_slider_radius, 5.0..=100.0)
                                 .text("Grab Radius"),
                         );
+
+                        // Grabbing a group picks up every particle in it, wherever they ended up.
+                        ui.checkbox(&mut ui_state.grab_whole_group, "Grab Whole Group");
+                        if ui_state.grab_whole_group {
+                            show_group_picker(ui, "Group", &mut ui_state.grab_group);
+                        }
                     }
 
                     // For the Add Fluid tool, show density and radius sliders.
@@ -341,6 +1311,16 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                             egui::Slider::new(&mut ui_state.add_fluid_density, 0.01..=1.0)
                                 .text("Fluid Density"),
                         );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.add_fluid_temperature, 0.0..=100.0)
+                                .text("Fluid Temperature"),
+                        );
+                        ui.checkbox(&mut ui_state.add_fluid_pool, "Fill Pool");
+                        if ui_state.add_fluid_pool {
+                            ui.checkbox(&mut ui_state.pool_hydrostatic, "Hydrostatic Start");
+                        }
+                        show_fluid_material_picker(ui, &mut ui_state.fluid_material);
+                        show_group_picker(ui, "Group", &mut ui_state.particle_group);
                     }
 
                     // For the Remove Fluid tool, show a radius slider.
@@ -351,9 +1331,52 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                         );
                     }
 
-                    // For the Add Wall tool, show some text as there are no options for Add Wall.
+                    // For the Add Wall tool, show a picker for what the wall is made of.
                     SimTool::AddWall => {
                         ui.label("Click anywhere in the simulation to add a wall!");
+
+                        egui::ComboBox::from_label("Material").show_index(
+                            ui,
+                            &mut ui_state.wall_material,
+                            WALL_MATERIAL_COUNT,
+                            |i| Into::<SimWallMaterial>::into(i).as_str().to_owned(),
+                        );
+                        // Walls can follow the simulation's slip condition, or have their own.
+                        egui::ComboBox::from_label("Slip").show_index(
+                            ui,
+                            &mut ui_state.wall_slip_override,
+                            WALL_SLIP_COUNT + 1,
+                            |i| match i.checked_sub(1) {
+                                Some(slip) => Into::<SimWallSlip>::into(slip).as_str().to_owned(),
+                                None => "Simulation's".to_owned(),
+                            },
+                        );
+                    }
+
+                    /* For the Add Porous Wall tool, show a slider for how freely fluid soaks through
+                    the wall. */
+                    SimTool::AddPorousWall => {
+                        ui.label("Click anywhere in the simulation to add a sponge-like wall!");
+
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.wall_permeability, 0.01..=0.99)
+                                .text("Permeability"),
+                        );
+                    }
+
+                    // For the Add Valve tool, show which way new valves let fluid through.
+                    SimTool::AddValve => {
+                        ui.label("Click to add a one-way valve, right click to remove one!");
+
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Flow Direction:");
+                            egui::ComboBox::from_id_source("valve_direction").show_index(
+                                ui,
+                                &mut ui_state.valve_direction,
+                                SURFACE_DIRECTION_COUNT,
+                                |i| Into::<SimSurfaceDirection>::into(i).as_str().to_owned(),
+                            );
+                        });
                     }
 
                     // For the Remove Wall tool, show some text as there are no options for Remove Wall.
@@ -361,6 +1384,43 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                         ui.label("Click a wall in the simulation to remove it!");
                     }
 
+                    // For the Add Obstacle tool, show an obstacle picker and its rotation.
+                    SimTool::AddObstacle => {
+                        ui.label("Left click to place the obstacle, right click to rotate it!");
+
+                        egui::ComboBox::from_label("Obstacle").show_index(
+                            ui,
+                            &mut ui_state.selected_obstacle,
+                            OBSTACLE_COUNT,
+                            |i| Into::<SimObstacle>::into(i).as_str().to_owned(),
+                        );
+
+                        let mut rotation_degrees: u16 = ui_state.obstacle_rotation as u16 * 90;
+                        ui.add(
+                            egui::Slider::new(&mut rotation_degrees, 0..=270)
+                                .step_by(90.0)
+                                .text("Rotation"),
+                        );
+                        ui_state.obstacle_rotation = (rotation_degrees / 90) as u8;
+                    }
+
+                    /* For the Add Pipe tool, show sliders for the pipe's inner width at each end;
+                    a narrower outlet than inlet makes a funnel. */
+                    SimTool::AddPipe => {
+                        ui.label("Click and drag to draw a pipe, then let go to build it!");
+
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.pipe_inlet_width, 1.0..=10.0)
+                                .step_by(1.0)
+                                .text("Inlet Width"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.pipe_outlet_width, 1.0..=10.0)
+                                .step_by(1.0)
+                                .text("Outlet Width"),
+                        );
+                    }
+
                     /* For the Add Faucet tool, show sliders for the direction, volume, and speed
                     of the fluid coming out of the faucet. */
                     SimTool::AddFaucet => {
@@ -377,6 +1437,51 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                             egui::Slider::new(&mut ui_state.faucet_pressure, 0.0..=100.0)
                                 .text("Faucet Pressure"),
                         );
+
+                        // Wider nozzles pour sheets and sprays instead of a single stream.
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Shape:");
+                            egui::ComboBox::from_id_source("faucet_shape").show_index(
+                                ui,
+                                &mut ui_state.faucet_shape,
+                                FAUCET_SHAPE_COUNT,
+                                |i| {
+                                    let shape: SimFaucetShape = i.into();
+                                    shape.as_str().to_owned()
+                                },
+                            );
+                        });
+                        let shape: SimFaucetShape = ui_state.faucet_shape.into();
+                        if matches!(shape, SimFaucetShape::Cone | SimFaucetShape::Fan) {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.faucet_spread, 0.0..=180.0)
+                                    .text("Spray Angle"),
+                            );
+                        }
+
+                        // Pulsing faucets turn themselves on and off, like a dripping tap or a geyser.
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.faucet_pulse_period, 0.0..=10.0)
+                                .text("Pulse Period (s)"),
+                        );
+                        if ui_state.faucet_pulse_period > 0.0 {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.faucet_duty_cycle, 0.0..=100.0)
+                                    .text("Time Pouring (%)"),
+                            );
+                        }
+
+                        // Path-following faucets sweep back and forth along a path drawn by dragging.
+                        ui.checkbox(&mut ui_state.faucet_follow_path, "Follow Path");
+                        if ui_state.faucet_follow_path {
+                            ui.label("Click and drag to draw the faucet's path!");
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.faucet_path_speed, 5.0..=200.0)
+                                    .text("Path Speed"),
+                            );
+                        }
+                        show_fluid_material_picker(ui, &mut ui_state.fluid_material);
+                        show_group_picker(ui, "Group", &mut ui_state.particle_group);
                     }
 
                     // For the Remove Faucet tool, show some text as there are no options for Remove Faucet.
@@ -385,7 +1490,9 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                     }
 
                     /* For the Add Drain tool, show a sucking radius radius slider and a pressure slider
-                    for controlling how intensely a drain pulls fluid inwards. */
+                    for controlling how intensely a drain pulls fluid inwards, along with an optional
+                    limit on how quickly the drain can consume fluid and how much it can hold before
+                    clogging up. */
                     SimTool::AddDrain => {
                         ui.add(
                             egui::Slider::new(&mut ui_state.drain_radius, 0.0..=35.0)
@@ -395,6 +1502,140 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
                             egui::Slider::new(&mut ui_state.drain_pressure, 0.0..=50.0)
                                 .text("Drain Pressure"),
                         );
+                        ui.checkbox(&mut ui_state.drain_limit_throughput, "Limit Throughput");
+                        if ui_state.drain_limit_throughput {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.drain_capacity, 1.0..=500.0)
+                                    .text("Particles Per Second"),
+                            );
+                        }
+                        ui.checkbox(&mut ui_state.drain_limit_volume, "Limit Volume");
+                        if ui_state.drain_limit_volume {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.drain_volume, 10.0..=5000.0)
+                                    .step_by(10.0)
+                                    .text("Particles Until Clogged"),
+                            );
+                        }
+                    }
+
+                    // For the Container tool, explain how to mark and unmark containers.
+                    SimTool::Container => {
+                        ui.label("Click and drag to mark a container and measure how full it is!");
+                        ui.label("Right click a container to remove it.");
+                    }
+
+                    /* For the Add Pump tool, explain how to connect a pump, and show sliders for which
+                    way and how hard it pushes fluid out of its outlet, and how long the fluid takes
+                    to get there. */
+                    SimTool::AddPump => {
+                        ui.label("Click and drag from a pump's intake to its outlet to connect it!");
+                        ui.label("Right click either end of a pump to remove it.");
+
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.pump_direction, 0.0..=360.0)
+                                .text("Outlet Direction"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.pump_pressure, 0.0..=100.0)
+                                .text("Pump Pressure"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.pump_delay, 0.0..=10.0)
+                                .text("Delay (s)"),
+                        );
+                    }
+
+                    /* For the Spinner tool, show sliders for the size of the spinner, how many blades it
+                    has, and how quickly (and which way) it spins; or, for waterwheels, how heavy the
+                    wheel is. */
+                    SimTool::Spinner => {
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.spinner_radius, 5.0..=80.0)
+                                .text("Blade Length"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.spinner_blade_count, 1..=8)
+                                .text("Blades"),
+                        );
+                        ui.checkbox(&mut ui_state.spinner_is_waterwheel, "Waterwheel");
+                        if ui_state.spinner_is_waterwheel {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.spinner_inertia, 0.05..=5.0)
+                                    .text("Inertia (s)"),
+                            );
+                            ui.label("Waterwheels are turned by the fluid flowing past them.");
+                        } else {
+                            ui.add(
+                                egui::Slider::new(&mut ui_state.spinner_speed, -10.0..=10.0)
+                                    .text("Spin Speed"),
+                            );
+                            ui.label("Negative speeds spin clockwise.");
+                        }
+                        ui.label("Right click a spinner to remove it.");
+                    }
+
+                    /* For the Wind tool, show sliders for which way the painted wind blows, how
+                    strongly, and how wide a brush it's painted with. */
+                    SimTool::Wind => {
+                        ui.label("Click and drag to paint wind, right click to calm it!");
+
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.wind_direction, 0.0..=360.0)
+                                .text("Wind Direction"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.wind_strength, 0.0..=1000.0)
+                                .text("Wind Strength"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.wind_radius, 1.0..=50.0)
+                                .text("Brush Radius"),
+                        );
+                    }
+
+                    // For the Vortex tool, show sliders for how hard and how widely it swirls the fluid.
+                    SimTool::Vortex => {
+                        ui.label(
+                            "Hold left click to swirl fluid counter-clockwise, right click for clockwise!",
+                        );
+
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.vortex_strength, 0.0..=3000.0)
+                                .text("Vortex Strength"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.vortex_radius, 5.0..=100.0)
+                                .text("Vortex Radius"),
+                        );
+                    }
+
+                    // For the Flow Meter tool, explain how flow meters are placed and removed.
+                    SimTool::FlowMeter => {
+                        ui.label("Click and drag across the fluid to measure how much flows by!");
+                        ui.label("Flow from right to left (facing along the meter) is positive.");
+                        ui.label("Right click a flow meter to remove it.");
+                    }
+
+                    // For the Probe tool, explain how probes are placed and removed.
+                    SimTool::Probe => {
+                        ui.label("Click to place a probe that records the fluid in its cell!");
+                        ui.label("Right click a probe to remove it.");
+                    }
+
+                    /* For the Attractor tool, show sliders for how hard attractors pull fluid in
+                    (or push it away) and how far their pull reaches. */
+                    SimTool::Attractor => {
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.attractor_strength, -2000.0..=2000.0)
+                                .text("Attractor Strength"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.attractor_radius, 5.0..=100.0)
+                                .text("Attractor Radius"),
+                        );
+                        ui.label("Negative strengths push fluid away.");
+                        ui.label("Right click an attractor to remove it.");
                     }
 
                     // For the Remove Drain tool, show some text as there are no options for Remove Drain.
@@ -413,6 +1654,7 @@ fn show_current_tool_menu(ui_state: &mut UIStateManager, contexts: &mut EguiCont
 fn show_visualization_menu(
     ui_state: &mut UIStateManager,
     contexts: &mut EguiContexts,
+    heatmap_legend: &GridHeatmapLegend,
     mut ev_viz: EventWriter<ModifyVisualizationEvent>,
 ) {
     // Whenever our visualization is modified, update this variable and send an event out.
@@ -433,18 +1675,98 @@ fn show_visualization_menu(
                 if ui.checkbox(&mut ui_state.show_grid, "Show Grid").clicked() {
                     viz_mod = true;
                 }
+                if ui
+                    .checkbox(&mut ui_state.show_cell_types, "Show Cell Types")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                ui.checkbox(&mut ui_state.show_minimap, "Show Minimap");
                 if ui
                     .checkbox(&mut ui_state.show_velocity_vectors, "Show Velocities")
                     .clicked()
                 {
                     viz_mod = true;
                 }
+                if ui
+                    .checkbox(&mut ui_state.show_streamlines, "Show Streamlines")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui_state.show_streamlines
+                    && ui
+                        .checkbox(&mut ui_state.streamlines_from_cursor, "Seed From Cursor")
+                        .clicked()
+                {
+                    viz_mod = true;
+                }
                 if ui
                     .checkbox(&mut ui_state.show_gravity_vector, "Show Gravity")
                     .clicked()
                 {
                     viz_mod = true;
                 }
+                if ui
+                    .checkbox(&mut ui_state.show_temperature, "Show Temperature")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui
+                    .checkbox(&mut ui_state.show_liquid_surface, "Show Liquid Surface")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui
+                    .checkbox(&mut ui_state.show_liquid_outline, "Show Surface Outline")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui
+                    .checkbox(&mut ui_state.show_particle_trails, "Show Trails")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui_state.show_particle_trails {
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut ui_state.trail_length, 2..=60)
+                                .text("Trail Length"),
+                        )
+                        .changed()
+                    {
+                        viz_mod = true;
+                    }
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut ui_state.trail_fade, 0.5..=4.0)
+                                .text("Trail Fade"),
+                        )
+                        .changed()
+                    {
+                        viz_mod = true;
+                    }
+                }
+                if ui
+                    .checkbox(&mut ui_state.show_onion_skin, "Onion Skin While Paused")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui_state.show_onion_skin
+                    && ui
+                        .add(
+                            egui::Slider::new(&mut ui_state.onion_skin_frames, 1..=10)
+                                .text("Ghost Steps"),
+                        )
+                        .changed()
+                {
+                    viz_mod = true;
+                }
 
                 ui.separator();
 
@@ -452,7 +1774,15 @@ fn show_visualization_menu(
                 ui.horizontal_wrapped(|ui| {
                     // Labels for each button.
                     ui.label("Color by:");
-                    let color_options = ["Velocity", "Density", "None"];
+                    let color_options = [
+                        "Velocity",
+                        "Density",
+                        "Temperature",
+                        "Vorticity",
+                        "Pressure",
+                        "Material",
+                        "None",
+                    ];
 
                     // Combobox setup and event polling:
                     if egui::ComboBox::from_id_source(0)
@@ -498,6 +1828,29 @@ fn show_visualization_menu(
 
                 ui.separator();
 
+                // Grid heatmap dropdown.
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Heatmap:");
+                    let heatmap_options =
+                        ["None", "Pressure", "Divergence", "Density", "Vorticity"];
+                    if egui::ComboBox::from_id_source("grid_heatmap")
+                        .show_index(
+                            ui,
+                            &mut ui_state.grid_heatmap_variable,
+                            heatmap_options.len(),
+                            |i| heatmap_options[i].to_owned(),
+                        )
+                        .changed()
+                    {
+                        viz_mod = true;
+                    }
+                });
+                if ui_state.grid_heatmap_variable != 0 {
+                    show_heatmap_legend(ui, heatmap_legend);
+                }
+
+                ui.separator();
+
                 // Sliders for the particle size and gravity direction.
                 if ui
                     .add(
@@ -508,6 +1861,134 @@ fn show_visualization_menu(
                 {
                     viz_mod = true;
                 }
+
+                // Particle size mapping dropdown.
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Size by:");
+                    let size_options = ["Radius", "Density", "Speed"];
+                    if egui::ComboBox::from_id_source("particle_size_variable")
+                        .show_index(
+                            ui,
+                            &mut ui_state.particle_size_variable,
+                            size_options.len(),
+                            |i| size_options[i].to_owned(),
+                        )
+                        .changed()
+                    {
+                        viz_mod = true;
+                    }
+                });
+                if ui.checkbox(&mut ui_state.use_bloom, "Bloom").clicked() {
+                    viz_mod = true;
+                }
+
+                ui.separator();
+
+                // Custom WGSL fragment shader for the particles, relative to the assets folder.
+                if ui
+                    .checkbox(&mut ui_state.use_custom_shader, "Custom Particle Shader")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui
+                    .add_enabled(
+                        ui_state.use_custom_shader,
+                        egui::TextEdit::singleline(&mut ui_state.custom_shader_path),
+                    )
+                    .lost_focus()
+                {
+                    viz_mod = true;
+                }
+
+                // Background image behind the grid, relative to the assets folder.
+                if ui
+                    .checkbox(&mut ui_state.use_background_image, "Background Image")
+                    .clicked()
+                {
+                    viz_mod = true;
+                }
+                if ui_state.use_background_image {
+                    if ui
+                        .add(egui::TextEdit::singleline(
+                            &mut ui_state.background_image_path,
+                        ))
+                        .lost_focus()
+                    {
+                        viz_mod = true;
+                    }
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut ui_state.background_scale, 0.25..=4.0)
+                                .text("Background Scale"),
+                        )
+                        .changed()
+                    {
+                        viz_mod = true;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Offset:");
+                        for axis in 0..2 {
+                            if ui
+                                .add(egui::DragValue::new(&mut ui_state.background_offset[axis]))
+                                .changed()
+                            {
+                                viz_mod = true;
+                            }
+                        }
+                    });
+                }
+
+                ui.separator();
+
+                /* Particles are tagged with a group when they are emitted; each group can be hidden,
+                tinted, grabbed as a whole, or deleted to see where a particular pour ended up. */
+                ui.collapsing("Particle Groups", |ui| {
+                    for group in 0..PARTICLE_GROUP_COUNT {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Group {}", group + 1));
+                            if ui
+                                .checkbox(&mut ui_state.group_visible[group], "Show")
+                                .clicked()
+                            {
+                                viz_mod = true;
+                            }
+                            if ui
+                                .checkbox(&mut ui_state.group_tinted[group], "Tint")
+                                .clicked()
+                            {
+                                viz_mod = true;
+                            }
+                            if ui
+                                .color_edit_button_rgb(&mut ui_state.group_tints[group])
+                                .changed()
+                            {
+                                viz_mod = true;
+                            }
+                            if ui.small_button("Select").clicked() {
+                                ui_state.selected_tool = SimTool::Grab;
+                                ui_state.grab_whole_group = true;
+                                ui_state.grab_group = group;
+                            }
+                            if ui.small_button("Delete").clicked() {
+                                ui_state.clear = Some(ClearEvent::group(group as u8));
+                            }
+                        });
+                    }
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Save to:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut ui_state.screenshot_directory)
+                            .desired_width(120.0),
+                    );
+                });
+                if ui.button("Take Screenshot (F12)").clicked() {
+                    ui_state.take_screenshot = true;
+                }
             });
         });
 
@@ -616,10 +2097,22 @@ pub fn load_user_interface_icons(ui_state: &mut UIStateManager, asset_server: &A
         asset_server.load("../assets/ui/removefluid.png"),
         asset_server.load("../assets/ui/addwall.png"),
         asset_server.load("../assets/ui/removewall.png"),
+        asset_server.load("../assets/ui/select.png"),
+        asset_server.load("../assets/ui/addwall.png"),
         asset_server.load("../assets/ui/addfaucet.png"),
         asset_server.load("../assets/ui/removefaucet.png"),
         asset_server.load("../assets/ui/adddrain.png"),
         asset_server.load("../assets/ui/removedrain.png"),
+        asset_server.load("../assets/ui/select.png"),
+        asset_server.load("../assets/ui/rotate.png"),
+        asset_server.load("../assets/ui/movecamera.png"),
+        asset_server.load("../assets/ui/rotate.png"),
+        asset_server.load("../assets/ui/adddrain.png"),
+        asset_server.load("../assets/ui/addwall.png"),
+        asset_server.load("../assets/ui/addwall.png"),
+        asset_server.load("../assets/ui/adddrain.png"),
+        asset_server.load("../assets/ui/select.png"),
+        asset_server.load("../assets/ui/select.png"),
     ];
     let play_pause_icon_handles: [Handle<Image>; 2] = [
         asset_server.load("../assets/ui/play.png"),
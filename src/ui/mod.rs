@@ -1,6 +1,8 @@
-mod interaction;
+pub mod interaction;
 mod interface;
 
+use std::f32::consts::PI;
+
 use bevy::prelude::*;
 use bevy::{
     asset::{AssetServer, Handle},
@@ -13,12 +15,16 @@ use bevy_egui::{
     EguiContexts,
 };
 
-use self::interaction::{change_cursor_icon, handle_camera_input, handle_input};
-use crate::events::{ResetEvent, ClearEvent, UseToolEvent};
+use self::interaction::{
+    capture_screenshot, change_cursor_icon, follow_camera_target, handle_camera_input,
+    handle_input, update_minimap_camera,
+};
+use crate::events::{ResetEvent, ClearEvent, SequencerEvent, UseToolEvent};
 use crate::file_system::JuiceStates;
+use crate::simulation::{SimConstraints, SimGrid, SimGridEdge};
 use crate::{
     events::{ModifyVisualizationEvent, PlayPauseStepEvent},
-    util,
+    juice_renderer, simulation, util,
 };
 
 pub struct JuiceUI;
@@ -30,17 +36,21 @@ impl Plugin for JuiceUI {
         app.add_systems(Update, update_ui);
         app.add_systems(Update, handle_input);
         app.add_systems(Update, handle_camera_input);
+        app.add_systems(Update, follow_camera_target.after(handle_camera_input));
+        app.add_systems(Update, update_minimap_camera.after(follow_camera_target));
         app.add_systems(Update, change_cursor_icon);
+        app.add_systems(Update, capture_screenshot);
 
 		app.add_event::<ResetEvent>();
 		app.add_event::<ClearEvent>();
         app.add_event::<UseToolEvent>();
         app.add_event::<PlayPauseStepEvent>();
         app.add_event::<ModifyVisualizationEvent>();
+        app.add_event::<SequencerEvent>();
     }
 }
 
-const UI_ICON_COUNT: usize = 12;
+const UI_ICON_COUNT: usize = 24;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SimTool {
     Camera = 0,
@@ -51,10 +61,22 @@ pub enum SimTool {
     RemoveFluid,
     AddWall,
     RemoveWall,
+    AddObstacle,
+    AddPipe,
     AddFaucet,
     RemoveFaucet,
     AddDrain,
     RemoveDrain,
+    Container,
+    Spinner,
+    Wind,
+    Vortex,
+    Attractor,
+    AddPorousWall,
+    AddValve,
+    AddPump,
+    Probe,
+    FlowMeter,
 }
 
 impl Into<SimTool> for usize {
@@ -68,10 +90,22 @@ impl Into<SimTool> for usize {
             5 => SimTool::RemoveFluid,
             6 => SimTool::AddWall,
             7 => SimTool::RemoveWall,
-            8 => SimTool::AddFaucet,
-            9 => SimTool::RemoveFaucet,
-            10 => SimTool::AddDrain,
-            11 => SimTool::RemoveDrain,
+            8 => SimTool::AddObstacle,
+            9 => SimTool::AddPipe,
+            10 => SimTool::AddFaucet,
+            11 => SimTool::RemoveFaucet,
+            12 => SimTool::AddDrain,
+            13 => SimTool::RemoveDrain,
+            14 => SimTool::Container,
+            15 => SimTool::Spinner,
+            16 => SimTool::Wind,
+            17 => SimTool::Vortex,
+            18 => SimTool::Attractor,
+            19 => SimTool::AddPorousWall,
+            20 => SimTool::AddValve,
+            21 => SimTool::AddPump,
+            22 => SimTool::Probe,
+            23 => SimTool::FlowMeter,
             _ => {
                 eprintln!("Invalid SimTool; defaulting to Grab!");
                 SimTool::Grab
@@ -91,10 +125,22 @@ impl SimTool {
             Self::RemoveFluid => "Remove Fluid",
             Self::AddWall => "Add Wall",
             Self::RemoveWall => "Remove Wall",
+            Self::AddObstacle => "Add Obstacle",
+            Self::AddPipe => "Add Pipe",
             Self::AddFaucet => "Add Faucet",
             Self::RemoveFaucet => "Remove Faucet",
             Self::AddDrain => "Add Drain",
             Self::RemoveDrain => "Remove Drain",
+            Self::Container => "Container",
+            Self::Spinner => "Spinner",
+            Self::Wind => "Wind",
+            Self::Vortex => "Vortex",
+            Self::Attractor => "Attractor",
+            Self::AddPorousWall => "Add Porous Wall",
+            Self::AddValve => "Add Valve",
+            Self::AddPump => "Add Pump",
+            Self::Probe => "Probe",
+            Self::FlowMeter => "Flow Meter",
         }
     }
 }
@@ -105,24 +151,167 @@ pub struct UIStateManager {
     pub selected_tool: SimTool,
     pub tool_icon_handles: Vec<Handle<Image>>,
     pub zoom_slider: f32,
+    pub camera_follow_mode: usize, // 0: nothing, 1: the fluid's centroid, 2: a clicked particle.
+    pub followed_particle: Option<Entity>,
+    pub show_minimap: bool,
+    pub camera_view: bevy::math::Rect, // Part of the world the camera can see.
+    pub minimap_jump: Option<bevy::math::Vec2>, // Where the minimap was clicked to move the camera to.
     pub grab_slider_radius: f32,
     pub add_remove_fluid_radius: f32,
     pub add_fluid_density: f32,
+    pub add_fluid_temperature: f32,
+    pub add_fluid_pool: bool,
+    pub pool_hydrostatic: bool,
     pub faucet_direction: f32,
     pub faucet_radius: f32,
     pub faucet_pressure: f32,
+    pub faucet_shape: usize,
+    pub faucet_spread: f32,
+    pub faucet_pulse_period: f32,
+    pub faucet_duty_cycle: f32,
+    pub faucet_follow_path: bool,
+    pub faucet_path_speed: f32,
+    pub faucet_path: Vec<bevy::math::Vec2>,
+    pub is_drawing_faucet_path: bool,
     pub drain_radius: f32,
     pub drain_pressure: f32,
+    pub drain_limit_throughput: bool,
+    pub drain_capacity: f32,
+    pub drain_limit_volume: bool,
+    pub drain_volume: f32,
+    pub selected_obstacle: usize,
+    pub obstacle_rotation: u8,
+    pub wall_material: usize,
+    pub wall_slip_override: usize,
+    pub wall_permeability: f32,
+    pub valve_direction: usize,
+    pub pipe_inlet_width: f32,
+    pub pipe_outlet_width: f32,
+    pub pipe_path: Vec<bevy::math::Vec2>,
+    pub is_drawing_pipe: bool,
+    pub container_corner: bevy::math::Vec2,
+    pub is_drawing_container: bool,
+    pub pump_intake: bevy::math::Vec2,
+    pub is_drawing_pump: bool,
+    pub pump_direction: f32,
+    pub pump_pressure: f32,
+    pub pump_delay: f32,
+    pub probe_quantity: usize,
+    pub export_probes: bool,
+    pub flow_meter_start: bevy::math::Vec2,
+    pub is_drawing_flow_meter: bool,
+    pub spinner_radius: f32,
+    pub spinner_blade_count: u8,
+    pub spinner_speed: f32,
+    pub spinner_is_waterwheel: bool,
+    pub spinner_inertia: f32,
+    pub wind_direction: f32,
+    pub wind_strength: f32,
+    pub wind_radius: f32,
+    pub vortex_strength: f32,
+    pub vortex_radius: f32,
+    pub attractor_strength: f32,
+    pub attractor_radius: f32,
+    pub fluid_material: usize,
+    pub particle_group: usize,
+    pub grab_whole_group: bool,
+    pub grab_group: usize,
 
     pub show_visualization: bool,
     pub show_grid: bool,
+    pub show_cell_types: bool,
     pub show_velocity_vectors: bool,
+    pub show_streamlines: bool,
+    pub streamlines_from_cursor: bool,
     pub show_gravity_vector: bool,
+    pub show_temperature: bool,
+    pub show_liquid_surface: bool,
+    pub show_liquid_outline: bool,
+    pub show_particle_trails: bool,
+    pub trail_length: usize,
+    pub trail_fade: f32,
+    pub show_onion_skin: bool,
+    pub onion_skin_frames: usize,
     pub particle_physical_size: f32,
+    pub particle_size_variable: usize,
+    pub use_bloom: bool,
+    pub use_custom_shader: bool,
+    pub custom_shader_path: String,
+    pub use_background_image: bool,
+    pub background_image_path: String,
+    pub background_scale: f32,
+    pub background_offset: [f32; 2],
     pub gravity_direction: f32,
     pub gravity_magnitude: f32,
+    pub zero_gravity: bool,
     pub fluid_color_variable: usize,
+    pub grid_heatmap_variable: usize,
     pub fluid_colors: [[f32; 3]; 4],
+    pub group_visible: [bool; simulation::PARTICLE_GROUP_COUNT],
+    pub group_tinted: [bool; simulation::PARTICLE_GROUP_COUNT],
+    pub group_tints: [[f32; 3]; simulation::PARTICLE_GROUP_COUNT],
+
+    pub show_simulation_settings: bool,
+    pub substeps: u8,
+    pub solver_kind: usize,
+    pub transfer_scheme: usize,
+    pub advection_scheme: usize,
+    pub pressure_solver: usize,
+    pub pressure_tolerance: f32,
+    pub gpu_transfers: bool,
+    pub solver_residual: simulation::sim_pressure_solver::SimSolverResidual,
+    pub wrap_horizontal: bool,
+    pub wrap_vertical: bool,
+    pub edge_boundaries: [usize; 4],
+    pub inflow_speed: f32,
+    pub inflow_profile: usize,
+    pub viscosity: f32,
+    pub surface_tension: f32,
+    pub interface_tension: f32,
+    pub erosion_speed: f32,
+    pub deposit_speed: f32,
+    pub collision_restitution: f32,
+    pub collision_friction: f32,
+    pub wall_slip: usize,
+    pub vorticity_confinement: f32,
+    pub thermal_expansion: f32,
+    pub evaporation_rate: f32,
+    pub condensation: bool,
+    pub secondary_particles: bool,
+    pub secondary_spawn_rate: f32,
+    pub reseeding: bool,
+    pub min_particles_per_cell: u8,
+    pub max_particles_per_cell: u8,
+    pub adaptive_particles: bool,
+    pub merge_speed: f32,
+    pub split_shear: f32,
+    pub max_particle_mass: f32,
+    pub narrow_band: bool,
+    pub narrow_band_width: u8,
+    pub safeguard_response: usize,
+    pub max_density_ratio: f32,
+    pub max_particle_speed: f32,
+    pub deterministic: bool,
+    pub seed: u64,
+    pub record_telemetry: bool,
+    pub show_diagnostics: bool,
+    pub diagnostics_quantity: usize,
+    pub show_profiler: bool,
+    pub add_domain: bool,
+    pub clear_domains: bool,
+    pub domain_count: usize,
+
+    pub show_sequencer: bool,
+    pub sequencer_time: f32,
+    pub sequencer_action: usize,
+    pub sequencer_point: bevy::math::Vec2,
+    pub sequencer_other_point: bevy::math::Vec2,
+
+    pub screenshot_directory: String, // Where screenshots are saved to.
+    pub take_screenshot: bool, // Save a screenshot of the next frame.
+
+    pub toast_message: String,
+    pub toast_seconds_left: f32,
 
     pub is_paused: bool,
     pub play_pause_icon_handles: Vec<Handle<Image>>,
@@ -134,8 +323,9 @@ pub struct UIStateManager {
     pub show_informational: bool,
 
 	pub file_state:					JuiceStates,
+	pub quick_save_slot:			String,
 	pub reset:						bool,
-	pub clear:						bool,
+	pub clear:						Option<ClearEvent>,
 }
 
 impl Default for UIStateManager {
@@ -146,24 +336,102 @@ impl Default for UIStateManager {
             selected_tool: SimTool::AddFluid,
             tool_icon_handles: vec![Handle::default(); UI_ICON_COUNT],
             zoom_slider: 1.0,
+            camera_follow_mode: 0,
+            followed_particle: None,
+            show_minimap: false,
+            camera_view: bevy::math::Rect::default(),
+            minimap_jump: None,
             grab_slider_radius: 15.0,
             add_remove_fluid_radius: 25.0,
             add_fluid_density: 0.5,
+            add_fluid_temperature: simulation::AMBIENT_TEMPERATURE,
+            add_fluid_pool: false,
+            pool_hydrostatic: true,
             faucet_direction: 320.0,
             faucet_radius: 1.0,
             faucet_pressure: 35.0,
+            faucet_shape: 0,
+            faucet_spread: 30.0,
+            faucet_pulse_period: 0.0,
+            faucet_duty_cycle: 50.0,
+            faucet_follow_path: false,
+            faucet_path_speed: 40.0,
+            faucet_path: Vec::new(),
+            is_drawing_faucet_path: false,
             drain_radius: 10.5,
             drain_pressure: 30.0,
+            drain_limit_throughput: false,
+            drain_capacity: 60.0,
+            drain_limit_volume: false,
+            drain_volume: 500.0,
+            selected_obstacle: 0,
+            obstacle_rotation: 0,
+            wall_material: 0,
+            wall_slip_override: 0,
+            wall_permeability: 0.3,
+            valve_direction: 2,
+            pipe_inlet_width: 3.0,
+            pipe_outlet_width: 3.0,
+            pipe_path: Vec::new(),
+            is_drawing_pipe: false,
+            container_corner: bevy::math::Vec2::ZERO,
+            is_drawing_container: false,
+            pump_intake: bevy::math::Vec2::ZERO,
+            is_drawing_pump: false,
+            pump_direction: 90.0,
+            pump_pressure: 20.0,
+            pump_delay: 0.5,
+            probe_quantity: 0,
+            export_probes: false,
+            flow_meter_start: bevy::math::Vec2::ZERO,
+            is_drawing_flow_meter: false,
+            spinner_radius: 30.0,
+            spinner_blade_count: 4,
+            spinner_speed: 2.0,
+            spinner_is_waterwheel: false,
+            spinner_inertia: 0.5,
+            wind_direction: 0.0,
+            wind_strength: 300.0,
+            wind_radius: 20.0,
+            vortex_strength: 800.0,
+            vortex_radius: 30.0,
+            attractor_strength: 600.0,
+            attractor_radius: 40.0,
+            fluid_material: 0,
+            particle_group: 0,
+            grab_whole_group: false,
+            grab_group: 0,
 
             // Visualization menu.
             show_visualization: true,
             show_grid: false,
+            show_cell_types: false,
             show_velocity_vectors: false,
+            show_streamlines: false,
+            streamlines_from_cursor: false,
             show_gravity_vector: false,
+            show_temperature: false,
+            show_liquid_surface: false,
+            show_liquid_outline: false,
+            show_particle_trails: false,
+            trail_length: 12,
+            trail_fade: 1.5,
+            show_onion_skin: false,
+            onion_skin_frames: 4,
             particle_physical_size: 0.4,
+            particle_size_variable: 0,
+            use_bloom: false,
+            use_custom_shader: false,
+            custom_shader_path: String::from("shaders/particle.wgsl"),
+            use_background_image: false,
+            background_image_path: String::from("backgrounds/background.png"),
+            background_scale: 1.0,
+            background_offset: [0.0, 0.0],
             gravity_direction: 270.0,
             gravity_magnitude: 9.81,
+            zero_gravity: false,
             fluid_color_variable: 0,
+            grid_heatmap_variable: 0,
             fluid_colors: [
                 [
                     util::JUICE_BLUE.r(),
@@ -186,6 +454,84 @@ impl Default for UIStateManager {
                     util::JUICE_RED.b(),
                 ],
             ],
+            group_visible: [true; simulation::PARTICLE_GROUP_COUNT],
+            group_tinted: [false; simulation::PARTICLE_GROUP_COUNT],
+            group_tints: [
+                [1.0, 0.3, 0.3],
+                [0.3, 1.0, 0.3],
+                [0.3, 0.5, 1.0],
+                [1.0, 1.0, 0.3],
+                [1.0, 0.3, 1.0],
+                [0.3, 1.0, 1.0],
+                [1.0, 0.6, 0.2],
+                [1.0, 1.0, 1.0],
+            ],
+
+            // Simulation settings menu.
+            show_simulation_settings: false,
+            substeps: 1,
+            solver_kind: 0,
+            transfer_scheme: 0,
+            advection_scheme: 0,
+            pressure_solver: 0,
+            pressure_tolerance: simulation::sim_pressure_solver::DEFAULT_PRESSURE_TOLERANCE,
+            gpu_transfers: false,
+            solver_residual: simulation::sim_pressure_solver::SimSolverResidual::default(),
+            wrap_horizontal: false,
+            wrap_vertical: false,
+            edge_boundaries: [0; 4],
+            inflow_speed: 100.0,
+            inflow_profile: 0,
+            viscosity: 0.0,
+            surface_tension: 0.0,
+            interface_tension: 0.0,
+            erosion_speed: 150.0,
+            deposit_speed: 20.0,
+            collision_restitution: 0.0,
+            collision_friction: 0.0,
+            wall_slip: 0,
+            vorticity_confinement: 0.0,
+            thermal_expansion: 0.0,
+            evaporation_rate: 0.0,
+            condensation: false,
+            secondary_particles: false,
+            secondary_spawn_rate: 20.0,
+            reseeding: false,
+            min_particles_per_cell: 1,
+            max_particles_per_cell: 6,
+            adaptive_particles: false,
+            merge_speed: 20.0,
+            split_shear: 10.0,
+            max_particle_mass: 4.0,
+            narrow_band: false,
+            narrow_band_width: 3,
+            safeguard_response: 0,
+            max_density_ratio: 4.0,
+            max_particle_speed: 1500.0,
+            deterministic: false,
+            seed: simulation::sim_rng::DEFAULT_SIM_SEED,
+            record_telemetry: false,
+            show_diagnostics: false,
+            diagnostics_quantity: 0,
+            show_profiler: false,
+            add_domain: false,
+            clear_domains: false,
+            domain_count: 0,
+
+            // Scene timeline (sequencer) menu.
+            show_sequencer: false,
+            sequencer_time: 0.0,
+            sequencer_action: 0,
+            sequencer_point: bevy::math::Vec2::ZERO,
+            sequencer_other_point: bevy::math::Vec2::ZERO,
+
+            // Screenshots.
+            screenshot_directory: String::from("screenshots"),
+            take_screenshot: false,
+
+            // Warnings shown briefly at the bottom of the screen.
+            toast_message: String::new(),
+            toast_seconds_left: 0.0,
 
             // Play/pause.
             is_paused: false,
@@ -201,12 +547,88 @@ impl Default for UIStateManager {
 
 			// File and scene stuff.
 			file_state:					JuiceStates::Running,
+			quick_save_slot:			String::from("quicksave"),
 			reset:						false,
-			clear:						false,
+			clear:						None,
 		}
 	}
 }
 
+impl UIStateManager {
+    /// Gravity as the sliders (and the zero gravity checkbox) set it.
+    pub fn gravity(&self) -> bevy::math::Vec2 {
+        if self.zero_gravity {
+            return bevy::math::Vec2::ZERO;
+        }
+
+        util::polar_to_cartesian(bevy::math::Vec2 {
+            x: self.gravity_magnitude * self.gravity_magnitude * 4.0,
+            y: util::degrees_to_radians(self.gravity_direction) - PI,
+        })
+    }
+
+    /** Point the gravity sliders at the simulation's gravity.  Zero gravity has no direction, so the
+    sliders are left where they were for when gravity is switched back on. */
+    pub fn show_gravity(&mut self, gravity: bevy::math::Vec2) {
+        self.zero_gravity = gravity == bevy::math::Vec2::ZERO;
+        if !self.zero_gravity {
+            let polar_gravity: bevy::math::Vec2 = util::cartesian_to_polar(gravity);
+            self.gravity_magnitude = f32::sqrt(polar_gravity.x / 4.0);
+            self.gravity_direction = util::radians_to_degrees(polar_gravity.y + PI);
+        }
+    }
+
+    /** Show the simulation's settings after something other than the UI has changed them, like
+    loading a scene or resetting the simulation. */
+    pub fn read_simulation_settings(&mut self, constraints: &SimConstraints, grid: &SimGrid) {
+        self.show_gravity(constraints.gravity);
+        self.substeps = constraints.substeps;
+        self.solver_kind = constraints.solver_kind as usize;
+        self.transfer_scheme = constraints.transfer_scheme as usize;
+        self.advection_scheme = constraints.advection_scheme as usize;
+        self.pressure_solver = constraints.pressure_solver as usize;
+        self.pressure_tolerance = constraints.pressure_tolerance;
+        self.gpu_transfers = constraints.gpu_transfers;
+        self.solver_residual = constraints.solver_residual;
+        self.viscosity = constraints.viscosity;
+        self.surface_tension = constraints.surface_tension;
+        self.interface_tension = constraints.interface_tension;
+        self.erosion_speed = constraints.erosion_speed;
+        self.deposit_speed = constraints.deposit_speed;
+        self.collision_restitution = constraints.collision_restitution;
+        self.collision_friction = constraints.collision_friction;
+        self.wall_slip = constraints.wall_slip as usize;
+        self.vorticity_confinement = constraints.vorticity_confinement;
+        self.thermal_expansion = constraints.thermal_expansion;
+        self.evaporation_rate = constraints.evaporation_rate;
+        self.condensation = constraints.condensation;
+        self.secondary_particles = constraints.secondary_particles;
+        self.secondary_spawn_rate = constraints.secondary_spawn_rate;
+        self.reseeding = constraints.reseeding;
+        self.min_particles_per_cell = constraints.min_particles_per_cell;
+        self.max_particles_per_cell = constraints.max_particles_per_cell;
+        self.adaptive_particles = constraints.adaptive_particles;
+        self.merge_speed = constraints.merge_speed;
+        self.split_shear = constraints.split_shear;
+        self.max_particle_mass = constraints.max_particle_mass;
+        self.narrow_band = constraints.narrow_band;
+        self.narrow_band_width = constraints.narrow_band_width;
+        self.safeguard_response = constraints.safeguard_response as usize;
+        self.max_density_ratio = constraints.max_density_ratio;
+        self.max_particle_speed = constraints.max_particle_speed;
+        self.deterministic = constraints.deterministic;
+        self.seed = constraints.seed;
+        self.inflow_speed = constraints.inflow_speed;
+        self.inflow_profile = constraints.inflow_profile as usize;
+
+        self.wrap_horizontal = grid.wrap_horizontal;
+        self.wrap_vertical = grid.wrap_vertical;
+        for edge in SimGridEdge::ALL {
+            self.edge_boundaries[edge as usize] = grid.edge_boundaries[edge as usize] as usize;
+        }
+    }
+}
+
 pub fn init_ui(
     contexts: EguiContexts,
     asset_server: Res<AssetServer>,
@@ -219,8 +641,32 @@ pub fn update_ui(
     contexts: EguiContexts,
     ui_state: ResMut<UIStateManager>,
     windows: Query<&Window>,
+    containers: Query<&simulation::SimContainer>,
+    probes: Query<&simulation::sim_probes::SimProbe>,
+    flow_meters: Query<&simulation::sim_flow_meter::SimFlowMeter>,
+    sequencer: Res<simulation::sim_sequencer::SimSequencer>,
+    diagnostics: Res<simulation::sim_diagnostics::SimDiagnostics>,
+    telemetry: Res<simulation::sim_telemetry::SimTelemetry>,
+    grid: Res<simulation::SimGrid>,
+    heatmap_legend: Res<juice_renderer::GridHeatmapLegend>,
     ev_viz: EventWriter<ModifyVisualizationEvent>,
     ev_pause: EventWriter<PlayPauseStepEvent>,
+    ev_sequencer: EventWriter<SequencerEvent>,
 ) {
-    interface::draw_user_interface(contexts, ui_state, windows, ev_viz, ev_pause);
+    interface::draw_user_interface(
+        contexts,
+        ui_state,
+        windows,
+        containers,
+        probes,
+        flow_meters,
+        sequencer,
+        diagnostics,
+        telemetry,
+        grid,
+        heatmap_legend,
+        ev_viz,
+        ev_pause,
+        ev_sequencer,
+    );
 }
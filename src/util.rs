@@ -99,10 +99,13 @@ pub fn control_camera(
     *absolute_zoom = f32::min(*absolute_zoom, max_zoom);
     projection.scale = 1.0 / *absolute_zoom;
 
-    // Rotate the camera depending on the direction of gravity.
-    // TODO: Make the camera rotate "in-place" as opposed to "around" the simulation.
-    let gravity_angle: f32 = cartesian_to_polar(constraints.gravity).y;
-    transform.rotation = Quat::from_rotation_z(gravity_angle + FRAC_PI_2);
+    /* Rotate the camera depending on the direction of gravity.  Zero gravity has no direction, so
+    the camera stays however it was turned.
+    TODO: Make the camera rotate "in-place" as opposed to "around" the simulation. */
+    if constraints.gravity != Vec2::ZERO {
+        let gravity_angle: f32 = cartesian_to_polar(constraints.gravity).y;
+        transform.rotation = Quat::from_rotation_z(gravity_angle + FRAC_PI_2);
+    }
 }
 
 /// Get the mouse cursor's position on the screen!  Returns (0.0, 0.0) if cursor position not found.